@@ -0,0 +1,462 @@
+//! Display order of text that mixes left-to-right scripts with right-to-left scripts like Hebrew
+//! and Arabic. This follows the Unicode Bidirectional Algorithm (UAX #9) without explicit
+//! embeddings and isolates, which books rarely use. The direction of a paragraph is the direction
+//! of its first letter.
+//!
+//! Characters are classified by their Unicode block instead of the full character database, which
+//! would not fit into flash. Arabic letters are not shaped into their joined forms.
+//!
+//! Lines are reordered on the stack, as this runs for every line of a page.
+
+use crate::pagination::{MAXIMUM_LINE_CHARACTERS, MAXIMUM_LINE_SIZE};
+
+/// A value for each character of a line
+type PerCharacter<T> = heapless::Vec<T, MAXIMUM_LINE_CHARACTERS>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Direction {
+    LeftToRight,
+    RightToLeft,
+}
+
+/// Bidirectional character types of UAX #9 that matter without explicit embeddings
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Class {
+    /// L
+    Left,
+    /// R
+    Right,
+    /// AL
+    ArabicLetter,
+    /// EN
+    EuropeanNumber,
+    /// AN
+    ArabicNumber,
+    /// ES, like the plus sign
+    EuropeanSeparator,
+    /// CS, like the decimal point
+    CommonSeparator,
+    /// ET, like currency symbols
+    EuropeanTerminator,
+    /// NSM, like vowel points
+    Mark,
+    /// WS and ON
+    Neutral,
+}
+
+impl Class {
+    fn of(character: char) -> Self {
+        match character {
+            '0'..='9' | '\u{06F0}'..='\u{06F9}' => Self::EuropeanNumber,
+            '\u{0660}'..='\u{0669}' | '\u{066B}' | '\u{066C}' => Self::ArabicNumber,
+            '+' | '-' => Self::EuropeanSeparator,
+            ',' | '.' | ':' | '/' | '\u{00A0}' => Self::CommonSeparator,
+            '#' | '$' | '%' | '\u{00A2}'..='\u{00A5}' | '\u{00B0}' | '\u{20A0}'..='\u{20CF}' => {
+                Self::EuropeanTerminator
+            }
+            '\u{0300}'..='\u{036F}'
+            | '\u{0591}'..='\u{05BD}'
+            | '\u{05BF}'
+            | '\u{05C1}'
+            | '\u{05C2}'
+            | '\u{05C4}'
+            | '\u{05C5}'
+            | '\u{05C7}'
+            | '\u{0610}'..='\u{061A}'
+            | '\u{064B}'..='\u{065F}'
+            | '\u{0670}'
+            | '\u{06D6}'..='\u{06DC}'
+            | '\u{06DF}'..='\u{06E4}'
+            | '\u{06E7}'
+            | '\u{06E8}'
+            | '\u{06EA}'..='\u{06ED}' => Self::Mark,
+            '\u{0590}'..='\u{05FF}' | '\u{07C0}'..='\u{085F}' | '\u{FB1D}'..='\u{FB4F}' => {
+                Self::Right
+            }
+            '\u{0600}'..='\u{07BF}'
+            | '\u{0860}'..='\u{08FF}'
+            | '\u{FB50}'..='\u{FDFF}'
+            | '\u{FE70}'..='\u{FEFF}' => Self::ArabicLetter,
+            _ if character.is_alphabetic() => Self::Left,
+            _ => Self::Neutral,
+        }
+    }
+
+    fn is_strong(self) -> bool {
+        matches!(self, Self::Left | Self::Right | Self::ArabicLetter)
+    }
+}
+
+impl Direction {
+    fn class(self) -> Class {
+        match self {
+            Self::LeftToRight => Class::Left,
+            Self::RightToLeft => Class::Right,
+        }
+    }
+
+    fn level(self) -> u8 {
+        match self {
+            Self::LeftToRight => 0,
+            Self::RightToLeft => 1,
+        }
+    }
+}
+
+/// Direction of the paragraph the text starts with. Left to right if it has no letters.
+pub fn paragraph_direction(text: &str) -> Direction {
+    text.chars()
+        .take_while(|&character| character != '\n')
+        .map(Class::of)
+        .find(|class| class.is_strong())
+        .map_or(Direction::LeftToRight, |class| match class {
+            Class::Left => Direction::LeftToRight,
+            _ => Direction::RightToLeft,
+        })
+}
+
+/// Whether the text has characters that are not shown left to right
+pub fn has_right_to_left(text: &str) -> bool {
+    text.chars().map(Class::of).any(|class| {
+        matches!(
+            class,
+            Class::Right | Class::ArabicLetter | Class::ArabicNumber
+        )
+    })
+}
+
+/// Puts the characters of a line in the order they are shown from left to right. Brackets in right
+/// to left text are mirrored, as their glyphs point the other way. Characters beyond
+/// [`MAXIMUM_LINE_CHARACTERS`] are left out, which pagination never produces.
+pub fn reorder(line: &str, direction: Direction) -> heapless::String<MAXIMUM_LINE_SIZE> {
+    let mut characters: PerCharacter<char> = line.chars().take(MAXIMUM_LINE_CHARACTERS).collect();
+    let mut classes: PerCharacter<Class> = characters
+        .iter()
+        .map(|&character| Class::of(character))
+        .collect();
+    resolve_weak(&mut classes, direction.class());
+    resolve_brackets(&characters, &mut classes, direction.class());
+    resolve_neutral(&mut classes, direction.class());
+
+    let mut levels: PerCharacter<u8> = classes
+        .iter()
+        .map(|&class| match (direction, class) {
+            (Direction::LeftToRight, Class::Left) => 0,
+            (Direction::LeftToRight, Class::Right) => 1,
+            (Direction::LeftToRight, _) => 2,
+            (Direction::RightToLeft, Class::Right) => 1,
+            (Direction::RightToLeft, _) => 2,
+        })
+        .collect();
+
+    // Whitespace at the end of the line goes with the paragraph (L1)
+    for (character, level) in characters.iter().zip(levels.iter_mut()).rev() {
+        if !character.is_whitespace() {
+            break;
+        }
+
+        *level = direction.level();
+    }
+
+    // L4
+    for (character, &level) in characters.iter_mut().zip(&levels) {
+        if level % 2 == 1 {
+            *character = mirror(*character);
+        }
+    }
+
+    // Reverses the runs from the highest level down to the lowest odd level (L2)
+    let highest = levels.iter().copied().max().unwrap_or(0);
+    for level in (1..=highest).rev() {
+        let mut index = 0;
+        while index < levels.len() {
+            if levels[index] < level {
+                index += 1;
+                continue;
+            }
+
+            let start = index;
+            while index < levels.len() && levels[index] >= level {
+                index += 1;
+            }
+
+            characters[start..index].reverse();
+            levels[start..index].reverse();
+        }
+    }
+
+    characters.into_iter().collect()
+}
+
+/// Resolves marks, numbers and separators (W1 to W7). Afterwards there are only left, right,
+/// numbers and neutrals.
+fn resolve_weak(classes: &mut [Class], start: Class) {
+    // Marks take the class of the character they belong to (W1)
+    let mut previous = start;
+    for class in classes.iter_mut() {
+        if *class == Class::Mark {
+            *class = previous;
+        }
+
+        previous = *class;
+    }
+
+    // Numbers after Arabic letters are Arabic numbers (W2) and Arabic letters are right to left
+    // (W3)
+    let mut last_strong = start;
+    for class in classes.iter_mut() {
+        match *class {
+            Class::ArabicLetter => {
+                last_strong = Class::ArabicLetter;
+                *class = Class::Right;
+            }
+            Class::Left | Class::Right => last_strong = *class,
+            Class::EuropeanNumber if last_strong == Class::ArabicLetter => {
+                *class = Class::ArabicNumber;
+            }
+            _ => {}
+        }
+    }
+
+    // A single separator between numbers of the same kind is part of the number (W4)
+    for index in 1..classes.len().saturating_sub(1) {
+        let (before, after) = (classes[index - 1], classes[index + 1]);
+        classes[index] = match classes[index] {
+            Class::EuropeanSeparator
+                if before == Class::EuropeanNumber && after == Class::EuropeanNumber =>
+            {
+                Class::EuropeanNumber
+            }
+            Class::CommonSeparator
+                if before == after
+                    && matches!(before, Class::EuropeanNumber | Class::ArabicNumber) =>
+            {
+                before
+            }
+            class => class,
+        };
+    }
+
+    // Terminators next to a number are part of it (W5)
+    let mut index = 0;
+    while index < classes.len() {
+        if classes[index] != Class::EuropeanTerminator {
+            index += 1;
+            continue;
+        }
+
+        let start = index;
+        while index < classes.len() && classes[index] == Class::EuropeanTerminator {
+            index += 1;
+        }
+
+        let is_next_to_number = (start > 0 && classes[start - 1] == Class::EuropeanNumber)
+            || classes.get(index) == Some(&Class::EuropeanNumber);
+        if is_next_to_number {
+            classes[start..index].fill(Class::EuropeanNumber);
+        }
+    }
+
+    // The other separators and terminators are neutral (W6) and numbers in left to right text are
+    // left to right (W7)
+    let mut last_strong = start;
+    for class in classes.iter_mut() {
+        match *class {
+            Class::Left | Class::Right => last_strong = *class,
+            Class::EuropeanSeparator | Class::CommonSeparator | Class::EuropeanTerminator => {
+                *class = Class::Neutral;
+            }
+            Class::EuropeanNumber if last_strong == Class::Left => *class = Class::Left,
+            _ => {}
+        }
+    }
+}
+
+/// Direction of a resolved class for the neutrals next to it. Numbers count as right to left.
+fn direction(class: Class) -> Class {
+    match class {
+        Class::Left => Class::Left,
+        _ => Class::Right,
+    }
+}
+
+/// Pairs of brackets take the same direction, so they enclose the same text when shown (N0)
+fn resolve_brackets(characters: &[char], classes: &mut [Class], paragraph: Class) {
+    // Opening brackets with the index of the bracket and the closing bracket that matches. Pushing
+    // can't fail, as there are no more openings or pairs than characters.
+    let mut openings: PerCharacter<(usize, char)> = PerCharacter::new();
+    let mut pairs: PerCharacter<(usize, usize)> = PerCharacter::new();
+    for (index, &character) in characters.iter().enumerate() {
+        match character {
+            '(' | '[' | '{' => {
+                let _ = openings.push((index, mirror(character)));
+            }
+            ')' | ']' | '}' => {
+                if let Some(opening) = openings
+                    .iter()
+                    .rposition(|&(_, closing)| closing == character)
+                {
+                    let _ = pairs.push((openings[opening].0, index));
+                    openings.truncate(opening);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    pairs.sort_unstable();
+    for (opening, closing) in pairs {
+        let mut inside = classes[opening + 1..closing]
+            .iter()
+            .filter(|&&class| class != Class::Neutral)
+            .map(|&class| direction(class));
+        let resolved = if inside.clone().any(|class| class == paragraph) {
+            paragraph
+        } else if let Some(opposite) = inside.next() {
+            // Only takes the opposite direction if the text before has it too
+            let before = classes[..opening]
+                .iter()
+                .rfind(|&&class| class != Class::Neutral)
+                .map_or(paragraph, |&class| direction(class));
+            if before == opposite {
+                opposite
+            } else {
+                paragraph
+            }
+        } else {
+            continue;
+        };
+
+        classes[opening] = resolved;
+        classes[closing] = resolved;
+    }
+}
+
+/// Neutrals between text of the same direction take that direction and the direction of the
+/// paragraph otherwise (N1 and N2)
+fn resolve_neutral(classes: &mut [Class], paragraph: Class) {
+    let mut index = 0;
+    while index < classes.len() {
+        if classes[index] != Class::Neutral {
+            index += 1;
+            continue;
+        }
+
+        let start = index;
+        while index < classes.len() && classes[index] == Class::Neutral {
+            index += 1;
+        }
+
+        let before = start
+            .checked_sub(1)
+            .map_or(paragraph, |before| direction(classes[before]));
+        let after = classes
+            .get(index)
+            .map_or(paragraph, |&after| direction(after));
+        let resolved = if before == after { before } else { paragraph };
+        classes[start..index].fill(resolved);
+    }
+}
+
+/// The character that looks like the mirror image
+fn mirror(character: char) -> char {
+    match character {
+        '(' => ')',
+        ')' => '(',
+        '[' => ']',
+        ']' => '[',
+        '{' => '}',
+        '}' => '{',
+        '<' => '>',
+        '>' => '<',
+        '«' => '»',
+        '»' => '«',
+        '‹' => '›',
+        '›' => '‹',
+        character => character,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn paragraphs_take_the_direction_of_their_first_letter() {
+        assert_eq!(
+            paragraph_direction("12 \u{05D0}\u{05D1} abc"),
+            Direction::RightToLeft
+        );
+        assert_eq!(
+            paragraph_direction("abc \u{05D0}\u{05D1}"),
+            Direction::LeftToRight
+        );
+        // Only the first paragraph counts
+        assert_eq!(paragraph_direction("123\n\u{05D0}"), Direction::LeftToRight);
+    }
+
+    #[test]
+    fn left_to_right_text_stays_in_order() {
+        let line = "Alice (and the rabbit) 1.5";
+        assert!(!has_right_to_left(line));
+        assert_eq!(reorder(line, Direction::LeftToRight).as_str(), line);
+    }
+
+    #[test]
+    fn right_to_left_words_in_left_to_right_text_are_reversed() {
+        assert_eq!(
+            reorder("abc \u{05D0}\u{05D1}\u{05D2} def", Direction::LeftToRight).as_str(),
+            "abc \u{05D2}\u{05D1}\u{05D0} def"
+        );
+    }
+
+    #[test]
+    fn left_to_right_words_in_right_to_left_text_keep_their_order() {
+        assert_eq!(
+            reorder("\u{05D0}\u{05D1}\u{05D2} abc", Direction::RightToLeft).as_str(),
+            "abc \u{05D2}\u{05D1}\u{05D0}"
+        );
+    }
+
+    #[test]
+    fn numbers_in_right_to_left_text_keep_their_order() {
+        assert_eq!(
+            reorder("\u{05D0}\u{05D1} 123", Direction::RightToLeft).as_str(),
+            "123 \u{05D1}\u{05D0}"
+        );
+        // The separator is part of the number
+        assert_eq!(
+            reorder("\u{05D0}\u{05D1} 1.5", Direction::RightToLeft).as_str(),
+            "1.5 \u{05D1}\u{05D0}"
+        );
+    }
+
+    #[test]
+    fn numbers_after_arabic_letters_are_arabic_numbers() {
+        // Arabic numbers in left to right text are shown right to left with the letters before
+        assert_eq!(
+            reorder("a \u{0628}\u{062A} 12", Direction::LeftToRight).as_str(),
+            "a 12 \u{062A}\u{0628}"
+        );
+    }
+
+    #[test]
+    fn brackets_in_right_to_left_text_are_mirrored() {
+        assert_eq!(
+            reorder(
+                "\u{05D0}\u{05D1} (\u{05D2}\u{05D3})",
+                Direction::RightToLeft
+            )
+            .as_str(),
+            "(\u{05D3}\u{05D2}) \u{05D1}\u{05D0}"
+        );
+    }
+
+    #[test]
+    fn brackets_around_left_to_right_text_enclose_it() {
+        assert_eq!(
+            reorder("\u{05D0}\u{05D1} (abc)", Direction::RightToLeft).as_str(),
+            "(abc) \u{05D1}\u{05D0}"
+        );
+    }
+}
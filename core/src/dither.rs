@@ -0,0 +1,168 @@
+//! Converts 8-bit grayscale images to the 1-bit format of the panel, e.g. for cover art and sleep
+//! screen photos. Rows are pushed one at a time so that images can be decoded as a stream without
+//! keeping the whole image in memory.
+
+use alloc::vec;
+use alloc::vec::Vec;
+
+use embedded_graphics::{Pixel, pixelcolor::BinaryColor, prelude::*};
+
+/// Gray values from this on are white
+const THRESHOLD: i16 = 128;
+
+/// 4x4 Bayer matrix scaled to the 0..=255 range
+const BAYER_MATRIX: [[u8; 4]; 4] = [
+    [8, 136, 40, 168],
+    [200, 72, 232, 104],
+    [56, 184, 24, 152],
+    [248, 120, 216, 88],
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Method {
+    /// Error diffusion with the best quality
+    FloydSteinberg,
+    /// Cheaper, with a regular pattern, but no state between rows
+    Ordered,
+}
+
+pub struct Ditherer {
+    method: Method,
+    /// Where the top left pixel of the image is drawn
+    origin: Point,
+    width: usize,
+    row: i32,
+    /// Error diffused into the current row. Has a pixel of padding on each side so the edges
+    /// don't need special cases.
+    current_errors: Vec<i16>,
+    /// Error diffused into the next row
+    next_errors: Vec<i16>,
+}
+
+impl Ditherer {
+    pub fn new(method: Method, origin: Point, width: usize) -> Self {
+        let errors = match method {
+            Method::FloydSteinberg => width + 2,
+            Method::Ordered => 0,
+        };
+
+        Self {
+            method,
+            origin,
+            width,
+            row: 0,
+            current_errors: vec![0; errors],
+            next_errors: vec![0; errors],
+        }
+    }
+
+    /// Dithers the next row of gray values where 0 is black and 255 is white and draws it.
+    /// Rows longer than the width are cut off.
+    pub fn push_row<D>(&mut self, gray: &[u8], target: &mut D) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = BinaryColor>,
+    {
+        let gray = &gray[..gray.len().min(self.width)];
+        let y = self.origin.y + self.row;
+        self.row += 1;
+
+        match self.method {
+            Method::Ordered => {
+                let matrix_row = &BAYER_MATRIX[y.rem_euclid(4) as usize];
+                let pixels = gray.iter().zip(self.origin.x..).map(|(value, x)| {
+                    let threshold = matrix_row[x.rem_euclid(4) as usize];
+                    Pixel(Point::new(x, y), to_color(*value > threshold))
+                });
+
+                target.draw_iter(pixels)
+            }
+            Method::FloydSteinberg => {
+                core::mem::swap(&mut self.current_errors, &mut self.next_errors);
+                self.next_errors.fill(0);
+
+                let current_errors = &mut self.current_errors;
+                let next_errors = &mut self.next_errors;
+                let pixels =
+                    gray.iter()
+                        .zip(self.origin.x..)
+                        .enumerate()
+                        .map(move |(index, (value, x))| {
+                            // Shifted by the padding
+                            let index = index + 1;
+                            let value = i16::from(*value) + current_errors[index];
+                            let is_white = value >= THRESHOLD;
+                            let error = value - if is_white { 255 } else { 0 };
+
+                            // Distribute the error to the neighbors not yet visited
+                            current_errors[index + 1] += error * 7 / 16;
+                            next_errors[index - 1] += error * 3 / 16;
+                            next_errors[index] += error * 5 / 16;
+                            next_errors[index + 1] += error / 16;
+
+                            Pixel(Point::new(x, y), to_color(is_white))
+                        });
+
+                target.draw_iter(pixels)
+            }
+        }
+    }
+}
+
+/// Ink is drawn with "on"
+fn to_color(is_white: bool) -> BinaryColor {
+    if is_white {
+        BinaryColor::Off
+    } else {
+        BinaryColor::On
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+
+    use embedded_graphics::mock_display::MockDisplay;
+
+    use super::*;
+
+    fn dither(method: Method, gray: u8) -> MockDisplay<BinaryColor> {
+        let mut display = MockDisplay::new();
+        let mut ditherer = Ditherer::new(method, Point::new(2, 1), 8);
+        for _ in 0..8 {
+            ditherer.push_row(&[gray; 8], &mut display).unwrap();
+        }
+        display
+    }
+
+    fn black_pixels(display: &MockDisplay<BinaryColor>) -> usize {
+        let area = display.affected_area();
+        area.points()
+            .filter(|point| display.get_pixel(*point) == Some(BinaryColor::On))
+            .count()
+    }
+
+    #[test]
+    fn black_and_white_stay_solid() {
+        for method in [Method::FloydSteinberg, Method::Ordered] {
+            assert_eq!(black_pixels(&dither(method, 0)), 64);
+            assert_eq!(black_pixels(&dither(method, 255)), 0);
+        }
+    }
+
+    #[test]
+    fn gray_is_about_half_black() {
+        for method in [Method::FloydSteinberg, Method::Ordered] {
+            let black = black_pixels(&dither(method, 128));
+            assert!((28..=36).contains(&black), "{method:?} drew {black}");
+        }
+    }
+
+    #[test]
+    fn rows_start_at_origin_and_are_cut_at_width() {
+        let mut display = MockDisplay::new();
+        let mut ditherer = Ditherer::new(Method::Ordered, Point::new(2, 1), 4);
+        ditherer.push_row(&[0; 10], &mut display).unwrap();
+        let drawn: Vec<Point> = display.affected_area().points().collect();
+        assert_eq!(drawn, (2..6).map(|x| Point::new(x, 1)).collect::<Vec<_>>());
+    }
+}
@@ -0,0 +1,663 @@
+use core::mem::MaybeUninit;
+use core::ops::{Deref, DerefMut, Range, RangeInclusive};
+
+use embedded_graphics::{
+    Pixel,
+    pixelcolor::BinaryColor,
+    prelude::{Dimensions, DrawTarget, OriginDimensions, Point, Size, Transform},
+    primitives::Rectangle,
+};
+
+use crate::{DISPLAY_HEIGHT, DISPLAY_WIDTH, Region};
+
+#[allow(dead_code, reason = "only portrait is drawn so far")]
+enum Orientation {
+    Portrait,
+    Landscape,
+}
+
+/// How drawn pixels combine with what is in the frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, defmt::Format)]
+pub enum DrawMode {
+    /// Pixels take the color they are drawn with
+    #[default]
+    Normal,
+    /// Pixels take the opposite color, e.g. for text on a black background
+    Invert,
+    /// Black flips the pixels and white leaves them alone. Drawing the same twice brings back what
+    /// was there, so a selection highlight can move without drawing what is below it again.
+    Xor,
+}
+
+impl DrawMode {
+    /// The byte with the pixels in the mask drawn in the color
+    fn apply(self, byte: u8, mask: u8, color: BinaryColor) -> u8 {
+        // Black clears the bits
+        match (self, color) {
+            (Self::Normal, BinaryColor::On) | (Self::Invert, BinaryColor::Off) => byte & !mask,
+            (Self::Normal, BinaryColor::Off) | (Self::Invert, BinaryColor::On) => byte | mask,
+            (Self::Xor, BinaryColor::On) => byte ^ mask,
+            (Self::Xor, BinaryColor::Off) => byte,
+        }
+    }
+}
+
+pub struct Frame {
+    buffer: [u8; Self::BUFFER_SIZE],
+    /// The orientation is an experimental idea to allow for different display orientations.
+    orientation: Orientation,
+    draw_mode: DrawMode,
+}
+
+impl Frame {
+    // The display is in portrait mode by default
+    const WIDTH: u16 = DISPLAY_WIDTH;
+    const HEIGHT: u16 = DISPLAY_HEIGHT;
+
+    /// Each bit in a byte represents a pixel (0 = off, 1 = on)
+    const WIDTH_BYTES: usize = {
+        // There is no div_exact yet
+        assert!(
+            Self::WIDTH.is_multiple_of(8),
+            "Display width must be a multiple of 8"
+        );
+
+        // Can't truncate as a u16 fits into a usize
+        (Self::WIDTH / 8) as usize
+    };
+    pub const BUFFER_SIZE: usize = {
+        // Can't truncate as a u16 fits into a usize
+        let Some(size) = Self::WIDTH_BYTES.checked_mul(Self::HEIGHT as usize) else {
+            panic!("Frame buffer size overflows");
+        };
+
+        size
+    };
+    /// Drawing is in portrait, so the panel rows are the columns
+    pub const SIZE: Size = Size::new(Self::HEIGHT as u32, Self::WIDTH as u32);
+
+    /// The bytes of each buffer row within the region. The region needs to be valid.
+    pub fn region_rows(&self, region: Region) -> impl Iterator<Item = &[u8]> {
+        let start = usize::from(region.x / 8);
+        let end = start + usize::from(region.width / 8);
+
+        let (rows, _) = self.buffer.as_chunks::<{ Self::WIDTH_BYTES }>();
+        rows.iter()
+            .skip(usize::from(region.y))
+            .take(usize::from(region.height))
+            .map(move |row| &row[start..end])
+    }
+
+    /// Where the frame differs from the other one, e.g. from what the panel shows. None if they are
+    /// the same.
+    pub fn difference(&self, other: &Frame) -> Option<Difference> {
+        let mut rows: Option<RangeInclusive<usize>> = None;
+        let mut columns: Option<RangeInclusive<usize>> = None;
+        let mut changed_bytes = 0;
+        let (new_rows, _) = self.buffer.as_chunks::<{ Self::WIDTH_BYTES }>();
+        let (old_rows, _) = other.buffer.as_chunks::<{ Self::WIDTH_BYTES }>();
+        let row_pairs = new_rows.iter().zip(old_rows);
+        for (row, (new, old)) in row_pairs.enumerate() {
+            // Most rows are the same when only a part of the screen changed
+            if new == old {
+                continue;
+            }
+
+            for (column, _) in new
+                .iter()
+                .zip(old)
+                .enumerate()
+                .filter(|(_, (new, old))| new != old)
+            {
+                changed_bytes += 1;
+                columns = Some(match columns {
+                    Some(columns) => *columns.start().min(&column)..=*columns.end().max(&column),
+                    None => column..=column,
+                });
+            }
+
+            rows = Some(match rows {
+                Some(rows) => *rows.start()..=row,
+                None => row..=row,
+            });
+        }
+
+        let (rows, columns) = (rows?, columns?);
+        // Can't truncate as the panel is less than 1000 pixels on each side
+        let region = Region {
+            x: (columns.start() * 8) as u16,
+            y: *rows.start() as u16,
+            width: (columns.count() * 8) as u16,
+            height: rows.count() as u16,
+        };
+
+        Some(Difference {
+            region,
+            changed_bytes,
+        })
+    }
+
+    /// Makes the frame the same as the other one
+    pub fn copy_from(&mut self, other: &Frame) {
+        self.buffer.copy_from_slice(&other.buffer);
+    }
+
+    /// Makes the region the same as in the other frame, e.g. to keep a copy of what the panel shows
+    /// after only the region was updated. The region needs to be valid.
+    pub fn copy_region_from(&mut self, other: &Frame, region: Region) {
+        let start = usize::from(region.x / 8);
+        let end = start + usize::from(region.width / 8);
+        let (rows, _) = self.buffer.as_chunks_mut::<{ Self::WIDTH_BYTES }>();
+        let (other_rows, _) = other.buffer.as_chunks::<{ Self::WIDTH_BYTES }>();
+        let row_pairs = rows
+            .iter_mut()
+            .zip(other_rows)
+            .skip(usize::from(region.y))
+            .take(usize::from(region.height));
+        for (row, other_row) in row_pairs {
+            row[start..end].copy_from_slice(&other_row[start..end]);
+        }
+    }
+
+    /// Applies to everything drawn from now on except for copies of bytes and frames. Drawing code
+    /// that changes it should set it back to normal when done.
+    pub fn set_draw_mode(&mut self, mode: DrawMode) {
+        self.draw_mode = mode;
+    }
+
+    pub fn draw_mode(&self) -> DrawMode {
+        self.draw_mode
+    }
+
+    /// Fills the part of the area within the frame a byte at a time instead of pixel by pixel
+    pub fn fill_rect(&mut self, area: &Rectangle, color: BinaryColor) {
+        let area = area.intersection(&self.bounding_box());
+        if area.is_zero_sized() {
+            return;
+        }
+
+        // Frame Y runs along the buffer rows and frame X against them, see draw_iter
+        // Can't truncate as the area is within the frame
+        let left = area.top_left.y as usize;
+        let columns = left..left + area.size.height as usize;
+        let first_row =
+            usize::from(Self::HEIGHT) - area.top_left.x as usize - area.size.width as usize;
+        for row in first_row..first_row + area.size.width as usize {
+            self.fill_row(row, columns.clone(), color);
+        }
+    }
+
+    /// Line to the right of the start, clipped to the frame
+    pub fn horizontal_line(&mut self, start: Point, length: u32, color: BinaryColor) {
+        self.fill_rect(&Rectangle::new(start, Size::new(length, 1)), color);
+    }
+
+    /// Line down from the start, clipped to the frame. Along a buffer row, so it is the quicker
+    /// one.
+    pub fn vertical_line(&mut self, start: Point, length: u32, color: BinaryColor) {
+        self.fill_rect(&Rectangle::new(start, Size::new(1, length)), color);
+    }
+
+    /// Copies the bytes into the region, row after row like [`Self::region_rows`] returns them,
+    /// e.g. to put back a part of a frame that was saved before
+    pub fn blit(&mut self, bytes: &[u8], region: Region) -> Result<(), DrawError> {
+        if !region.is_valid() {
+            return Err(DrawError::OutOfBounds);
+        }
+
+        let start = usize::from(region.x / 8);
+        let width = usize::from(region.width / 8);
+        if bytes.len() != width * usize::from(region.height) {
+            return Err(DrawError::LengthMismatch);
+        }
+
+        let (rows, _) = self.buffer.as_chunks_mut::<{ Self::WIDTH_BYTES }>();
+        let rows = rows.iter_mut().skip(usize::from(region.y));
+        for (row, source) in rows.zip(bytes.chunks_exact(width)) {
+            row[start..start + width].copy_from_slice(source);
+        }
+
+        Ok(())
+    }
+
+    /// Color of the pixel or None if it is outside of the frame
+    pub fn get_pixel(&self, point: Point) -> Option<BinaryColor> {
+        let (row, column) = Self::position(point)?;
+        let byte = self.buffer[row * Self::WIDTH_BYTES + column / 8];
+        if byte & (0b1000_0000 >> (column % 8)) == 0 {
+            Some(BinaryColor::On)
+        } else {
+            Some(BinaryColor::Off)
+        }
+    }
+
+    /// Copies the area of the other frame to the destination in this one, e.g. to put back what was
+    /// under a popup without drawing the page again. Parts outside of either frame are skipped.
+    pub fn copy_region(&mut self, source: &Frame, area: Rectangle, destination: Point) {
+        let offset = destination - area.top_left;
+        let area = area
+            .intersection(&self.bounding_box())
+            .translate(offset)
+            .intersection(&self.bounding_box())
+            .translate(-offset);
+        if area.is_zero_sized() {
+            return;
+        }
+
+        // Frame Y runs along the buffer rows, so each frame column is a span of a buffer row
+        // Can't truncate as the area is within both frames
+        let length = area.size.height as usize;
+        for x in area.top_left.x..area.top_left.x + area.size.width as i32 {
+            let from = Point::new(x, area.top_left.y);
+            let (Some((source_row, source_column)), Some((row, column))) =
+                (Self::position(from), Self::position(from + offset))
+            else {
+                continue;
+            };
+
+            let source_row = &source.buffer
+                [source_row * Self::WIDTH_BYTES..(source_row + 1) * Self::WIDTH_BYTES];
+            let row = &mut self.buffer[row * Self::WIDTH_BYTES..(row + 1) * Self::WIDTH_BYTES];
+            copy_bits(source_row, source_column, row, column, length);
+        }
+    }
+
+    /// Buffer row and column of the point or None if it is outside of the frame, see draw_iter
+    fn position(point: Point) -> Option<(usize, usize)> {
+        let x = u16::try_from(point.x).ok().filter(|x| *x < Self::HEIGHT)?;
+        let y = u16::try_from(point.y).ok().filter(|y| *y < Self::WIDTH)?;
+        Some((usize::from(Self::HEIGHT - 1 - x), usize::from(y)))
+    }
+
+    /// Sets the pixels of the columns within a buffer row to the color. The columns must not be
+    /// empty.
+    fn fill_row(&mut self, row: usize, columns: Range<usize>, color: BinaryColor) {
+        let row = &mut self.buffer[row * Self::WIDTH_BYTES..(row + 1) * Self::WIDTH_BYTES];
+        let draw_mode = self.draw_mode;
+        let (first, last) = (columns.start / 8, (columns.end - 1) / 8);
+        for (index, byte) in row[first..=last].iter_mut().enumerate() {
+            let index = first + index;
+            let start = if index == first { columns.start % 8 } else { 0 };
+            let end = if index == last {
+                (columns.end - 1) % 8 + 1
+            } else {
+                8
+            };
+            // The leftmost pixel is the most significant bit
+            let mask = (0xFF_u8 >> start) & (0xFF_u8 << (8 - end));
+            *byte = draw_mode.apply(*byte, mask, color);
+        }
+    }
+}
+
+/// Copies the bits of a span of pixels from one buffer row to another. Whole bytes are copied when
+/// both spans start at the same bit.
+fn copy_bits(source: &[u8], source_start: usize, row: &mut [u8], start: usize, length: usize) {
+    let mut index = 0;
+    while index < length {
+        let (from, to) = (source_start + index, start + index);
+        if from.is_multiple_of(8) && to.is_multiple_of(8) && length - index >= 8 {
+            let bytes = (length - index) / 8;
+            row[to / 8..to / 8 + bytes].copy_from_slice(&source[from / 8..from / 8 + bytes]);
+            index += bytes * 8;
+            continue;
+        }
+
+        let mask = 0b1000_0000 >> (to % 8);
+        if source[from / 8] & (0b1000_0000 >> (from % 8)) == 0 {
+            row[to / 8] &= !mask;
+        } else {
+            row[to / 8] |= mask;
+        }
+        index += 1;
+    }
+}
+
+/// How a frame differs from another
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct Difference {
+    /// Covers all pixels that changed
+    pub region: Region,
+    /// Bytes of the buffer that changed, each with up to 8 pixels
+    pub changed_bytes: usize,
+}
+
+impl Frame {
+    /// Makes the frame white in place, as building it first would put it on the stack. Frames are
+    /// most of the RAM, so they live in statics or on the heap.
+    pub fn initialize(frame: &mut MaybeUninit<Frame>) -> &mut Frame {
+        let pointer = frame.as_mut_ptr();
+        // SAFETY: The pointer is valid for writes and every field is written before the frame is
+        // assumed to be initialized
+        unsafe {
+            (&raw mut (*pointer).buffer).write_bytes(0b1111_1111, 1);
+            (&raw mut (*pointer).orientation).write(Orientation::Portrait);
+            (&raw mut (*pointer).draw_mode).write(DrawMode::Normal);
+            frame.assume_init_mut()
+        }
+    }
+}
+
+impl Deref for Frame {
+    type Target = [u8];
+
+    fn deref(&self) -> &Self::Target {
+        &self.buffer
+    }
+}
+
+impl DerefMut for Frame {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.buffer
+    }
+}
+
+impl OriginDimensions for Frame {
+    fn size(&self) -> Size {
+        Self::SIZE
+    }
+}
+
+#[derive(Debug, defmt::Format)]
+pub enum DrawError {
+    /// If more details about the error are needed at runtime, then add them
+    OutOfBounds,
+    /// The bytes don't fill the region
+    LengthMismatch,
+}
+
+impl DrawTarget for Frame {
+    type Color = BinaryColor;
+
+    type Error = DrawError;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = embedded_graphics::Pixel<Self::Color>>,
+    {
+        const X_RANGE: Range<u16> = 0..Frame::HEIGHT;
+        const Y_RANGE: Range<u16> = 0..Frame::WIDTH;
+
+        for Pixel(point, color) in pixels {
+            let x = u16::try_from(point.x).map_err(|_| DrawError::OutOfBounds)?;
+            let y = u16::try_from(point.y).map_err(|_| DrawError::OutOfBounds)?;
+
+            if !X_RANGE.contains(&x) || !Y_RANGE.contains(&y) {
+                return Err(DrawError::OutOfBounds);
+            }
+
+            // Map to pixel on hardware
+            let x_hardware = usize::from(y);
+            // Display is inverted
+            let y_hardware = usize::from(DISPLAY_HEIGHT - x);
+            // Make it zero-indexed
+            let y_index = y_hardware - 1;
+
+            let row_start = y_index * Frame::WIDTH_BYTES;
+            // Locate the byte that contains the pixel. This is a floor division
+            let row_pixel_index = x_hardware / 8;
+            let index = row_start + row_pixel_index;
+            // The remainder defines the bit index within the byte. The part that is left over from finding the pixel index in the row (x_hardware / 8)
+            let bit_index = 7 - x_hardware % 8;
+
+            // E-Ink dark is charged = black and clears the bit, light is not charged = white
+            self.buffer[index] = self
+                .draw_mode
+                .apply(self.buffer[index], 1 << bit_index, color);
+        }
+        Ok(())
+    }
+
+    fn fill_solid(&mut self, area: &Rectangle, color: Self::Color) -> Result<(), Self::Error> {
+        self.fill_rect(area, color);
+        // Drawing pixel by pixel fails for the pixels outside as well
+        if !area.is_zero_sized() && area.intersection(&self.bounding_box()) != *area {
+            return Err(DrawError::OutOfBounds);
+        }
+
+        Ok(())
+    }
+
+    fn clear(&mut self, color: Self::Color) -> Result<(), Self::Error> {
+        self.fill_rect(&self.bounding_box(), color);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::boxed::Box;
+    use alloc::vec::Vec;
+
+    use embedded_graphics::Drawable;
+    use embedded_graphics::prelude::{PointsIter, Primitive};
+    use embedded_graphics::primitives::PrimitiveStyle;
+
+    use super::*;
+
+    fn white_frame() -> Box<Frame> {
+        let mut frame = Box::new_uninit();
+        Frame::initialize(&mut frame);
+        // SAFETY: Initialized right before
+        unsafe { frame.assume_init() }
+    }
+
+    /// The pixels that are black, by buffer row and column
+    fn black_bits(frame: &Frame) -> Vec<(usize, usize)> {
+        let (rows, _) = frame.as_chunks::<{ Frame::WIDTH_BYTES }>();
+        rows.iter()
+            .enumerate()
+            .flat_map(|(row, bytes)| {
+                (0..usize::from(DISPLAY_WIDTH))
+                    .filter(move |column| bytes[column / 8] & (0b1000_0000 >> (column % 8)) == 0)
+                    .map(move |column| (row, column))
+            })
+            .collect()
+    }
+
+    fn draw_pixel(frame: &mut Frame, point: Point, color: BinaryColor) -> Result<(), DrawError> {
+        frame.draw_iter([Pixel(point, color)])
+    }
+
+    #[test]
+    fn portrait_is_turned_onto_landscape_panel() {
+        let mut frame = white_frame();
+        assert_eq!(frame.size(), Size::new(480, 800));
+
+        // Top left of the portrait frame is the start of the last panel row
+        draw_pixel(&mut frame, Point::new(0, 0), BinaryColor::On).unwrap();
+        assert_eq!(black_bits(&frame), [(479, 0)]);
+        assert_eq!(frame[479 * 100], 0b0111_1111);
+
+        // Bottom right is the end of the first row
+        let mut frame = white_frame();
+        draw_pixel(&mut frame, Point::new(479, 799), BinaryColor::On).unwrap();
+        assert_eq!(black_bits(&frame), [(0, 799)]);
+        assert_eq!(frame[99], 0b1111_1110);
+
+        // Down the portrait frame is along a row
+        let mut frame = white_frame();
+        draw_pixel(&mut frame, Point::new(10, 9), BinaryColor::On).unwrap();
+        assert_eq!(black_bits(&frame), [(469, 9)]);
+        assert_eq!(frame[469 * 100 + 1], 0b1011_1111);
+    }
+
+    #[test]
+    fn every_pixel_has_its_own_bit() {
+        let mut frame = white_frame();
+        for x in 0..480 {
+            for y in 0..800 {
+                let point = Point::new(x, y);
+                let (row, column) = Frame::position(point).unwrap();
+                let index = row * Frame::WIDTH_BYTES + column / 8;
+                let mask = 0b1000_0000 >> (column % 8);
+                assert_ne!(frame[index] & mask, 0, "{point} was drawn before");
+
+                draw_pixel(&mut frame, point, BinaryColor::On).unwrap();
+                assert_eq!(frame[index] & mask, 0, "{point} is not at its bit");
+                assert_eq!(frame.get_pixel(point), Some(BinaryColor::On));
+            }
+        }
+
+        // Each pixel cleared a different bit, so all are black now
+        assert!(frame.iter().all(|byte| *byte == 0));
+    }
+
+    #[test]
+    fn pixels_outside_fail() {
+        let mut frame = white_frame();
+        for point in [
+            Point::new(-1, 0),
+            Point::new(0, -1),
+            Point::new(480, 0),
+            Point::new(0, 800),
+            Point::new(i32::MAX, i32::MIN),
+        ] {
+            assert!(matches!(
+                draw_pixel(&mut frame, point, BinaryColor::On),
+                Err(DrawError::OutOfBounds)
+            ));
+            assert_eq!(frame.get_pixel(point), None);
+        }
+        assert!(black_bits(&frame).is_empty());
+    }
+
+    #[test]
+    fn filled_rectangles_match_pixels() {
+        let areas = [
+            Rectangle::new(Point::new(0, 0), Size::new(480, 800)),
+            Rectangle::new(Point::new(3, 5), Size::new(1, 1)),
+            Rectangle::new(Point::new(17, 3), Size::new(40, 13)),
+            Rectangle::new(Point::new(100, 8), Size::new(7, 16)),
+            Rectangle::new(Point::new(479, 790), Size::new(1, 10)),
+        ];
+
+        for area in areas {
+            let mut filled = white_frame();
+            filled.fill_rect(&area, BinaryColor::On);
+            let mut drawn = white_frame();
+            area.into_styled(PrimitiveStyle::with_fill(BinaryColor::On))
+                .draw(&mut *drawn)
+                .unwrap();
+            assert!(filled[..] == drawn[..], "{area:?} differs");
+        }
+    }
+
+    #[test]
+    fn rectangles_are_clipped() {
+        let mut frame = white_frame();
+        let area = Rectangle::new(Point::new(-5, 795), Size::new(10, 10));
+        frame.fill_rect(&area, BinaryColor::On);
+        assert_eq!(black_bits(&frame).len(), 5 * 5);
+        assert!(matches!(
+            frame.fill_solid(&area, BinaryColor::On),
+            Err(DrawError::OutOfBounds)
+        ));
+    }
+
+    #[test]
+    fn xor_twice_restores() {
+        let mut frame = white_frame();
+        frame.fill_rect(
+            &Rectangle::new(Point::new(10, 10), Size::new(30, 30)),
+            BinaryColor::On,
+        );
+        let before = frame.to_vec();
+
+        frame.set_draw_mode(DrawMode::Xor);
+        let highlight = Rectangle::new(Point::new(0, 20), Size::new(480, 20));
+        frame.fill_rect(&highlight, BinaryColor::On);
+        assert!(frame[..] != before[..]);
+        frame.fill_rect(&highlight, BinaryColor::On);
+        assert!(frame[..] == before[..]);
+    }
+
+    #[test]
+    fn difference_covers_changed_bytes() {
+        let old = white_frame();
+        let mut new = white_frame();
+        assert_eq!(new.difference(&old), None);
+
+        draw_pixel(&mut new, Point::new(0, 0), BinaryColor::On).unwrap();
+        draw_pixel(&mut new, Point::new(2, 20), BinaryColor::On).unwrap();
+        let difference = new.difference(&old).unwrap();
+        assert_eq!(
+            difference.region,
+            Region {
+                x: 0,
+                y: 477,
+                width: 24,
+                height: 3
+            }
+        );
+        assert_eq!(difference.changed_bytes, 2);
+        assert_eq!(
+            Region::covering(Rectangle::new(Point::new(0, 0), Size::new(3, 21))),
+            Some(difference.region)
+        );
+    }
+
+    #[test]
+    fn copied_areas_match_pixels() {
+        let mut source = white_frame();
+        for index in 0..200 {
+            // Scattered pixels, so misplaced bits show
+            let point = Point::new(index * 7 % 480, index * 13 % 800);
+            draw_pixel(&mut source, point, BinaryColor::On).unwrap();
+        }
+
+        let area = Rectangle::new(Point::new(5, 3), Size::new(60, 90));
+        for destination in [Point::new(5, 3), Point::new(100, 16), Point::new(101, 21)] {
+            let mut copy = white_frame();
+            copy.copy_region(&source, area, destination);
+
+            let offset = destination - area.top_left;
+            for point in area.points() {
+                assert_eq!(copy.get_pixel(point + offset), source.get_pixel(point));
+            }
+            assert_eq!(
+                black_bits(&copy).len(),
+                area.points()
+                    .filter(|point| source.get_pixel(*point) == Some(BinaryColor::On))
+                    .count()
+            );
+        }
+    }
+
+    #[test]
+    fn blitted_bytes_come_back_as_rows() {
+        let mut frame = white_frame();
+        let region = Region {
+            x: 16,
+            y: 2,
+            width: 16,
+            height: 3,
+        };
+        let bytes = [1, 2, 3, 4, 5, 6];
+        frame.blit(&bytes, region).unwrap();
+        let rows: Vec<&[u8]> = frame.region_rows(region).collect();
+        assert_eq!(rows, [&[1, 2][..], &[3, 4], &[5, 6]]);
+
+        assert!(matches!(
+            frame.blit(&bytes[..5], region),
+            Err(DrawError::LengthMismatch)
+        ));
+    }
+    #[test]
+    fn copied_region_leaves_the_rest() {
+        let mut frame = white_frame();
+        let mut other = white_frame();
+        other.fill(0);
+        let region = Region {
+            x: 8,
+            y: 1,
+            width: 16,
+            height: 2,
+        };
+        frame.copy_region_from(&other, region);
+
+        let changed = frame.difference(&white_frame()).unwrap();
+        assert_eq!(changed.region, region);
+        assert_eq!(changed.changed_bytes, 4);
+    }
+}
@@ -0,0 +1,227 @@
+//! The buttons sit on two resistor ladders that are read by the ADC. Each button pulls the level of
+//! its ladder down to its own step, so the level tells which button is pressed. The thresholds
+//! between the steps were measured on one unit, so they can be measured again with the calibration.
+
+/// Measured values and rough midway points
+/// Midway points:     ~2850 ~2300 ~1550 ~550
+/// Recorded values: 3087, 2629, 2013, 1117, 4
+const LADDER_1_THRESHOLDS: [u16; 5] = [2850, 2300, 1550, 550, 0];
+
+/// Measured values and rough midway points
+/// Midway points:               ~2350  ~850
+/// Recorded values:            3087, 1670, 4
+const LADDER_2_THRESHOLDS: [u16; 3] = [2350, 850, 0];
+
+/// How far the value has to move past the range of the active button to leave it. Well below the
+/// distance between the thresholds and the recorded values.
+const HYSTERESIS: u16 = 100;
+/// Levels of neighboring buttons closer than this can't be told apart reliably
+const MINIMUM_LEVEL_DISTANCE: u16 = 4 * HYSTERESIS;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Ladder {
+    /// Back, confirm, left and right
+    One,
+    /// Up and down
+    Two,
+}
+
+impl Ladder {
+    const fn buttons(self) -> u8 {
+        match self {
+            Self::One => 4,
+            Self::Two => 2,
+        }
+    }
+}
+
+/// Thresholds between the levels of the buttons on each ladder from the highest level down
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct Calibration {
+    ladder_1: [u16; 5],
+    ladder_2: [u16; 3],
+}
+
+impl Default for Calibration {
+    fn default() -> Self {
+        Self {
+            ladder_1: LADDER_1_THRESHOLDS,
+            ladder_2: LADDER_2_THRESHOLDS,
+        }
+    }
+}
+
+impl Calibration {
+    /// Puts the thresholds halfway between the levels with nothing pressed and with each button
+    /// pressed. None if the levels are not in the order of the buttons or too close to tell apart.
+    pub fn from_levels(
+        released: (u16, u16),
+        ladder_1: [u16; 4],
+        ladder_2: [u16; 2],
+    ) -> Option<Self> {
+        let mut calibration = Self::default();
+        let mut previous = released.0;
+        for (threshold, level) in calibration.ladder_1.iter_mut().zip(ladder_1) {
+            *threshold = midpoint(previous, level)?;
+            previous = level;
+        }
+
+        let mut previous = released.1;
+        for (threshold, level) in calibration.ladder_2.iter_mut().zip(ladder_2) {
+            *threshold = midpoint(previous, level)?;
+            previous = level;
+        }
+
+        Some(calibration)
+    }
+
+    /// The thresholds without the last one which is always 0, e.g. `2850,2300,1550,550,2350,850`
+    pub fn parse(value: &str) -> Option<Self> {
+        let mut numbers = value.split(',').map(|number| number.trim().parse::<u16>());
+        let mut calibration = Self::default();
+        let (_, ladder_1) = calibration.ladder_1.split_last_mut()?;
+        let (_, ladder_2) = calibration.ladder_2.split_last_mut()?;
+        for threshold in ladder_1.iter_mut().chain(ladder_2.iter_mut()) {
+            *threshold = numbers.next()?.ok()?;
+        }
+
+        let is_descending =
+            |thresholds: &[u16]| thresholds.windows(2).all(|pair| pair[0] > pair[1]);
+        if numbers.next().is_some()
+            || !is_descending(&calibration.ladder_1)
+            || !is_descending(&calibration.ladder_2)
+        {
+            return None;
+        }
+
+        Some(calibration)
+    }
+
+    fn thresholds(&self, ladder: Ladder) -> &[u16] {
+        match ladder {
+            Ladder::One => &self.ladder_1,
+            Ladder::Two => &self.ladder_2,
+        }
+    }
+
+    /// The threshold below which a button of the ladder is pressed
+    pub fn highest(&self, ladder: Ladder) -> u16 {
+        self.thresholds(ladder)[0]
+    }
+
+    /// Which button of the ladder the level falls into, by its number from the highest level down.
+    /// The button that was active before keeps being active until the level is clearly outside its
+    /// range, so a level jittering across a threshold doesn't switch between buttons or release and
+    /// press again.
+    pub fn active_button(&self, ladder: Ladder, level: u16, previous: Option<u8>) -> Option<u8> {
+        let thresholds = self.thresholds(ladder);
+        if let Some(button) = previous.filter(|&button| button < ladder.buttons()) {
+            let start = thresholds[usize::from(button) + 1].saturating_sub(HYSTERESIS);
+            let end = thresholds[usize::from(button)].saturating_add(HYSTERESIS);
+            if start < level && level <= end {
+                return Some(button);
+            }
+        }
+
+        (0..ladder.buttons()).find(|&button| {
+            let start = thresholds[usize::from(button) + 1];
+            let end = thresholds[usize::from(button)];
+            start < level && level <= end
+        })
+    }
+}
+
+/// Writes the format that [`Calibration::parse`] reads
+impl core::fmt::Display for Calibration {
+    fn fmt(&self, formatter: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let (_, ladder_1) = self.ladder_1.split_last().unwrap_or((&0, &[]));
+        let (_, ladder_2) = self.ladder_2.split_last().unwrap_or((&0, &[]));
+        for (index, threshold) in ladder_1.iter().chain(ladder_2).enumerate() {
+            if index > 0 {
+                formatter.write_str(",")?;
+            }
+            write!(formatter, "{threshold}")?;
+        }
+        Ok(())
+    }
+}
+
+/// The threshold between a level and the next lower one. None if they are too close for the
+/// hysteresis to work.
+fn midpoint(higher: u16, lower: u16) -> Option<u16> {
+    let distance = higher.checked_sub(lower)?;
+    (distance >= MINIMUM_LEVEL_DISTANCE).then_some(lower + distance / 2)
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use alloc::string::ToString;
+
+    use super::*;
+
+    #[test]
+    fn recorded_levels_fall_into_their_buttons() {
+        let calibration = Calibration::default();
+        let ladder_1 = [3087, 2629, 2013, 1117, 4]
+            .map(|level| calibration.active_button(Ladder::One, level, None));
+        assert_eq!(ladder_1, [None, Some(0), Some(1), Some(2), Some(3)]);
+
+        let ladder_2 =
+            [3087, 1670, 4].map(|level| calibration.active_button(Ladder::Two, level, None));
+        assert_eq!(ladder_2, [None, Some(0), Some(1)]);
+    }
+
+    #[test]
+    fn active_button_holds_across_the_threshold() {
+        let calibration = Calibration::default();
+        // Just below the threshold between the first and the second button
+        assert_eq!(calibration.active_button(Ladder::One, 2250, None), Some(1));
+        assert_eq!(
+            calibration.active_button(Ladder::One, 2250, Some(0)),
+            Some(0)
+        );
+        // Clearly in the range of the second button
+        assert_eq!(
+            calibration.active_button(Ladder::One, 2150, Some(0)),
+            Some(1)
+        );
+        // Released only once clearly above the highest threshold
+        assert_eq!(
+            calibration.active_button(Ladder::One, 2900, Some(0)),
+            Some(0)
+        );
+        assert_eq!(calibration.active_button(Ladder::One, 2960, Some(0)), None);
+    }
+
+    #[test]
+    fn calibration_puts_thresholds_between_levels() {
+        let calibration = Calibration::from_levels((3087, 3087), [2629, 2013, 1117, 4], [1670, 4]);
+        assert_eq!(
+            calibration,
+            Some(Calibration {
+                ladder_1: [2858, 2321, 1565, 560, 0],
+                ladder_2: [2378, 837, 0],
+            })
+        );
+
+        // The second and third button can't be told apart
+        assert_eq!(
+            Calibration::from_levels((3087, 3087), [2629, 2400, 1117, 4], [1670, 4]),
+            None
+        );
+    }
+
+    #[test]
+    fn calibration_parses_what_it_writes() {
+        let calibration = Calibration::default();
+        assert_eq!(calibration.to_string(), "2850,2300,1550,550,2350,850");
+        assert_eq!(
+            Calibration::parse(&calibration.to_string()),
+            Some(calibration)
+        );
+        assert_eq!(Calibration::parse("2850,2300,2400,550,2350,850"), None);
+        assert_eq!(Calibration::parse("2850,2300,1550,550,2350"), None);
+    }
+}
@@ -0,0 +1,26 @@
+//! The frame buffer, the image conversion, the pagination of the reader with the display order of
+//! right-to-left text, the button ladders, the decompression of Kindle books and MD5, without
+//! anything that touches the hardware. Kept apart from the firmware so the coordinate and bit math,
+//! the page breaks, the line order, the button thresholds, the decompression and the checksums can
+//! be tested on the host.
+
+#![no_std]
+
+extern crate alloc;
+
+pub mod bidi;
+pub mod dither;
+mod frame;
+pub mod ladder;
+pub mod lut;
+pub mod md5;
+pub mod mobi;
+pub mod pagination;
+mod region;
+
+pub use crate::frame::{Difference, DrawError, DrawMode, Frame};
+pub use crate::region::{RamWindow, Region, RegionError};
+
+/// The panel is landscape with the rows of the controller RAM along the long side
+pub const DISPLAY_WIDTH: u16 = 800;
+pub const DISPLAY_HEIGHT: u16 = 480;
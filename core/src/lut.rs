@@ -0,0 +1,262 @@
+//! Custom waveforms in the format shared by the crosspoint/open xteink community.
+//!
+//! A waveform file contains the 105 byte look-up table (LUT) followed by the gate voltage, the three
+//! source voltages and VCOM, 110 bytes in total. The file can either contain these bytes as is or
+//! the C array from the SDK as text like `{ 0x2A, 0x60, ... }` so that tuned waveforms can be
+//! copied over without conversion.
+//!
+//! The LUT starts with the voltages of ten groups for each of the five LUTs, one LUT per transition
+//! between the RAM buffers and one for VCOM. Each byte holds the voltage of the four phases of a
+//! group, two bits each. The phase lengths and repeat count of the ten groups and the frame rates
+//! follow.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+
+const WAVEFORM_SIZE: usize = 105;
+/// Voltage bytes of one LUT, one per group
+const GROUPS: usize = 10;
+/// Where the lengths of the phases of the first group start, after the voltages of the five LUTs
+const TIMING_OFFSET: usize = GROUPS * 5;
+/// Where the frame rates start, after the four phase lengths and the repeat count of each group
+const FRAME_RATE_OFFSET: usize = TIMING_OFFSET + GROUPS * 5;
+/// Frames of the single pulse of the animation waveform
+const ANIMATION_FRAMES: u8 = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum ParseLutError {
+    /// Expected 110 values but found a different number
+    InvalidLength(usize),
+    /// A value doesn't fit into a byte
+    ValueOutOfRange,
+    /// Neither the binary format nor text
+    InvalidEncoding,
+    /// A `/*` comment without the closing `*/`
+    UnterminatedComment,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Lut {
+    pub waveform: [u8; WAVEFORM_SIZE],
+    pub gate_voltage: u8,
+    pub source_voltage: [u8; 3],
+    pub vcom: u8,
+}
+
+impl Lut {
+    pub const SIZE: usize = WAVEFORM_SIZE + 5;
+
+    /// Waveform like the A2 mode of other readers. A single short pulse drives the pixels that
+    /// changed straight to black or white and leaves the rest alone. It skips the shaking that
+    /// reduces ghosting, so it is only good for small regions like a moving cursor.
+    pub const ANIMATION: Self = Self::animation();
+
+    const fn animation() -> Self {
+        // Phase A of the first group. VSH1 drives a pixel to black and VSL to white.
+        const TO_BLACK: u8 = 0b0100_0000;
+        const TO_WHITE: u8 = 0b1000_0000;
+
+        let mut waveform = [0; WAVEFORM_SIZE];
+        // The LUTs go from the bit in the red RAM to the bit in the black and white RAM with 1 for
+        // white. Unchanged pixels don't get a voltage.
+        waveform[GROUPS] = TO_WHITE;
+        waveform[GROUPS * 2] = TO_BLACK;
+        // The other phases and groups have a length of 0 and are skipped
+        waveform[TIMING_OFFSET] = ANIMATION_FRAMES;
+        let mut index = FRAME_RATE_OFFSET;
+        while index < WAVEFORM_SIZE {
+            // 50 Hz for both groups in the byte
+            waveform[index] = 0x22;
+            index += 1;
+        }
+
+        Self {
+            waveform,
+            // 20 V
+            gate_voltage: 0x17,
+            // VSH1 15 V, VSH2 5 V and VSL -15 V
+            source_voltage: [0x41, 0xA8, 0x32],
+            // -1.2 V
+            vcom: 0x30,
+        }
+    }
+
+    pub fn parse(bytes: &[u8]) -> Result<Self, ParseLutError> {
+        // Decided by the content and not the size, as a text file can have any size including
+        // the one of the binary format
+        let values = if is_text(bytes) {
+            parse_text(bytes)?
+        } else if bytes.len() == Self::SIZE {
+            bytes.to_vec()
+        } else {
+            return Err(ParseLutError::InvalidEncoding);
+        };
+
+        if values.len() != Self::SIZE {
+            return Err(ParseLutError::InvalidLength(values.len()));
+        }
+
+        let (waveform, rest) = values.split_at(WAVEFORM_SIZE);
+        let mut lut = Self {
+            waveform: [0; WAVEFORM_SIZE],
+            gate_voltage: rest[0],
+            source_voltage: [rest[1], rest[2], rest[3]],
+            vcom: rest[4],
+        };
+        lut.waveform.copy_from_slice(waveform);
+
+        Ok(lut)
+    }
+}
+
+/// Source code only has printable characters and whitespace. A binary waveform has zeros for
+/// the unused phases and groups, so it is never mistaken for text.
+fn is_text(bytes: &[u8]) -> bool {
+    !bytes.is_empty()
+        && bytes
+            .iter()
+            .all(|byte| byte.is_ascii_graphic() || byte.is_ascii_whitespace())
+}
+
+/// Replaces `//` and `/* */` comments with a space, so numbers in comments are not taken as values
+fn strip_comments(text: &str) -> Result<String, ParseLutError> {
+    let mut code = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('/') {
+        code.push_str(&rest[..start]);
+        let comment = &rest[start..];
+        if let Some(comment) = comment.strip_prefix("//") {
+            // Keep the line break to separate the values around the comment
+            rest = comment.find('\n').map_or("", |end| &comment[end..]);
+        } else if let Some(comment) = comment.strip_prefix("/*") {
+            let end = comment
+                .find("*/")
+                .ok_or(ParseLutError::UnterminatedComment)?;
+            rest = &comment[end + "*/".len()..];
+        } else {
+            code.push('/');
+            rest = &comment[1..];
+            continue;
+        }
+        code.push(' ');
+    }
+
+    code.push_str(rest);
+    Ok(code)
+}
+
+/// Parses the values of a C array like `const uint8_t lut[] = { 0x2A, 0x60, /* ... */ };`
+fn parse_text(bytes: &[u8]) -> Result<Vec<u8>, ParseLutError> {
+    let text = core::str::from_utf8(bytes).map_err(|_| ParseLutError::InvalidEncoding)?;
+    let text = strip_comments(text)?;
+    // Skip the declaration as it might contain the array length
+    let text = text
+        .split_once('{')
+        .map_or(text.as_str(), |(_, values)| values);
+    let text = text.split_once('}').map_or(text, |(values, _)| values);
+
+    let mut values = Vec::with_capacity(Lut::SIZE);
+    for token in text
+        .split(|character: char| character == ',' || character.is_whitespace())
+        .filter(|token| !token.is_empty())
+    {
+        let value = if let Some(hexadecimal) = token
+            .strip_prefix("0x")
+            .or_else(|| token.strip_prefix("0X"))
+        {
+            u16::from_str_radix(hexadecimal, 16)
+        } else {
+            token.parse::<u16>()
+        };
+
+        // Ignore anything that is not a number like casts
+        let Ok(value) = value else {
+            continue;
+        };
+
+        let value = u8::try_from(value).map_err(|_| ParseLutError::ValueOutOfRange)?;
+        values.push(value);
+    }
+
+    Ok(values)
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use alloc::format;
+
+    use super::*;
+
+    fn binary() -> Vec<u8> {
+        let mut bytes = Lut::ANIMATION.waveform.to_vec();
+        bytes.extend([0x17, 0x41, 0xA8, 0x32, 0x30]);
+        bytes
+    }
+
+    /// The values as a C array with a value per line and the comment after each value
+    fn text(comment: impl Fn(usize) -> String) -> String {
+        let mut text = String::from("const uint8_t lut[110] = {\n");
+        for (index, byte) in binary().iter().enumerate() {
+            text.push_str(&format!("0x{byte:02X},{}\n", comment(index)));
+        }
+        text.push_str("};\n");
+        text
+    }
+
+    #[test]
+    fn parses_binary() {
+        assert_eq!(Lut::parse(&binary()), Ok(Lut::ANIMATION));
+    }
+
+    #[test]
+    fn ignores_numbers_in_comments() {
+        let line_comments = text(|index| format!(" // Byte {index}"));
+        assert_eq!(Lut::parse(line_comments.as_bytes()), Ok(Lut::ANIMATION));
+
+        let block_comments = text(|index| format!(" /* Byte {index}, 0x{index:02X} */"));
+        assert_eq!(Lut::parse(block_comments.as_bytes()), Ok(Lut::ANIMATION));
+
+        let multiline = format!(
+            "/* Tuned for\n 20 degrees {{ 1 }} */\n{}",
+            text(|_| String::new())
+        );
+        assert_eq!(Lut::parse(multiline.as_bytes()), Ok(Lut::ANIMATION));
+    }
+
+    #[test]
+    fn rejects_unterminated_comment() {
+        let text = text(|index| {
+            if index == 50 {
+                "/* Timing".into()
+            } else {
+                String::new()
+            }
+        });
+        assert_eq!(
+            Lut::parse(text.as_bytes()),
+            Err(ParseLutError::UnterminatedComment)
+        );
+    }
+
+    #[test]
+    fn detects_text_of_the_binary_size() {
+        // Single digits and commas padded to exactly the size of the binary format
+        let mut text = "1,".repeat(Lut::SIZE / 2);
+        text.truncate(Lut::SIZE);
+        assert_eq!(text.len(), Lut::SIZE);
+        assert_eq!(
+            Lut::parse(text.as_bytes()),
+            Err(ParseLutError::InvalidLength(Lut::SIZE / 2))
+        );
+    }
+
+    #[test]
+    fn rejects_binary_of_another_size() {
+        assert_eq!(
+            Lut::parse(&binary()[..Lut::SIZE - 1]),
+            Err(ParseLutError::InvalidEncoding)
+        );
+    }
+}
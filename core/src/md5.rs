@@ -0,0 +1,251 @@
+//! MD5 as the KOReader sync protocol uses it for the key of the password and to identify books, and
+//! for the checksum of firmware updates. It is not used for anything that needs to be secure.
+
+use alloc::string::String;
+use core::fmt::Write;
+
+const BLOCK_SIZE: usize = 64;
+
+/// Shift amounts of each round
+const SHIFTS: [u32; 64] = [
+    7, 12, 17, 22, 7, 12, 17, 22, 7, 12, 17, 22, 7, 12, 17, 22, 5, 9, 14, 20, 5, 9, 14, 20, 5, 9,
+    14, 20, 5, 9, 14, 20, 4, 11, 16, 23, 4, 11, 16, 23, 4, 11, 16, 23, 4, 11, 16, 23, 6, 10, 15,
+    21, 6, 10, 15, 21, 6, 10, 15, 21, 6, 10, 15, 21,
+];
+
+/// Integer part of the sines of the rounds times 2^32
+const CONSTANTS: [u32; 64] = [
+    0xD76A_A478,
+    0xE8C7_B756,
+    0x2420_70DB,
+    0xC1BD_CEEE,
+    0xF57C_0FAF,
+    0x4787_C62A,
+    0xA830_4613,
+    0xFD46_9501,
+    0x6980_98D8,
+    0x8B44_F7AF,
+    0xFFFF_5BB1,
+    0x895C_D7BE,
+    0x6B90_1122,
+    0xFD98_7193,
+    0xA679_438E,
+    0x49B4_0821,
+    0xF61E_2562,
+    0xC040_B340,
+    0x265E_5A51,
+    0xE9B6_C7AA,
+    0xD62F_105D,
+    0x0244_1453,
+    0xD8A1_E681,
+    0xE7D3_FBC8,
+    0x21E1_CDE6,
+    0xC337_07D6,
+    0xF4D5_0D87,
+    0x455A_14ED,
+    0xA9E3_E905,
+    0xFCEF_A3F8,
+    0x676F_02D9,
+    0x8D2A_4C8A,
+    0xFFFA_3942,
+    0x8771_F681,
+    0x6D9D_6122,
+    0xFDE5_380C,
+    0xA4BE_EA44,
+    0x4BDE_CFA9,
+    0xF6BB_4B60,
+    0xBEBF_BC70,
+    0x289B_7EC6,
+    0xEAA1_27FA,
+    0xD4EF_3085,
+    0x0488_1D05,
+    0xD9D4_D039,
+    0xE6DB_99E5,
+    0x1FA2_7CF8,
+    0xC4AC_5665,
+    0xF429_2244,
+    0x432A_FF97,
+    0xAB94_23A7,
+    0xFC93_A039,
+    0x655B_59C3,
+    0x8F0C_CC92,
+    0xFFEF_F47D,
+    0x8584_5DD1,
+    0x6FA8_7E4F,
+    0xFE2C_E6E0,
+    0xA301_4314,
+    0x4E08_11A1,
+    0xF753_7E82,
+    0xBD3A_F235,
+    0x2AD7_D2BB,
+    0xEB86_D391,
+];
+
+/// Hash that is fed in pieces
+pub struct Md5 {
+    state: [u32; 4],
+    /// Start of a block that is not full yet
+    buffer: [u8; BLOCK_SIZE],
+    buffered: usize,
+    /// Bytes fed so far
+    length: u64,
+}
+
+impl Md5 {
+    pub fn new() -> Self {
+        Self {
+            state: [0x6745_2301, 0xEFCD_AB89, 0x98BA_DCFE, 0x1032_5476],
+            buffer: [0; BLOCK_SIZE],
+            buffered: 0,
+            length: 0,
+        }
+    }
+
+    /// Feeds the next piece of the data
+    pub fn update(&mut self, mut data: &[u8]) {
+        // Can't truncate as usize is 32 bits
+        self.length += data.len() as u64;
+
+        if self.buffered > 0 {
+            let taken = data.len().min(BLOCK_SIZE - self.buffered);
+            self.buffer[self.buffered..self.buffered + taken].copy_from_slice(&data[..taken]);
+            self.buffered += taken;
+            data = &data[taken..];
+            if self.buffered < BLOCK_SIZE {
+                return;
+            }
+
+            let block = self.buffer;
+            self.compress(&block);
+            self.buffered = 0;
+        }
+
+        let (blocks, rest) = data.as_chunks::<BLOCK_SIZE>();
+        for block in blocks {
+            self.compress(block);
+        }
+
+        self.buffer[..rest.len()].copy_from_slice(rest);
+        self.buffered = rest.len();
+    }
+
+    /// The digest of all the pieces
+    pub fn finish(mut self) -> [u8; 16] {
+        let length = self.length.wrapping_mul(8);
+        // A one bit, zeros up to 8 bytes before the end of a block and the length in bits
+        self.update(&[0x80]);
+        while self.buffered != BLOCK_SIZE - 8 {
+            self.update(&[0]);
+        }
+        self.update(&length.to_le_bytes());
+
+        let mut digest = [0; 16];
+        for (bytes, word) in digest.as_chunks_mut::<4>().0.iter_mut().zip(self.state) {
+            *bytes = word.to_le_bytes();
+        }
+        digest
+    }
+
+    fn compress(&mut self, block: &[u8; BLOCK_SIZE]) {
+        let words: [u32; 16] = core::array::from_fn(|index| {
+            u32::from_le_bytes([
+                block[index * 4],
+                block[index * 4 + 1],
+                block[index * 4 + 2],
+                block[index * 4 + 3],
+            ])
+        });
+
+        let [mut a, mut b, mut c, mut d] = self.state;
+        for round in 0..64 {
+            let (mixed, word) = match round / 16 {
+                0 => ((b & c) | (!b & d), round),
+                1 => ((d & b) | (!d & c), (5 * round + 1) % 16),
+                2 => (b ^ c ^ d, (3 * round + 5) % 16),
+                _ => (c ^ (b | !d), (7 * round) % 16),
+            };
+
+            let rotated = a
+                .wrapping_add(mixed)
+                .wrapping_add(CONSTANTS[round])
+                .wrapping_add(words[word])
+                .rotate_left(SHIFTS[round]);
+            a = d;
+            d = c;
+            c = b;
+            b = b.wrapping_add(rotated);
+        }
+
+        for (state, value) in self.state.iter_mut().zip([a, b, c, d]) {
+            *state = state.wrapping_add(value);
+        }
+    }
+}
+
+impl Default for Md5 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Lowercase hexadecimal like the protocol expects
+pub fn hex(digest: &[u8; 16]) -> String {
+    let mut hex = String::with_capacity(32);
+    for byte in digest {
+        // Can't fail as writing to a string doesn't fail
+        let _ = write!(hex, "{byte:02x}");
+    }
+    hex
+}
+
+/// The digest of data in one piece
+pub fn digest(data: &[u8]) -> [u8; 16] {
+    let mut md5 = Md5::new();
+    md5.update(data);
+    md5.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The test suite of RFC 1321
+    #[test]
+    fn digests_match_the_rfc() {
+        let suite: [(&[u8], &str); 7] = [
+            (b"", "d41d8cd98f00b204e9800998ecf8427e"),
+            (b"a", "0cc175b9c0f1b6a831c399e269772661"),
+            (b"abc", "900150983cd24fb0d6963f7d28e17f72"),
+            (b"message digest", "f96b697d7cb7938d525a2f31aaf161d0"),
+            (
+                b"abcdefghijklmnopqrstuvwxyz",
+                "c3fcd3d76192e4007dfb496cca67e13b",
+            ),
+            (
+                b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789",
+                "d174ab98d277d9f5a5611c2c9f419d9f",
+            ),
+            (
+                b"12345678901234567890123456789012345678901234567890123456789012345678901234567890",
+                "57edf4a22be3c955ac49da2e2107b67a",
+            ),
+        ];
+
+        for (data, expected) in suite {
+            assert_eq!(hex(&digest(data)), expected);
+        }
+    }
+
+    #[test]
+    fn pieces_make_the_same_digest() {
+        let data =
+            b"12345678901234567890123456789012345678901234567890123456789012345678901234567890";
+        // Splits within the first block, at its end and in the second block
+        for split in [1, 63, 64, 70] {
+            let mut md5 = Md5::new();
+            md5.update(&data[..split]);
+            md5.update(&data[split..]);
+            assert_eq!(md5.finish(), digest(data));
+        }
+    }
+}
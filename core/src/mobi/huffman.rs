@@ -0,0 +1,396 @@
+//! HUFF/CDIC compression of Kindle books. Text records are Huffman codes for phrases in the CDIC
+//! dictionary records. The dictionaries are often larger than the heap of the reader, so the
+//! phrases are looked up through a [`Dictionary`] that can read them as they come up. Phrases can
+//! be compressed themselves, which is decoded with a stack instead of recursion.
+//!
+//! The HUFF record has two tables:
+//! - 256 u32 entries by the top byte of the code with the code length in the lowest 5 bits, a bit
+//!   for whether the length is final and the highest code of that length in the upper 24 bits
+//! - 32 pairs of u32 lowest and highest code for each code length from 1 to 32
+//!
+//! Each CDIC record has a header with the number of phrases and how many bits of the phrase index
+//! select the phrase within a record. A table of u16 offsets follows, each to a u16 length of the
+//! phrase with the highest bit set if the phrase is not compressed. All integers are big endian.
+
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::mobi::DecompressError;
+
+const HUFF_MAGIC: [u8; 4] = *b"HUFF";
+const CDIC_MAGIC: [u8; 4] = *b"CDIC";
+const HUFF_HEADER_SIZE: usize = 16;
+/// The offsets of the phrases follow right after
+pub const CDIC_HEADER_SIZE: u32 = 16;
+/// Phrases made up of phrases made up of phrases are rare, deeper nesting is likely a loop
+const MAXIMUM_DEPTH: usize = 32;
+/// Set in the length of a phrase that is not compressed
+const LITERAL_FLAG: u16 = 0x8000;
+
+/// Why the HUFF or CDIC records can't be used
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum HuffmanError {
+    /// The HUFF record is too short, has offsets outside of it or codes without a length
+    InvalidHuff,
+    /// The first CDIC record doesn't start with its header
+    InvalidCdic,
+}
+
+/// A phrase as it is stored in a CDIC record
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Phrase {
+    pub data: Vec<u8>,
+    /// Whether the data is text rather than codes for more phrases
+    pub is_literal: bool,
+}
+
+impl Phrase {
+    /// Splits the u16 that is stored before the phrase into its length in bytes and whether it is
+    /// literal
+    pub fn length(stored: u16) -> (usize, bool) {
+        (
+            usize::from(stored & !LITERAL_FLAG),
+            stored & LITERAL_FLAG != 0,
+        )
+    }
+}
+
+/// Where the phrases are looked up while decompressing, like the CDIC records in the book file
+pub trait Dictionary {
+    type Error: From<DecompressError>;
+
+    /// The phrase at the entry of the CDIC record, with records counted from the first CDIC
+    /// record. See [`CDIC_HEADER_SIZE`] and [`Phrase::length`] for how to find it in the record.
+    fn phrase(
+        &mut self,
+        record: usize,
+        entry: u32,
+    ) -> impl Future<Output = Result<Phrase, Self::Error>>;
+}
+
+/// Code length for codes by their top byte
+#[derive(Debug, Clone, Copy)]
+struct Entry {
+    length: u8,
+    /// Whether longer codes don't start with the same byte
+    is_final: bool,
+    /// Highest code of the length shifted to the top of 32 bits
+    maximum: u64,
+}
+
+/// The code tables of a book
+pub struct Huffman {
+    entries: Vec<Entry>,
+    /// Lowest code of each length shifted to the top of 32 bits
+    minimum: [u64; 33],
+    /// Highest code of each length shifted to the top of 32 bits
+    maximum: [u64; 33],
+    /// Number of bits of the phrase index that select the phrase within a CDIC record
+    bits: u32,
+}
+
+/// Phrase that is being decoded
+struct Pending {
+    data: Vec<u8>,
+    /// Position in bits
+    position: usize,
+}
+
+impl Huffman {
+    /// Reads the tables from the HUFF record and the header of the first CDIC record
+    pub fn parse(huff: &[u8], cdic_header: &[u8]) -> Result<Self, HuffmanError> {
+        if huff.len() < HUFF_HEADER_SIZE || huff[..4] != HUFF_MAGIC {
+            return Err(HuffmanError::InvalidHuff);
+        }
+
+        // Can't truncate as usize is at least 32 bits
+        let entries_offset = u32_at(huff, 8) as usize;
+        let codes_offset = u32_at(huff, 12) as usize;
+        let entries = huff
+            .get(entries_offset..entries_offset + 256 * 4)
+            .ok_or(HuffmanError::InvalidHuff)?;
+        let codes = huff
+            .get(codes_offset..codes_offset + 64 * 4)
+            .ok_or(HuffmanError::InvalidHuff)?;
+
+        let entries = entries
+            .as_chunks::<4>()
+            .0
+            .iter()
+            .map(|&entry| {
+                let value = u32::from_be_bytes(entry);
+                // Can't truncate as it is masked to 5 bits
+                let length = (value & 0x1F) as u8;
+                let maximum = (u64::from(value >> 8) + 1) << (32 - u32::from(length));
+                Entry {
+                    length,
+                    is_final: value & 0x80 != 0,
+                    maximum: maximum.saturating_sub(1),
+                }
+            })
+            .collect::<Vec<_>>();
+        if entries.iter().any(|entry| entry.length == 0) {
+            return Err(HuffmanError::InvalidHuff);
+        }
+
+        let mut minimum = [0; 33];
+        let mut maximum = [0; 33];
+        for (index, pair) in codes.as_chunks::<8>().0.iter().enumerate() {
+            let length = index + 1;
+            minimum[length] = u64::from(u32_at(pair, 0)) << (32 - length);
+            maximum[length] = ((u64::from(u32_at(pair, 4)) + 1) << (32 - length)) - 1;
+        }
+
+        // Can't truncate as the header size is small
+        if cdic_header.len() < CDIC_HEADER_SIZE as usize || cdic_header[..4] != CDIC_MAGIC {
+            return Err(HuffmanError::InvalidCdic);
+        }
+
+        let bits = u32_at(cdic_header, 12);
+        if bits > 16 {
+            return Err(HuffmanError::InvalidCdic);
+        }
+
+        Ok(Self {
+            entries,
+            minimum,
+            maximum,
+            bits,
+        })
+    }
+
+    /// Decompresses a text record and appends it to the output
+    pub async fn decompress<D: Dictionary>(
+        &self,
+        dictionary: &mut D,
+        data: &[u8],
+        output: &mut Vec<u8>,
+    ) -> Result<(), D::Error> {
+        let mut stack = vec![Pending {
+            data: data.to_vec(),
+            position: 0,
+        }];
+
+        while let Some(pending) = stack.last_mut() {
+            let code = u64::from(pending.peek());
+            // Can't truncate as it is the top byte
+            let entry = self.entries[(code >> 24) as usize];
+            let mut length = usize::from(entry.length);
+            let mut maximum = entry.maximum;
+            if !entry.is_final {
+                while length < 32 && code < self.minimum[length] {
+                    length += 1;
+                }
+
+                maximum = self.maximum[length];
+            }
+
+            if pending.position + length > pending.data.len() * 8 {
+                stack.pop();
+                continue;
+            }
+
+            pending.position += length;
+            // Can't truncate as the index is at most 32 bits
+            let index = (maximum.wrapping_sub(code) >> (32 - length)) as u32;
+            // Can't truncate as usize is at least 32 bits
+            let record = (index >> self.bits) as usize;
+            let phrase = dictionary
+                .phrase(record, index & ((1 << self.bits) - 1))
+                .await?;
+            if phrase.is_literal {
+                output.extend_from_slice(&phrase.data);
+            } else if stack.len() < MAXIMUM_DEPTH {
+                stack.push(Pending {
+                    data: phrase.data,
+                    position: 0,
+                });
+            } else {
+                return Err(DecompressError::TooDeep.into());
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Pending {
+    /// The next 32 bits from the position, padded with zeros at the end
+    fn peek(&self) -> u32 {
+        let byte = self.position / 8;
+        let mut bytes = [0; 5];
+        for (index, value) in bytes.iter_mut().enumerate() {
+            *value = self.data.get(byte + index).copied().unwrap_or(0);
+        }
+
+        let value = u64::from_be_bytes([0, 0, 0, bytes[0], bytes[1], bytes[2], bytes[3], bytes[4]]);
+        // Can't truncate as it is masked to 32 bits
+        ((value >> (8 - self.position % 8)) & 0xFFFF_FFFF) as u32
+    }
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes([
+        bytes[offset],
+        bytes[offset + 1],
+        bytes[offset + 2],
+        bytes[offset + 3],
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use core::pin::pin;
+    use core::task::{Context, Poll, Waker};
+
+    use super::*;
+
+    /// Every code is 2 bits long, which makes code 3 phrase 0 and code 0 phrase 3
+    fn huff_record() -> Vec<u8> {
+        let entries_offset = HUFF_HEADER_SIZE as u32;
+        let codes_offset = entries_offset + 256 * 4;
+        let mut record = HUFF_MAGIC.to_vec();
+        record.extend_from_slice(&(HUFF_HEADER_SIZE as u32).to_be_bytes());
+        record.extend_from_slice(&entries_offset.to_be_bytes());
+        record.extend_from_slice(&codes_offset.to_be_bytes());
+
+        // Highest code of length 2 is 3, final and of length 2
+        let entry = (3u32 << 8) | 0x80 | 2;
+        for _ in 0..256 {
+            record.extend_from_slice(&entry.to_be_bytes());
+        }
+        // Only codes that aren't final look at the lowest and highest codes of each length
+        record.extend_from_slice(&[0; 64 * 4]);
+        record
+    }
+
+    fn cdic_record(phrases: &[(&[u8], bool)]) -> Vec<u8> {
+        let mut record = CDIC_MAGIC.to_vec();
+        record.extend_from_slice(&CDIC_HEADER_SIZE.to_be_bytes());
+        record.extend_from_slice(&(phrases.len() as u32).to_be_bytes());
+        // 2 bits select one of the 4 phrases
+        record.extend_from_slice(&2u32.to_be_bytes());
+
+        let mut offset = 2 * phrases.len();
+        let mut stored = Vec::new();
+        for (data, is_literal) in phrases {
+            record.extend_from_slice(&(offset as u16).to_be_bytes());
+            let flag = if *is_literal { LITERAL_FLAG } else { 0 };
+            stored.extend_from_slice(&(data.len() as u16 | flag).to_be_bytes());
+            stored.extend_from_slice(data);
+            offset += 2 + data.len();
+        }
+        record.extend_from_slice(&stored);
+        record
+    }
+
+    /// The CDIC records in memory, read the way the firmware reads them from the book file
+    struct Records(Vec<Vec<u8>>);
+
+    impl Dictionary for Records {
+        type Error = DecompressError;
+
+        async fn phrase(&mut self, record: usize, entry: u32) -> Result<Phrase, DecompressError> {
+            let record = self.0.get(record).ok_or(DecompressError::UnknownPhrase)?;
+            let table = CDIC_HEADER_SIZE as usize + 2 * entry as usize;
+            let offset = u16::from_be_bytes([record[table], record[table + 1]]);
+            let start = CDIC_HEADER_SIZE as usize + usize::from(offset);
+            let (length, is_literal) =
+                Phrase::length(u16::from_be_bytes([record[start], record[start + 1]]));
+            Ok(Phrase {
+                data: record[start + 2..start + 2 + length].to_vec(),
+                is_literal,
+            })
+        }
+    }
+
+    /// The dictionary in memory never waits
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let mut future = pin!(future);
+        match future
+            .as_mut()
+            .poll(&mut Context::from_waker(Waker::noop()))
+        {
+            Poll::Ready(output) => output,
+            Poll::Pending => panic!("Dictionary in memory is not ready"),
+        }
+    }
+
+    fn decompress(phrases: &[(&[u8], bool)], data: &[u8]) -> Result<Vec<u8>, DecompressError> {
+        let mut records = Records(vec![cdic_record(phrases)]);
+        let huffman = Huffman::parse(&huff_record(), &records.0[0]).unwrap();
+        let mut output = Vec::new();
+        block_on(huffman.decompress(&mut records, data, &mut output))?;
+        Ok(output)
+    }
+
+    #[test]
+    fn codes_are_replaced_by_their_phrases() {
+        let phrases: [(&[u8], bool); 4] = [
+            (b"Hello", true),
+            (b" ", true),
+            (b"world", true),
+            (b"!", true),
+        ];
+        // Phrases 0, 1, 2 and 3
+        let text = decompress(&phrases, &[0b11_10_01_00]).unwrap();
+        assert_eq!(text, b"Hello world!");
+    }
+
+    #[test]
+    fn compressed_phrases_are_decoded_in_place() {
+        let phrases: [(&[u8], bool); 4] = [
+            (b"Hello", true),
+            (b" ", true),
+            (b"world", true),
+            // Phrases 0, 1, 2 and 1
+            (&[0b11_10_01_10], false),
+        ];
+        // Phrases 3, 0, 1 and 2
+        let text = decompress(&phrases, &[0b00_11_10_01]).unwrap();
+        assert_eq!(text, b"Hello world Hello world");
+    }
+
+    #[test]
+    fn phrases_that_contain_themselves_fail() {
+        let phrases: [(&[u8], bool); 4] = [
+            (b"a", true),
+            (b"b", true),
+            (b"c", true),
+            // Phrase 3 over and over
+            (&[0b00_00_00_00], false),
+        ];
+        assert_eq!(
+            decompress(&phrases, &[0b00_00_00_00]),
+            Err(DecompressError::TooDeep)
+        );
+    }
+
+    #[test]
+    fn missing_phrases_fail() {
+        let cdic = cdic_record(&[(b"a", true)]);
+        let huffman = Huffman::parse(&huff_record(), &cdic).unwrap();
+        // The dictionary has no CDIC record to look the phrase up in
+        let mut dictionary = Records(Vec::new());
+        let mut output = Vec::new();
+        assert_eq!(
+            block_on(huffman.decompress(&mut dictionary, &[0b11_00_00_00], &mut output)),
+            Err(DecompressError::UnknownPhrase)
+        );
+    }
+
+    #[test]
+    fn records_without_the_magic_are_rejected() {
+        let cdic = cdic_record(&[(b"a", true)]);
+        assert!(matches!(
+            Huffman::parse(b"HUFX and more", &cdic),
+            Err(HuffmanError::InvalidHuff)
+        ));
+        assert!(matches!(
+            Huffman::parse(&huff_record(), b"CDIX and more bytes"),
+            Err(HuffmanError::InvalidCdic)
+        ));
+    }
+}
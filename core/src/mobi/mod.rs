@@ -0,0 +1,81 @@
+//! Decompression of the text records of Kindle books in the MOBI and AZW3 formats. The firmware
+//! reads the records from the SD card and hands them to [`palm_doc`] or [`huffman`], depending on
+//! the compression in the header of the book. Each text record can end with entries that are not
+//! part of the text, which [`trailing_size`] tells apart.
+
+pub mod huffman;
+pub mod palm_doc;
+
+/// Why a text record can't be decompressed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum DecompressError {
+    /// Refers to bytes before the start of the record or past its end
+    OutOfBounds,
+    /// Refers to a phrase that isn't in the dictionary
+    UnknownPhrase,
+    /// Phrases are nested deeper than the maximum, which is most likely a loop
+    TooDeep,
+}
+
+/// Size of the entries at the end of a text record that are not part of the text. Each flag above
+/// the lowest stands for an entry that ends with its size, written backwards in 7 bit groups. The
+/// lowest flag stands for the bytes of a character that continues in the next record.
+pub fn trailing_size(data: &[u8], flags: u16) -> usize {
+    let mut size = 0;
+    for flag in 1..16 {
+        if flags & (1 << flag) == 0 {
+            continue;
+        }
+
+        let mut entry_size = 0;
+        let mut shift = 0;
+        for &byte in data[..data.len().saturating_sub(size)].iter().rev().take(4) {
+            entry_size |= usize::from(byte & 0x7F) << shift;
+            shift += 7;
+            if byte & 0x80 != 0 {
+                break;
+            }
+        }
+
+        size += entry_size;
+    }
+
+    if flags & 1 != 0
+        && let Some(&byte) = data
+            .len()
+            .checked_sub(size + 1)
+            .and_then(|index| data.get(index))
+    {
+        size += usize::from(byte & 0x03) + 1;
+    }
+
+    size.min(data.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn no_flags_leave_the_whole_record() {
+        assert_eq!(trailing_size(b"text", 0), 0);
+    }
+
+    #[test]
+    fn entries_end_with_their_size() {
+        // An entry of 3 bytes including its size after the text
+        assert_eq!(trailing_size(b"text\x01\x02\x83", 0b10), 3);
+    }
+
+    #[test]
+    fn multibyte_flag_counts_the_continued_bytes() {
+        // Two bytes of a character that continues in the next record and the count
+        assert_eq!(trailing_size(b"text\xE2\x82\x02", 0b01), 3);
+        assert_eq!(trailing_size(b"text\xE2\x82\x02\x01\x82", 0b11), 5);
+    }
+
+    #[test]
+    fn sizes_larger_than_the_record_are_capped() {
+        assert_eq!(trailing_size(b"\xFF", 0b10), 1);
+    }
+}
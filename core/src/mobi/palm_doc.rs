@@ -0,0 +1,124 @@
+//! PalmDoc compression, a simple LZ77 variant where each record is compressed on its own
+
+use alloc::vec::Vec;
+
+use crate::mobi::DecompressError;
+
+/// Decompresses a text record and appends it to the output
+pub fn decompress(data: &[u8], output: &mut Vec<u8>) -> Result<(), DecompressError> {
+    let start = output.len();
+    let mut index = 0;
+    while let Some(&byte) = data.get(index) {
+        index += 1;
+        match byte {
+            // The next 1 to 8 bytes are copied as they are
+            0x01..=0x08 => {
+                let length = usize::from(byte);
+                let literal = data
+                    .get(index..index + length)
+                    .ok_or(DecompressError::OutOfBounds)?;
+                output.extend_from_slice(literal);
+                index += length;
+            }
+            0x00 | 0x09..=0x7F => output.push(byte),
+            // Refers back into the text with 11 bits of distance and 3 bits of length
+            0x80..=0xBF => {
+                let next = *data.get(index).ok_or(DecompressError::OutOfBounds)?;
+                index += 1;
+                let pair = u16::from_be_bytes([byte, next]);
+                let distance = usize::from((pair >> 3) & 0x07FF);
+                let length = usize::from(pair & 0x0007) + 3;
+                if distance == 0 || distance > output.len() - start {
+                    return Err(DecompressError::OutOfBounds);
+                }
+
+                // The copy can overlap with itself to repeat text
+                for _ in 0..length {
+                    output.push(output[output.len() - distance]);
+                }
+            }
+            // A space followed by a character
+            0xC0..=0xFF => {
+                output.push(b' ');
+                output.push(byte ^ 0x80);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn text_is_copied() {
+        let mut output = Vec::new();
+        decompress(b"Alice", &mut output).unwrap();
+        assert_eq!(output, b"Alice");
+    }
+
+    #[test]
+    fn literals_keep_bytes_that_have_a_meaning() {
+        let mut output = Vec::new();
+        decompress(b"\x02\xC3\xA9!", &mut output).unwrap();
+        assert_eq!(output, "é!".as_bytes());
+    }
+
+    #[test]
+    fn space_and_character_share_a_byte() {
+        let mut output = Vec::new();
+        // 'W' is 0x57
+        decompress(b"Hello\xD7orld", &mut output).unwrap();
+        assert_eq!(output, b"Hello World");
+    }
+
+    #[test]
+    fn back_references_repeat_earlier_text() {
+        let mut output = Vec::new();
+        // Distance 4 and length 4
+        let pair = 0x8000 | (4u16 << 3) | (4 - 3);
+        let mut data = b"abcd".to_vec();
+        data.extend_from_slice(&pair.to_be_bytes());
+        decompress(&data, &mut output).unwrap();
+        assert_eq!(output, b"abcdabcd");
+    }
+
+    #[test]
+    fn back_references_can_overlap() {
+        let mut output = Vec::new();
+        // Distance 1 and length 5
+        let pair = 0x8000 | (1u16 << 3) | (5 - 3);
+        let mut data = b"a".to_vec();
+        data.extend_from_slice(&pair.to_be_bytes());
+        decompress(&data, &mut output).unwrap();
+        assert_eq!(output, b"aaaaaa");
+    }
+
+    #[test]
+    fn back_references_stay_within_the_record() {
+        // Text of an earlier record is not part of this one
+        let mut output = b"earlier".to_vec();
+        let pair = 0x8000 | (3u16 << 3);
+        let mut data = b"x".to_vec();
+        data.extend_from_slice(&pair.to_be_bytes());
+        assert_eq!(
+            decompress(&data, &mut output),
+            Err(DecompressError::OutOfBounds)
+        );
+    }
+
+    #[test]
+    fn truncated_records_fail() {
+        let mut output = Vec::new();
+        assert_eq!(
+            decompress(b"\x03ab", &mut output),
+            Err(DecompressError::OutOfBounds)
+        );
+        assert_eq!(
+            decompress(b"ab\x80", &mut output),
+            Err(DecompressError::OutOfBounds)
+        );
+    }
+}
@@ -0,0 +1,445 @@
+//! Breaks text into lines and pages without knowing the font. Lines are broken at spaces and at the
+//! line breaks in the text. Pages are laid out one after another from where the previous one
+//! ended, so there is no need to lay out the whole text up front.
+//!
+//! Words that don't fit at the end of a line are broken by a [`Hyphenator`] if it finds a place.
+//! Characters are measured by [`Metrics`] in pixels rather than counted, as fonts can mix widths.
+//!
+//! Positions in the text are byte offsets rather than page numbers, so a position still points to
+//! the same text after a font size change moves the page breaks.
+//!
+//! Breaking doesn't use the heap and the start of the previous page is found by laying out from the
+//! start of the text again instead of keeping the starts of all pages.
+
+/// Characters on a line of a page. Lines are laid out and drawn in fixed buffers of this size, so
+/// turning pages never needs the heap. Lines end early when they reach it, which only happens with
+/// glyphs far narrower than the built-in fonts.
+pub const MAXIMUM_LINE_CHARACTERS: usize = 128;
+/// Bytes of the text of a line, as UTF-8 takes up to 4 bytes for a character
+pub const MAXIMUM_LINE_SIZE: usize = 4 * MAXIMUM_LINE_CHARACTERS;
+/// Bytes of the longest word that is hyphenated. Longer words are broken wherever the line is full.
+pub const MAXIMUM_WORD_SIZE: usize = 64;
+
+/// Byte offsets into a word where it can be broken. There can't be more than the word has bytes.
+pub type Breaks = heapless::Vec<usize, MAXIMUM_WORD_SIZE>;
+
+/// Finds where words can be broken with a hyphen, e.g. with the patterns of a language
+pub trait Hyphenator {
+    /// Byte offsets into the word where it can be broken in ascending order. Words longer than
+    /// [`MAXIMUM_WORD_SIZE`] have none.
+    fn breaks(&self, word: &str) -> Breaks;
+}
+
+/// Only breaks lines between words
+pub struct NoHyphenation;
+
+impl Hyphenator for NoHyphenation {
+    fn breaks(&self, _word: &str) -> Breaks {
+        Breaks::new()
+    }
+}
+
+/// Measures text the way it is drawn
+pub trait Metrics {
+    /// Horizontal distance to the next character in pixels
+    fn advance(&self, character: char) -> u32;
+}
+
+/// Where the lines and pages of a text break for a font and text area
+pub struct Pages<'a> {
+    metrics: &'a dyn Metrics,
+    hyphenator: &'a dyn Hyphenator,
+    /// In the pixels of the metrics
+    line_width: u32,
+    rows: usize,
+}
+
+impl<'a> Pages<'a> {
+    /// Pages have at least one line, so they make progress even if no line fits
+    pub fn new(
+        metrics: &'a dyn Metrics,
+        hyphenator: &'a dyn Hyphenator,
+        line_width: u32,
+        rows: usize,
+    ) -> Self {
+        Self {
+            metrics,
+            hyphenator,
+            line_width,
+            rows: rows.max(1),
+        }
+    }
+
+    fn width(&self, text: &str) -> u32 {
+        text.chars()
+            .map(|character| self.metrics.advance(character))
+            .sum()
+    }
+
+    /// Byte offset into the text where the page starting at the offset ends
+    pub fn page_end(&self, text: &str, start: usize) -> usize {
+        let mut end = start;
+        for _ in 0..self.rows {
+            if end >= text.len() {
+                break;
+            }
+
+            end += self.line(&text[end..]).next;
+        }
+
+        end
+    }
+
+    /// Start of the page containing the position and its number counting from 1. Lays out the
+    /// pages from the start of the text, so turning back from a page uses the position before it.
+    pub fn page_at(&self, text: &str, position: usize) -> (usize, usize) {
+        let mut start = 0;
+        let mut number = 1;
+        loop {
+            let end = self.page_end(text, start);
+            if end > position || end >= text.len() {
+                return (start, number);
+            }
+
+            start = end;
+            number += 1;
+        }
+    }
+
+    /// Splits off the first line of the text. Words longer than a line are broken anywhere if the
+    /// hyphenator finds no place to break them. Text without spaces like Chinese or Japanese is
+    /// broken wherever the line is full. Lines also end before they have more characters than
+    /// the buffers they are drawn with hold.
+    pub fn line(&self, text: &str) -> Line {
+        let maximum = self.line_width;
+        let mut width = 0;
+        let mut last_space = None;
+        for (count, (index, character)) in text.char_indices().enumerate() {
+            let advance = self.metrics.advance(character);
+            // The first character is always taken, so pages make progress. One character is left
+            // for the hyphen.
+            let fits =
+                index == 0 || (width + advance <= maximum && count < MAXIMUM_LINE_CHARACTERS - 1);
+            match character {
+                '\n' => return Line::new(index, index + 1),
+                ' ' if !fits => return Line::new(index, index + 1),
+                ' ' => last_space = Some(index),
+                _ if !fits => {
+                    let word_start = last_space.map_or(0, |space| space + 1);
+                    if let Some(line) = self.hyphenate(text, word_start, index, maximum) {
+                        return line;
+                    }
+
+                    return match last_space {
+                        Some(space) => Line::new(space, space + 1),
+                        None => Line::new(index, index),
+                    };
+                }
+                _ => {}
+            }
+
+            width += advance;
+        }
+
+        Line::new(text.len(), text.len())
+    }
+
+    /// Breaks the word starting at the offset at the last place before the character that didn't
+    /// fit that leaves room for the hyphen. The width alone isn't enough, as the line can also be
+    /// full of characters.
+    fn hyphenate(&self, text: &str, word_start: usize, full: usize, maximum: u32) -> Option<Line> {
+        let word = &text[word_start..];
+        let word = &word[..word.find([' ', '\n']).unwrap_or(word.len())];
+        // Punctuation around the word is not part of the patterns
+        let letters = word.trim_start_matches(|character: char| !character.is_alphabetic());
+        let letters_start = word_start + word.len() - letters.len();
+        let letters = letters.trim_end_matches(|character: char| !character.is_alphabetic());
+
+        let hyphen = self.metrics.advance('-');
+        self.hyphenator
+            .breaks(letters)
+            .into_iter()
+            .map(|offset| letters_start + offset)
+            .rfind(|&end| end <= full && self.width(&text[..end]) + hyphen <= maximum)
+            .map(|end| Line {
+                length: end,
+                next: end,
+                is_hyphenated: true,
+            })
+    }
+}
+
+/// A line of text from its start
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Line {
+    /// Length in bytes of the text on the line
+    pub length: usize,
+    /// Where the line after it starts. The space or line break the line was broken at is skipped.
+    pub next: usize,
+    /// Whether a word was broken and the line ends with a hyphen
+    pub is_hyphenated: bool,
+}
+
+impl Line {
+    fn new(length: usize, next: usize) -> Self {
+        Self {
+            length,
+            next,
+            is_hyphenated: false,
+        }
+    }
+}
+
+/// Checks the invariants of the pagination on many generated texts and layouts, in the style of
+/// quickcheck. A failing case prints its seed, which reproduces it.
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use alloc::string::String;
+    use alloc::vec::Vec;
+
+    use super::*;
+
+    const CASES: u64 = 500;
+
+    /// Xorshift, so the cases are the same on every run without a dependency
+    struct Random(u64);
+
+    impl Random {
+        fn new(seed: u64) -> Self {
+            // Xorshift is stuck at 0
+            Self(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1)
+        }
+
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn below(&mut self, limit: usize) -> usize {
+            // Can't truncate as the limits are small
+            (self.next() % limit as u64) as usize
+        }
+
+        fn pick<T: Copy>(&mut self, items: &[T]) -> T {
+            items[self.below(items.len())]
+        }
+    }
+
+    /// Latin in one width and CJK in twice that, like the body font with glyphs from the SD card
+    struct Mixed;
+
+    impl Metrics for Mixed {
+        fn advance(&self, character: char) -> u32 {
+            if character.is_ascii() { 6 } else { 12 }
+        }
+    }
+
+    /// Breaks every word after each second character, as long as the rules of the patterns allow
+    struct EverySecond;
+
+    impl Hyphenator for EverySecond {
+        fn breaks(&self, word: &str) -> Breaks {
+            if word.len() > MAXIMUM_WORD_SIZE {
+                return Breaks::new();
+            }
+
+            word.char_indices()
+                .map(|(index, _)| index)
+                .skip(2)
+                .step_by(2)
+                .collect()
+        }
+    }
+
+    fn text(random: &mut Random) -> String {
+        const WORDS: &[&str] = &[
+            "a",
+            "the",
+            "reader",
+            "e-ink",
+            "\"quoted\",",
+            "über",
+            "naïve",
+            "漢字かな交じり文",
+            "supercalifragilisticexpialidocious",
+            "Donaudampfschifffahrtsgesellschaftskapitänsmützenabzeichenhersteller",
+        ];
+        const SEPARATORS: &[&str] = &[" ", " ", " ", " ", "  ", "\n", "\n\n", ""];
+
+        let mut text = String::new();
+        for _ in 0..random.below(150) {
+            text.push_str(random.pick(WORDS));
+            text.push_str(random.pick(SEPARATORS));
+        }
+        text
+    }
+
+    fn pages<'a>(random: &mut Random, hyphenator: &'a dyn Hyphenator) -> Pages<'a> {
+        // Down to narrower than a character and a single row
+        let line_width = random.pick(&[1, 20, 60, 200, 472, 2000]);
+        let rows = random.below(8);
+        Pages::new(&Mixed, hyphenator, line_width, rows)
+    }
+
+    /// Runs the check on generated cases with and without hyphenation
+    fn check(property: impl Fn(&Pages, &str)) {
+        for seed in 0..CASES {
+            let mut random = Random::new(seed);
+            let text = text(&mut random);
+            let hyphenator: &dyn Hyphenator = if random.below(2) == 0 {
+                &NoHyphenation
+            } else {
+                &EverySecond
+            };
+            let pages = pages(&mut random, hyphenator);
+            std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| property(&pages, &text)))
+                .unwrap_or_else(|_| panic!("failed with seed {seed}"));
+        }
+    }
+
+    /// Page starts from the start of the text to its end
+    fn page_starts(pages: &Pages, text: &str) -> Vec<usize> {
+        let mut starts = Vec::new();
+        let mut start = 0;
+        while start < text.len() {
+            starts.push(start);
+            start = pages.page_end(text, start);
+        }
+        starts
+    }
+
+    #[test]
+    fn no_text_is_lost_or_repeated() {
+        check(|pages, text| {
+            let mut start = 0;
+            let mut rebuilt = String::new();
+            while start < text.len() {
+                let line = pages.line(&text[start..]);
+                assert!(line.next > 0, "no progress at {start}");
+                assert!(line.length <= line.next);
+                assert!(text.is_char_boundary(start + line.length));
+
+                rebuilt.push_str(&text[start..start + line.length]);
+                // Only the space or line break the line was broken at is skipped
+                let skipped = &text[start + line.length..start + line.next];
+                assert!(matches!(skipped, "" | " " | "\n"), "skipped {skipped:?}");
+                rebuilt.push_str(skipped);
+                start += line.next;
+            }
+
+            assert_eq!(start, text.len());
+            assert_eq!(rebuilt, text);
+        });
+    }
+
+    #[test]
+    fn pages_follow_each_other() {
+        check(|pages, text| {
+            let starts = page_starts(pages, text);
+            for (index, start) in starts.iter().enumerate() {
+                let end = pages.page_end(text, *start);
+                assert!(end > *start);
+                assert_eq!(end, starts.get(index + 1).copied().unwrap_or(text.len()));
+            }
+        });
+    }
+
+    #[test]
+    fn lines_fit() {
+        check(|pages, text| {
+            let mut start = 0;
+            while start < text.len() {
+                let line = pages.line(&text[start..]);
+                let content = &text[start..start + line.length];
+                let hyphen = if line.is_hyphenated {
+                    Mixed.advance('-')
+                } else {
+                    0
+                };
+                // A single character that is wider than the line is taken anyway
+                assert!(
+                    pages.width(content) + hyphen <= pages.line_width
+                        || content.chars().count() <= 1,
+                    "{content:?} is too wide"
+                );
+                // Room is left for the hyphen
+                assert!(content.chars().count() < MAXIMUM_LINE_CHARACTERS);
+                start += line.next;
+            }
+        });
+    }
+
+    #[test]
+    fn breaks_are_stable() {
+        check(|pages, text| {
+            assert_eq!(page_starts(pages, text), page_starts(pages, text));
+
+            // The same settings laid out again from scratch
+            let again = Pages::new(
+                pages.metrics,
+                pages.hyphenator,
+                pages.line_width,
+                pages.rows,
+            );
+            assert_eq!(page_starts(pages, text), page_starts(&again, text));
+        });
+    }
+
+    #[test]
+    fn resuming_reproduces_the_page() {
+        check(|pages, text| {
+            let starts = page_starts(pages, text);
+            // Finding a page lays out all before it, so only some pages are checked
+            let step = starts.len() / 4 + 1;
+            for (index, start) in starts.iter().enumerate().step_by(step) {
+                let end = pages.page_end(text, *start);
+                // Any saved offset on the page finds it again, e.g. after a restart
+                for position in [*start, (*start + end) / 2, end - 1] {
+                    assert_eq!(pages.page_at(text, position), (*start, index + 1));
+                }
+                // Laying out from the saved start gives the same page as from the beginning
+                assert_eq!(pages.page_end(text, *start), end);
+            }
+
+            if let Some(last) = starts.last() {
+                assert_eq!(pages.page_at(text, text.len()), (*last, starts.len()));
+            }
+        });
+    }
+
+    #[test]
+    fn empty_text_has_one_page() {
+        let pages = Pages::new(&Mixed, &NoHyphenation, 100, 3);
+        assert_eq!(pages.page_end("", 0), 0);
+        assert_eq!(pages.page_at("", 0), (0, 1));
+    }
+
+    #[test]
+    fn breaks_at_spaces_and_line_breaks() {
+        // Four characters per line
+        let pages = Pages::new(&Mixed, &NoHyphenation, 24, 2);
+        assert_eq!(pages.line("ab cd"), Line::new(2, 3));
+        assert_eq!(pages.line("abcd efg"), Line::new(4, 5));
+        assert_eq!(pages.line("a\nb"), Line::new(1, 2));
+        // Too long without a space
+        assert_eq!(pages.line("abcdefgh"), Line::new(4, 4));
+        assert_eq!(pages.page_end("ab cd ef gh", 0), 6);
+    }
+
+    #[test]
+    fn hyphen_leaves_room() {
+        let pages = Pages::new(&Mixed, &EverySecond, 36, 1);
+        assert_eq!(
+            pages.line("abcdefghij"),
+            Line {
+                length: 4,
+                next: 4,
+                is_hyphenated: true
+            }
+        );
+    }
+}
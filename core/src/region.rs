@@ -0,0 +1,278 @@
+use embedded_graphics::primitives::Rectangle;
+
+use crate::{DISPLAY_HEIGHT, DISPLAY_WIDTH};
+
+/// Rectangle on the panel in the coordinates of the controller RAM and the frame buffer.
+/// X is along a buffer row and Y counts the rows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct Region {
+    pub x: u16,
+    pub y: u16,
+    pub width: u16,
+    pub height: u16,
+}
+
+/// Why a region can't be written to the controller RAM
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum RegionError {
+    /// Without a width or height
+    Empty,
+    /// Doesn't start or end on a byte boundary along X
+    Unaligned,
+    /// Reaches past the edge of the panel
+    OutOfBounds,
+}
+
+/// Inclusive address ranges of a region in the controller RAM, for the data entry mode that
+/// increments X and decrements Y
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct RamWindow {
+    pub x_start: u16,
+    pub x_end: u16,
+    pub y_start: u16,
+    pub y_end: u16,
+}
+
+impl Region {
+    pub const FULL: Self = Self {
+        x: 0,
+        y: 0,
+        width: DISPLAY_WIDTH,
+        height: DISPLAY_HEIGHT,
+    };
+
+    /// Each byte holds 8 pixels along X, so regions need to start and end on a byte boundary.
+    /// The region also needs to be within the panel and not be empty.
+    pub fn is_valid(&self) -> bool {
+        self.validate().is_ok()
+    }
+
+    /// The same as [`Self::is_valid`], but tells which requirement the region misses
+    pub fn validate(&self) -> Result<(), RegionError> {
+        if self.width == 0 || self.height == 0 {
+            return Err(RegionError::Empty);
+        }
+
+        if !self.x.is_multiple_of(8) || !self.width.is_multiple_of(8) {
+            return Err(RegionError::Unaligned);
+        }
+
+        let is_within_bounds = self
+            .x
+            .checked_add(self.width)
+            .is_some_and(|right| right <= DISPLAY_WIDTH)
+            && self
+                .y
+                .checked_add(self.height)
+                .is_some_and(|bottom| bottom <= DISPLAY_HEIGHT);
+        if !is_within_bounds {
+            return Err(RegionError::OutOfBounds);
+        }
+
+        Ok(())
+    }
+
+    /// Addresses of the region in the controller RAM. All address math for the controller goes
+    /// through here.
+    pub fn ram_window(&self) -> Result<RamWindow, RegionError> {
+        self.validate()?;
+
+        // Can't overflow as the region is not empty and within the panel
+        Ok(RamWindow {
+            x_start: self.x,
+            x_end: self.x + self.width - 1,
+            // The gates are wired in reverse, so the first row of the frame is the last row of
+            // the RAM and the controller counts down from there
+            y_start: DISPLAY_HEIGHT - 1 - self.y,
+            y_end: DISPLAY_HEIGHT - self.y - self.height,
+        })
+    }
+
+    /// Smallest valid region that covers the area of the frame, e.g. what was just drawn over. The
+    /// portrait frame is turned into the landscape panel coordinates, clamped to the panel and
+    /// widened along X to byte boundaries. None if the area is empty or outside of the panel.
+    ///
+    /// Partial refreshes should get their region from here instead of working out the alignment
+    /// themselves. It is const to be able to compute the regions of fixed areas at compile time.
+    pub const fn covering(area: Rectangle) -> Option<Self> {
+        // Frame Y runs along panel X and frame X against panel Y, see the DrawTarget of Frame
+        let left = clamp(area.top_left.y, DISPLAY_WIDTH);
+        let right = clamp(
+            area.top_left.y.saturating_add_unsigned(area.size.height),
+            DISPLAY_WIDTH,
+        );
+        let top = clamp(
+            (DISPLAY_HEIGHT as i32)
+                .saturating_sub(area.top_left.x)
+                .saturating_sub_unsigned(area.size.width),
+            DISPLAY_HEIGHT,
+        );
+        let bottom = clamp(
+            (DISPLAY_HEIGHT as i32).saturating_sub(area.top_left.x),
+            DISPLAY_HEIGHT,
+        );
+
+        // The panel width is a multiple of 8, so the widened region stays within the panel
+        let left = left / 8 * 8;
+        let right = right.div_ceil(8) * 8;
+        if left >= right || top >= bottom {
+            return None;
+        }
+
+        Some(Self {
+            x: left,
+            y: top,
+            width: right - left,
+            height: bottom - top,
+        })
+    }
+}
+
+/// Value limited to the range from 0 to the maximum
+const fn clamp(value: i32, maximum: u16) -> u16 {
+    if value < 0 {
+        0
+    } else if value > maximum as i32 {
+        maximum
+    } else {
+        // Can't truncate as the value is at most the maximum
+        value as u16
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use embedded_graphics::prelude::{Point, Size};
+
+    use super::*;
+
+    #[test]
+    fn whole_frame_covers_panel() {
+        let frame = Rectangle::new(Point::zero(), Size::new(480, 800));
+        assert_eq!(Region::covering(frame), Some(Region::FULL));
+        assert!(Region::FULL.is_valid());
+    }
+
+    #[test]
+    fn covering_widens_to_bytes() {
+        let area = Rectangle::new(Point::new(10, 3), Size::new(5, 10));
+        assert_eq!(
+            Region::covering(area),
+            Some(Region {
+                x: 0,
+                y: 465,
+                width: 16,
+                height: 5
+            })
+        );
+    }
+
+    #[test]
+    fn covering_clamps_to_panel() {
+        let area = Rectangle::new(Point::new(-10, 790), Size::new(20, 20));
+        assert_eq!(
+            Region::covering(area),
+            Some(Region {
+                x: 784,
+                y: 470,
+                width: 16,
+                height: 10
+            })
+        );
+
+        let outside = Rectangle::new(Point::new(480, 0), Size::new(10, 10));
+        assert_eq!(Region::covering(outside), None);
+        let empty = Rectangle::new(Point::new(10, 10), Size::zero());
+        assert_eq!(Region::covering(empty), None);
+    }
+
+    #[test]
+    fn invalid_regions() {
+        let valid = Region {
+            x: 8,
+            y: 0,
+            width: 16,
+            height: 1,
+        };
+        assert!(valid.is_valid());
+        assert!(!Region { x: 4, ..valid }.is_valid());
+        assert!(!Region { width: 12, ..valid }.is_valid());
+        assert!(!Region { height: 0, ..valid }.is_valid());
+        assert!(!Region { x: 792, ..valid }.is_valid());
+        assert!(!Region { y: 480, ..valid }.is_valid());
+        assert!(
+            !Region {
+                x: u16::MAX - 7,
+                ..valid
+            }
+            .is_valid()
+        );
+    }
+
+    #[test]
+    fn validate_tells_reason() {
+        let valid = Region {
+            x: 8,
+            y: 0,
+            width: 16,
+            height: 1,
+        };
+        assert_eq!(valid.validate(), Ok(()));
+        assert_eq!(
+            Region { width: 0, ..valid }.validate(),
+            Err(RegionError::Empty)
+        );
+        assert_eq!(
+            Region { x: 4, ..valid }.validate(),
+            Err(RegionError::Unaligned)
+        );
+        assert_eq!(
+            Region {
+                y: u16::MAX,
+                ..valid
+            }
+            .validate(),
+            Err(RegionError::OutOfBounds)
+        );
+    }
+
+    #[test]
+    fn ram_window_mirrors_rows() {
+        assert_eq!(
+            Region::FULL.ram_window(),
+            Ok(RamWindow {
+                x_start: 0,
+                x_end: 799,
+                y_start: 479,
+                y_end: 0,
+            })
+        );
+
+        let region = Region {
+            x: 16,
+            y: 10,
+            width: 8,
+            height: 20,
+        };
+        assert_eq!(
+            region.ram_window(),
+            Ok(RamWindow {
+                x_start: 16,
+                x_end: 23,
+                y_start: 469,
+                y_end: 450,
+            })
+        );
+
+        let bottom = Region {
+            y: 479,
+            height: 1,
+            ..region
+        };
+        assert_eq!(bottom.ram_window().map(|window| window.y_end), Ok(0));
+        assert_eq!(
+            Region { y: 479, ..region }.ram_window(),
+            Err(RegionError::OutOfBounds)
+        );
+    }
+}
@@ -0,0 +1,1423 @@
+use alloc::boxed::Box;
+use core::convert::Infallible;
+use core::num::NonZeroU16;
+
+use crustpoint_core::lut::Lut;
+use crustpoint_core::{DISPLAY_HEIGHT, DISPLAY_WIDTH, Frame, Region};
+use defmt::info;
+use embassy_time::{Duration, Instant, Timer};
+use embedded_hal::digital::{InputPin, OutputPin};
+use embedded_hal_async::digital::Wait;
+use embedded_hal_async::spi::SpiDevice;
+
+use crate::error::*;
+use crate::ghosting::GhostingPolicy;
+use crate::monitor::Monitor;
+use crate::retry::RetryPolicy;
+use crate::statistics::RefreshStatistics;
+use crate::temperature::{self, Temperature};
+use crate::watchdog::StaleBusyWatchdog;
+
+/// Commands of the controller, see the SSD1677 datasheet
+#[derive(Debug, Clone, Copy, defmt::Format)]
+#[repr(u8)]
+pub enum Command {
+    // Initialization and reset
+    SoftReset = 0x12,
+    TemperatureSensorControl = 0x18,
+    ReadTemperature = 0x1B,
+    BoosterSoftStart = 0x0C,
+    DriverOutputControl = 0x01,
+    BorderWaveformControl = 0x3C,
+
+    // RAM and buffer management
+    DataEntryMode = 0x11,
+    SetRamXRange = 0x44,
+    SetRamYRange = 0x45,
+    SetRamXCounter = 0x4E,
+    SetRamYCounter = 0x4F,
+    AutoWriteBwRam = 0x46,
+    AutoWriteRedRam = 0x47,
+    WriteBwRam = 0x24,
+    WriteRedRam = 0x26,
+
+    // Display update and refresh
+    DisplayUpdateControl1 = 0x21,
+    DisplayUpdateControl2 = 0x22,
+    MasterActivation = 0x20,
+
+    // LUT and voltage settings
+    /// Write temperature
+    WriteTemperature = 0x1A,
+    WriteLut = 0x32,
+    GateVoltage = 0x03,
+    SourceVoltage = 0x04,
+    WriteVcom = 0x2C,
+
+    // Power management
+    DeepSleep = 0x10,
+}
+
+#[derive(Debug, defmt::Format)]
+#[repr(u8)]
+enum ControlMode {
+    /// Normal mode - compare RED vs BW for partial
+    Normal = 0x00,
+    /// Bypass RED RAM (treat as 0) - for full refresh
+    BypassRed = 0x40,
+}
+
+/// Inverts the content of both RAM buffers when combined with a [`ControlMode`]. Inverting both
+/// keeps the comparison for partial refreshes intact.
+const INVERT_RAM: u8 = 0x88;
+
+/// Written to the temperature register so the controller loads its quickest waveform from OTP, the
+/// one for a warm panel
+const HOT_TEMPERATURE: u8 = 0x5A;
+/// Rows of a region sent to the controller in one SPI transaction
+const ROWS_PER_TRANSACTION: usize = 8;
+/// Regions of at most this share of the panel can be shown with the animation waveform
+const ANIMATION_AREA_DIVISOR: u32 = 8;
+
+/// What the border around the active area shows. Based on the border waveform control register:
+/// bits 7-6 select the source, bit 2 and bits 1-0 pick the LUT for the grayscale transition.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+#[repr(u8)]
+pub enum BorderColor {
+    /// Grayscale transition with LUT 0
+    Black = 0b0000_0000,
+    /// Grayscale transition with LUT 1
+    White = 0b0000_0001,
+    /// Follows the LUT of the refresh waveform
+    FollowLut = 0b0000_0100,
+    /// Border is not driven and keeps its current color
+    HighImpedance = 0b1100_0000,
+}
+
+/// Driver for the SSD1677 controller. The bus and the pins are the traits of embedded-hal, so the
+/// command sequences can be checked against mocks as well as run on the GPIOs of the chip. The pins
+/// can't fail like the GPIOs of the chip, so only errors of the bus are reported.
+pub struct EinkDisplay<SPI, RESET, DC, BUSY, M>
+where
+    SPI: SpiDevice,
+    RESET: OutputPin<Error = Infallible>,
+    DC: OutputPin<Error = Infallible>,
+    BUSY: InputPin<Error = Infallible> + Wait,
+    M: Monitor,
+{
+    spi: SPI,
+    reset: RESET,
+    /// Based on usage this pin is used to select between data and command mode.
+    /// When set to low, the pin is in command mode to send commands.
+    /// When set to high, the pin is in data mode to send data.
+    data_command: DC,
+    busy: BUSY,
+    power: PowerState,
+    is_custom_lut_active: bool,
+    /// Kept to load it again after the animation waveform replaced it
+    custom_lut: Option<Lut>,
+    /// Set for the refresh with the animation waveform
+    is_animation_lut_active: bool,
+    /// The animation waveform leaves ghosting behind that the next refresh of the whole panel
+    /// clears with a full refresh
+    has_animation_ghosting: bool,
+    statistics: RefreshStatistics,
+    ghosting_policy: GhostingPolicy,
+    retry_policy: RetryPolicy,
+    /// Last successful measurement
+    temperature: Option<Temperature>,
+    /// When the temperature was last measured, even if that failed, to not retry on every refresh
+    temperature_checked_at: Option<Instant>,
+    border: BorderColor,
+    /// Shows white content on black
+    is_inverted: bool,
+    /// Set when a change only shows with a full refresh as partial refreshes only update the
+    /// pixels that differ between the RAM buffers
+    is_full_refresh_required: bool,
+    /// When the last full refresh finished. None until the first one.
+    last_full_refresh: Option<Instant>,
+    /// Whether showing a frame in deep sleep wakes the controller instead of failing
+    is_auto_wake: bool,
+    /// Fast refreshes use the quickest waveform and full refreshes come less often, which leaves
+    /// more ghosting behind
+    is_turbo: bool,
+    watchdog: StaleBusyWatchdog,
+    monitor: M,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum RefreshMode {
+    Fast,
+    Full,
+    HalfRefresh,
+}
+
+/// Power state of the controller. Refreshes, resets and deep sleep move it between the states.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+enum PowerState {
+    /// The clock and the analog rails are on, so a fast refresh can start right away
+    PoweredOn,
+    /// The clock and the analog rails are off, e.g. after a reset. Turning them on takes at least
+    /// a half refresh.
+    Standby,
+    /// Ignores everything but a reset. The panel keeps showing the last frame.
+    DeepSleep,
+}
+
+/// What the controller is busy with, which tells how long it may take
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum BusyOperation {
+    SoftReset,
+    /// Filling a RAM buffer with one value
+    ClearRam,
+    Refresh(RefreshMode),
+    /// Loading the temperature from the sensor into the register
+    MeasureTemperature,
+    /// Turning off the analog power rails and the clock
+    PowerDown,
+}
+
+/// The operating sequence of the SSD1677 datasheet waits 10 ms after a reset. Filling the RAM and
+/// loading the temperature don't drive the panel either, so they are as quick.
+const INTERNAL_TIMEOUT: Duration = Duration::from_millis(500);
+/// Turning off the analog power rails discharges the panel, which takes longer than the internal
+/// operations
+const POWER_DOWN_TIMEOUT: Duration = Duration::from_secs(2);
+/// A refresh lasts as long as the frames of its waveform, which come from the OTP of the panel and
+/// are not known up front. Until a refresh of the mode was measured, see [`RefreshStatistics`], this
+/// leaves room for the seconds a full refresh takes.
+const UNMEASURED_REFRESH_TIMEOUT: Duration = Duration::from_secs(10);
+/// Refreshes with the same waveform take the same number of frames, so a refresh that takes this
+/// many times the longest one measured for its mode is stuck
+const REFRESH_MARGIN: u32 = 3;
+/// Lower bound, so a quick mode doesn't time out from the jitter of waking up the task
+const MINIMUM_REFRESH_TIMEOUT: Duration = Duration::from_secs(1);
+/// The controller picks waveforms with longer phases for low temperatures, as the particles move
+/// slower, so refreshes in the cold take longer than the ones measured before
+const COLD_REFRESH_FACTOR: u32 = 2;
+
+impl BusyOperation {
+    /// How long the operation may take before the controller is considered stuck. The longest
+    /// refresh measured with the waveform of the mode, if any.
+    fn timeout(
+        self,
+        longest_refresh: Option<Duration>,
+        temperature: Option<Temperature>,
+    ) -> Duration {
+        let timeout = match self {
+            Self::SoftReset | Self::ClearRam | Self::MeasureTemperature => return INTERNAL_TIMEOUT,
+            Self::PowerDown => return POWER_DOWN_TIMEOUT,
+            Self::Refresh(_) => longest_refresh.map_or(UNMEASURED_REFRESH_TIMEOUT, |longest| {
+                (longest * REFRESH_MARGIN)
+                    .clamp(MINIMUM_REFRESH_TIMEOUT, UNMEASURED_REFRESH_TIMEOUT)
+            }),
+        };
+
+        if temperature.is_some_and(Temperature::is_cold) {
+            return timeout * COLD_REFRESH_FACTOR;
+        }
+
+        timeout
+    }
+}
+
+/// Reported to the [`Monitor`] when a refresh starts and ends
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum PanelState {
+    Idle,
+    Refreshing(RefreshMode),
+}
+
+impl<SPI, RESET, DC, BUSY, M> EinkDisplay<SPI, RESET, DC, BUSY, M>
+where
+    SPI: SpiDevice,
+    RESET: OutputPin<Error = Infallible>,
+    DC: OutputPin<Error = Infallible>,
+    BUSY: InputPin<Error = Infallible> + Wait,
+    M: Monitor,
+{
+    fn new(spi: SPI, reset: RESET, data_command: DC, busy: BUSY, monitor: M) -> Self {
+        Self {
+            spi,
+            reset,
+            data_command,
+            busy,
+            power: PowerState::Standby,
+            is_custom_lut_active: false,
+            custom_lut: None,
+            is_animation_lut_active: false,
+            has_animation_ghosting: false,
+            statistics: RefreshStatistics::new(),
+            ghosting_policy: GhostingPolicy::new(),
+            retry_policy: RetryPolicy::DEFAULT,
+            temperature: None,
+            temperature_checked_at: None,
+            border: BorderColor::White,
+            is_inverted: false,
+            is_full_refresh_required: false,
+            last_full_refresh: None,
+            is_auto_wake: true,
+            is_turbo: false,
+            watchdog: StaleBusyWatchdog::new(),
+            monitor,
+        }
+    }
+
+    async fn reset(&mut self) {
+        info!("Resetting display");
+        let Ok(()) = self.reset.set_high();
+        // Might need to be blocking if it needs to be the exact time
+        Timer::after_millis(20).await;
+        let Ok(()) = self.reset.set_low();
+        Timer::after_millis(2).await;
+        let Ok(()) = self.reset.set_high();
+        Timer::after_millis(20).await;
+        info!("Display reset completed");
+    }
+
+    async fn send_command(&mut self, command: Command) -> Result<(), SendCommandError<SPI::Error>> {
+        self.monitor.command(command);
+        let _transfer = self.monitor.start_transfer();
+
+        // Set into command mode
+        let Ok(()) = self.data_command.set_low();
+        self.write(&[command as u8]).await?;
+        Ok(())
+    }
+
+    async fn send_data(&mut self, data: &[u8]) -> Result<(), SendDataError<SPI::Error>> {
+        self.monitor.data(data);
+        let _transfer = self.monitor.start_transfer();
+
+        // Set into data mode
+        let Ok(()) = self.data_command.set_high();
+        self.write(data).await?;
+        Ok(())
+    }
+
+    /// Sends the bytes again after an error as often as the retry policy allows
+    async fn write(&mut self, bytes: &[u8]) -> Result<(), SPI::Error> {
+        let mut retries = 0;
+        loop {
+            let Err(error) = self.spi.write(bytes).await else {
+                return Ok(());
+            };
+
+            if retries >= self.retry_policy.retries {
+                return Err(error);
+            }
+
+            defmt::warn!(
+                "SPI transaction failed, sending it again: {:?}",
+                defmt::Debug2Format(&error)
+            );
+            retries += 1;
+            self.statistics.record_retry();
+            Timer::after(self.retry_policy.delay).await;
+        }
+    }
+
+    async fn wait_for_idle(
+        &mut self,
+        operation: BusyOperation,
+    ) -> Result<(), WaitForBusyTimeoutError> {
+        let longest_refresh = match operation {
+            // The statistics don't tell the waveforms written for fast refreshes apart
+            BusyOperation::Refresh(RefreshMode::Fast)
+                if self.is_custom_lut_active || self.is_animation_lut_active =>
+            {
+                None
+            }
+            BusyOperation::Refresh(mode) => {
+                let statistics = self.statistics.get(mode);
+                (statistics.count > 0).then_some(statistics.maximum)
+            }
+            _ => None,
+        };
+        let duration = operation.timeout(longest_refresh, self.temperature);
+        let Ok(()) = self
+            .monitor
+            .wait_for_busy(duration, self.busy.wait_for_low())
+            .await
+            .map_err(|_| WaitForBusyTimeoutError { operation })?;
+        Ok(())
+    }
+
+    /// Sends the command followed by its parameters. The data/command line changes in between, so
+    /// these are two transactions, but the parameters go out in one instead of one per byte.
+    async fn send_command_with_data(
+        &mut self,
+        command: Command,
+        data: &[u8],
+    ) -> Result<(), SendCommandWithDataError<SPI::Error>> {
+        self.send_command(command).await?;
+        self.send_data(data).await?;
+        Ok(())
+    }
+
+    async fn set_ram_area(&mut self, region: Region) -> Result<(), SetRamAreaError<SPI::Error>> {
+        // Data entry x increment y decrement???
+        const DATA_ENTRY_X_INC_Y_DEC: u8 = 0x01;
+
+        let window = region
+            .ram_window()
+            .map_err(|reason| SetRamAreaError::InvalidRegion(region, reason))?;
+        // The controller takes addresses with the low byte first
+        let [x_start_low, x_start_high] = window.x_start.to_le_bytes();
+        let [x_end_low, x_end_high] = window.x_end.to_le_bytes();
+        let [y_start_low, y_start_high] = window.y_start.to_le_bytes();
+        let [y_end_low, y_end_high] = window.y_end.to_le_bytes();
+
+        self.send_command_with_data(Command::DataEntryMode, &[DATA_ENTRY_X_INC_Y_DEC])
+            .await?;
+
+        // Set RAM X address range (start, end) - X is in PIXELS
+        self.send_command_with_data(
+            Command::SetRamXRange,
+            &[x_start_low, x_start_high, x_end_low, x_end_high],
+        )
+        .await?;
+
+        // Set RAM Y address range (start, end) - Y is in PIXELS
+        self.send_command_with_data(
+            Command::SetRamYRange,
+            &[y_start_low, y_start_high, y_end_low, y_end_high],
+        )
+        .await?;
+
+        // Set RAM X address counter - X is in PIXELS
+        self.send_command_with_data(Command::SetRamXCounter, &[x_start_low, x_start_high])
+            .await?;
+
+        // Set RAM Y address counter - Y is in PIXELS
+        self.send_command_with_data(Command::SetRamYCounter, &[y_start_low, y_start_high])
+            .await?;
+        Ok(())
+    }
+
+    async fn initialize_controller(&mut self) -> Result<(), InitializeControllerError<SPI::Error>> {
+        info!("Initializing SSD1677 controller");
+
+        // Soft reset
+        self.send_command(Command::SoftReset).await?;
+        self.wait_for_idle(BusyOperation::SoftReset).await?;
+
+        // Temperature sensor control (internal)
+        const TEMPERATURE_SENSOR_INTERNAL: u8 = 0x80;
+        self.send_command_with_data(
+            Command::TemperatureSensorControl,
+            &[TEMPERATURE_SENSOR_INTERNAL],
+        )
+        .await?;
+
+        // Booster soft-start control (GDEQ0426T82 specific values)
+        self.send_command_with_data(
+            Command::BoosterSoftStart,
+            &[0xAE, 0xC7, 0xC3, 0xC0, 0xC0, 0x40],
+        )
+        .await?;
+
+        // Driver output control: set display height (480) and scan direction
+        let [gates_low, gates_high] = (DISPLAY_HEIGHT - 1).to_le_bytes();
+        self.send_command_with_data(Command::DriverOutputControl, &[gates_low, gates_high, 0x02])
+            .await?;
+
+        // Border waveform control
+        self.send_command_with_data(Command::BorderWaveformControl, &[self.border as u8])
+            .await?;
+
+        // Set up full screen RAM area
+        self.set_ram_area(Region::FULL).await?;
+
+        info!("Clearing RAM buffers");
+        // Auto write BW RAM
+        self.send_command_with_data(Command::AutoWriteBwRam, &[0xF7])
+            .await?;
+        self.wait_for_idle(BusyOperation::ClearRam).await?;
+
+        // Auto write Red RAM
+        self.send_command_with_data(Command::AutoWriteRedRam, &[0xF7])
+            .await?;
+        self.wait_for_idle(BusyOperation::ClearRam).await?;
+
+        info!("SSD1677 controller initialized");
+        Ok(())
+    }
+
+    /// Takes the reset and data/command lines as outputs and the busy line as an input with a
+    /// pull-down
+    pub async fn initialize(
+        spi: SPI,
+        reset: RESET,
+        data_command: DC,
+        busy: BUSY,
+        monitor: M,
+    ) -> Result<Self, InitializationError<SPI::Error>> {
+        info!("Initializing e-ink display driver");
+        let mut this = Self::new(spi, reset, data_command, busy, monitor);
+
+        this.reset().await;
+
+        this.initialize_controller().await?;
+
+        match this.read_temperature().await {
+            Ok(temperature) => info!(
+                "Display temperature: {} degrees Celsius",
+                temperature.celsius()
+            ),
+            Err(error) => defmt::warn!(
+                "Failed to read display temperature: {:?}",
+                defmt::Debug2Format(&error)
+            ),
+        }
+
+        info!("E-ink display driver initialized");
+
+        Ok(this)
+    }
+
+    async fn refresh(
+        &mut self,
+        mode: RefreshMode,
+        turn_screen_off: bool,
+    ) -> Result<(), RefreshError<SPI::Error>> {
+        // Configure Display Update Control 1
+        self.send_command(Command::DisplayUpdateControl1).await?;
+        // Configure buffer comparison mode
+        let mut control_mode = match mode {
+            RefreshMode::Fast => ControlMode::Normal,
+            RefreshMode::Full | RefreshMode::HalfRefresh => ControlMode::BypassRed,
+        } as u8;
+
+        if self.is_inverted {
+            control_mode |= INVERT_RAM;
+        }
+
+        self.send_data(&[control_mode, 0x00]).await?;
+
+        // (From crosspoint/open xteink community sdk)
+        // best guess at display mode bits:
+        // bit | hex | name                    | effect
+        // ----+-----+--------------------------+-------------------------------------------
+        // 7   | 80  | CLOCK_ON                | Start internal oscillator
+        // 6   | 40  | ANALOG_ON               | Enable analog power rails (VGH/VGL drivers)
+        // 5   | 20  | TEMP_LOAD               | Load temperature (internal or I2C)
+        // 4   | 10  | LUT_LOAD                | Load waveform LUT
+        // 3   | 08  | MODE_SELECT             | Mode 1/2
+        // 2   | 04  | DISPLAY_START           | Run display
+        // 1   | 02  | ANALOG_OFF_PHASE        | Shutdown step 1 (undocumented)
+        // 0   | 01  | CLOCK_OFF               | Disable internal oscillato
+
+        // Select appropriate display mode based on refresh type
+        // let mut display_mode = 0b0000_0000u8;
+        let mut display_mode = 0x00;
+
+        if self.power != PowerState::PoweredOn {
+            info!("Turning screen on");
+            // Set CLOCK_ON and ANALOG_ON bits
+            self.power = PowerState::PoweredOn;
+            // display_mode |= 0b1100_0000
+            display_mode |= 0xC0;
+        }
+
+        if turn_screen_off {
+            info!("Turning screen off");
+            self.power = PowerState::Standby;
+            // Set ANALOG_OFF_PHASE and CLOCK_OFF bits
+            // 0x03;
+            display_mode |= 0b000_00011;
+        }
+
+        match mode {
+            RefreshMode::Fast => {
+                let is_lut_written = self.is_custom_lut_active || self.is_animation_lut_active;
+                // A custom LUT is already tuned, so it is kept
+                if self.is_turbo && !is_lut_written {
+                    self.send_command(Command::WriteTemperature).await?;
+                    self.send_data(&[HOT_TEMPERATURE]).await?;
+                }
+
+                display_mode |= if is_lut_written {
+                    // 0x0C
+                    0b0000_1100
+                } else {
+                    // 0x1C
+                    0b0001_1100
+                };
+            }
+            RefreshMode::Full => {
+                // 0x34;
+                display_mode |= 0b0011_0100;
+            }
+            RefreshMode::HalfRefresh => {
+                // Write high temp to the register for a faster refresh
+                self.send_command(Command::WriteTemperature).await?;
+                self.send_data(&[HOT_TEMPERATURE]).await?;
+                display_mode |= 0b1101_0100;
+            }
+        }
+
+        // Power on and refresh display
+        self.send_command(Command::DisplayUpdateControl2).await?;
+        self.send_data(&[display_mode]).await?;
+
+        let Ok(is_busy) = self.busy.is_high();
+        defmt::debug!("Is busy? {}", is_busy);
+        self.send_command(Command::MasterActivation).await?;
+
+        // Wait for display to finish updating
+        self.monitor.panel_state(PanelState::Refreshing(mode));
+        let start = Instant::now();
+        let result = self.wait_for_idle(BusyOperation::Refresh(mode)).await;
+        self.monitor.panel_state(PanelState::Idle);
+        result?;
+
+        self.statistics.record(mode, start.elapsed());
+
+        Ok(())
+    }
+
+    /// Last temperature measured by the sensor of the controller. None until the first refresh
+    /// measured it or if the measurement failed.
+    pub fn temperature(&self) -> Option<Temperature> {
+        self.temperature
+    }
+
+    /// Busy durations of the refreshes and SPI retries since start up or the last reset
+    pub fn statistics(&self) -> &RefreshStatistics {
+        &self.statistics
+    }
+
+    pub fn reset_statistics(&mut self) {
+        self.statistics.reset();
+    }
+
+    /// Sets how often failed SPI transactions are sent again before the error is reported
+    pub fn set_retry_policy(&mut self, policy: RetryPolicy) {
+        self.retry_policy = policy;
+    }
+
+    /// Sets after how many consecutive fast or half refreshes the next one is upgraded to a full
+    /// refresh to clear ghosting. None turns the upgrade off.
+    pub fn set_full_refresh_interval(&mut self, interval: Option<NonZeroU16>) {
+        self.ghosting_policy.set_interval(interval);
+    }
+
+    /// Trades more ghosting for quicker page turns. Takes effect with the next refresh.
+    pub fn set_turbo(&mut self, is_turbo: bool) {
+        self.is_turbo = is_turbo;
+        self.ghosting_policy.set_relaxed(is_turbo);
+    }
+
+    pub fn is_turbo(&self) -> bool {
+        self.is_turbo
+    }
+
+    /// Whether the next refresh shows the whole frame no matter what changed, because the screen is
+    /// off or a setting like dark mode only shows with a full refresh
+    pub fn needs_whole_frame(&self) -> bool {
+        self.power != PowerState::PoweredOn || self.is_full_refresh_required
+    }
+
+    /// Time since the last full refresh. None if there was none yet.
+    pub fn since_full_refresh(&self) -> Option<Duration> {
+        self.last_full_refresh.map(|instant| instant.elapsed())
+    }
+
+    /// Consecutive fast and half refreshes since the last full refresh
+    pub fn partial_refreshes_since_full(&self) -> u16 {
+        self.ghosting_policy.partial_refreshes()
+    }
+
+    /// Restarts counting towards the next automatic full refresh, e.g. to align it with chapter
+    /// boundaries
+    pub fn reset_partial_refresh_count(&mut self) {
+        self.ghosting_policy.reset();
+    }
+
+    /// Returns the refresh mode that was used which can differ from the requested one. If refreshes
+    /// keep failing, the controller is recovered and the frame shown with a full refresh.
+    pub async fn display(
+        &mut self,
+        refresh_mode: RefreshMode,
+        frame: &Frame,
+    ) -> Result<RefreshMode, DisplayError<SPI::Error>> {
+        self.display_changed(refresh_mode, frame, Region::FULL)
+            .await
+    }
+
+    /// The same as [`Self::display`], but a fast refresh only sends the region that changed since
+    /// the last frame, as the controller RAM still holds the rest. Other refreshes send the whole
+    /// frame.
+    pub async fn display_changed(
+        &mut self,
+        refresh_mode: RefreshMode,
+        frame: &Frame,
+        changed: Region,
+    ) -> Result<RefreshMode, DisplayError<SPI::Error>> {
+        changed
+            .validate()
+            .map_err(|reason| DisplayError::InvalidRegion(changed, reason))?;
+
+        if self.power == PowerState::DeepSleep && !self.is_auto_wake {
+            return Err(DisplayError::Asleep);
+        }
+
+        self.wake().await?;
+        match self.try_display(refresh_mode, frame, changed).await {
+            Ok(refresh_mode) => Ok(refresh_mode),
+            Err(error) => self.recover_from(error, frame).await,
+        }
+    }
+
+    /// Gets the panel going again after showing the frame failed. A controller that stayed busy
+    /// through a refresh is reset right away, as it doesn't get over that by itself. Other
+    /// failures only lead to a reset when they pile up, see [`StaleBusyWatchdog`].
+    ///
+    /// After the reset the frame is shown once more with a full refresh. The display either
+    /// recovered then, which publishes an event, or is unrecoverable.
+    async fn recover_from(
+        &mut self,
+        error: DisplayError<SPI::Error>,
+        frame: &Frame,
+    ) -> Result<RefreshMode, DisplayError<SPI::Error>> {
+        if matches!(
+            error,
+            DisplayError::InvalidRegion(..) | DisplayError::Asleep
+        ) {
+            return Err(error);
+        }
+
+        if !error.is_busy_timeout() && !self.watchdog.record_failure() {
+            return Err(error);
+        }
+
+        defmt::warn!(
+            "Display stopped responding, recovering after: {:?}",
+            defmt::Debug2Format(&error)
+        );
+        self.recover().await?;
+        match self
+            .try_display(RefreshMode::Full, frame, Region::FULL)
+            .await
+        {
+            Ok(refresh_mode) => {
+                self.watchdog.record_recovery();
+                self.monitor.recovered();
+                Ok(refresh_mode)
+            }
+            Err(error) => Err(DisplayError::Unrecoverable(Box::new(error))),
+        }
+    }
+
+    /// Resets and initializes the controller again and sends a custom LUT again. The next refresh
+    /// is a full refresh to clear whatever the failed refreshes left behind.
+    async fn recover(&mut self) -> Result<(), InitializeControllerError<SPI::Error>> {
+        self.monitor.panel_state(PanelState::Idle);
+        self.reset().await;
+        self.initialize_controller().await?;
+
+        // The reset turned the screen off and brought back the waveform from OTP, so a custom one
+        // needs to be sent again
+        self.power = PowerState::Standby;
+        self.is_custom_lut_active = false;
+        self.is_full_refresh_required = true;
+        if let Some(lut) = self.custom_lut.take() {
+            let restored = self.write_lut(&lut).await;
+            self.custom_lut = Some(lut);
+            restored?;
+            self.is_custom_lut_active = true;
+        }
+        Ok(())
+    }
+
+    /// Resets and initializes the controller like after a failure, e.g. to time the initialization.
+    /// The next refresh is a full refresh.
+    pub async fn reinitialize(&mut self) -> Result<(), InitializeControllerError<SPI::Error>> {
+        self.recover().await
+    }
+
+    /// Whether the controller is in deep sleep. The panel keeps showing the last frame.
+    pub fn is_asleep(&self) -> bool {
+        self.power == PowerState::DeepSleep
+    }
+
+    /// Whether showing a frame in deep sleep wakes the controller, which is the default. Otherwise
+    /// it fails with [`DisplayError::Asleep`] until [`Self::wake`] is called, e.g. to keep the
+    /// display off until the power button is pressed.
+    pub fn set_auto_wake(&mut self, is_auto_wake: bool) {
+        self.is_auto_wake = is_auto_wake;
+    }
+
+    /// Brings the controller out of deep sleep, which is also done by the next refresh unless auto
+    /// wake is off. The next refresh is a full refresh, as the reset cleared the RAM buffers.
+    pub async fn wake(&mut self) -> Result<(), InitializeControllerError<SPI::Error>> {
+        if self.power != PowerState::DeepSleep {
+            return Ok(());
+        }
+
+        info!("Waking display");
+        // Leaves the controller in standby
+        self.recover().await?;
+        Ok(())
+    }
+
+    async fn try_display(
+        &mut self,
+        mut refresh_mode: RefreshMode,
+        frame: &Frame,
+        changed: Region,
+    ) -> Result<RefreshMode, DisplayError<SPI::Error>> {
+        if self.power == PowerState::Standby && refresh_mode == RefreshMode::Fast {
+            // Turning the screen on takes at least a half refresh. More thorough ones are kept.
+            refresh_mode = RefreshMode::HalfRefresh;
+        }
+
+        if let Some(temperature) = self.current_temperature().await {
+            refresh_mode = temperature.adjust(refresh_mode);
+        }
+
+        if self.is_full_refresh_required || self.has_animation_ghosting {
+            refresh_mode = RefreshMode::Full;
+        }
+
+        refresh_mode = self.ghosting_policy.apply(refresh_mode);
+
+        match refresh_mode {
+            RefreshMode::Fast => {
+                // For fast refresh, write to BW buffer only
+                self.write_region(Command::WriteBwRam, frame, changed, false)
+                    .await?;
+            }
+            RefreshMode::HalfRefresh | RefreshMode::Full => {
+                // For full refresh, write to both buffers before refresh
+                self.write_region(Command::WriteBwRam, frame, Region::FULL, false)
+                    .await?;
+                self.write_region(Command::WriteRedRam, frame, Region::FULL, false)
+                    .await?;
+            }
+        }
+
+        self.refresh(refresh_mode, false).await?;
+
+        if refresh_mode == RefreshMode::Full {
+            self.last_full_refresh = Some(Instant::now());
+            self.is_full_refresh_required = false;
+            self.has_animation_ghosting = false;
+        }
+
+        Ok(refresh_mode)
+    }
+
+    /// Dark mode with white content on black. Inverted in the controller, so frames are drawn as
+    /// usual. Takes effect with the next refresh which will be a full refresh.
+    pub fn set_inverted(&mut self, is_inverted: bool) {
+        if self.is_inverted != is_inverted {
+            self.is_inverted = is_inverted;
+            self.is_full_refresh_required = true;
+        }
+    }
+
+    async fn write_region(
+        &mut self,
+        ram: Command,
+        frame: &Frame,
+        region: Region,
+        invert: bool,
+    ) -> Result<(), DisplayError<SPI::Error>> {
+        self.set_ram_area(region).await?;
+        self.send_command(ram).await?;
+
+        // Rows across the whole width follow each other in the frame and go out in one transaction
+        if region.width == DISPLAY_WIDTH && !invert {
+            let row_bytes = usize::from(DISPLAY_WIDTH / 8);
+            let start = usize::from(region.y) * row_bytes;
+            let end = start + usize::from(region.height) * row_bytes;
+            self.send_data(&frame[start..end]).await?;
+            return Ok(());
+        }
+
+        // Otherwise a few rows are gathered per transaction to not pay its overhead for each row
+        let mut buffer = [0; DISPLAY_WIDTH as usize / 8 * ROWS_PER_TRANSACTION];
+        let mut length = 0;
+        for row in frame.region_rows(region) {
+            let rows = &mut buffer[length..length + row.len()];
+            rows.copy_from_slice(row);
+            if invert {
+                rows.iter_mut().for_each(|byte| *byte = !*byte);
+            }
+
+            length += row.len();
+            if length + row.len() > buffer.len() {
+                self.send_data(&buffer[..length]).await?;
+                length = 0;
+            }
+        }
+
+        if length > 0 {
+            self.send_data(&buffer[..length]).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Updates most of the panel with a fast refresh while the given regions, e.g. images, get a
+    /// local full refresh. This balances quality and speed on pages mixing text and images.
+    ///
+    /// The controller always refreshes the whole panel, so the full refresh is emulated by having
+    /// every pixel in the regions differ between the RAM buffers. This drives them to the inverse
+    /// and back with two more fast refreshes while the rest of the panel stays untouched.
+    pub async fn display_composite(
+        &mut self,
+        frame: &Frame,
+        full_regions: &[Region],
+    ) -> Result<(), DisplayError<SPI::Error>> {
+        for region in full_regions {
+            region
+                .validate()
+                .map_err(|reason| DisplayError::InvalidRegion(*region, reason))?;
+        }
+
+        let refresh_mode = self.display(RefreshMode::Fast, frame).await?;
+        if refresh_mode != RefreshMode::Fast || full_regions.is_empty() {
+            // Everything got a proper refresh already
+            return Ok(());
+        }
+
+        // Sync the RAM holding the previous frame so that only the regions differ from now on
+        self.write_region(Command::WriteRedRam, frame, Region::FULL, false)
+            .await?;
+
+        for region in full_regions {
+            self.write_region(Command::WriteBwRam, frame, *region, true)
+                .await?;
+        }
+        self.refresh(RefreshMode::Fast, false).await?;
+
+        for region in full_regions {
+            self.write_region(Command::WriteRedRam, frame, *region, true)
+                .await?;
+            self.write_region(Command::WriteBwRam, frame, *region, false)
+                .await?;
+        }
+        self.refresh(RefreshMode::Fast, false).await?;
+
+        for region in full_regions {
+            self.write_region(Command::WriteRedRam, frame, *region, false)
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Updates only the region with a fast refresh, e.g. for a status bar that changes on its own.
+    /// The rest of the panel keeps its content even if the frame differs there, unless the screen
+    /// is off or needs a full refresh anyway. Then the whole frame is shown.
+    pub async fn display_region(
+        &mut self,
+        frame: &Frame,
+        region: Region,
+    ) -> Result<(), DisplayError<SPI::Error>> {
+        region
+            .validate()
+            .map_err(|reason| DisplayError::InvalidRegion(region, reason))?;
+
+        // A fast refresh only changes the pixels that differ between the RAM buffers, so only the
+        // region is written. Without a screen that is on, the whole panel needs a proper refresh.
+        if self.needs_whole_frame() {
+            self.display(RefreshMode::Full, frame).await?;
+            return Ok(());
+        }
+
+        if let Err(error) = self.try_display_region(frame, region).await {
+            self.recover_from(error, frame).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn try_display_region(
+        &mut self,
+        frame: &Frame,
+        region: Region,
+    ) -> Result<(), DisplayError<SPI::Error>> {
+        self.write_region(Command::WriteBwRam, frame, region, false)
+            .await?;
+        self.refresh(RefreshMode::Fast, false).await?;
+        self.write_region(Command::WriteRedRam, frame, region, false)
+            .await?;
+        Ok(())
+    }
+
+    /// Updates only the region with the animation waveform, which takes a fraction of a fast
+    /// refresh, e.g. for a cursor moving through a menu. The next refresh of the whole panel is a
+    /// full refresh to clear the ghosting it leaves behind. Regions that are too large for it get
+    /// a fast refresh, see [`Self::display_region`].
+    pub async fn display_animation(
+        &mut self,
+        frame: &Frame,
+        region: Region,
+    ) -> Result<(), DisplayError<SPI::Error>> {
+        region
+            .validate()
+            .map_err(|reason| DisplayError::InvalidRegion(region, reason))?;
+
+        let area = u32::from(region.width) * u32::from(region.height);
+        let panel = u32::from(DISPLAY_WIDTH) * u32::from(DISPLAY_HEIGHT);
+        if self.needs_whole_frame() || area * ANIMATION_AREA_DIVISOR > panel {
+            return self.display_region(frame, region).await;
+        }
+
+        self.write_lut(&Lut::ANIMATION).await?;
+        self.is_animation_lut_active = true;
+        self.has_animation_ghosting = true;
+        let result = self.display_region(frame, region).await;
+        self.is_animation_lut_active = false;
+
+        // The next refresh loads the waveform from OTP anyway, but a custom one needs to be sent
+        // again
+        if let Some(lut) = self.custom_lut.take() {
+            let restored = self.write_lut(&lut).await;
+            self.custom_lut = Some(lut);
+            if restored.is_err() {
+                self.is_custom_lut_active = false;
+            }
+            restored?;
+        }
+
+        result
+    }
+
+    /// Measures the temperature with the controller's internal sensor.
+    /// This requires the display data line to be readable through the SPI device.
+    pub async fn read_temperature(
+        &mut self,
+    ) -> Result<Temperature, ReadTemperatureError<SPI::Error>> {
+        self.temperature_checked_at = Some(Instant::now());
+
+        // Load the temperature from the sensor into the register. Not loading the LUT as that would
+        // replace a custom LUT.
+        // 0x20 TEMP_LOAD
+        let mut display_mode = 0b0010_0000;
+        if self.power != PowerState::PoweredOn {
+            // Start the oscillator just for the measurement
+            // 0x81 CLOCK_ON and CLOCK_OFF
+            display_mode |= 0b1000_0001;
+        }
+
+        self.send_command(Command::DisplayUpdateControl2).await?;
+        self.send_data(&[display_mode]).await?;
+        self.send_command(Command::MasterActivation).await?;
+        self.wait_for_idle(BusyOperation::MeasureTemperature)
+            .await?;
+
+        self.send_command(Command::ReadTemperature).await?;
+        // Set into data mode
+        let Ok(()) = self.data_command.set_high();
+        let mut register = [0; 2];
+        self.spi
+            .read(&mut register)
+            .await
+            .map_err(ReadTemperatureError::Read)?;
+
+        if register == [0xFF, 0xFF] {
+            return Err(ReadTemperatureError::NoResponse);
+        }
+
+        let temperature = Temperature::from_register(register);
+        if !temperature.is_plausible() {
+            return Err(ReadTemperatureError::Implausible(temperature));
+        }
+
+        self.temperature = Some(temperature);
+        Ok(temperature)
+    }
+
+    /// The last measured temperature which gets measured again when it is outdated. None if the
+    /// temperature could never be read.
+    async fn current_temperature(&mut self) -> Option<Temperature> {
+        let is_outdated = self
+            .temperature_checked_at
+            .is_none_or(|checked_at| checked_at.elapsed() >= temperature::MAXIMUM_AGE);
+
+        if is_outdated && let Err(error) = self.read_temperature().await {
+            defmt::warn!(
+                "Failed to read display temperature: {:?}",
+                defmt::Debug2Format(&error)
+            );
+        }
+
+        self.temperature
+    }
+
+    /// Changes the border around the active area to match the content, e.g. black for a dark sleep
+    /// screen. Takes effect with the next refresh.
+    pub async fn set_border(
+        &mut self,
+        border: BorderColor,
+    ) -> Result<(), SetBorderError<SPI::Error>> {
+        self.send_command(Command::BorderWaveformControl).await?;
+        self.send_data(&[border as u8]).await?;
+        self.border = border;
+        Ok(())
+    }
+
+    /// Replaces the waveform used for fast refreshes until the custom LUT is cleared
+    pub async fn set_custom_lut(&mut self, lut: &Lut) -> Result<(), SetCustomLutError<SPI::Error>> {
+        info!("Setting custom LUT");
+        self.write_lut(lut).await?;
+        self.is_custom_lut_active = true;
+        self.custom_lut = Some(lut.clone());
+        Ok(())
+    }
+
+    async fn write_lut(&mut self, lut: &Lut) -> Result<(), SetCustomLutError<SPI::Error>> {
+        self.send_command(Command::WriteLut).await?;
+        self.send_data(&lut.waveform).await?;
+
+        self.send_command(Command::GateVoltage).await?;
+        self.send_data(&[lut.gate_voltage]).await?;
+
+        self.send_command(Command::SourceVoltage).await?;
+        self.send_data(&lut.source_voltage).await?;
+
+        self.send_command(Command::WriteVcom).await?;
+        self.send_data(&[lut.vcom]).await?;
+        Ok(())
+    }
+
+    /// Goes back to the waveform from the controller's OTP memory which is loaded on the next
+    /// fast refresh
+    pub fn clear_custom_lut(&mut self) {
+        self.is_custom_lut_active = false;
+        self.custom_lut = None;
+    }
+
+    pub async fn enter_deep_sleep(&mut self) -> Result<(), EnterDeepSleepError<SPI::Error>> {
+        info!("Preparing display to enter deep sleep");
+        // First, power down the display properly
+        // This shuts down the analog power rails and clock
+        if self.power == PowerState::PoweredOn {
+            self.send_command(Command::DisplayUpdateControl1).await?;
+            self.send_data(&[ControlMode::BypassRed as u8]).await?;
+
+            self.send_command(Command::DisplayUpdateControl2).await?;
+            // Set ANALOG_OFF_PHASE (bit 1) and CLOCK_OFF (bit 0)
+            // 0x03
+            self.send_data(&[0b0000_0011]).await?;
+
+            // Wait for the power-down sequence to complete
+            self.wait_for_idle(BusyOperation::PowerDown).await?;
+
+            self.power = PowerState::Standby;
+        }
+
+        info!("Entering deep sleep");
+        // Now enter deep sleep mode
+        self.send_command(Command::DeepSleep).await?;
+        // Enter deep sleep
+        self.send_data(&[0x01]).await?;
+        self.power = PowerState::DeepSleep;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use alloc::rc::Rc;
+    use alloc::vec;
+    use alloc::vec::Vec;
+    use core::cell::RefCell;
+
+    use crustpoint_core::RegionError;
+    use embassy_futures::block_on;
+    use embedded_hal::digital::ErrorType;
+    use embedded_hal_mock::eh1::spi::{Mock, Transaction};
+
+    use super::*;
+
+    /// Records the levels it is set to, shared with the test as the driver owns the pin
+    #[derive(Clone, Default)]
+    struct OutputLog(Rc<RefCell<Vec<bool>>>);
+
+    impl OutputLog {
+        fn levels(&self) -> Vec<bool> {
+            self.0.borrow().clone()
+        }
+    }
+
+    impl ErrorType for OutputLog {
+        type Error = Infallible;
+    }
+
+    impl OutputPin for OutputLog {
+        fn set_low(&mut self) -> Result<(), Infallible> {
+            self.0.borrow_mut().push(false);
+            Ok(())
+        }
+
+        fn set_high(&mut self) -> Result<(), Infallible> {
+            self.0.borrow_mut().push(true);
+            Ok(())
+        }
+    }
+
+    /// A controller that finishes everything right away
+    struct IdleBusyLine;
+
+    impl ErrorType for IdleBusyLine {
+        type Error = Infallible;
+    }
+
+    impl InputPin for IdleBusyLine {
+        fn is_high(&mut self) -> Result<bool, Infallible> {
+            Ok(false)
+        }
+
+        fn is_low(&mut self) -> Result<bool, Infallible> {
+            Ok(true)
+        }
+    }
+
+    impl Wait for IdleBusyLine {
+        async fn wait_for_high(&mut self) -> Result<(), Infallible> {
+            unreachable!("The driver only waits for the controller to become idle")
+        }
+
+        async fn wait_for_low(&mut self) -> Result<(), Infallible> {
+            Ok(())
+        }
+
+        async fn wait_for_rising_edge(&mut self) -> Result<(), Infallible> {
+            unreachable!("The driver only waits for the controller to become idle")
+        }
+
+        async fn wait_for_falling_edge(&mut self) -> Result<(), Infallible> {
+            unreachable!("The driver only waits for the controller to become idle")
+        }
+
+        async fn wait_for_any_edge(&mut self) -> Result<(), Infallible> {
+            unreachable!("The driver only waits for the controller to become idle")
+        }
+    }
+
+    type TestDisplay = EinkDisplay<Mock<u8>, OutputLog, OutputLog, IdleBusyLine, ()>;
+
+    /// The transactions the driver should send and the level of the data/command line for each
+    #[derive(Default)]
+    struct Expected {
+        transactions: Vec<Transaction<u8>>,
+        data_command: Vec<bool>,
+    }
+
+    impl Expected {
+        fn transaction(mut self, operation: Transaction<u8>, is_data: bool) -> Self {
+            self.transactions.extend([
+                Transaction::transaction_start(),
+                operation,
+                Transaction::transaction_end(),
+            ]);
+            self.data_command.push(is_data);
+            self
+        }
+
+        /// The command followed by its parameters, if it has any
+        fn command(self, command: Command, data: &[u8]) -> Self {
+            let this = self.transaction(Transaction::write_vec(vec![command as u8]), false);
+            if data.is_empty() {
+                return this;
+            }
+
+            this.transaction(Transaction::write_vec(data.to_vec()), true)
+        }
+
+        fn read(self, response: &[u8]) -> Self {
+            self.transaction(Transaction::read_vec(response.to_vec()), true)
+        }
+    }
+
+    /// Runs the operation against mocks and checks that it sent exactly the expected transactions
+    fn assert_sends(expected: Expected, operation: impl AsyncFnOnce(&mut TestDisplay)) {
+        let mut spi = Mock::new(&expected.transactions);
+        let data_command = OutputLog::default();
+        let mut display = EinkDisplay::new(
+            spi.clone(),
+            OutputLog::default(),
+            data_command.clone(),
+            IdleBusyLine,
+            (),
+        );
+
+        block_on(operation(&mut display));
+
+        spi.done();
+        assert_eq!(data_command.levels(), expected.data_command);
+    }
+
+    /// What the initialization and a reset after a failure send
+    fn initialize_controller(expected: Expected) -> Expected {
+        expected
+            .command(Command::SoftReset, &[])
+            .command(Command::TemperatureSensorControl, &[0x80])
+            .command(
+                Command::BoosterSoftStart,
+                &[0xAE, 0xC7, 0xC3, 0xC0, 0xC0, 0x40],
+            )
+            // 479 gates
+            .command(Command::DriverOutputControl, &[0xDF, 0x01, 0x02])
+            .command(Command::BorderWaveformControl, &[BorderColor::White as u8])
+            .command(Command::DataEntryMode, &[0x01])
+            // X from 0 to 799 and Y from 479 down to 0
+            .command(Command::SetRamXRange, &[0x00, 0x00, 0x1F, 0x03])
+            .command(Command::SetRamYRange, &[0xDF, 0x01, 0x00, 0x00])
+            .command(Command::SetRamXCounter, &[0x00, 0x00])
+            .command(Command::SetRamYCounter, &[0xDF, 0x01])
+            .command(Command::AutoWriteBwRam, &[0xF7])
+            .command(Command::AutoWriteRedRam, &[0xF7])
+    }
+
+    #[test]
+    fn initialization_resets_and_configures_the_controller() {
+        let expected = initialize_controller(Expected::default())
+            // Loads the temperature with the clock turned on just for it
+            .command(Command::DisplayUpdateControl2, &[0xA1])
+            .command(Command::MasterActivation, &[])
+            .command(Command::ReadTemperature, &[])
+            // 25 degrees Celsius in sixteenths in the upper 12 bits
+            .read(&[0x19, 0x00]);
+
+        let mut spi = Mock::new(&expected.transactions);
+        let reset = OutputLog::default();
+        let data_command = OutputLog::default();
+        let display = block_on(EinkDisplay::initialize(
+            spi.clone(),
+            reset.clone(),
+            data_command.clone(),
+            IdleBusyLine,
+            (),
+        ))
+        .unwrap();
+
+        spi.done();
+        assert_eq!(reset.levels(), [true, false, true]);
+        assert_eq!(data_command.levels(), expected.data_command);
+        assert_eq!(display.temperature().map(Temperature::celsius), Some(25));
+    }
+
+    #[test]
+    fn ram_area_counts_rows_down_from_the_mirrored_start() {
+        let region = Region {
+            x: 16,
+            y: 10,
+            width: 8,
+            height: 20,
+        };
+        let expected = Expected::default()
+            .command(Command::DataEntryMode, &[0x01])
+            // X from 16 to 23 and Y from 469 down to 450, low byte first
+            .command(Command::SetRamXRange, &[0x10, 0x00, 0x17, 0x00])
+            .command(Command::SetRamYRange, &[0xD5, 0x01, 0xC2, 0x01])
+            .command(Command::SetRamXCounter, &[0x10, 0x00])
+            .command(Command::SetRamYCounter, &[0xD5, 0x01]);
+
+        assert_sends(expected, async |display| {
+            display.set_ram_area(region).await.unwrap();
+        });
+    }
+
+    #[test]
+    fn ram_area_of_an_invalid_region_sends_nothing() {
+        let unaligned = Region {
+            x: 4,
+            y: 0,
+            width: 8,
+            height: 8,
+        };
+
+        assert_sends(Expected::default(), async |display| {
+            let result = display.set_ram_area(unaligned).await;
+            assert!(matches!(
+                result,
+                Err(SetRamAreaError::InvalidRegion(_, RegionError::Unaligned))
+            ));
+        });
+    }
+
+    #[test]
+    fn refresh_picks_the_display_mode_bits() {
+        let expected = Expected::default()
+            // Full refresh from standby turns the clock and the analog rails on
+            .command(Command::DisplayUpdateControl1, &[0x40, 0x00])
+            .command(Command::DisplayUpdateControl2, &[0xF4])
+            .command(Command::MasterActivation, &[])
+            // Fast refresh compares the RAM buffers and loads the waveform from OTP
+            .command(Command::DisplayUpdateControl1, &[0x00, 0x00])
+            .command(Command::DisplayUpdateControl2, &[0x1C])
+            .command(Command::MasterActivation, &[])
+            // Dark mode inverts both RAM buffers
+            .command(Command::DisplayUpdateControl1, &[0x88, 0x00])
+            .command(Command::DisplayUpdateControl2, &[0x1C])
+            .command(Command::MasterActivation, &[])
+            // Half refresh loads the waveform for a warm panel and turns the screen off after
+            .command(Command::DisplayUpdateControl1, &[0xC8, 0x00])
+            .command(Command::WriteTemperature, &[HOT_TEMPERATURE])
+            .command(Command::DisplayUpdateControl2, &[0xD7])
+            .command(Command::MasterActivation, &[]);
+
+        assert_sends(expected, async |display| {
+            display.refresh(RefreshMode::Full, false).await.unwrap();
+            display.refresh(RefreshMode::Fast, false).await.unwrap();
+            display.set_inverted(true);
+            display.refresh(RefreshMode::Fast, false).await.unwrap();
+            display
+                .refresh(RefreshMode::HalfRefresh, true)
+                .await
+                .unwrap();
+            assert_eq!(display.power, PowerState::Standby);
+        });
+    }
+
+    #[test]
+    fn fast_refresh_keeps_a_written_waveform() {
+        let lut = Lut::ANIMATION;
+        let expected = Expected::default()
+            .command(Command::WriteLut, &lut.waveform)
+            .command(Command::GateVoltage, &[lut.gate_voltage])
+            .command(Command::SourceVoltage, &lut.source_voltage)
+            .command(Command::WriteVcom, &[lut.vcom])
+            .command(Command::DisplayUpdateControl1, &[0x00, 0x00])
+            // Without LUT_LOAD, which would replace the waveform with the one from OTP
+            .command(Command::DisplayUpdateControl2, &[0xCC])
+            .command(Command::MasterActivation, &[]);
+
+        assert_sends(expected, async |display| {
+            display.set_turbo(true);
+            display.set_custom_lut(&lut).await.unwrap();
+            display.refresh(RefreshMode::Fast, false).await.unwrap();
+        });
+    }
+
+    #[test]
+    fn wake_sends_the_custom_waveform_again() {
+        let lut = Lut::ANIMATION;
+        let write_lut = |expected: Expected| {
+            expected
+                .command(Command::WriteLut, &lut.waveform)
+                .command(Command::GateVoltage, &[lut.gate_voltage])
+                .command(Command::SourceVoltage, &lut.source_voltage)
+                .command(Command::WriteVcom, &[lut.vcom])
+        };
+        let expected = write_lut(Expected::default()).command(Command::DeepSleep, &[0x01]);
+        let expected = write_lut(initialize_controller(expected));
+
+        assert_sends(expected, async |display| {
+            display.set_custom_lut(&lut).await.unwrap();
+            display.enter_deep_sleep().await.unwrap();
+            display.wake().await.unwrap();
+            assert!(display.is_custom_lut_active);
+            assert_eq!(display.power, PowerState::Standby);
+        });
+    }
+}
@@ -0,0 +1,140 @@
+use alloc::boxed::Box;
+
+use crustpoint_core::{Region, RegionError};
+use embedded_hal::spi::Error;
+
+use crate::driver::BusyOperation;
+use crate::temperature::Temperature;
+
+#[derive(Debug, thiserror::Error)]
+#[error("Failed to send command")]
+pub struct SendCommandError<E: Error>(#[from] pub(crate) E);
+
+#[derive(Debug, thiserror::Error)]
+#[error("Failed to send data")]
+pub struct SendDataError<E: Error>(#[from] pub(crate) E);
+
+#[derive(Debug, thiserror::Error)]
+pub enum SendCommandWithDataError<E: Error> {
+    #[error("Failed to send command")]
+    SendCommand(#[from] SendCommandError<E>),
+    #[error("Failed to send data")]
+    SendData(#[from] SendDataError<E>),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum SetRamAreaError<E: Error> {
+    #[error("Region {0:?} has no addresses in the controller RAM: {1:?}")]
+    InvalidRegion(Region, RegionError),
+    #[error("Failed to send command with data")]
+    SendCommandWithData(#[from] SendCommandWithDataError<E>),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum InitializeControllerError<E: Error> {
+    #[error("Failed to send command")]
+    SendCommand(#[from] SendCommandError<E>),
+    #[error("Failed to send command with data")]
+    SendCommandWithData(#[from] SendCommandWithDataError<E>),
+    #[error("Timed out waiting for busy")]
+    WaitForBusy(#[from] WaitForBusyTimeoutError),
+    #[error("Failed to set RAM area")]
+    SetRamArea(#[from] SetRamAreaError<E>),
+    #[error("Failed to load waveform")]
+    SetLut(#[from] SetCustomLutError<E>),
+}
+
+#[derive(Debug, thiserror::Error, defmt::Format)]
+#[error("Timeout waiting for busy after {operation:?}")]
+pub struct WaitForBusyTimeoutError {
+    /// What the controller was still busy with
+    pub operation: BusyOperation,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum InitializationError<E: Error> {
+    #[error("Failed to initialize e-ink display controller")]
+    InitializeController(#[from] InitializeControllerError<E>),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum RefreshError<E: Error> {
+    #[error("Failed to send command")]
+    SendCommand(#[from] SendCommandError<E>),
+    #[error("Failed to send data")]
+    SendData(#[from] SendDataError<E>),
+    #[error("Failed to wait for busy")]
+    WaitForBusy(#[from] WaitForBusyTimeoutError),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum DisplayError<E: Error> {
+    #[error("Region {0:?} can't be shown: {1:?}")]
+    InvalidRegion(Region, RegionError),
+    #[error("Failed to set RAM area")]
+    SetRamArea(#[from] SetRamAreaError<E>),
+    #[error("Failed to send command")]
+    SendCommand(#[from] SendCommandError<E>),
+    #[error("Failed to send data")]
+    SendData(#[from] SendDataError<E>),
+    #[error("Failed to refresh display")]
+    Refresh(#[from] RefreshError<E>),
+    #[error("Failed to reset and initialize the display again")]
+    Recover(#[from] InitializeControllerError<E>),
+    /// Boxed as the error is recursive
+    #[error("Display failed again after it was reset and initialized")]
+    Unrecoverable(#[source] Box<DisplayError<E>>),
+    #[error("Display is in deep sleep and waking it for a refresh is off")]
+    Asleep,
+    #[error("Failed to load waveform")]
+    SetLut(#[from] SetCustomLutError<E>),
+}
+
+impl<E: Error> DisplayError<E> {
+    /// Whether the controller didn't finish a refresh in time, which it doesn't get over by itself
+    pub(crate) fn is_busy_timeout(&self) -> bool {
+        matches!(self, Self::Refresh(RefreshError::WaitForBusy(_)))
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum EnterDeepSleepError<E: Error> {
+    #[error("Failed to send command")]
+    SendCommand(#[from] SendCommandError<E>),
+    #[error("Failed to send data")]
+    SendData(#[from] SendDataError<E>),
+    #[error("Failed to wait for busy")]
+    WaitForBusy(#[from] WaitForBusyTimeoutError),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum SetCustomLutError<E: Error> {
+    #[error("Failed to send command")]
+    SendCommand(#[from] SendCommandError<E>),
+    #[error("Failed to send data")]
+    SendData(#[from] SendDataError<E>),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ReadTemperatureError<E: Error> {
+    #[error("Failed to send command")]
+    SendCommand(#[from] SendCommandError<E>),
+    #[error("Failed to send data")]
+    SendData(#[from] SendDataError<E>),
+    #[error("Failed to wait for busy")]
+    WaitForBusy(#[from] WaitForBusyTimeoutError),
+    #[error("Failed to read temperature register")]
+    Read(E),
+    #[error("Controller did not respond, the data line might not be connected for reading")]
+    NoResponse,
+    #[error("Temperature is outside of the sensor range")]
+    Implausible(Temperature),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum SetBorderError<E: Error> {
+    #[error("Failed to send command")]
+    SendCommand(#[from] SendCommandError<E>),
+    #[error("Failed to send data")]
+    SendData(#[from] SendDataError<E>),
+}
@@ -0,0 +1,73 @@
+//! Fast and half refreshes leave traces of the previous content behind which add up over time.
+//! A full refresh clears them, so after a number of consecutive partial refreshes the next one gets
+//! upgraded to a full refresh.
+
+use core::num::NonZeroU16;
+
+use defmt::info;
+
+use crate::driver::RefreshMode;
+
+const DEFAULT_INTERVAL: NonZeroU16 = NonZeroU16::new(10).unwrap();
+/// The interval is stretched by this while more ghosting is accepted for speed
+const RELAXED_FACTOR: u16 = 3;
+
+pub(crate) struct GhostingPolicy {
+    /// Every this many partial refreshes are upgraded to a full refresh. None disables the upgrade.
+    interval: Option<NonZeroU16>,
+    /// Consecutive fast and half refreshes since the last full refresh
+    partial_refreshes: u16,
+    /// Accepts more ghosting before upgrading, e.g. for quicker page turns
+    is_relaxed: bool,
+}
+
+impl GhostingPolicy {
+    pub(crate) const fn new() -> Self {
+        Self {
+            interval: Some(DEFAULT_INTERVAL),
+            partial_refreshes: 0,
+            is_relaxed: false,
+        }
+    }
+
+    pub(crate) fn set_interval(&mut self, interval: Option<NonZeroU16>) {
+        self.interval = interval;
+    }
+
+    pub(crate) fn set_relaxed(&mut self, is_relaxed: bool) {
+        self.is_relaxed = is_relaxed;
+    }
+
+    pub(crate) fn partial_refreshes(&self) -> u16 {
+        self.partial_refreshes
+    }
+
+    pub(crate) fn reset(&mut self) {
+        self.partial_refreshes = 0;
+    }
+
+    /// Counts the refresh and returns the mode that should be used instead
+    pub(crate) fn apply(&mut self, mode: RefreshMode) -> RefreshMode {
+        if mode == RefreshMode::Full {
+            self.reset();
+            return mode;
+        }
+
+        self.partial_refreshes = self.partial_refreshes.saturating_add(1);
+        let factor = if self.is_relaxed { RELAXED_FACTOR } else { 1 };
+        let is_due = self.interval.is_some_and(|interval| {
+            self.partial_refreshes >= interval.get().saturating_mul(factor)
+        });
+
+        if !is_due {
+            return mode;
+        }
+
+        info!(
+            "Upgrading {} to full refresh after {} partial refreshes",
+            mode, self.partial_refreshes
+        );
+        self.reset();
+        RefreshMode::Full
+    }
+}
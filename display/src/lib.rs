@@ -0,0 +1,25 @@
+//! Driver for the SSD1677 controller of the e-ink panel. It only talks to the controller through
+//! the traits of embedded-hal and reports to the firmware through a [`Monitor`], so the command
+//! sequences can be checked on the host against mocks.
+
+#![no_std]
+
+extern crate alloc;
+
+mod driver;
+mod error;
+mod ghosting;
+mod monitor;
+mod retry;
+mod statistics;
+mod temperature;
+mod watchdog;
+
+pub use crate::driver::{
+    BorderColor, BusyOperation, Command, EinkDisplay, PanelState, RefreshMode,
+};
+pub use crate::error::*;
+pub use crate::monitor::Monitor;
+pub use crate::retry::RetryPolicy;
+pub use crate::statistics::{RefreshStatistics, Statistics};
+pub use crate::temperature::Temperature;
@@ -0,0 +1,41 @@
+//! The firmware around the driver supervises it, keeps metrics and logs what it sends. The driver
+//! reports to it through these hooks instead of depending on the firmware.
+
+use embassy_time::{Duration, TimeoutError, with_timeout};
+
+use crate::driver::{Command, PanelState};
+
+pub trait Monitor {
+    /// Held while a transaction is on the bus, e.g. to reset the device when it never completes
+    type Transfer;
+
+    fn start_transfer(&self) -> Self::Transfer;
+
+    /// Waits for the busy line, which is the future, for at most the timeout
+    fn wait_for_busy<F: Future>(
+        &self,
+        timeout: Duration,
+        future: F,
+    ) -> impl Future<Output = Result<F::Output, TimeoutError>> {
+        with_timeout(timeout, future)
+    }
+
+    /// Called before the command is sent
+    fn command(&self, _command: Command) {}
+
+    /// Called before the data is sent
+    fn data(&self, _data: &[u8]) {}
+
+    /// Called when a refresh starts and ends
+    fn panel_state(&self, _state: PanelState) {}
+
+    /// Called when the display showed a frame again after it was reset to recover from failures
+    fn recovered(&self) {}
+}
+
+/// Doesn't report anything, e.g. for tests
+impl Monitor for () {
+    type Transfer = ();
+
+    fn start_transfer(&self) {}
+}
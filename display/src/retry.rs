@@ -0,0 +1,25 @@
+//! A single glitched SPI transaction, e.g. a DMA error, would otherwise fail the refresh of a whole
+//! page. Failed transactions are sent again a few times with a short pause in between before the
+//! error is reported.
+//!
+//! A data transaction that broke off part way is sent again from its start, which can leave a few
+//! bytes in the wrong place of the controller RAM until the region is written again. If the errors
+//! don't stop, the watchdog recovers the display with a full refresh.
+
+use embassy_time::Duration;
+
+/// How often a failed transaction is sent again and how long to wait before each attempt
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub struct RetryPolicy {
+    /// Attempts after the first one. 0 reports the first error.
+    pub retries: u8,
+    pub delay: Duration,
+}
+
+impl RetryPolicy {
+    /// Short enough to not hold up a page turn noticeably
+    pub const DEFAULT: Self = Self {
+        retries: 2,
+        delay: Duration::from_millis(2),
+    };
+}
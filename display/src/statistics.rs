@@ -0,0 +1,129 @@
+//! Measures how long the panel stays busy for each refresh mode to be able to tune waveforms and
+//! decide on a refresh policy based on data. Also counts the SPI transactions that had to be sent
+//! again, which tell a flaky connection to the panel apart from a controller that stopped.
+
+use defmt::info;
+use embassy_time::Duration;
+
+use crate::driver::RefreshMode;
+
+/// Log the statistics every this many refreshes
+const LOG_INTERVAL: u32 = 10;
+
+#[derive(Debug, Clone, Copy, defmt::Format)]
+pub struct Statistics {
+    pub count: u32,
+    pub minimum: Duration,
+    pub maximum: Duration,
+    total: Duration,
+}
+
+impl Statistics {
+    const fn new() -> Self {
+        Self {
+            count: 0,
+            minimum: Duration::MAX,
+            maximum: Duration::MIN,
+            total: Duration::MIN,
+        }
+    }
+
+    pub fn average(&self) -> Option<Duration> {
+        if self.count == 0 {
+            return None;
+        }
+
+        Some(self.total / self.count)
+    }
+
+    fn record(&mut self, duration: Duration) {
+        self.count = self.count.saturating_add(1);
+        self.minimum = self.minimum.min(duration);
+        self.maximum = self.maximum.max(duration);
+        self.total = self.total.checked_add(duration).unwrap_or(Duration::MAX);
+    }
+}
+
+#[derive(Debug, defmt::Format)]
+pub struct RefreshStatistics {
+    fast: Statistics,
+    full: Statistics,
+    half_refresh: Statistics,
+    /// SPI transactions that were sent again after an error
+    retries: u32,
+}
+
+impl RefreshStatistics {
+    pub(crate) const fn new() -> Self {
+        Self {
+            fast: Statistics::new(),
+            full: Statistics::new(),
+            half_refresh: Statistics::new(),
+            retries: 0,
+        }
+    }
+
+    pub fn get(&self, mode: RefreshMode) -> &Statistics {
+        match mode {
+            RefreshMode::Fast => &self.fast,
+            RefreshMode::Full => &self.full,
+            RefreshMode::HalfRefresh => &self.half_refresh,
+        }
+    }
+
+    fn get_mut(&mut self, mode: RefreshMode) -> &mut Statistics {
+        match mode {
+            RefreshMode::Fast => &mut self.fast,
+            RefreshMode::Full => &mut self.full,
+            RefreshMode::HalfRefresh => &mut self.half_refresh,
+        }
+    }
+
+    pub fn retries(&self) -> u32 {
+        self.retries
+    }
+
+    pub fn reset(&mut self) {
+        *self = Self::new();
+    }
+
+    /// Records how long the display was busy for a refresh with the given mode
+    pub(crate) fn record(&mut self, mode: RefreshMode, busy: Duration) {
+        self.get_mut(mode).record(busy);
+
+        let total = self.fast.count + self.full.count + self.half_refresh.count;
+        if total.is_multiple_of(LOG_INTERVAL) {
+            self.log();
+        }
+    }
+
+    pub(crate) fn record_retry(&mut self) {
+        self.retries = self.retries.saturating_add(1);
+    }
+
+    pub fn log(&self) {
+        for mode in [
+            RefreshMode::Fast,
+            RefreshMode::Full,
+            RefreshMode::HalfRefresh,
+        ] {
+            let statistics = self.get(mode);
+            let Some(average) = statistics.average() else {
+                continue;
+            };
+
+            info!(
+                "{} refreshes: {}, busy min {} ms, avg {} ms, max {} ms",
+                mode,
+                statistics.count,
+                statistics.minimum.as_millis(),
+                average.as_millis(),
+                statistics.maximum.as_millis()
+            );
+        }
+
+        if self.retries > 0 {
+            info!("SPI transactions sent again: {}", self.retries);
+        }
+    }
+}
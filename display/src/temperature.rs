@@ -0,0 +1,49 @@
+//! E-ink behaves very differently between cold and warm temperatures, so the controller picks
+//! waveforms based on the temperature from its internal sensor.
+
+use embassy_time::Duration;
+
+use crate::driver::RefreshMode;
+
+/// The temperature doesn't change quickly, so measurements are reused for a while
+pub(crate) const MAXIMUM_AGE: Duration = Duration::from_secs(10 * 60);
+/// Below this the waveforms for fast and half refreshes leave washed out images behind as the
+/// particles move slower. The full refresh waveform is picked by the controller for the measured
+/// temperature instead.
+const COLD_THRESHOLD_CELSIUS: i16 = 10;
+/// Range of the sensor according to the datasheet. Values outside likely come from a floating
+/// data line.
+const PLAUSIBLE_CELSIUS: core::ops::RangeInclusive<i16> = -40..=85;
+
+/// Temperature in sixteenths of a degree Celsius
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, defmt::Format)]
+pub struct Temperature(i16);
+
+impl Temperature {
+    /// The register holds a 12-bit two's complement value in the upper bits of the two bytes
+    pub(crate) fn from_register(bytes: [u8; 2]) -> Self {
+        Self(i16::from_be_bytes(bytes) >> 4)
+    }
+
+    pub fn celsius(self) -> i16 {
+        self.0 / 16
+    }
+
+    pub(crate) fn is_plausible(self) -> bool {
+        PLAUSIBLE_CELSIUS.contains(&self.celsius())
+    }
+
+    /// Whether only the full refresh waveform leaves a clean image
+    pub fn is_cold(self) -> bool {
+        self.celsius() < COLD_THRESHOLD_CELSIUS
+    }
+
+    /// Picks a refresh mode that works at this temperature
+    pub(crate) fn adjust(self, mode: RefreshMode) -> RefreshMode {
+        if self.is_cold() {
+            return RefreshMode::Full;
+        }
+
+        mode
+    }
+}
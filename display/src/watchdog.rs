@@ -0,0 +1,47 @@
+//! A controller that stopped responding, e.g. after a brown-out of the panel supply, keeps failing
+//! every refresh with busy timeouts or SPI errors. Single failures are reported to the caller, but
+//! when they pile up the controller gets reset and initialized again. A refresh that stays busy
+//! doesn't wait for more failures, as the controller doesn't get over that by itself.
+
+use embassy_time::{Duration, Instant};
+
+/// Failures further apart than this are unrelated
+const WINDOW: Duration = Duration::from_secs(60);
+/// Failures within the window that trigger a recovery
+const LIMIT: u8 = 3;
+
+pub(crate) struct StaleBusyWatchdog {
+    /// Start of the current window. None without a failure in it.
+    window_start: Option<Instant>,
+    failures: u8,
+}
+
+impl StaleBusyWatchdog {
+    pub(crate) const fn new() -> Self {
+        Self {
+            window_start: None,
+            failures: 0,
+        }
+    }
+
+    /// Counts the failure and returns whether the display should be recovered
+    pub(crate) fn record_failure(&mut self) -> bool {
+        let now = Instant::now();
+        let is_in_window = self
+            .window_start
+            .is_some_and(|start| now.duration_since(start) < WINDOW);
+
+        if !is_in_window {
+            self.window_start = Some(now);
+            self.failures = 0;
+        }
+
+        self.failures = self.failures.saturating_add(1);
+        self.failures >= LIMIT
+    }
+
+    pub(crate) fn record_recovery(&mut self) {
+        self.window_start = None;
+        self.failures = 0;
+    }
+}
@@ -0,0 +1,115 @@
+//! Long operations like indexing the SD card or parsing a book report how far they are, so the UI
+//! doesn't appear frozen. The progress is drawn into a strip at the bottom of the screen before the
+//! next refresh, which doesn't hold up the operation.
+
+use core::fmt::Write;
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::watch::{Receiver, Watch};
+use embedded_graphics::Drawable;
+use embedded_graphics::mono_font::MonoTextStyle;
+use embedded_graphics::mono_font::ascii::FONT_8X13;
+use embedded_graphics::pixelcolor::BinaryColor;
+use embedded_graphics::prelude::{DrawTarget, OriginDimensions, Point, Primitive, Size};
+use embedded_graphics::primitives::{PrimitiveStyle, Rectangle};
+use embedded_graphics::text::{Baseline, Text};
+
+use crate::eink_display::{DrawError, Frame, Region};
+
+/// A multiple of 8 as the strip is a column band of the panel and [`REGION`] would cover more
+/// otherwise
+const HEIGHT: u32 = 24;
+const PADDING: i32 = 6;
+/// Space on the left for the label
+const LABEL_WIDTH: u32 = 200;
+const BAR_HEIGHT: u32 = 10;
+
+const AREA: Rectangle = Rectangle::new(
+    // Can't truncate as the frame is less than 1000 pixels tall
+    Point::new(0, (Frame::SIZE.height - HEIGHT) as i32),
+    Size::new(Frame::SIZE.width, HEIGHT),
+);
+/// The strip in panel coordinates
+pub(crate) const REGION: Region = Region::covering(AREA).unwrap();
+
+/// Only the UI waits for changes
+const RECEIVERS: usize = 1;
+
+/// None while no long operation is running
+static ACTIVITY: Watch<CriticalSectionRawMutex, Option<Activity>, RECEIVERS> =
+    Watch::new_with(None);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub(crate) struct Activity {
+    /// What is being done, e.g. "Indexing library"
+    pub(crate) label: &'static str,
+    pub(crate) done: u32,
+    /// Zero if the amount of work is not known
+    pub(crate) total: u32,
+}
+
+/// Updates the progress of the running operation
+pub(crate) fn report(label: &'static str, done: u32, total: u32) {
+    ACTIVITY
+        .sender()
+        .send(Some(Activity { label, done, total }));
+}
+
+/// Called when the operation is done, also when it failed
+pub(crate) fn finish() {
+    ACTIVITY.sender().send(None);
+}
+
+/// None if the receiver is taken
+pub(crate) fn receiver()
+-> Option<Receiver<'static, CriticalSectionRawMutex, Option<Activity>, RECEIVERS>> {
+    ACTIVITY.receiver()
+}
+
+/// Draws the activity into the strip at the bottom of the frame or clears the strip without one.
+/// Displaying [`REGION`] with `display_region` updates only the strip.
+pub(crate) fn draw(frame: &mut Frame, activity: Option<Activity>) -> Result<(), DrawError> {
+    let size = frame.size();
+    let area = AREA;
+    frame.fill_solid(&area, BinaryColor::Off)?;
+
+    let Some(activity) = activity else {
+        return Ok(());
+    };
+
+    let middle = area.top_left.y + HEIGHT as i32 / 2;
+    let style = MonoTextStyle::new(&FONT_8X13, BinaryColor::On);
+    Text::with_baseline(
+        activity.label,
+        Point::new(PADDING, middle),
+        style,
+        Baseline::Middle,
+    )
+    .draw(frame)?;
+
+    let bar_width = size.width - LABEL_WIDTH - PADDING as u32;
+    let bar = Rectangle::new(
+        Point::new(LABEL_WIDTH as i32, middle - BAR_HEIGHT as i32 / 2),
+        Size::new(bar_width, BAR_HEIGHT),
+    );
+
+    if activity.total == 0 {
+        // Without a total there is nothing to fill, so the count shows that something happens
+        let mut text = heapless::String::<12>::new();
+        // Can't fail as a u32 has at most 10 digits
+        let _ = write!(text, "{}", activity.done);
+        return Text::with_baseline(&text, bar.top_left, style, Baseline::Top)
+            .draw(frame)
+            .map(|_| ());
+    }
+
+    bar.into_styled(PrimitiveStyle::with_stroke(BinaryColor::On, 1))
+        .draw(frame)?;
+    let filled = u64::from(bar_width) * u64::from(activity.done.min(activity.total))
+        / u64::from(activity.total);
+    // Can't truncate as it is at most the bar width
+    frame.fill_solid(
+        &Rectangle::new(bar.top_left, Size::new(filled as u32, BAR_HEIGHT)),
+        BinaryColor::On,
+    )
+}
@@ -0,0 +1,298 @@
+//! Shutting down before the battery runs empty. The voltage at which a unit browns out depends on
+//! the cell and the load, so instead of one fixed threshold for all units, the voltage last
+//! measured before each brown-out reset is recorded and the shutdown threshold follows it.
+//!
+//! For showing the charge, the voltage sags under load and in the cold, which would make the
+//! percentage jump around. The [`Gauge`] only takes readings while the device is quiet, corrects
+//! them for the temperature and smooths them.
+//!
+//! The board doesn't tell whether USB power is connected, so charging is told from the voltage
+//! instead. A charging cell rises steadily and sits above what a cell reaches at rest.
+
+use alloc::format;
+use alloc::string::String;
+
+use embassy_time::{Duration, Instant};
+use esp_hal::rtc_cntl::SocResetReason;
+use portable_atomic::{AtomicBool, AtomicU16, Ordering};
+
+use crate::eink_display::{self, Temperature};
+use crate::sd_card::{ReadFileError, ReadPriority, SdCard, WriteFileError};
+
+/// The brown-out history belongs to the device and not a profile
+const FILE_PATH: &str = "/BROWNOUT.TXT";
+/// Way more than the history needs, but keeps a broken file from taking up all memory
+const MAXIMUM_FILE_SIZE: u32 = 1024;
+
+/// Used until the first brown-out was recorded
+const DEFAULT_SHUTDOWN_MILLIVOLTS: u16 = 3400;
+/// Even if a unit held out to lower voltages, below this the cell itself takes damage
+const MINIMUM_SHUTDOWN_MILLIVOLTS: u16 = 3200;
+/// Keeps a single bad measurement from forcing shutdowns with plenty of charge left
+const MAXIMUM_SHUTDOWN_MILLIVOLTS: u16 = 3600;
+/// Time to save the reading progress and draw the sleep screen before the brown-out
+const SHUTDOWN_MARGIN_MILLIVOLTS: u16 = 100;
+/// Older brown-outs are weighted as much as this many recent ones, so the threshold settles but
+/// still follows an aging cell
+const HISTORY_WEIGHT: u16 = 4;
+/// Readings outside of this are not from a connected lithium cell
+const PLAUSIBLE_MILLIVOLTS: core::ops::RangeInclusive<u16> = 2500..=4500;
+
+/// Voltage of a lithium polymer cell under light load at every 10 percent from empty to full
+const DISCHARGE_CURVE: [u16; 11] = [
+    3300, 3600, 3690, 3740, 3770, 3790, 3820, 3870, 3920, 4000, 4150,
+];
+
+/// Cell voltage drops in the cold without the charge changing. Readings are raised by this much
+/// per degree below the reference temperature.
+const COLD_MILLIVOLTS_PER_DEGREE: u16 = 2;
+const REFERENCE_CELSIUS: i16 = 25;
+/// Each reading moves the smoothed voltage by this fraction of the difference
+const SMOOTHING: i32 = 8;
+
+/// A resting cell stays below this, only the charger holds it higher
+const CHARGER_MILLIVOLTS: u16 = 4180;
+/// Rising by this much within [`TREND_WINDOW`] counts as charging and falling by it as not
+const TREND_MILLIVOLTS: u16 = 5;
+/// Long enough for the smoothed voltage to move more than the noise of the readings
+const TREND_WINDOW: Duration = Duration::from_secs(120);
+
+/// Set while the radio transmits, as the current draw of a Wi-Fi burst makes the voltage sag
+static IS_RADIO_ACTIVE: AtomicBool = AtomicBool::new(false);
+
+/// Last measured voltage. RTC fast memory keeps its content through a brown-out reset, so after one
+/// this is what the battery was at shortly before. It has random content after power loss.
+#[esp_hal::ram(unstable(rtc_fast, persistent))]
+static LAST_MILLIVOLTS: AtomicU16 = AtomicU16::new(0);
+
+/// Set while the gauge sees the battery charging, e.g. for the power button to know whether to
+/// keep waking up to show the charge
+static IS_CHARGING: AtomicBool = AtomicBool::new(false);
+
+/// Remembers the measurement in case the next one doesn't happen because of a brown-out
+pub(crate) fn record(millivolts: u16) {
+    LAST_MILLIVOLTS.store(millivolts, Ordering::Relaxed);
+}
+
+/// Most recent voltage, e.g. for diagnostics
+pub(crate) fn last_millivolts() -> u16 {
+    LAST_MILLIVOLTS.load(Ordering::Relaxed)
+}
+
+/// Whether the reading is from a battery at all. Without one, like on USB power, there is nothing
+/// to shut down for.
+pub(crate) fn is_plausible(millivolts: u16) -> bool {
+    PLAUSIBLE_MILLIVOLTS.contains(&millivolts)
+}
+
+/// Remaining charge estimated from the voltage. The curve is flat in the middle, so this is rough.
+pub(crate) fn percentage(millivolts: u16) -> u8 {
+    let Some(upper) = DISCHARGE_CURVE
+        .iter()
+        .position(|&voltage| millivolts < voltage)
+    else {
+        return 100;
+    };
+
+    let Some(lower) = upper.checked_sub(1) else {
+        return 0;
+    };
+
+    let start = DISCHARGE_CURVE[lower];
+    let step = DISCHARGE_CURVE[upper] - start;
+    // Can't truncate as the point is at most 100 and the fraction less than 10
+    (lower * 10) as u8 + ((millivolts - start) * 10 / step) as u8
+}
+
+/// Whether the charger holds the voltage above what a resting cell reaches
+pub(crate) fn is_charger_voltage(millivolts: u16) -> bool {
+    millivolts >= CHARGER_MILLIVOLTS
+}
+
+/// Whether the voltage went from the earlier reading up by enough to be charging
+pub(crate) fn is_rising(earlier: u16, millivolts: u16) -> bool {
+    millivolts >= earlier.saturating_add(TREND_MILLIVOLTS)
+}
+
+/// Whether the battery was charging at the last reading of the gauge
+pub(crate) fn is_charging() -> bool {
+    IS_CHARGING.load(Ordering::Relaxed)
+}
+
+/// Called by the radio around transmissions to keep battery readings out of them
+pub(crate) fn set_radio_active(is_active: bool) {
+    IS_RADIO_ACTIVE.store(is_active, Ordering::Relaxed);
+}
+
+/// Whether nothing draws a lot of current right now, so a reading is close to the resting voltage
+pub(crate) fn is_quiet() -> bool {
+    !eink_display::is_refreshing() && !IS_RADIO_ACTIVE.load(Ordering::Relaxed)
+}
+
+/// Battery voltage for showing the charge
+#[derive(Debug, Clone, Default, defmt::Format)]
+pub(crate) struct Gauge {
+    /// None until the first quiet reading
+    millivolts: Option<u16>,
+    /// When the current trend window started and the voltage then
+    trend_start: Option<(Instant, u16)>,
+    is_charging: bool,
+}
+
+impl Gauge {
+    /// Adds a reading. It should be taken while the device [`is_quiet`].
+    pub(crate) fn record(&mut self, millivolts: u16, temperature: Option<Temperature>) {
+        let coldness = temperature.map_or(0, |temperature| {
+            // Can't truncate as the difference is positive
+            REFERENCE_CELSIUS
+                .saturating_sub(temperature.celsius())
+                .max(0) as u16
+        });
+        let millivolts = millivolts.saturating_add(coldness * COLD_MILLIVOLTS_PER_DEGREE);
+
+        self.millivolts = Some(match self.millivolts {
+            Some(smoothed) => {
+                let difference = i32::from(millivolts) - i32::from(smoothed);
+                // Can't truncate as the result is between the two voltages
+                (i32::from(smoothed) + difference / SMOOTHING) as u16
+            }
+            None => millivolts,
+        });
+        self.update_trend();
+    }
+
+    /// Compares the smoothed voltage with the start of the window once the window is over
+    fn update_trend(&mut self) {
+        let Some(millivolts) = self.millivolts else {
+            return;
+        };
+
+        let now = Instant::now();
+        let Some((start, earlier)) = self.trend_start else {
+            self.trend_start = Some((now, millivolts));
+            return;
+        };
+
+        if now.duration_since(start) < TREND_WINDOW {
+            return;
+        }
+
+        // A flat voltage keeps the state, as charging slows down in the flat middle of the curve
+        if is_rising(millivolts, earlier) {
+            // Falling, e.g. after unplugging
+            self.is_charging = false;
+        } else if is_rising(earlier, millivolts) || is_charger_voltage(millivolts) {
+            self.is_charging = true;
+        }
+        self.trend_start = Some((now, millivolts));
+        IS_CHARGING.store(self.is_charging, Ordering::Relaxed);
+    }
+
+    /// Whether the voltage rises or is held up by the charger
+    pub(crate) fn is_charging(&self) -> bool {
+        self.is_charging
+    }
+
+    /// Compensated and smoothed voltage. None until the first reading.
+    pub(crate) fn millivolts(&self) -> Option<u16> {
+        self.millivolts
+    }
+
+    pub(crate) fn percentage(&self) -> Option<u8> {
+        self.millivolts.map(percentage)
+    }
+}
+
+/// The voltage recorded before the reset if it was caused by a brown-out
+pub(crate) fn brown_out_voltage(reset_reason: Option<SocResetReason>) -> Option<u16> {
+    if reset_reason != Some(SocResetReason::SysBrownOut) {
+        return None;
+    }
+
+    let millivolts = LAST_MILLIVOLTS.load(Ordering::Relaxed);
+    is_plausible(millivolts).then_some(millivolts)
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, defmt::Format)]
+pub(crate) struct BrownOutHistory {
+    /// Smoothed voltage of the recorded brown-outs. None until the first.
+    voltage: Option<u16>,
+    /// Number of recorded brown-outs
+    count: u16,
+}
+
+impl BrownOutHistory {
+    /// A device without a history file didn't brown out yet
+    pub(crate) async fn load(sd_card: &mut SdCard) -> Result<Self, ReadFileError> {
+        let content = match sd_card
+            .read_file(FILE_PATH, MAXIMUM_FILE_SIZE, ReadPriority::Interactive)
+            .await
+        {
+            Ok(content) => content,
+            Err(ReadFileError::Open(error)) if error.is_not_found() => return Ok(Self::default()),
+            Err(error) => return Err(error),
+        };
+
+        Ok(Self::parse(&String::from_utf8_lossy(&content)))
+    }
+
+    pub(crate) async fn save(&self, sd_card: &mut SdCard) -> Result<(), WriteFileError> {
+        sd_card
+            .write_file(FILE_PATH, self.serialize().as_bytes())
+            .await
+    }
+
+    /// Adds a brown-out at the voltage to the history
+    pub(crate) fn record(&mut self, millivolts: u16) {
+        self.count = self.count.saturating_add(1);
+        let weight = i32::from(self.count.min(HISTORY_WEIGHT));
+        self.voltage = Some(match self.voltage {
+            Some(voltage) => {
+                let difference = i32::from(millivolts) - i32::from(voltage);
+                // Can't truncate as the result is between the two voltages
+                (i32::from(voltage) + difference / weight) as u16
+            }
+            None => millivolts,
+        });
+    }
+
+    /// The battery voltage below which the device shuts down on its own
+    pub(crate) fn shutdown_threshold(&self) -> u16 {
+        self.voltage
+            .map_or(DEFAULT_SHUTDOWN_MILLIVOLTS, |voltage| {
+                voltage.saturating_add(SHUTDOWN_MARGIN_MILLIVOLTS)
+            })
+            .clamp(MINIMUM_SHUTDOWN_MILLIVOLTS, MAXIMUM_SHUTDOWN_MILLIVOLTS)
+    }
+
+    /// Unknown keys and invalid values are skipped to be able to read files from other versions
+    fn parse(content: &str) -> Self {
+        let mut history = Self::default();
+        for line in content.lines() {
+            let Some((key, value)) = line.split_once('=') else {
+                continue;
+            };
+
+            let Ok(value) = value.trim().parse() else {
+                continue;
+            };
+
+            match key.trim() {
+                "voltage" if is_plausible(value) => history.voltage = Some(value),
+                "count" => history.count = value,
+                _ => {}
+            }
+        }
+
+        history
+    }
+
+    fn serialize(&self) -> String {
+        let mut content = format!("count={}\n", self.count);
+        if let Some(voltage) = self.voltage {
+            content.push_str(&format!("voltage={voltage}\n"));
+        }
+
+        content
+    }
+}
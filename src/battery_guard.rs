@@ -0,0 +1,47 @@
+//! Watches the battery while the reader is off, so a reader left on the shelf for months finds a
+//! message to charge it instead of a sleep screen with a cell that is run down. The ESP32-C3 has no
+//! low-power coprocessor that could sample the battery during deep sleep, so the reader wakes up
+//! now and then to measure it and goes straight back to sleep. Each check takes a few seconds,
+//! which is little against hours of deep sleep.
+//!
+//! Below the critical voltage it shows the warning and stops waking up, leaving only the sleep
+//! current until the power button is pressed.
+
+use embedded_graphics::Drawable;
+use embedded_graphics::mono_font::MonoTextStyle;
+use embedded_graphics::mono_font::ascii::FONT_10X20;
+use embedded_graphics::pixelcolor::BinaryColor;
+use embedded_graphics::prelude::Point;
+use embedded_graphics::primitives::Rectangle;
+use embedded_graphics::text::{Alignment, Text};
+
+use crate::eink_display::{DrawError, Frame};
+
+/// Time in deep sleep between checks of the battery while not charging
+pub(crate) const CHECK_INTERVAL: core::time::Duration =
+    core::time::Duration::from_secs(6 * 60 * 60);
+/// Resting voltage at which a few percent are left. The cell only sees the sleep current, so this
+/// is above the shutdown threshold under load.
+const CRITICAL_MILLIVOLTS: u16 = 3450;
+
+/// Whether the battery needs to be charged before it runs down
+pub(crate) fn is_critical(millivolts: u16) -> bool {
+    millivolts < CRITICAL_MILLIVOLTS
+}
+
+/// Draws the request to charge on a white frame
+pub(crate) fn draw_warning(frame: &mut Frame) -> Result<(), DrawError> {
+    // White
+    frame.fill(0xFF);
+
+    let center = Rectangle::new(Point::zero(), Frame::SIZE).center();
+    let style = MonoTextStyle::new(&FONT_10X20, BinaryColor::On);
+    Text::with_alignment(
+        "The battery is almost empty.\nPlease charge the reader.",
+        center,
+        style,
+        Alignment::Center,
+    )
+    .draw(frame)?;
+    Ok(())
+}
@@ -0,0 +1,198 @@
+//! Battery voltage over the last days, to see how long a charge lasts and as the data to calibrate
+//! the percentage estimate with. While the reader is on, the smoothed voltage is sampled now and
+//! then and appended to a log on the SD card, one `seconds,millivolts,charging` line per sample.
+//! Like the event log it alternates between two files, so it never grows beyond twice the length.
+//!
+//! The seconds are from the RTC, which keeps counting through deep sleep but starts over after the
+//! battery was disconnected. The graph only shows the samples up to the newest one.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::Write;
+
+use defmt::warn;
+use embassy_time::Duration;
+use embedded_graphics::Drawable;
+use embedded_graphics::mono_font::MonoTextStyle;
+use embedded_graphics::mono_font::ascii::{FONT_8X13, FONT_10X20};
+use embedded_graphics::pixelcolor::BinaryColor;
+use embedded_graphics::prelude::{Point, Primitive, Size};
+use embedded_graphics::primitives::{Line, PrimitiveStyle, Rectangle};
+use embedded_graphics::text::{Alignment, Text};
+
+use crate::eink_display::{DrawError, Frame};
+use crate::event_log;
+use crate::sd_card::{ReadFileError, ReadPriority, SdCard};
+use crate::{SharedRtc, SharedSdCard};
+
+const FILES: [&str; 2] = ["/LOGS/BATTERY1.TXT", "/LOGS/BATTERY2.TXT"];
+/// Samples are only taken while the reader is on, so a file covers days of reading. Small enough
+/// for the smallest heap to load both.
+const MAXIMUM_LENGTH: u32 = 4 * 1024;
+/// Time between samples while the reader is on
+pub(crate) const SAMPLE_INTERVAL: Duration = Duration::from_secs(10 * 60);
+/// How far back the graph goes from the newest sample
+const GRAPH_SECONDS: u32 = 3 * 24 * 60 * 60;
+/// Voltage range of the graph, from empty to charging
+const GRAPH_MILLIVOLTS: core::ops::RangeInclusive<u16> = 3300..=4200;
+const GRAPH_AREA: Rectangle = Rectangle::new(Point::new(60, 80), Size::new(400, 600));
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub(crate) struct Sample {
+    /// RTC time in seconds
+    pub(crate) seconds: u32,
+    pub(crate) millivolts: u16,
+    pub(crate) is_charging: bool,
+}
+
+impl Sample {
+    fn parse(line: &str) -> Option<Self> {
+        let mut fields = line.trim().split(',');
+        let seconds = fields.next()?.parse().ok()?;
+        let millivolts = fields.next()?.parse().ok()?;
+        let is_charging = fields.next()? == "1";
+        Some(Self {
+            seconds,
+            millivolts,
+            is_charging,
+        })
+    }
+}
+
+/// Appends a sample taken now to the history. Without an SD card the sample is lost.
+pub(crate) async fn record(
+    sd_card: &SharedSdCard,
+    rtc: &SharedRtc,
+    millivolts: u16,
+    is_charging: bool,
+) {
+    // Can't truncate as the RTC doesn't run for more than a century
+    let seconds = (rtc.lock(|rtc| rtc.borrow().current_time_us()) / 1_000_000) as u32;
+    let mut line = String::new();
+    // Can't fail as writing to a string doesn't fail
+    let _ = writeln!(line, "{seconds},{millivolts},{}", u8::from(is_charging));
+
+    let mut sd_card = sd_card.lock().await;
+    let Some(sd_card) = sd_card.as_mut() else {
+        return;
+    };
+
+    if let Err(error) = event_log::append(sd_card, FILES, MAXIMUM_LENGTH, &line).await {
+        warn!(
+            "Failed to record battery history: {:?}",
+            defmt::Debug2Format(&error)
+        );
+    }
+}
+
+/// The samples of both files from oldest to newest. Missing files have no samples.
+pub(crate) async fn load(sd_card: &mut SdCard) -> Result<Vec<Sample>, ReadFileError> {
+    let mut samples = Vec::new();
+    for path in FILES {
+        let content = match sd_card
+            .read_file(path, MAXIMUM_LENGTH * 2, ReadPriority::Interactive)
+            .await
+        {
+            Ok(content) => content,
+            Err(ReadFileError::Open(error)) if error.is_not_found() => continue,
+            Err(error) => return Err(error),
+        };
+
+        samples.extend(
+            String::from_utf8_lossy(&content)
+                .lines()
+                .filter_map(Sample::parse),
+        );
+    }
+
+    // The files take turns, so either can hold the older samples
+    samples.sort_by_key(|sample| sample.seconds);
+    Ok(samples)
+}
+
+/// Plots the voltage of the last days on a white frame. Charging is drawn thicker.
+pub(crate) fn draw(samples: &[Sample], frame: &mut Frame) -> Result<(), DrawError> {
+    // White
+    frame.fill(0xFF);
+
+    let title_style = MonoTextStyle::new(&FONT_10X20, BinaryColor::On);
+    let label_style = MonoTextStyle::new(&FONT_8X13, BinaryColor::On);
+    Text::new("Battery history", Point::new(10, 30), title_style).draw(frame)?;
+
+    GRAPH_AREA
+        .into_styled(PrimitiveStyle::with_stroke(BinaryColor::On, 1))
+        .draw(frame)?;
+    let top_left = GRAPH_AREA.top_left;
+    let bottom_right = GRAPH_AREA.bottom_right().unwrap_or(top_left);
+    let (maximum, minimum) = (GRAPH_MILLIVOLTS.end(), GRAPH_MILLIVOLTS.start());
+    Text::with_alignment(
+        &format_volts(*maximum),
+        top_left + Point::new(-6, 5),
+        label_style,
+        Alignment::Right,
+    )
+    .draw(frame)?;
+    Text::with_alignment(
+        &format_volts(*minimum),
+        Point::new(top_left.x - 6, bottom_right.y + 5),
+        label_style,
+        Alignment::Right,
+    )
+    .draw(frame)?;
+    Text::new(
+        "3 days ago",
+        Point::new(top_left.x, bottom_right.y + 20),
+        label_style,
+    )
+    .draw(frame)?;
+    Text::with_alignment(
+        "Newest",
+        Point::new(bottom_right.x, bottom_right.y + 20),
+        label_style,
+        Alignment::Right,
+    )
+    .draw(frame)?;
+
+    let Some(newest) = samples.last() else {
+        Text::new("No samples yet", top_left + Point::new(10, 30), title_style).draw(frame)?;
+        return Ok(());
+    };
+
+    let start = newest.seconds.saturating_sub(GRAPH_SECONDS);
+    let point = |sample: &Sample| {
+        // Can't truncate as the offset is within the graph seconds
+        let x = (u64::from(sample.seconds - start) * u64::from(GRAPH_AREA.size.width - 1)
+            / u64::from(GRAPH_SECONDS)) as i32;
+        let millivolts = sample.millivolts.clamp(*minimum, *maximum);
+        let y = u32::from(maximum - millivolts) * (GRAPH_AREA.size.height - 1)
+            / u32::from(maximum - minimum);
+        // Can't truncate as the graph is less than 1000 pixels high
+        top_left + Point::new(x, y as i32)
+    };
+
+    let shown = samples.iter().filter(|sample| sample.seconds >= start);
+    let mut previous: Option<&Sample> = None;
+    for sample in shown {
+        if let Some(previous) = previous {
+            let width = if sample.is_charging { 3 } else { 1 };
+            Line::new(point(previous), point(sample))
+                .into_styled(PrimitiveStyle::with_stroke(BinaryColor::On, width))
+                .draw(frame)?;
+        }
+        previous = Some(sample);
+    }
+
+    Ok(())
+}
+
+fn format_volts(millivolts: u16) -> heapless::String<8> {
+    let mut text = heapless::String::new();
+    // Can't fail as "4.20 V" fits
+    let _ = write!(
+        text,
+        "{}.{:02} V",
+        millivolts / 1000,
+        millivolts % 1000 / 10
+    );
+    text
+}
@@ -0,0 +1,244 @@
+//! Measures how fast the SD card reads, how long the display controller takes to initialize and how
+//! long the refreshes of the panel take, to base decisions about caching and how much text to lay
+//! out at once on numbers. Started with `benchmark` on the console or from the hidden entry of the
+//! settings, which shows up after pressing left five times on the first entry.
+//!
+//! The reads go to a test file that is written for them and removed after. The refreshes show the
+//! test pattern, so the screen needs to be drawn again after.
+
+use alloc::format;
+use alloc::vec;
+use core::fmt::{self, Display};
+
+use defmt::{info, warn};
+use embassy_time::Instant;
+use embedded_graphics::Drawable;
+use embedded_graphics::mono_font::MonoTextStyle;
+use embedded_graphics::mono_font::ascii::FONT_10X20;
+use embedded_graphics::pixelcolor::BinaryColor;
+use embedded_graphics::prelude::Point;
+use embedded_graphics::text::Text;
+use embedded_sdmmc::Mode;
+
+use crate::display_task::ShowMode;
+use crate::eink_display::{DrawError, Frame, RefreshMode};
+use crate::sd_card::{OpenError, ReadPriority, SdCard, VolumeError};
+use crate::{SharedDisplay, SharedSdCard, console, toast};
+
+const FILE_PATH: &str = "/BENCH.TMP";
+/// Large enough for the chunk size to settle during the sequential read
+const FILE_SIZE: u32 = 64 * 1024;
+/// Bytes read at once from start to end
+const SEQUENTIAL_READ_SIZE: usize = 4096;
+/// Bytes read at once at random offsets, one block of the card
+const RANDOM_READ_SIZE: usize = 512;
+const RANDOM_READS: u32 = 32;
+
+#[derive(Debug, thiserror::Error)]
+enum BenchmarkError {
+    #[error("Failed to open test file")]
+    Open(#[from] OpenError),
+    #[error("Failed to write test file")]
+    Write(VolumeError),
+    #[error("Failed to read test file")]
+    Read(VolumeError),
+}
+
+/// The measurements that succeeded
+#[derive(Debug, Clone, Copy, Default, defmt::Format)]
+pub(crate) struct Report {
+    /// Kilobytes per second reading the test file from start to end
+    pub(crate) sequential_read: Option<u64>,
+    /// Average microseconds to read a block at a random offset
+    pub(crate) random_read: Option<u64>,
+    /// Milliseconds to reset and initialize the display controller, mostly the reset pulse and
+    /// clearing its RAM
+    pub(crate) initialization: Option<u64>,
+    /// Milliseconds of each refresh mode until the panel is done
+    pub(crate) full_refresh: Option<u64>,
+    pub(crate) fast_refresh: Option<u64>,
+    /// Milliseconds to update the toast strip
+    pub(crate) partial_refresh: Option<u64>,
+}
+
+impl Display for Report {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let lines = [
+            ("Sequential read", self.sequential_read, "KB/s"),
+            ("Random read", self.random_read, "us"),
+            ("Initialization", self.initialization, "ms"),
+            ("Full refresh", self.full_refresh, "ms"),
+            ("Fast refresh", self.fast_refresh, "ms"),
+            ("Partial refresh", self.partial_refresh, "ms"),
+        ];
+        for (index, (name, value, unit)) in lines.into_iter().enumerate() {
+            if index > 0 {
+                writeln!(formatter)?;
+            }
+            match value {
+                Some(value) => write!(formatter, "{name}: {value} {unit}")?,
+                None => write!(formatter, "{name}: failed")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Runs the measurements one after another. Leaves the frame with the test pattern.
+pub(crate) async fn run(
+    sd_card: &SharedSdCard,
+    display: &SharedDisplay,
+    frame: &mut Frame,
+) -> Report {
+    let mut report = Report::default();
+    match sd_card.lock().await.as_mut() {
+        Some(sd_card) => match measure_reads(sd_card).await {
+            Ok((sequential, random)) => {
+                report.sequential_read = Some(sequential);
+                report.random_read = Some(random);
+            }
+            Err(error) => warn!(
+                "Failed to benchmark SD card: {:?}",
+                defmt::Debug2Format(&error)
+            ),
+        },
+        None => warn!("No SD card to benchmark"),
+    }
+
+    if let Err(error) = console::draw_test_pattern(frame) {
+        warn!("Failed to draw test pattern: {:?}", error);
+        return report;
+    }
+
+    // Before the full refresh, which the initialization needs after it
+    report.initialization = time(async { display.lock().await.reinitialize().await }).await;
+    report.full_refresh = time(display.show(ShowMode::Exact(RefreshMode::Full), frame)).await;
+    report.fast_refresh = time(display.show(ShowMode::Exact(RefreshMode::Fast), frame)).await;
+    match toast::draw("Benchmark", frame) {
+        Ok(()) => {
+            report.partial_refresh = time(display.show_region(frame, toast::REGION)).await;
+        }
+        Err(error) => warn!("Failed to draw toast: {:?}", error),
+    }
+
+    info!("Benchmark: {}", report);
+    report
+}
+
+/// Draws the report on a white frame
+pub(crate) fn draw(report: &Report, frame: &mut Frame) -> Result<(), DrawError> {
+    // White
+    frame.fill(0xFF);
+
+    let style = MonoTextStyle::new(&FONT_10X20, BinaryColor::On);
+    Text::new("Benchmark", Point::new(10, 30), style).draw(frame)?;
+    let text = format!("{report}\n\nPress any button");
+    Text::new(&text, Point::new(10, 75), style).draw(frame)?;
+    Ok(())
+}
+
+/// Milliseconds until the refresh or initialization is done or None if it failed
+async fn time<T, E: fmt::Debug>(refresh: impl Future<Output = Result<T, E>>) -> Option<u64> {
+    let start = Instant::now();
+    match refresh.await {
+        Ok(_) => Some(start.elapsed().as_millis()),
+        Err(error) => {
+            warn!("Failed to measure: {:?}", defmt::Debug2Format(&error));
+            None
+        }
+    }
+}
+
+/// Kilobytes per second reading from start to end and the average microseconds of a random read
+async fn measure_reads(sd_card: &mut SdCard) -> Result<(u64, u64), BenchmarkError> {
+    write_test_file(sd_card).await?;
+    let result = read_test_file(sd_card).await;
+
+    if let Err(error) = sd_card.remove(FILE_PATH).await {
+        warn!(
+            "Failed to remove {}: {:?}",
+            FILE_PATH,
+            defmt::Debug2Format(&error)
+        );
+    }
+
+    result
+}
+
+async fn write_test_file(sd_card: &mut SdCard) -> Result<(), BenchmarkError> {
+    let file = sd_card
+        .open(FILE_PATH, Mode::ReadWriteCreateOrTruncate)
+        .await?;
+
+    // Can't truncate as the index is less than the read size
+    let buffer: [u8; SEQUENTIAL_READ_SIZE] = core::array::from_fn(|index| index as u8);
+    let mut result = Ok(());
+    // Can't truncate as the file size is a reasonable amount
+    for _ in 0..FILE_SIZE as usize / SEQUENTIAL_READ_SIZE {
+        result = sd_card.write(file, &buffer).await;
+        if result.is_err() {
+            break;
+        }
+    }
+
+    // Closing flushes the data to the card
+    let closed = sd_card.close(file).await;
+    result.and(closed).map_err(BenchmarkError::Write)
+}
+
+async fn read_test_file(sd_card: &mut SdCard) -> Result<(u64, u64), BenchmarkError> {
+    let file = sd_card.open(FILE_PATH, Mode::ReadOnly).await?;
+    let result = time_reads(sd_card, file).await;
+
+    if let Err(error) = sd_card.close(file).await {
+        warn!("Failed to close {}: {:?}", FILE_PATH, error);
+    }
+
+    result.map_err(BenchmarkError::Read)
+}
+
+async fn time_reads(
+    sd_card: &mut SdCard,
+    file: embedded_sdmmc::RawFile,
+) -> Result<(u64, u64), VolumeError> {
+    let mut buffer = vec![0; SEQUENTIAL_READ_SIZE];
+    let start = Instant::now();
+    let mut offset = 0;
+    while offset < FILE_SIZE {
+        let read = sd_card
+            .read_at(file, offset, &mut buffer, ReadPriority::Background)
+            .await?;
+        if read == 0 {
+            break;
+        }
+        // Can't truncate as it is at most the read size
+        offset += read as u32;
+    }
+    let elapsed = start.elapsed().as_micros().max(1);
+    let sequential = u64::from(offset) * 1_000_000 / 1024 / elapsed;
+
+    // Same offsets every time to compare runs. Xorshift as there is no random number generator
+    // that doesn't need the radio.
+    let mut state: u32 = 0x2545_F491;
+    // Can't truncate as the block size is a small constant
+    let blocks = FILE_SIZE / RANDOM_READ_SIZE as u32;
+    let start = Instant::now();
+    for _ in 0..RANDOM_READS {
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        // Can't truncate as the block size is a small constant
+        let offset = state % blocks * RANDOM_READ_SIZE as u32;
+        sd_card
+            .read_at(
+                file,
+                offset,
+                &mut buffer[..RANDOM_READ_SIZE],
+                ReadPriority::Interactive,
+            )
+            .await?;
+    }
+    let random = start.elapsed().as_micros() / u64::from(RANDOM_READS);
+
+    Ok((sequential, random))
+}
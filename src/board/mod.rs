@@ -0,0 +1,12 @@
+//! Pin assignments and peripheral bring-up for the supported hardware.
+//! The board is selected with a cargo feature so other hardware revisions or development boards
+//! can be supported without changing the application.
+
+#[cfg(feature = "xteink-x4")]
+mod xteink_x4;
+
+#[cfg(feature = "xteink-x4")]
+pub(crate) use crate::board::xteink_x4::*;
+
+#[cfg(not(feature = "xteink-x4"))]
+compile_error!("No board selected. Enable a board feature like \"xteink-x4\".");
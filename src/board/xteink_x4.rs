@@ -0,0 +1,141 @@
+use esp_hal::gpio::{Input, InputConfig, Level, Output, OutputConfig, Pull};
+#[cfg(feature = "radio")]
+use esp_hal::peripherals::{BT, WIFI};
+use esp_hal::peripherals::{FLASH, GPIO3, LPWR, Peripherals, SW_INTERRUPT, TIMG0, USB_DEVICE};
+
+use crate::input::Analog;
+use crate::spi;
+
+/// Needs to be an RTC pin to wake the device from deep sleep
+pub(crate) type PowerButton = GPIO3<'static>;
+
+/// Display reset (GPIO5) and data/command (GPIO4) are RTC pads, which are held one by one. A
+/// floating reset line would wake the controller from its deep sleep.
+const HELD_RTC_PADS: u32 = 1 << 5 | 1 << 4;
+
+pub(crate) struct Display {
+    pub(crate) spi: spi::Device<'static>,
+    pub(crate) reset: Output<'static>,
+    pub(crate) data_command: Output<'static>,
+    pub(crate) busy: Input<'static>,
+}
+
+#[cfg(feature = "radio")]
+pub(crate) struct Radio {
+    pub(crate) wifi: WIFI<'static>,
+    pub(crate) bluetooth: BT<'static>,
+}
+
+pub(crate) struct Board {
+    pub(crate) display: Display,
+    pub(crate) sd_card_spi: spi::CardDevice,
+    pub(crate) analog: Analog<'static>,
+    pub(crate) power_button: PowerButton,
+    pub(crate) flash: FLASH<'static>,
+    pub(crate) low_power: LPWR<'static>,
+    pub(crate) timer_group_0: TIMG0<'static>,
+    pub(crate) software_interrupt: SW_INTERRUPT<'static>,
+    /// USB serial port of the chip for the debug console
+    pub(crate) usb_serial: USB_DEVICE<'static>,
+    #[cfg(feature = "radio")]
+    pub(crate) radio: Radio,
+}
+
+/// Keeps the display lines at their levels through deep sleep, so the panel doesn't see noise and
+/// the pins don't draw current while they float. The display chip select and the SPI lines are
+/// digital pads, which are all held at once when deep sleep starts.
+pub(crate) fn hold_display_pins() {
+    let rtc = LPWR::regs();
+    // SAFETY: Only adds the hold bits of the display pads
+    rtc.pad_hold()
+        .modify(|reader, writer| unsafe { writer.bits(reader.bits() | HELD_RTC_PADS) });
+    rtc.dig_iso().modify(|_, writer| {
+        writer
+            .dg_pad_force_unhold()
+            .clear_bit()
+            .dg_pad_autohold_en()
+            .set_bit()
+    });
+}
+
+/// Lets go of the pins held through deep sleep, as the display can't be reset while they are held.
+/// Does nothing after power on.
+fn release_pins() {
+    let rtc = LPWR::regs();
+    // SAFETY: Only clears the hold bits of the display pads
+    rtc.pad_hold()
+        .modify(|reader, writer| unsafe { writer.bits(reader.bits() & !HELD_RTC_PADS) });
+    rtc.dig_iso().modify(|_, writer| {
+        writer
+            .dg_pad_autohold_en()
+            .clear_bit()
+            .clr_dg_pad_autohold()
+            .set_bit()
+    });
+}
+
+impl Board {
+    pub(crate) fn set_up(peripherals: Peripherals) -> Result<Self, spi::SetUpError> {
+        // Before the pins are used after waking from deep sleep
+        release_pins();
+
+        // Custom pins for XteinkX4, not hardware SPI defaults
+        // SPI Clock (SCLK = serial clock)
+        let serial_clock = peripherals.GPIO8;
+        // SPI Master Out Slave In (MOSI)
+        let master_out_slave_in = peripherals.GPIO10;
+        let master_in_slave_out = peripherals.GPIO7;
+        // Display Chip Select (CS)
+        let display_chip_select = peripherals.GPIO21;
+        let sd_card_chip_select = peripherals.GPIO12;
+        let direct_memory_access_channel = peripherals.DMA_CH0;
+
+        let (display_spi, sd_card_spi) = spi::set_up_devices(
+            peripherals.SPI2,
+            serial_clock,
+            master_out_slave_in,
+            master_in_slave_out,
+            direct_memory_access_channel,
+            display_chip_select,
+            sd_card_chip_select,
+        )?;
+
+        let display = Display {
+            spi: display_spi,
+            // Reset (RST)
+            reset: Output::new(peripherals.GPIO5, Level::Low, OutputConfig::default()),
+            // Data/Command (DC)
+            data_command: Output::new(peripherals.GPIO4, Level::High, OutputConfig::default()),
+            // Busy
+            busy: Input::new(
+                peripherals.GPIO6,
+                InputConfig::default().with_pull(Pull::Down),
+            ),
+        };
+
+        // Battery level and the two button resistor ladders
+        let analog = Analog::new(
+            peripherals.ADC1,
+            peripherals.GPIO0,
+            peripherals.GPIO1,
+            peripherals.GPIO2,
+        );
+
+        Ok(Self {
+            display,
+            sd_card_spi,
+            analog,
+            power_button: peripherals.GPIO3,
+            flash: peripherals.FLASH,
+            low_power: peripherals.LPWR,
+            timer_group_0: peripherals.TIMG0,
+            software_interrupt: peripherals.SW_INTERRUPT,
+            usb_serial: peripherals.USB_DEVICE,
+            #[cfg(feature = "radio")]
+            radio: Radio {
+                wifi: peripherals.WIFI,
+                bluetooth: peripherals.BT,
+            },
+        })
+    }
+}
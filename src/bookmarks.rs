@@ -0,0 +1,272 @@
+//! Bookmarks per book and profile. They are kept in a file per book in the profile directory on the
+//! SD card, so they survive firmware updates and moving the card between devices. Each line is a
+//! bookmark like `bookmark=1234;It was a dark and stormy night` with the offset into the text
+//! content of the book and the start of the page to recognize it in the list.
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::ops::Range;
+
+use defmt::error;
+use embedded_graphics::Drawable;
+use embedded_graphics::mono_font::MonoTextStyle;
+use embedded_graphics::mono_font::ascii::FONT_10X20;
+use embedded_graphics::pixelcolor::BinaryColor;
+use embedded_graphics::prelude::{OriginDimensions, Point, Size};
+use embedded_graphics::primitives::Rectangle;
+use embedded_graphics::text::Text;
+
+use crate::SharedDisplay;
+use crate::eink_display::Frame;
+use crate::input::Analog;
+use crate::profile::Profile;
+use crate::sd_card::{ReadFileError, ReadPriority, SdCard, VolumeError, WriteFileError};
+use crate::widget::{ListView, Response};
+
+/// Directory within the profile directory
+const DIRECTORY: &str = "BOOKMARK";
+/// Keeps the file small enough to read at once
+const MAXIMUM_BOOKMARKS: usize = 64;
+const MAXIMUM_FILE_SIZE: u32 = 8 * 1024;
+/// Characters of the page kept as label, about a row on the screen
+const LABEL_LENGTH: usize = 40;
+/// Where the bookmark list starts below the title
+const LIST_TOP: i32 = 45;
+
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum SaveBookmarksError {
+    #[error("Failed to create bookmark directory")]
+    CreateDirectory(VolumeError),
+    #[error("Failed to write bookmark file")]
+    Write(#[from] WriteFileError),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, defmt::Format)]
+pub(crate) struct Bookmark {
+    /// Offset into the text content of the book
+    pub(crate) position: u32,
+    /// Start of the bookmarked page
+    pub(crate) label: String,
+}
+
+/// Bookmarks of a book sorted by their position
+#[derive(Debug, Clone, Default, PartialEq, Eq, defmt::Format)]
+pub(crate) struct Bookmarks {
+    bookmarks: Vec<Bookmark>,
+}
+
+impl Bookmarks {
+    /// A book without a bookmark file has no bookmarks
+    pub(crate) async fn load(
+        sd_card: &mut SdCard,
+        profile: &Profile,
+        book_path: &str,
+    ) -> Result<Self, ReadFileError> {
+        let content = match sd_card
+            .read_file(
+                &profile.book_path(DIRECTORY, book_path),
+                MAXIMUM_FILE_SIZE,
+                ReadPriority::Interactive,
+            )
+            .await
+        {
+            Ok(content) => content,
+            Err(ReadFileError::Open(error)) if error.is_not_found() => return Ok(Self::default()),
+            Err(error) => return Err(error),
+        };
+
+        Ok(Self::parse(&String::from_utf8_lossy(&content)))
+    }
+
+    pub(crate) async fn save(
+        &self,
+        sd_card: &mut SdCard,
+        profile: &Profile,
+        book_path: &str,
+    ) -> Result<(), SaveBookmarksError> {
+        sd_card
+            .create_directories(&profile.path(DIRECTORY))
+            .await
+            .map_err(SaveBookmarksError::CreateDirectory)?;
+
+        sd_card
+            .write_file(
+                &profile.book_path(DIRECTORY, book_path),
+                self.serialize().as_bytes(),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.bookmarks.len()
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.bookmarks.is_empty()
+    }
+
+    pub(crate) fn get(&self, index: usize) -> Option<&Bookmark> {
+        self.bookmarks.get(index)
+    }
+
+    /// Whether the page spanning the positions has a bookmark, e.g. to mark it on the screen
+    pub(crate) fn contains(&self, page: &Range<u32>) -> bool {
+        self.bookmarks
+            .iter()
+            .any(|bookmark| page.contains(&bookmark.position))
+    }
+
+    /// Removes the bookmarks on the page or adds one at its start if there is none. The text of the
+    /// page is used for the label. Returns whether the page is bookmarked now.
+    pub(crate) fn toggle(&mut self, page: Range<u32>, text: &str) -> bool {
+        if self.contains(&page) {
+            self.bookmarks
+                .retain(|bookmark| !page.contains(&bookmark.position));
+            return false;
+        }
+
+        if self.bookmarks.len() >= MAXIMUM_BOOKMARKS {
+            error!(
+                "More than {} bookmarks, not adding another",
+                MAXIMUM_BOOKMARKS
+            );
+            return false;
+        }
+
+        self.insert(Bookmark {
+            position: page.start,
+            label: label(text),
+        });
+        true
+    }
+
+    fn insert(&mut self, bookmark: Bookmark) {
+        let index = self
+            .bookmarks
+            .partition_point(|other| other.position < bookmark.position);
+        self.bookmarks.insert(index, bookmark);
+    }
+
+    /// Unknown keys and invalid values are skipped to be able to read files from other versions
+    fn parse(content: &str) -> Self {
+        let mut bookmarks = Self::default();
+        for line in content.lines() {
+            let Some((key, value)) = line.split_once('=') else {
+                continue;
+            };
+
+            if key.trim() != "bookmark" || bookmarks.len() >= MAXIMUM_BOOKMARKS {
+                continue;
+            }
+
+            let (position, label) = value.split_once(';').unwrap_or((value, ""));
+            let Ok(position) = position.trim().parse() else {
+                continue;
+            };
+
+            bookmarks.insert(Bookmark {
+                position,
+                label: String::from(label),
+            });
+        }
+
+        bookmarks
+    }
+
+    fn serialize(&self) -> String {
+        let mut content = String::new();
+        for bookmark in &self.bookmarks {
+            content.push_str(&format!(
+                "bookmark={};{}\n",
+                bookmark.position, bookmark.label
+            ));
+        }
+
+        content
+    }
+
+    /// Readable list of the bookmarks with a line per bookmark to share them with other devices
+    pub(crate) fn export(&self) -> String {
+        let mut content = String::new();
+        for bookmark in &self.bookmarks {
+            content.push_str(&format!("{}\t{}\n", bookmark.position, bookmark.label));
+        }
+
+        content
+    }
+
+    /// Lists the bookmarks and lets the reader pick one with the buttons. Returns the position to
+    /// jump to or None if the reader backed out.
+    pub(crate) async fn select(
+        &self,
+        analog: &mut Analog<'_>,
+        display: &SharedDisplay,
+        frame: &mut Frame,
+    ) -> Option<u32> {
+        let mut list = ListView::new(self.len(), 0);
+        loop {
+            self.draw_list(frame, &mut list);
+            if let Err(error) = display.animate(frame).await {
+                error!(
+                    "Failed to display bookmarks: {:?}",
+                    defmt::Debug2Format(&error)
+                );
+            }
+
+            loop {
+                match list.handle(analog.wait_for_press().await) {
+                    Response::Ignored => {}
+                    Response::Changed => break,
+                    Response::Confirmed => {
+                        return self.get(list.selected()).map(|bookmark| bookmark.position);
+                    }
+                    Response::Cancelled => return None,
+                }
+            }
+        }
+    }
+
+    fn draw_list(&self, frame: &mut Frame, list: &mut ListView) {
+        // White
+        frame.fill(0xFF);
+
+        let title = if self.is_empty() {
+            "No bookmarks"
+        } else {
+            "Bookmarks"
+        };
+        let style = MonoTextStyle::new(&FONT_10X20, BinaryColor::On);
+        if let Err(error) = Text::new(title, Point::new(10, 30), style).draw(frame) {
+            error!("Failed to draw bookmarks: {:?}", error);
+        }
+
+        let size = frame.size();
+        let area = Rectangle::new(
+            Point::new(0, LIST_TOP),
+            Size::new(size.width, size.height - LIST_TOP as u32),
+        );
+        if let Err(error) = list.draw(frame, &area, |index| self.bookmarks[index].label.as_str()) {
+            error!("Failed to draw bookmarks: {:?}", error);
+        }
+    }
+}
+
+/// The first words of the page on a single line
+fn label(text: &str) -> String {
+    let mut label = String::new();
+    for word in text.split_whitespace() {
+        if !label.is_empty() {
+            label.push(' ');
+        }
+        label.push_str(word);
+
+        if label.chars().count() >= LABEL_LENGTH {
+            break;
+        }
+    }
+
+    label.chars().take(LABEL_LENGTH).collect()
+}
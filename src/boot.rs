@@ -0,0 +1,51 @@
+//! Why the device started decides what it shows first. Waking from deep sleep continues where the
+//! reader left off, while resets that were not asked for are explained to the reader.
+
+use esp_hal::rtc_cntl::SocResetReason;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub(crate) enum Boot {
+    /// Power on or a reset from flashing. Runs the whole start up.
+    Cold,
+    /// Woken from deep sleep with the power button. The session in RTC memory is still valid.
+    Wake,
+    /// The supply voltage dropped too low, most likely because the battery is empty
+    BrownOut,
+    /// A watchdog reset the device because the firmware got stuck
+    Watchdog,
+}
+
+impl Boot {
+    pub(crate) fn from_reset_reason(reset_reason: Option<SocResetReason>) -> Self {
+        match reset_reason {
+            Some(SocResetReason::CoreDeepSleep) => Self::Wake,
+            Some(SocResetReason::SysBrownOut) => Self::BrownOut,
+            Some(
+                SocResetReason::CoreMwdt0
+                | SocResetReason::CoreMwdt1
+                | SocResetReason::CoreRtcWdt
+                | SocResetReason::Cpu0Mwdt0
+                | SocResetReason::Cpu0Mwdt1
+                | SocResetReason::Cpu0RtcWdt
+                | SocResetReason::SysRtcWdt
+                | SocResetReason::SysSuperWdt,
+            ) => Self::Watchdog,
+            _ => Self::Cold,
+        }
+    }
+
+    /// Whether the session from before deep sleep is restored instead of asking again, e.g. who is
+    /// reading
+    pub(crate) fn is_session_restored(self) -> bool {
+        self == Self::Wake
+    }
+
+    /// Explanation for the reader if the device restarted unexpectedly
+    pub(crate) fn notice(self) -> Option<&'static str> {
+        match self {
+            Self::Cold | Self::Wake => None,
+            Self::BrownOut => Some("The battery ran empty.\nPlease charge the device."),
+            Self::Watchdog => Some("The device got stuck\nand was restarted."),
+        }
+    }
+}
@@ -0,0 +1,156 @@
+//! Guided measurement of the button levels, as the built-in thresholds were measured on one unit and
+//! the resistors of the button ladders differ between units. The reader presses each button in turn
+//! and the thresholds are put halfway between the levels. Opened from the settings or by holding a
+//! button on each ladder, e.g. back and up, while the reader starts.
+//!
+//! The levels of the presses are read directly, so the calibration works even if the thresholds are
+//! so far off that the buttons can't be used to navigate.
+
+use alloc::format;
+
+use defmt::{error, info, warn};
+use embassy_time::{Duration, Timer, with_timeout};
+use embedded_graphics::Drawable;
+use embedded_graphics::mono_font::MonoTextStyle;
+use embedded_graphics::mono_font::ascii::FONT_10X20;
+use embedded_graphics::pixelcolor::BinaryColor;
+use embedded_graphics::prelude::Point;
+use embedded_graphics::text::Text;
+
+use crate::SharedDisplay;
+use crate::eink_display::Frame;
+use crate::input::{Analog, Calibration};
+use crate::supervisor::{self, Task};
+
+/// Names of the buttons on the device in the order of the ladders, the first four on the first
+const BUTTONS: [&str; 6] = ["back", "confirm", "left", "right", "up", "down"];
+const FIRST_LADDER_BUTTONS: usize = 4;
+/// A level this far below the one with nothing pressed counts as a press
+const PRESS_MARGIN: u16 = 300;
+/// Time to let the level settle after a press before it is taken
+const SETTLE_TIME: Duration = Duration::from_millis(200);
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+/// Gives up if the reader doesn't press the button, so the reader doesn't get stuck at start up
+const PRESS_TIMEOUT: Duration = Duration::from_secs(30);
+const RELEASE_TIMEOUT: Duration = Duration::from_secs(5);
+/// How long the message is shown if the calibration failed
+const MESSAGE_TIME: Duration = Duration::from_secs(3);
+
+/// Asks the reader to press each button and applies the thresholds. Returns them to be saved or
+/// None if the calibration was given up and the previous thresholds are kept.
+pub(crate) async fn calibrate(
+    analog: &mut Analog<'_>,
+    display: &SharedDisplay,
+    frame: &mut Frame,
+) -> Option<Calibration> {
+    show("Release all buttons", display, frame).await;
+    // Thresholds that are far off can report a button that isn't pressed. Levels taken while a
+    // button is still held don't pass the checks of the calibration, so this carries on anyway.
+    if with_timeout(RELEASE_TIMEOUT, wait_for_release(analog))
+        .await
+        .is_err()
+    {
+        warn!("Button calibration timed out waiting for release");
+    }
+    Timer::after(SETTLE_TIME).await;
+    let released = analog.ladder_levels().await;
+    info!("Levels with no button pressed: {}", released);
+
+    let mut ladder_1 = [0; FIRST_LADDER_BUTTONS];
+    let mut ladder_2 = [0; BUTTONS.len() - FIRST_LADDER_BUTTONS];
+    for (index, name) in BUTTONS.into_iter().enumerate() {
+        show(&format!("Press and hold {name}"), display, frame).await;
+        let is_first_ladder = index < FIRST_LADDER_BUTTONS;
+        let released = if is_first_ladder {
+            released.0
+        } else {
+            released.1
+        };
+
+        let Ok(level) =
+            with_timeout(PRESS_TIMEOUT, measure(analog, is_first_ladder, released)).await
+        else {
+            warn!("Button calibration timed out waiting for {}", name);
+            return None;
+        };
+        info!("Level of {}: {}", name, level);
+
+        if is_first_ladder {
+            ladder_1[index] = level;
+        } else {
+            ladder_2[index - FIRST_LADDER_BUTTONS] = level;
+        }
+    }
+
+    let Some(calibration) = Calibration::from_levels(released, ladder_1, ladder_2) else {
+        warn!(
+            "Button levels can't be told apart: {} {}",
+            ladder_1, ladder_2
+        );
+        show(
+            "The buttons could not be told apart.\nThe previous levels are kept.",
+            display,
+            frame,
+        )
+        .await;
+        // Not waiting for a press, as the previous thresholds might not detect one
+        Timer::after(MESSAGE_TIME).await;
+        return None;
+    };
+
+    info!("Button calibration: {}", calibration);
+    analog.set_calibration(calibration);
+    show("Buttons calibrated\n\nPress any button", display, frame).await;
+    analog.wait_for_press().await;
+    Some(calibration)
+}
+
+/// Waits for the button to be pressed and released again and returns its level while it was held
+async fn measure(analog: &mut Analog<'_>, is_first_ladder: bool, released: u16) -> u16 {
+    let watch = supervisor::watch(Task::Input);
+    let threshold = released.saturating_sub(PRESS_MARGIN);
+    let ladder_level = |levels: (u16, u16)| if is_first_ladder { levels.0 } else { levels.1 };
+
+    while ladder_level(analog.ladder_levels().await) > threshold {
+        watch.check_in();
+        Timer::after(POLL_INTERVAL).await;
+    }
+
+    Timer::after(SETTLE_TIME).await;
+    let level = ladder_level(analog.ladder_levels().await);
+
+    while ladder_level(analog.ladder_levels().await) <= threshold {
+        watch.check_in();
+        Timer::after(POLL_INTERVAL).await;
+    }
+
+    level
+}
+
+/// Waits until no button is pressed on either ladder, e.g. the ones held to start the calibration
+async fn wait_for_release(analog: &mut Analog<'_>) {
+    let watch = supervisor::watch(Task::Input);
+    while analog.pressed_button().await.is_some() {
+        watch.check_in();
+        Timer::after(POLL_INTERVAL).await;
+    }
+}
+
+/// Shows the instruction on an otherwise white screen
+async fn show(text: &str, display: &SharedDisplay, frame: &mut Frame) {
+    // White
+    frame.fill(0xFF);
+
+    let style = MonoTextStyle::new(&FONT_10X20, BinaryColor::On);
+    let title = Text::new("Button calibration", Point::new(10, 30), style).draw(frame);
+    if let Err(error) = title.and_then(|_| Text::new(text, Point::new(10, 75), style).draw(frame)) {
+        error!("Failed to draw calibration: {:?}", error);
+    }
+
+    if let Err(error) = display.submit(frame).await {
+        error!(
+            "Failed to display calibration: {:?}",
+            defmt::Debug2Format(&error)
+        );
+    }
+}
@@ -0,0 +1,103 @@
+//! While the reader is off and plugged in, it wakes up now and then to show how far the battery
+//! charged, like other readers do. Turning off while charging shows the charging screen instead of
+//! the sleep screen. Once the battery stops charging, the sleep screen comes back and the reader
+//! sleeps until the power button is pressed.
+//!
+//! Charging is told from the voltage, see the battery module. The voltage of the last wake up is
+//! kept in RTC memory to see whether it still rises.
+
+use core::fmt::Write;
+
+use embedded_graphics::Drawable;
+use embedded_graphics::mono_font::MonoTextStyle;
+use embedded_graphics::mono_font::ascii::FONT_10X20;
+use embedded_graphics::pixelcolor::BinaryColor;
+use embedded_graphics::prelude::{DrawTarget, Point, Primitive, Size};
+use embedded_graphics::primitives::{PrimitiveStyle, Rectangle};
+use embedded_graphics::text::{Alignment, Text};
+use portable_atomic::{AtomicBool, AtomicU16, Ordering};
+
+use crate::battery;
+use crate::eink_display::{DrawError, Frame};
+
+/// Time in deep sleep between updates of the charging screen
+pub(crate) const WAKE_INTERVAL: core::time::Duration = core::time::Duration::from_secs(5 * 60);
+
+const BATTERY_SIZE: Size = Size::new(160, 80);
+const BATTERY_TIP_SIZE: Size = Size::new(12, 32);
+const OUTLINE_WIDTH: u32 = 6;
+/// Between the outline and the charge
+const GAP: u32 = 6;
+
+/// Voltage at the last update of the charging screen. RTC fast memory keeps it through deep sleep.
+/// It has random content after power loss, which only matters for the first comparison.
+#[esp_hal::ram(unstable(rtc_fast, persistent))]
+static PREVIOUS_MILLIVOLTS: AtomicU16 = AtomicU16::new(0);
+/// Whether the panel shows the charging screen, which only matters on a wake up by the timer
+#[esp_hal::ram(unstable(rtc_fast, persistent))]
+static IS_SHOWN: AtomicBool = AtomicBool::new(false);
+
+/// Remembers the voltage when the reader turns off while charging
+pub(crate) fn start(millivolts: u16) {
+    PREVIOUS_MILLIVOLTS.store(millivolts, Ordering::Relaxed);
+    IS_SHOWN.store(true, Ordering::Relaxed);
+}
+
+/// Called when the charging screen was replaced, e.g. by the sleep screen after charging stopped
+pub(crate) fn finish() {
+    IS_SHOWN.store(false, Ordering::Relaxed);
+}
+
+/// Whether the timer woke the reader to update the charging screen
+pub(crate) fn is_shown() -> bool {
+    IS_SHOWN.load(Ordering::Relaxed)
+}
+
+/// Whether the battery still charges since the last update. Keeps the voltage for the next one.
+pub(crate) fn is_still_charging(millivolts: u16) -> bool {
+    let previous = PREVIOUS_MILLIVOLTS.swap(millivolts, Ordering::Relaxed);
+    battery::is_charger_voltage(millivolts) || battery::is_rising(previous, millivolts)
+}
+
+/// Draws a large battery with the charge and the percentage below it on a white frame
+pub(crate) fn draw(millivolts: u16, frame: &mut Frame) -> Result<(), DrawError> {
+    // White
+    frame.fill(0xFF);
+
+    let center = Rectangle::new(Point::zero(), Frame::SIZE).center();
+    // Can't truncate as the icon is small
+    let top_left = center - Point::new(BATTERY_SIZE.width as i32, BATTERY_SIZE.height as i32) / 2;
+    Rectangle::new(top_left, BATTERY_SIZE)
+        .into_styled(PrimitiveStyle::with_stroke(BinaryColor::On, OUTLINE_WIDTH))
+        .draw(frame)?;
+
+    // Can't truncate as the icon is small
+    let tip_top_left = top_left
+        + Point::new(
+            BATTERY_SIZE.width as i32,
+            (BATTERY_SIZE.height - BATTERY_TIP_SIZE.height) as i32 / 2,
+        );
+    frame.fill_solid(
+        &Rectangle::new(tip_top_left, BATTERY_TIP_SIZE),
+        BinaryColor::On,
+    )?;
+
+    let percentage = battery::percentage(millivolts);
+    let inset = OUTLINE_WIDTH + GAP;
+    let inner = BATTERY_SIZE - Size::new(inset * 2, inset * 2);
+    let charge = Size::new(inner.width * u32::from(percentage) / 100, inner.height);
+    // Can't truncate as the inset is small
+    frame.fill_solid(
+        &Rectangle::new(top_left + Point::new(inset as i32, inset as i32), charge),
+        BinaryColor::On,
+    )?;
+
+    let mut text = heapless::String::<16>::new();
+    // Can't fail as "Charging 100%" fits
+    let _ = write!(text, "Charging {percentage}%");
+    let style = MonoTextStyle::new(&FONT_10X20, BinaryColor::On);
+    // Can't truncate as the icon is small
+    let below = center + Point::new(0, BATTERY_SIZE.height as i32 / 2 + 40);
+    Text::with_alignment(&text, below, style, Alignment::Center).draw(frame)?;
+    Ok(())
+}
@@ -0,0 +1,191 @@
+//! Time of day for the status bar. The RTC keeps counting through deep sleep but starts over when
+//! the battery is disconnected, so the Unix time at which it started is kept in RTC memory next to
+//! it. That is set from a time server over SNTP each time the network comes up. Until then there is
+//! no time to show.
+//!
+//! Local time is UTC shifted by the offset from the configuration file, as there are no time zone
+//! rules on the reader. The offset needs to be changed by hand for daylight saving time.
+
+use embassy_time::Duration;
+use portable_atomic::{AtomicI16, AtomicU32, Ordering};
+
+use crate::SharedRtc;
+use crate::status_bar::Time;
+
+/// Marks the start time as set, as RTC memory has random content after power loss
+const MAGIC: u32 = 0x434C_4F4B;
+const SECONDS_PER_DAY: i64 = 24 * 60 * 60;
+/// The time zones on earth are within 14 hours of UTC
+const MAXIMUM_UTC_OFFSET_MINUTES: i16 = 14 * 60;
+
+/// Unix time in seconds when the RTC started counting. RTC fast memory keeps it through deep sleep.
+#[esp_hal::ram(unstable(rtc_fast, persistent))]
+static START: AtomicU32 = AtomicU32::new(0);
+/// [`START`] combined with [`MAGIC`] once it was set
+#[esp_hal::ram(unstable(rtc_fast, persistent))]
+static CHECK: AtomicU32 = AtomicU32::new(0);
+/// How far local time is ahead of UTC
+static UTC_OFFSET_MINUTES: AtomicI16 = AtomicI16::new(0);
+
+/// Called with the time from a time server
+pub(crate) fn set(rtc: &SharedRtc, unix_seconds: u32) {
+    let start = unix_seconds.wrapping_sub(rtc_seconds(rtc));
+    START.store(start, Ordering::Relaxed);
+    CHECK.store(start ^ MAGIC, Ordering::Relaxed);
+}
+
+/// Called with the offset from the configuration file. Offsets beyond the time zones are ignored.
+pub(crate) fn set_utc_offset(minutes: i16) {
+    if minutes.abs() <= MAXIMUM_UTC_OFFSET_MINUTES {
+        UTC_OFFSET_MINUTES.store(minutes, Ordering::Relaxed);
+    }
+}
+
+/// Unix time in seconds. None until it was set after the battery was disconnected.
+pub(crate) fn now(rtc: &SharedRtc) -> Option<u32> {
+    let start = START.load(Ordering::Relaxed);
+    let is_set = CHECK.load(Ordering::Relaxed) == start ^ MAGIC;
+    is_set.then(|| start.wrapping_add(rtc_seconds(rtc)))
+}
+
+/// Local time of day. None until the time was set.
+pub(crate) fn time_of_day(rtc: &SharedRtc) -> Option<Time> {
+    let offset = i64::from(UTC_OFFSET_MINUTES.load(Ordering::Relaxed)) * 60;
+    let seconds = (i64::from(now(rtc)?) + offset).rem_euclid(SECONDS_PER_DAY);
+    // Can't truncate as a day has 24 hours of 60 minutes
+    Some(Time {
+        hour: (seconds / 3600) as u8,
+        minute: (seconds / 60 % 60) as u8,
+    })
+}
+
+/// How long until the time of day shows the next minute. None until the time was set.
+pub(crate) fn until_next_minute(rtc: &SharedRtc) -> Option<Duration> {
+    let seconds = now(rtc)?;
+    Some(Duration::from_secs(u64::from(60 - seconds % 60)))
+}
+
+fn rtc_seconds(rtc: &SharedRtc) -> u32 {
+    // Can't truncate as the RTC doesn't run for more than a century
+    (rtc.lock(|rtc| rtc.borrow().current_time_us()) / 1_000_000) as u32
+}
+
+#[cfg(feature = "radio")]
+pub(crate) use network::run;
+
+#[cfg(feature = "radio")]
+mod network {
+    use defmt::{info, warn};
+    use embassy_net::dns::DnsQueryType;
+    use embassy_net::udp::{BindError, PacketMetadata, RecvError, SendError, UdpSocket};
+    use embassy_net::{IpEndpoint, Stack};
+    use embassy_time::{Duration, with_timeout};
+
+    use crate::{SharedRtc, status_bar};
+
+    /// Servers of the NTP pool project close to the reader
+    const SERVER: &str = "pool.ntp.org";
+    const PORT: u16 = 123;
+    /// SNTP packets without the optional fields
+    const PACKET_SIZE: usize = 48;
+    /// No leap second warning, version 4 and client mode
+    const CLIENT_HEADER: u8 = 0b00_100_011;
+    const SERVER_MODE: u8 = 4;
+    /// Where the seconds of the time the server sent the response are
+    const TRANSMIT_SECONDS: usize = 40;
+    /// Seconds from the NTP epoch in 1900 to the Unix epoch in 1970
+    const UNIX_EPOCH: u32 = 2_208_988_800;
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    #[derive(Debug, thiserror::Error)]
+    enum SyncClockError {
+        #[error("Failed to look up the time server address")]
+        Resolve,
+        #[error("Failed to open socket")]
+        Bind(BindError),
+        #[error("Failed to send request")]
+        Send(SendError),
+        #[error("Failed to receive response")]
+        Receive(RecvError),
+        #[error("Time server did not respond")]
+        Timeout,
+        #[error("Response is not valid SNTP")]
+        InvalidResponse,
+    }
+
+    /// Sets the clock each time the network comes up, which also makes up for the drift of the
+    /// RTC
+    #[embassy_executor::task]
+    pub(crate) async fn run(stack: Stack<'static>, rtc: &'static SharedRtc) {
+        loop {
+            stack.wait_config_up().await;
+            match query(stack).await {
+                Ok(unix_seconds) => {
+                    info!("Clock set to {} seconds since 1970", unix_seconds);
+                    super::set(rtc, unix_seconds);
+                    status_bar::update();
+                }
+                Err(error) => warn!("Failed to set clock: {:?}", defmt::Debug2Format(&error)),
+            }
+            stack.wait_config_down().await;
+        }
+    }
+
+    /// Asks the time server for the time. Returns it as Unix time in seconds.
+    async fn query(stack: Stack<'_>) -> Result<u32, SyncClockError> {
+        let address = *stack
+            .dns_query(SERVER, DnsQueryType::A)
+            .await
+            .map_err(|_| SyncClockError::Resolve)?
+            .first()
+            .ok_or(SyncClockError::Resolve)?;
+
+        let mut receive_metadata = [PacketMetadata::EMPTY; 1];
+        let mut receive_buffer = [0; PACKET_SIZE];
+        let mut send_metadata = [PacketMetadata::EMPTY; 1];
+        let mut send_buffer = [0; PACKET_SIZE];
+        let mut socket = UdpSocket::new(
+            stack,
+            &mut receive_metadata,
+            &mut receive_buffer,
+            &mut send_metadata,
+            &mut send_buffer,
+        );
+        // Any free port
+        socket.bind(0).map_err(SyncClockError::Bind)?;
+
+        let mut request = [0; PACKET_SIZE];
+        request[0] = CLIENT_HEADER;
+        socket
+            .send_to(&request, IpEndpoint::new(address, PORT))
+            .await
+            .map_err(SyncClockError::Send)?;
+
+        let mut response = [0; PACKET_SIZE];
+        let (length, _) = with_timeout(TIMEOUT, socket.recv_from(&mut response))
+            .await
+            .map_err(|_| SyncClockError::Timeout)?
+            .map_err(SyncClockError::Receive)?;
+        parse_response(&response[..length]).ok_or(SyncClockError::InvalidResponse)
+    }
+
+    /// The time the server sent the response as Unix time. None if the server has no time to give,
+    /// like when it asks clients to go away with a stratum of 0.
+    fn parse_response(packet: &[u8]) -> Option<u32> {
+        let &[header, stratum, ..] = packet else {
+            return None;
+        };
+        if header & 0b111 != SERVER_MODE || stratum == 0 {
+            return None;
+        }
+
+        let seconds = packet.get(TRANSMIT_SECONDS..TRANSMIT_SECONDS + 4)?;
+        let seconds = u32::from_be_bytes(seconds.try_into().ok()?);
+        if seconds == 0 {
+            return None;
+        }
+
+        // Wraps around with the NTP era in 2036, which keeps the Unix time right until 2106
+        Some(seconds.wrapping_sub(UNIX_EPOCH))
+    }
+}
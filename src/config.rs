@@ -0,0 +1,300 @@
+//! Optional configuration file on the SD card to set up the device from a computer. It is read at
+//! start up and overrides the settings of the profile where they overlap.
+//!
+//! The file at [`PATH`] is written in a subset of TOML, as the file system only has short names
+//! with an extension of up to three letters:
+//!
+//! ```toml
+//! [wifi]
+//! ssid = "Home"
+//! password = "secret"
+//!
+//! # Which button each button on the device acts as
+//! [buttons]
+//! left = "right"
+//! right = "left"
+//! # Samples per reading of the buttons up to 9, more for a noisy board
+//! oversampling = 5
+//!
+//! # Shortcuts for a button on each ladder pressed at once, see the shortcut module
+//! [chords]
+//! back+up = "screenshot"
+//! confirm+down = "full_refresh"
+//!
+//! [display]
+//! # Fast refreshes before a full refresh clears the ghosting, 0 for never
+//! full_refresh_interval = 10
+//! # How often a failed transfer to the panel is sent again, more for a flaky connection
+//! spi_retries = 2
+//! # Whether a new frame wakes the display after the power button turned it off
+//! wake_on_refresh = true
+//!
+//! [fonts]
+//! size = "large"
+//!
+//! # Minutes local time is ahead of UTC for the clock in the status bar, 60 for Central Europe
+//! [clock]
+//! utc_offset_minutes = 60
+//!
+//! [power]
+//! # Whether the chip sleeps between readings of the buttons. Turn it off to keep the serial
+//! # console on USB while on battery.
+//! light_sleep = false
+//!
+//! # Milliseconds the power button is held to turn the reader off instead of the display
+//! [power_button]
+//! long_press_ms = 2000
+//! # Shorter presses are ignored
+//! minimum_press_ms = 50
+//!
+//! # Progress sync with KOReader, see the kosync module
+//! [kosync]
+//! server = "http://books.example.com:7200"
+//! username = "reader"
+//! password = "secret"
+//!
+//! # Library sync, see the webdav module
+//! [webdav]
+//! url = "http://cloud.example.com/remote.php/dav/files/reader/Books"
+//! username = "reader"
+//! password = "secret"
+//! # Where the books go on the card
+//! directory = "/BOOKS/WEBDAV"
+//! # Whether to upload the bookmarks next to the books
+//! upload_bookmarks = true
+//! ```
+//!
+//! Values are strings in double quotes or numbers. Unknown sections, keys and invalid values are
+//! skipped.
+
+use alloc::string::String;
+use core::num::{NonZeroU8, NonZeroU16};
+
+use defmt::warn;
+use embassy_time::Duration;
+
+use crate::input::Chord;
+use crate::input::{Button, ButtonMap};
+use crate::kosync;
+use crate::sd_card::{ReadFileError, ReadPriority, SdCard};
+use crate::shortcut::{self, Shortcuts};
+use crate::theme;
+use crate::webdav;
+
+const PATH: &str = "/CONFIG.INI";
+const MAXIMUM_FILE_SIZE: u32 = 4 * 1024;
+
+#[derive(Clone, PartialEq, Eq)]
+pub(crate) struct WifiCredentials {
+    pub(crate) ssid: String,
+    /// Empty for open networks
+    pub(crate) password: String,
+}
+
+/// Keeps the password out of the logs
+impl core::fmt::Debug for WifiCredentials {
+    fn fmt(&self, formatter: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        formatter
+            .debug_struct("WifiCredentials")
+            .field("ssid", &self.ssid)
+            .finish_non_exhaustive()
+    }
+}
+
+impl defmt::Format for WifiCredentials {
+    fn format(&self, formatter: defmt::Formatter<'_>) {
+        defmt::write!(
+            formatter,
+            "WifiCredentials {{ ssid: {}, .. }}",
+            self.ssid.as_str()
+        );
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, defmt::Format)]
+pub(crate) struct Config {
+    pub(crate) wifi: Option<WifiCredentials>,
+    pub(crate) buttons: ButtonMap,
+    /// Samples per reading of the buttons, None to keep the built-in number
+    pub(crate) oversampling: Option<NonZeroU8>,
+    pub(crate) shortcuts: Shortcuts,
+    /// None to keep the built-in interval. Some(None) turns automatic full refreshes off.
+    pub(crate) full_refresh_interval: Option<Option<NonZeroU16>>,
+    /// How often a failed SPI transaction to the panel is sent again, None for the built-in number
+    pub(crate) spi_retries: Option<u8>,
+    /// None keeps waking the display for a refresh
+    pub(crate) wake_on_refresh: Option<bool>,
+    /// Replaces the font size of the settings
+    pub(crate) font_size: Option<theme::Font>,
+    /// How far local time is ahead of UTC, None for UTC
+    pub(crate) utc_offset_minutes: Option<i16>,
+    /// None keeps light sleep on
+    pub(crate) light_sleep: Option<bool>,
+    /// How long the power button is held to turn the reader off, None for the built-in time
+    pub(crate) long_press: Option<Duration>,
+    /// Shorter presses of the power button are ignored, None for the built-in time
+    pub(crate) minimum_press: Option<Duration>,
+    /// Where to sync the reading progress to
+    pub(crate) kosync: Option<kosync::Account>,
+    /// Folder to mirror into the library
+    pub(crate) webdav: Option<webdav::Remote>,
+}
+
+impl Config {
+    /// Without a file nothing is overridden
+    pub(crate) async fn load(sd_card: &mut SdCard) -> Result<Self, ReadFileError> {
+        let content = match sd_card
+            .read_file(PATH, MAXIMUM_FILE_SIZE, ReadPriority::Interactive)
+            .await
+        {
+            Ok(content) => content,
+            Err(ReadFileError::Open(error)) if error.is_not_found() => return Ok(Self::default()),
+            Err(error) => return Err(error),
+        };
+
+        Ok(Self::parse(&String::from_utf8_lossy(&content)))
+    }
+
+    fn parse(content: &str) -> Self {
+        let mut config = Self::default();
+        let mut section = "";
+        let mut ssid = None;
+        let mut password = None;
+        let mut kosync_server = None;
+        let mut kosync_username = None;
+        let mut kosync_password = None;
+        let mut webdav_url = None;
+        let mut webdav_username = None;
+        let mut webdav_password = None;
+        let mut webdav_directory = None;
+        let mut upload_bookmarks = false;
+        for line in content.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with(['#', ';']) {
+                continue;
+            }
+
+            if let Some(name) = line
+                .strip_prefix('[')
+                .and_then(|line| line.strip_suffix(']'))
+            {
+                section = name.trim();
+                continue;
+            }
+
+            let Some((key, value)) = line.split_once('=') else {
+                continue;
+            };
+
+            let key = key.trim();
+            let Some(value) = parse_value(value) else {
+                warn!("Invalid value for {} in config", key);
+                continue;
+            };
+
+            match (section, key) {
+                ("wifi", "ssid") => ssid = Some(value),
+                ("wifi", "password") => password = Some(value),
+                ("buttons", "oversampling") => config.oversampling = value.parse().ok(),
+                ("buttons", button) => {
+                    if let (Some(button), Some(acts_as)) =
+                        (Button::parse(button), Button::parse(&value))
+                    {
+                        config.buttons.set(button, acts_as);
+                    }
+                }
+                ("chords", chord) => {
+                    if let Some(chord) = Chord::parse(chord) {
+                        config.shortcuts.set(chord, shortcut::Action::parse(&value));
+                    }
+                }
+                ("display", "full_refresh_interval") => {
+                    if let Ok(interval) = value.parse() {
+                        config.full_refresh_interval = Some(NonZeroU16::new(interval));
+                    }
+                }
+                ("display", "spi_retries") => config.spi_retries = value.parse().ok(),
+                ("display", "wake_on_refresh") => {
+                    config.wake_on_refresh = Some(parse_flag(&value));
+                }
+                ("fonts", "size") => config.font_size = theme::Font::parse(&value),
+                ("clock", "utc_offset_minutes") => config.utc_offset_minutes = value.parse().ok(),
+                ("power", "light_sleep") => config.light_sleep = Some(parse_flag(&value)),
+                ("power_button", "long_press_ms") => {
+                    config.long_press = value.parse().ok().map(Duration::from_millis);
+                }
+                ("power_button", "minimum_press_ms") => {
+                    config.minimum_press = value.parse().ok().map(Duration::from_millis);
+                }
+                ("kosync", "server") => kosync_server = Some(value),
+                ("kosync", "username") => kosync_username = Some(value),
+                ("kosync", "password") => kosync_password = Some(value),
+                ("webdav", "url") => webdav_url = Some(value),
+                ("webdav", "username") => webdav_username = Some(value),
+                ("webdav", "password") => webdav_password = Some(value),
+                ("webdav", "directory") => webdav_directory = Some(value),
+                ("webdav", "upload_bookmarks") => upload_bookmarks = parse_flag(&value),
+                _ => {}
+            }
+        }
+
+        config.wifi = ssid
+            .filter(|ssid| !ssid.is_empty())
+            .map(|ssid| WifiCredentials {
+                ssid,
+                password: password.unwrap_or_default(),
+            });
+
+        if let (Some(server), Some(username)) = (kosync_server, kosync_username) {
+            config.kosync =
+                kosync::Account::new(&server, username, &kosync_password.unwrap_or_default());
+            if config.kosync.is_none() {
+                warn!("Invalid kosync server in config, only http:// URLs are supported");
+            }
+        }
+
+        if let Some(url) = webdav_url {
+            config.webdav = webdav::Remote::new(
+                &url,
+                webdav_username.as_deref(),
+                &webdav_password.unwrap_or_default(),
+                webdav_directory,
+                upload_bookmarks,
+            );
+            if config.webdav.is_none() {
+                warn!("Invalid WebDAV URL in config, only http:// URLs are supported");
+            }
+        }
+
+        config
+    }
+}
+
+fn parse_flag(value: &str) -> bool {
+    matches!(value, "yes" | "true" | "1")
+}
+
+/// The value without quotes, escapes and a comment after it. None if a string is not closed.
+fn parse_value(value: &str) -> Option<String> {
+    let value = value.trim();
+    let Some(quoted) = value.strip_prefix('"') else {
+        let value = value.split_once('#').map_or(value, |(value, _)| value);
+        return Some(String::from(value.trim()));
+    };
+
+    let mut unescaped = String::new();
+    let mut characters = quoted.chars();
+    while let Some(character) = characters.next() {
+        match character {
+            '"' => return Some(unescaped),
+            '\\' => match characters.next()? {
+                'n' => unescaped.push('\n'),
+                't' => unescaped.push('\t'),
+                escaped => unescaped.push(escaped),
+            },
+            _ => unescaped.push(character),
+        }
+    }
+
+    None
+}
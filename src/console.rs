@@ -0,0 +1,388 @@
+//! Debug console on the USB serial port for bringing up boards and for readers helping to find
+//! problems without the defmt tooling. Connect with any serial terminal and type `help` for the
+//! commands.
+//!
+//! The console shares the port with the defmt logs, which show up as garbled characters in a plain
+//! terminal.
+
+use alloc::format;
+use alloc::string::String;
+
+use embassy_time::Instant;
+use embedded_graphics::Drawable;
+use embedded_graphics::mono_font::MonoTextStyle;
+use embedded_graphics::mono_font::ascii::FONT_10X20;
+use embedded_graphics::pixelcolor::BinaryColor;
+use embedded_graphics::prelude::{DrawTarget, OriginDimensions, Point, Size};
+use embedded_graphics::primitives::Rectangle;
+use embedded_graphics::text::Text;
+use embedded_io_async::{Read, Write};
+use esp_hal::Async;
+use esp_hal::peripherals::USB_DEVICE;
+use esp_hal::usb_serial_jtag::{UsbSerialJtag, UsbSerialJtagTx};
+
+use crate::diagnostics::{self, Report};
+use crate::display_task::{DisplayHandle, ShowMode};
+use crate::eink_display::{DrawError, Frame, RefreshMode, RefreshRequest};
+use crate::event_bus::{self, Event};
+use crate::sd_card::ReadPriority;
+use crate::verbosity::{self, Level, Module};
+use crate::{REFRESH_QUEUE, SharedSdCard, ShutdownSignal, battery};
+
+const MAXIMUM_LINE_LENGTH: usize = 128;
+const PROMPT: &str = "> ";
+const HELP: &str = "\
+battery          Last battery reading
+refresh MODE     Refresh the whole screen with the mode full, half, fast or auto
+ls [PATH]        List a directory on the SD card
+cat PATH         Print a file on the SD card
+draw test        Draw a test pattern
+sleep            Turn the reader off
+stats            Uptime and refresh times
+memory           Heap and stack usage
+overlay on|off   Show the memory usage at the bottom of the screen
+log MODULE LEVEL How much the display module logs: normal, debug or trace
+benchmark        Measure SD card reads and refreshes
+trace            Print the bytes sent to the display since the last dump
+help             This list";
+/// Bytes of a file printed at once
+const CHUNK_SIZE: usize = 512;
+/// Side of the squares of the test pattern
+const SQUARE_SIZE: u32 = 40;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+enum Command<'a> {
+    Help,
+    Battery,
+    Refresh(ShowMode),
+    List(&'a str),
+    Show(&'a str),
+    DrawTest,
+    Sleep,
+    Stats,
+    Memory,
+    Overlay(bool),
+    Log(Module, Level),
+    Benchmark,
+    Trace,
+}
+
+/// The command on the line or what is wrong with it
+fn parse(line: &str) -> Result<Command<'_>, &'static str> {
+    let mut words = line.split_whitespace();
+    let command = match (words.next(), words.next()) {
+        (Some("help"), None) => Command::Help,
+        (Some("battery"), None) => Command::Battery,
+        (Some("refresh"), Some(mode)) => Command::Refresh(match mode {
+            "full" => ShowMode::Exact(RefreshMode::Full),
+            "half" => ShowMode::Exact(RefreshMode::HalfRefresh),
+            "fast" => ShowMode::Exact(RefreshMode::Fast),
+            "auto" => ShowMode::Auto,
+            _ => return Err("Unknown refresh mode, use full, half, fast or auto"),
+        }),
+        (Some("ls"), path) => Command::List(path.unwrap_or("/")),
+        (Some("cat"), Some(path)) => Command::Show(path),
+        (Some("draw"), Some("test")) => Command::DrawTest,
+        (Some("sleep"), None) => Command::Sleep,
+        (Some("stats"), None) => Command::Stats,
+        (Some("memory"), None) => Command::Memory,
+        (Some("overlay"), Some("on")) => Command::Overlay(true),
+        (Some("overlay"), Some("off")) => Command::Overlay(false),
+        (Some("log"), Some(module)) => {
+            let Some(module) = Module::parse(module) else {
+                return Err("Unknown module, use display");
+            };
+            let Some(level) = words.next().and_then(Level::parse) else {
+                return Err("Unknown level, use normal, debug or trace");
+            };
+            Command::Log(module, level)
+        }
+        (Some("benchmark"), None) => Command::Benchmark,
+        (Some("trace"), None) => Command::Trace,
+        _ => return Err("Unknown command, type help for the list"),
+    };
+
+    if words.next().is_some() {
+        return Err("Too many arguments");
+    }
+    Ok(command)
+}
+
+/// Checkerboard with a label to check the panel for stuck pixels and ghosting
+pub(crate) fn draw_test_pattern(frame: &mut Frame) -> Result<(), DrawError> {
+    let size = frame.size();
+    frame.clear(BinaryColor::Off)?;
+    for row in 0..size.height.div_ceil(SQUARE_SIZE) {
+        for column in (row % 2..size.width.div_ceil(SQUARE_SIZE)).step_by(2) {
+            // Can't truncate as the frame is less than 1000 pixels on each side
+            let top_left = Point::new((column * SQUARE_SIZE) as i32, (row * SQUARE_SIZE) as i32);
+            let square = Rectangle::new(top_left, Size::new_equal(SQUARE_SIZE));
+            frame.fill_solid(&square, BinaryColor::On)?;
+        }
+    }
+
+    let label = Rectangle::new(Point::new(0, 0), Size::new(size.width, SQUARE_SIZE));
+    frame.fill_solid(&label, BinaryColor::Off)?;
+    let style = MonoTextStyle::new(&FONT_10X20, BinaryColor::On);
+    Text::new("Test pattern", Point::new(10, 26), style).draw(frame)?;
+    Ok(())
+}
+
+/// Reads commands from the USB serial port and answers them
+#[embassy_executor::task]
+pub(crate) async fn run(
+    usb: USB_DEVICE<'static>,
+    sd_card: &'static SharedSdCard,
+    display: DisplayHandle,
+    shutdown: &'static ShutdownSignal,
+) {
+    let (mut receiver, mut transmitter) = UsbSerialJtag::new(usb).into_async().split();
+    let mut line = heapless::String::<MAXIMUM_LINE_LENGTH>::new();
+    let mut buffer = [0; 64];
+    loop {
+        let read = match receiver.read(&mut buffer).await {
+            Ok(read) => read,
+            Err(error) => {
+                defmt::warn!(
+                    "Failed to read from console: {:?}",
+                    defmt::Debug2Format(&error)
+                );
+                continue;
+            }
+        };
+
+        for &byte in &buffer[..read] {
+            match byte {
+                b'\r' | b'\n' if line.is_empty() => {
+                    // The line feed after a carriage return
+                    if byte == b'\r' {
+                        print(&mut transmitter, "\n").await;
+                        print(&mut transmitter, PROMPT).await;
+                    }
+                }
+                b'\r' | b'\n' => {
+                    print(&mut transmitter, "\n").await;
+                    match parse(&line) {
+                        Ok(command) => {
+                            let context = Context {
+                                sd_card,
+                                display,
+                                shutdown,
+                            };
+                            execute(command, context, &mut transmitter).await;
+                        }
+                        Err(error) => print(&mut transmitter, error).await,
+                    }
+                    line.clear();
+                    print(&mut transmitter, "\n").await;
+                    print(&mut transmitter, PROMPT).await;
+                }
+                // Backspace and delete
+                0x08 | 0x7F => {
+                    if line.pop().is_some() {
+                        print(&mut transmitter, "\x08 \x08").await;
+                    }
+                }
+                byte if byte.is_ascii_graphic() || byte == b' ' => {
+                    if line.push(char::from(byte)).is_ok() {
+                        print(&mut transmitter, &line[line.len() - 1..]).await;
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Context {
+    sd_card: &'static SharedSdCard,
+    display: DisplayHandle,
+    shutdown: &'static ShutdownSignal,
+}
+
+async fn execute(command: Command<'_>, context: Context, output: &mut UsbSerialJtagTx<'_, Async>) {
+    match command {
+        Command::Help => print(output, HELP).await,
+        Command::Battery => {
+            let millivolts = battery::last_millivolts();
+            let text = if battery::is_plausible(millivolts) {
+                format!(
+                    "{millivolts} mV, about {}%",
+                    battery::percentage(millivolts)
+                )
+            } else {
+                format!("No battery connected, read {millivolts} mV")
+            };
+            print(output, &text).await;
+        }
+        Command::Refresh(mode) => {
+            REFRESH_QUEUE.request(RefreshRequest { region: None, mode });
+            print(output, "Refresh requested").await;
+        }
+        Command::List(path) => list(path, context.sd_card, output).await,
+        Command::Show(path) => show(path, context.sd_card, output).await,
+        Command::DrawTest => {
+            event_bus::publish(Event::TestPattern);
+            print(output, "Test pattern requested").await;
+        }
+        Command::Sleep => {
+            context.shutdown.signal(());
+            print(output, "Turning off").await;
+        }
+        Command::Stats => {
+            let display = context.display.lock().await;
+            let statistics = display.statistics();
+            let mut text = format!("Uptime: {} s", Instant::now().as_secs());
+            for (name, mode) in [
+                ("Full", RefreshMode::Full),
+                ("Half", RefreshMode::HalfRefresh),
+                ("Fast", RefreshMode::Fast),
+            ] {
+                let refreshes = statistics.get(mode);
+                text.push_str(&format!(
+                    "\n{name} refreshes: {}, average {} ms",
+                    refreshes.count,
+                    refreshes.average().map_or(0, |average| average.as_millis())
+                ));
+            }
+            text.push_str(&format!("\nSPI retries: {}", statistics.retries()));
+            drop(display);
+            print(output, &text).await;
+        }
+        Command::Memory => print(output, &format!("{}", Report::collect())).await,
+        Command::Overlay(is_shown) => {
+            diagnostics::set_overlay_shown(is_shown);
+            let text = if is_shown {
+                "Overlay on"
+            } else {
+                "Overlay off"
+            };
+            print(output, text).await;
+        }
+        Command::Log(module, level) => {
+            verbosity::set(module, level);
+            print(output, "Log level changed").await;
+        }
+        Command::Benchmark => {
+            event_bus::publish(Event::Benchmark);
+            print(output, "Benchmark requested, results are shown on screen").await;
+        }
+        #[cfg(feature = "display-trace")]
+        Command::Trace => print_trace(output).await,
+        #[cfg(not(feature = "display-trace"))]
+        Command::Trace => print(output, "Built without the display-trace feature").await,
+    }
+}
+
+/// Prints and removes the recorded bytes a batch at a time, to not hold off interrupts while
+/// waiting for the port
+#[cfg(feature = "display-trace")]
+async fn print_trace(output: &mut UsbSerialJtagTx<'_, Async>) {
+    use crate::eink_display::trace::{self, BATCH_SIZE, Entry};
+
+    // Only what is there now, as the display keeps recording while the port is busy
+    let mut remaining = trace::len();
+    let overwritten = trace::take_overwritten();
+    print(
+        output,
+        &format!("{remaining} entries, {overwritten} overwritten\n"),
+    )
+    .await;
+
+    let mut batch = [Entry::EMPTY; BATCH_SIZE];
+    while remaining > 0 {
+        let entries = trace::take_batch(&mut batch);
+        if entries.is_empty() {
+            break;
+        }
+
+        remaining = remaining.saturating_sub(entries.len());
+        let mut text = String::new();
+        for entry in entries {
+            match entry {
+                Entry::Command(command) => text.push_str(&format!("C {command:#04x}\n")),
+                Entry::Data(data) => text.push_str(&format!("D {data:#04x}\n")),
+                Entry::Truncated(length) => {
+                    text.push_str(&format!("D ... {length} more bytes\n"));
+                }
+            }
+        }
+        print(output, &text).await;
+    }
+}
+
+async fn list(path: &str, sd_card: &SharedSdCard, output: &mut UsbSerialJtagTx<'_, Async>) {
+    let entries = match sd_card.lock().await.as_mut() {
+        Some(sd_card) => sd_card.list_directory(path).await,
+        None => return print(output, "No SD card").await,
+    };
+
+    let entries = match entries {
+        Ok(entries) => entries,
+        Err(error) => {
+            return print(output, &format!("Failed to list {path}: {error:?}")).await;
+        }
+    };
+
+    let mut text = String::new();
+    for entry in entries {
+        let name = format!("{}", entry.name);
+        if entry.attributes.is_directory() {
+            text.push_str(&format!("{name}/\n"));
+        } else {
+            text.push_str(&format!("{name:<13} {}\n", entry.size));
+        }
+    }
+    print(output, text.trim_end()).await;
+}
+
+/// Prints the file in pieces, so it doesn't need to fit into memory
+async fn show(path: &str, sd_card: &SharedSdCard, output: &mut UsbSerialJtagTx<'_, Async>) {
+    let mut sd_card = sd_card.lock().await;
+    let Some(sd_card) = sd_card.as_mut() else {
+        return print(output, "No SD card").await;
+    };
+
+    let file = match sd_card.open(path, embedded_sdmmc::Mode::ReadOnly).await {
+        Ok(file) => file,
+        Err(error) => {
+            return print(output, &format!("Failed to open {path}: {error:?}")).await;
+        }
+    };
+
+    let mut buffer = [0; CHUNK_SIZE];
+    let mut offset = 0;
+    loop {
+        let read = match sd_card
+            .read_at(file, offset, &mut buffer, ReadPriority::Background)
+            .await
+        {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(error) => {
+                print(output, &format!("\nFailed to read {path}: {error:?}")).await;
+                break;
+            }
+        };
+
+        print(output, &String::from_utf8_lossy(&buffer[..read])).await;
+        // Can't truncate as it is at most the chunk size
+        offset += read as u32;
+    }
+
+    if let Err(error) = sd_card.close(file).await {
+        defmt::warn!("Failed to close {}: {:?}", path, error);
+    }
+}
+
+/// Writes the text with the line breaks terminals expect
+async fn print(output: &mut UsbSerialJtagTx<'_, Async>, text: &str) {
+    for (index, line) in text.split('\n').enumerate() {
+        if index > 0 {
+            let _ = output.write_all(b"\r\n").await;
+        }
+        let _ = output.write_all(line.as_bytes()).await;
+    }
+    let _ = output.flush().await;
+}
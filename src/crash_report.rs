@@ -0,0 +1,175 @@
+//! Panics and fatal errors leave a report in the coredump partition, as the logs are gone once the
+//! device resets and readers rarely have the defmt tooling attached. The next start offers to
+//! export the report to the SD card, so it can be attached to a bug report.
+//!
+//! The report is written without the heap and the shared flash, as either could be what broke.
+
+use alloc::string::String;
+use alloc::vec;
+use core::fmt::{self, Write};
+use core::panic::PanicInfo;
+
+use embassy_time::Instant;
+use embedded_storage::{ReadStorage, Storage};
+use esp_hal::peripherals::FLASH;
+use esp_hal::rtc_cntl::reset_reason;
+use esp_hal::system::Cpu;
+use esp_storage::{FlashStorage, FlashStorageError};
+use portable_atomic::{AtomicBool, AtomicU32, Ordering};
+
+use crate::flash::{self, FindPartitionError, SharedFlash};
+use crate::sd_card::{OpenError, SdCard, WriteFileError};
+use crate::supervisor::Task;
+
+/// Name of the data partition in partition-table.csv
+const PARTITION_LABEL: &str = "coredump";
+const MAGIC: [u8; 4] = *b"CRSH";
+/// Magic followed by the report length
+const HEADER_SIZE: usize = 8;
+/// Longest report, so it fits on the stack of the panic handler
+const MAXIMUM_LENGTH: usize = 1024;
+/// Enough for the return addresses of the frames esp-backtrace captures
+const MAXIMUM_BACKTRACE_LENGTH: usize = 256;
+const EXPORT_DIRECTORY: &str = "/LOGS";
+/// Where the report is exported to on the SD card
+const EXPORT_PATH: &str = "/LOGS/CRASH.TXT";
+
+/// Offset of the partition or 0 until [`enable`] found it. The panic handler can't look it up as it
+/// would need the heap.
+static PARTITION_OFFSET: AtomicU32 = AtomicU32::new(0);
+/// Set by the first panic, so a panic while writing the report doesn't loop
+static IS_PANICKING: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum LoadCrashReportError {
+    #[error("Failed to read from flash")]
+    Read(FlashStorageError),
+    #[error("Crash report is longer than the maximum length")]
+    TooLong(u32),
+}
+
+/// Lets panics and fatal errors write a report
+pub(crate) fn enable(flash: &SharedFlash) -> Result<(), FindPartitionError> {
+    let partition = flash::find_partition(flash, PARTITION_LABEL)?;
+    PARTITION_OFFSET.store(partition.offset, Ordering::Relaxed);
+    Ok(())
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo<'_>) -> ! {
+    defmt::error!("Panic: {}", defmt::Display2Format(info));
+
+    if !IS_PANICKING.swap(true, Ordering::Relaxed) {
+        let backtrace = esp_backtrace::Backtrace::capture();
+        let mut addresses = heapless::String::<MAXIMUM_BACKTRACE_LENGTH>::new();
+        for frame in backtrace.frames() {
+            let _ = write!(addresses, " {:#010x}", frame.program_counter());
+        }
+        defmt::error!("Backtrace:{}", addresses.as_str());
+
+        save(format_args!("Panic: {info}\nBacktrace:{addresses}"));
+    }
+
+    esp_hal::system::software_reset()
+}
+
+/// Writes a report for an error that stops the application. The shared flash is only used while
+/// its lock is held, which never spans an await, so this is fine to call from tasks but not from
+/// interrupts.
+pub(crate) fn save_fatal_error(error: &dyn fmt::Debug) {
+    save(format_args!("Fatal error: {error:?}"));
+}
+
+/// Writes a report for a task the supervisor found stuck
+pub(crate) fn save_stuck(task: Task) {
+    save(format_args!("Watchdog: {task:?} stopped checking in"));
+}
+
+fn save(cause: fmt::Arguments<'_>) {
+    let offset = PARTITION_OFFSET.load(Ordering::Relaxed);
+    if offset == 0 {
+        return;
+    }
+
+    let mut report = heapless::String::<MAXIMUM_LENGTH>::new();
+    // A report cut off at the maximum length is still useful
+    let _ = write!(
+        report,
+        "{cause}\nStarted after: {:?}\nUptime: {} s\nVersion: {}\n",
+        reset_reason(Cpu::ProCpu),
+        Instant::now().as_secs(),
+        env!("CARGO_PKG_VERSION")
+    );
+
+    let mut data = [0; HEADER_SIZE + MAXIMUM_LENGTH];
+    data[..MAGIC.len()].copy_from_slice(&MAGIC);
+    // Can't truncate as it is at most the maximum length
+    data[MAGIC.len()..HEADER_SIZE].copy_from_slice(&(report.len() as u32).to_le_bytes());
+    data[HEADER_SIZE..HEADER_SIZE + report.len()].copy_from_slice(report.as_bytes());
+
+    // SAFETY: Nothing else uses the flash while the report is written, as the panic handler and
+    // the tasks don't run in the middle of a flash operation. The peripheral is dropped right after.
+    let mut flash = FlashStorage::new(unsafe { FLASH::steal() });
+    if let Err(error) = flash.write(offset, &data[..HEADER_SIZE + report.len()]) {
+        defmt::error!(
+            "Failed to save crash report: {:?}",
+            defmt::Debug2Format(&error)
+        );
+    }
+}
+
+/// The report of the last crash if there is one
+pub(crate) fn load(flash: &SharedFlash) -> Result<Option<String>, LoadCrashReportError> {
+    let offset = PARTITION_OFFSET.load(Ordering::Relaxed);
+    if offset == 0 {
+        return Ok(None);
+    }
+
+    let mut header = [0; HEADER_SIZE];
+    flash
+        .lock(|flash| flash.borrow_mut().read(offset, &mut header))
+        .map_err(LoadCrashReportError::Read)?;
+
+    let (magic, length) = header.split_at(MAGIC.len());
+    if magic != MAGIC {
+        return Ok(None);
+    }
+
+    let length = u32::from_le_bytes([length[0], length[1], length[2], length[3]]);
+    // Can't truncate as usize is 32 bits
+    if length as usize > MAXIMUM_LENGTH {
+        return Err(LoadCrashReportError::TooLong(length));
+    }
+
+    let mut report = vec![0; length as usize];
+    // Can't truncate as the header size is a small constant
+    flash
+        .lock(|flash| {
+            flash
+                .borrow_mut()
+                .read(offset + HEADER_SIZE as u32, &mut report)
+        })
+        .map_err(LoadCrashReportError::Read)?;
+
+    // A reset in the middle of writing the report can leave broken characters
+    Ok(Some(String::from_utf8_lossy(&report).into_owned()))
+}
+
+/// Writes the report to the SD card
+pub(crate) async fn export(sd_card: &mut SdCard, report: &str) -> Result<(), WriteFileError> {
+    sd_card
+        .create_directories(EXPORT_DIRECTORY)
+        .await
+        .map_err(OpenError::from)?;
+    sd_card.write_file(EXPORT_PATH, report.as_bytes()).await
+}
+
+/// Removes the report after it was exported or dismissed
+pub(crate) fn clear(flash: &SharedFlash) -> Result<(), FlashStorageError> {
+    let offset = PARTITION_OFFSET.load(Ordering::Relaxed);
+    if offset == 0 {
+        return Ok(());
+    }
+
+    flash.lock(|flash| flash.borrow_mut().write(offset, &[0; HEADER_SIZE]))
+}
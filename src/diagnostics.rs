@@ -0,0 +1,207 @@
+//! Heap and stack usage at runtime, as the heap is tight and a stack overflow only shows up as
+//! memory corruption. Reported on the console and optionally drawn over the screen.
+//!
+//! The embassy tasks don't have stacks of their own. They all run on the main stack, so that has
+//! the one high-water mark that matters. Interrupts run on it as well.
+
+use core::alloc::{GlobalAlloc, Layout};
+use core::fmt::{self, Display, Write};
+
+use embedded_graphics::Drawable;
+use embedded_graphics::mono_font::MonoTextStyle;
+use embedded_graphics::mono_font::ascii::FONT_8X13;
+use embedded_graphics::pixelcolor::BinaryColor;
+use embedded_graphics::prelude::{DrawTarget, Point, Size};
+use embedded_graphics::primitives::Rectangle;
+use embedded_graphics::text::{Baseline, Text};
+use portable_atomic::{AtomicBool, Ordering};
+
+use crate::eink_display::{DrawError, Frame, Region};
+use crate::event_bus::{self, Event};
+
+/// Written to the unused stack at start up. Words that still hold it were never used.
+const PAINT: u32 = 0xDEAD_BEEF;
+/// Left alone below the stack pointer while painting for the painting itself
+const PAINT_MARGIN: usize = 256;
+/// A multiple of 8 as the strip is a column band of the panel
+const OVERLAY_HEIGHT: u32 = 16;
+// Can't truncate as the frame is less than 1000 pixels high
+const OVERLAY_AREA: Rectangle = Rectangle::new(
+    Point::new(0, (Frame::SIZE.height - OVERLAY_HEIGHT) as i32),
+    Size::new(Frame::SIZE.width, OVERLAY_HEIGHT),
+);
+/// The overlay strip in panel coordinates
+pub(crate) const OVERLAY_REGION: Region = Region::covering(OVERLAY_AREA).unwrap();
+
+static IS_OVERLAY_SHOWN: AtomicBool = AtomicBool::new(false);
+
+unsafe extern "C" {
+    /// Lowest address of the main stack from the esp-hal linker script
+    static _stack_end_cpu0: u32;
+    /// Highest address of the main stack where it starts growing down from
+    static _stack_start_cpu0: u32;
+}
+
+#[derive(Debug, Clone, Copy, defmt::Format)]
+pub(crate) struct Report {
+    pub(crate) heap_used: usize,
+    /// Most that was in use at once since start up
+    pub(crate) heap_peak: usize,
+    pub(crate) heap_free: usize,
+    /// Largest allocation that would succeed. Far below the free memory means fragmentation.
+    pub(crate) largest_free_block: usize,
+    /// Deepest the stack was since start up
+    pub(crate) stack_used: usize,
+    pub(crate) stack_size: usize,
+}
+
+impl Report {
+    pub(crate) fn collect() -> Self {
+        let (bottom, top) = stack_bounds();
+        Self {
+            heap_used: esp_alloc::HEAP.used(),
+            heap_peak: esp_alloc::HEAP.stats().max_usage,
+            heap_free: esp_alloc::HEAP.free(),
+            largest_free_block: largest_free_block(),
+            stack_used: stack_used(),
+            stack_size: top - bottom,
+        }
+    }
+}
+
+impl Display for Report {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            formatter,
+            "Heap: {} bytes used, {} bytes at peak, {} bytes free",
+            self.heap_used, self.heap_peak, self.heap_free
+        )?;
+        writeln!(
+            formatter,
+            "Largest free block: {} bytes",
+            self.largest_free_block
+        )?;
+        write!(
+            formatter,
+            "Stack: {} of {} bytes at most",
+            self.stack_used, self.stack_size
+        )
+    }
+}
+
+fn stack_bounds() -> (usize, usize) {
+    // SAFETY: Only the addresses of the linker symbols are taken
+    unsafe {
+        (
+            (&raw const _stack_end_cpu0) as usize,
+            (&raw const _stack_start_cpu0) as usize,
+        )
+    }
+}
+
+/// Fills the unused part of the stack with a pattern to later find out how deep it grew. Called
+/// once at start up while the stack is still shallow.
+pub(crate) fn paint_stack() {
+    let stack_pointer: usize;
+    // SAFETY: Only reads the stack pointer
+    unsafe { core::arch::asm!("mv {}, sp", out(reg) stack_pointer) };
+
+    let (bottom, _) = stack_bounds();
+    let end = stack_pointer.saturating_sub(PAINT_MARGIN);
+    for address in (bottom..end).step_by(size_of::<u32>()) {
+        // SAFETY: Nothing lives below the stack pointer. Interrupts that push their frames there
+        // in between only overwrite the pattern, which they would have used anyway.
+        unsafe { (address as *mut u32).write_volatile(PAINT) };
+    }
+}
+
+/// Bytes allocated on the heap since start up, including what was freed again. Code that should
+/// not allocate can compare it before and after.
+pub(crate) fn total_allocated() -> usize {
+    esp_alloc::HEAP.stats().total_allocated
+}
+
+/// Bytes of the stack that were used since it was painted
+fn stack_used() -> usize {
+    let (bottom, top) = stack_bounds();
+    let untouched = (bottom..top)
+        .step_by(size_of::<u32>())
+        // SAFETY: The addresses are within the stack and aligned
+        .take_while(|&address| unsafe { (address as *const u32).read_volatile() } == PAINT)
+        .count();
+
+    top - bottom - untouched * size_of::<u32>()
+}
+
+/// Finds the largest allocation that succeeds with a binary search, as esp-alloc doesn't report
+/// it. Each attempt is freed right away with interrupts disabled, so nothing else sees it.
+fn largest_free_block() -> usize {
+    let fits = |size: usize| {
+        let Ok(layout) = Layout::from_size_align(size, size_of::<u32>()) else {
+            return false;
+        };
+
+        critical_section::with(|_| {
+            // SAFETY: The layout is not zero-sized and the block is freed with the same layout
+            unsafe {
+                let block = esp_alloc::HEAP.alloc(layout);
+                if block.is_null() {
+                    return false;
+                }
+                esp_alloc::HEAP.dealloc(block, layout);
+                true
+            }
+        })
+    };
+
+    let mut fitting = 0;
+    let mut too_large = esp_alloc::HEAP.free() + 1;
+    while too_large - fitting > 1 {
+        let size = fitting + (too_large - fitting) / 2;
+        if fits(size) {
+            fitting = size;
+        } else {
+            too_large = size;
+        }
+    }
+
+    fitting
+}
+
+/// Whether the overlay should be drawn over the screen
+pub(crate) fn is_overlay_shown() -> bool {
+    IS_OVERLAY_SHOWN.load(Ordering::Relaxed)
+}
+
+pub(crate) fn set_overlay_shown(is_shown: bool) {
+    IS_OVERLAY_SHOWN.store(is_shown, Ordering::Relaxed);
+    event_bus::publish(Event::Overlay(is_shown));
+}
+
+/// Draws the report in a strip along the bottom of the frame. Displaying [`OVERLAY_REGION`] with
+/// `display_region` updates only the strip.
+pub(crate) fn draw_overlay(report: &Report, frame: &mut Frame) -> Result<(), DrawError> {
+    frame.fill_solid(&OVERLAY_AREA, BinaryColor::On)?;
+
+    let mut text = heapless::String::<64>::new();
+    // Always fits, as the numbers are at most 6 digits each
+    let _ = write!(
+        text,
+        "Heap {}/{}K peak {}K block {}K Stack {}K",
+        report.heap_used / 1024,
+        (report.heap_used + report.heap_free) / 1024,
+        report.heap_peak / 1024,
+        report.largest_free_block / 1024,
+        report.stack_used / 1024
+    );
+
+    let style = MonoTextStyle::new(&FONT_8X13, BinaryColor::Off);
+    Text::with_baseline(
+        &text,
+        OVERLAY_AREA.top_left + Point::new(4, 2),
+        style,
+        Baseline::Top,
+    )
+    .draw(frame)?;
+    Ok(())
+}
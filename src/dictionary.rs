@@ -0,0 +1,224 @@
+//! Offline dictionary lookup from a file on the SD card. Dictionaries are too large for memory, so
+//! the words are found with a binary search over a sorted index that is read one entry at a time.
+//!
+//! The file at [`PATH`] starts with a header:
+//! - 4 bytes magic `CPDI`
+//! - u32 number of words
+//! - u32 offset of the definitions from the start of the file
+//!
+//! The index follows with one [`ENTRY_SIZE`] byte entry per word sorted by the bytes of the word:
+//! - the lowercase word in UTF-8, padded with zeros to [`WORD_SIZE`] bytes
+//! - u32 offset of the definition from the start of the definitions
+//! - u32 length of the definition
+//!
+//! Each definition is UTF-8 text compressed with raw deflate. All integers are little endian.
+
+use alloc::string::String;
+use alloc::vec;
+use core::cmp::Ordering;
+
+use embedded_sdmmc::{Mode, RawFile};
+use miniz_oxide::inflate::{TINFLStatus, decompress_to_vec_with_limit};
+
+use crate::sd_card::{OpenError, ReadPriority, SdCard, VolumeError};
+
+const PATH: &str = "/DICT.BIN";
+const MAGIC: [u8; 4] = *b"CPDI";
+const HEADER_SIZE: u32 = 12;
+/// Longer words are not in the dictionary
+const WORD_SIZE: usize = 24;
+const ENTRY_SIZE: usize = WORD_SIZE + 8;
+/// More than fits into the popup, but keeps a broken file from taking up all memory
+const MAXIMUM_DEFINITION_SIZE: usize = 4096;
+/// Characters per line in the popup
+const LINE_LENGTH: usize = 40;
+/// Lines in the popup including the word
+const MAXIMUM_LINES: usize = 16;
+
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum LookUpError {
+    #[error("Failed to open dictionary file")]
+    Open(#[from] OpenError),
+    #[error("Failed to read dictionary file")]
+    Read(VolumeError),
+    #[error("File is not a dictionary")]
+    NotDictionary,
+    #[error("Dictionary file ends early")]
+    Truncated,
+    #[error("Definition is too large")]
+    TooLarge(u32),
+    #[error("Failed to decompress definition")]
+    Decompress(TINFLStatus),
+}
+
+/// Returns the definition of the word or None if the dictionary doesn't know it
+pub(crate) async fn look_up(
+    sd_card: &mut SdCard,
+    word: &str,
+) -> Result<Option<String>, LookUpError> {
+    let file = sd_card.open(PATH, Mode::ReadOnly).await?;
+    let result = look_up_in_file(sd_card, file, word).await;
+
+    if let Err(error) = sd_card.close(file).await {
+        defmt::warn!("Failed to close dictionary: {:?}", error);
+    }
+
+    result
+}
+
+async fn look_up_in_file(
+    sd_card: &mut SdCard,
+    file: RawFile,
+    word: &str,
+) -> Result<Option<String>, LookUpError> {
+    let mut header = [0; HEADER_SIZE as usize];
+    read_exact(sd_card, file, 0, &mut header).await?;
+    if header[..4] != MAGIC {
+        return Err(LookUpError::NotDictionary);
+    }
+
+    let count = u32_at(&header, 4);
+    let definitions = u32_at(&header, 8);
+
+    let Some(key) = key(word) else {
+        return Ok(None);
+    };
+
+    // Binary search over the entries in the file
+    let mut low = 0;
+    let mut high = count;
+    let mut entry = [0; ENTRY_SIZE];
+    while low < high {
+        let middle = low + (high - low) / 2;
+        // Can't truncate as the entry size is small
+        let offset = HEADER_SIZE + middle * ENTRY_SIZE as u32;
+        read_exact(sd_card, file, offset, &mut entry).await?;
+
+        match entry[..WORD_SIZE].cmp(&key) {
+            Ordering::Less => low = middle + 1,
+            Ordering::Greater => high = middle,
+            Ordering::Equal => {
+                let offset = u32_at(&entry, WORD_SIZE);
+                let length = u32_at(&entry, WORD_SIZE + 4);
+                return read_definition(sd_card, file, definitions + offset, length)
+                    .await
+                    .map(Some);
+            }
+        }
+    }
+
+    Ok(None)
+}
+
+async fn read_definition(
+    sd_card: &mut SdCard,
+    file: RawFile,
+    offset: u32,
+    length: u32,
+) -> Result<String, LookUpError> {
+    // Compressed text is smaller than the text itself, so the same maximum applies
+    if length as usize > MAXIMUM_DEFINITION_SIZE {
+        return Err(LookUpError::TooLarge(length));
+    }
+
+    // Can't truncate as the length is at most the maximum size
+    let mut compressed = vec![0; length as usize];
+    read_exact(sd_card, file, offset, &mut compressed).await?;
+
+    let text = decompress_to_vec_with_limit(&compressed, MAXIMUM_DEFINITION_SIZE)
+        .map_err(|error| LookUpError::Decompress(error.status))?;
+
+    Ok(String::from_utf8_lossy(&text).into_owned())
+}
+
+async fn read_exact(
+    sd_card: &mut SdCard,
+    file: RawFile,
+    offset: u32,
+    buffer: &mut [u8],
+) -> Result<(), LookUpError> {
+    let read = sd_card
+        .read_at(file, offset, buffer, ReadPriority::Interactive)
+        .await
+        .map_err(LookUpError::Read)?;
+
+    if read < buffer.len() {
+        return Err(LookUpError::Truncated);
+    }
+
+    Ok(())
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([
+        bytes[offset],
+        bytes[offset + 1],
+        bytes[offset + 2],
+        bytes[offset + 3],
+    ])
+}
+
+/// The word as it is stored in the index. None if it is too long to be in there.
+fn key(word: &str) -> Option<[u8; WORD_SIZE]> {
+    let word = word.to_lowercase();
+    if word.is_empty() || word.len() > WORD_SIZE {
+        return None;
+    }
+
+    let mut key = [0; WORD_SIZE];
+    key[..word.len()].copy_from_slice(word.as_bytes());
+    Some(key)
+}
+
+/// The word that contains the byte at the index of the text without surrounding punctuation. None
+/// if the index is between words.
+pub(crate) fn word_at(text: &str, index: usize) -> Option<&str> {
+    let is_word = |character: char| character.is_alphanumeric() || character == '\'';
+
+    let character = text.get(index..)?.chars().next()?;
+    if !is_word(character) {
+        return None;
+    }
+
+    let start = text[..index]
+        .char_indices()
+        .rev()
+        .find(|&(_, character)| !is_word(character))
+        .map_or(0, |(position, character)| position + character.len_utf8());
+    let end = text[index..]
+        .find(|character| !is_word(character))
+        .map_or(text.len(), |position| index + position);
+
+    // Quotes around the word are not part of it
+    Some(text[start..end].trim_matches('\''))
+}
+
+/// The word and its definition wrapped into lines for a [`Dialog`](crate::widget::Dialog). Cut off
+/// with an ellipsis if it is too long.
+pub(crate) fn popup_message(word: &str, definition: &str) -> String {
+    let mut lines = vec![String::from(word)];
+    for paragraph in definition.lines() {
+        let mut line = String::new();
+        for part in paragraph.split_whitespace() {
+            if !line.is_empty() && line.chars().count() + 1 + part.chars().count() > LINE_LENGTH {
+                lines.push(core::mem::take(&mut line));
+            }
+
+            if !line.is_empty() {
+                line.push(' ');
+            }
+            line.extend(part.chars().take(LINE_LENGTH));
+        }
+
+        lines.push(line);
+    }
+
+    if lines.len() > MAXIMUM_LINES {
+        lines.truncate(MAXIMUM_LINES);
+        if let Some(last) = lines.last_mut() {
+            last.push_str("...");
+        }
+    }
+
+    lines.join("\n")
+}
@@ -0,0 +1,489 @@
+//! The panel is shared by all tasks through [`SharedDisplay`], so the tasks showing something
+//! don't need to get hold of the display and keep it through a refresh.
+//!
+//! A copy of what the panel shows is kept next to the display. Frames that are submitted instead of
+//! shown with a refresh mode are compared with it, so the cheapest refresh for what changed can be
+//! picked. Frames are shown within the future of the task that shows them, so the frame stays
+//! borrowed for as long as it is sent, even if that future is dropped half way.
+//!
+//! Frames shown with [`ShowMode::Auto`] get their mode picked here as well, from the state of the
+//! panel and how much changed.
+//!
+//! The copy also lets the shortcuts refresh what is shown and save it as a screenshot, and the
+//! status bar task update the status bar, without the screen that drew it. These commands don't
+//! borrow anything from their sender and are carried out by a task of their own, so they finish
+//! even if the sender stops waiting, e.g. on shutdown. They are handled one after another in the
+//! order they were sent.
+//!
+//! Settings, statistics and the custom waveform are still reached through [`SharedDisplay::lock`],
+//! which waits for the refresh in progress.
+
+use alloc::string::String;
+use core::ops::Deref;
+
+use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+use embassy_sync::channel::Channel;
+use embassy_sync::mutex::{Mutex, MutexGuard};
+use embassy_sync::signal::Signal;
+use embassy_time::{Duration, Instant};
+
+use crate::eink_display::{
+    Difference, DisplayError, DrawError, EnterDeepSleepError, Frame, RefreshMode, Region,
+    Temperature,
+};
+use crate::screenshot::{self, ScreenshotError};
+use crate::snapshot::{self, SaveSnapshotError};
+use crate::spi;
+use crate::status_bar::{self, StatusBar};
+use crate::{Display, SharedSdCard};
+
+type SpiError = <spi::Device<'static> as embedded_hal_async::spi::ErrorType>::Error;
+
+/// Changes within a region of at most this share of the panel only update the region
+const PARTIAL_AREA_DIVISOR: u32 = 4;
+/// Changes to at least this share of the buffer bytes get a full refresh, as they leave the most
+/// ghosting behind, e.g. a new image. Three quarters. Not in turbo mode, where the ghosting is
+/// accepted.
+const FULL_CHANGE_NUMERATOR: usize = 3;
+const FULL_CHANGE_DENOMINATOR: usize = 4;
+/// Ghosting also builds up with time and not only with the number of refreshes, so frames shown
+/// with the automatic mode get a full refresh after this long without one
+const MAXIMUM_FULL_REFRESH_AGE: Duration = Duration::from_secs(30 * 60);
+
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum ShownFrameError {
+    #[error("What the panel shows is not known, e.g. after a failed refresh")]
+    Unknown,
+    #[error("Failed to refresh display")]
+    Display(DisplayError<SpiError>),
+    #[error("No SD card to save the screenshot to")]
+    NoCard,
+    #[error("Failed to save screenshot")]
+    Screenshot(#[from] ScreenshotError),
+    #[error("Failed to keep snapshot")]
+    Snapshot(#[from] SaveSnapshotError),
+    #[error("Failed to draw over what the panel shows: {0:?}")]
+    Draw(DrawError),
+}
+
+/// How a frame is shown with [`SharedDisplay::show`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub(crate) enum ShowMode {
+    /// The refresh mode is picked from the state of the panel and what changed
+    Auto,
+    /// The refresh mode or a more thorough one the driver upgrades it to
+    Exact(RefreshMode),
+}
+
+/// What the display task is asked to do
+enum DisplayCommand {
+    /// Shows what the panel shows again with the refresh mode, e.g. to clear ghosting
+    RefreshShown { mode: RefreshMode },
+    /// Saves what the panel shows as a screenshot on the SD card
+    SaveShown { sd_card: &'static SharedSdCard },
+    /// Keeps what the panel shows in RTC memory to bring it back after deep sleep
+    KeepShown,
+    /// Draws the status over the status bar the panel shows, if it shows one
+    UpdateStatusBar { shown: StatusBar, status: StatusBar },
+    /// Turns the panel off before deep sleep
+    Sleep,
+}
+
+enum Outcome {
+    RefreshedShown(Result<(), ShownFrameError>),
+    SavedShown(Result<String, ShownFrameError>),
+    KeptShown(Result<(), ShownFrameError>),
+    UpdatedStatusBar(Result<(), ShownFrameError>),
+    Slept(Result<(), EnterDeepSleepError<SpiError>>),
+}
+
+/// Tells the outcomes of the commands apart, so an outcome whose sender stopped waiting for it
+/// doesn't reach the sender of the next command
+type CommandId = u32;
+
+/// The display for all tasks. Commands without a frame are sent to the display task.
+pub(crate) struct SharedDisplay {
+    /// Locked before the display by everything that needs both
+    shadow: Mutex<NoopRawMutex, Shadow>,
+    display: Mutex<NoopRawMutex, Display>,
+    /// Held while sending a command until its outcome is back. Counts the commands sent.
+    sender: Mutex<NoopRawMutex, CommandId>,
+    commands: Channel<NoopRawMutex, (CommandId, DisplayCommand), 1>,
+    outcomes: Signal<NoopRawMutex, (CommandId, Outcome)>,
+}
+
+impl SharedDisplay {
+    /// The shadow frame becomes the copy of what the panel shows
+    pub(crate) fn new(display: Display, shadow: &'static mut Frame) -> Self {
+        Self {
+            shadow: Mutex::new(Shadow {
+                frame: shadow,
+                // The first frame was shown before the display was shared
+                is_valid: false,
+            }),
+            display: Mutex::new(display),
+            sender: Mutex::new(0),
+            commands: Channel::new(),
+            outcomes: Signal::new(),
+        }
+    }
+
+    /// A handle to pass to the tasks that show something
+    pub(crate) fn handle(&'static self) -> DisplayHandle {
+        DisplayHandle(self)
+    }
+
+    /// The display for settings and statistics. Waits for the refresh in progress.
+    pub(crate) async fn lock(&self) -> MutexGuard<'_, NoopRawMutex, Display> {
+        self.display.lock().await
+    }
+
+    /// Shows the frame and returns the refresh mode that was used, see [`Display::display`]
+    pub(crate) async fn show(
+        &self,
+        mode: ShowMode,
+        frame: &Frame,
+    ) -> Result<RefreshMode, DisplayError<SpiError>> {
+        let mut shadow = self.shadow.lock().await;
+        let mut display = self.display.lock().await;
+        let mode = resolve(mode, &display, &shadow, frame);
+        // Stays unknown if the refresh is dropped half way
+        shadow.is_valid = false;
+        let result = display.display(mode, frame).await;
+        shadow.update(frame, result.is_ok());
+        result
+    }
+
+    /// Updates only the region of the panel, see [`Display::display_region`]
+    pub(crate) async fn show_region(
+        &self,
+        frame: &Frame,
+        region: Region,
+    ) -> Result<(), DisplayError<SpiError>> {
+        let mut shadow = self.shadow.lock().await;
+        let mut display = self.display.lock().await;
+        // Stays unknown if the refresh is dropped half way
+        let was_valid = core::mem::replace(&mut shadow.is_valid, false);
+        let started = Instant::now();
+        let result = display.display_region(frame, region).await;
+        // A full refresh in between, e.g. as the screen was off, showed the whole frame
+        let is_whole_shown = display
+            .since_full_refresh()
+            .is_some_and(|age| age <= started.elapsed());
+        if is_whole_shown {
+            shadow.update(frame, result.is_ok());
+        } else if result.is_ok() {
+            // The rest of the panel can differ from the frame
+            shadow.frame.copy_region_from(frame, region);
+            shadow.is_valid = was_valid;
+        }
+        result
+    }
+
+    /// Shows the frame with the cheapest refresh for what changed since the last frame. Nothing is
+    /// refreshed if nothing changed.
+    pub(crate) async fn submit(&self, frame: &Frame) -> Result<(), DisplayError<SpiError>> {
+        let mut shadow = self.shadow.lock().await;
+        let mut display = self.display.lock().await;
+        submit(&mut display, &mut shadow, frame, false).await
+    }
+
+    /// Shows the frame like [`Self::submit`], but small changes like a cursor moving through a
+    /// menu are shown with the animation waveform, see [`Display::display_animation`]. The next
+    /// refresh of the whole panel, e.g. after leaving the menu, clears the ghosting it leaves
+    /// behind.
+    pub(crate) async fn animate(&self, frame: &Frame) -> Result<(), DisplayError<SpiError>> {
+        let mut shadow = self.shadow.lock().await;
+        let mut display = self.display.lock().await;
+        submit(&mut display, &mut shadow, frame, true).await
+    }
+
+    /// Shows what the panel shows again with the refresh mode without a frame from the caller
+    pub(crate) async fn refresh_shown(&self, mode: RefreshMode) -> Result<(), ShownFrameError> {
+        match self.send(DisplayCommand::RefreshShown { mode }).await {
+            Outcome::RefreshedShown(result) => result,
+            _ => unreachable!("Outcome of another command"),
+        }
+    }
+
+    /// Saves what the panel shows as a screenshot and returns its path
+    pub(crate) async fn save_shown(
+        &self,
+        sd_card: &'static SharedSdCard,
+    ) -> Result<String, ShownFrameError> {
+        match self.send(DisplayCommand::SaveShown { sd_card }).await {
+            Outcome::SavedShown(result) => result,
+            _ => unreachable!("Outcome of another command"),
+        }
+    }
+
+    /// Keeps what the panel shows in RTC memory, so it comes back right away after deep sleep, see
+    /// [`snapshot::restore`]
+    pub(crate) async fn keep_shown(&self) -> Result<(), ShownFrameError> {
+        match self.send(DisplayCommand::KeepShown).await {
+            Outcome::KeptShown(result) => result,
+            _ => unreachable!("Outcome of another command"),
+        }
+    }
+
+    /// Updates the status bar on the panel from the shown status to the new one. Screens without
+    /// the status bar or with another one, e.g. drawn before the status changed, are left alone.
+    pub(crate) async fn update_status_bar(
+        &self,
+        shown: StatusBar,
+        status: StatusBar,
+    ) -> Result<(), ShownFrameError> {
+        match self
+            .send(DisplayCommand::UpdateStatusBar { shown, status })
+            .await
+        {
+            Outcome::UpdatedStatusBar(result) => result,
+            _ => unreachable!("Outcome of another command"),
+        }
+    }
+
+    /// Turns the panel off before deep sleep
+    pub(crate) async fn sleep(&self) -> Result<(), EnterDeepSleepError<SpiError>> {
+        match self.send(DisplayCommand::Sleep).await {
+            Outcome::Slept(result) => result,
+            _ => unreachable!("Outcome of another command"),
+        }
+    }
+
+    async fn send(&self, command: DisplayCommand) -> Outcome {
+        let mut sent = self.sender.lock().await;
+        *sent = sent.wrapping_add(1);
+        let id = *sent;
+        self.commands.send((id, command)).await;
+        loop {
+            // A sender that stopped waiting leaves the outcome of its command behind
+            let (outcome_id, outcome) = self.outcomes.wait().await;
+            if outcome_id == id {
+                return outcome;
+            }
+        }
+    }
+}
+
+/// The display for a task of its own. Copies refer to the same [`SharedDisplay`], so any number of
+/// tasks can show frames at the same time and the display task shows them one after another.
+#[derive(Clone, Copy)]
+pub(crate) struct DisplayHandle(&'static SharedDisplay);
+
+impl Deref for DisplayHandle {
+    type Target = SharedDisplay;
+
+    fn deref(&self) -> &Self::Target {
+        self.0
+    }
+}
+
+/// Copy of what the panel shows
+struct Shadow {
+    frame: &'static mut Frame,
+    /// Cleared when the panel shows something the copy doesn't have, e.g. after a failed refresh
+    is_valid: bool,
+}
+
+impl Shadow {
+    /// Takes over the frame after it was shown. A failed refresh leaves the panel unknown.
+    fn update(&mut self, frame: &Frame, is_shown: bool) {
+        if is_shown {
+            self.frame.copy_from(frame);
+        }
+        self.is_valid = is_shown;
+    }
+}
+
+/// How a submitted frame is shown
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+enum Update {
+    Unchanged,
+    Region(Region),
+    /// Fast refresh of the whole panel that only sends the changed region, e.g. a page turn
+    Changed(Region),
+    Whole(RefreshMode),
+}
+
+impl Update {
+    fn choose(difference: Option<Difference>, is_turbo: bool) -> Self {
+        let Some(difference) = difference else {
+            return Self::Unchanged;
+        };
+
+        let region = difference.region;
+        let area = u32::from(region.width) * u32::from(region.height);
+        let panel = u32::from(Region::FULL.width) * u32::from(Region::FULL.height);
+        if !is_turbo && is_large_change(difference) {
+            Self::Whole(RefreshMode::Full)
+        } else if area * PARTIAL_AREA_DIVISOR <= panel {
+            Self::Region(region)
+        } else {
+            Self::Changed(region)
+        }
+    }
+}
+
+/// Whether the change leaves so much ghosting behind that it needs a full refresh
+fn is_large_change(difference: Difference) -> bool {
+    difference.changed_bytes * FULL_CHANGE_DENOMINATOR >= Frame::BUFFER_SIZE * FULL_CHANGE_NUMERATOR
+}
+
+/// Picks the refresh for a frame shown with [`ShowMode::Auto`]. Exact modes are kept, and the
+/// driver can still upgrade the picked one.
+fn resolve(mode: ShowMode, display: &Display, shadow: &Shadow, frame: &Frame) -> RefreshMode {
+    if let ShowMode::Exact(mode) = mode {
+        return mode;
+    }
+
+    // Turning the screen on takes at least a half refresh
+    if display.needs_whole_frame() {
+        return RefreshMode::HalfRefresh;
+    }
+
+    let is_cold = display.temperature().is_some_and(Temperature::is_cold);
+    let is_full_refresh_due = display
+        .since_full_refresh()
+        .is_none_or(|age| age >= MAXIMUM_FULL_REFRESH_AGE);
+    // Without a copy of what the panel shows, the change is not known and not counted
+    let is_large_change = !display.is_turbo()
+        && shadow.is_valid
+        && frame.difference(shadow.frame).is_some_and(is_large_change);
+
+    if is_cold || is_full_refresh_due || is_large_change {
+        RefreshMode::Full
+    } else {
+        RefreshMode::Fast
+    }
+}
+
+/// Carries out the commands for the display that don't come with a frame
+#[embassy_executor::task]
+pub(crate) async fn run(shared: &'static SharedDisplay) {
+    loop {
+        let (id, command) = shared.commands.receive().await;
+        let outcome = match command {
+            // Tasks that hold the card can wait for the display, so the card is taken before the
+            // copy of what the panel shows
+            DisplayCommand::SaveShown { sd_card } => {
+                Outcome::SavedShown(save_shown(&shared.shadow, sd_card).await)
+            }
+            DisplayCommand::KeepShown => {
+                let shadow = shared.shadow.lock().await;
+                Outcome::KeptShown(if shadow.is_valid {
+                    snapshot::keep(shadow.frame).map_err(ShownFrameError::from)
+                } else {
+                    Err(ShownFrameError::Unknown)
+                })
+            }
+            DisplayCommand::RefreshShown { mode } => {
+                let mut shadow = shared.shadow.lock().await;
+                let mut display = shared.display.lock().await;
+                let result = if shadow.is_valid {
+                    display
+                        .display(mode, shadow.frame)
+                        .await
+                        .map(|_| ())
+                        .map_err(ShownFrameError::Display)
+                } else {
+                    Err(ShownFrameError::Unknown)
+                };
+                // A failed refresh leaves the panel unknown
+                shadow.is_valid = result.is_ok();
+                Outcome::RefreshedShown(result)
+            }
+            DisplayCommand::UpdateStatusBar { shown, status } => {
+                let mut shadow = shared.shadow.lock().await;
+                let mut display = shared.display.lock().await;
+                Outcome::UpdatedStatusBar(
+                    update_status_bar(&mut display, &mut shadow, shown, status).await,
+                )
+            }
+            DisplayCommand::Sleep => {
+                let mut shadow = shared.shadow.lock().await;
+                let mut display = shared.display.lock().await;
+                shadow.is_valid = false;
+                Outcome::Slept(display.enter_deep_sleep().await)
+            }
+        };
+
+        shared.outcomes.signal((id, outcome));
+    }
+}
+
+/// Shows what changed since the shadow with the cheapest refresh. Small changes get the animation
+/// waveform if animated.
+async fn submit(
+    display: &mut Display,
+    shadow: &mut Shadow,
+    frame: &Frame,
+    is_animated: bool,
+) -> Result<(), DisplayError<SpiError>> {
+    let update = if shadow.is_valid && !display.needs_whole_frame() {
+        Update::choose(frame.difference(shadow.frame), display.is_turbo())
+    } else {
+        Update::Whole(RefreshMode::Fast)
+    };
+    // Stays unknown if the refresh is dropped half way
+    shadow.is_valid = false;
+
+    let result = match update {
+        Update::Unchanged => Ok(()),
+        Update::Region(region) if is_animated => display.display_animation(frame, region).await,
+        Update::Region(region) => display.display_region(frame, region).await,
+        Update::Changed(region) => display
+            .display_changed(RefreshMode::Fast, frame, region)
+            .await
+            .map(|_| ()),
+        Update::Whole(mode) => display.display(mode, frame).await.map(|_| ()),
+    };
+    shadow.update(frame, result.is_ok());
+    result
+}
+
+/// Draws the status over the status bar in the copy of what the panel shows and updates only its
+/// region
+async fn update_status_bar(
+    display: &mut Display,
+    shadow: &mut Shadow,
+    shown: StatusBar,
+    status: StatusBar,
+) -> Result<(), ShownFrameError> {
+    // Waking the panel for the status bar would undo turning it off
+    if display.is_asleep() {
+        return Ok(());
+    }
+
+    if !shadow.is_valid {
+        return Err(ShownFrameError::Unknown);
+    }
+
+    let is_drawn =
+        status_bar::draw_over(shadow.frame, &shown, &status).map_err(ShownFrameError::Draw)?;
+    if !is_drawn {
+        return Ok(());
+    }
+
+    let result = display
+        .display_region(shadow.frame, status_bar::REGION)
+        .await;
+    // A failed refresh leaves the panel unknown
+    shadow.is_valid = result.is_ok();
+    result.map_err(ShownFrameError::Display)
+}
+
+async fn save_shown(
+    shadow: &Mutex<NoopRawMutex, Shadow>,
+    sd_card: &SharedSdCard,
+) -> Result<String, ShownFrameError> {
+    let mut sd_card = sd_card.lock().await;
+    let shadow = shadow.lock().await;
+    if !shadow.is_valid {
+        return Err(ShownFrameError::Unknown);
+    }
+
+    match sd_card.as_mut() {
+        Some(sd_card) => Ok(screenshot::save(shadow.frame, sd_card).await?),
+        None => Err(ShownFrameError::NoCard),
+    }
+}
@@ -0,0 +1,24 @@
+//! Loads custom waveforms from the SD card, see [`Lut`] for the format
+
+use crate::sd_card::{ReadFileError, ReadPriority, SdCard};
+
+pub(crate) use crustpoint_core::lut::{Lut, ParseLutError};
+
+/// Text files have some overhead for the formatting and comments
+const MAXIMUM_FILE_SIZE: u32 = 4096;
+
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum LoadLutError {
+    #[error("Failed to read LUT file")]
+    Read(#[from] ReadFileError),
+    #[error("Failed to parse LUT file: {0:?}")]
+    Parse(ParseLutError),
+}
+
+pub(crate) async fn load_lut(sd_card: &mut SdCard, path: &str) -> Result<Lut, LoadLutError> {
+    let bytes = sd_card
+        .read_file(path, MAXIMUM_FILE_SIZE, ReadPriority::Interactive)
+        .await?;
+
+    Lut::parse(&bytes).map_err(LoadLutError::Parse)
+}
@@ -0,0 +1,84 @@
+//! Hooks the driver into the firmware. Its transfers are supervised, waiting for the busy line goes
+//! through the timeouts that keep metrics, and other tasks learn when the panel refreshes.
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use crustpoint_display::{Command, Monitor, PanelState};
+use defmt::info;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::watch::{Receiver, Watch};
+use embassy_time::{Duration, TimeoutError};
+
+#[cfg(feature = "display-trace")]
+use crate::eink_display::trace;
+use crate::event_bus;
+use crate::event_log::{self, Event};
+use crate::supervisor::{self, Task};
+use crate::timeout::{self, Operation};
+use crate::verbosity::{self, Level, Module};
+
+/// Set while the panel is refreshing so other subsystems can back off to keep the UI responsive
+static IS_REFRESHING: AtomicBool = AtomicBool::new(false);
+
+pub(crate) fn is_refreshing() -> bool {
+    IS_REFRESHING.load(Ordering::Relaxed)
+}
+
+/// Tasks that can wait for changes of the panel state at the same time
+const PANEL_STATE_RECEIVERS: usize = 2;
+
+/// The same as [`IS_REFRESHING`] for tasks that want to wait for a change, e.g. to show that the
+/// device is busy during a full refresh that takes seconds
+static PANEL_STATE: Watch<CriticalSectionRawMutex, PanelState, PANEL_STATE_RECEIVERS> =
+    Watch::new_with(PanelState::Idle);
+
+/// None if all receivers are taken
+pub(crate) fn panel_state_receiver()
+-> Option<Receiver<'static, CriticalSectionRawMutex, PanelState, PANEL_STATE_RECEIVERS>> {
+    PANEL_STATE.receiver()
+}
+
+pub(crate) struct FirmwareMonitor;
+
+impl Monitor for FirmwareMonitor {
+    type Transfer = supervisor::Watch;
+
+    fn start_transfer(&self) -> supervisor::Watch {
+        supervisor::watch(Task::Display)
+    }
+
+    fn wait_for_busy<F: Future>(
+        &self,
+        duration: Duration,
+        future: F,
+    ) -> impl Future<Output = Result<F::Output, TimeoutError>> {
+        timeout::run(Operation::DisplayBusy, duration, future)
+    }
+
+    fn command(&self, command: Command) {
+        if verbosity::is_enabled(Module::Display, Level::Debug) {
+            info!("Sending command: {:?}", command);
+        }
+        #[cfg(feature = "display-trace")]
+        trace::record_command(command as u8);
+    }
+
+    fn data(&self, data: &[u8]) {
+        if verbosity::is_enabled(Module::Display, Level::Trace) {
+            info!("Sending {} bytes of data", data.len());
+        }
+        #[cfg(feature = "display-trace")]
+        trace::record_data(data);
+    }
+
+    fn panel_state(&self, state: PanelState) {
+        IS_REFRESHING.store(state != PanelState::Idle, Ordering::Relaxed);
+        PANEL_STATE.sender().send(state);
+    }
+
+    fn recovered(&self) {
+        // Lets the UI tell the reader why the screen flashed
+        event_bus::publish(event_bus::Event::DisplayRecovered);
+        event_log::record(Event::DisplayRecovered);
+    }
+}
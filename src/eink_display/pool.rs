@@ -0,0 +1,15 @@
+use static_cell::StaticCell;
+
+use crate::eink_display::Frame;
+use crate::memory::FRAME_POOL_SIZE;
+
+/// Frames are most of the RAM and too large for the stack and the futures of the tasks, so they
+/// live in statics
+static POOL: [StaticCell<Frame>; FRAME_POOL_SIZE] = [const { StaticCell::new() }; FRAME_POOL_SIZE];
+
+/// A white frame from the static pool for a task that keeps it for the rest of the run. None once
+/// all frames of the pool are taken.
+pub(crate) fn take_frame() -> Option<&'static mut Frame> {
+    let frame = POOL.iter().find_map(StaticCell::try_uninit)?;
+    Some(Frame::initialize(frame))
+}
@@ -0,0 +1,116 @@
+//! Several subsystems updating at about the same time, like the status bar and a progress strip,
+//! would each flash the panel right after another. Their refresh requests are collected for a short
+//! window and merged into a single refresh instead.
+
+use core::cell::RefCell;
+
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::signal::Signal;
+use embassy_time::{Duration, Timer};
+
+use crate::display_task::ShowMode;
+use crate::eink_display::{RefreshMode, Region};
+
+/// How long to wait for more requests after the first one
+const COALESCE_WINDOW: Duration = Duration::from_millis(200);
+
+/// What needs to be shown of the frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub(crate) struct RefreshRequest {
+    /// None for the whole frame
+    pub(crate) region: Option<Region>,
+    pub(crate) mode: ShowMode,
+}
+
+impl RefreshRequest {
+    /// Combines both requests into one that covers both regions with the more thorough mode
+    fn merge(self, other: Self) -> Self {
+        let region = match (self.region, other.region) {
+            (Some(first), Some(second)) => Some(bounding_box(first, second)),
+            _ => None,
+        };
+
+        Self {
+            region,
+            mode: stronger(self.mode, other.mode),
+        }
+    }
+}
+
+fn bounding_box(first: Region, second: Region) -> Region {
+    let x = first.x.min(second.x);
+    let y = first.y.min(second.y);
+    let right = (first.x + first.width).max(second.x + second.width);
+    let bottom = (first.y + first.height).max(second.y + second.height);
+    Region {
+        x,
+        y,
+        width: right - x,
+        height: bottom - y,
+    }
+}
+
+fn stronger(first: ShowMode, second: ShowMode) -> ShowMode {
+    let rank = |mode| match mode {
+        ShowMode::Exact(RefreshMode::Fast) => 0,
+        // Can turn out to be any mode, but is at least a fast refresh
+        ShowMode::Auto => 1,
+        ShowMode::Exact(RefreshMode::HalfRefresh) => 2,
+        ShowMode::Exact(RefreshMode::Full) => 3,
+    };
+
+    if rank(second) > rank(first) {
+        second
+    } else {
+        first
+    }
+}
+
+pub(crate) struct RefreshQueue {
+    /// Merged requests that were not taken yet
+    pending: Mutex<CriticalSectionRawMutex, RefCell<Option<RefreshRequest>>>,
+    /// Wakes the render loop when the first request comes in
+    requested: Signal<CriticalSectionRawMutex, ()>,
+}
+
+impl RefreshQueue {
+    pub(crate) const fn new() -> Self {
+        Self {
+            pending: Mutex::new(RefCell::new(None)),
+            requested: Signal::new(),
+        }
+    }
+
+    /// Adds the request without waiting for the refresh
+    pub(crate) fn request(&self, request: RefreshRequest) {
+        self.pending.lock(|pending| {
+            let mut pending = pending.borrow_mut();
+            *pending = Some(match *pending {
+                Some(previous) => previous.merge(request),
+                None => request,
+            });
+        });
+        self.requested.signal(());
+    }
+
+    /// Waits for a request and returns it merged with those that came in shortly after. Can be
+    /// cancelled without losing requests.
+    pub(crate) async fn next(&self) -> RefreshRequest {
+        while self.pending.lock(|pending| pending.borrow().is_none()) {
+            self.requested.wait().await;
+        }
+
+        Timer::after(COALESCE_WINDOW).await;
+
+        loop {
+            if let Some(request) = self.pending.lock(|pending| pending.borrow_mut().take()) {
+                self.requested.reset();
+                return request;
+            }
+
+            // Only happens if another loop took the requests during the window
+            self.requested.wait().await;
+        }
+    }
+}
@@ -0,0 +1,138 @@
+//! Records the bytes sent to the controller into a ring buffer so that waveform and initialization
+//! experiments can be compared against captures from the vendor SDK.
+
+use core::cell::RefCell;
+
+use defmt::info;
+use embassy_sync::blocking_mutex::{Mutex, raw::CriticalSectionRawMutex};
+
+const CAPACITY: usize = 4096;
+/// Entries taken out of the trace at once, so interrupts are only held off for a short copy
+pub(crate) const BATCH_SIZE: usize = 32;
+/// Frame data is too large to keep, so only the start of each data transfer is recorded
+const MAXIMUM_DATA_BYTES_PER_TRANSFER: usize = 16;
+
+/// Kept at 4 bytes, as the trace holds thousands of them
+#[derive(Debug, Clone, Copy, defmt::Format)]
+pub(crate) enum Entry {
+    Command(u8),
+    Data(u8),
+    /// The number of bytes at the end of a data transfer that were not recorded. Saturates for
+    /// transfers larger than 64 KiB, which is more than a whole frame.
+    Truncated(u16),
+}
+
+impl Entry {
+    /// An entry to fill batches with before they are taken
+    pub(crate) const EMPTY: Self = Self::Command(0);
+}
+
+struct Trace {
+    entries: [Entry; CAPACITY],
+    /// Index the next entry is written to
+    next: usize,
+    length: usize,
+    /// Entries that got overwritten since the last dump
+    overwritten: usize,
+}
+
+impl Trace {
+    const fn new() -> Self {
+        Self {
+            entries: [Entry::Command(0); CAPACITY],
+            next: 0,
+            length: 0,
+            overwritten: 0,
+        }
+    }
+
+    fn push(&mut self, entry: Entry) {
+        self.entries[self.next] = entry;
+        self.next = (self.next + 1) % CAPACITY;
+        if self.length == CAPACITY {
+            self.overwritten += 1;
+        } else {
+            self.length += 1;
+        }
+    }
+
+    /// Moves the oldest entries into the batch and returns how many were moved
+    fn take(&mut self, batch: &mut [Entry]) -> usize {
+        let start = (self.next + CAPACITY - self.length) % CAPACITY;
+        let count = self.length.min(batch.len());
+        for (offset, entry) in batch[..count].iter_mut().enumerate() {
+            *entry = self.entries[(start + offset) % CAPACITY];
+        }
+
+        self.length -= count;
+        count
+    }
+}
+
+/// Bytes the trace takes up in RAM for the budget of the build profile
+pub(crate) const SIZE: usize = size_of::<Trace>();
+
+/// Static to keep it off the stack and to be able to dump it from anywhere
+static TRACE: Mutex<CriticalSectionRawMutex, RefCell<Trace>> =
+    Mutex::new(RefCell::new(Trace::new()));
+
+pub(super) fn record_command(command: u8) {
+    TRACE.lock(|trace| trace.borrow_mut().push(Entry::Command(command)));
+}
+
+pub(super) fn record_data(data: &[u8]) {
+    TRACE.lock(|trace| {
+        let mut trace = trace.borrow_mut();
+        let (recorded, truncated) = data.split_at(data.len().min(MAXIMUM_DATA_BYTES_PER_TRANSFER));
+        for byte in recorded {
+            trace.push(Entry::Data(*byte));
+        }
+
+        if !truncated.is_empty() {
+            let length = u16::try_from(truncated.len()).unwrap_or(u16::MAX);
+            trace.push(Entry::Truncated(length));
+        }
+    });
+}
+
+/// The number of entries recorded and not taken yet
+pub(crate) fn len() -> usize {
+    TRACE.lock(|trace| trace.borrow().length)
+}
+
+/// The number of entries that got overwritten before they were taken and resets it
+pub(crate) fn take_overwritten() -> usize {
+    TRACE.lock(|trace| core::mem::take(&mut trace.borrow_mut().overwritten))
+}
+
+/// Takes out the oldest entries that fit into the batch. Only holds the lock for the copy, so
+/// callers can log or print the entries without blocking interrupts.
+pub(crate) fn take_batch(batch: &mut [Entry; BATCH_SIZE]) -> &[Entry] {
+    let count = TRACE.lock(|trace| trace.borrow_mut().take(batch));
+    &batch[..count]
+}
+
+/// Logs all recorded entries from oldest to newest and removes them
+pub(crate) fn dump() {
+    info!(
+        "Display trace: {} entries, {} overwritten",
+        len(),
+        take_overwritten()
+    );
+
+    let mut batch = [Entry::EMPTY; BATCH_SIZE];
+    loop {
+        let entries = take_batch(&mut batch);
+        if entries.is_empty() {
+            break;
+        }
+
+        for entry in entries {
+            match entry {
+                Entry::Command(command) => info!("C {=u8:#04x}", command),
+                Entry::Data(data) => info!("D {=u8:#04x}", data),
+                Entry::Truncated(length) => info!("D ... {} more bytes", length),
+            }
+        }
+    }
+}
@@ -0,0 +1,106 @@
+//! Title, author and cover of a book from its package document, for the library to show books
+//! without opening them
+
+use alloc::string::String;
+
+use embedded_sdmmc::Mode;
+
+use crate::epub::xml::{self, Tags};
+use crate::epub::zip::{Archive, ZipError};
+use crate::epub::{CONTAINER_PATH, directory, resolve};
+use crate::sd_card::{OpenError, SdCard};
+
+/// Package documents of large books with long tables of contents can take a few dozen kilobytes
+const MAXIMUM_PACKAGE_SIZE: u32 = 32 * 1024;
+
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum MetadataError {
+    #[error("Failed to open book")]
+    Open(#[from] OpenError),
+    #[error("Failed to read book archive")]
+    Zip(#[from] ZipError),
+    #[error("Book has no package document")]
+    NoPackage,
+    #[error("Package document is not UTF-8")]
+    NotUtf8,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub(crate) struct Metadata {
+    pub(crate) title: Option<String>,
+    pub(crate) author: Option<String>,
+    /// Path of the cover image within the archive
+    pub(crate) cover: Option<String>,
+}
+
+pub(crate) async fn read(sd_card: &mut SdCard, path: &str) -> Result<Metadata, MetadataError> {
+    let file = sd_card.open(path, Mode::ReadOnly).await?;
+    let result = match Archive::open(sd_card, file).await {
+        Ok(archive) => read_package(sd_card, &archive).await,
+        Err(error) => Err(error.into()),
+    };
+
+    if let Err(error) = sd_card.close(file).await {
+        defmt::warn!("Failed to close book: {:?}", error);
+    }
+
+    result
+}
+
+async fn read_package(sd_card: &mut SdCard, archive: &Archive) -> Result<Metadata, MetadataError> {
+    let entry = archive
+        .find(CONTAINER_PATH)
+        .ok_or(MetadataError::NoPackage)?;
+    let container = archive.read(sd_card, entry, MAXIMUM_PACKAGE_SIZE).await?;
+    let mut package_path = None;
+    Tags::new().push(&container, |tag| {
+        if package_path.is_none() && xml::name(tag) == "rootfile" {
+            package_path = xml::attribute(tag, "full-path").map(|path| resolve("", path));
+        }
+    });
+    drop(container);
+
+    let package_path = package_path.ok_or(MetadataError::NoPackage)?;
+    let entry = archive
+        .find(&package_path)
+        .ok_or(MetadataError::NoPackage)?;
+    let package = archive.read(sd_card, entry, MAXIMUM_PACKAGE_SIZE).await?;
+    let package = core::str::from_utf8(&package).map_err(|_| MetadataError::NotUtf8)?;
+
+    // EPUB 2 names the cover item in a meta element and EPUB 3 marks it in the manifest
+    let mut cover_id = None;
+    let mut cover = None;
+    let base = directory(&package_path);
+    Tags::new().push(package.as_bytes(), |tag| match xml::name(tag) {
+        "meta" if xml::attribute(tag, "name") == Some("cover") => {
+            cover_id = xml::attribute(tag, "content").map(String::from);
+        }
+        "item" => {
+            let is_cover = xml::attribute(tag, "properties")
+                .is_some_and(|properties| properties.split(' ').any(|name| name == "cover-image"));
+            if is_cover && let Some(href) = xml::attribute(tag, "href") {
+                cover = Some(resolve(base, href));
+            }
+        }
+        _ => {}
+    });
+
+    if cover.is_none()
+        && let Some(id) = cover_id
+    {
+        Tags::new().push(package.as_bytes(), |tag| {
+            if xml::name(tag) == "item"
+                && xml::attribute(tag, "id") == Some(&id)
+                && let Some(href) = xml::attribute(tag, "href")
+            {
+                cover = Some(resolve(base, href));
+            }
+        });
+    }
+
+    Ok(Metadata {
+        title: xml::text(package, "title").filter(|title| !title.is_empty()),
+        author: xml::text(package, "creator").filter(|author| !author.is_empty()),
+        cover,
+    })
+}
@@ -0,0 +1,65 @@
+//! EPUB books are ZIP archives of XHTML chapters, images and a package document that lists the
+//! files and their reading order. The container file at [`CONTAINER_PATH`] points to the package
+//! document.
+
+pub(crate) mod metadata;
+pub(crate) mod validation;
+pub(crate) mod xml;
+pub(crate) mod zip;
+
+use alloc::string::String;
+use alloc::vec::Vec;
+
+const CONTAINER_PATH: &str = "META-INF/container.xml";
+
+/// Directory part of a path within the archive, empty for files at the top
+fn directory(path: &str) -> &str {
+    path.rsplit_once('/').map_or("", |(directory, _)| directory)
+}
+
+/// Path within the archive of a reference relative to the directory, e.g. `OEBPS/images/a.png` for
+/// `../images/a.png` in `OEBPS/text`. Fragments are removed and percent-encoded characters decoded.
+fn resolve(directory: &str, reference: &str) -> String {
+    let reference = reference.split('#').next().unwrap_or_default();
+
+    let mut parts: Vec<&str> = directory
+        .split('/')
+        .filter(|part| !part.is_empty())
+        .collect();
+    for part in reference.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => {
+                parts.pop();
+            }
+            _ => parts.push(part),
+        }
+    }
+
+    percent_decode(&parts.join("/"))
+}
+
+fn percent_decode(text: &str) -> String {
+    let mut bytes = Vec::with_capacity(text.len());
+    let mut rest = text.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        let decoded = (byte == b'%')
+            .then(|| tail.get(..2))
+            .flatten()
+            .and_then(|hex| core::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+
+        match decoded {
+            Some(decoded) => {
+                bytes.push(decoded);
+                rest = &tail[2..];
+            }
+            None => {
+                bytes.push(byte);
+                rest = tail;
+            }
+        }
+    }
+
+    String::from_utf8_lossy(&bytes).into_owned()
+}
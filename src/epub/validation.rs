@@ -0,0 +1,412 @@
+//! Report of the structural problems of a book, for readers and developers to find out why a book
+//! renders oddly. Problems that don't keep the rest of the book from being checked are collected
+//! as [`Issue`]s instead of stopping at the first one.
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use defmt::{error, info};
+use embedded_graphics::Drawable;
+use embedded_graphics::mono_font::MonoTextStyle;
+use embedded_graphics::mono_font::ascii::FONT_10X20;
+use embedded_graphics::pixelcolor::BinaryColor;
+use embedded_graphics::prelude::{OriginDimensions, Point, Size};
+use embedded_graphics::primitives::Rectangle;
+use embedded_graphics::text::Text;
+use embedded_sdmmc::Mode;
+
+use crate::SharedDisplay;
+use crate::activity;
+use crate::eink_display::Frame;
+use crate::epub::xml::{self, Tags};
+use crate::epub::zip::{Archive, Compression, Entry, ZipError};
+use crate::epub::{CONTAINER_PATH, directory, resolve};
+use crate::input::Analog;
+use crate::sd_card::{OpenError, SdCard};
+use crate::widget::{ListView, Response};
+
+const MIMETYPE: &[u8] = b"application/epub+zip";
+/// Books with DRM have their content encrypted
+const ENCRYPTION_PATH: &str = "META-INF/encryption.xml";
+/// Package documents of large books with long tables of contents can take a few dozen kilobytes
+const MAXIMUM_PACKAGE_SIZE: u32 = 32 * 1024;
+/// Keeps a badly broken book from filling the memory with issues
+const MAXIMUM_ISSUES: usize = 100;
+/// Where the issue list starts below the title
+const LIST_TOP: i32 = 45;
+
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum ValidateError {
+    #[error("Failed to open book")]
+    Open(#[from] OpenError),
+    #[error("Failed to read book archive")]
+    Zip(#[from] ZipError),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Issue {
+    /// The `mimetype` file is missing or has the wrong content
+    InvalidMimetype,
+    /// The book is protected with DRM
+    Encrypted,
+    UnsupportedCompression {
+        path: String,
+        method: u16,
+    },
+    MissingContainer,
+    /// The container doesn't name a package document
+    NoPackage,
+    MissingPackage(String),
+    PackageTooLarge(u32),
+    /// The package document lists a file that is not in the book
+    MissingFile(String),
+    EmptySpine,
+    /// The reading order refers to an item that is not in the manifest
+    MissingSpineItem(String),
+    /// A chapter shows an image that is not in the book
+    BrokenImage {
+        document: String,
+        image: String,
+    },
+    /// Only UTF-8 is supported
+    UnsupportedEncoding {
+        document: String,
+        encoding: String,
+    },
+    /// A file could not be decompressed
+    Unreadable(String),
+    /// More issues were found than are kept
+    TooManyIssues,
+}
+
+impl Issue {
+    /// One line for the report screen
+    pub(crate) fn describe(&self) -> String {
+        match self {
+            Self::InvalidMimetype => String::from("Invalid mimetype file"),
+            Self::Encrypted => String::from("Book is encrypted (DRM)"),
+            Self::UnsupportedCompression { path, method } => {
+                format!("Compression {method}: {path}")
+            }
+            Self::MissingContainer => String::from("Missing META-INF/container.xml"),
+            Self::NoPackage => String::from("Container names no package"),
+            Self::MissingPackage(path) => format!("Missing package: {path}"),
+            Self::PackageTooLarge(size) => format!("Package too large: {size} bytes"),
+            Self::MissingFile(path) => format!("Missing file: {path}"),
+            Self::EmptySpine => String::from("Reading order is empty"),
+            Self::MissingSpineItem(id) => format!("Missing spine item: {id}"),
+            Self::BrokenImage { document, image } => format!("Broken image {image} in {document}"),
+            Self::UnsupportedEncoding { document, encoding } => {
+                format!("Encoding {encoding}: {document}")
+            }
+            Self::Unreadable(path) => format!("Unreadable: {path}"),
+            Self::TooManyIssues => format!("More than {MAXIMUM_ISSUES} issues"),
+        }
+    }
+}
+
+struct Report {
+    issues: Vec<Issue>,
+}
+
+impl Report {
+    fn add(&mut self, issue: Issue) {
+        if self.issues.len() < MAXIMUM_ISSUES {
+            self.issues.push(issue);
+        } else if self.issues.last() != Some(&Issue::TooManyIssues) {
+            self.issues.push(Issue::TooManyIssues);
+        }
+    }
+}
+
+/// Item of the package manifest
+struct Item {
+    id: String,
+    /// Path within the archive
+    path: String,
+}
+
+/// Checks the structure of the EPUB at the path and returns the issues found. An empty list means
+/// no issues were found.
+pub(crate) async fn validate(
+    sd_card: &mut SdCard,
+    path: &str,
+) -> Result<Vec<Issue>, ValidateError> {
+    let file = sd_card.open(path, Mode::ReadOnly).await?;
+    let result = match Archive::open(sd_card, file).await {
+        Ok(archive) => Ok(validate_archive(sd_card, &archive).await),
+        Err(error) => Err(error.into()),
+    };
+
+    if let Err(error) = sd_card.close(file).await {
+        defmt::warn!("Failed to close book: {:?}", error);
+    }
+    activity::finish();
+
+    result
+}
+
+async fn validate_archive(sd_card: &mut SdCard, archive: &Archive) -> Vec<Issue> {
+    let mut report = Report { issues: Vec::new() };
+
+    // Needs to be the first file and stored uncompressed
+    let is_mimetype_valid = match archive.entries().first() {
+        Some(entry) if entry.name == "mimetype" && entry.compression == Compression::Stored => {
+            archive
+                .read(sd_card, entry, 64)
+                .await
+                .is_ok_and(|content| content.trim_ascii() == MIMETYPE)
+        }
+        _ => false,
+    };
+    if !is_mimetype_valid {
+        report.add(Issue::InvalidMimetype);
+    }
+
+    if archive.find(ENCRYPTION_PATH).is_some() {
+        report.add(Issue::Encrypted);
+    }
+
+    for entry in archive.entries() {
+        if let Compression::Other(method) = entry.compression {
+            report.add(Issue::UnsupportedCompression {
+                path: entry.name.clone(),
+                method,
+            });
+        }
+    }
+
+    let Some(package_path) = package_path(sd_card, archive, &mut report).await else {
+        return report.issues;
+    };
+
+    let Some(entry) = archive.find(&package_path) else {
+        report.add(Issue::MissingPackage(package_path));
+        return report.issues;
+    };
+
+    let package = match archive.read(sd_card, entry, MAXIMUM_PACKAGE_SIZE).await {
+        Ok(package) => package,
+        Err(ZipError::TooLarge(size)) => {
+            report.add(Issue::PackageTooLarge(size));
+            return report.issues;
+        }
+        Err(error) => {
+            error!("Failed to read package: {:?}", defmt::Debug2Format(&error));
+            report.add(Issue::Unreadable(package_path));
+            return report.issues;
+        }
+    };
+
+    // Manifest paths are relative to the package document
+    let base = directory(&package_path);
+    let mut manifest = Vec::new();
+    let mut spine = Vec::new();
+    Tags::new().push(&package, |tag| match xml::name(tag) {
+        "item" => {
+            if let (Some(id), Some(href)) = (xml::attribute(tag, "id"), xml::attribute(tag, "href"))
+            {
+                manifest.push(Item {
+                    id: String::from(id),
+                    path: resolve(base, href),
+                });
+            }
+        }
+        "itemref" => {
+            if let Some(id) = xml::attribute(tag, "idref") {
+                spine.push(String::from(id));
+            }
+        }
+        _ => {}
+    });
+    drop(package);
+
+    for item in &manifest {
+        if archive.find(&item.path).is_none() {
+            report.add(Issue::MissingFile(item.path.clone()));
+        }
+    }
+
+    if spine.is_empty() {
+        report.add(Issue::EmptySpine);
+    }
+
+    let total = u32::try_from(spine.len()).unwrap_or(u32::MAX);
+    for (done, id) in (0..).zip(&spine) {
+        activity::report("Checking book", done, total);
+
+        let Some(item) = manifest.iter().find(|item| item.id == *id) else {
+            report.add(Issue::MissingSpineItem(id.clone()));
+            continue;
+        };
+
+        // Already reported as missing
+        if let Some(entry) = archive.find(&item.path) {
+            check_document(sd_card, archive, entry, &mut report).await;
+        }
+    }
+
+    info!("Found {} issues", report.issues.len());
+    report.issues
+}
+
+/// Path of the package document that the container points to
+async fn package_path(
+    sd_card: &mut SdCard,
+    archive: &Archive,
+    report: &mut Report,
+) -> Option<String> {
+    let Some(entry) = archive.find(CONTAINER_PATH) else {
+        report.add(Issue::MissingContainer);
+        return None;
+    };
+
+    let container = match archive.read(sd_card, entry, MAXIMUM_PACKAGE_SIZE).await {
+        Ok(container) => container,
+        Err(error) => {
+            error!(
+                "Failed to read container: {:?}",
+                defmt::Debug2Format(&error)
+            );
+            report.add(Issue::Unreadable(String::from(CONTAINER_PATH)));
+            return None;
+        }
+    };
+
+    let mut path = None;
+    Tags::new().push(&container, |tag| {
+        if path.is_none() && xml::name(tag) == "rootfile" {
+            path = xml::attribute(tag, "full-path").map(|path| resolve("", path));
+        }
+    });
+
+    if path.is_none() {
+        report.add(Issue::NoPackage);
+    }
+
+    path
+}
+
+/// Looks for images that are not in the book and encodings other than UTF-8 in a chapter
+async fn check_document(
+    sd_card: &mut SdCard,
+    archive: &Archive,
+    entry: &Entry,
+    report: &mut Report,
+) {
+    let base = directory(&entry.name);
+    let mut tags = Tags::new();
+    let mut is_start = true;
+    let result = archive
+        .read_with(sd_card, entry, |text| {
+            if is_start {
+                is_start = false;
+                if text.starts_with(&[0xFF, 0xFE]) || text.starts_with(&[0xFE, 0xFF]) {
+                    report.add(Issue::UnsupportedEncoding {
+                        document: entry.name.clone(),
+                        encoding: String::from("UTF-16"),
+                    });
+                }
+            }
+
+            tags.push(text, |tag| {
+                let reference = match xml::name(tag) {
+                    "?xml" => {
+                        if let Some(encoding) = xml::attribute(tag, "encoding")
+                            && !encoding.eq_ignore_ascii_case("utf-8")
+                        {
+                            report.add(Issue::UnsupportedEncoding {
+                                document: entry.name.clone(),
+                                encoding: String::from(encoding),
+                            });
+                        }
+                        None
+                    }
+                    "img" => xml::attribute(tag, "src"),
+                    // SVG images
+                    "image" => {
+                        xml::attribute(tag, "xlink:href").or_else(|| xml::attribute(tag, "href"))
+                    }
+                    _ => None,
+                };
+
+                // Images from the web or embedded into the document are not looked up
+                let Some(reference) = reference.filter(|reference| !reference.contains(':')) else {
+                    return;
+                };
+
+                let path = resolve(base, reference);
+                if archive.find(&path).is_none() {
+                    report.add(Issue::BrokenImage {
+                        document: entry.name.clone(),
+                        image: path,
+                    });
+                }
+            });
+        })
+        .await;
+
+    if let Err(error) = result {
+        error!(
+            "Failed to read {}: {:?}",
+            entry.name.as_str(),
+            defmt::Debug2Format(&error)
+        );
+        report.add(Issue::Unreadable(entry.name.clone()));
+    }
+}
+
+/// Shows the issues as a list until the reader backs out
+pub(crate) async fn show_report(
+    path: &str,
+    issues: &[Issue],
+    analog: &mut Analog<'_>,
+    display: &SharedDisplay,
+    frame: &mut Frame,
+) {
+    let lines: Vec<String> = if issues.is_empty() {
+        vec![String::from("No issues found")]
+    } else {
+        issues.iter().map(Issue::describe).collect()
+    };
+
+    let mut list = ListView::new(lines.len(), 0);
+    loop {
+        draw_report(path, &lines, frame, &mut list);
+        if let Err(error) = display.submit(frame).await {
+            error!(
+                "Failed to display validation report: {:?}",
+                defmt::Debug2Format(&error)
+            );
+        }
+
+        loop {
+            match list.handle(analog.wait_for_press().await) {
+                Response::Ignored | Response::Confirmed => {}
+                Response::Changed => break,
+                Response::Cancelled => return,
+            }
+        }
+    }
+}
+
+fn draw_report(path: &str, lines: &[String], frame: &mut Frame, list: &mut ListView) {
+    // White
+    frame.fill(0xFF);
+
+    let style = MonoTextStyle::new(&FONT_10X20, BinaryColor::On);
+    if let Err(error) = Text::new(path, Point::new(10, 30), style).draw(frame) {
+        error!("Failed to draw validation report: {:?}", error);
+    }
+
+    let size = frame.size();
+    let area = Rectangle::new(
+        Point::new(0, LIST_TOP),
+        Size::new(size.width, size.height - LIST_TOP as u32),
+    );
+    if let Err(error) = list.draw(frame, &area, |index| lines[index].as_str()) {
+        error!("Failed to draw validation report: {:?}", error);
+    }
+}
@@ -0,0 +1,139 @@
+//! Just enough XML for the metadata and chapters of books. Tags are picked out of the text as it is
+//! decompressed without building a document tree, so documents don't need to fit into memory.
+
+use alloc::string::String;
+
+/// Attributes after this are cut off, but the interesting ones usually come first
+const MAXIMUM_TAG_SIZE: usize = 512;
+
+/// Collects the tags from text that comes in pieces
+pub(crate) struct Tags {
+    tag: heapless::Vec<u8, MAXIMUM_TAG_SIZE>,
+    is_inside: bool,
+}
+
+impl Tags {
+    pub(crate) fn new() -> Self {
+        Self {
+            tag: heapless::Vec::new(),
+            is_inside: false,
+        }
+    }
+
+    /// Calls the handler with the content between `<` and `>` of each tag that ends in the text
+    pub(crate) fn push(&mut self, text: &[u8], mut handle: impl FnMut(&str)) {
+        for &byte in text {
+            match (self.is_inside, byte) {
+                (false, b'<') => {
+                    self.is_inside = true;
+                    self.tag.clear();
+                }
+                (true, b'>') => {
+                    self.is_inside = false;
+                    // Tags cut off in the middle of a character are skipped
+                    if let Ok(tag) = core::str::from_utf8(&self.tag) {
+                        handle(tag);
+                    }
+                }
+                (true, _) => {
+                    // Too long tags are cut off
+                    let _ = self.tag.push(byte);
+                }
+                (false, _) => {}
+            }
+        }
+    }
+}
+
+/// Name of the tag without the namespace prefix, e.g. `item` for `opf:item id="cover"`
+pub(crate) fn name(tag: &str) -> &str {
+    let name = tag
+        .split(|character: char| character.is_ascii_whitespace() || character == '/')
+        .next()
+        .unwrap_or_default();
+    name.rsplit_once(':').map_or(name, |(_, name)| name)
+}
+
+/// Value of the attribute in the tag without resolving entities. The name is matched including
+/// its namespace prefix if there is one, e.g. `xlink:href`.
+pub(crate) fn attribute<'a>(tag: &'a str, name: &str) -> Option<&'a str> {
+    let mut rest = tag;
+    while let Some(position) = rest.find(name) {
+        let before = rest[..position].chars().next_back();
+        let after = rest[position + name.len()..].trim_start();
+        rest = &rest[position + name.len()..];
+
+        if !before.is_some_and(|character| character.is_ascii_whitespace()) {
+            continue;
+        }
+
+        let Some(value) = after.strip_prefix('=') else {
+            continue;
+        };
+
+        let value = value.trim_start();
+        let quote = value.chars().next()?;
+        if quote != '"' && quote != '\'' {
+            return None;
+        }
+
+        let value = &value[1..];
+        return value.find(quote).map(|end| &value[..end]);
+    }
+
+    None
+}
+
+/// Text of the first element with the name, e.g. `Emma` for `title` in `<dc:title>Emma</dc:title>`.
+/// Only the text up to the next tag is taken, so elements with markup inside are cut off.
+pub(crate) fn text(document: &str, element: &str) -> Option<String> {
+    let mut rest = document;
+    while let Some(start) = rest.find('<') {
+        let end = start + rest[start..].find('>')?;
+        let tag = &rest[start + 1..end];
+        rest = &rest[end + 1..];
+        if name(tag) == element && !tag.ends_with('/') {
+            let content = &rest[..rest.find('<').unwrap_or(rest.len())];
+            return Some(unescape(content.trim()));
+        }
+    }
+
+    None
+}
+
+/// Resolves the entities that XML predefines
+fn unescape(text: &str) -> String {
+    let mut unescaped = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        unescaped.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        let resolved = rest.find(';').and_then(|end| {
+            let character = match &rest[1..end] {
+                "amp" => '&',
+                "lt" => '<',
+                "gt" => '>',
+                "quot" => '"',
+                "apos" => '\'',
+                _ => return None,
+            };
+            Some((character, end))
+        });
+
+        match resolved {
+            Some((character, end)) => {
+                unescaped.push(character);
+                rest = &rest[end + 1..];
+            }
+            // Not an entity but an ampersand
+            None => {
+                unescaped.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+
+    unescaped.push_str(rest);
+    unescaped
+}
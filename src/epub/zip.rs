@@ -0,0 +1,346 @@
+//! Reading files from the ZIP container of a book. Only the central directory with the names of
+//! the files is kept in memory. Files are decompressed in pieces and handed on as they come, as a
+//! chapter can be larger than the heap.
+
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use embedded_sdmmc::RawFile;
+use miniz_oxide::inflate::TINFLStatus;
+use miniz_oxide::inflate::core::{DecompressorOxide, decompress, inflate_flags};
+
+use crate::image::try_zeroed;
+use crate::power_profile;
+use crate::sd_card::{ReadPriority, SdCard, VolumeError};
+
+const END_SIGNATURE: [u8; 4] = [b'P', b'K', 5, 6];
+const DIRECTORY_SIGNATURE: [u8; 4] = [b'P', b'K', 1, 2];
+const LOCAL_SIGNATURE: [u8; 4] = [b'P', b'K', 3, 4];
+const END_SIZE: usize = 22;
+const DIRECTORY_ENTRY_SIZE: usize = 46;
+const LOCAL_HEADER_SIZE: usize = 30;
+/// The end record can be followed by a comment of up to 64 KiB, but books rarely have one
+const END_SEARCH_SIZE: u32 = 1024;
+/// Enough for books with hundreds of files
+const MAXIMUM_DIRECTORY_SIZE: u32 = 32 * 1024;
+/// Compressed data is read from the SD card in pieces of this size
+const READ_BUFFER_SIZE: usize = 1024;
+/// Deflate can refer back this far into the decompressed data
+const WINDOW_SIZE: usize = 32 * 1024;
+/// Marks sizes and offsets that are stored in the ZIP64 extra field instead
+const ZIP64_MARKER: u32 = u32::MAX;
+
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum ZipError {
+    #[error("Failed to read archive")]
+    Read(VolumeError),
+    #[error("File is not a ZIP archive")]
+    NotZip,
+    #[error("Archive ends early")]
+    Truncated,
+    #[error("Archive directory is larger than the maximum size")]
+    DirectoryTooLarge(u32),
+    #[error("Archive entry is larger than the maximum size")]
+    TooLarge(u32),
+    #[error("ZIP64 archives are not supported")]
+    Zip64,
+    #[error("Archive entry uses an unsupported compression method")]
+    UnsupportedCompression(u16),
+    #[error("Failed to decompress archive entry")]
+    Inflate(TINFLStatus),
+    #[error("Not enough memory to decompress archive entry")]
+    OutOfMemory,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub(crate) enum Compression {
+    Stored,
+    Deflated,
+    /// Methods like bzip2 or LZMA that EPUB doesn't allow
+    Other(u16),
+}
+
+impl Compression {
+    fn from_method(method: u16) -> Self {
+        match method {
+            0 => Self::Stored,
+            8 => Self::Deflated,
+            _ => Self::Other(method),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Entry {
+    /// Path within the archive like `OEBPS/chapter1.xhtml`
+    pub(crate) name: String,
+    pub(crate) compression: Compression,
+    compressed_size: u32,
+    /// Decompressed size
+    pub(crate) size: u32,
+    /// Offset of the local header in the file
+    offset: u32,
+}
+
+/// An open ZIP file. The file needs to stay open while the archive is used.
+pub(crate) struct Archive {
+    file: RawFile,
+    entries: Vec<Entry>,
+}
+
+impl Archive {
+    /// Reads the central directory of the ZIP file
+    pub(crate) async fn open(sd_card: &mut SdCard, file: RawFile) -> Result<Self, ZipError> {
+        let length = sd_card.length(file).await.map_err(ZipError::Read)?;
+        // Can't truncate as the end record size is small
+        if length < END_SIZE as u32 {
+            return Err(ZipError::NotZip);
+        }
+
+        let search_start = length.saturating_sub(END_SEARCH_SIZE);
+        // Can't truncate as it is at most the search size
+        let mut tail = vec![0; (length - search_start) as usize];
+        read_exact(sd_card, file, search_start, &mut tail).await?;
+
+        let end = tail
+            .windows(END_SIGNATURE.len())
+            .rposition(|window| window == END_SIGNATURE)
+            .filter(|position| position + END_SIZE <= tail.len())
+            .ok_or(ZipError::NotZip)?;
+        let end = &tail[end..end + END_SIZE];
+
+        let count = u16_at(end, 10);
+        let size = u32_at(end, 12);
+        let offset = u32_at(end, 16);
+        if offset == ZIP64_MARKER || size == ZIP64_MARKER {
+            return Err(ZipError::Zip64);
+        }
+        if size > MAXIMUM_DIRECTORY_SIZE {
+            return Err(ZipError::DirectoryTooLarge(size));
+        }
+
+        // Can't truncate as it is at most the maximum size
+        let mut directory = try_zeroed(size as usize).ok_or(ZipError::OutOfMemory)?;
+        read_exact(sd_card, file, offset, &mut directory).await?;
+
+        let mut entries = Vec::with_capacity(usize::from(count));
+        let mut rest = directory.as_slice();
+        for _ in 0..count {
+            if rest.len() < DIRECTORY_ENTRY_SIZE || rest[..4] != DIRECTORY_SIGNATURE {
+                return Err(ZipError::Truncated);
+            }
+
+            let name_length = usize::from(u16_at(rest, 28));
+            let extra_length = usize::from(u16_at(rest, 30));
+            let comment_length = usize::from(u16_at(rest, 32));
+            let entry_size = DIRECTORY_ENTRY_SIZE + name_length + extra_length + comment_length;
+            if rest.len() < entry_size {
+                return Err(ZipError::Truncated);
+            }
+
+            let name = &rest[DIRECTORY_ENTRY_SIZE..DIRECTORY_ENTRY_SIZE + name_length];
+            let entry = Entry {
+                name: String::from_utf8_lossy(name).into_owned(),
+                compression: Compression::from_method(u16_at(rest, 10)),
+                compressed_size: u32_at(rest, 20),
+                size: u32_at(rest, 24),
+                offset: u32_at(rest, 42),
+            };
+            if entry.compressed_size == ZIP64_MARKER || entry.offset == ZIP64_MARKER {
+                return Err(ZipError::Zip64);
+            }
+
+            entries.push(entry);
+            rest = &rest[entry_size..];
+        }
+
+        Ok(Self { file, entries })
+    }
+
+    pub(crate) fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    /// Looks up the entry by its exact path within the archive
+    pub(crate) fn find(&self, name: &str) -> Option<&Entry> {
+        self.entries.iter().find(|entry| entry.name == name)
+    }
+
+    /// Decompresses the whole entry into memory if it is not larger than the maximum size
+    pub(crate) async fn read(
+        &self,
+        sd_card: &mut SdCard,
+        entry: &Entry,
+        maximum_size: u32,
+    ) -> Result<Vec<u8>, ZipError> {
+        if entry.size > maximum_size {
+            return Err(ZipError::TooLarge(entry.size));
+        }
+
+        // Can't truncate as it is at most the maximum size
+        let mut content = Vec::with_capacity(entry.size as usize);
+        self.read_with(sd_card, entry, |piece| {
+            // The size in the directory could be wrong
+            let remaining = (maximum_size as usize).saturating_sub(content.len());
+            content.extend_from_slice(&piece[..piece.len().min(remaining)]);
+        })
+        .await?;
+
+        Ok(content)
+    }
+
+    /// Decompresses the entry and hands it to the consumer piece by piece
+    pub(crate) async fn read_with(
+        &self,
+        sd_card: &mut SdCard,
+        entry: &Entry,
+        mut consume: impl FnMut(&[u8]),
+    ) -> Result<(), ZipError> {
+        let _boost = power_profile::boost();
+        let data_start = self.data_start(sd_card, entry).await?;
+        let mut inflater = match entry.compression {
+            Compression::Stored => None,
+            Compression::Deflated => Some(Inflater::new()?),
+            Compression::Other(method) => return Err(ZipError::UnsupportedCompression(method)),
+        };
+
+        let mut buffer = vec![0; READ_BUFFER_SIZE];
+        let mut offset = 0;
+        while offset < entry.compressed_size {
+            // Can't truncate as it is at most the buffer size
+            let length = (entry.compressed_size - offset).min(READ_BUFFER_SIZE as u32) as usize;
+            let piece = &mut buffer[..length];
+            read_exact(sd_card, self.file, data_start + offset, piece).await?;
+            // Can't truncate as it is at most the buffer size
+            offset += length as u32;
+
+            match inflater.as_mut() {
+                Some(inflater) => {
+                    let has_more = offset < entry.compressed_size;
+                    inflater.push(piece, has_more, &mut consume)?;
+                }
+                None => consume(piece),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Where the data of an entry that is stored without compression starts in the file, so it
+    /// can be read in place like an image
+    pub(crate) async fn stored_offset(
+        &self,
+        sd_card: &mut SdCard,
+        entry: &Entry,
+    ) -> Result<u32, ZipError> {
+        match entry.compression {
+            Compression::Stored => self.data_start(sd_card, entry).await,
+            Compression::Deflated => Err(ZipError::UnsupportedCompression(8)),
+            Compression::Other(method) => Err(ZipError::UnsupportedCompression(method)),
+        }
+    }
+
+    pub(crate) fn file(&self) -> RawFile {
+        self.file
+    }
+
+    async fn data_start(&self, sd_card: &mut SdCard, entry: &Entry) -> Result<u32, ZipError> {
+        let mut header = [0; LOCAL_HEADER_SIZE];
+        read_exact(sd_card, self.file, entry.offset, &mut header).await?;
+        if header[..4] != LOCAL_SIGNATURE {
+            return Err(ZipError::Truncated);
+        }
+
+        // The lengths in the local header can differ from those in the directory
+        Ok(entry.offset
+            // Can't truncate as the header is small
+            + LOCAL_HEADER_SIZE as u32
+            + u32::from(u16_at(&header, 26))
+            + u32::from(u16_at(&header, 28)))
+    }
+}
+
+/// Decompresses raw deflate data in a wrapping window
+struct Inflater {
+    decompressor: Box<DecompressorOxide>,
+    window: Vec<u8>,
+    position: usize,
+    is_done: bool,
+}
+
+impl Inflater {
+    fn new() -> Result<Self, ZipError> {
+        Ok(Self {
+            decompressor: Box::default(),
+            window: try_zeroed(WINDOW_SIZE).ok_or(ZipError::OutOfMemory)?,
+            position: 0,
+            is_done: false,
+        })
+    }
+
+    fn push(
+        &mut self,
+        mut data: &[u8],
+        has_more: bool,
+        consume: &mut impl FnMut(&[u8]),
+    ) -> Result<(), ZipError> {
+        let flags = if has_more {
+            inflate_flags::TINFL_FLAG_HAS_MORE_INPUT
+        } else {
+            0
+        };
+
+        while !self.is_done {
+            let start = self.position;
+            let (status, consumed, written) =
+                decompress(&mut self.decompressor, data, &mut self.window, start, flags);
+            data = &data[consumed..];
+            self.position = (start + written) % WINDOW_SIZE;
+
+            consume(&self.window[start..start + written]);
+
+            match status {
+                TINFLStatus::Done => self.is_done = true,
+                TINFLStatus::NeedsMoreInput if has_more => return Ok(()),
+                // Window is full and starts over at the beginning
+                TINFLStatus::HasMoreOutput => {}
+                _ => return Err(ZipError::Inflate(status)),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+async fn read_exact(
+    sd_card: &mut SdCard,
+    file: RawFile,
+    offset: u32,
+    buffer: &mut [u8],
+) -> Result<(), ZipError> {
+    let read = sd_card
+        .read_at(file, offset, buffer, ReadPriority::Background)
+        .await
+        .map_err(ZipError::Read)?;
+
+    if read < buffer.len() {
+        return Err(ZipError::Truncated);
+    }
+
+    Ok(())
+}
+
+fn u16_at(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([
+        bytes[offset],
+        bytes[offset + 1],
+        bytes[offset + 2],
+        bytes[offset + 3],
+    ])
+}
@@ -0,0 +1,139 @@
+//! What the subsystems tell the home screen arrives as an [`Event`] over a single channel, so its
+//! loop sleeps until there is something to do instead of checking every subsystem in turn. The
+//! subsystems publish without waiting, from whichever task noticed the change. The loop waits for
+//! the buttons and its timers next to the channel.
+//!
+//! Events that can't wait, like a battery about to run empty, are taken before those that were
+//! queued earlier. Events of the same priority keep their order.
+
+use core::cmp::Ordering as Comparison;
+
+use defmt::warn;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::priority_channel::{Max, PriorityChannel};
+use portable_atomic::{AtomicU32, Ordering};
+
+use crate::input::Button;
+use crate::power_button::Press;
+use crate::sd_card::CardChange;
+
+/// Events that were not handled yet. Later events are dropped, which only happens while the loop
+/// is held up, e.g. by a benchmark.
+const CAPACITY: usize = 16;
+
+static EVENTS: PriorityChannel<CriticalSectionRawMutex, Queued, Max, CAPACITY> =
+    PriorityChannel::new();
+/// Orders events of the same priority. Doesn't wrap around in the lifetime of a battery charge.
+static SEQUENCE: AtomicU32 = AtomicU32::new(0);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub(crate) enum Event {
+    /// A press or gesture of the buttons on the front
+    Button(Button),
+    /// The power button handles turning the display and the reader off by itself
+    PowerButton(Press),
+    SdCard(CardChange),
+    Battery(BatteryAlert),
+    /// Asked for by the console, drawn into the frame of the home screen as there is no memory for
+    /// a second one
+    TestPattern,
+    /// Asked for by the console. Needs the frame as well.
+    Benchmark,
+    /// The memory overlay was turned on or off
+    Overlay(bool),
+    /// The display controller was reset after it stayed busy or failed repeatedly, so the screen
+    /// flashed
+    DisplayRecovered,
+    #[cfg(feature = "radio")]
+    Network(NetworkEvent),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub(crate) enum BatteryAlert {
+    /// Below the shutdown threshold, so the reader needs to turn off before a brown-out
+    Low,
+    ChargingStarted,
+    ChargingStopped,
+}
+
+#[cfg(feature = "radio")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub(crate) enum NetworkEvent {
+    Connected,
+    Disconnected,
+}
+
+/// Higher priorities are taken first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Priority {
+    Background,
+    Normal,
+    Interactive,
+    Urgent,
+}
+
+impl Event {
+    fn priority(self) -> Priority {
+        match self {
+            Self::Battery(BatteryAlert::Low) => Priority::Urgent,
+            Self::Button(_) | Self::PowerButton(_) => Priority::Interactive,
+            Self::SdCard(_)
+            | Self::Battery(_)
+            | Self::TestPattern
+            | Self::Benchmark
+            | Self::Overlay(_) => Priority::Normal,
+            #[cfg(feature = "radio")]
+            Self::Network(_) => Priority::Normal,
+            Self::DisplayRecovered => Priority::Background,
+        }
+    }
+}
+
+/// An event in the channel, ordered by its priority and then by when it was published
+struct Queued {
+    priority: Priority,
+    sequence: u32,
+    event: Event,
+}
+
+impl Ord for Queued {
+    fn cmp(&self, other: &Self) -> Comparison {
+        // The earlier event is the greater one, as the channel takes the greatest first
+        self.priority
+            .cmp(&other.priority)
+            .then_with(|| other.sequence.cmp(&self.sequence))
+    }
+}
+
+impl PartialOrd for Queued {
+    fn partial_cmp(&self, other: &Self) -> Option<Comparison> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Queued {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Comparison::Equal
+    }
+}
+
+impl Eq for Queued {}
+
+/// Queues the event without waiting for it to be handled
+pub(crate) fn publish(event: Event) {
+    let queued = Queued {
+        priority: event.priority(),
+        sequence: SEQUENCE.fetch_add(1, Ordering::Relaxed),
+        event,
+    };
+
+    if EVENTS.try_send(queued).is_err() {
+        warn!("Event queue full. Dropping {}", event);
+    }
+}
+
+/// Waits for the most urgent event. Can be cancelled without losing events.
+pub(crate) async fn next() -> Event {
+    let Queued { event, .. } = EVENTS.receive().await;
+    event
+}
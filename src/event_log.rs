@@ -0,0 +1,208 @@
+//! Mirrors important events like errors, battery readings and wake causes to a log file on the SD
+//! card, so problems readers run into can be diagnosed after the fact without the defmt tooling
+//! attached.
+//!
+//! Events are queued without waiting and written in batches to not wear out the card. The log
+//! alternates between two files and the older one is cleared when the newer one is full, so it
+//! never takes more than twice the maximum length.
+
+use alloc::string::String;
+use core::fmt::{self, Display, Write};
+
+use defmt::warn;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::channel::Channel;
+use embassy_time::{Instant, Timer};
+use embedded_sdmmc::Mode;
+use esp_hal::rtc_cntl::SocResetReason;
+use esp_hal::rtc_cntl::sleep::SleepSource;
+use portable_atomic::{AtomicU32, Ordering};
+
+use crate::sd_card::{CardChange, OpenError, SdCard, WriteFileError};
+use crate::timeout::Operation;
+use crate::{SharedRtc, SharedSdCard};
+
+/// Also holds other logs that alternate between two files, like the battery history
+pub(crate) const DIRECTORY: &str = "/LOGS";
+/// The files the log alternates between
+const FILES: [&str; 2] = ["/LOGS/EVENTS1.TXT", "/LOGS/EVENTS2.TXT"];
+/// Length after which the log continues in the other file
+const MAXIMUM_LENGTH: u32 = 64 * 1024;
+/// Events that can wait for the next flush. Later events are dropped and counted.
+const CAPACITY: usize = 16;
+const FLUSH_INTERVAL_SECONDS: u64 = 10;
+
+static EVENTS: Channel<CriticalSectionRawMutex, Entry, CAPACITY> = Channel::new();
+/// Events that were dropped since the last flush because the queue was full
+static DROPPED: AtomicU32 = AtomicU32::new(0);
+
+#[derive(Debug)]
+pub(crate) enum Event {
+    /// Start after power on, a reset or waking up from deep sleep
+    Started {
+        reset: Option<SocResetReason>,
+        wake: SleepSource,
+    },
+    Battery {
+        millivolts: u16,
+        percentage: Option<u8>,
+    },
+    Timeout(Operation),
+    DisplayRecovered,
+    SdCard(CardChange),
+    ShuttingDown {
+        is_battery_low: bool,
+    },
+    Error(String),
+}
+
+impl Display for Event {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Started { reset, wake } => {
+                write!(formatter, "Started after reset {reset:?}, wake up {wake:?}")
+            }
+            Self::Battery {
+                millivolts,
+                percentage: Some(percentage),
+            } => write!(formatter, "Battery at {millivolts} mV, {percentage}%"),
+            Self::Battery {
+                millivolts,
+                percentage: None,
+            } => write!(formatter, "Battery at {millivolts} mV"),
+            Self::Timeout(operation) => write!(formatter, "{operation:?} timed out"),
+            Self::DisplayRecovered => formatter.write_str("Display recovered"),
+            Self::SdCard(CardChange::Inserted) => formatter.write_str("SD card inserted"),
+            Self::SdCard(CardChange::Removed) => formatter.write_str("SD card removed"),
+            Self::ShuttingDown {
+                is_battery_low: true,
+            } => formatter.write_str("Shutting down because the battery is low"),
+            Self::ShuttingDown {
+                is_battery_low: false,
+            } => formatter.write_str("Shutting down"),
+            Self::Error(message) => write!(formatter, "Error: {message}"),
+        }
+    }
+}
+
+struct Entry {
+    time: Instant,
+    event: Event,
+}
+
+/// Queues the event for the log file without waiting for the SD card
+pub(crate) fn record(event: Event) {
+    let entry = Entry {
+        time: Instant::now(),
+        event,
+    };
+
+    if EVENTS.try_send(entry).is_err() {
+        DROPPED.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Writes the queued events to the log now and then
+#[embassy_executor::task]
+pub(crate) async fn run(sd_card: &'static SharedSdCard, rtc: &'static SharedRtc) {
+    loop {
+        Timer::after_secs(FLUSH_INTERVAL_SECONDS).await;
+        flush(sd_card, rtc).await;
+    }
+}
+
+/// Writes the queued events to the log. Without an SD card they stay queued.
+pub(crate) async fn flush(sd_card: &SharedSdCard, rtc: &SharedRtc) {
+    if EVENTS.is_empty() && DROPPED.load(Ordering::Relaxed) == 0 {
+        return;
+    }
+
+    let mut sd_card = sd_card.lock().await;
+    let Some(sd_card) = sd_card.as_mut() else {
+        return;
+    };
+
+    // The RTC keeps counting through deep sleep, unlike the instants which start at every boot
+    let offset = rtc
+        .lock(|rtc| rtc.borrow().current_time_us())
+        .saturating_sub(Instant::now().as_micros());
+
+    let mut text = String::new();
+    while let Ok(entry) = EVENTS.try_receive() {
+        let microseconds = entry.time.as_micros() + offset;
+        let _ = writeln!(
+            text,
+            "[{:>9}.{:03}] {}",
+            microseconds / 1_000_000,
+            microseconds / 1_000 % 1_000,
+            entry.event
+        );
+    }
+
+    let dropped = DROPPED.swap(0, Ordering::Relaxed);
+    if dropped > 0 {
+        let _ = writeln!(text, "{dropped} events dropped");
+    }
+
+    if let Err(error) = append(sd_card, FILES, MAXIMUM_LENGTH, &text).await {
+        warn!(
+            "Failed to write event log: {:?}",
+            defmt::Debug2Format(&error)
+        );
+    }
+}
+
+/// Appends to the first of the files in the log directory that is not full. The other file is
+/// cleared when the one written to becomes full.
+pub(crate) async fn append(
+    sd_card: &mut SdCard,
+    files: [&str; 2],
+    maximum_length: u32,
+    text: &str,
+) -> Result<(), WriteFileError> {
+    sd_card
+        .create_directories(DIRECTORY)
+        .await
+        .map_err(OpenError::from)?;
+
+    // The other file is cleared as soon as one is full, so the first file is only full when the
+    // log continues in the second
+    let [first, second] = files;
+    let (current, other) = if length(sd_card, first).await? < maximum_length {
+        (first, second)
+    } else {
+        (second, first)
+    };
+
+    let file = sd_card.open(current, Mode::ReadWriteCreateOrAppend).await?;
+    let written = sd_card.write(file, text.as_bytes()).await;
+    let length = sd_card.length(file).await;
+    // Closing flushes the data to the card
+    let closed = sd_card.close(file).await;
+    let length = written
+        .and(closed)
+        .and(length)
+        .map_err(WriteFileError::Write)?;
+
+    if length >= maximum_length {
+        sd_card.write_file(other, &[]).await?;
+    }
+
+    Ok(())
+}
+
+/// Length of the file at the path or 0 if it doesn't exist
+async fn length(sd_card: &mut SdCard, path: &str) -> Result<u32, OpenError> {
+    let file = match sd_card.open(path, Mode::ReadOnly).await {
+        Ok(file) => file,
+        Err(error) if error.is_not_found() => return Ok(0),
+        Err(error) => return Err(error),
+    };
+
+    let length = sd_card.length(file).await;
+    if let Err(error) = sd_card.close(file).await {
+        warn!("Failed to close {}: {:?}", path, error);
+    }
+
+    Ok(length?)
+}
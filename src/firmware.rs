@@ -0,0 +1,337 @@
+//! Firmware updates from the SD card, for readers without WiFi. A firmware image at [`PATH`] is
+//! installed at start up or from the menu. The image is the app image `espflash save-image` writes,
+//! with the MD5 checksum of it in the file at [`CHECKSUM_PATH`], for which the output of `md5sum`
+//! works. The checksum catches copies that were cut off and broken cards. It doesn't prove who
+//! built the image, as there is no signature.
+//!
+//! The flash has two app slots, `app0` and `app1` in partition-table.csv. The update is written to
+//! the slot that isn't running and only booted once it was read back and matches, so a failed
+//! update leaves the running firmware as it was. The files are removed from the card after the
+//! update to not install it again on the next start.
+//!
+//! New firmware is on trial on its first start. It confirms itself once the display and the SD card
+//! are up. If it crashes or hangs before that, the next start or the timeout of the trial switches
+//! back to the slot of the previous firmware, so a broken build can't leave the reader unusable.
+
+use alloc::string::String;
+use alloc::vec;
+use crustpoint_core::md5::Md5;
+use embassy_time::Timer;
+use embedded_sdmmc::{Mode, RawFile};
+use embedded_storage::{ReadStorage, Storage};
+use esp_bootloader_esp_idf::ota::OtaImageState;
+use esp_bootloader_esp_idf::ota_updater::OtaUpdater;
+use esp_bootloader_esp_idf::partitions::{self, PARTITION_TABLE_MAX_LEN};
+use esp_storage::FlashStorageError;
+
+use crate::flash::{self, FindPartitionError, Partition, SharedFlash};
+use crate::sd_card::{OpenError, ReadFileError, ReadPriority, SdCard, VolumeError};
+
+pub(crate) const PATH: &str = "/FIRMWARE.BIN";
+pub(crate) const CHECKSUM_PATH: &str = "/FIRMWARE.MD5";
+const MAXIMUM_CHECKSUM_FILE_SIZE: u32 = 256;
+/// Flash is erased in sectors, so the image is copied in pieces of this size
+const SECTOR_SIZE: usize = 4096;
+/// First byte of an app image
+const IMAGE_MAGIC: u8 = 0xE9;
+/// The header is followed by the extended header, which has the chip the image is for
+const HEADER_SIZE: usize = 24;
+const CHIP_ID_OFFSET: usize = 12;
+const ESP32_C3_CHIP_ID: u16 = 5;
+/// Time new firmware has to confirm itself. Long enough for the card to mount on a cold start.
+const TRIAL_SECONDS: u64 = 60;
+
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum UpdateError {
+    #[error("Failed to open firmware")]
+    Open(#[from] OpenError),
+    #[error("Failed to read firmware")]
+    Read(VolumeError),
+    #[error("Failed to read checksum")]
+    ReadChecksum(ReadFileError),
+    #[error("Checksum file doesn't contain an MD5 checksum")]
+    InvalidChecksum,
+    #[error("Firmware doesn't match its checksum")]
+    ChecksumMismatch,
+    #[error("File is not a firmware image for this reader")]
+    InvalidImage,
+    #[error("Firmware is larger than the app slot")]
+    TooLarge,
+    #[error("Failed to access the app slots")]
+    Slots(partitions::Error),
+    #[error("Failed to find the app slot")]
+    FindPartition(#[from] FindPartitionError),
+    #[error("Failed to write to flash")]
+    Write(FlashStorageError),
+    #[error("Failed to read from flash")]
+    ReadFlash(FlashStorageError),
+    #[error("Written firmware doesn't match the file")]
+    Verify,
+    #[error("Failed to remove the firmware from the card")]
+    Remove(OpenError),
+}
+
+/// How the running firmware was started
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub(crate) enum Trial {
+    /// Firmware that confirmed itself before or was flashed directly
+    Confirmed,
+    /// First start of new firmware, which has to confirm itself
+    Started,
+}
+
+/// Checks the running firmware at start up. Firmware that didn't confirm itself on the previous
+/// start is rolled back to the previous firmware, which restarts the device.
+pub(crate) fn start_trial(flash: &SharedFlash) -> Result<Trial, partitions::Error> {
+    let mut buffer = [0; PARTITION_TABLE_MAX_LEN];
+    flash.lock(|flash| {
+        let mut flash = flash.borrow_mut();
+        let mut updater = OtaUpdater::new(&mut *flash, &mut buffer)?;
+        match updater.current_ota_state() {
+            Ok(OtaImageState::New) => {
+                updater.set_current_ota_state(OtaImageState::PendingVerify)?;
+                Ok(Trial::Started)
+            }
+            Ok(OtaImageState::PendingVerify) => {
+                defmt::error!("Firmware didn't confirm itself, rolling back");
+                roll_back(&mut updater)?;
+                esp_hal::system::software_reset()
+            }
+            // Directly flashed firmware has no state
+            _ => Ok(Trial::Confirmed),
+        }
+    })
+}
+
+/// Marks the running firmware as working, so it keeps being started
+pub(crate) fn confirm(flash: &SharedFlash) -> Result<(), partitions::Error> {
+    let mut buffer = [0; PARTITION_TABLE_MAX_LEN];
+    flash.lock(|flash| {
+        let mut flash = flash.borrow_mut();
+        let mut updater = OtaUpdater::new(&mut *flash, &mut buffer)?;
+        if matches!(
+            updater.current_ota_state(),
+            Ok(OtaImageState::New | OtaImageState::PendingVerify)
+        ) {
+            defmt::info!("Firmware confirmed");
+            updater.set_current_ota_state(OtaImageState::Valid)?;
+        }
+        Ok(())
+    })
+}
+
+/// Rolls back if the firmware on trial doesn't confirm itself in time, e.g. because it is stuck
+/// waiting for the display
+#[embassy_executor::task]
+pub(crate) async fn supervise_trial(flash: &'static SharedFlash) {
+    Timer::after_secs(TRIAL_SECONDS).await;
+
+    let mut buffer = [0; PARTITION_TABLE_MAX_LEN];
+    let result: Result<(), partitions::Error> = flash.lock(|flash| {
+        let mut flash = flash.borrow_mut();
+        let mut updater = OtaUpdater::new(&mut *flash, &mut buffer)?;
+        if updater.current_ota_state()? != OtaImageState::PendingVerify {
+            return Ok(());
+        }
+
+        defmt::error!("Firmware didn't confirm itself in time, rolling back");
+        roll_back(&mut updater)?;
+        esp_hal::system::software_reset()
+    });
+
+    if let Err(error) = result {
+        defmt::error!(
+            "Failed to check firmware trial: {:?}",
+            defmt::Debug2Format(&error)
+        );
+    }
+}
+
+/// Marks the running firmware as broken and starts the other slot from now on
+fn roll_back<F: Storage>(updater: &mut OtaUpdater<'_, F>) -> Result<(), partitions::Error> {
+    updater.set_current_ota_state(OtaImageState::Invalid)?;
+    updater.activate_next_partition()
+}
+
+/// Whether there is firmware on the card to install
+pub(crate) async fn is_available(sd_card: &mut SdCard) -> bool {
+    let Ok(file) = sd_card.open(PATH, Mode::ReadOnly).await else {
+        return false;
+    };
+
+    if let Err(error) = sd_card.close(file).await {
+        defmt::warn!("Failed to close firmware: {:?}", error);
+    }
+    true
+}
+
+/// Copies the firmware from the card into the inactive slot and boots it on the next start. The
+/// device needs to be restarted for the update to take effect.
+pub(crate) async fn install(sd_card: &mut SdCard, flash: &SharedFlash) -> Result<(), UpdateError> {
+    let checksum = match sd_card
+        .read_file(
+            CHECKSUM_PATH,
+            MAXIMUM_CHECKSUM_FILE_SIZE,
+            ReadPriority::Interactive,
+        )
+        .await
+    {
+        Ok(content) => parse_checksum(&String::from_utf8_lossy(&content))
+            .ok_or(UpdateError::InvalidChecksum)?,
+        Err(error) => return Err(UpdateError::ReadChecksum(error)),
+    };
+
+    let file = sd_card.open(PATH, Mode::ReadOnly).await?;
+    let result = copy(sd_card, file, flash, &checksum).await;
+    if let Err(error) = sd_card.close(file).await {
+        defmt::warn!("Failed to close firmware: {:?}", error);
+    }
+    result?;
+
+    // Without removing the files the update would be installed again at every start
+    sd_card.remove(PATH).await.map_err(UpdateError::Remove)?;
+    if let Err(error) = sd_card.remove(CHECKSUM_PATH).await {
+        defmt::warn!(
+            "Failed to remove firmware checksum: {:?}",
+            defmt::Debug2Format(&error)
+        );
+    }
+
+    activate(flash)
+}
+
+/// Checks the image on the card and writes it into the inactive slot
+async fn copy(
+    sd_card: &mut SdCard,
+    file: RawFile,
+    flash: &SharedFlash,
+    checksum: &[u8; 16],
+) -> Result<(), UpdateError> {
+    let length = sd_card.length(file).await.map_err(UpdateError::Read)?;
+    let mut buffer = vec![0; SECTOR_SIZE];
+
+    let read = sd_card
+        .read_at(
+            file,
+            0,
+            &mut buffer[..HEADER_SIZE],
+            ReadPriority::Background,
+        )
+        .await
+        .map_err(UpdateError::Read)?;
+    check_header(&buffer[..read])?;
+
+    let mut md5 = Md5::new();
+    for offset in (0..length).step_by(SECTOR_SIZE) {
+        let read = sd_card
+            .read_at(file, offset, &mut buffer, ReadPriority::Background)
+            .await
+            .map_err(UpdateError::Read)?;
+        md5.update(&buffer[..read]);
+    }
+    if md5.finish() != *checksum {
+        return Err(UpdateError::ChecksumMismatch);
+    }
+
+    let slot = inactive_slot(flash)?;
+    // Can't truncate as usize is 32 bits
+    if length as usize > slot.size {
+        return Err(UpdateError::TooLarge);
+    }
+
+    defmt::info!(
+        "Writing {} bytes of firmware at {=u32:#x}",
+        length,
+        slot.offset
+    );
+    for offset in (0..length).step_by(SECTOR_SIZE) {
+        let read = sd_card
+            .read_at(file, offset, &mut buffer, ReadPriority::Background)
+            .await
+            .map_err(UpdateError::Read)?;
+        flash
+            .lock(|flash| {
+                flash
+                    .borrow_mut()
+                    .write(slot.offset + offset, &buffer[..read])
+            })
+            .map_err(UpdateError::Write)?;
+    }
+
+    // Reading it back catches flash that didn't take the write
+    let mut md5 = Md5::new();
+    for offset in (0..length).step_by(SECTOR_SIZE) {
+        // Can't truncate as the remaining length is less than the sector size
+        let size = (length - offset).min(SECTOR_SIZE as u32) as usize;
+        flash
+            .lock(|flash| {
+                flash
+                    .borrow_mut()
+                    .read(slot.offset + offset, &mut buffer[..size])
+            })
+            .map_err(UpdateError::ReadFlash)?;
+        md5.update(&buffer[..size]);
+    }
+    if md5.finish() != *checksum {
+        return Err(UpdateError::Verify);
+    }
+
+    Ok(())
+}
+
+/// The slot that is not running, which the update goes into
+fn inactive_slot(flash: &SharedFlash) -> Result<Partition, UpdateError> {
+    let mut buffer = [0; PARTITION_TABLE_MAX_LEN];
+    let slot: Result<_, partitions::Error> = flash.lock(|flash| {
+        let mut flash = flash.borrow_mut();
+        let mut updater = OtaUpdater::new(&mut *flash, &mut buffer)?;
+        let (_, slot) = updater.next_partition()?;
+        Ok(slot)
+    });
+    let slot = slot.map_err(UpdateError::Slots)?;
+    Ok(flash::find_app_partition(flash, slot)?)
+}
+
+/// Makes the bootloader start the inactive slot from now on
+fn activate(flash: &SharedFlash) -> Result<(), UpdateError> {
+    let mut buffer = [0; PARTITION_TABLE_MAX_LEN];
+    flash
+        .lock(|flash| {
+            let mut flash = flash.borrow_mut();
+            let mut updater = OtaUpdater::new(&mut *flash, &mut buffer)?;
+            updater.activate_next_partition()?;
+            updater.set_current_ota_state(OtaImageState::New)
+        })
+        .map_err(UpdateError::Slots)
+}
+
+/// Rejects files that are not app images for this chip, like a merged image with the bootloader
+fn check_header(header: &[u8]) -> Result<(), UpdateError> {
+    let Some(&[low, high]) = header.get(CHIP_ID_OFFSET..CHIP_ID_OFFSET + 2) else {
+        return Err(UpdateError::InvalidImage);
+    };
+
+    if header.len() < HEADER_SIZE
+        || header[0] != IMAGE_MAGIC
+        || u16::from_le_bytes([low, high]) != ESP32_C3_CHIP_ID
+    {
+        return Err(UpdateError::InvalidImage);
+    }
+
+    Ok(())
+}
+
+/// Checksum at the start of the file as hexadecimal digits like `md5sum` writes it
+fn parse_checksum(content: &str) -> Option<[u8; 16]> {
+    let digits = content.split_whitespace().next()?.as_bytes();
+    if digits.len() != 32 || !digits.iter().all(u8::is_ascii_hexdigit) {
+        return None;
+    }
+
+    let mut checksum = [0; 16];
+    for (byte, pair) in checksum.iter_mut().zip(digits.chunks(2)) {
+        let pair = core::str::from_utf8(pair).ok()?;
+        *byte = u8::from_str_radix(pair, 16).ok()?;
+    }
+    Some(checksum)
+}
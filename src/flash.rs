@@ -0,0 +1,69 @@
+//! Besides the firmware, the flash chip holds the data partitions from partition-table.csv. The
+//! storages for the partitions share the one flash peripheral.
+
+use core::cell::RefCell;
+
+use alloc::vec;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+use esp_bootloader_esp_idf::partitions::{
+    self, AppPartitionSubType, PARTITION_TABLE_MAX_LEN, PartitionEntry, PartitionType,
+};
+use esp_storage::FlashStorage;
+
+pub(crate) type SharedFlash = Mutex<NoopRawMutex, RefCell<FlashStorage<'static>>>;
+
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum FindPartitionError {
+    #[error("Failed to read partition table")]
+    PartitionTable(partitions::Error),
+    #[error("The partition is not in the partition table")]
+    NotFound,
+}
+
+/// Location of a partition in flash
+#[derive(Debug, Clone, Copy, defmt::Format)]
+pub(crate) struct Partition {
+    pub(crate) offset: u32,
+    pub(crate) size: usize,
+}
+
+/// Looks up the data partition by its name in partition-table.csv
+pub(crate) fn find_partition(
+    flash: &SharedFlash,
+    label: &str,
+) -> Result<Partition, FindPartitionError> {
+    find(flash, |partition| partition.label_as_str() == label)
+}
+
+/// Looks up the app partition of the slot, e.g. `app1` for the second OTA slot
+pub(crate) fn find_app_partition(
+    flash: &SharedFlash,
+    slot: AppPartitionSubType,
+) -> Result<Partition, FindPartitionError> {
+    find(flash, |partition| {
+        partition.partition_type() == PartitionType::App(slot)
+    })
+}
+
+fn find(
+    flash: &SharedFlash,
+    matches: impl Fn(&PartitionEntry<'_>) -> bool,
+) -> Result<Partition, FindPartitionError> {
+    let mut buffer = vec![0; PARTITION_TABLE_MAX_LEN];
+    flash.lock(|flash| {
+        let mut flash = flash.borrow_mut();
+        let table = partitions::read_partition_table(&mut *flash, &mut buffer)
+            .map_err(FindPartitionError::PartitionTable)?;
+        let partition = table
+            .iter()
+            .find(|partition| matches(partition))
+            .ok_or(FindPartitionError::NotFound)?;
+
+        Ok(Partition {
+            offset: partition.offset(),
+            // Can't truncate as usize is 32 bits
+            size: partition.len() as usize,
+        })
+    })
+}
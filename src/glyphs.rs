@@ -0,0 +1,367 @@
+//! Glyphs for the characters the built-in fonts don't have, like Chinese, Japanese and Korean.
+//! These fonts are far too large for flash, so the glyphs are read from a font file on the SD card
+//! as the pages need them and the most recently used ones are kept in memory.
+//!
+//! The file at [`PATH`] starts with a header:
+//! - 4 bytes magic `CPGL`
+//! - u8 glyph width and u8 glyph height in pixels
+//! - u16 number of ranges
+//!
+//! A 12 byte entry for each range of code points follows, sorted by the first code point:
+//! - u32 first code point
+//! - u32 number of code points
+//! - u32 offset of the bitmap of the first code point from the start of the file
+//!
+//! The bitmaps of a range follow each other. Each is a row after another from the top with the
+//! leftmost pixel in the highest bit, a set bit for ink and each row padded to a full byte. All
+//! integers are little endian.
+//!
+//! The memory for the glyphs is taken from the heap once when the file is opened, so loading the
+//! glyphs for a page doesn't allocate.
+
+use alloc::vec;
+use alloc::vec::Vec;
+use core::cell::RefCell;
+
+use defmt::warn;
+use embedded_graphics::Drawable;
+use embedded_graphics::image::{Image, ImageRaw};
+use embedded_graphics::pixelcolor::BinaryColor;
+use embedded_graphics::prelude::{DrawTarget, Point, Size};
+use embedded_sdmmc::{Mode, RawFile};
+
+use crate::SharedSdCard;
+use crate::memory;
+use crate::sd_card::{OpenError, ReadPriority, SdCard, VolumeError};
+
+const PATH: &str = "/GLYPHS.BIN";
+const MAGIC: [u8; 4] = *b"CPGL";
+const HEADER_SIZE: usize = 8;
+const RANGE_SIZE: usize = 12;
+/// Enough for the scripts of a few languages
+const MAXIMUM_RANGES: usize = 256;
+/// Larger glyphs wouldn't fit on a line with the margins
+const MAXIMUM_GLYPH_SIZE: u8 = 64;
+/// Bytes of the bitmap of the largest glyph
+const MAXIMUM_BITMAP_SIZE: usize =
+    (MAXIMUM_GLYPH_SIZE as usize).div_ceil(8) * MAXIMUM_GLYPH_SIZE as usize;
+
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum OpenGlyphsError {
+    #[error("No SD card to read glyphs from")]
+    NoSdCard,
+    #[error("Failed to open glyph file")]
+    Open(#[from] OpenError),
+    #[error("Failed to read glyph file")]
+    Read(VolumeError),
+    #[error("File is not a glyph file")]
+    NotGlyphs,
+    #[error("Glyph file ends early")]
+    Truncated,
+    #[error("Glyphs are empty or larger than the maximum size")]
+    InvalidSize,
+    #[error("Glyph file has more ranges than the maximum")]
+    TooManyRanges(u16),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum LoadGlyphsError {
+    #[error("SD card was removed")]
+    NoSdCard,
+    #[error("Failed to open glyph file")]
+    Open(#[from] OpenError),
+    #[error("Failed to read glyph file")]
+    Read(VolumeError),
+    #[error("Glyph file ends early")]
+    Truncated,
+}
+
+/// Code points from the first on that have a glyph in the file
+#[derive(Debug, Clone, Copy)]
+struct Range {
+    first: u32,
+    count: u32,
+    /// Offset of the bitmap of the first code point in the file
+    offset: u32,
+}
+
+/// Glyphs in memory. The slots are reused for other glyphs once all are taken.
+struct Slots {
+    /// Glyphs that fit into the memory of the cache
+    capacity: usize,
+    characters: Vec<char>,
+    /// When the glyph in a slot was last needed for a page, to replace the least recently used one
+    last_used: Vec<u32>,
+    /// The bitmaps of all slots one after another
+    bitmaps: Vec<u8>,
+    /// Counts up for each page
+    clock: u32,
+    /// Characters of the page that still need to be loaded, kept to not allocate for each page
+    missing: Vec<char>,
+}
+
+/// Keeps the recently used glyphs of the font file. Loading the glyphs needs the SD card while
+/// drawing them doesn't, so the glyphs for a page are loaded before it is drawn.
+pub(crate) struct GlyphCache {
+    sd_card: &'static SharedSdCard,
+    size: Size,
+    ranges: Vec<Range>,
+    slots: RefCell<Slots>,
+}
+
+impl GlyphCache {
+    pub(crate) async fn open(sd_card: &'static SharedSdCard) -> Result<Self, OpenGlyphsError> {
+        let mut card = sd_card.lock().await;
+        let card = card.as_mut().ok_or(OpenGlyphsError::NoSdCard)?;
+        let file = card.open(PATH, Mode::ReadOnly).await?;
+        let result = read_ranges(card, file).await;
+
+        if let Err(error) = card.close(file).await {
+            warn!("Failed to close glyph file: {:?}", error);
+        }
+
+        let (size, ranges) = result?;
+        let capacity = memory::GLYPH_CACHE_SIZE / bitmap_size(size);
+        Ok(Self {
+            sd_card,
+            size,
+            ranges,
+            slots: RefCell::new(Slots {
+                capacity,
+                characters: Vec::with_capacity(capacity),
+                last_used: Vec::with_capacity(capacity),
+                bitmaps: Vec::with_capacity(capacity * bitmap_size(size)),
+                clock: 0,
+                missing: Vec::with_capacity(capacity),
+            }),
+        })
+    }
+
+    /// Size of every glyph in pixels
+    pub(crate) fn size(&self) -> Size {
+        self.size
+    }
+
+    /// Whether the font file has a glyph for the character, even if it is not loaded yet
+    pub(crate) fn contains(&self, character: char) -> bool {
+        self.range(character).is_some()
+    }
+
+    fn range(&self, character: char) -> Option<&Range> {
+        let code_point = u32::from(character);
+        let index = self
+            .ranges
+            .partition_point(|range| range.first <= code_point)
+            .checked_sub(1)?;
+        let range = &self.ranges[index];
+        (code_point - range.first < range.count).then_some(range)
+    }
+
+    /// Loads the glyphs of the characters in the text that are not in memory yet. If the text needs
+    /// more glyphs than fit into memory, the rest is left out.
+    pub(crate) async fn load(&self, text: &str) -> Result<(), LoadGlyphsError> {
+        // Taken out while loading, as inserting the glyphs borrows the slots
+        let mut missing = core::mem::take(&mut self.slots.borrow_mut().missing);
+        self.mark_used(text, &mut missing);
+        let result = self.load_into_slots(&missing).await;
+        self.slots.borrow_mut().missing = missing;
+        result
+    }
+
+    async fn load_into_slots(&self, missing: &[char]) -> Result<(), LoadGlyphsError> {
+        if missing.is_empty() {
+            return Ok(());
+        }
+
+        let mut card = self.sd_card.lock().await;
+        let card = card.as_mut().ok_or(LoadGlyphsError::NoSdCard)?;
+        let file = card.open(PATH, Mode::ReadOnly).await?;
+        let result = self.load_missing(card, file, missing).await;
+
+        if let Err(error) = card.close(file).await {
+            warn!("Failed to close glyph file: {:?}", error);
+        }
+
+        result
+    }
+
+    /// Marks the glyphs for the text that are in memory as used and collects the others, at most
+    /// as many as fit into memory
+    fn mark_used(&self, text: &str, missing: &mut Vec<char>) {
+        let mut slots = self.slots.borrow_mut();
+        slots.clock = slots.clock.wrapping_add(1);
+        let clock = slots.clock;
+
+        missing.clear();
+        for character in text.chars().filter(|character| !character.is_ascii()) {
+            if let Some(slot) = slots
+                .characters
+                .iter()
+                .position(|&cached| cached == character)
+            {
+                slots.last_used[slot] = clock;
+            } else if missing.len() < slots.capacity
+                && self.contains(character)
+                && !missing.contains(&character)
+            {
+                missing.push(character);
+            }
+        }
+    }
+
+    async fn load_missing(
+        &self,
+        sd_card: &mut SdCard,
+        file: RawFile,
+        missing: &[char],
+    ) -> Result<(), LoadGlyphsError> {
+        let bitmap_size = bitmap_size(self.size);
+        let mut bitmap = [0; MAXIMUM_BITMAP_SIZE];
+        let bitmap = &mut bitmap[..bitmap_size];
+        for &character in missing {
+            let Some(range) = self.range(character) else {
+                continue;
+            };
+
+            // Can't truncate as glyphs are small
+            let offset = range.offset + (u32::from(character) - range.first) * bitmap_size as u32;
+            let read = sd_card
+                .read_at(file, offset, bitmap, ReadPriority::Interactive)
+                .await
+                .map_err(LoadGlyphsError::Read)?;
+            if read < bitmap.len() {
+                return Err(LoadGlyphsError::Truncated);
+            }
+
+            if !self.insert(character, bitmap) {
+                warn!("Glyph cache is full, leaving out the rest of the glyphs");
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Puts the glyph into a free slot or the least recently used one. Returns false if all slots
+    /// hold glyphs for the current page.
+    fn insert(&self, character: char, bitmap: &[u8]) -> bool {
+        let mut slots = self.slots.borrow_mut();
+        let clock = slots.clock;
+
+        if slots.characters.len() < slots.capacity {
+            slots.characters.push(character);
+            slots.last_used.push(clock);
+            slots.bitmaps.extend_from_slice(bitmap);
+            return true;
+        }
+
+        // Glyphs needed for the current page are kept
+        let least_recently_used = slots
+            .last_used
+            .iter()
+            .enumerate()
+            .filter(|&(_, &last_used)| last_used != clock)
+            .max_by_key(|&(_, &last_used)| clock.wrapping_sub(last_used));
+        let Some((slot, _)) = least_recently_used else {
+            return false;
+        };
+
+        slots.characters[slot] = character;
+        slots.last_used[slot] = clock;
+        slots.bitmaps[slot * bitmap.len()..(slot + 1) * bitmap.len()].copy_from_slice(bitmap);
+        true
+    }
+
+    /// Draws the glyph with its top left at the position. Glyphs that are not loaded are left out.
+    pub(crate) fn draw<D>(
+        &self,
+        character: char,
+        target: &mut D,
+        position: Point,
+    ) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = BinaryColor>,
+    {
+        let slots = self.slots.borrow();
+        let Some(slot) = slots
+            .characters
+            .iter()
+            .position(|&cached| cached == character)
+        else {
+            return Ok(());
+        };
+
+        let bitmap_size = bitmap_size(self.size);
+        let bitmap = &slots.bitmaps[slot * bitmap_size..(slot + 1) * bitmap_size];
+        let image = ImageRaw::<BinaryColor>::new(bitmap, self.size.width);
+        Image::new(&image, position).draw(target)
+    }
+}
+
+/// Bytes of the bitmap of a glyph of the size
+fn bitmap_size(size: Size) -> usize {
+    // Can't truncate as glyphs are small
+    size.width.div_ceil(8) as usize * size.height as usize
+}
+
+async fn read_ranges(
+    sd_card: &mut SdCard,
+    file: RawFile,
+) -> Result<(Size, Vec<Range>), OpenGlyphsError> {
+    let mut header = [0; HEADER_SIZE];
+    read_exact(sd_card, file, 0, &mut header).await?;
+    if header[..4] != MAGIC {
+        return Err(OpenGlyphsError::NotGlyphs);
+    }
+
+    let (width, height) = (header[4], header[5]);
+    if !(1..=MAXIMUM_GLYPH_SIZE).contains(&width) || !(1..=MAXIMUM_GLYPH_SIZE).contains(&height) {
+        return Err(OpenGlyphsError::InvalidSize);
+    }
+
+    let count = u16::from_le_bytes([header[6], header[7]]);
+    if usize::from(count) > MAXIMUM_RANGES {
+        return Err(OpenGlyphsError::TooManyRanges(count));
+    }
+
+    let mut entries = vec![0; usize::from(count) * RANGE_SIZE];
+    // Can't truncate as the header is small
+    read_exact(sd_card, file, HEADER_SIZE as u32, &mut entries).await?;
+    let ranges = entries
+        .chunks_exact(RANGE_SIZE)
+        .map(|entry| Range {
+            first: u32_at(entry, 0),
+            count: u32_at(entry, 4),
+            offset: u32_at(entry, 8),
+        })
+        .collect();
+
+    Ok((Size::new(u32::from(width), u32::from(height)), ranges))
+}
+
+async fn read_exact(
+    sd_card: &mut SdCard,
+    file: RawFile,
+    offset: u32,
+    buffer: &mut [u8],
+) -> Result<(), OpenGlyphsError> {
+    let read = sd_card
+        .read_at(file, offset, buffer, ReadPriority::Interactive)
+        .await
+        .map_err(OpenGlyphsError::Read)?;
+
+    if read < buffer.len() {
+        return Err(OpenGlyphsError::Truncated);
+    }
+
+    Ok(())
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([
+        bytes[offset],
+        bytes[offset + 1],
+        bytes[offset + 2],
+        bytes[offset + 3],
+    ])
+}
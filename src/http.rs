@@ -0,0 +1,479 @@
+//! Just enough HTTP/1.1 for the clients on the reader, like the progress sync and the library sync.
+//! Each request gets its own connection that the server closes after the response, which saves
+//! keeping track of connections. Bodies are decoded as they arrive, so downloads don't need to fit
+//! into memory. There is no TLS, so only `http://` URLs work.
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+/// Responses start with a head that is usually a few hundred bytes
+const MAXIMUM_HEAD_SIZE: usize = 4 * 1024;
+const BASE64_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum HttpError {
+    #[cfg(feature = "radio")]
+    #[error("Failed to look up the server address")]
+    Resolve,
+    #[cfg(feature = "radio")]
+    #[error("Failed to connect to the server")]
+    Connect(embassy_net::tcp::ConnectError),
+    #[cfg(feature = "radio")]
+    #[error("Connection to the server failed")]
+    Connection(embassy_net::tcp::Error),
+    #[error("Response head is larger than the maximum")]
+    HeadTooLarge,
+    #[error("Response body is larger than the maximum")]
+    BodyTooLarge,
+    #[error("Response is not valid HTTP")]
+    InvalidResponse,
+    #[error("Connection closed before the end of the response")]
+    Truncated,
+}
+
+/// Where a server is, from a URL like `http://books.example.com:8080/dav`
+#[derive(Debug, Clone, PartialEq, Eq, defmt::Format)]
+pub(crate) struct Url {
+    pub(crate) host: String,
+    pub(crate) port: u16,
+    /// Starts with a slash and has none at the end, or is empty for the root
+    pub(crate) path: String,
+}
+
+impl Url {
+    /// None if it isn't a plain HTTP URL
+    pub(crate) fn parse(url: &str) -> Option<Self> {
+        let url = url.strip_prefix("http://")?;
+        let (authority, path) = match url.find('/') {
+            Some(index) => url.split_at(index),
+            None => (url, ""),
+        };
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) => (host, port.parse().ok()?),
+            None => (authority, 80),
+        };
+
+        if host.is_empty() {
+            return None;
+        }
+
+        Some(Self {
+            host: String::from(host),
+            port,
+            path: String::from(path.trim_end_matches('/')),
+        })
+    }
+
+    /// Head of a request for the path below the path of the URL. The body length is left out for
+    /// requests without a body.
+    pub(crate) fn request(
+        &self,
+        method: &str,
+        path: &str,
+        headers: &[(&str, &str)],
+        body_length: Option<usize>,
+    ) -> String {
+        let mut head = format!(
+            "{method} {}{path} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n",
+            self.path, self.host
+        );
+        for (name, value) in headers {
+            head.push_str(&format!("{name}: {value}\r\n"));
+        }
+        if let Some(length) = body_length {
+            head.push_str(&format!("Content-Length: {length}\r\n"));
+        }
+        head.push_str("\r\n");
+        head
+    }
+}
+
+/// Value of the header for HTTP basic authentication
+pub(crate) fn basic_authorization(username: &str, password: &str) -> String {
+    let credentials = format!("{username}:{password}");
+    let mut encoded = String::from("Basic ");
+    for chunk in credentials.as_bytes().chunks(3) {
+        let bytes = [
+            chunk[0],
+            chunk.get(1).copied().unwrap_or_default(),
+            chunk.get(2).copied().unwrap_or_default(),
+        ];
+        let group = u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]);
+        for index in 0..4 {
+            if index > chunk.len() {
+                encoded.push('=');
+            } else {
+                // Can't truncate as it is masked to 6 bits
+                let sextet = (group >> (18 - 6 * index)) & 0x3F;
+                encoded.push(char::from(BASE64_ALPHABET[sextet as usize]));
+            }
+        }
+    }
+    encoded
+}
+
+/// How the end of the body is found
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+enum Framing {
+    Length(u32),
+    Chunked,
+    /// The body ends when the server closes the connection
+    Close,
+}
+
+/// Status line and headers of a response that are needed to read it
+#[derive(Debug, Clone, PartialEq, Eq, defmt::Format)]
+pub(crate) struct Head {
+    pub(crate) status: u16,
+    framing: Framing,
+}
+
+impl Head {
+    pub(crate) fn is_success(&self) -> bool {
+        (200..300).contains(&self.status)
+    }
+}
+
+/// The head if the bytes contain all of it, and where the body starts
+fn parse_head(bytes: &[u8]) -> Result<Option<(Head, usize)>, HttpError> {
+    let Some(end) = bytes.windows(4).position(|window| window == b"\r\n\r\n") else {
+        return if bytes.len() > MAXIMUM_HEAD_SIZE {
+            Err(HttpError::HeadTooLarge)
+        } else {
+            Ok(None)
+        };
+    };
+
+    let head = core::str::from_utf8(&bytes[..end]).map_err(|_| HttpError::InvalidResponse)?;
+    let mut lines = head.split("\r\n");
+    let status = lines
+        .next()
+        .and_then(|line| line.split(' ').nth(1))
+        .and_then(|status| status.parse().ok())
+        .ok_or(HttpError::InvalidResponse)?;
+
+    let mut framing = Framing::Close;
+    for line in lines {
+        let Some((name, value)) = line.split_once(':') else {
+            continue;
+        };
+
+        let (name, value) = (name.trim(), value.trim());
+        if name.eq_ignore_ascii_case("transfer-encoding") && value.eq_ignore_ascii_case("chunked") {
+            framing = Framing::Chunked;
+        } else if name.eq_ignore_ascii_case("content-length") && framing == Framing::Close {
+            let length = value.parse().map_err(|_| HttpError::InvalidResponse)?;
+            framing = Framing::Length(length);
+        }
+    }
+
+    // Responses with these statuses have no body whatever the headers say
+    if status == 204 || status == 304 || (100..200).contains(&status) {
+        framing = Framing::Length(0);
+    }
+
+    Ok(Some((Head { status, framing }, end + 4)))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+enum ChunkState {
+    /// Hexadecimal size so far and whether the extensions after it started
+    Size(u32, bool),
+    Data(u32),
+    /// Line break after the data
+    DataEnd(u8),
+    /// Optional trailer headers after the last chunk, skipped until the empty line
+    Trailer {
+        is_line_empty: bool,
+    },
+    Done,
+}
+
+/// Takes the body out of the bytes as they arrive from the connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+struct Body {
+    framing: Framing,
+    /// Bytes left for bodies with a length
+    remaining: u32,
+    chunk: ChunkState,
+}
+
+impl Body {
+    fn new(framing: Framing) -> Self {
+        Self {
+            framing,
+            remaining: match framing {
+                Framing::Length(length) => length,
+                _ => 0,
+            },
+            chunk: ChunkState::Size(0, false),
+        }
+    }
+
+    fn is_done(&self) -> bool {
+        match self.framing {
+            Framing::Length(_) => self.remaining == 0,
+            Framing::Chunked => self.chunk == ChunkState::Done,
+            Framing::Close => false,
+        }
+    }
+
+    /// Takes the next piece of the body from the input and moves the input past it. Returns None
+    /// when the input is used up without finding more of the body.
+    fn next<'a>(&mut self, input: &mut &'a [u8]) -> Result<Option<&'a [u8]>, HttpError> {
+        match self.framing {
+            Framing::Close => Ok(take(input, input.len())),
+            Framing::Length(_) => {
+                // Can't truncate as it is at most the remaining length
+                let data = take(input, (self.remaining as usize).min(input.len()));
+                if let Some(data) = data {
+                    self.remaining -= data.len() as u32;
+                }
+                Ok(data)
+            }
+            Framing::Chunked => self.next_chunked(input),
+        }
+    }
+
+    fn next_chunked<'a>(&mut self, input: &mut &'a [u8]) -> Result<Option<&'a [u8]>, HttpError> {
+        while let Some((&byte, rest)) = input.split_first() {
+            self.chunk = match self.chunk {
+                ChunkState::Data(remaining) => {
+                    // Can't truncate as it is at most the remaining length
+                    let data = take(input, (remaining as usize).min(input.len()));
+                    let length = data.map_or(0, <[u8]>::len) as u32;
+                    self.chunk = match remaining - length {
+                        0 => ChunkState::DataEnd(0),
+                        remaining => ChunkState::Data(remaining),
+                    };
+                    return Ok(data);
+                }
+                ChunkState::Size(size, is_extension) => {
+                    *input = rest;
+                    match (byte, is_extension) {
+                        (b'\n', _) if size == 0 => ChunkState::Trailer {
+                            is_line_empty: true,
+                        },
+                        (b'\n', _) => ChunkState::Data(size),
+                        (b'\r', _) | (_, true) => ChunkState::Size(size, is_extension),
+                        (b';', false) => ChunkState::Size(size, true),
+                        (_, false) => {
+                            let digit = char::from(byte)
+                                .to_digit(16)
+                                .ok_or(HttpError::InvalidResponse)?;
+                            let size = size
+                                .checked_mul(16)
+                                .and_then(|size| size.checked_add(digit))
+                                .ok_or(HttpError::InvalidResponse)?;
+                            ChunkState::Size(size, false)
+                        }
+                    }
+                }
+                ChunkState::DataEnd(seen) => {
+                    *input = rest;
+                    match byte {
+                        b'\n' => ChunkState::Size(0, false),
+                        b'\r' if seen == 0 => ChunkState::DataEnd(1),
+                        _ => return Err(HttpError::InvalidResponse),
+                    }
+                }
+                ChunkState::Trailer { is_line_empty } => {
+                    *input = rest;
+                    match byte {
+                        b'\n' if is_line_empty => ChunkState::Done,
+                        b'\n' => ChunkState::Trailer {
+                            is_line_empty: true,
+                        },
+                        b'\r' => ChunkState::Trailer { is_line_empty },
+                        _ => ChunkState::Trailer {
+                            is_line_empty: false,
+                        },
+                    }
+                }
+                ChunkState::Done => return Ok(None),
+            };
+        }
+
+        Ok(None)
+    }
+}
+
+/// Splits off the first bytes of the input, None if there are none
+fn take<'a>(input: &mut &'a [u8], length: usize) -> Option<&'a [u8]> {
+    if length == 0 {
+        return None;
+    }
+
+    let (data, rest) = input.split_at(length);
+    *input = rest;
+    Some(data)
+}
+
+#[cfg(feature = "radio")]
+pub(crate) use network::{Response, send};
+
+#[cfg(feature = "radio")]
+mod network {
+    use alloc::vec::Vec;
+
+    use embassy_net::dns::DnsQueryType;
+    use embassy_net::tcp::TcpSocket;
+    use embassy_net::{IpAddress, Stack};
+    use embassy_time::Duration;
+
+    use super::{Body, Framing, Head, HttpError, Url, parse_head};
+
+    const TIMEOUT: Duration = Duration::from_secs(15);
+    /// Bytes read from the connection at once
+    const READ_SIZE: usize = 512;
+
+    /// Response whose body is read as it arrives
+    pub(crate) struct Response<'a> {
+        pub(crate) head: Head,
+        socket: TcpSocket<'a>,
+        body: Body,
+        /// Bytes from the connection that were not decoded yet
+        received: [u8; READ_SIZE],
+        start: usize,
+        end: usize,
+    }
+
+    impl Response<'_> {
+        /// Reads the next piece of the body into the buffer and returns its length, which is 0 at
+        /// the end of the body
+        pub(crate) async fn read(&mut self, buffer: &mut [u8]) -> Result<usize, HttpError> {
+            loop {
+                if self.body.is_done() || buffer.is_empty() {
+                    return Ok(0);
+                }
+
+                // Only take what fits into the buffer so the rest stays for the next read
+                let available = &self.received[self.start..self.end];
+                let mut input = &available[..available.len().min(buffer.len())];
+                let length = input.len();
+                let data = self.body.next(&mut input)?;
+                self.start += length - input.len();
+
+                if let Some(data) = data {
+                    buffer[..data.len()].copy_from_slice(data);
+                    return Ok(data.len());
+                }
+
+                if self.start < self.end {
+                    continue;
+                }
+
+                let read = self
+                    .socket
+                    .read(&mut self.received)
+                    .await
+                    .map_err(HttpError::Connection)?;
+                if read == 0 {
+                    return match self.body.framing {
+                        Framing::Close => Ok(0),
+                        _ => Err(HttpError::Truncated),
+                    };
+                }
+
+                self.start = 0;
+                self.end = read;
+            }
+        }
+
+        /// Reads the whole body into memory if it is not larger than the maximum size
+        pub(crate) async fn read_to_end(
+            &mut self,
+            maximum_size: usize,
+        ) -> Result<Vec<u8>, HttpError> {
+            let mut body = Vec::new();
+            let mut buffer = [0; READ_SIZE];
+            loop {
+                let read = self.read(&mut buffer).await?;
+                if read == 0 {
+                    return Ok(body);
+                }
+
+                if body.len() + read > maximum_size {
+                    return Err(HttpError::BodyTooLarge);
+                }
+                body.extend_from_slice(&buffer[..read]);
+            }
+        }
+    }
+
+    impl Drop for Response<'_> {
+        fn drop(&mut self) {
+            self.socket.abort();
+        }
+    }
+
+    /// Connects to the server of the URL, sends the request and reads the head of the response.
+    /// The buffers are for the connection and need to outlive the response.
+    pub(crate) async fn send<'a>(
+        stack: Stack<'a>,
+        url: &Url,
+        head: &str,
+        body: &[u8],
+        receive_buffer: &'a mut [u8],
+        send_buffer: &'a mut [u8],
+    ) -> Result<Response<'a>, HttpError> {
+        let address = match url.host.parse() {
+            Ok(address) => IpAddress::Ipv4(address),
+            Err(_) => *stack
+                .dns_query(&url.host, DnsQueryType::A)
+                .await
+                .map_err(|_| HttpError::Resolve)?
+                .first()
+                .ok_or(HttpError::Resolve)?,
+        };
+
+        let mut socket = TcpSocket::new(stack, receive_buffer, send_buffer);
+        socket.set_timeout(Some(TIMEOUT));
+        socket
+            .connect((address, url.port))
+            .await
+            .map_err(HttpError::Connect)?;
+
+        for part in [head.as_bytes(), body] {
+            let mut part = part;
+            while !part.is_empty() {
+                let written = socket.write(part).await.map_err(HttpError::Connection)?;
+                part = &part[written..];
+            }
+        }
+        socket.flush().await.map_err(HttpError::Connection)?;
+
+        let mut received = Vec::new();
+        let mut buffer = [0; READ_SIZE];
+        let (head, body_start) = loop {
+            let read = socket
+                .read(&mut buffer)
+                .await
+                .map_err(HttpError::Connection)?;
+            if read == 0 {
+                return Err(HttpError::Truncated);
+            }
+
+            received.extend_from_slice(&buffer[..read]);
+            if let Some(head) = parse_head(&received)? {
+                break head;
+            }
+        };
+
+        // What came after the head is the start of the body. The head is read in pieces of the
+        // same size, so it fits.
+        let leftover = &received[body_start..];
+        let mut response = Response {
+            body: Body::new(head.framing),
+            head,
+            socket,
+            received: [0; READ_SIZE],
+            start: 0,
+            end: leftover.len(),
+        };
+        response.received[..leftover.len()].copy_from_slice(leftover);
+        Ok(response)
+    }
+}
@@ -0,0 +1,167 @@
+//! Knuth–Liang hyphenation with the patterns TeX uses, so long words can be broken at the end of a
+//! line instead of leaving a gap. The patterns of a language are a text file in the hyphenation
+//! directory on the SD card, e.g. `/HYPHEN/EN_US.TXT`, with the patterns separated by whitespace
+//! like `.ach4 a1b` and comment lines starting with `%`. The language is picked in the settings.
+
+use alloc::format;
+use alloc::vec::Vec;
+
+use crate::memory::MAXIMUM_WORD_SIZE;
+use crate::pagination::{Breaks, Hyphenator};
+use crate::sd_card::{ReadFileError, ReadPriority, SdCard};
+
+const DIRECTORY: &str = "/HYPHEN";
+const EXTENSION: &str = "TXT";
+/// Complete pattern sets are larger, so they need to be trimmed to the common patterns to fit into
+/// memory. Also keeps the offsets of the patterns within 16 bits.
+const MAXIMUM_FILE_SIZE: u32 = 24 * 1024;
+/// Breaks need to leave at least this many letters on the line
+const MINIMUM_PREFIX: usize = 2;
+/// Breaks need to move at least this many letters to the next line
+const MINIMUM_SUFFIX: usize = 3;
+
+/// Languages are named after their pattern file which is limited to FAT short names by
+/// embedded_sdmmc
+pub(crate) type Language = heapless::String<8>;
+
+/// Hyphenation patterns of a language
+pub(crate) struct Patterns {
+    content: Vec<u8>,
+    /// Offsets of the patterns in the content sorted by their letters
+    patterns: Vec<u16>,
+    /// Letters of the longest pattern, as there is no need to look up longer parts of words
+    longest: usize,
+}
+
+impl Patterns {
+    pub(crate) async fn load(sd_card: &mut SdCard, language: &str) -> Result<Self, ReadFileError> {
+        let content = sd_card
+            .read_file(
+                &format!("{DIRECTORY}/{language}.{EXTENSION}"),
+                MAXIMUM_FILE_SIZE,
+                ReadPriority::Interactive,
+            )
+            .await?;
+
+        Ok(Self::parse(content))
+    }
+
+    fn parse(content: Vec<u8>) -> Self {
+        let mut patterns = Vec::new();
+        let mut longest = 0;
+        let mut offset = 0;
+        for line in content.split(|&byte| byte == b'\n') {
+            if !line.starts_with(b"%") {
+                let mut start = None;
+                // The extra whitespace ends the last pattern of the line
+                for (index, &byte) in line.iter().chain(b" ").enumerate() {
+                    match (start, byte.is_ascii_whitespace()) {
+                        (None, false) => start = Some(index),
+                        (Some(pattern_start), true) => {
+                            let pattern = &line[pattern_start..index];
+                            longest = longest.max(letters(pattern).count());
+                            // Can't truncate as the file is smaller than 64 KiB
+                            patterns.push((offset + pattern_start) as u16);
+                            start = None;
+                        }
+                        _ => {}
+                    }
+                }
+            }
+
+            offset += line.len() + 1;
+        }
+
+        let mut patterns = Self {
+            content,
+            patterns,
+            longest,
+        };
+        let mut offsets = core::mem::take(&mut patterns.patterns);
+        offsets.sort_unstable_by(|first, second| {
+            letters(patterns.pattern(*first)).cmp(letters(patterns.pattern(*second)))
+        });
+        patterns.patterns = offsets;
+        patterns
+    }
+
+    /// The pattern with its digits starting at the offset
+    fn pattern(&self, offset: u16) -> &[u8] {
+        let pattern = &self.content[usize::from(offset)..];
+        let end = pattern
+            .iter()
+            .position(|byte| byte.is_ascii_whitespace())
+            .unwrap_or(pattern.len());
+        &pattern[..end]
+    }
+
+    fn find(&self, part: &[u8]) -> Option<&[u8]> {
+        let index = self
+            .patterns
+            .binary_search_by(|offset| letters(self.pattern(*offset)).cmp(part.iter().copied()))
+            .ok()?;
+        Some(self.pattern(self.patterns[index]))
+    }
+}
+
+impl Hyphenator for Patterns {
+    fn breaks(&self, word: &str) -> Breaks {
+        if word.chars().count() < MINIMUM_PREFIX + MINIMUM_SUFFIX || word.len() > MAXIMUM_WORD_SIZE
+        {
+            return Breaks::new();
+        }
+
+        // The dots mark the start and end of the word for patterns that only apply there. Fits, as
+        // the word is at most the maximum size.
+        let mut dotted = heapless::Vec::<u8, { MAXIMUM_WORD_SIZE + 2 }>::new();
+        let _ = dotted.push(b'.');
+        let _ = dotted.extend_from_slice(word.as_bytes());
+        let _ = dotted.push(b'.');
+        dotted.make_ascii_lowercase();
+
+        // The value at an index is for between the letter before and the letter at the index
+        let mut values = [0; MAXIMUM_WORD_SIZE + 3];
+        for start in 0..dotted.len() {
+            let end = dotted.len().min(start + self.longest);
+            for end in start + 1..=end {
+                let Some(pattern) = self.find(&dotted[start..end]) else {
+                    continue;
+                };
+
+                for (index, value) in digits(pattern) {
+                    values[start + index] = values[start + index].max(value);
+                }
+            }
+        }
+
+        // Odd values allow a break. The offset into the word is one less because of the dot.
+        (1..word.len())
+            .filter(|&offset| values[offset + 1] % 2 == 1 && word.is_char_boundary(offset))
+            .filter(|&offset| {
+                word[..offset].chars().count() >= MINIMUM_PREFIX
+                    && word[offset..].chars().count() >= MINIMUM_SUFFIX
+            })
+            .collect()
+    }
+}
+
+/// The letters of the pattern without the digits
+fn letters(pattern: &[u8]) -> impl Iterator<Item = u8> {
+    pattern
+        .iter()
+        .copied()
+        .filter(|byte| !byte.is_ascii_digit())
+}
+
+/// The digits of the pattern with the number of letters before them
+fn digits(pattern: &[u8]) -> impl Iterator<Item = (usize, u8)> {
+    let mut index = 0;
+    pattern.iter().filter_map(move |&byte| {
+        if byte.is_ascii_digit() {
+            Some((index, byte - b'0'))
+        } else {
+            index += 1;
+            None
+        }
+    })
+}
@@ -0,0 +1,243 @@
+//! Uncompressed BMP decoding with 1, 4, 8, 24 and 32 bits per pixel. BMP can be exported by most
+//! image editors and each row can be read on its own without decompressing anything before it.
+
+use embedded_graphics::prelude::Size;
+use embedded_sdmmc::{Mode, RawFile};
+
+use crate::image::{RowSink, luma, try_zeroed};
+use crate::sd_card::{OpenError, ReadPriority, SdCard, VolumeError};
+
+const FILE_HEADER_SIZE: u32 = 14;
+/// File header and the BITMAPINFOHEADER which the newer header versions extend
+const HEADER_SIZE: usize = FILE_HEADER_SIZE as usize + 40;
+/// Uncompressed
+const BI_RGB: u32 = 0;
+/// Black for 0 and white for 1, each in blue, green, red and a reserved byte
+const MONOCHROME_PALETTE: [u8; 8] = [0, 0, 0, 0, 0xFF, 0xFF, 0xFF, 0];
+pub(crate) const MONOCHROME_HEADER_SIZE: usize = HEADER_SIZE + MONOCHROME_PALETTE.len();
+/// Uncompressed with color masks which are assumed to be the usual BGRA order
+const BI_BITFIELDS: u32 = 3;
+
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum DecodeError<E> {
+    #[error("Failed to open image file")]
+    Open(#[from] OpenError),
+    #[error("Failed to read image file")]
+    Read(VolumeError),
+    #[error("File is not a BMP image")]
+    NotBmp,
+    #[error("Image file ends early")]
+    Truncated,
+    #[error("Image header is invalid")]
+    InvalidHeader,
+    #[error("Image uses a header version, bit depth or compression that is not supported")]
+    Unsupported,
+    #[error("Not enough memory to decode image")]
+    OutOfMemory,
+    #[error("Failed to process decoded row")]
+    Sink(E),
+}
+
+fn u16_at(header: &[u8; HEADER_SIZE], offset: usize) -> u16 {
+    u16::from_le_bytes([header[offset], header[offset + 1]])
+}
+
+fn u32_at(header: &[u8; HEADER_SIZE], offset: usize) -> u32 {
+    u32::from_le_bytes([
+        header[offset],
+        header[offset + 1],
+        header[offset + 2],
+        header[offset + 3],
+    ])
+}
+
+/// Header of an uncompressed image with 1 bit per pixel, 0 for black and 1 for white. The rows
+/// follow from the bottom up, each padded to a multiple of 4 bytes.
+pub(crate) fn monochrome_header(size: Size) -> [u8; MONOCHROME_HEADER_SIZE] {
+    let row_size = size.width.div_ceil(32) * 4;
+    let image_size = row_size * size.height;
+    // Can't truncate as the header is a few bytes
+    let data_offset = MONOCHROME_HEADER_SIZE as u32;
+
+    let mut header = [0; MONOCHROME_HEADER_SIZE];
+    let mut put = |offset: usize, bytes: &[u8]| {
+        header[offset..offset + bytes.len()].copy_from_slice(bytes);
+    };
+    put(0, b"BM");
+    put(2, &(data_offset + image_size).to_le_bytes());
+    put(10, &data_offset.to_le_bytes());
+    put(14, &40_u32.to_le_bytes());
+    put(18, &size.width.to_le_bytes());
+    put(22, &size.height.to_le_bytes());
+    // Planes and bits per pixel
+    put(26, &1_u16.to_le_bytes());
+    put(28, &1_u16.to_le_bytes());
+    put(30, &BI_RGB.to_le_bytes());
+    put(34, &image_size.to_le_bytes());
+    // Colors in the palette
+    put(46, &2_u32.to_le_bytes());
+    put(HEADER_SIZE, &MONOCHROME_PALETTE);
+    header
+}
+
+/// Decodes the BMP image at the path and hands the rows to the sink
+pub(crate) async fn decode<S: RowSink>(
+    sd_card: &mut SdCard,
+    path: &str,
+    sink: &mut S,
+) -> Result<(), DecodeError<S::Error>> {
+    let file = sd_card.open(path, Mode::ReadOnly).await?;
+    let result = decode_file(sd_card, file, 0, sink).await;
+
+    if let Err(error) = sd_card.close(file).await {
+        defmt::warn!("Failed to close file: {:?}", error);
+    }
+
+    result
+}
+
+/// Decodes the BMP image that starts at the offset of an open file, like an image within a book
+pub(crate) async fn decode_at<S: RowSink>(
+    sd_card: &mut SdCard,
+    file: RawFile,
+    offset: u32,
+    sink: &mut S,
+) -> Result<(), DecodeError<S::Error>> {
+    decode_file(sd_card, file, offset, sink).await
+}
+
+async fn read_exact<E>(
+    sd_card: &mut SdCard,
+    file: RawFile,
+    offset: u32,
+    buffer: &mut [u8],
+) -> Result<(), DecodeError<E>> {
+    let read = sd_card
+        .read_at(file, offset, buffer, ReadPriority::Background)
+        .await
+        .map_err(DecodeError::Read)?;
+    if read < buffer.len() {
+        return Err(DecodeError::Truncated);
+    }
+
+    Ok(())
+}
+
+/// Offsets in the image are from its start in the file
+async fn decode_file<S: RowSink>(
+    sd_card: &mut SdCard,
+    file: RawFile,
+    start: u32,
+    sink: &mut S,
+) -> Result<(), DecodeError<S::Error>> {
+    let mut header = [0; HEADER_SIZE];
+    read_exact(sd_card, file, start, &mut header).await?;
+    if !header.starts_with(b"BM") {
+        return Err(DecodeError::NotBmp);
+    }
+
+    let data_offset = u32_at(&header, 10);
+    let header_size = u32_at(&header, 14);
+    let width = u32_at(&header, 18).cast_signed();
+    // Rows are stored bottom up unless the height is negative
+    let height = u32_at(&header, 22).cast_signed();
+    let bits_per_pixel = u16_at(&header, 28);
+    let compression = u32_at(&header, 30);
+    let colors_used = u32_at(&header, 46);
+
+    // The old OS/2 header has a different layout
+    if header_size < 40 {
+        return Err(DecodeError::Unsupported);
+    }
+
+    if width <= 0 || height == 0 {
+        return Err(DecodeError::InvalidHeader);
+    }
+
+    match (bits_per_pixel, compression) {
+        (1 | 4 | 8 | 24, BI_RGB) | (32, BI_RGB | BI_BITFIELDS) => {}
+        _ => return Err(DecodeError::Unsupported),
+    }
+
+    let is_top_down = height < 0;
+    let width = width.unsigned_abs();
+    let height = height.unsigned_abs();
+
+    // Gray values of the color table for the indexed bit depths
+    let mut palette = [0; 256];
+    if bits_per_pixel <= 8 {
+        let maximum = 1 << bits_per_pixel;
+        let count = if colors_used == 0 {
+            maximum
+        } else {
+            colors_used.min(maximum)
+        };
+
+        // Entries are blue, green, red and an unused byte
+        let mut entries = try_zeroed(count as usize * 4).ok_or(DecodeError::OutOfMemory)?;
+        let offset = start
+            .checked_add(FILE_HEADER_SIZE)
+            .and_then(|offset| offset.checked_add(header_size))
+            .ok_or(DecodeError::InvalidHeader)?;
+        read_exact(sd_card, file, offset, &mut entries).await?;
+
+        let (entries, _) = entries.as_chunks::<4>();
+        for (gray, [blue, green, red, _]) in palette.iter_mut().zip(entries) {
+            *gray = luma(*red, *green, *blue);
+        }
+    }
+
+    // Rows are padded to 4 bytes
+    let stride = width
+        .checked_mul(u32::from(bits_per_pixel))
+        .and_then(|bits| bits.checked_add(31))
+        .map(|bits| bits / 32 * 4)
+        .ok_or(DecodeError::InvalidHeader)?;
+    let mut row = try_zeroed(stride as usize).ok_or(DecodeError::OutOfMemory)?;
+    let mut gray = try_zeroed(width as usize).ok_or(DecodeError::OutOfMemory)?;
+
+    sink.start(Size::new(width, height))
+        .map_err(DecodeError::Sink)?;
+
+    for index in 0..height {
+        let stored_index = if is_top_down {
+            index
+        } else {
+            height - 1 - index
+        };
+
+        let offset = stored_index
+            .checked_mul(stride)
+            .and_then(|offset| offset.checked_add(data_offset))
+            .and_then(|offset| offset.checked_add(start))
+            .ok_or(DecodeError::InvalidHeader)?;
+        read_exact(sd_card, file, offset, &mut row).await?;
+
+        convert_to_gray(&row, bits_per_pixel, &palette, &mut gray);
+        sink.push_row(&gray).map_err(DecodeError::Sink)?;
+    }
+
+    Ok(())
+}
+
+fn convert_to_gray(row: &[u8], bits_per_pixel: u16, palette: &[u8; 256], gray: &mut [u8]) {
+    match bits_per_pixel {
+        24 | 32 => {
+            let bytes_per_pixel = usize::from(bits_per_pixel / 8);
+            for (gray, pixel) in gray.iter_mut().zip(row.chunks_exact(bytes_per_pixel)) {
+                // Blue, green, red and for 32 bits an alpha that is usually unused
+                *gray = luma(pixel[2], pixel[1], pixel[0]);
+            }
+        }
+        _ => {
+            let depth = usize::from(bits_per_pixel);
+            let mask = (1 << depth) - 1;
+            for (pixel, gray) in gray.iter_mut().enumerate() {
+                let bit = pixel * depth;
+                let shift = 8 - depth - bit % 8;
+                let index = (usize::from(row[bit / 8]) >> shift) & mask;
+                *gray = palette[index];
+            }
+        }
+    }
+}
@@ -0,0 +1,142 @@
+//! Images from the SD card like sleep screens and book covers. The decoders hand the image over row
+//! by row as 8-bit gray to a [`RowSink`], so an image is never fully in memory.
+
+pub(crate) mod bmp;
+pub(crate) mod png;
+
+pub(crate) use crustpoint_core::dither;
+
+use alloc::vec::Vec;
+
+use embedded_graphics::prelude::{Dimensions, DrawTargetExt, OriginDimensions, Point, Size};
+
+use crate::eink_display::{DrawError, Frame};
+use crate::image::dither::{Ditherer, Method};
+use crate::power_profile;
+use crate::sd_card::SdCard;
+
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum LoadImageError {
+    #[error("Failed to decode PNG image")]
+    Png(#[from] png::DecodeError<DrawError>),
+    #[error("Failed to decode BMP image")]
+    Bmp(#[from] bmp::DecodeError<DrawError>),
+    #[error("File extension is not one of a supported image format")]
+    UnknownFormat,
+}
+
+impl LoadImageError {
+    pub(crate) fn is_not_found(&self) -> bool {
+        match self {
+            Self::Png(png::DecodeError::Open(error)) | Self::Bmp(bmp::DecodeError::Open(error)) => {
+                error.is_not_found()
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Receives a decoded image
+pub(crate) trait RowSink {
+    type Error;
+
+    /// Called once with the size of the image before any row
+    fn start(&mut self, size: Size) -> Result<(), Self::Error>;
+
+    /// The gray values of the next row from the top where 0 is black and 255 is white.
+    /// Transparent pixels are blended onto white.
+    fn push_row(&mut self, gray: &[u8]) -> Result<(), Self::Error>;
+}
+
+/// Dithers the image into the frame. Images are centered and cut off where they are larger than
+/// the frame.
+pub(crate) struct FrameSink<'a> {
+    frame: &'a mut Frame,
+    method: Method,
+    ditherer: Option<Ditherer>,
+}
+
+impl<'a> FrameSink<'a> {
+    pub(crate) fn new(frame: &'a mut Frame, method: Method) -> Self {
+        Self {
+            frame,
+            method,
+            ditherer: None,
+        }
+    }
+}
+
+impl RowSink for FrameSink<'_> {
+    type Error = DrawError;
+
+    fn start(&mut self, size: Size) -> Result<(), Self::Error> {
+        let bounds = self.frame.size();
+        // Images are at most a few thousand pixels wide, so these don't overflow
+        let origin = Point::new(
+            (bounds.width as i32 - size.width as i32) / 2,
+            (bounds.height as i32 - size.height as i32) / 2,
+        );
+
+        self.ditherer = Some(Ditherer::new(self.method, origin, size.width as usize));
+        Ok(())
+    }
+
+    fn push_row(&mut self, gray: &[u8]) -> Result<(), Self::Error> {
+        let Some(ditherer) = self.ditherer.as_mut() else {
+            return Ok(());
+        };
+
+        let area = self.frame.bounding_box();
+        ditherer.push_row(gray, &mut self.frame.clipped(&area))
+    }
+}
+
+/// Decodes the image at the path into the frame. The format is picked by the file extension.
+pub(crate) async fn load(
+    sd_card: &mut SdCard,
+    path: &str,
+    frame: &mut Frame,
+    method: Method,
+) -> Result<(), LoadImageError> {
+    let _boost = power_profile::boost();
+    let mut sink = FrameSink::new(frame, method);
+    match extension(path) {
+        Some(extension) if extension.eq_ignore_ascii_case("png") => {
+            png::decode(sd_card, path, &mut sink).await?;
+        }
+        Some(extension) if extension.eq_ignore_ascii_case("bmp") => {
+            bmp::decode(sd_card, path, &mut sink).await?;
+        }
+        _ => return Err(LoadImageError::UnknownFormat),
+    }
+
+    Ok(())
+}
+
+/// Whether the file name has the extension of a supported image format
+pub(crate) fn is_supported(file_name: &str) -> bool {
+    extension(file_name).is_some_and(|extension| {
+        extension.eq_ignore_ascii_case("png") || extension.eq_ignore_ascii_case("bmp")
+    })
+}
+
+fn extension(path: &str) -> Option<&str> {
+    let file_name = path.rsplit('/').next()?;
+    file_name.rsplit_once('.').map(|(_, extension)| extension)
+}
+
+/// Perceived brightness with the ITU-R BT.601 weights in fixed point
+fn luma(red: u8, green: u8, blue: u8) -> u8 {
+    let sum = 77 * u32::from(red) + 150 * u32::from(green) + 29 * u32::from(blue);
+    // Can't truncate as the weights add up to 256
+    (sum >> 8) as u8
+}
+
+/// Buffer allocation that fails instead of aborting for sizes that come from files or are large
+/// compared to the heap
+pub(crate) fn try_zeroed(size: usize) -> Option<Vec<u8>> {
+    let mut buffer = Vec::new();
+    buffer.try_reserve_exact(size).ok()?;
+    buffer.resize(size, 0);
+    Some(buffer)
+}
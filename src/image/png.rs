@@ -0,0 +1,570 @@
+//! Streamed PNG decoding. Rows are converted to 8-bit gray as soon as they are decompressed and
+//! handed on to a [`RowSink`]. Only two rows and the 32 KiB deflate window are in memory at a time
+//! instead of the whole image which would not fit the heap for most book covers. Interlaced images
+//! are not supported.
+
+use alloc::boxed::Box;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::mem::size_of;
+
+use embedded_graphics::prelude::Size;
+use embedded_sdmmc::{Mode, RawFile};
+use miniz_oxide::inflate::TINFLStatus;
+use miniz_oxide::inflate::core::{DecompressorOxide, decompress, inflate_flags};
+
+use crate::image::{RowSink, luma, try_zeroed};
+use crate::sd_card::{OpenError, ReadPriority, SdCard, VolumeError};
+
+pub(crate) const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1A, b'\n'];
+/// Deflate can refer back this far into the decompressed data
+const WINDOW_SIZE: usize = 32 * 1024;
+/// Compressed data is read from the SD card in pieces of this size. Also needs to fit the largest
+/// palette.
+const READ_BUFFER_SIZE: usize = 1024;
+const FLAGS: u32 =
+    inflate_flags::TINFL_FLAG_PARSE_ZLIB_HEADER | inflate_flags::TINFL_FLAG_HAS_MORE_INPUT;
+
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum DecodeError<E> {
+    #[error("Failed to open image file")]
+    Open(#[from] OpenError),
+    #[error("Failed to read image file")]
+    Read(VolumeError),
+    #[error("File is not a PNG image")]
+    NotPng,
+    #[error("Image file ends early")]
+    Truncated,
+    #[error("Image data comes before the header")]
+    MissingHeader,
+    #[error("Image header is invalid")]
+    InvalidHeader,
+    #[error("Image uses a color type, bit depth or interlacing that is not supported")]
+    Unsupported,
+    #[error("Image has a palette color type but no palette")]
+    MissingPalette,
+    #[error("Failed to decompress image data")]
+    Inflate(TINFLStatus),
+    #[error("Row uses an unknown filter type")]
+    InvalidFilter(u8),
+    #[error("Not enough memory to decode image")]
+    OutOfMemory,
+    #[error("Failed to process decoded row")]
+    Sink(E),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+enum ColorType {
+    Gray,
+    Rgb,
+    Palette,
+    GrayAlpha,
+    Rgba,
+}
+
+impl ColorType {
+    fn channels(self) -> usize {
+        match self {
+            ColorType::Gray | ColorType::Palette => 1,
+            ColorType::GrayAlpha => 2,
+            ColorType::Rgb => 3,
+            ColorType::Rgba => 4,
+        }
+    }
+}
+
+#[derive(Debug, Clone, defmt::Format)]
+struct Header {
+    width: u32,
+    height: u32,
+    color_type: ColorType,
+    bit_depth: u8,
+}
+
+impl Header {
+    fn parse<E>(data: &[u8]) -> Result<Self, DecodeError<E>> {
+        let [
+            w0,
+            w1,
+            w2,
+            w3,
+            h0,
+            h1,
+            h2,
+            h3,
+            bit_depth,
+            color_type,
+            compression,
+            filter,
+            interlace,
+        ] = *data
+        else {
+            return Err(DecodeError::InvalidHeader);
+        };
+
+        let width = u32::from_be_bytes([w0, w1, w2, w3]);
+        let height = u32::from_be_bytes([h0, h1, h2, h3]);
+        if width == 0 || height == 0 || compression != 0 || filter != 0 {
+            return Err(DecodeError::InvalidHeader);
+        }
+
+        if interlace != 0 {
+            return Err(DecodeError::Unsupported);
+        }
+
+        // Allowed combinations from the specification
+        let color_type = match (color_type, bit_depth) {
+            (0, 1 | 2 | 4 | 8 | 16) => ColorType::Gray,
+            (2, 8 | 16) => ColorType::Rgb,
+            (3, 1 | 2 | 4 | 8) => ColorType::Palette,
+            (4, 8 | 16) => ColorType::GrayAlpha,
+            (6, 8 | 16) => ColorType::Rgba,
+            _ => return Err(DecodeError::Unsupported),
+        };
+
+        Ok(Self {
+            width,
+            height,
+            color_type,
+            bit_depth,
+        })
+    }
+
+    fn bits_per_pixel(&self) -> usize {
+        self.color_type.channels() * usize::from(self.bit_depth)
+    }
+
+    /// Bytes of a row without the filter type
+    fn row_size(&self) -> Option<usize> {
+        usize::try_from(self.width)
+            .ok()?
+            .checked_mul(self.bits_per_pixel())?
+            .checked_add(7)
+            .map(|bits| bits / 8)
+    }
+
+    /// Filters work on whole pixels but at least on bytes
+    fn filter_stride(&self) -> usize {
+        self.bits_per_pixel().div_ceil(8)
+    }
+}
+
+/// Gray values of the palette entries with the transparency already applied
+struct Palette([u8; 256]);
+
+impl Palette {
+    fn new(colors: &[u8]) -> Self {
+        let mut gray = [0; 256];
+        let (colors, _) = colors.as_chunks::<3>();
+        for (entry, [red, green, blue]) in gray.iter_mut().zip(colors) {
+            *entry = luma(*red, *green, *blue);
+        }
+
+        Self(gray)
+    }
+
+    fn apply_transparency(&mut self, alpha: &[u8]) {
+        for (entry, alpha) in self.0.iter_mut().zip(alpha) {
+            *entry = blend_onto_white(*entry, *alpha);
+        }
+    }
+}
+
+fn blend_onto_white(gray: u8, alpha: u8) -> u8 {
+    let blended = u32::from(gray) * u32::from(alpha) + 255 * u32::from(255 - alpha);
+    // Can't truncate as it is at most 255 * 255 / 255
+    (blended / 255) as u8
+}
+
+fn paeth(left: u8, up: u8, up_left: u8) -> u8 {
+    let estimate = i16::from(left) + i16::from(up) - i16::from(up_left);
+    let distance_left = (estimate - i16::from(left)).abs();
+    let distance_up = (estimate - i16::from(up)).abs();
+    let distance_up_left = (estimate - i16::from(up_left)).abs();
+
+    if distance_left <= distance_up && distance_left <= distance_up_left {
+        left
+    } else if distance_up <= distance_up_left {
+        up
+    } else {
+        up_left
+    }
+}
+
+/// Reverses the filter of a row in place
+fn unfilter<E>(
+    filter: u8,
+    row: &mut [u8],
+    previous: &[u8],
+    stride: usize,
+) -> Result<(), DecodeError<E>> {
+    match filter {
+        // None
+        0 => {}
+        // Sub
+        1 => {
+            for index in stride..row.len() {
+                row[index] = row[index].wrapping_add(row[index - stride]);
+            }
+        }
+        // Up
+        2 => {
+            for (byte, up) in row.iter_mut().zip(previous) {
+                *byte = byte.wrapping_add(*up);
+            }
+        }
+        // Average
+        3 => {
+            for index in 0..row.len() {
+                let left = if index >= stride {
+                    row[index - stride]
+                } else {
+                    0
+                };
+                let average = (u16::from(left) + u16::from(previous[index])) / 2;
+                // Can't truncate as the average of two bytes fits a byte
+                row[index] = row[index].wrapping_add(average as u8);
+            }
+        }
+        // Paeth
+        4 => {
+            for index in 0..row.len() {
+                let (left, up_left) = if index >= stride {
+                    (row[index - stride], previous[index - stride])
+                } else {
+                    (0, 0)
+                };
+
+                row[index] = row[index].wrapping_add(paeth(left, previous[index], up_left));
+            }
+        }
+        _ => return Err(DecodeError::InvalidFilter(filter)),
+    }
+
+    Ok(())
+}
+
+/// Assembles the decompressed bytes into rows and converts them to gray
+struct Rows {
+    header: Header,
+    palette: Option<Palette>,
+    /// Filter type followed by the row
+    current: Vec<u8>,
+    filled: usize,
+    /// Previous row without the filter type. Starts as zeros as the filters expect.
+    previous: Vec<u8>,
+    gray: Vec<u8>,
+    count: u32,
+}
+
+impl Rows {
+    fn new<E>(header: Header, palette: Option<Palette>) -> Result<Self, DecodeError<E>> {
+        if header.color_type == ColorType::Palette && palette.is_none() {
+            return Err(DecodeError::MissingPalette);
+        }
+
+        let row_size = header.row_size().ok_or(DecodeError::OutOfMemory)?;
+        let width = usize::try_from(header.width).map_err(|_| DecodeError::OutOfMemory)?;
+
+        Ok(Self {
+            current: try_zeroed(row_size + 1).ok_or(DecodeError::OutOfMemory)?,
+            filled: 0,
+            previous: try_zeroed(row_size).ok_or(DecodeError::OutOfMemory)?,
+            gray: try_zeroed(width).ok_or(DecodeError::OutOfMemory)?,
+            header,
+            palette,
+            count: 0,
+        })
+    }
+
+    fn push<S: RowSink>(
+        &mut self,
+        mut bytes: &[u8],
+        sink: &mut S,
+    ) -> Result<(), DecodeError<S::Error>> {
+        // Anything after the last row is ignored
+        while !bytes.is_empty() && !self.is_complete() {
+            let take = (self.current.len() - self.filled).min(bytes.len());
+            self.current[self.filled..self.filled + take].copy_from_slice(&bytes[..take]);
+            self.filled += take;
+            bytes = &bytes[take..];
+
+            if self.filled == self.current.len() {
+                self.finish_row(sink)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn is_complete(&self) -> bool {
+        self.count == self.header.height
+    }
+
+    fn finish_row<S: RowSink>(&mut self, sink: &mut S) -> Result<(), DecodeError<S::Error>> {
+        let (filter, row) = self
+            .current
+            .split_first_mut()
+            .ok_or(DecodeError::Truncated)?;
+        unfilter(*filter, row, &self.previous, self.header.filter_stride())?;
+
+        self.convert_to_gray();
+        sink.push_row(&self.gray).map_err(DecodeError::Sink)?;
+
+        self.previous.copy_from_slice(&self.current[1..]);
+        self.filled = 0;
+        self.count += 1;
+        Ok(())
+    }
+
+    fn convert_to_gray(&mut self) {
+        let row = &self.current[1..];
+        let depth = usize::from(self.header.bit_depth);
+        let channels = self.header.color_type.channels();
+
+        // Sample value scaled to 8 bits. 16-bit samples are cut to their high byte.
+        let sample = |index: usize| -> u8 {
+            match depth {
+                8 => row[index],
+                16 => row[index * 2],
+                _ => {
+                    let bit = index * depth;
+                    let shift = 8 - depth - bit % 8;
+                    let mask = (1 << depth) - 1;
+                    // Can't truncate as the value is masked to less than 8 bits
+                    ((usize::from(row[bit / 8]) >> shift) & mask) as u8
+                }
+            }
+        };
+
+        // Palette indices are not scaled
+        let scale = |value: u8| -> u8 {
+            match depth {
+                1 => value * 255,
+                2 => value * 85,
+                4 => value * 17,
+                _ => value,
+            }
+        };
+
+        for (pixel, gray) in self.gray.iter_mut().enumerate() {
+            let first = pixel * channels;
+            *gray = match self.header.color_type {
+                ColorType::Gray => scale(sample(first)),
+                ColorType::Rgb => luma(sample(first), sample(first + 1), sample(first + 2)),
+                ColorType::Palette => self
+                    .palette
+                    .as_ref()
+                    .map_or(0, |palette| palette.0[usize::from(sample(first))]),
+                ColorType::GrayAlpha => blend_onto_white(sample(first), sample(first + 1)),
+                ColorType::Rgba => blend_onto_white(
+                    luma(sample(first), sample(first + 1), sample(first + 2)),
+                    sample(first + 3),
+                ),
+            };
+        }
+    }
+}
+
+/// Decompresses the image data in a wrapping window
+struct Inflater {
+    decompressor: Box<DecompressorOxide>,
+    window: Vec<u8>,
+    position: usize,
+    is_done: bool,
+}
+
+impl Inflater {
+    fn new<E>() -> Result<Self, DecodeError<E>> {
+        Ok(Self {
+            decompressor: Box::default(),
+            window: try_zeroed(WINDOW_SIZE).ok_or(DecodeError::OutOfMemory)?,
+            position: 0,
+            is_done: false,
+        })
+    }
+
+    fn push<S: RowSink>(
+        &mut self,
+        mut data: &[u8],
+        rows: &mut Rows,
+        sink: &mut S,
+    ) -> Result<(), DecodeError<S::Error>> {
+        while !self.is_done {
+            let start = self.position;
+            let (status, consumed, written) =
+                decompress(&mut self.decompressor, data, &mut self.window, start, FLAGS);
+            data = &data[consumed..];
+            self.position = (start + written) % WINDOW_SIZE;
+
+            rows.push(&self.window[start..start + written], sink)?;
+
+            match status {
+                TINFLStatus::Done => self.is_done = true,
+                TINFLStatus::NeedsMoreInput => return Ok(()),
+                // Window is full and starts over at the beginning
+                TINFLStatus::HasMoreOutput => {}
+                _ => return Err(DecodeError::Inflate(status)),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Reads the file front to back
+struct Reader {
+    file: RawFile,
+    offset: u32,
+}
+
+impl Reader {
+    async fn read_exact<E>(
+        &mut self,
+        sd_card: &mut SdCard,
+        buffer: &mut [u8],
+    ) -> Result<(), DecodeError<E>> {
+        let read = sd_card
+            .read_at(self.file, self.offset, buffer, ReadPriority::Background)
+            .await
+            .map_err(DecodeError::Read)?;
+        if read < buffer.len() {
+            return Err(DecodeError::Truncated);
+        }
+
+        // Can't truncate as the buffers are small
+        self.offset += read as u32;
+        Ok(())
+    }
+
+    fn skip(&mut self, length: u32) {
+        self.offset = self.offset.saturating_add(length);
+    }
+}
+
+/// Decodes the PNG image at the path and hands the rows to the sink
+pub(crate) async fn decode<S: RowSink>(
+    sd_card: &mut SdCard,
+    path: &str,
+    sink: &mut S,
+) -> Result<(), DecodeError<S::Error>> {
+    let file = sd_card.open(path, Mode::ReadOnly).await?;
+    let result = decode_file(sd_card, Reader { file, offset: 0 }, sink).await;
+
+    if let Err(error) = sd_card.close(file).await {
+        defmt::warn!("Failed to close file: {:?}", error);
+    }
+
+    result
+}
+
+/// Decodes the PNG image that starts at the offset of an open file, like an image within a book
+pub(crate) async fn decode_at<S: RowSink>(
+    sd_card: &mut SdCard,
+    file: RawFile,
+    offset: u32,
+    sink: &mut S,
+) -> Result<(), DecodeError<S::Error>> {
+    decode_file(sd_card, Reader { file, offset }, sink).await
+}
+
+async fn decode_file<S: RowSink>(
+    sd_card: &mut SdCard,
+    mut reader: Reader,
+    sink: &mut S,
+) -> Result<(), DecodeError<S::Error>> {
+    let mut signature = [0; SIGNATURE.len()];
+    reader.read_exact(sd_card, &mut signature).await?;
+    if signature != SIGNATURE {
+        return Err(DecodeError::NotPng);
+    }
+
+    let mut buffer = vec![0; READ_BUFFER_SIZE];
+    let mut header = None;
+    let mut palette = None;
+    let mut decoder: Option<(Inflater, Rows)> = None;
+
+    loop {
+        let mut chunk = [0; 8];
+        reader.read_exact(sd_card, &mut chunk).await?;
+        let [l0, l1, l2, l3, kind @ ..] = chunk;
+        let length = u32::from_be_bytes([l0, l1, l2, l3]);
+        // Only used for the small chunks that need to fit the buffer
+        let small = usize::try_from(length)
+            .ok()
+            .filter(|length| *length <= READ_BUFFER_SIZE);
+
+        match &kind {
+            b"IHDR" => {
+                let data = &mut buffer[..small.ok_or(DecodeError::InvalidHeader)?];
+                reader.read_exact(sd_card, data).await?;
+                header = Some(Header::parse(data)?);
+            }
+            b"PLTE" => {
+                let data = &mut buffer[..small.ok_or(DecodeError::InvalidHeader)?];
+                reader.read_exact(sd_card, data).await?;
+                palette = Some(Palette::new(data));
+            }
+            // Transparency of the palette entries. Transparency of the other color types is rare
+            // and skipped.
+            b"tRNS" if palette.is_some() => {
+                let data = &mut buffer[..small.ok_or(DecodeError::InvalidHeader)?];
+                reader.read_exact(sd_card, data).await?;
+                if let Some(palette) = palette.as_mut() {
+                    palette.apply_transparency(data);
+                }
+            }
+            b"IDAT" => {
+                let (inflater, rows) = match decoder.as_mut() {
+                    Some(decoder) => decoder,
+                    None => {
+                        let header = header.take().ok_or(DecodeError::MissingHeader)?;
+                        check_memory(&header)?;
+                        sink.start(Size::new(header.width, header.height))
+                            .map_err(DecodeError::Sink)?;
+                        let rows = Rows::new(header, palette.take())?;
+                        decoder.insert((Inflater::new()?, rows))
+                    }
+                };
+
+                let mut remaining = length;
+                while remaining > 0 {
+                    // Can't truncate as it is at most the buffer size
+                    let size = remaining.min(READ_BUFFER_SIZE as u32);
+                    let data = &mut buffer[..size as usize];
+                    reader.read_exact(sd_card, data).await?;
+                    inflater.push(data, rows, sink)?;
+                    remaining -= size;
+                }
+            }
+            b"IEND" => break,
+            _ => reader.skip(length),
+        }
+
+        // CRC
+        reader.skip(4);
+    }
+
+    match decoder {
+        Some((_inflater, rows)) if rows.is_complete() => Ok(()),
+        _ => Err(DecodeError::Truncated),
+    }
+}
+
+/// The decompressor is allocated with the infallible allocation which would abort if the heap is
+/// too small, so check the heap upfront
+fn check_memory<E>(header: &Header) -> Result<(), DecodeError<E>> {
+    let row_size = header.row_size().ok_or(DecodeError::OutOfMemory)?;
+    // Can't truncate as usize is 32 bits
+    let required = size_of::<DecompressorOxide>()
+        + WINDOW_SIZE
+        + row_size
+            .saturating_mul(2)
+            .saturating_add(header.width as usize);
+
+    if esp_alloc::HEAP.free() < required {
+        return Err(DecodeError::OutOfMemory);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,81 @@
+//! Presses of a button on each of the two ladders at once, which the ladders can tell apart unlike
+//! two buttons on the same ladder. Chords are shortcuts that work on every screen, so they are
+//! handed to the shortcut task instead of the screen waiting for a press.
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::signal::Signal;
+
+use crate::input::{Button, PIN_1_BUTTONS, PIN_2_BUTTONS};
+
+static CHORD: Signal<CriticalSectionRawMutex, Chord> = Signal::new();
+
+/// The buttons on the device before the button map, as the map is for single presses
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub(crate) struct Chord {
+    /// One of back, confirm, left and right
+    first: Button,
+    /// Up or down
+    second: Button,
+}
+
+impl Chord {
+    pub(crate) const COUNT: usize = PIN_1_BUTTONS.len() * PIN_2_BUTTONS.len();
+
+    /// From the active buttons of the ladders by their numbers
+    pub(crate) fn new(first: u8, second: u8) -> Option<Self> {
+        Some(Self {
+            first: *PIN_1_BUTTONS.get(usize::from(first))?,
+            second: *PIN_2_BUTTONS.get(usize::from(second))?,
+        })
+    }
+
+    /// Names of the buttons joined by a plus like `back+up`
+    pub(crate) fn parse(value: &str) -> Option<Self> {
+        let (first, second) = value.split_once('+')?;
+        let first = Button::parse(first.trim())?;
+        let second = Button::parse(second.trim())?;
+        let first = PIN_1_BUTTONS.iter().position(|&button| button == first)?;
+        let second = PIN_2_BUTTONS.iter().position(|&button| button == second)?;
+        // Can't truncate as there are only a few buttons
+        Self::new(first as u8, second as u8)
+    }
+
+    /// Unique for each chord and less than [`Self::COUNT`]
+    pub(crate) fn index(self) -> usize {
+        let first = PIN_1_BUTTONS
+            .iter()
+            .position(|&button| button == self.first)
+            .unwrap_or_default();
+        let second = PIN_2_BUTTONS
+            .iter()
+            .position(|&button| button == self.second)
+            .unwrap_or_default();
+        first * PIN_2_BUTTONS.len() + second
+    }
+}
+
+/// Reports a chord once when it is pressed and not again while it is held
+#[derive(Default)]
+pub(crate) struct Chords {
+    held: Option<Chord>,
+}
+
+impl Chords {
+    /// Takes the chord held at the reading and hands it on if it was just pressed. Returns whether a
+    /// chord is held, as its buttons don't count as single presses then.
+    pub(crate) fn update(&mut self, chord: Option<Chord>) -> bool {
+        if let Some(chord) = chord
+            && self.held != Some(chord)
+        {
+            CHORD.signal(chord);
+        }
+
+        self.held = chord;
+        chord.is_some()
+    }
+}
+
+/// Waits for the next chord on any screen
+pub(crate) async fn wait() -> Chord {
+    CHORD.wait().await
+}
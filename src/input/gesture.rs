@@ -0,0 +1,73 @@
+//! Turns the buttons that are pressed at each reading into presses. A button that turns pages or
+//! moves a cursor repeats while it is held, first after a delay and then faster and faster, to skim
+//! through pages and long lists without pressing it again and again. Other buttons only count once
+//! per press, so holding confirm doesn't open what it opened again.
+
+use embassy_time::{Duration, Instant};
+
+use crate::input::Button;
+
+/// How long a button is held before it repeats
+const REPEAT_DELAY: Duration = Duration::from_millis(500);
+const FIRST_INTERVAL: Duration = Duration::from_millis(300);
+/// Fastest repeat, which still leaves time for fast refreshes
+const MINIMUM_INTERVAL: Duration = Duration::from_millis(80);
+/// Each interval is this share of the previous one. Four fifths.
+const ACCELERATION_NUMERATOR: u32 = 4;
+const ACCELERATION_DENOMINATOR: u32 = 5;
+
+struct Held {
+    button: Button,
+    next_repeat: Instant,
+    interval: Duration,
+}
+
+/// The button that is held since the last reading
+#[derive(Default)]
+pub(crate) struct Gestures {
+    held: Option<Held>,
+}
+
+impl Gestures {
+    /// The button that was pressed at the last reading
+    pub(crate) fn held(&self) -> Option<Button> {
+        self.held.as_ref().map(|held| held.button)
+    }
+
+    /// Takes the button pressed at the reading and returns it if it counts as a press, which is
+    /// when it was just pressed or repeats
+    pub(crate) fn update(&mut self, pressed: Option<Button>, now: Instant) -> Option<Button> {
+        let Some(button) = pressed else {
+            self.held = None;
+            return None;
+        };
+
+        match &mut self.held {
+            Some(held) if held.button == button => {
+                if !repeats(button) || now < held.next_repeat {
+                    return None;
+                }
+
+                held.next_repeat = now + held.interval;
+                held.interval = (held.interval * ACCELERATION_NUMERATOR / ACCELERATION_DENOMINATOR)
+                    .max(MINIMUM_INTERVAL);
+                Some(button)
+            }
+            _ => {
+                self.held = Some(Held {
+                    button,
+                    next_repeat: now + REPEAT_DELAY,
+                    interval: FIRST_INTERVAL,
+                });
+                Some(button)
+            }
+        }
+    }
+}
+
+fn repeats(button: Button) -> bool {
+    matches!(
+        button,
+        Button::Left | Button::Right | Button::Up | Button::Down
+    )
+}
@@ -0,0 +1,300 @@
+//! Reads analog values from GPIO pins. These values are used to determine the state of buttons and battery level.
+
+use core::num::NonZeroU8;
+
+use crustpoint_core::ladder::Ladder;
+use embassy_futures::yield_now;
+use embassy_time::{Duration, Instant, Timer};
+use esp_hal::{
+    Async,
+    analog::adc::{Adc, AdcCalLine, AdcConfig, AdcPin, Attenuation},
+    peripherals::{ADC1, GPIO0, GPIO1, GPIO2},
+};
+
+pub(crate) use crate::input::chord::Chord;
+pub(crate) use crustpoint_core::ladder::Calibration;
+
+use crate::SharedRtc;
+use crate::input::chord::Chords;
+use crate::input::gesture::Gestures;
+use crate::light_sleep;
+use crate::supervisor::{self, Task};
+
+pub(crate) mod chord;
+mod gesture;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub(crate) enum Button {
+    Back,
+    Confirm,
+    Left,
+    Right,
+    Up,
+    Down,
+}
+
+impl Button {
+    const ALL: [Self; 6] = [
+        Self::Back,
+        Self::Confirm,
+        Self::Left,
+        Self::Right,
+        Self::Up,
+        Self::Down,
+    ];
+
+    pub(crate) fn parse(value: &str) -> Option<Self> {
+        match value {
+            "back" => Some(Self::Back),
+            "confirm" => Some(Self::Confirm),
+            "left" => Some(Self::Left),
+            "right" => Some(Self::Right),
+            "up" => Some(Self::Up),
+            "down" => Some(Self::Down),
+            _ => None,
+        }
+    }
+
+    fn index(self) -> usize {
+        match self {
+            Self::Back => 0,
+            Self::Confirm => 1,
+            Self::Left => 2,
+            Self::Right => 3,
+            Self::Up => 4,
+            Self::Down => 5,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub(crate) enum InputEvent {
+    /// Just pressed or repeating while held, see [`gesture`]
+    Pressed(Button),
+    /// Let go of
+    Released(Button),
+}
+
+/// Which button each of the buttons on the device acts as, e.g. to swap the buttons that turn the
+/// page
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub(crate) struct ButtonMap([Button; 6]);
+
+impl Default for ButtonMap {
+    fn default() -> Self {
+        Self(Button::ALL)
+    }
+}
+
+impl ButtonMap {
+    /// Makes the button on the device act as the other button
+    pub(crate) fn set(&mut self, button: Button, acts_as: Button) {
+        self.0[button.index()] = acts_as;
+    }
+
+    fn get(&self, button: Button) -> Button {
+        self.0[button.index()]
+    }
+}
+
+/// In the order of the levels of their ladder
+const PIN_1_BUTTONS: [Button; 4] = [Button::Back, Button::Confirm, Button::Left, Button::Right];
+const PIN_2_BUTTONS: [Button; 2] = [Button::Up, Button::Down];
+
+/// The battery is measured through a divider that halves the voltage
+const BATTERY_DIVIDER: u16 = 2;
+
+/// How often to check the buttons while waiting for a press
+const BUTTON_POLL_INTERVAL: Duration = Duration::from_millis(20);
+/// The chip only goes to light sleep between readings once no button was held for this long, so
+/// held buttons repeat on time
+const IDLE_BEFORE_LIGHT_SLEEP: Duration = Duration::from_millis(500);
+/// Samples of each button ladder that are taken for a reading by default
+const DEFAULT_SAMPLES: u8 = 3;
+/// Each sample takes a few microseconds, so more than this only slows down the polling
+const MAXIMUM_SAMPLES: u8 = 9;
+/// Middle of the samples, which ignores single samples that are far off unlike an average
+fn median(samples: &mut [u16]) -> u16 {
+    samples.sort_unstable();
+    samples[samples.len() / 2]
+}
+
+pub(crate) struct Analog<'a> {
+    adc: Adc<'a, ADC1<'a>, Async>,
+    pin: (
+        AdcPin<GPIO0<'a>, ADC1<'a>, AdcCalLine<ADC1<'a>>>,
+        AdcPin<GPIO1<'a>, ADC1<'a>, AdcCalLine<ADC1<'a>>>,
+        AdcPin<GPIO2<'a>, ADC1<'a>, AdcCalLine<ADC1<'a>>>,
+    ),
+    buttons: ButtonMap,
+    calibration: Calibration,
+    /// Samples of each ladder per reading, from 1 to [`MAXIMUM_SAMPLES`]
+    samples: u8,
+    /// The active button of each ladder at the last reading for the hysteresis
+    active: (Option<u8>, Option<u8>),
+    gestures: Gestures,
+    chords: Chords,
+    /// Set to go to light sleep between readings, see [`light_sleep`]
+    light_sleep: Option<&'a SharedRtc>,
+}
+
+impl<'a> Analog<'a> {
+    pub(crate) fn new(adc: ADC1<'a>, pin_0: GPIO0<'a>, pin_1: GPIO1<'a>, pin_2: GPIO2<'a>) -> Self {
+        let mut configuration = AdcConfig::new();
+        let pin_0 = configuration
+            .enable_pin_with_cal::<_, AdcCalLine<ADC1<'static>>>(pin_0, Attenuation::_11dB);
+        let pin_1 = configuration
+            .enable_pin_with_cal::<_, AdcCalLine<ADC1<'static>>>(pin_1, Attenuation::_11dB);
+        let pin_2 = configuration
+            .enable_pin_with_cal::<_, AdcCalLine<ADC1<'static>>>(pin_2, Attenuation::_11dB);
+        let adc = Adc::new(adc, configuration).into_async();
+
+        Self {
+            adc,
+            pin: (pin_0, pin_1, pin_2),
+            buttons: ButtonMap::default(),
+            calibration: Calibration::default(),
+            samples: DEFAULT_SAMPLES,
+            active: (None, None),
+            gestures: Gestures::default(),
+            chords: Chords::default(),
+            light_sleep: None,
+        }
+    }
+
+    /// Lets the chip go to light sleep between readings while waiting for a press
+    pub(crate) fn enable_light_sleep(&mut self, rtc: &'a SharedRtc) {
+        self.light_sleep = Some(rtc);
+    }
+
+    pub(crate) fn set_buttons(&mut self, buttons: ButtonMap) {
+        self.buttons = buttons;
+    }
+
+    pub(crate) fn set_calibration(&mut self, calibration: Calibration) {
+        self.calibration = calibration;
+        self.active = (None, None);
+    }
+
+    /// Takes the median of this many samples for each reading of the buttons. More samples filter
+    /// out more noise but take longer.
+    pub(crate) fn set_samples(&mut self, samples: NonZeroU8) {
+        self.samples = samples.get().min(MAXIMUM_SAMPLES);
+    }
+
+    /// The values of the two button ladders, each the median of the samples. The buttons pull the
+    /// value down from where it is with none of them pressed.
+    pub(crate) async fn ladder_levels(&mut self) -> (u16, u16) {
+        let mut samples_1 = [0; MAXIMUM_SAMPLES as usize];
+        let mut samples_2 = [0; MAXIMUM_SAMPLES as usize];
+        let count = usize::from(self.samples);
+        for index in 0..count {
+            samples_1[index] = self.adc.read_oneshot(&mut self.pin.1).await;
+            samples_2[index] = self.adc.read_oneshot(&mut self.pin.2).await;
+        }
+
+        (
+            median(&mut samples_1[..count]),
+            median(&mut samples_2[..count]),
+        )
+    }
+
+    /// The active button of each ladder by its number in the ranges
+    async fn active_buttons(&mut self) -> (Option<u8>, Option<u8>) {
+        let (value_1, value_2) = self.ladder_levels().await;
+        self.active = (
+            self.calibration
+                .active_button(Ladder::One, value_1, self.active.0),
+            self.calibration
+                .active_button(Ladder::Two, value_2, self.active.1),
+        );
+        self.active
+    }
+
+    /// Battery voltage in millivolts. The calibrated reading is already in millivolts.
+    pub(crate) async fn battery_millivolts(&mut self) -> u16 {
+        let value = self.adc.read_oneshot(&mut self.pin.0).await;
+        value.saturating_mul(BATTERY_DIVIDER)
+    }
+
+    /// The currently pressed button after the button map. If buttons on both ladders are pressed,
+    /// the one on the first wins.
+    pub(crate) async fn pressed_button(&mut self) -> Option<Button> {
+        let (button_1, button_2) = self.active_buttons().await;
+        self.mapped_button(button_1, button_2)
+    }
+
+    fn mapped_button(&self, button_1: Option<u8>, button_2: Option<u8>) -> Option<Button> {
+        button_1
+            .map(|index| PIN_1_BUTTONS[usize::from(index)])
+            .or_else(|| button_2.map(|index| PIN_2_BUTTONS[usize::from(index)]))
+            .map(|button| self.buttons.get(button))
+    }
+
+    /// Hands on a chord if one was just pressed and returns whether one is held
+    fn update_chords(&mut self, button_1: Option<u8>, button_2: Option<u8>) -> bool {
+        let chord = button_1
+            .zip(button_2)
+            .and_then(|(first, second)| Chord::new(first, second));
+        self.chords.update(chord)
+    }
+
+    /// Whether a button on each of the ladders is held, which can't happen by accident, e.g. to
+    /// start the calibration at start up. Only the highest threshold is used, so it works with
+    /// thresholds that are off.
+    pub(crate) async fn is_chord_held(&mut self) -> bool {
+        let (value_1, value_2) = self.ladder_levels().await;
+        value_1 <= self.calibration.highest(Ladder::One)
+            && value_2 <= self.calibration.highest(Ladder::Two)
+    }
+
+    /// Waits until a button is pressed. A button that is still held from before only counts again
+    /// once it repeats, see [`gesture`]. Chords are handed to the shortcuts, see [`chord`].
+    pub(crate) async fn wait_for_press(&mut self) -> Button {
+        loop {
+            if let InputEvent::Pressed(button) = self.next_event().await {
+                return button;
+            }
+        }
+    }
+
+    /// Waits until the readings cross a threshold into a press or out of it. The ladders can't
+    /// interrupt, so they are read every few milliseconds until then. Can be cancelled without
+    /// losing a press.
+    pub(crate) async fn next_event(&mut self) -> InputEvent {
+        let watch = supervisor::watch(Task::Input);
+        let mut held = Instant::now();
+        loop {
+            let (button_1, button_2) = self.active_buttons().await;
+            if button_1.is_some() || button_2.is_some() {
+                held = Instant::now();
+            }
+
+            if !self.update_chords(button_1, button_2) {
+                let pressed = self.mapped_button(button_1, button_2);
+                let previous = self.gestures.held();
+                if let Some(button) = self.gestures.update(pressed, Instant::now()) {
+                    return InputEvent::Pressed(button);
+                }
+
+                if pressed.is_none()
+                    && let Some(button) = previous
+                {
+                    return InputEvent::Released(button);
+                }
+            }
+
+            watch.check_in();
+            match self.light_sleep {
+                Some(rtc)
+                    if held.elapsed() >= IDLE_BEFORE_LIGHT_SLEEP && light_sleep::is_possible() =>
+                {
+                    light_sleep::sleep(rtc, BUTTON_POLL_INTERVAL);
+                    // Lets the other tasks catch up with what happened during the sleep
+                    yield_now().await;
+                }
+                _ => Timer::after(BUTTON_POLL_INTERVAL).await,
+            }
+        }
+    }
+}
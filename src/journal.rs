@@ -0,0 +1,96 @@
+//! Reading progress is only saved to the SD card now and then, as writing it on every page turn
+//! costs battery and wears out the card. Until then it is kept in RTC fast memory, which keeps its
+//! content through panics, watchdog and brown-out resets. Progress that is still in there at start
+//! up was not saved because the session ended unexpectedly, so it is saved then.
+//!
+//! Bookmarks are saved as soon as they are toggled, so they don't need to be recovered.
+
+use core::sync::atomic::Ordering;
+
+use portable_atomic::AtomicU32;
+
+use crate::profile::BookId;
+use crate::progress::{AudiobookPosition, Progress};
+
+/// RTC fast memory has random content after power loss, so the entry is only trusted if it starts
+/// with the magic and the checksum matches
+const MAGIC: u32 = u32::from_le_bytes(*b"JRNL");
+/// Stands for no audiobook position
+const NO_AUDIOBOOK: u32 = u32::MAX;
+
+const MAGIC_INDEX: usize = 0;
+const CHECKSUM_INDEX: usize = 1;
+const BOOK_INDEX: usize = 2;
+const POSITION_INDEX: usize = 3;
+const AUDIOBOOK_INDEX: usize = 4;
+const LENGTH_INDEX: usize = 5;
+const WORDS: usize = 6;
+
+/// Progress of the book that is read with the active profile, which is kept in RTC fast memory as
+/// well. Only load and store atomics are available, so the words are written one by one with the
+/// magic last. A reset in the middle of writing loses the entry, but not more than the last page.
+#[esp_hal::ram(unstable(rtc_fast, persistent))]
+static ENTRY: [AtomicU32; WORDS] = [const { AtomicU32::new(0) }; WORDS];
+
+/// Keeps the progress until it is saved with [`Progress::save`]
+pub(crate) fn record(book: BookId, progress: &Progress) {
+    ENTRY[MAGIC_INDEX].store(0, Ordering::Relaxed);
+
+    let fields = fields(book, progress);
+    ENTRY[BOOK_INDEX].store(fields[0], Ordering::Relaxed);
+    ENTRY[POSITION_INDEX].store(fields[1], Ordering::Relaxed);
+    ENTRY[AUDIOBOOK_INDEX].store(fields[2], Ordering::Relaxed);
+    ENTRY[LENGTH_INDEX].store(fields[3], Ordering::Relaxed);
+    ENTRY[CHECKSUM_INDEX].store(checksum(&fields), Ordering::Relaxed);
+
+    ENTRY[MAGIC_INDEX].store(MAGIC, Ordering::Relaxed);
+}
+
+/// Progress that was recorded but not saved yet
+pub(crate) fn pending() -> Option<(BookId, Progress)> {
+    if ENTRY[MAGIC_INDEX].load(Ordering::Relaxed) != MAGIC {
+        return None;
+    }
+
+    let fields = [
+        ENTRY[BOOK_INDEX].load(Ordering::Relaxed),
+        ENTRY[POSITION_INDEX].load(Ordering::Relaxed),
+        ENTRY[AUDIOBOOK_INDEX].load(Ordering::Relaxed),
+        ENTRY[LENGTH_INDEX].load(Ordering::Relaxed),
+    ];
+    if ENTRY[CHECKSUM_INDEX].load(Ordering::Relaxed) != checksum(&fields) {
+        return None;
+    }
+
+    let [book, position, audiobook, length] = fields;
+    let progress = Progress {
+        position,
+        audiobook: (audiobook != NO_AUDIOBOOK).then_some(AudiobookPosition(audiobook)),
+        length,
+    };
+
+    Some((BookId(book), progress))
+}
+
+/// Called after the progress was saved. Newer progress that was recorded in the meantime is kept.
+pub(crate) fn forget(book: BookId, progress: &Progress) {
+    if pending().is_some_and(|(pending_book, pending_progress)| {
+        pending_book == book && pending_progress == *progress
+    }) {
+        ENTRY[MAGIC_INDEX].store(0, Ordering::Relaxed);
+    }
+}
+
+fn fields(book: BookId, progress: &Progress) -> [u32; WORDS - 2] {
+    let audiobook = progress
+        .audiobook
+        .map_or(NO_AUDIOBOOK, |AudiobookPosition(seconds)| seconds);
+    [book.0, progress.position, audiobook, progress.length]
+}
+
+/// FNV-1a over the words, as the entry only needs to be told apart from random memory
+fn checksum(fields: &[u32]) -> u32 {
+    fields.iter().fold(0x811C_9DC5, |hash, field| {
+        (hash ^ field).wrapping_mul(0x0100_0193)
+    })
+}
@@ -0,0 +1,396 @@
+//! Syncs the reading progress with a server that speaks the protocol of the KOReader sync server
+//! (kosync), so a book can be continued on other devices running KOReader. It runs whenever the
+//! network comes up and syncs the books that were read most recently.
+//!
+//! Books are identified like KOReader does it, by the MD5 of samples spread over the file, so the
+//! file has to be the same on both devices. The position is sent as the offset into the text with
+//! the share of the book that was read. KOReader doesn't know what to make of the offset and uses
+//! the percentage, and so does this device for positions from KOReader.
+//!
+//! There is no clock to tell which progress is newer, so the progress that is further into the
+//! book wins. The connection is plain HTTP, as there is no TLS yet.
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+use crustpoint_core::md5::{self, Md5};
+
+use crate::http::{HttpError, Url};
+use crate::profile::Profile;
+use crate::progress::{Progress, SaveProgressError};
+use crate::sd_card::{OpenError, ReadFileError, ReadPriority, SdCard, VolumeError};
+
+/// Name of the device in the progress that is sent. Positions from devices with this name are
+/// offsets into the text like here.
+const DEVICE: &str = "crustpoint";
+/// Bytes hashed at each sample of the book file
+const SAMPLE_SIZE: usize = 1024;
+/// Last sample is at 1 GiB
+const LAST_SAMPLE: u32 = 10;
+/// Progress that differs less is on the same page
+const TOLERANCE: f32 = 0.0001;
+
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum KosyncError {
+    #[error("Failed to open book")]
+    Open(#[from] OpenError),
+    #[error("Failed to read book")]
+    Read(VolumeError),
+    #[error("Failed to load progress")]
+    LoadProgress(#[from] ReadFileError),
+    #[error("Failed to save progress")]
+    SaveProgress(#[from] SaveProgressError),
+    #[error("Request to the server failed")]
+    Http(#[from] HttpError),
+    #[error("Server rejected the user name or password")]
+    Unauthorized,
+    #[error("Server responded with status {0}")]
+    Status(u16),
+}
+
+/// Account on the sync server
+#[derive(Clone, PartialEq, Eq)]
+pub(crate) struct Account {
+    server: Url,
+    username: String,
+    /// MD5 of the password, which is all the protocol sends
+    key: String,
+}
+
+/// Keeps the key out of the logs
+impl core::fmt::Debug for Account {
+    fn fmt(&self, formatter: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        formatter
+            .debug_struct("Account")
+            .field("server", &self.server)
+            .field("username", &self.username)
+            .finish_non_exhaustive()
+    }
+}
+
+impl defmt::Format for Account {
+    fn format(&self, formatter: defmt::Formatter<'_>) {
+        defmt::write!(
+            formatter,
+            "Account {{ server: {}, username: {}, .. }}",
+            self.server,
+            self.username.as_str()
+        );
+    }
+}
+
+impl Account {
+    /// The server is a URL like `http://books.example.com:7200/sync`. None if it isn't a plain HTTP
+    /// URL or the user name is empty.
+    pub(crate) fn new(server: &str, username: String, password: &str) -> Option<Self> {
+        let server = Url::parse(server)?;
+        if username.is_empty() {
+            return None;
+        }
+
+        Some(Self {
+            server,
+            username,
+            key: md5::hex(&md5::digest(password.as_bytes())),
+        })
+    }
+
+    /// Head of a request with the body, which is JSON if there is one
+    fn request(&self, method: &str, path: &str, body: &str) -> String {
+        let mut headers = vec![
+            ("Accept", "application/vnd.koreader.v1+json"),
+            ("x-auth-user", self.username.as_str()),
+            ("x-auth-key", self.key.as_str()),
+        ];
+        if !body.is_empty() {
+            headers.push(("Content-Type", "application/json"));
+        }
+
+        let body_length = (!body.is_empty()).then_some(body.len());
+        self.server.request(method, path, &headers, body_length)
+    }
+}
+
+/// Progress of a book as the server keeps it
+#[derive(Debug, Clone, PartialEq, defmt::Format)]
+struct RemoteProgress {
+    /// Offset into the text for this device, an XPointer or page for KOReader
+    progress: String,
+    percentage: f32,
+    device: String,
+}
+
+/// Identifies the book like KOReader by hashing 1 KiB at the start and at offsets that grow by a
+/// factor of 4 from 1 KiB on, as far as the file goes
+async fn document(sd_card: &mut SdCard, book_path: &str) -> Result<String, KosyncError> {
+    let file = sd_card
+        .open(book_path, embedded_sdmmc::Mode::ReadOnly)
+        .await?;
+    let result = hash_samples(sd_card, file).await;
+
+    if let Err(error) = sd_card.close(file).await {
+        defmt::warn!("Failed to close book: {:?}", error);
+    }
+
+    Ok(md5::hex(&result?))
+}
+
+async fn hash_samples(
+    sd_card: &mut SdCard,
+    file: embedded_sdmmc::RawFile,
+) -> Result<[u8; 16], KosyncError> {
+    let length = sd_card.length(file).await.map_err(KosyncError::Read)?;
+    let mut md5 = Md5::new();
+    let mut sample = [0; SAMPLE_SIZE];
+    let offsets = core::iter::once(0).chain((0..=LAST_SAMPLE).map(|index| 1024 << (2 * index)));
+    for offset in offsets.take_while(|&offset| offset < length) {
+        let read = sd_card
+            .read_at(file, offset, &mut sample, ReadPriority::Background)
+            .await
+            .map_err(KosyncError::Read)?;
+        md5.update(&sample[..read]);
+    }
+
+    Ok(md5.finish())
+}
+
+fn progress_body(document: &str, progress: &Progress, percentage: f32, device_id: &str) -> String {
+    format!(
+        "{{\"document\":\"{document}\",\"progress\":\"{}\",\"percentage\":{percentage:.4},\
+         \"device\":\"{DEVICE}\",\"device_id\":\"{device_id}\"}}",
+        progress.position
+    )
+}
+
+/// Tells the device apart from other devices of the same kind for the server
+fn device_id(profile: &Profile) -> String {
+    let mut md5 = Md5::new();
+    md5.update(DEVICE.as_bytes());
+    md5.update(profile.name().as_bytes());
+    md5::hex(&md5.finish())
+}
+
+fn check_status(status: u16) -> Result<(), KosyncError> {
+    match status {
+        200..=299 => Ok(()),
+        401 | 403 => Err(KosyncError::Unauthorized),
+        status => Err(KosyncError::Status(status)),
+    }
+}
+
+/// The progress in the response body of the server, None if it has none for the book
+fn parse_progress(body: &str) -> Option<RemoteProgress> {
+    Some(RemoteProgress {
+        progress: String::from(json_field(body, "progress")?),
+        percentage: json_field(body, "percentage")?.parse().ok()?,
+        device: String::from(json_field(body, "device").unwrap_or_default()),
+    })
+}
+
+/// Value of a field of a flat JSON object without quotes. Escapes are left as they are, as the
+/// fields that are read don't contain any.
+fn json_field<'a>(json: &'a str, key: &str) -> Option<&'a str> {
+    let quoted = format!("\"{key}\"");
+    let start = json.find(&quoted)? + quoted.len();
+    let value = json[start..].trim_start().strip_prefix(':')?.trim_start();
+
+    if let Some(string) = value.strip_prefix('"') {
+        let mut escaped = false;
+        let end = string.find(|character| {
+            let is_end = character == '"' && !escaped;
+            escaped = character == '\\' && !escaped;
+            is_end
+        })?;
+        return Some(&string[..end]);
+    }
+
+    let end = value.find([',', '}']).unwrap_or(value.len());
+    let value = value[..end].trim();
+    (value != "null").then_some(value)
+}
+
+/// Where the local progress ends up after seeing the progress on the server
+#[derive(Debug, Clone, PartialEq, defmt::Format)]
+enum Outcome {
+    /// Both are on the same page
+    InSync,
+    /// The server is further into the book
+    Adopt(Progress),
+    /// This device is further into the book or the server has no progress for it
+    Push,
+}
+
+fn reconcile(local: &Progress, remote: Option<&RemoteProgress>) -> Outcome {
+    let Some(percentage) = local.percentage() else {
+        // Without the length the position can't be compared
+        return Outcome::InSync;
+    };
+
+    let Some(remote) = remote else {
+        return Outcome::Push;
+    };
+
+    if (remote.percentage - percentage).abs() < TOLERANCE {
+        return Outcome::InSync;
+    }
+
+    if remote.percentage < percentage {
+        return Outcome::Push;
+    }
+
+    let position = match remote.progress.parse() {
+        Ok(position) if remote.device == DEVICE => position,
+        // Can't truncate as the percentage is at most 1
+        _ => (remote.percentage.clamp(0.0, 1.0) * local.length as f32) as u32,
+    };
+    Outcome::Adopt(Progress {
+        position: position.min(local.length),
+        ..local.clone()
+    })
+}
+
+#[cfg(feature = "radio")]
+pub(crate) use network::run;
+
+#[cfg(feature = "radio")]
+mod network {
+    use alloc::format;
+    use alloc::string::String;
+    use alloc::vec::Vec;
+
+    use defmt::{info, warn};
+    use embassy_net::Stack;
+
+    use super::{
+        Account, KosyncError, Outcome, check_status, device_id, document, parse_progress,
+        progress_body, reconcile,
+    };
+    use crate::SharedSdCard;
+    use crate::http;
+    use crate::profile::Profile;
+    use crate::progress::Progress;
+    use crate::recent::Recent;
+
+    /// Only the books read most recently are synced to keep the radio on for a short time
+    const MAXIMUM_BOOKS: usize = 5;
+    /// Responses are a small JSON object
+    const MAXIMUM_RESPONSE_SIZE: usize = 2 * 1024;
+    const BUFFER_SIZE: usize = 1024;
+
+    /// Syncs each time the network comes up. Started with the network if there is an account in
+    /// the configuration.
+    #[embassy_executor::task]
+    pub(crate) async fn run(
+        stack: Stack<'static>,
+        account: Account,
+        profile: Profile,
+        sd_card: &'static SharedSdCard,
+    ) {
+        loop {
+            stack.wait_config_up().await;
+            sync(stack, &account, &profile, sd_card).await;
+            stack.wait_config_down().await;
+        }
+    }
+
+    /// Syncs the progress of the books that were read most recently. Failures are logged, as
+    /// syncing is attempted again the next time the network comes up.
+    pub(crate) async fn sync(
+        stack: Stack<'_>,
+        account: &Account,
+        profile: &Profile,
+        sd_card: &SharedSdCard,
+    ) {
+        let recent = match sd_card.lock().await.as_mut() {
+            Some(sd_card) => Recent::load(sd_card, profile).await,
+            None => return,
+        };
+        let recent = match recent {
+            Ok(recent) => recent,
+            Err(error) => {
+                warn!(
+                    "Failed to load recent books: {:?}",
+                    defmt::Debug2Format(&error)
+                );
+                return;
+            }
+        };
+
+        info!("Syncing reading progress");
+        let device_id = device_id(profile);
+        for book_path in recent.books().take(MAXIMUM_BOOKS) {
+            let result = sync_book(stack, account, profile, &device_id, book_path, sd_card).await;
+            if let Err(error) = result {
+                warn!(
+                    "Failed to sync progress of {}: {:?}",
+                    book_path,
+                    defmt::Debug2Format(&error)
+                );
+            }
+        }
+    }
+
+    async fn sync_book(
+        stack: Stack<'_>,
+        account: &Account,
+        profile: &Profile,
+        device_id: &str,
+        book_path: &str,
+        sd_card: &SharedSdCard,
+    ) -> Result<(), KosyncError> {
+        let (document, local) = match sd_card.lock().await.as_mut() {
+            Some(sd_card) => (
+                document(sd_card, book_path).await?,
+                Progress::load(sd_card, profile, book_path).await?,
+            ),
+            None => return Ok(()),
+        };
+
+        let path = format!("/syncs/progress/{document}");
+        let (status, body) =
+            exchange(stack, account, &account.request("GET", &path, ""), "").await?;
+        check_status(status)?;
+        let remote = parse_progress(&String::from_utf8_lossy(&body));
+
+        match reconcile(&local, remote.as_ref()) {
+            Outcome::InSync => Ok(()),
+            Outcome::Adopt(progress) => {
+                info!("Continuing {} from the sync server", book_path);
+                if let Some(sd_card) = sd_card.lock().await.as_mut() {
+                    progress.save(sd_card, profile, book_path).await?;
+                }
+                Ok(())
+            }
+            Outcome::Push => {
+                // Can't fail as pushing needs the percentage
+                let percentage = local.percentage().unwrap_or_default();
+                let body = progress_body(&document, &local, percentage, device_id);
+                let head = account.request("PUT", "/syncs/progress", &body);
+                let (status, _) = exchange(stack, account, &head, &body).await?;
+                check_status(status)
+            }
+        }
+    }
+
+    /// Sends the request and reads the whole response
+    async fn exchange(
+        stack: Stack<'_>,
+        account: &Account,
+        head: &str,
+        body: &str,
+    ) -> Result<(u16, Vec<u8>), KosyncError> {
+        let mut receive_buffer = [0; BUFFER_SIZE];
+        let mut send_buffer = [0; BUFFER_SIZE];
+        let mut response = http::send(
+            stack,
+            &account.server,
+            head,
+            body.as_bytes(),
+            &mut receive_buffer,
+            &mut send_buffer,
+        )
+        .await?;
+        let body = response.read_to_end(MAXIMUM_RESPONSE_SIZE).await?;
+        Ok((response.head.status, body))
+    }
+}
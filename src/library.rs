@@ -0,0 +1,477 @@
+//! Index of the books on the SD card with their title, author and cover, so the library can be
+//! shown without opening every book. The card is walked for books when the index is refreshed, but
+//! only books that are new or changed since the last time are opened.
+//!
+//! The index is kept in the file at [`PATH`]. Each line is a book like
+//! `book=/BOOKS/EMMA.EPUB\t52431\t1234567\tEmma\tJane Austen\tOEBPS/cover.jpg` with the path, the
+//! size and modification time to notice changes, the title, the author and the cover. The cover is
+//! a path within the archive for EPUB books and the record like `#12` for Kindle books. The covers
+//! are scaled down to thumbnails as the books are read.
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::cmp::Ordering;
+
+use defmt::{error, info, warn};
+use embedded_sdmmc::{Mode, Timestamp};
+
+use crate::activity;
+use crate::epub::metadata;
+use crate::mobi;
+use crate::recent::Recent;
+use crate::sd_card::{ReadFileError, ReadPriority, SdCard, VolumeError, WriteFileError};
+use crate::thumbnail;
+
+const PATH: &str = "/LIBRARY.TXT";
+/// Keeps the index small enough to read at once
+const MAXIMUM_BOOKS: usize = 256;
+const MAXIMUM_FILE_SIZE: u32 = 32 * 1024;
+/// Books in deeper directories are not found
+const MAXIMUM_DEPTH: usize = 4;
+/// Directories of the reader itself that have no books
+const SKIPPED_DIRECTORIES: [&str; 5] = ["PROFILES", "THEMES", "SLEEP", "HYPHEN", "THUMBS"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub(crate) enum Format {
+    Epub,
+    /// MOBI and AZW3
+    Kindle,
+}
+
+impl Format {
+    /// By the extension of the file name
+    pub(crate) fn of(path: &str) -> Option<Self> {
+        let (_, extension) = path.rsplit_once('.')?;
+        match extension.to_ascii_uppercase().as_str() {
+            "EPUB" => Some(Self::Epub),
+            "MOBI" | "AZW3" | "AZW" | "PRC" => Some(Self::Kindle),
+            _ => None,
+        }
+    }
+}
+
+/// Order of the books in the library
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, defmt::Format)]
+pub(crate) enum Sort {
+    /// By title
+    #[default]
+    Name,
+    /// Newest first
+    Modified,
+    /// Most recently opened by the profile first, then the books that were not read yet
+    RecentlyRead,
+    /// Books without an author last
+    Author,
+}
+
+impl Sort {
+    pub(crate) fn as_str(self) -> &'static str {
+        match self {
+            Self::Name => "name",
+            Self::Modified => "modified",
+            Self::RecentlyRead => "recent",
+            Self::Author => "author",
+        }
+    }
+
+    pub(crate) fn parse(value: &str) -> Option<Self> {
+        match value {
+            "name" => Some(Self::Name),
+            "modified" => Some(Self::Modified),
+            "recent" => Some(Self::RecentlyRead),
+            "author" => Some(Self::Author),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn next(self) -> Self {
+        match self {
+            Self::Name => Self::Modified,
+            Self::Modified => Self::RecentlyRead,
+            Self::RecentlyRead => Self::Author,
+            Self::Author => Self::Name,
+        }
+    }
+}
+
+/// Which books the library shows by their format
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, defmt::Format)]
+pub(crate) enum Filter {
+    #[default]
+    All,
+    Only(Format),
+}
+
+impl Filter {
+    pub(crate) fn as_str(self) -> &'static str {
+        match self {
+            Self::All => "all",
+            Self::Only(Format::Epub) => "epub",
+            Self::Only(Format::Kindle) => "kindle",
+        }
+    }
+
+    pub(crate) fn parse(value: &str) -> Option<Self> {
+        match value {
+            "all" => Some(Self::All),
+            "epub" => Some(Self::Only(Format::Epub)),
+            "kindle" => Some(Self::Only(Format::Kindle)),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn next(self) -> Self {
+        match self {
+            Self::All => Self::Only(Format::Epub),
+            Self::Only(Format::Epub) => Self::Only(Format::Kindle),
+            Self::Only(Format::Kindle) => Self::All,
+        }
+    }
+
+    fn matches(self, book: &Book) -> bool {
+        match self {
+            Self::All => true,
+            Self::Only(format) => book.format() == Some(format),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, defmt::Format)]
+pub(crate) enum Cover {
+    /// Path of the image within the EPUB archive
+    Archive(String),
+    /// Record of the image in the Kindle book
+    Record(u16),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, defmt::Format)]
+pub(crate) struct Book {
+    pub(crate) path: String,
+    pub(crate) size: u32,
+    /// Packed like in FAT directory entries
+    pub(crate) modified: u32,
+    /// The file name if the book has no title
+    pub(crate) title: String,
+    pub(crate) author: Option<String>,
+    pub(crate) cover: Option<Cover>,
+}
+
+impl Book {
+    pub(crate) fn format(&self) -> Option<Format> {
+        Format::of(&self.path)
+    }
+}
+
+/// A book file found on the SD card
+struct File {
+    path: String,
+    size: u32,
+    modified: u32,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, defmt::Format)]
+pub(crate) struct Library {
+    /// Sorted by path
+    books: Vec<Book>,
+}
+
+impl Library {
+    /// A card without an index file was not indexed yet
+    pub(crate) async fn load(sd_card: &mut SdCard) -> Result<Self, ReadFileError> {
+        let content = match sd_card
+            .read_file(PATH, MAXIMUM_FILE_SIZE, ReadPriority::Interactive)
+            .await
+        {
+            Ok(content) => content,
+            Err(ReadFileError::Open(error)) if error.is_not_found() => return Ok(Self::default()),
+            Err(error) => return Err(error),
+        };
+
+        Ok(Self::parse(&String::from_utf8_lossy(&content)))
+    }
+
+    pub(crate) async fn save(&self, sd_card: &mut SdCard) -> Result<(), WriteFileError> {
+        sd_card.write_file(PATH, self.serialize().as_bytes()).await
+    }
+
+    pub(crate) fn books(&self) -> &[Book] {
+        &self.books
+    }
+
+    /// Indexes of the books that pass the filter in the order of the sort. Books that sort the same
+    /// are ordered by title.
+    pub(crate) fn arrange(&self, sort: Sort, filter: Filter, recent: &Recent) -> Vec<usize> {
+        let mut order = (0..self.books.len())
+            .filter(|&index| filter.matches(&self.books[index]))
+            .collect::<Vec<_>>();
+
+        order.sort_by(|&first, &second| {
+            let (first, second) = (&self.books[first], &self.books[second]);
+            let ordering = match sort {
+                Sort::Name => Ordering::Equal,
+                Sort::Modified => second.modified.cmp(&first.modified),
+                Sort::RecentlyRead => {
+                    let rank = |book: &Book| recent.rank(&book.path).unwrap_or(usize::MAX);
+                    rank(first).cmp(&rank(second))
+                }
+                Sort::Author => match (&first.author, &second.author) {
+                    (Some(first), Some(second)) => compare_text(first, second),
+                    (first, second) => first.is_none().cmp(&second.is_none()),
+                },
+            };
+
+            ordering.then_with(|| compare_text(&first.title, &second.title))
+        });
+
+        order
+    }
+
+    /// Looks for books on the card and reads the metadata of those that are new or changed.
+    /// Returns whether the index changed and needs to be saved.
+    pub(crate) async fn refresh(&mut self, sd_card: &mut SdCard) -> Result<bool, VolumeError> {
+        let mut files = find_books(sd_card).await?;
+        files.sort_unstable_by(|first, second| first.path.cmp(&second.path));
+
+        let mut is_changed = false;
+        let mut books = Vec::with_capacity(files.len());
+        let total = u32::try_from(files.len()).unwrap_or(u32::MAX);
+        for (done, file) in (0..).zip(files) {
+            let unchanged = self.books.iter().position(|book| {
+                book.path == file.path && book.size == file.size && book.modified == file.modified
+            });
+            if let Some(index) = unchanged {
+                books.push(self.books.swap_remove(index));
+                continue;
+            }
+
+            activity::report("Indexing library", done, total);
+            let book = read_book(sd_card, file).await;
+            if let Some(cover) = &book.cover
+                && let Err(error) = thumbnail::generate(sd_card, &book, cover).await
+            {
+                warn!(
+                    "Failed to make thumbnail of {}: {:?}",
+                    book.path.as_str(),
+                    defmt::Debug2Format(&error)
+                );
+            }
+
+            books.push(book);
+            is_changed = true;
+        }
+        activity::finish();
+
+        // Books that are left were removed from the card
+        is_changed |= !self.books.is_empty();
+        self.books = books;
+        info!("Library has {} books", self.books.len());
+        Ok(is_changed)
+    }
+
+    /// Lines that are not books are skipped to be able to read files from other versions
+    fn parse(content: &str) -> Self {
+        let mut books = Vec::new();
+        for line in content.lines() {
+            let Some(("book", value)) = line.split_once('=') else {
+                continue;
+            };
+
+            let mut fields = value.split('\t');
+            let (Some(path), Some(size), Some(modified), Some(title)) =
+                (fields.next(), fields.next(), fields.next(), fields.next())
+            else {
+                continue;
+            };
+
+            let (Ok(size), Ok(modified)) = (size.parse(), modified.parse()) else {
+                continue;
+            };
+
+            let author = fields.next().filter(|author| !author.is_empty());
+            let cover = fields
+                .next()
+                .and_then(|cover| match cover.strip_prefix('#') {
+                    Some(record) => record.parse().ok().map(Cover::Record),
+                    None if cover.is_empty() => None,
+                    None => Some(Cover::Archive(String::from(cover))),
+                });
+
+            if books.len() >= MAXIMUM_BOOKS {
+                break;
+            }
+
+            books.push(Book {
+                path: String::from(path),
+                size,
+                modified,
+                title: String::from(title),
+                author: author.map(String::from),
+                cover,
+            });
+        }
+
+        Self { books }
+    }
+
+    fn serialize(&self) -> String {
+        let mut content = String::new();
+        for book in &self.books {
+            let cover = match &book.cover {
+                Some(Cover::Archive(path)) => field(path),
+                Some(Cover::Record(record)) => format!("#{record}"),
+                None => String::new(),
+            };
+
+            content.push_str(&format!(
+                "book={}\t{}\t{}\t{}\t{}\t{}\n",
+                book.path,
+                book.size,
+                book.modified,
+                field(&book.title),
+                field(book.author.as_deref().unwrap_or_default()),
+                cover
+            ));
+        }
+
+        content
+    }
+}
+
+/// Walks the directories of the card for files with the extension of a book
+async fn find_books(sd_card: &mut SdCard) -> Result<Vec<File>, VolumeError> {
+    let mut books = Vec::new();
+    // Paths of the directories that are left to list, starting at the root
+    let mut directories = vec![String::new()];
+    while let Some(directory) = directories.pop() {
+        let path = if directory.is_empty() {
+            "/"
+        } else {
+            &directory
+        };
+        let entries = match sd_card.list_directory(path).await {
+            Ok(entries) => entries,
+            // Without the root, there is nothing to index
+            Err(error) if directory.is_empty() => return Err(error),
+            Err(error) => {
+                warn!("Failed to list {}: {:?}", path, error);
+                continue;
+            }
+        };
+
+        for entry in entries {
+            let attributes = entry.attributes;
+            let name = format!("{}", entry.name);
+            // Also skips "." and ".."
+            if name.starts_with('.') || attributes.is_hidden() || attributes.is_system() {
+                continue;
+            }
+
+            let path = format!("{directory}/{name}");
+            if attributes.is_directory() {
+                let depth = path.matches('/').count();
+                if depth < MAXIMUM_DEPTH && !SKIPPED_DIRECTORIES.contains(&name.as_str()) {
+                    directories.push(path);
+                }
+            } else if Format::of(&name).is_some() {
+                if books.len() >= MAXIMUM_BOOKS {
+                    error!("More than {} books, ignoring the rest", MAXIMUM_BOOKS);
+                    return Ok(books);
+                }
+
+                books.push(File {
+                    path,
+                    size: entry.size,
+                    modified: packed(&entry.mtime),
+                });
+            }
+        }
+    }
+
+    Ok(books)
+}
+
+/// Books that can't be read are still listed with their file name
+async fn read_book(sd_card: &mut SdCard, file: File) -> Book {
+    let name = file.path.rsplit('/').next().unwrap_or_default();
+    let name = name.rsplit_once('.').map_or(name, |(name, _)| name);
+    let mut book = Book {
+        title: String::from(name),
+        path: file.path,
+        size: file.size,
+        modified: file.modified,
+        author: None,
+        cover: None,
+    };
+
+    match Format::of(&book.path) {
+        Some(Format::Epub) => match metadata::read(sd_card, &book.path).await {
+            Ok(metadata) => {
+                book.title = metadata.title.unwrap_or(book.title);
+                book.author = metadata.author;
+                book.cover = metadata.cover.map(Cover::Archive);
+            }
+            Err(error) => {
+                error!(
+                    "Failed to read metadata of {}: {:?}",
+                    book.path.as_str(),
+                    defmt::Debug2Format(&error)
+                );
+            }
+        },
+        Some(Format::Kindle) => match read_kindle_book(sd_card, &book.path).await {
+            Ok(kindle_book) => {
+                if !kindle_book.title().is_empty() {
+                    book.title = String::from(kindle_book.title());
+                }
+                book.author = kindle_book.author().map(String::from);
+                book.cover = kindle_book.cover().map(Cover::Record);
+            }
+            Err(error) => {
+                error!(
+                    "Failed to read metadata of {}: {:?}",
+                    book.path.as_str(),
+                    defmt::Debug2Format(&error)
+                );
+            }
+        },
+        None => {}
+    }
+
+    book
+}
+
+async fn read_kindle_book(sd_card: &mut SdCard, path: &str) -> Result<mobi::Book, mobi::MobiError> {
+    let file = sd_card.open(path, Mode::ReadOnly).await?;
+    let result = mobi::Book::open(sd_card, file).await;
+
+    if let Err(error) = sd_card.close(file).await {
+        warn!("Failed to close book: {:?}", error);
+    }
+
+    result
+}
+
+/// Ignores the case so lowercase titles don't end up after all others
+fn compare_text(first: &str, second: &str) -> Ordering {
+    first
+        .chars()
+        .flat_map(char::to_lowercase)
+        .cmp(second.chars().flat_map(char::to_lowercase))
+}
+
+/// Tabs and line breaks would end the field or the line
+fn field(text: &str) -> String {
+    text.replace(['\t', '\n', '\r'], " ")
+}
+
+/// Modification time packed like in FAT directory entries, which is enough to notice changes
+fn packed(timestamp: &Timestamp) -> u32 {
+    (u32::from(timestamp.year_since_1970) << 25)
+        | (u32::from(timestamp.zero_indexed_month) << 21)
+        | (u32::from(timestamp.zero_indexed_day) << 16)
+        | (u32::from(timestamp.hours) << 11)
+        | (u32::from(timestamp.minutes) << 5)
+        | u32::from(timestamp.seconds / 2)
+}
@@ -0,0 +1,312 @@
+//! The books of the library to pick one to read, either as a list of titles or as a grid of covers.
+//! The covers are the thumbnails that were made when the library was indexed. The first entry opens
+//! a menu to sort and filter the books.
+
+use alloc::string::String;
+
+use defmt::{error, info};
+use embedded_graphics::Drawable;
+use embedded_graphics::mono_font::MonoTextStyle;
+use embedded_graphics::mono_font::ascii::{FONT_8X13, FONT_10X20};
+use embedded_graphics::pixelcolor::BinaryColor;
+use embedded_graphics::prelude::{OriginDimensions, Point, Primitive, Size};
+use embedded_graphics::primitives::{PrimitiveStyle, Rectangle};
+use embedded_graphics::text::{Alignment, Baseline, Text, TextStyleBuilder};
+
+use crate::eink_display::Frame;
+use crate::input::Analog;
+use crate::library::{Filter, Format, Library, Sort};
+use crate::thumbnail;
+use crate::widget::{GridView, ListView, Response};
+use crate::{SharedDisplay, SharedSdCard};
+
+/// Where the books start below the title
+const LIST_TOP: i32 = 45;
+const COLUMNS: usize = 3;
+/// A cover with a line for the title below
+const CELL_HEIGHT: u32 = 220;
+/// Space between the top of a cell and the cover
+const COVER_TOP: i32 = 8;
+const MENU_LABEL: &str = "Sort and filter";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, defmt::Format)]
+pub(crate) enum View {
+    #[default]
+    List,
+    Grid,
+}
+
+impl View {
+    pub(crate) fn as_str(self) -> &'static str {
+        match self {
+            Self::List => "list",
+            Self::Grid => "grid",
+        }
+    }
+
+    pub(crate) fn parse(value: &str) -> Option<Self> {
+        match value {
+            "list" => Some(Self::List),
+            "grid" => Some(Self::Grid),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn toggled(self) -> Self {
+        match self {
+            Self::List => Self::Grid,
+            Self::Grid => Self::List,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub(crate) enum Choice {
+    /// Open the menu to sort and filter
+    Arrange,
+    /// Index of the book in the library
+    Book(usize),
+}
+
+/// Lets the reader pick a book with the buttons. The books are shown in the order of their indexes
+/// in the library. The selected entry counts the menu entry before the books. Returns None if the
+/// reader backed out.
+pub(crate) async fn select(
+    library: &Library,
+    order: &[usize],
+    view: View,
+    selected: usize,
+    sd_card: &SharedSdCard,
+    analog: &mut Analog<'_>,
+    display: &SharedDisplay,
+    frame: &mut Frame,
+) -> Option<Choice> {
+    let entry = match view {
+        View::List => select_from_list(library, order, selected, analog, display, frame).await,
+        View::Grid => {
+            select_from_grid(library, order, selected, sd_card, analog, display, frame).await
+        }
+    }?;
+
+    let Some(entry) = entry.checked_sub(1) else {
+        return Some(Choice::Arrange);
+    };
+
+    let index = order[entry];
+    info!("Selected book {}", library.books()[index].path.as_str());
+    Some(Choice::Book(index))
+}
+
+/// Lets the reader change the sort and the filter with the buttons until they back out
+pub(crate) async fn arrange(
+    sort: &mut Sort,
+    filter: &mut Filter,
+    analog: &mut Analog<'_>,
+    display: &SharedDisplay,
+    frame: &mut Frame,
+) {
+    let mut list = ListView::new(2, 0);
+    loop {
+        draw_heading(MENU_LABEL, frame);
+        let label = |index: usize| match index {
+            0 => match sort {
+                Sort::Name => "Sort by name",
+                Sort::Modified => "Sort by last modified",
+                Sort::RecentlyRead => "Sort by recently read",
+                Sort::Author => "Sort by author",
+            },
+            _ => match filter {
+                Filter::All => "Show all books",
+                Filter::Only(Format::Epub) => "Show EPUB books",
+                Filter::Only(Format::Kindle) => "Show Kindle books",
+            },
+        };
+        if let Err(error) = list.draw(frame, &area(frame), label) {
+            error!("Failed to draw library menu: {:?}", error);
+        }
+        show(display, frame).await;
+
+        loop {
+            match list.handle(analog.wait_for_press().await) {
+                Response::Ignored => {}
+                Response::Changed => break,
+                Response::Confirmed => {
+                    match list.selected() {
+                        0 => *sort = sort.next(),
+                        _ => *filter = filter.next(),
+                    }
+                    break;
+                }
+                Response::Cancelled => return,
+            }
+        }
+    }
+}
+
+async fn select_from_list(
+    library: &Library,
+    order: &[usize],
+    selected: usize,
+    analog: &mut Analog<'_>,
+    display: &SharedDisplay,
+    frame: &mut Frame,
+) -> Option<usize> {
+    let mut list = ListView::new(order.len() + 1, selected);
+    loop {
+        draw_heading("Library", frame);
+        let label = |entry: usize| match entry.checked_sub(1) {
+            Some(entry) => library.books()[order[entry]].title.as_str(),
+            None => MENU_LABEL,
+        };
+        if let Err(error) = list.draw(frame, &area(frame), label) {
+            error!("Failed to draw library: {:?}", error);
+        }
+        show(display, frame).await;
+
+        loop {
+            match list.handle(analog.wait_for_press().await) {
+                Response::Ignored => {}
+                Response::Changed => break,
+                Response::Confirmed => return Some(list.selected()),
+                Response::Cancelled => return None,
+            }
+        }
+    }
+}
+
+async fn select_from_grid(
+    library: &Library,
+    order: &[usize],
+    selected: usize,
+    sd_card: &SharedSdCard,
+    analog: &mut Analog<'_>,
+    display: &SharedDisplay,
+    frame: &mut Frame,
+) -> Option<usize> {
+    let mut grid = GridView::new(order.len() + 1, selected, COLUMNS, CELL_HEIGHT);
+    loop {
+        draw_heading("Library", frame);
+        let area = area(frame);
+        let visible = grid.visible(&area);
+        for entry in visible {
+            let cell = grid.cell(&area, entry);
+            match entry.checked_sub(1) {
+                Some(entry) => draw_cell(library, order[entry], &cell, sd_card, frame).await,
+                None => draw_menu_cell(&cell, frame),
+            }
+        }
+        if let Err(error) = grid.draw_cursor(frame, &area) {
+            error!("Failed to draw library: {:?}", error);
+        }
+        show(display, frame).await;
+
+        loop {
+            match grid.handle(analog.wait_for_press().await) {
+                Response::Ignored => {}
+                Response::Changed => break,
+                Response::Confirmed => return Some(grid.selected()),
+                Response::Cancelled => return None,
+            }
+        }
+    }
+}
+
+/// Draws the cover with the title below. Books without a thumbnail get an empty frame instead.
+async fn draw_cell(
+    library: &Library,
+    index: usize,
+    cell: &Rectangle,
+    sd_card: &SharedSdCard,
+    frame: &mut Frame,
+) {
+    let book = &library.books()[index];
+    let thumbnail = match sd_card.lock().await.as_mut() {
+        Some(sd_card) => thumbnail::load(sd_card, book).await,
+        None => Ok(None),
+    };
+    let thumbnail = thumbnail.unwrap_or_else(|error| {
+        error!(
+            "Failed to load thumbnail: {:?}",
+            defmt::Debug2Format(&error)
+        );
+        None
+    });
+
+    // Can't truncate as the cells are wider than the thumbnails
+    let cover = cell.top_left
+        + Point::new(
+            (cell.size.width.saturating_sub(thumbnail::SIZE.width) / 2) as i32,
+            COVER_TOP,
+        );
+    let result = match thumbnail {
+        Some(thumbnail) => thumbnail.draw(frame, cover),
+        None => Rectangle::new(cover, thumbnail::SIZE)
+            .into_styled(PrimitiveStyle::with_stroke(BinaryColor::On, 1))
+            .draw(frame),
+    };
+    if let Err(error) = result {
+        error!("Failed to draw cover: {:?}", error);
+    }
+
+    // Titles that are longer than the cell are cut off
+    let style = MonoTextStyle::new(&FONT_8X13, BinaryColor::On);
+    // Can't truncate as the cells are narrow
+    let maximum_length = (cell.size.width / FONT_8X13.character_size.width).saturating_sub(1);
+    let title = book
+        .title
+        .chars()
+        .take(maximum_length as usize)
+        .collect::<String>();
+    let text_style = TextStyleBuilder::new()
+        .alignment(Alignment::Center)
+        .baseline(Baseline::Top)
+        .build();
+    // Can't truncate as the cells are small
+    let position = Point::new(
+        cell.center().x,
+        cover.y + thumbnail::SIZE.height as i32 + COVER_TOP,
+    );
+    if let Err(error) = Text::with_text_style(&title, position, style, text_style).draw(frame) {
+        error!("Failed to draw title: {:?}", error);
+    }
+}
+
+/// The menu entry in place of a cover
+fn draw_menu_cell(cell: &Rectangle, frame: &mut Frame) {
+    let style = MonoTextStyle::new(&FONT_8X13, BinaryColor::On);
+    let text_style = TextStyleBuilder::new()
+        .alignment(Alignment::Center)
+        .baseline(Baseline::Middle)
+        .build();
+    let text = Text::with_text_style(MENU_LABEL, cell.center(), style, text_style);
+    if let Err(error) = text.draw(frame) {
+        error!("Failed to draw library menu: {:?}", error);
+    }
+}
+
+fn draw_heading(heading: &str, frame: &mut Frame) {
+    // White
+    frame.fill(0xFF);
+
+    let style = MonoTextStyle::new(&FONT_10X20, BinaryColor::On);
+    if let Err(error) = Text::new(heading, Point::new(10, 30), style).draw(frame) {
+        error!("Failed to draw library: {:?}", error);
+    }
+}
+
+fn area(frame: &Frame) -> Rectangle {
+    let size = frame.size();
+    Rectangle::new(
+        Point::new(0, LIST_TOP),
+        Size::new(size.width, size.height - LIST_TOP as u32),
+    )
+}
+
+async fn show(display: &SharedDisplay, frame: &Frame) {
+    if let Err(error) = display.animate(frame).await {
+        error!(
+            "Failed to display library: {:?}",
+            defmt::Debug2Format(&error)
+        );
+    }
+}
@@ -0,0 +1,39 @@
+//! While waiting for a button, the chip goes to light sleep between the readings instead of idling
+//! with its clocks running, which takes the current while a page is read down to a fraction.
+//!
+//! The button ladders are analog and can't wake the chip on an edge, so it wakes on a timer for
+//! each reading and right away for the power button. Light sleep stops every task until the chip
+//! wakes up, so it is only entered when nothing else needs the chip:
+//! - No refresh is running, as the driver waits for the busy line.
+//! - The radio was not started, as the connection would drop.
+//! - The battery is not charging, as then the reader is on USB, where the serial console and the
+//!   logs would drop with the USB controller.
+
+use embassy_time::Duration;
+use esp_hal::rtc_cntl::sleep::{GpioWakeupSource, TimerWakeupSource};
+use portable_atomic::{AtomicBool, Ordering};
+
+use crate::{SharedRtc, battery, eink_display};
+
+static IS_RADIO_STARTED: AtomicBool = AtomicBool::new(false);
+
+/// Keeps the chip awake from now on, as the radio needs it to stay connected
+pub(crate) fn keep_awake_for_radio() {
+    IS_RADIO_STARTED.store(true, Ordering::Relaxed);
+}
+
+/// Whether no other task needs the chip to stay awake right now
+pub(crate) fn is_possible() -> bool {
+    !eink_display::is_refreshing()
+        && !IS_RADIO_STARTED.load(Ordering::Relaxed)
+        && !battery::is_charging()
+}
+
+/// Sleeps until the time is up or the power button is pressed. Blocks all tasks, so callers need
+/// to yield to the executor afterwards.
+pub(crate) fn sleep(rtc: &SharedRtc, duration: Duration) {
+    let timer = TimerWakeupSource::new(core::time::Duration::from_micros(duration.as_micros()));
+    // Wakes on the pins that enabled it, which is the power button
+    let gpio = GpioWakeupSource::new();
+    rtc.lock(|rtc| rtc.borrow_mut().sleep_light(&[&timer, &gpio]));
+}
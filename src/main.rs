@@ -7,33 +7,170 @@
 )]
 #![deny(clippy::large_stack_frames)]
 
+mod activity;
+mod battery;
+mod battery_guard;
+mod battery_history;
+mod benchmark;
+mod board;
+mod bookmarks;
+mod boot;
+mod calibration;
+mod charging;
+mod clock;
+mod config;
+mod console;
+mod crash_report;
+mod diagnostics;
+mod dictionary;
+mod display_task;
 mod eink_display;
+mod epub;
+mod event_bus;
+mod event_log;
+mod firmware;
+mod flash;
+mod glyphs;
+mod http;
+mod hyphenation;
+mod image;
 mod input;
+mod journal;
+mod kosync;
+mod library;
+mod library_screen;
+mod light_sleep;
+mod mdns;
+mod memory;
+mod mobi;
+#[cfg(feature = "radio")]
+mod network;
+mod no_card;
+mod pagination;
+mod power_button;
+mod power_profile;
+mod profile;
+mod progress;
+#[cfg(feature = "radio")]
+mod provisioning;
+mod qr_code;
+mod quote;
+mod reader;
+mod reader_menu;
+mod recent;
+mod screenshot;
+mod sd_card;
+mod settings;
+mod settings_screen;
+mod shortcut;
+mod sleep_screen;
+mod snapshot;
 mod spi;
+mod status_bar;
+mod supervisor;
+mod theme;
+mod thumbnail;
+mod timeout;
+mod toast;
+mod verbosity;
+mod webdav;
+mod widget;
+mod wifi;
+#[cfg(feature = "radio")]
+mod wifi_screen;
 
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::cell::RefCell;
 use defmt::{error, info};
 use embassy_executor::Spawner;
-use embassy_time::Timer;
+use embassy_futures::select::{Either, Either4, select, select4};
+use embassy_sync::blocking_mutex::{self, raw::NoopRawMutex};
+use embassy_sync::{mutex::Mutex, signal::Signal};
+use embassy_time::{Duration, Instant, Timer};
 use embedded_graphics::Drawable;
 use embedded_graphics::mono_font::MonoTextStyle;
 use embedded_graphics::mono_font::ascii::FONT_10X20;
 use embedded_graphics::pixelcolor::BinaryColor;
 use embedded_graphics::prelude::Point;
 use embedded_graphics::text::Text;
-use esp_hal::gpio::{self, Input, InputConfig};
-use esp_hal::peripherals::{GPIO3, LPWR};
-use esp_hal::rtc_cntl::sleep::{RtcioWakeupSource, WakeupLevel};
-use esp_hal::rtc_cntl::{reset_reason, wakeup_cause};
+use esp_hal::gpio::{self, Input, InputConfig, Output, WakeEvent};
+use esp_hal::rtc_cntl::sleep::{RtcioWakeupSource, SleepSource, TimerWakeupSource, WakeupLevel};
+use esp_hal::rtc_cntl::{SocResetReason, reset_reason, wakeup_cause};
 use esp_hal::system::Cpu;
 use esp_hal::timer::timg::TimerGroup;
 use esp_hal::{clock::CpuClock, rtc_cntl::Rtc};
+use esp_storage::FlashStorage;
+use static_cell::StaticCell;
 use {esp_backtrace as _, esp_println as _};
 
-use crate::eink_display::{EinkDisplay, Frame};
-use crate::input::Analog;
+use crate::activity::Activity;
+use crate::battery::BrownOutHistory;
+use crate::board::Board;
+use crate::bookmarks::Bookmarks;
+use crate::boot::Boot;
+use crate::config::Config;
+use crate::display_task::{DisplayHandle, SharedDisplay, ShowMode};
+use crate::eink_display::{
+    EinkDisplay, FirmwareMonitor, Frame, LoadLutError, RefreshQueue, RefreshRequest, RetryPolicy,
+    load_lut,
+};
+use crate::epub::validation;
+use crate::event_bus::{BatteryAlert, Event};
+use crate::flash::SharedFlash;
+use crate::glyphs::{GlyphCache, OpenGlyphsError};
+use crate::hyphenation::Patterns;
+use crate::input::{Analog, Button, InputEvent};
+use crate::library::{Book, Format, Library};
+use crate::pagination::{Hyphenator, NoHyphenation};
+use crate::power_button::Press;
+use crate::profile::{Profile, Profiles};
+use crate::progress::{AudiobookPosition, Progress};
+use crate::qr_code::DrawQrCodeError;
+use crate::quote::Quote;
+use crate::recent::Recent;
+use crate::sd_card::{CardChange, ReadFileError, SdCard, Unmounted};
+use crate::settings::{LoadStoredSettingsError, Settings, SettingsStorage};
+use crate::settings_screen::SettingsScreen;
+use crate::snapshot::{self, LoadSnapshotError, SnapshotStorage};
+use crate::status_bar::StatusBar;
+use crate::theme::Theme;
+use crate::widget::{Dialog, Response};
 
 extern crate alloc;
 
+type Display = EinkDisplay<
+    spi::Device<'static>,
+    Output<'static>,
+    Output<'static>,
+    Input<'static>,
+    FirmwareMonitor,
+>;
+/// Mounted in the background after start up. None until then or if there is no usable card.
+type SharedSdCard = Mutex<NoopRawMutex, Option<SdCard>>;
+/// Profiles are stored on the SD card, so they are only known after it is mounted
+type ProfilesSignal = Signal<NoopRawMutex, Profiles>;
+/// Turns the device off like the power button, for when the battery is about to run empty
+type ShutdownSignal = Signal<NoopRawMutex, ()>;
+/// Puts the device into deep sleep and keeps the time through it
+type SharedRtc = blocking_mutex::Mutex<NoopRawMutex, RefCell<Rtc<'static>>>;
+
+/// Refreshes requested by the subsystems drawing into the frame
+static REFRESH_QUEUE: RefreshQueue = RefreshQueue::new();
+
+/// Waveforms tuned by the community can be tried out by putting them on the SD card
+const CUSTOM_LUT_PATH: &str = "/waveform.lut";
+/// The memory overlay is only redrawn now and then, as every refresh costs battery
+const OVERLAY_INTERVAL: Duration = Duration::from_secs(10);
+/// Time between readings of the battery on the home screen. The gauge smooths over several.
+const BATTERY_INTERVAL: Duration = Duration::from_secs(5);
+/// What the buttons open on the home screen, see the home loop in [`run`]
+#[cfg(feature = "radio")]
+const HOME_TEXT: &str = "Crustpoint\n\nConfirm: Library\nRight: Settings\nLeft: Sync library";
+#[cfg(not(feature = "radio"))]
+const HOME_TEXT: &str = "Crustpoint\n\nConfirm: Library\nRight: Settings";
+
 // This creates a default app-descriptor required by the esp-idf bootloader.
 // For more information see: <https://docs.espressif.com/projects/esp-idf/en/stable/esp32/api-reference/system/app_image_format.html#application-description>
 esp_bootloader_esp_idf::esp_app_desc!();
@@ -56,149 +193,2212 @@ enum ApplicationError {
     ),
     #[error("Error spawning task")]
     Spawn(#[from] embassy_executor::SpawnError),
+    #[error("No frame left in the pool")]
+    NoFrame,
 }
 
 #[embassy_executor::task]
 async fn handle_power_button(
-    mut pin: GPIO3<'static>,
-    lpwr: LPWR<'static>,
-    mut eink_display: EinkDisplay<'static, spi::Device<'static>>,
+    mut pin: board::PowerButton,
+    rtc: &'static SharedRtc,
+    display: DisplayHandle,
+    sd_card: &'static SharedSdCard,
+    shutdown: &'static ShutdownSignal,
+    mut frame: Option<&'static mut Frame>,
 ) {
-    loop {
+    let is_charging = loop {
         let borrowed = pin.reborrow();
 
         let mut power_button = Input::new(borrowed, InputConfig::default());
-        // Low = pressed, High = released
-        power_button.wait_for_low().await;
-
-        info!("Power button pressed. Turning off");
-
-        let frame = Frame::default();
+        // Wakes the chip from light sleep between the readings of the other buttons
+        if let Err(error) = power_button.wakeup_enable(true, WakeEvent::LowLevel) {
+            error!(
+                "Failed to let the power button wake from light sleep: {:?}",
+                defmt::Debug2Format(&error)
+            );
+        }
+        let press = power_button::wait_for_press(&mut power_button);
+        let is_battery_low = match select(press, shutdown.wait()).await {
+            Either::First(Press::Short) => {
+                toggle_display_sleep(&display).await;
+                event_bus::publish(Event::PowerButton(Press::Short));
+                continue;
+            }
+            Either::First(Press::Long) => {
+                info!("Power button held. Turning off");
+                event_bus::publish(Event::PowerButton(Press::Long));
+                false
+            }
+            Either::Second(()) => {
+                info!("Battery low. Turning off");
+                true
+            }
+        };
+        event_log::record(event_log::Event::ShuttingDown { is_battery_low });
+        event_log::flush(sd_card, rtc).await;
 
-        if let Err(error) = eink_display
-            .display(eink_display::RefreshMode::Full, &frame)
-            .await
-        {
+        // The page comes back from RTC memory when the reader wakes up
+        if let Err(error) = display.keep_shown().await {
             error!(
-                "Failed to update display before entering deep sleep: {:?}",
+                "Failed to keep screen through deep sleep: {:?}",
                 defmt::Debug2Format(&error)
             );
-            continue;
         }
 
-        let Err(error) = eink_display.enter_deep_sleep().await else {
-            break;
+        // The charging screen takes the place of the sleep screen until the battery is charged
+        let is_charging = battery::is_charging();
+        let millivolts = battery::last_millivolts();
+        if is_charging {
+            charging::start(millivolts);
+        } else {
+            charging::finish();
+        }
+
+        // The low RAM profile has no frame to spare, so the page stays on the panel until the
+        // charging screen is drawn on the first wake
+        if let Some(frame) = frame.as_deref_mut() {
+            // White
+            frame.fill(0xFF);
+            if is_charging {
+                if let Err(error) = charging::draw(millivolts, frame) {
+                    error!("Failed to draw charging screen: {:?}", error);
+                }
+            } else if let Some(sd_card) = sd_card.lock().await.as_mut() {
+                sleep_screen::draw(sd_card, frame).await;
+            }
+
+            if let Err(error) = display
+                .show(ShowMode::Exact(eink_display::RefreshMode::Full), frame)
+                .await
+            {
+                error!(
+                    "Failed to update display before entering deep sleep: {:?}",
+                    defmt::Debug2Format(&error)
+                );
+                continue;
+            }
+        }
+
+        // A button that is still held would wake the reader right away.
+        // Low = pressed, High = released
+        power_button.wait_for_high().await;
+
+        let Err(error) = display.sleep().await else {
+            break is_charging;
         };
 
         error!(
             "Failed to enter deep sleep: {:?}",
             defmt::Debug2Format(&error)
         );
-    }
+    };
+
+    let timer = if is_charging {
+        charging::WAKE_INTERVAL
+    } else {
+        battery_guard::CHECK_INTERVAL
+    };
+    enter_deep_sleep(&mut pin, rtc, Some(timer)).await;
+}
 
+/// Sleeps until the power button is pressed or the timer is up, which is either to update the
+/// charging screen or to check the battery
+async fn enter_deep_sleep(
+    power_button: &mut board::PowerButton,
+    rtc: &SharedRtc,
+    timer: Option<core::time::Duration>,
+) {
     // Just to be safe and avoid bricking the device when we accidentally run the deep sleep after reboot
     Timer::after_secs(5).await;
     info!("Entering deep sleep");
 
     let wakeup_pins: &mut [(&mut dyn gpio::RtcPinWithResistors, WakeupLevel)] =
-        &mut [(&mut pin, WakeupLevel::Low)];
+        &mut [(power_button, WakeupLevel::Low)];
 
     let rtcio = RtcioWakeupSource::new(wakeup_pins);
+    board::hold_display_pins();
 
-    // LPWR = Low Power Watchdog and Reset? Low Power Wrapper? LowPoWeR? Laser Power?
-    let mut real_time_control = Rtc::new(lpwr);
-    real_time_control.sleep_deep(&[&rtcio]);
+    rtc.lock(|rtc| {
+        let mut rtc = rtc.borrow_mut();
+        match timer {
+            Some(duration) => rtc.sleep_deep(&[&rtcio, &TimerWakeupSource::new(duration)]),
+            None => rtc.sleep_deep(&[&rtcio]),
+        }
+    });
 }
 
-/// Just a convenience replacement for main to be able to return errors
-async fn run(spawner: Spawner) -> Result<(), ApplicationError> {
-    let reset_reason = reset_reason(Cpu::ProCpu);
-    let wake_reason = wakeup_cause();
+/// Woken up by the timer while off, either to update the charging screen or to check the battery.
+/// Goes back to deep sleep right after.
+async fn handle_timer_wake(
+    analog: &mut Analog<'_>,
+    display: &mut Display,
+    frame: &mut Frame,
+    sd_card_spi: spi::CardDevice,
+    power_button: &mut board::PowerButton,
+    rtc: &SharedRtc,
+) {
+    let millivolts = analog.battery_millivolts().await;
+    let timer = if charging::is_shown() {
+        update_charging_screen(millivolts, display, frame, sd_card_spi).await
+    } else {
+        check_battery(millivolts, display, frame).await
+    };
 
+    // The controller was reset while starting
+    if let Err(error) = display.enter_deep_sleep().await {
+        error!(
+            "Failed to put display to sleep: {:?}",
+            defmt::Debug2Format(&error)
+        );
+    }
+
+    enter_deep_sleep(power_button, rtc, timer).await;
+}
+
+/// Shows the charge or the sleep screen again once the battery stopped charging. Returns when to
+/// wake up next.
+async fn update_charging_screen(
+    millivolts: u16,
+    display: &mut Display,
+    frame: &mut Frame,
+    sd_card_spi: spi::CardDevice,
+) -> Option<core::time::Duration> {
+    let is_charging = charging::is_still_charging(millivolts);
     info!(
-        "Reset reason: {:?}; Wakeup reason: {:?}",
-        defmt::Debug2Format(&reset_reason),
-        wake_reason
+        "Updating charging screen at {} mV. Charging: {}",
+        millivolts, is_charging
     );
 
-    let config = esp_hal::Config::default().with_cpu_clock(CpuClock::max());
-    let peripherals = esp_hal::init(config);
+    if is_charging {
+        if let Err(error) = charging::draw(millivolts, frame) {
+            error!("Failed to draw charging screen: {:?}", error);
+        }
+    } else {
+        charging::finish();
+        // White
+        frame.fill(0xFF);
+        match Unmounted::new(sd_card_spi).mount().await {
+            Ok(mut sd_card) => {
+                sleep_screen::draw(&mut sd_card, frame).await;
+            }
+            Err((error, _)) => {
+                error!("Failed to mount SD card: {:?}", defmt::Debug2Format(&error));
+            }
+        }
+    }
 
-    // esp_alloc::heap_allocator!(#[esp_hal::ram(reclaimed)] size: 66320);
-    // COEX needs more RAM - so we've added some more
-    esp_alloc::heap_allocator!(size: 64 * 1024);
+    if let Err(error) = display
+        .display(eink_display::RefreshMode::Full, frame)
+        .await
+    {
+        error!(
+            "Failed to display charging screen: {:?}",
+            defmt::Debug2Format(&error)
+        );
+    }
 
-    let timer_group_0 = TimerGroup::new(peripherals.TIMG0);
-    let software_interrupt =
-        esp_hal::interrupt::software::SoftwareInterruptControl::new(peripherals.SW_INTERRUPT);
-    esp_rtos::start(timer_group_0.timer0, software_interrupt.software_interrupt0);
+    Some(if is_charging {
+        charging::WAKE_INTERVAL
+    } else {
+        battery_guard::CHECK_INTERVAL
+    })
+}
 
-    info!("Embassy initialized!");
+/// Leaves the sleep screen as it is unless the battery needs to be charged. Returns when to wake up
+/// next, which is not until the power button once the warning is shown.
+async fn check_battery(
+    millivolts: u16,
+    display: &mut Display,
+    frame: &mut Frame,
+) -> Option<core::time::Duration> {
+    info!("Checking battery while off: {} mV", millivolts);
+    // Without a battery, e.g. on USB power, there is nothing to protect
+    if !battery::is_plausible(millivolts) || !battery_guard::is_critical(millivolts) {
+        return Some(battery_guard::CHECK_INTERVAL);
+    }
 
-    // Set up epaper display
-    // Custom pins for XteinkX4, not hardware SPI defaults
-    // SPI Clock (SCLK = serial clock)
-    let serial_clock = peripherals.GPIO8;
-    // SPI Master Out Slave In (MOSI)
-    let master_out_slave_in = peripherals.GPIO10;
-    let master_in_slave_out = peripherals.GPIO7;
-    // Display Chip Select (CS)
-    let display_chip_select = peripherals.GPIO21;
-    // Data/Command (DC)
-    let data_command = peripherals.GPIO4;
-    // Reset (RST)
-    let reset = peripherals.GPIO5;
-    // Busy
-    let busy = peripherals.GPIO6;
-
-    let mut analog = Analog::new(
-        peripherals.ADC1,
-        peripherals.GPIO0,
-        peripherals.GPIO1,
-        peripherals.GPIO2,
-    );
+    info!("Battery critical while off");
+    if let Err(error) = battery_guard::draw_warning(frame) {
+        error!("Failed to draw battery warning: {:?}", error);
+    }
+
+    if let Err(error) = display
+        .display(eink_display::RefreshMode::Full, frame)
+        .await
+    {
+        error!(
+            "Failed to display battery warning: {:?}",
+            defmt::Debug2Format(&error)
+        );
+    }
 
-    let direct_memory_access_channel = peripherals.DMA_CH0;
-    let sd_card_chip_select = peripherals.GPIO12;
+    None
+}
 
-    let (display_spi, _sd_card_spi) = spi::set_up_devices(
-        peripherals.SPI2,
-        serial_clock,
-        master_out_slave_in,
-        master_in_slave_out,
-        direct_memory_access_channel,
-        display_chip_select,
-        sd_card_chip_select,
-    )?;
+/// Turns the display off or on again. The panel keeps the page while the controller sleeps, and
+/// the next refresh wakes it as well unless that is turned off in the config.
+async fn toggle_display_sleep(display: &SharedDisplay) {
+    let mut display = display.lock().await;
+    if display.is_asleep() {
+        info!("Power button pressed. Waking display");
+        if let Err(error) = display.wake().await {
+            error!("Failed to wake display: {:?}", defmt::Debug2Format(&error));
+        }
+        return;
+    }
 
-    info!("Initializing display");
+    info!("Power button pressed. Putting display to sleep");
+    if let Err(error) = display.enter_deep_sleep().await {
+        error!(
+            "Failed to put display to sleep: {:?}",
+            defmt::Debug2Format(&error)
+        );
+    }
+}
 
-    let mut display = EinkDisplay::initialize(display_spi, reset, data_command, busy)
-        .await
-        .map_err(ApplicationError::SetUpEinkDisplay)?;
+/// Mounting the SD card and what depends on it is not needed for the first frame, so it happens in
+/// the background to get to a visible page faster. Keeps watching the slot for the card being
+/// removed or inserted afterwards.
+#[embassy_executor::task]
+async fn initialize_storage(
+    device: spi::CardDevice,
+    sd_card: &'static SharedSdCard,
+    display: DisplayHandle,
+    profiles: &'static ProfilesSignal,
+) {
+    let mut mounted = sd_card.lock().await;
+    // A missing or broken SD card should not keep the device from starting
+    let unmounted = match Unmounted::new(device).mount().await {
+        Ok(card) => {
+            *mounted = Some(card);
+            None
+        }
+        Err((error, slot)) => {
+            error!("Failed to mount SD card: {:?}", defmt::Debug2Format(&error));
+            Some(slot)
+        }
+    };
+
+    let loaded = match mounted.as_mut() {
+        Some(card) => {
+            apply_custom_lut(card, &mut *display.lock().await).await;
+            match Profiles::load(card).await {
+                Ok(loaded) => loaded,
+                Err(error) => {
+                    error!("Failed to load profiles: {:?}", defmt::Debug2Format(&error));
+                    Profiles::single()
+                }
+            }
+        }
+        None => Profiles::single(),
+    };
+
+    drop(mounted);
+    profiles.signal(loaded);
+    sd_card::watch(sd_card, unmounted).await;
+}
+
+async fn apply_custom_lut(sd_card: &mut SdCard, display: &mut Display) {
+    let lut = match load_lut(sd_card, CUSTOM_LUT_PATH).await {
+        Ok(lut) => lut,
+        Err(LoadLutError::Read(ReadFileError::Open(error))) if error.is_not_found() => {
+            info!("No custom LUT on SD card, using built-in waveforms");
+            return;
+        }
+        Err(error) => {
+            error!(
+                "Failed to load custom LUT: {:?}",
+                defmt::Debug2Format(&error)
+            );
+            return;
+        }
+    };
+
+    if let Err(error) = display.set_custom_lut(&lut).await {
+        error!(
+            "Failed to set custom LUT: {:?}",
+            defmt::Debug2Format(&error)
+        );
+    }
+}
+
+/// Without an SD card, the settings last saved in flash are used
+async fn load_settings(
+    sd_card: &SharedSdCard,
+    profile: &Profile,
+    storage: Option<&SettingsStorage>,
+) -> Settings {
+    let mut sd_card = sd_card.lock().await;
+    let Some(sd_card) = sd_card.as_mut() else {
+        return match storage.map(SettingsStorage::load) {
+            None | Some(Err(LoadStoredSettingsError::NoSettings)) => Settings::default(),
+            Some(Ok(settings)) => settings,
+            Some(Err(error)) => {
+                error!(
+                    "Failed to load settings from flash: {:?}",
+                    defmt::Debug2Format(&error)
+                );
+                Settings::default()
+            }
+        };
+    };
+
+    let settings = match Settings::load(sd_card, profile).await {
+        Ok(settings) => settings,
+        Err(ReadFileError::Open(error)) if error.is_not_found() => Settings::default(),
+        Err(error) => {
+            error!("Failed to load settings: {:?}", defmt::Debug2Format(&error));
+            return Settings::default();
+        }
+    };
+
+    // Keeps the settings of the last profile for when the card is removed
+    if let Some(storage) = storage
+        && let Err(error) = storage.save(&settings)
+    {
+        error!(
+            "Failed to save settings to flash: {:?}",
+            defmt::Debug2Format(&error)
+        );
+    }
+
+    settings
+}
+
+/// Saves the settings to the profile on the SD card and to flash
+async fn save_settings(
+    settings: &Settings,
+    profile: &Profile,
+    sd_card: &SharedSdCard,
+    storage: Option<&SettingsStorage>,
+) {
+    if let Some(sd_card) = sd_card.lock().await.as_mut()
+        && let Err(error) = settings.save(sd_card, profile).await
+    {
+        error!("Failed to save settings: {:?}", defmt::Debug2Format(&error));
+    }
+
+    if let Some(storage) = storage
+        && let Err(error) = storage.save(settings)
+    {
+        error!(
+            "Failed to save settings to flash: {:?}",
+            defmt::Debug2Format(&error)
+        );
+    }
+}
+
+/// Makes the theme named in the settings the active one with the font size from the settings
+async fn apply_theme(sd_card: &SharedSdCard, settings: &Settings) {
+    let mut theme = match &settings.theme {
+        Some(name) => load_theme(sd_card, name).await,
+        None => Theme::default(),
+    };
+
+    if let Some(font_size) = settings.font_size {
+        theme.body_font = font_size;
+    }
+
+    theme::set_active(theme);
+}
+
+/// The hyphenation patterns for the language in the settings. None without a language or if the
+/// patterns can't be loaded.
+async fn load_hyphenation(sd_card: &SharedSdCard, settings: &Settings) -> Option<Patterns> {
+    let language = settings.language.as_ref()?;
+    let mut sd_card = sd_card.lock().await;
+    match Patterns::load(sd_card.as_mut()?, language).await {
+        Ok(patterns) => Some(patterns),
+        Err(error) => {
+            error!(
+                "Failed to load hyphenation patterns for {}: {:?}",
+                language.as_str(),
+                defmt::Debug2Format(&error)
+            );
+            None
+        }
+    }
+}
 
-    let mut frame = Frame::default();
+/// Without a glyph file, characters the fonts don't have are left out
+async fn load_glyphs(sd_card: &'static SharedSdCard) -> Option<GlyphCache> {
+    match GlyphCache::open(sd_card).await {
+        Ok(glyphs) => Some(glyphs),
+        Err(OpenGlyphsError::Open(error)) if error.is_not_found() => {
+            info!("No glyph file on the SD card");
+            None
+        }
+        Err(error) => {
+            error!(
+                "Failed to open glyph file: {:?}",
+                defmt::Debug2Format(&error)
+            );
+            None
+        }
+    }
+}
+
+/// The configuration file is optional, so errors only leave the defaults
+async fn load_config(sd_card: &SharedSdCard) -> Config {
+    let mut sd_card = sd_card.lock().await;
+    let Some(sd_card) = sd_card.as_mut() else {
+        return Config::default();
+    };
+
+    match Config::load(sd_card).await {
+        Ok(config) => config,
+        Err(error) => {
+            error!("Failed to load config: {:?}", defmt::Debug2Format(&error));
+            Config::default()
+        }
+    }
+}
+
+/// Loads the library index and brings it up to date with the books on the card
+async fn load_library(sd_card: &SharedSdCard) -> Library {
+    let mut sd_card = sd_card.lock().await;
+    let Some(sd_card) = sd_card.as_mut() else {
+        return Library::default();
+    };
+
+    let mut library = match Library::load(sd_card).await {
+        Ok(library) => library,
+        Err(error) => {
+            error!(
+                "Failed to load library index: {:?}",
+                defmt::Debug2Format(&error)
+            );
+            Library::default()
+        }
+    };
+
+    match library.refresh(sd_card).await {
+        Ok(true) => {
+            if let Err(error) = library.save(sd_card).await {
+                error!(
+                    "Failed to save library index: {:?}",
+                    defmt::Debug2Format(&error)
+                );
+            }
+        }
+        Ok(false) => {}
+        Err(error) => error!("Failed to index library: {:?}", error),
+    }
+
+    info!("{} books in the library", library.books().len());
+    library
+}
+
+/// Falls back to the built-in theme if the theme can't be loaded
+async fn load_theme(sd_card: &SharedSdCard, name: &str) -> Theme {
+    let mut sd_card = sd_card.lock().await;
+    let Some(sd_card) = sd_card.as_mut() else {
+        return Theme::default();
+    };
+
+    match Theme::load(sd_card, name).await {
+        Ok(theme) => {
+            info!("Using theme {}", name);
+            theme
+        }
+        Err(error) => {
+            error!(
+                "Failed to load theme {}: {:?}",
+                name,
+                defmt::Debug2Format(&error)
+            );
+            Theme::default()
+        }
+    }
+}
+
+/// Opens the settings of the profile until the reader backs out
+async fn open_settings(
+    settings: &mut Settings,
+    profile: &Profile,
+    sd_card: &SharedSdCard,
+    storage: Option<&SettingsStorage>,
+    hyphenator: &dyn Hyphenator,
+    glyphs: Option<&GlyphCache>,
+    #[cfg(feature = "radio")] radio: &mut network::Radio,
+    analog: &mut Analog<'_>,
+    display: &SharedDisplay,
+    frame: &mut Frame,
+) {
+    let mut screen = SettingsScreen::new();
+    // Where to continue the manual as long as the settings are open
+    let mut manual_position = 0;
+    while let Some(entry) = screen.select(settings, analog, display, frame).await {
+        match entry {
+            settings_screen::Entry::DarkMode => {
+                settings.dark_mode = !settings.dark_mode;
+                display.lock().await.set_inverted(settings.dark_mode);
+                save_settings(settings, profile, sd_card, storage).await;
+            }
+            settings_screen::Entry::FontSize => {
+                if let Some(font_size) =
+                    settings_screen::pick_font_size(settings.font_size, analog, display, frame)
+                        .await
+                {
+                    settings.font_size = font_size;
+                    apply_theme(sd_card, settings).await;
+                    save_settings(settings, profile, sd_card, storage).await;
+                }
+            }
+            settings_screen::Entry::Theme => {
+                change_theme(settings, profile, sd_card, storage, analog, display, frame).await;
+            }
+            settings_screen::Entry::LibraryView => {
+                settings.library_view = settings.library_view.toggled();
+                save_settings(settings, profile, sd_card, storage).await;
+            }
+            settings_screen::Entry::TurboPage => {
+                settings.turbo_page = !settings.turbo_page;
+                display.lock().await.set_turbo(settings.turbo_page);
+                save_settings(settings, profile, sd_card, storage).await;
+            }
+            #[cfg(feature = "radio")]
+            settings_screen::Entry::Wifi => provision_wifi(radio, analog, display, frame).await,
+            settings_screen::Entry::Manual => {
+                manual_position = reader::read(
+                    settings_screen::MANUAL,
+                    manual_position,
+                    hyphenator,
+                    glyphs,
+                    analog,
+                    display,
+                    frame,
+                )
+                .await;
+            }
+            settings_screen::Entry::BatteryHistory => {
+                show_battery_history(sd_card, display, frame).await;
+                analog.wait_for_press().await;
+            }
+            settings_screen::Entry::CalibrateButtons => {
+                if let Some(calibration) = calibration::calibrate(analog, display, frame).await {
+                    settings.button_calibration = Some(calibration);
+                    save_settings(settings, profile, sd_card, storage).await;
+                }
+            }
+            settings_screen::Entry::Benchmark => {
+                show_benchmark(sd_card, display, frame).await;
+                analog.wait_for_press().await;
+            }
+        }
+    }
+}
+
+/// Plots the battery history from the SD card. Without a card the graph is empty.
+async fn show_battery_history(sd_card: &SharedSdCard, display: &SharedDisplay, frame: &mut Frame) {
+    let samples = match sd_card.lock().await.as_mut() {
+        Some(sd_card) => battery_history::load(sd_card)
+            .await
+            .unwrap_or_else(|error| {
+                error!(
+                    "Failed to load battery history: {:?}",
+                    defmt::Debug2Format(&error)
+                );
+                Vec::new()
+            }),
+        None => Vec::new(),
+    };
+
+    if let Err(error) = battery_history::draw(&samples, frame) {
+        error!("Failed to draw battery history: {:?}", error);
+        return;
+    }
+
+    if let Err(error) = display.submit(frame).await {
+        error!(
+            "Failed to display battery history: {:?}",
+            defmt::Debug2Format(&error)
+        );
+    }
+}
+
+/// Reads the battery on the home screen and publishes what needs attention. Only quiet readings go
+/// into the charge, but shutting down before a brown-out needs to see the sag under load as well.
+async fn read_battery(
+    analog: &mut Analog<'_>,
+    gauge: &mut battery::Gauge,
+    battery_sampled: &mut Option<Instant>,
+    shutdown_threshold: u16,
+    display: &SharedDisplay,
+    sd_card: &SharedSdCard,
+    rtc: &SharedRtc,
+) {
+    let is_quiet = battery::is_quiet();
+    let millivolts = analog.battery_millivolts().await;
+    if !battery::is_plausible(millivolts) {
+        return;
+    }
+
+    battery::record(millivolts);
+    if millivolts < shutdown_threshold {
+        event_bus::publish(Event::Battery(BatteryAlert::Low));
+    }
+
+    if !is_quiet {
+        return;
+    }
+
+    let previous = gauge.percentage();
+    let was_charging = gauge.is_charging();
+    gauge.record(millivolts, display.lock().await.temperature());
+    status_bar::set_battery_percentage(gauge.percentage());
+    if gauge.percentage() != previous {
+        info!("Battery at {}%", gauge.percentage());
+        event_log::record(event_log::Event::Battery {
+            millivolts,
+            percentage: gauge.percentage(),
+        });
+    }
+
+    if let Some(millivolts) = gauge.millivolts()
+        && battery_sampled
+            .is_none_or(|sampled| sampled.elapsed() >= battery_history::SAMPLE_INTERVAL)
+    {
+        battery_history::record(sd_card, rtc, millivolts, gauge.is_charging()).await;
+        *battery_sampled = Some(Instant::now());
+    }
+
+    if gauge.is_charging() != was_charging {
+        let alert = if gauge.is_charging() {
+            BatteryAlert::ChargingStarted
+        } else {
+            BatteryAlert::ChargingStopped
+        };
+        event_bus::publish(Event::Battery(alert));
+        status_bar::update();
+    }
+}
+
+/// Runs the benchmark and shows the results
+async fn show_benchmark(sd_card: &SharedSdCard, display: &SharedDisplay, frame: &mut Frame) {
+    let report = benchmark::run(sd_card, display, frame).await;
+    if let Err(error) = benchmark::draw(&report, frame) {
+        error!("Failed to draw benchmark: {:?}", error);
+        return;
+    }
+
+    if let Err(error) = display.submit(frame).await {
+        error!(
+            "Failed to display benchmark: {:?}",
+            defmt::Debug2Format(&error)
+        );
+    }
+}
+
+/// Settings entry to switch between the theme packs on the SD card
+async fn change_theme(
+    settings: &mut Settings,
+    profile: &Profile,
+    sd_card: &SharedSdCard,
+    storage: Option<&SettingsStorage>,
+    analog: &mut Analog<'_>,
+    display: &SharedDisplay,
+    frame: &mut Frame,
+) {
+    let names = match sd_card.lock().await.as_mut() {
+        Some(sd_card) => theme::list(sd_card).await,
+        None => Ok(Vec::new()),
+    };
+    let names = names.unwrap_or_else(|error| {
+        error!("Failed to list themes: {:?}", error);
+        Vec::new()
+    });
+
+    let Some(selected) =
+        theme::select(&names, settings.theme.as_deref(), analog, display, frame).await
+    else {
+        return;
+    };
+
+    settings.theme = selected;
+    apply_theme(sd_card, settings).await;
+    save_settings(settings, profile, sd_card, storage).await;
+}
+
+/// Saves the reading progress that was left unsaved when the last session ended unexpectedly. It
+/// stays in RTC memory until there is an SD card to save it to.
+async fn recover_progress(sd_card: &SharedSdCard, profile: &Profile) {
+    let Some((book, progress)) = journal::pending() else {
+        return;
+    };
+
+    let mut sd_card = sd_card.lock().await;
+    let Some(sd_card) = sd_card.as_mut() else {
+        return;
+    };
+
+    info!("Recovering unsaved progress of book {}: {}", book, progress);
+    if let Err(error) = progress.save_book(sd_card, profile, book).await {
+        error!(
+            "Failed to save recovered progress: {:?}",
+            defmt::Debug2Format(&error)
+        );
+    }
+}
+
+/// Learns from a brown-out if that is what the device just came back from
+async fn load_shutdown_threshold(
+    sd_card: &SharedSdCard,
+    reset_reason: Option<SocResetReason>,
+) -> u16 {
+    let mut sd_card = sd_card.lock().await;
+    let Some(sd_card) = sd_card.as_mut() else {
+        return BrownOutHistory::default().shutdown_threshold();
+    };
+
+    let mut history = match BrownOutHistory::load(sd_card).await {
+        Ok(history) => history,
+        Err(error) => {
+            error!(
+                "Failed to load brown-out history: {:?}",
+                defmt::Debug2Format(&error)
+            );
+            BrownOutHistory::default()
+        }
+    };
+
+    if let Some(millivolts) = battery::brown_out_voltage(reset_reason) {
+        info!("Browned out at {} mV", millivolts);
+        history.record(millivolts);
+        if let Err(error) = history.save(sd_card).await {
+            error!(
+                "Failed to save brown-out history: {:?}",
+                defmt::Debug2Format(&error)
+            );
+        }
+    }
+
+    history.shutdown_threshold()
+}
+
+/// The home screen lists what the buttons open
+fn draw_home(frame: &mut Frame) {
+    if let Err(error) = status_bar::current().draw(frame) {
+        error!("Failed to draw status bar: {:?}", error);
+    }
 
     let style = MonoTextStyle::new(&FONT_10X20, BinaryColor::On);
-    let text = Text::new("Hello, World!", Point::new(0, 20), style);
-    if let Err(error) = text.draw(&mut frame) {
+    // Can't truncate as the status bar is small
+    let below_status_bar = StatusBar::area().size.height as i32;
+    let text = Text::new(HOME_TEXT, Point::new(10, below_status_bar + 30), style);
+    if let Err(error) = text.draw(frame) {
         error!("Failed to draw text: {:?}", error);
     }
+}
+
+/// Draws the home screen on a white frame and shows it
+async fn show_home(display: &SharedDisplay, frame: &mut Frame) {
+    frame.fill(0xFF);
+    draw_home(frame);
+    if let Err(error) = display.submit(frame).await {
+        error!("Failed to display home: {:?}", defmt::Debug2Format(&error));
+    }
+}
 
-    display
-        .display(eink_display::RefreshMode::Full, &frame)
+/// Shows the loaded snapshot of the last rendered screen while the actual first frame is prepared.
+/// Returns whether it is shown.
+async fn show_snapshot(
+    loaded: Result<(), LoadSnapshotError>,
+    display: &mut Display,
+    frame: &mut Frame,
+) -> bool {
+    match loaded {
+        Ok(()) => {}
+        Err(LoadSnapshotError::NoSnapshot) => {
+            info!("No snapshot saved yet");
+            return false;
+        }
+        Err(error) => {
+            error!("Failed to load snapshot: {:?}", defmt::Debug2Format(&error));
+            return false;
+        }
+    }
+
+    if let Err(error) = display
+        .display(eink_display::RefreshMode::Fast, frame)
         .await
-        .map_err(ApplicationError::Display)?;
+    {
+        error!(
+            "Failed to display snapshot: {:?}",
+            defmt::Debug2Format(&error)
+        );
+        return false;
+    }
 
-    spawner.spawn(handle_power_button(
-        peripherals.GPIO3,
-        peripherals.LPWR,
-        display,
-    ))?;
+    info!("Snapshot visible after {} ms", Instant::now().as_millis());
+    true
+}
 
-    loop {
-        analog.poll().await;
-        Timer::after_secs(1).await;
+/// Explains something to the reader over the home screen until they dismiss it
+async fn show_notice(
+    notice: &str,
+    analog: &mut Analog<'_>,
+    display: &SharedDisplay,
+    frame: &mut Frame,
+) {
+    let mut dialog = Dialog::new(notice, &["OK"]);
+    if let Err(error) = dialog.draw(frame) {
+        error!("Failed to draw notice: {:?}", error);
+    }
+
+    if let Err(error) = display.submit(frame).await {
+        error!(
+            "Failed to display notice: {:?}",
+            defmt::Debug2Format(&error)
+        );
+        return;
+    }
+
+    while !matches!(
+        dialog.handle(analog.wait_for_press().await),
+        Response::Confirmed | Response::Cancelled
+    ) {}
+
+    frame.fill(0xFF);
+    draw_home(frame);
+    if let Err(error) = display.submit(frame).await {
+        error!("Failed to display home: {:?}", defmt::Debug2Format(&error));
+    }
+}
+
+/// Asks with a dialog over the current screen. Returns the index of the chosen option or None if
+/// the reader backs out.
+async fn ask(
+    message: &str,
+    options: &[&str],
+    analog: &mut Analog<'_>,
+    display: &SharedDisplay,
+    frame: &mut Frame,
+) -> Option<usize> {
+    let mut dialog = Dialog::new(message, options);
+    loop {
+        if let Err(error) = dialog.draw(frame) {
+            error!("Failed to draw dialog: {:?}", error);
+        }
+        if let Err(error) = display.submit(frame).await {
+            error!(
+                "Failed to display dialog: {:?}",
+                defmt::Debug2Format(&error)
+            );
+        }
+
+        loop {
+            match dialog.handle(analog.wait_for_press().await) {
+                Response::Ignored => {}
+                Response::Changed => break,
+                Response::Confirmed => return Some(dialog.selected()),
+                Response::Cancelled => return None,
+            }
+        }
+    }
+}
+
+/// Message over the home screen while something runs that can't be cancelled
+async fn show_progress(message: &str, display: &SharedDisplay, frame: &mut Frame) {
+    if let Err(error) = Dialog::new(message, &[]).draw(frame) {
+        error!("Failed to draw progress: {:?}", error);
+    }
+
+    if let Err(error) = display.submit(frame).await {
+        error!(
+            "Failed to display progress: {:?}",
+            defmt::Debug2Format(&error)
+        );
+    }
+}
+
+/// Reader action for the word under the cursor. Shows its definition in a popup until the reader
+/// closes it.
+async fn look_up_word(
+    text: &str,
+    cursor: usize,
+    sd_card: &SharedSdCard,
+    analog: &mut Analog<'_>,
+    display: &SharedDisplay,
+    frame: &mut Frame,
+) {
+    let Some(word) = dictionary::word_at(text, cursor) else {
+        return;
+    };
+
+    let result = match sd_card.lock().await.as_mut() {
+        Some(sd_card) => dictionary::look_up(sd_card, word).await,
+        None => {
+            show_notice(
+                "Insert an SD card with\na dictionary.",
+                analog,
+                display,
+                frame,
+            )
+            .await;
+            return;
+        }
+    };
+
+    let message = match result {
+        Ok(Some(definition)) => dictionary::popup_message(word, &definition),
+        Ok(None) => format!("{word}\n\nNot in the dictionary."),
+        Err(dictionary::LookUpError::Open(error)) if error.is_not_found() => {
+            String::from("No dictionary found.\nCopy DICT.BIN to the SD card.")
+        }
+        Err(error) => {
+            error!("Failed to look up word: {:?}", defmt::Debug2Format(&error));
+            String::from("Failed to read the dictionary.")
+        }
+    };
+
+    show_notice(&message, analog, display, frame).await;
+}
+
+/// Lets the reader pick books from the library until they back out
+async fn open_library(
+    library: &Library,
+    settings: &mut Settings,
+    profile: &Profile,
+    sd_card: &SharedSdCard,
+    storage: Option<&SettingsStorage>,
+    hyphenator: &dyn Hyphenator,
+    glyphs: Option<&GlyphCache>,
+    analog: &mut Analog<'_>,
+    display: &SharedDisplay,
+    frame: &mut Frame,
+) {
+    let recent = match sd_card.lock().await.as_mut() {
+        Some(sd_card) => Recent::load(sd_card, profile).await,
+        None => Ok(Recent::default()),
+    };
+    let mut recent = recent.unwrap_or_else(|error| {
+        error!(
+            "Failed to load recent books: {:?}",
+            defmt::Debug2Format(&error)
+        );
+        Recent::default()
+    });
+
+    // Book to keep the cursor on when the order changes
+    let mut current = None;
+    loop {
+        let order = library.arrange(settings.library_sort, settings.library_filter, &recent);
+        // The menu entry comes before the books
+        let selected = current
+            .and_then(|current| order.iter().position(|&index| index == current))
+            .map_or(0, |position| position + 1);
+        let choice = library_screen::select(
+            library,
+            &order,
+            settings.library_view,
+            selected,
+            sd_card,
+            analog,
+            display,
+            frame,
+        )
+        .await;
+
+        let index = match choice {
+            None => return,
+            Some(library_screen::Choice::Arrange) => {
+                library_screen::arrange(
+                    &mut settings.library_sort,
+                    &mut settings.library_filter,
+                    analog,
+                    display,
+                    frame,
+                )
+                .await;
+                save_settings(settings, profile, sd_card, storage).await;
+                current = None;
+                continue;
+            }
+            Some(library_screen::Choice::Book(index)) => index,
+        };
+
+        current = Some(index);
+        let book = &library.books()[index];
+        recent.open(&book.path);
+        if let Some(sd_card) = sd_card.lock().await.as_mut()
+            && let Err(error) = recent.save(sd_card, profile).await
+        {
+            error!(
+                "Failed to save recent books: {:?}",
+                defmt::Debug2Format(&error)
+            );
+        }
+
+        match book.format() {
+            Some(Format::Kindle) => {
+                read_kindle_book(
+                    book, profile, sd_card, hyphenator, glyphs, analog, display, frame,
+                )
+                .await;
+            }
+            Some(Format::Epub) | None => {
+                let choice = ask(
+                    "EPUB books can't be opened yet.",
+                    &["OK", "Check book"],
+                    analog,
+                    display,
+                    frame,
+                )
+                .await;
+                if choice == Some(1) {
+                    check_book(&book.path, sd_card, analog, display, frame).await;
+                }
+            }
+        }
+    }
+}
+
+/// Reads a Kindle book from where the reader left off. Only the first section of longer books is
+/// shown until the reader can continue into the next one.
+async fn read_kindle_book(
+    book: &Book,
+    profile: &Profile,
+    sd_card: &SharedSdCard,
+    hyphenator: &dyn Hyphenator,
+    glyphs: Option<&GlyphCache>,
+    analog: &mut Analog<'_>,
+    display: &SharedDisplay,
+    frame: &mut Frame,
+) {
+    let path = book.path.as_str();
+    let result = match sd_card.lock().await.as_mut() {
+        Some(sd_card) => mobi::load_section(sd_card, path, 1, memory::MAXIMUM_TEXT_SIZE).await,
+        None => return,
+    };
+
+    let section = match result {
+        Ok(section) => section,
+        Err(error) => {
+            error!("Failed to open book: {:?}", defmt::Debug2Format(&error));
+            show_notice("The book could not be opened.", analog, display, frame).await;
+            return;
+        }
+    };
+
+    let (bookmarks, progress) = match sd_card.lock().await.as_mut() {
+        Some(sd_card) => (
+            Bookmarks::load(sd_card, profile, path).await,
+            Progress::load(sd_card, profile, path).await,
+        ),
+        None => return,
+    };
+    let mut bookmarks = bookmarks.unwrap_or_else(|error| {
+        error!(
+            "Failed to load bookmarks: {:?}",
+            defmt::Debug2Format(&error)
+        );
+        Bookmarks::default()
+    });
+    let mut progress = progress.unwrap_or_else(|error| {
+        error!("Failed to load progress: {:?}", defmt::Debug2Format(&error));
+        Progress::default()
+    });
+
+    let text = section.text.as_str();
+    // Can't truncate as the text is at most the maximum text size
+    progress.length = text.len() as u32;
+    // The book can be shorter than when it was last read
+    let mut position = usize::try_from(progress.position)
+        .unwrap_or(0)
+        .min(text.len());
+    loop {
+        let page =
+            match reader::read_book(text, position, hyphenator, glyphs, analog, display, frame)
+                .await
+            {
+                reader::Exit::Closed(start) => {
+                    // Can't truncate as the text is at most the maximum text size
+                    progress.position = start as u32;
+                    save_progress(&progress, profile, path, sd_card).await;
+                    return;
+                }
+                reader::Exit::Menu(page) => page,
+            };
+
+        position = page.start;
+        // Can't truncate as the text is at most the maximum text size
+        let positions = page.start as u32..page.end as u32;
+        let entry =
+            reader_menu::select(bookmarks.contains(&positions), analog, display, frame).await;
+        match entry {
+            None => {}
+            Some(reader_menu::Entry::Bookmark) => {
+                bookmarks.toggle(positions, &text[page]);
+                // Saved right away, so they don't need to be recovered after a crash
+                if let Some(sd_card) = sd_card.lock().await.as_mut()
+                    && let Err(error) = bookmarks.save(sd_card, profile, path).await
+                {
+                    error!(
+                        "Failed to save bookmarks: {:?}",
+                        defmt::Debug2Format(&error)
+                    );
+                }
+            }
+            Some(reader_menu::Entry::Bookmarks) => {
+                if let Some(bookmark) = bookmarks.select(analog, display, frame).await {
+                    // Bookmarks of an earlier version of the book can be past its end
+                    position = usize::try_from(bookmark).unwrap_or(0).min(text.len());
+                }
+            }
+            Some(reader_menu::Entry::LookUpWord) => {
+                let word = reader_menu::pick_word(
+                    "Look up word",
+                    &text[page.clone()],
+                    analog,
+                    display,
+                    frame,
+                )
+                .await;
+                if let Some(word) = word {
+                    look_up_word(
+                        text,
+                        page.start + word.start,
+                        sd_card,
+                        analog,
+                        display,
+                        frame,
+                    )
+                    .await;
+                }
+            }
+            Some(reader_menu::Entry::ShareQuote) => {
+                let page = &text[page];
+                if let Some(passage) = reader_menu::pick_quote(page, analog, display, frame).await {
+                    let quote = Quote {
+                        text: &page[passage],
+                        title: &book.title,
+                        author: book.author.as_deref(),
+                    };
+                    share_quote(&quote, analog, display, frame).await;
+                }
+            }
+            Some(reader_menu::Entry::AudiobookPosition) => {
+                let audiobook = progress.audiobook.unwrap_or(AudiobookPosition(0));
+                if let Some(audiobook) =
+                    reader_menu::adjust_audiobook(audiobook, analog, display, frame).await
+                {
+                    progress.audiobook = Some(audiobook);
+                    // Can't truncate as the text is at most the maximum text size
+                    progress.position = position as u32;
+                    save_progress(&progress, profile, path, sd_card).await;
+                }
+            }
+        }
+    }
+}
+
+async fn save_progress(
+    progress: &Progress,
+    profile: &Profile,
+    book_path: &str,
+    sd_card: &SharedSdCard,
+) {
+    if let Some(sd_card) = sd_card.lock().await.as_mut()
+        && let Err(error) = progress.save(sd_card, profile, book_path).await
+    {
+        error!("Failed to save progress: {:?}", defmt::Debug2Format(&error));
+    }
+}
+
+/// Shows the quote as a QR code until a button is pressed
+async fn share_quote(
+    quote: &Quote<'_>,
+    analog: &mut Analog<'_>,
+    display: &SharedDisplay,
+    frame: &mut Frame,
+) {
+    match quote.draw_share(frame) {
+        Ok(()) => {}
+        Err(DrawQrCodeError::TooLong(_)) => {
+            show_notice("The quote is too long to share.", analog, display, frame).await;
+            return;
+        }
+        Err(error) => {
+            error!("Failed to draw quote: {:?}", defmt::Debug2Format(&error));
+            return;
+        }
+    }
+
+    // Leftover ghosting makes the code harder to scan
+    if let Err(error) = display
+        .show(ShowMode::Exact(eink_display::RefreshMode::Full), frame)
+        .await
+    {
+        error!("Failed to display quote: {:?}", defmt::Debug2Format(&error));
+    }
+
+    analog.wait_for_press().await;
+}
+
+/// Lets the reader set up the WiFi network from a phone over Bluetooth or type it in with the
+/// keyboard. The network is started with it when the settings are closed.
+#[cfg(feature = "radio")]
+async fn provision_wifi(
+    radio: &mut network::Radio,
+    analog: &mut Analog<'_>,
+    display: &SharedDisplay,
+    frame: &mut Frame,
+) {
+    if radio.storage().is_none() {
+        show_notice(
+            "WiFi can't be saved on this reader.",
+            analog,
+            display,
+            frame,
+        )
+        .await;
+        return;
+    }
+
+    let choice = ask(
+        "Set up WiFi from a phone over Bluetooth\nor type it in on the reader?",
+        &["Cancel", "Phone", "Keyboard"],
+        analog,
+        display,
+        frame,
+    )
+    .await;
+    let credentials = match choice {
+        Some(1) => receive_wifi(radio, analog, display, frame).await,
+        Some(2) => wifi_screen::enter(analog, display, frame).await,
+        _ => None,
+    };
+    let Some(credentials) = credentials else {
+        return;
+    };
+
+    let Some(storage) = radio.storage() else {
+        return;
+    };
+    let notice = match storage.save(&credentials) {
+        Ok(()) => "The WiFi network was saved.",
+        Err(error) => {
+            error!(
+                "Failed to save WiFi network: {:?}",
+                defmt::Debug2Format(&error)
+            );
+            "The WiFi network could not be saved."
+        }
+    };
+
+    show_notice(notice, analog, display, frame).await;
+}
+
+/// Waits for a phone to write the WiFi network over Bluetooth. Returns None if the reader
+/// cancelled or the setup failed.
+#[cfg(feature = "radio")]
+async fn receive_wifi(
+    radio: &mut network::Radio,
+    analog: &mut Analog<'_>,
+    display: &SharedDisplay,
+    frame: &mut Frame,
+) -> Option<crate::config::WifiCredentials> {
+    let (controller, bluetooth) = match radio.bluetooth() {
+        Ok(bluetooth) => bluetooth,
+        Err(error) => {
+            error!("Failed to start radio: {:?}", defmt::Debug2Format(&error));
+            show_notice("Bluetooth could not be started.", analog, display, frame).await;
+            return None;
+        }
+    };
+
+    let mut dialog = Dialog::new(
+        "Connect to Crustpoint with a Bluetooth app\nand write the WiFi name and password.",
+        &["Cancel"],
+    );
+    if let Err(error) = dialog.draw(frame) {
+        error!("Failed to draw provisioning: {:?}", error);
+    }
+    if let Err(error) = display.submit(frame).await {
+        error!(
+            "Failed to display provisioning: {:?}",
+            defmt::Debug2Format(&error)
+        );
+    }
+
+    let cancel = async {
+        while !matches!(
+            dialog.handle(analog.wait_for_press().await),
+            Response::Confirmed | Response::Cancelled
+        ) {}
+    };
+    let received = select(provisioning::receive(controller, bluetooth), cancel).await;
+    match received {
+        Either::First(Ok(credentials)) => Some(credentials),
+        Either::First(Err(error)) => {
+            error!("Provisioning failed: {:?}", defmt::Debug2Format(&error));
+            show_notice("Bluetooth setup failed.", analog, display, frame).await;
+            None
+        }
+        Either::Second(()) => None,
+    }
+}
+
+/// Connects to the WiFi network once one is known, either from the configuration file or set up
+/// from the settings, and starts setting the clock and syncing the progress of the profile if
+/// there is an account for it. Does nothing once the network is started.
+#[cfg(feature = "radio")]
+fn start_network(
+    spawner: Spawner,
+    radio: &mut network::Radio,
+    config: &Config,
+    profile: &Profile,
+    sd_card: &'static SharedSdCard,
+    rtc: &'static SharedRtc,
+) {
+    if radio.stack().is_some() {
+        return;
+    }
+
+    let Some(credentials) = radio.credentials(config) else {
+        info!("No WiFi network set up");
+        return;
+    };
+
+    let stack = match radio.start(credentials) {
+        Ok(stack) => stack,
+        Err(error) => {
+            error!("Failed to start network: {:?}", defmt::Debug2Format(&error));
+            return;
+        }
+    };
+
+    if let Err(error) = spawner.spawn(clock::run(stack, rtc)) {
+        error!("Failed to start clock sync: {:?}", error);
+    }
+
+    if let Some(account) = &config.kosync {
+        let task = kosync::run(stack, account.clone(), profile.clone(), sd_card);
+        if let Err(error) = spawner.spawn(task) {
+            error!("Failed to start progress sync: {:?}", error);
+        }
+    }
+}
+
+/// Mirrors the WebDAV folder from the configuration into the library when left is pressed on the
+/// home screen. The caller loads the library again to pick the new books up.
+#[cfg(feature = "radio")]
+async fn sync_library(
+    stack: embassy_net::Stack<'_>,
+    remote: &webdav::Remote,
+    profile: &Profile,
+    sd_card: &SharedSdCard,
+    analog: &mut Analog<'_>,
+    display: &SharedDisplay,
+    frame: &mut Frame,
+) {
+    let mut dialog = Dialog::new("Syncing the library...", &["Cancel"]);
+    if let Err(error) = dialog.draw(frame) {
+        error!("Failed to draw library sync: {:?}", error);
+    }
+    if let Err(error) = display.submit(frame).await {
+        error!(
+            "Failed to display library sync: {:?}",
+            defmt::Debug2Format(&error)
+        );
+    }
+
+    let cancel = async {
+        while !matches!(
+            dialog.handle(analog.wait_for_press().await),
+            Response::Confirmed | Response::Cancelled
+        ) {}
+    };
+    let notice = match select(webdav::mirror(stack, remote, profile, sd_card), cancel).await {
+        Either::First(Ok(summary)) => {
+            info!("Library sync done: {}", summary);
+            if summary.failed > 0 {
+                format!(
+                    "Downloaded {} books, {} failed.\nThey are tried again next time.",
+                    summary.downloaded, summary.failed
+                )
+            } else {
+                format!("Downloaded {} new books.", summary.downloaded)
+            }
+        }
+        Either::First(Err(error)) => {
+            error!("Library sync failed: {:?}", defmt::Debug2Format(&error));
+            String::from("The library could not be synced.")
+        }
+        Either::Second(()) => return,
+    };
+
+    show_notice(&notice, analog, display, frame).await;
+}
+
+/// Installs a firmware update from the card if there is one and restarts into it. Runs at start up
+/// and from the menu.
+async fn update_firmware(
+    sd_card: &SharedSdCard,
+    flash: &SharedFlash,
+    analog: &mut Analog<'_>,
+    display: &SharedDisplay,
+    frame: &mut Frame,
+) {
+    // The card stays locked during the update so nothing else writes to it
+    let result = {
+        let mut sd_card = sd_card.lock().await;
+        let Some(sd_card) = sd_card.as_mut() else {
+            return;
+        };
+        if !firmware::is_available(sd_card).await {
+            return;
+        }
+
+        info!("Installing firmware update from {}", firmware::PATH);
+        show_progress(
+            "Installing the firmware update.\nDon't turn the reader off.",
+            display,
+            frame,
+        )
+        .await;
+        firmware::install(sd_card, flash).await
+    };
+
+    match result {
+        Ok(()) => {
+            info!("Firmware update installed, restarting");
+            esp_hal::system::software_reset();
+        }
+        Err(error) => {
+            error!(
+                "Failed to install firmware update: {:?}",
+                defmt::Debug2Format(&error)
+            );
+            show_notice(
+                "The firmware update failed.\nThe reader keeps the current firmware.",
+                analog,
+                display,
+                frame,
+            )
+            .await;
+        }
+    }
+}
+
+/// Offers to export the report of a crash in the last session to the card, so it can be attached
+/// to a bug report. Without a card the report is kept until there is one.
+async fn offer_crash_report(
+    sd_card: &SharedSdCard,
+    flash: &SharedFlash,
+    analog: &mut Analog<'_>,
+    display: &SharedDisplay,
+    frame: &mut Frame,
+) {
+    let report = match crash_report::load(flash) {
+        Ok(Some(report)) => report,
+        Ok(None) => return,
+        Err(error) => {
+            error!(
+                "Failed to load crash report: {:?}",
+                defmt::Debug2Format(&error)
+            );
+            return;
+        }
+    };
+
+    if sd_card.lock().await.is_none() {
+        return;
+    }
+
+    let mut dialog = Dialog::new(
+        "The reader crashed last time.\nA crash report is available.",
+        &["Dismiss", "Export to SD card"],
+    );
+    let choice = 'dialog: loop {
+        if let Err(error) = dialog.draw(frame) {
+            error!("Failed to draw crash report dialog: {:?}", error);
+        }
+        if let Err(error) = display.submit(frame).await {
+            error!(
+                "Failed to display crash report dialog: {:?}",
+                defmt::Debug2Format(&error)
+            );
+        }
+
+        loop {
+            match dialog.handle(analog.wait_for_press().await) {
+                Response::Ignored => {}
+                Response::Changed => break,
+                Response::Confirmed => break 'dialog Some(dialog.selected()),
+                // Asked again on the next start
+                Response::Cancelled => break 'dialog None,
+            }
+        }
+    };
+
+    let notice = match choice {
+        None => None,
+        Some(0) => {
+            info!("Crash report dismissed");
+            clear_crash_report(flash);
+            None
+        }
+        Some(_) => {
+            let result = match sd_card.lock().await.as_mut() {
+                Some(sd_card) => crash_report::export(sd_card, &report).await,
+                None => return,
+            };
+
+            // A report that failed to export is kept for the next try
+            if let Err(error) = result {
+                error!(
+                    "Failed to export crash report: {:?}",
+                    defmt::Debug2Format(&error)
+                );
+                Some("The crash report could not be saved.")
+            } else {
+                clear_crash_report(flash);
+                Some("The crash report was saved as\nLOGS/CRASH.TXT on the SD card.")
+            }
+        }
+    };
+
+    if let Some(notice) = notice {
+        show_notice(notice, analog, display, frame).await;
+        return;
+    }
+
+    frame.fill(0xFF);
+    draw_home(frame);
+    if let Err(error) = display.submit(frame).await {
+        error!("Failed to display home: {:?}", defmt::Debug2Format(&error));
+    }
+}
+
+fn clear_crash_report(flash: &SharedFlash) {
+    if let Err(error) = crash_report::clear(flash) {
+        error!(
+            "Failed to clear crash report: {:?}",
+            defmt::Debug2Format(&error)
+        );
+    }
+}
+
+/// Checks the structure of the EPUB book and lists the issues, to find out why a book renders
+/// oddly
+async fn check_book(
+    path: &str,
+    sd_card: &SharedSdCard,
+    analog: &mut Analog<'_>,
+    display: &SharedDisplay,
+    frame: &mut Frame,
+) {
+    let result = match sd_card.lock().await.as_mut() {
+        Some(sd_card) => validation::validate(sd_card, path).await,
+        None => return,
+    };
+
+    match result {
+        Ok(issues) => validation::show_report(path, &issues, analog, display, frame).await,
+        Err(error) => {
+            error!("Failed to check book: {:?}", defmt::Debug2Format(&error));
+            show_notice("The book could not be opened.", analog, display, frame).await;
+        }
+    }
+}
+
+/// Draws the activity strip at the bottom and asks for it to be refreshed, so the page stays as
+/// it is
+fn draw_activity(activity: Option<Activity>, frame: &mut Frame) {
+    if let Err(error) = activity::draw(frame, activity) {
+        error!("Failed to draw activity: {:?}", error);
+        return;
+    }
+
+    REFRESH_QUEUE.request(RefreshRequest {
+        region: Some(activity::REGION),
+        mode: ShowMode::Exact(eink_display::RefreshMode::Fast),
+    });
+}
+
+/// Shows the frame for the merged refresh requests
+async fn refresh(request: RefreshRequest, display: &SharedDisplay, frame: &Frame) {
+    let result = match request {
+        RefreshRequest {
+            region: Some(region),
+            mode: ShowMode::Exact(eink_display::RefreshMode::Fast),
+        } => display.show_region(frame, region).await,
+        RefreshRequest { mode, .. } => display.show(mode, frame).await.map(|_| ()),
+    };
+
+    if let Err(error) = result {
+        error!("Failed to refresh: {:?}", defmt::Debug2Format(&error));
+        event_log::record(event_log::Event::Error(format!(
+            "Failed to refresh: {error:?}"
+        )));
+    }
+}
+
+/// Draws the toast and asks for the refresh of its strip. Returns when it was drawn.
+fn show_toast(message: &str, frame: &mut Frame) -> Option<Instant> {
+    match toast::draw(message, frame) {
+        Ok(()) => {
+            REFRESH_QUEUE.request(RefreshRequest {
+                region: Some(toast::REGION),
+                mode: ShowMode::Exact(eink_display::RefreshMode::Fast),
+            });
+            Some(Instant::now())
+        }
+        Err(error) => {
+            error!("Failed to draw toast: {:?}", error);
+            None
+        }
+    }
+}
+
+/// Just a convenience replacement for main to be able to return errors
+async fn run(spawner: Spawner) -> Result<(), ApplicationError> {
+    diagnostics::paint_stack();
+    let reset_reason = reset_reason(Cpu::ProCpu);
+    let wake_reason = wakeup_cause();
+
+    info!(
+        "Reset reason: {:?}; Wakeup reason: {:?}",
+        defmt::Debug2Format(&reset_reason),
+        wake_reason
+    );
+
+    let boot = Boot::from_reset_reason(reset_reason);
+    info!("Boot: {}", boot);
+
+    let config = esp_hal::Config::default().with_cpu_clock(CpuClock::max());
+    let peripherals = esp_hal::init(config);
+
+    // esp_alloc::heap_allocator!(#[esp_hal::ram(reclaimed)] size: 66320);
+    esp_alloc::heap_allocator!(size: memory::HEAP_SIZE);
+
+    let Board {
+        display: display_pins,
+        sd_card_spi,
+        mut analog,
+        power_button,
+        flash,
+        low_power,
+        timer_group_0,
+        software_interrupt,
+        usb_serial,
+        #[cfg(feature = "radio")]
+        radio,
+    } = Board::set_up(peripherals)?;
+
+    let timer_group_0 = TimerGroup::new(timer_group_0);
+    let software_interrupt =
+        esp_hal::interrupt::software::SoftwareInterruptControl::new(software_interrupt);
+    esp_rtos::start(timer_group_0.timer0, software_interrupt.software_interrupt0);
+    spawner.spawn(supervisor::supervise(timer_group_0.wdt))?;
+
+    info!("Embassy initialized!");
+    event_log::record(event_log::Event::Started {
+        reset: reset_reason,
+        wake: wake_reason,
+    });
+
+    static FLASH: StaticCell<SharedFlash> = StaticCell::new();
+    let flash: &'static _ = FLASH.init(blocking_mutex::Mutex::new(RefCell::new(
+        FlashStorage::new(flash),
+    )));
+
+    // LPWR = Low Power Watchdog and Reset? Low Power Wrapper? LowPoWeR? Laser Power?
+    static RTC: StaticCell<SharedRtc> = StaticCell::new();
+    let rtc: &'static _ = RTC.init(blocking_mutex::Mutex::new(RefCell::new(Rtc::new(
+        low_power,
+    ))));
+
+    if let Err(error) = crash_report::enable(flash) {
+        error!(
+            "Failed to enable crash reports: {:?}",
+            defmt::Debug2Format(&error)
+        );
+    }
+
+    // Before anything that could get stuck, so a broken update is rolled back
+    match firmware::start_trial(flash) {
+        Ok(firmware::Trial::Started) => {
+            info!("Starting new firmware on trial");
+            spawner.spawn(firmware::supervise_trial(flash))?;
+        }
+        Ok(firmware::Trial::Confirmed) => {}
+        Err(error) => error!(
+            "Failed to check firmware state: {:?}",
+            defmt::Debug2Format(&error)
+        ),
+    }
+
+    info!("Initializing display");
+
+    let mut display = EinkDisplay::initialize(
+        display_pins.spi,
+        display_pins.reset,
+        display_pins.data_command,
+        display_pins.busy,
+        FirmwareMonitor,
+    )
+    .await
+    .map_err(ApplicationError::SetUpEinkDisplay)?;
+
+    let settings = Settings::default();
+    display.set_inverted(settings.dark_mode);
+
+    let frame = eink_display::take_frame().ok_or(ApplicationError::NoFrame)?;
+
+    if matches!(wake_reason, SleepSource::Timer) {
+        let mut power_button = power_button;
+        handle_timer_wake(
+            &mut analog,
+            &mut display,
+            frame,
+            sd_card_spi,
+            &mut power_button,
+            rtc,
+        )
+        .await;
+        return Ok(());
+    }
+
+    // The snapshot only speeds up start up, so the device works without it
+    let mut snapshot_storage = match SnapshotStorage::new(flash) {
+        Ok(storage) => Some(storage),
+        Err(error) => {
+            error!(
+                "Failed to open snapshot storage: {:?}",
+                defmt::Debug2Format(&error)
+            );
+            None
+        }
+    };
+
+    // The screen from before deep sleep is in RTC memory unless the battery was disconnected.
+    // Otherwise the snapshot in flash from the last start up is shown.
+    let is_restored = show_snapshot(snapshot::restore(frame), &mut display, frame).await;
+    if !is_restored && let Some(storage) = snapshot_storage.as_mut() {
+        let loaded = storage.load(frame);
+        show_snapshot(loaded, &mut display, frame).await;
+    }
+    // Start the actual first frame from white
+    frame.fill(0xFF);
+
+    draw_home(frame);
+
+    display
+        .display(eink_display::RefreshMode::Full, frame)
+        .await
+        .map_err(ApplicationError::Display)?;
+
+    // Cold boot to the first visible frame is what start up is optimized for
+    info!(
+        "First frame visible after {} ms",
+        Instant::now().as_millis()
+    );
+
+    if let Some(storage) = snapshot_storage.as_mut()
+        && let Err(error) = storage.save(frame)
+    {
+        error!("Failed to save snapshot: {:?}", defmt::Debug2Format(&error));
+    }
+
+    // Capture of the initialization and the first frame to compare against the vendor SDK
+    #[cfg(feature = "display-trace")]
+    eink_display::trace::dump();
+
+    static DISPLAY: StaticCell<SharedDisplay> = StaticCell::new();
+    let display: &'static _ = DISPLAY.init(SharedDisplay::new(
+        display,
+        eink_display::take_frame().ok_or(ApplicationError::NoFrame)?,
+    ));
+    spawner.spawn(display_task::run(display))?;
+
+    static SD_CARD: StaticCell<SharedSdCard> = StaticCell::new();
+    let sd_card: &'static _ = SD_CARD.init(Mutex::new(None));
+
+    static PROFILES: StaticCell<ProfilesSignal> = StaticCell::new();
+    let profiles: &'static _ = PROFILES.init(Signal::new());
+
+    static SHUTDOWN: StaticCell<ShutdownSignal> = StaticCell::new();
+    let shutdown: &'static _ = SHUTDOWN.init(Signal::new());
+
+    spawner.spawn(handle_power_button(
+        power_button,
+        rtc,
+        display.handle(),
+        sd_card,
+        shutdown,
+        // Taken last, as the pool of the low RAM profile has no frame left for it
+        eink_display::take_frame(),
+    ))?;
+    spawner.spawn(initialize_storage(
+        sd_card_spi,
+        sd_card,
+        display.handle(),
+        profiles,
+    ))?;
+    spawner.spawn(console::run(
+        usb_serial,
+        sd_card,
+        display.handle(),
+        shutdown,
+    ))?;
+    spawner.spawn(event_log::run(sd_card, rtc))?;
+
+    let mut profiles = profiles.wait().await;
+    // The display is up and the card was mounted if there is one, so the firmware works
+    if let Err(error) = firmware::confirm(flash) {
+        error!(
+            "Failed to confirm firmware: {:?}",
+            defmt::Debug2Format(&error)
+        );
+    }
+    update_firmware(sd_card, flash, &mut analog, display, frame).await;
+    offer_crash_report(sd_card, flash, &mut analog, display, frame).await;
+    // After waking up, the reader from before deep sleep continues
+    let is_picker_shown = profiles.len() > 1 && !boot.is_session_restored();
+    if is_picker_shown {
+        profiles.select(&mut analog, display, frame).await;
+    }
+
+    let settings_storage = match SettingsStorage::new(flash) {
+        Ok(storage) => Some(storage),
+        Err(error) => {
+            error!(
+                "Failed to open settings storage: {:?}",
+                defmt::Debug2Format(&error)
+            );
+            None
+        }
+    };
+
+    let mut settings = load_settings(sd_card, profiles.active(), settings_storage.as_ref()).await;
+    let config = load_config(sd_card).await;
+    info!("Config: {}", config);
+    analog.set_buttons(config.buttons);
+    if let Some(samples) = config.oversampling {
+        analog.set_samples(samples);
+    }
+    if config.light_sleep != Some(false) {
+        analog.enable_light_sleep(rtc);
+    }
+    power_button::set_thresholds(config.long_press, config.minimum_press);
+    spawner.spawn(shortcut::run(config.shortcuts, display.handle(), sd_card))?;
+    if let Some(minutes) = config.utc_offset_minutes {
+        clock::set_utc_offset(minutes);
+    }
+    spawner.spawn(status_bar::run(display.handle(), rtc))?;
+    if let Some(calibration) = settings.button_calibration {
+        analog.set_calibration(calibration);
+    }
+    if analog.is_chord_held().await {
+        info!("Button calibration requested at start up");
+        if let Some(calibration) = calibration::calibrate(&mut analog, display, frame).await {
+            settings.button_calibration = Some(calibration);
+            save_settings(
+                &settings,
+                profiles.active(),
+                sd_card,
+                settings_storage.as_ref(),
+            )
+            .await;
+        }
+    }
+    if let Some(font_size) = config.font_size {
+        settings.font_size = Some(font_size);
+    }
+    #[cfg(feature = "radio")]
+    let mut radio = network::Radio::new(spawner, radio, flash);
+    #[cfg(feature = "radio")]
+    start_network(
+        spawner,
+        &mut radio,
+        &config,
+        profiles.active(),
+        sd_card,
+        rtc,
+    );
+
+    info!(
+        "Settings of profile {}: {}",
+        profiles.active().name(),
+        settings
+    );
+    display.lock().await.set_inverted(settings.dark_mode);
+    display.lock().await.set_turbo(settings.turbo_page);
+    if let Some(interval) = config.full_refresh_interval {
+        display.lock().await.set_full_refresh_interval(interval);
+    }
+    if let Some(retries) = config.spi_retries {
+        display.lock().await.set_retry_policy(RetryPolicy {
+            retries,
+            ..RetryPolicy::DEFAULT
+        });
+    }
+    if let Some(is_auto_wake) = config.wake_on_refresh {
+        display.lock().await.set_auto_wake(is_auto_wake);
+    }
+    apply_theme(sd_card, &settings).await;
+    recover_progress(sd_card, profiles.active()).await;
+    // Pages break without hyphens if there are no patterns for the language
+    let hyphenation = load_hyphenation(sd_card, &settings).await;
+    let hyphenator: &dyn Hyphenator = match &hyphenation {
+        Some(patterns) => patterns,
+        None => &NoHyphenation,
+    };
+    let glyphs = load_glyphs(sd_card).await;
+
+    // Bring back the home screen and show it with the settings of the profile
+    if is_picker_shown || settings != Settings::default() {
+        frame.fill(0xFF);
+        draw_home(frame);
+        if let Err(error) = display.submit(frame).await {
+            error!("Failed to display home: {:?}", defmt::Debug2Format(&error));
+        }
+    }
+
+    if let Some(notice) = boot.notice() {
+        show_notice(notice, &mut analog, display, frame).await;
+    }
+
+    // Books can only be read from the card, so the reader is asked for one instead of being left at
+    // an empty home screen
+    if sd_card.lock().await.is_none() {
+        no_card::show(
+            &mut settings,
+            settings_storage.as_ref(),
+            &mut analog,
+            display,
+            frame,
+        )
+        .await;
+
+        frame.fill(0xFF);
+        draw_home(frame);
+        if let Err(error) = display.submit(frame).await {
+            error!("Failed to display home: {:?}", defmt::Debug2Format(&error));
+        }
+    }
+
+    // Indexed once here, so the library opens right away. Books that changed since the last start
+    // are read again.
+    let mut library = load_library(sd_card).await;
+    let shutdown_threshold = load_shutdown_threshold(sd_card, reset_reason).await;
+    info!("Shutting down below {} mV", shutdown_threshold);
+
+    let mut activity = activity::receiver();
+    let mut gauge = battery::Gauge::default();
+    // When the battery history was last sampled
+    let mut battery_sampled: Option<Instant> = None;
+
+    power_profile::start();
+    // When the battery is read next
+    let mut battery_due = Instant::now();
+    // When the memory overlay was last drawn if it is shown
+    let mut overlay_drawn: Option<Instant> = None;
+    // When the toast was drawn while one is shown
+    let mut toast_drawn: Option<Instant> = None;
+
+    loop {
+        // Drawn right away when it was just turned on
+        let overlay_due = diagnostics::is_overlay_shown()
+            .then(|| overlay_drawn.map_or(Instant::now(), |drawn| drawn + OVERLAY_INTERVAL));
+        let toast_due = toast_drawn.map(|drawn| drawn + toast::DURATION);
+        let deadline = [overlay_due, toast_due]
+            .into_iter()
+            .flatten()
+            .fold(battery_due, Instant::min);
+
+        let activity_changed = async {
+            match activity.as_mut() {
+                Some(receiver) => receiver.changed().await,
+                None => core::future::pending().await,
+            }
+        };
+        // Bound first, so the input is free again for the battery once the select is dropped
+        let woken = select4(
+            REFRESH_QUEUE.next(),
+            event_bus::next(),
+            analog.next_event(),
+            select(Timer::at(deadline), activity_changed),
+        )
+        .await;
+        let event = match woken {
+            Either4::First(request) => {
+                refresh(request, display, frame).await;
+                continue;
+            }
+            Either4::Second(event) => event,
+            Either4::Third(InputEvent::Pressed(button)) => Event::Button(button),
+            Either4::Third(InputEvent::Released(_)) => continue,
+            Either4::Fourth(Either::First(())) => {
+                let now = Instant::now();
+                if battery_due <= now {
+                    read_battery(
+                        &mut analog,
+                        &mut gauge,
+                        &mut battery_sampled,
+                        shutdown_threshold,
+                        display,
+                        sd_card,
+                        rtc,
+                    )
+                    .await;
+                    battery_due = now + BATTERY_INTERVAL;
+                }
+
+                if overlay_due.is_some_and(|due| due <= now) {
+                    let report = diagnostics::Report::collect();
+                    match diagnostics::draw_overlay(&report, frame) {
+                        Ok(()) => REFRESH_QUEUE.request(RefreshRequest {
+                            region: Some(diagnostics::OVERLAY_REGION),
+                            mode: ShowMode::Exact(eink_display::RefreshMode::Fast),
+                        }),
+                        Err(error) => error!("Failed to draw memory overlay: {:?}", error),
+                    }
+                    overlay_drawn = Some(now);
+                }
+
+                if toast_due.is_some_and(|due| due <= now) {
+                    toast_drawn = None;
+                    // The toast covered part of the home screen
+                    frame.fill(0xFF);
+                    draw_home(frame);
+                    REFRESH_QUEUE.request(RefreshRequest {
+                        region: Some(toast::REGION),
+                        mode: ShowMode::Exact(eink_display::RefreshMode::Fast),
+                    });
+                }
+                continue;
+            }
+            Either4::Fourth(Either::Second(activity)) => {
+                draw_activity(activity, frame);
+                continue;
+            }
+        };
+
+        match event {
+            Event::Battery(BatteryAlert::Low) => shutdown.signal(()),
+            Event::Battery(alert) => {
+                let message = if alert == BatteryAlert::ChargingStarted {
+                    "Charging"
+                } else {
+                    "Not charging"
+                };
+                info!("{}", message);
+                toast_drawn = show_toast(message, frame).or(toast_drawn);
+            }
+            Event::SdCard(change) => {
+                event_log::record(event_log::Event::SdCard(change));
+                // The books of the card that was taken out are gone
+                library = match change {
+                    CardChange::Inserted => load_library(sd_card).await,
+                    CardChange::Removed => Library::default(),
+                };
+                let message = match change {
+                    CardChange::Inserted => "SD card inserted",
+                    CardChange::Removed => "SD card removed",
+                };
+
+                toast_drawn = show_toast(message, frame).or(toast_drawn);
+            }
+            Event::TestPattern => match console::draw_test_pattern(frame) {
+                Ok(()) => REFRESH_QUEUE.request(RefreshRequest {
+                    region: None,
+                    mode: ShowMode::Exact(eink_display::RefreshMode::Full),
+                }),
+                Err(error) => error!("Failed to draw test pattern: {:?}", error),
+            },
+            Event::Benchmark => show_benchmark(sd_card, display, frame).await,
+            // Shown ones are drawn by the timer
+            Event::Overlay(true) => overlay_drawn = None,
+            Event::Overlay(false) => {
+                if overlay_drawn.take().is_some() {
+                    // The overlay covered part of the home screen
+                    frame.fill(0xFF);
+                    draw_home(frame);
+                    REFRESH_QUEUE.request(RefreshRequest {
+                        region: None,
+                        mode: ShowMode::Exact(eink_display::RefreshMode::Fast),
+                    });
+                }
+            }
+            Event::DisplayRecovered => info!("Display recovered after it stopped responding"),
+            Event::Button(Button::Confirm) => {
+                open_library(
+                    &library,
+                    &mut settings,
+                    profiles.active(),
+                    sd_card,
+                    settings_storage.as_ref(),
+                    hyphenator,
+                    glyphs.as_ref(),
+                    &mut analog,
+                    display,
+                    frame,
+                )
+                .await;
+                show_home(display, frame).await;
+            }
+            Event::Button(Button::Right) => {
+                open_settings(
+                    &mut settings,
+                    profiles.active(),
+                    sd_card,
+                    settings_storage.as_ref(),
+                    hyphenator,
+                    glyphs.as_ref(),
+                    #[cfg(feature = "radio")]
+                    &mut radio,
+                    &mut analog,
+                    display,
+                    frame,
+                )
+                .await;
+                // A network that was just set up is connected to right away
+                #[cfg(feature = "radio")]
+                start_network(
+                    spawner,
+                    &mut radio,
+                    &config,
+                    profiles.active(),
+                    sd_card,
+                    rtc,
+                );
+                show_home(display, frame).await;
+            }
+            #[cfg(feature = "radio")]
+            Event::Button(Button::Left) => match (radio.stack(), &config.webdav) {
+                (Some(stack), Some(remote)) => {
+                    sync_library(
+                        stack,
+                        remote,
+                        profiles.active(),
+                        sd_card,
+                        &mut analog,
+                        display,
+                        frame,
+                    )
+                    .await;
+                    library = load_library(sd_card).await;
+                    show_home(display, frame).await;
+                }
+                (None, _) => {
+                    let notice = "Set up WiFi in the settings\nto sync the library.";
+                    show_notice(notice, &mut analog, display, frame).await;
+                }
+                (Some(_), None) => {
+                    let notice = "Set a WebDAV folder in CONFIG.INI\nto sync the library.";
+                    show_notice(notice, &mut analog, display, frame).await;
+                }
+            },
+            Event::Button(button) => info!("{} pressed on the home screen", button),
+            // Handled by its own task, which also works while the home screen is not shown
+            Event::PowerButton(press) => info!("Power button pressed: {}", press),
+            #[cfg(feature = "radio")]
+            Event::Network(network) => info!("Network: {}", network),
+        }
     }
 
     Ok(())
@@ -213,6 +2413,8 @@ async fn main(spawner: Spawner) {
     let result = run(spawner).await;
     if let Err(error) = result {
         error!("Main failed: {:?}", defmt::Debug2Format(&error));
+        crash_report::save_fatal_error(&error);
+        event_log::record(event_log::Event::Error(format!("Main failed: {error:?}")));
     }
     info!("Main completed");
 }
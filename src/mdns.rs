@@ -0,0 +1,382 @@
+//! Multicast DNS responder so the reader can be reached as `crustpoint.local` and its servers show
+//! up in the network browsers of computers and phones, instead of having to look up the address.
+//! It answers the queries for the host name and for the DNS-SD records of the services that are
+//! running, and announces them when it starts.
+//!
+//! Only IPv4 is supported. Names in the responses are not compressed, as the packets are small.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::net::Ipv4Addr;
+
+/// The reader is reachable as this name with `.local` appended
+pub(crate) const HOST_NAME: &str = "crustpoint";
+/// Where the services are listed for browsers
+const SERVICES_NAME: &str = "_services._dns-sd._udp.local";
+const PORT: u16 = 5353;
+const MULTICAST_ADDRESS: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 251);
+/// How long other hosts keep the records in seconds
+const TIME_TO_LIVE: u32 = 120;
+const HEADER_SIZE: usize = 12;
+/// Labels of a name can point back to earlier labels, which is limited to not loop forever
+const MAXIMUM_POINTERS: usize = 16;
+
+const TYPE_A: u16 = 1;
+const TYPE_PTR: u16 = 12;
+const TYPE_TXT: u16 = 16;
+const TYPE_SRV: u16 = 33;
+const TYPE_ANY: u16 = 255;
+const CLASS_INTERNET: u16 = 1;
+/// Set on records this host owns so others replace their cached records instead of adding to them
+const CACHE_FLUSH: u16 = 0x8000;
+
+/// Service that is announced while it runs, like a web server for uploads
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub(crate) struct Service {
+    /// Name that is shown in browsers
+    pub(crate) instance: &'static str,
+    /// DNS-SD service type like `_http._tcp`
+    pub(crate) kind: &'static str,
+    pub(crate) port: u16,
+    /// Key and value pairs like `path=/` that tell clients more about the service
+    pub(crate) text: &'static [&'static str],
+}
+
+impl Service {
+    fn type_name(&self) -> String {
+        let mut name = String::from(self.kind);
+        name.push_str(".local");
+        name
+    }
+
+    fn instance_name(&self) -> String {
+        let mut name = String::from(self.instance);
+        name.push('.');
+        name.push_str(&self.type_name());
+        name
+    }
+}
+
+struct Question {
+    name: String,
+    kind: u16,
+}
+
+fn host_name() -> String {
+    let mut name = String::from(HOST_NAME);
+    name.push_str(".local");
+    name
+}
+
+/// Response to a query or None if it doesn't ask about anything of this host. Queries that don't
+/// come from the mDNS port are from simple resolvers, which get a unicast response that repeats
+/// the question.
+fn respond(
+    query: &[u8],
+    address: Ipv4Addr,
+    services: &[Service],
+    is_legacy: bool,
+) -> Option<Vec<u8>> {
+    let header = query.get(..HEADER_SIZE)?;
+    let is_response = header[2] & 0x80 != 0;
+    if is_response {
+        return None;
+    }
+
+    let question_count = u16::from_be_bytes([header[4], header[5]]);
+    let mut offset = HEADER_SIZE;
+    let mut questions = Vec::new();
+    for _ in 0..question_count {
+        let (name, end) = read_name(query, offset)?;
+        let kind = u16::from_be_bytes([*query.get(end)?, *query.get(end + 1)?]);
+        // Class follows the type
+        offset = end + 4;
+        if offset > query.len() {
+            return None;
+        }
+        questions.push(Question { name, kind });
+    }
+
+    let mut records = Records::default();
+    for question in &questions {
+        records.answer(question, address, services);
+    }
+    if records.answers == 0 {
+        return None;
+    }
+
+    let id = if is_legacy {
+        u16::from_be_bytes([header[0], header[1]])
+    } else {
+        0
+    };
+    let repeated = if is_legacy { &questions[..] } else { &[] };
+    Some(records.finish(id, repeated))
+}
+
+/// Unsolicited response with all records, sent when the responder starts
+fn announcement(address: Ipv4Addr, services: &[Service]) -> Vec<u8> {
+    let mut records = Records::default();
+    records.host(address);
+    for service in services {
+        records.service(service, address);
+    }
+    records.finish(0, &[])
+}
+
+/// Answers of a response as they are written
+#[derive(Default)]
+struct Records {
+    content: Vec<u8>,
+    answers: u16,
+    /// The address record is only added once when several answers need it
+    has_host: bool,
+}
+
+impl Records {
+    fn answer(&mut self, question: &Question, address: Ipv4Addr, services: &[Service]) {
+        let is_kind = |kind| question.kind == kind || question.kind == TYPE_ANY;
+        if question.name.eq_ignore_ascii_case(&host_name()) && is_kind(TYPE_A) {
+            self.host(address);
+        }
+
+        if question.name.eq_ignore_ascii_case(SERVICES_NAME) && is_kind(TYPE_PTR) {
+            for service in services {
+                self.record(
+                    SERVICES_NAME,
+                    TYPE_PTR,
+                    0,
+                    &encode_name(&service.type_name()),
+                );
+            }
+        }
+
+        for service in services {
+            if question.name.eq_ignore_ascii_case(&service.type_name()) && is_kind(TYPE_PTR) {
+                self.service(service, address);
+            }
+
+            if question.name.eq_ignore_ascii_case(&service.instance_name()) {
+                if is_kind(TYPE_SRV) {
+                    self.record(
+                        &service.instance_name(),
+                        TYPE_SRV,
+                        CACHE_FLUSH,
+                        &server(service),
+                    );
+                }
+                if is_kind(TYPE_TXT) {
+                    self.record(
+                        &service.instance_name(),
+                        TYPE_TXT,
+                        CACHE_FLUSH,
+                        &text(service),
+                    );
+                }
+            }
+        }
+    }
+
+    fn host(&mut self, address: Ipv4Addr) {
+        if self.has_host {
+            return;
+        }
+
+        self.has_host = true;
+        self.record(&host_name(), TYPE_A, CACHE_FLUSH, &address.octets());
+    }
+
+    /// Everything a browser needs to connect to the service in one go
+    fn service(&mut self, service: &Service, address: Ipv4Addr) {
+        let instance_name = service.instance_name();
+        self.record(
+            &service.type_name(),
+            TYPE_PTR,
+            0,
+            &encode_name(&instance_name),
+        );
+        self.record(&instance_name, TYPE_SRV, CACHE_FLUSH, &server(service));
+        self.record(&instance_name, TYPE_TXT, CACHE_FLUSH, &text(service));
+        self.host(address);
+    }
+
+    fn record(&mut self, name: &str, kind: u16, flags: u16, data: &[u8]) {
+        self.content.extend_from_slice(&encode_name(name));
+        self.content.extend_from_slice(&kind.to_be_bytes());
+        self.content
+            .extend_from_slice(&(CLASS_INTERNET | flags).to_be_bytes());
+        self.content.extend_from_slice(&TIME_TO_LIVE.to_be_bytes());
+        // Can't truncate as the data is at most a few hundred bytes
+        self.content
+            .extend_from_slice(&(data.len() as u16).to_be_bytes());
+        self.content.extend_from_slice(data);
+        self.answers += 1;
+    }
+
+    fn finish(self, id: u16, questions: &[Question]) -> Vec<u8> {
+        let mut packet = Vec::with_capacity(HEADER_SIZE + self.content.len());
+        packet.extend_from_slice(&id.to_be_bytes());
+        // Authoritative response
+        packet.extend_from_slice(&0x8400_u16.to_be_bytes());
+        // Can't truncate as there are only a few questions
+        packet.extend_from_slice(&(questions.len() as u16).to_be_bytes());
+        packet.extend_from_slice(&self.answers.to_be_bytes());
+        packet.extend_from_slice(&[0, 0, 0, 0]);
+        for question in questions {
+            packet.extend_from_slice(&encode_name(&question.name));
+            packet.extend_from_slice(&question.kind.to_be_bytes());
+            packet.extend_from_slice(&CLASS_INTERNET.to_be_bytes());
+        }
+        packet.extend_from_slice(&self.content);
+        packet
+    }
+}
+
+/// Data of the SRV record with the priority and weight left at 0
+fn server(service: &Service) -> Vec<u8> {
+    let mut data = Vec::new();
+    data.extend_from_slice(&[0, 0, 0, 0]);
+    data.extend_from_slice(&service.port.to_be_bytes());
+    data.extend_from_slice(&encode_name(&host_name()));
+    data
+}
+
+/// Data of the TXT record, which needs at least one string even if it is empty
+fn text(service: &Service) -> Vec<u8> {
+    let mut data = Vec::new();
+    for entry in service.text {
+        // Can't truncate as the entries are short constants
+        data.push(entry.len() as u8);
+        data.extend_from_slice(entry.as_bytes());
+    }
+    if data.is_empty() {
+        data.push(0);
+    }
+    data
+}
+
+fn encode_name(name: &str) -> Vec<u8> {
+    let mut encoded = Vec::with_capacity(name.len() + 2);
+    for label in name.split('.') {
+        // Can't truncate as the names are made of short constants
+        encoded.push(label.len() as u8);
+        encoded.extend_from_slice(label.as_bytes());
+    }
+    encoded.push(0);
+    encoded
+}
+
+/// The name at the offset with the labels joined by dots and where the name ends in the packet
+fn read_name(packet: &[u8], mut offset: usize) -> Option<(String, usize)> {
+    let mut name = String::new();
+    let mut end = None;
+    for _ in 0..MAXIMUM_POINTERS {
+        loop {
+            let length = *packet.get(offset)?;
+            if length == 0 {
+                return Some((name, end.unwrap_or(offset + 1)));
+            }
+
+            // Compressed names continue at an earlier offset
+            if length & 0xC0 == 0xC0 {
+                let pointer =
+                    usize::from(u16::from_be_bytes([length, *packet.get(offset + 1)?]) & 0x3FFF);
+                end.get_or_insert(offset + 2);
+                offset = pointer;
+                break;
+            }
+
+            let label = packet.get(offset + 1..offset + 1 + usize::from(length))?;
+            if !name.is_empty() {
+                name.push('.');
+            }
+            name.push_str(&String::from_utf8_lossy(label));
+            offset += 1 + usize::from(length);
+        }
+    }
+
+    None
+}
+
+#[cfg(feature = "radio")]
+pub(crate) use network::run;
+
+#[cfg(feature = "radio")]
+mod network {
+    use defmt::{info, warn};
+    use embassy_net::udp::{PacketMetadata, UdpSocket};
+    use embassy_net::{IpAddress, IpEndpoint, Stack};
+
+    use super::{MULTICAST_ADDRESS, PORT, Service, announcement, respond};
+
+    /// Queries and responses fit into a single Ethernet frame
+    const BUFFER_SIZE: usize = 1536;
+    const PACKETS: usize = 4;
+
+    /// Answers queries until the future is dropped, which is when the services stop
+    pub(crate) async fn run(stack: Stack<'_>, services: &[Service]) {
+        stack.wait_config_up().await;
+        let Some(config) = stack.config_v4() else {
+            return;
+        };
+        let address = config.address.address();
+
+        if let Err(error) = stack.join_multicast_group(MULTICAST_ADDRESS) {
+            warn!(
+                "Failed to join mDNS group: {:?}",
+                defmt::Debug2Format(&error)
+            );
+            return;
+        }
+
+        let mut receive_metadata = [PacketMetadata::EMPTY; PACKETS];
+        let mut receive_buffer = [0; BUFFER_SIZE];
+        let mut send_metadata = [PacketMetadata::EMPTY; PACKETS];
+        let mut send_buffer = [0; BUFFER_SIZE];
+        let mut socket = UdpSocket::new(
+            stack,
+            &mut receive_metadata,
+            &mut receive_buffer,
+            &mut send_metadata,
+            &mut send_buffer,
+        );
+        if let Err(error) = socket.bind(PORT) {
+            warn!("Failed to bind mDNS socket: {:?}", error);
+            return;
+        }
+
+        let group = IpEndpoint::new(IpAddress::Ipv4(MULTICAST_ADDRESS), PORT);
+        info!(
+            "Announcing {}.local at {}",
+            super::HOST_NAME,
+            defmt::Debug2Format(&address)
+        );
+        if let Err(error) = socket
+            .send_to(&announcement(address, services), group)
+            .await
+        {
+            warn!("Failed to announce services: {:?}", error);
+        }
+
+        let mut query = [0; BUFFER_SIZE];
+        loop {
+            let (length, metadata) = match socket.recv_from(&mut query).await {
+                Ok(received) => received,
+                Err(error) => {
+                    warn!("Failed to receive mDNS query: {:?}", error);
+                    continue;
+                }
+            };
+
+            let is_legacy = metadata.endpoint.port != PORT;
+            let Some(response) = respond(&query[..length], address, services, is_legacy) else {
+                continue;
+            };
+
+            let destination = if is_legacy { metadata.endpoint } else { group };
+            if let Err(error) = socket.send_to(&response, destination).await {
+                warn!("Failed to send mDNS response: {:?}", error);
+            }
+        }
+    }
+}
@@ -0,0 +1,78 @@
+//! Sizes of the large statically allocated buffers for each build profile.
+//! Buffers and caches should take their size from here so the budget can be checked at compile time.
+
+use crate::eink_display::Frame;
+
+#[cfg(all(feature = "low-ram", feature = "radio"))]
+compile_error!(
+    "The low RAM profile does not support the radio. Build without the default features."
+);
+
+#[cfg(not(feature = "low-ram"))]
+mod profile {
+    /// COEX needs more RAM
+    pub(crate) const HEAP_SIZE: usize = 64 * 1024;
+    pub(crate) const DMA_BUFFER_SIZE: usize = 32_000;
+    /// Frames for the main loop, for the copy of what the panel shows next to the display and for
+    /// the sleep screen of the power button
+    pub(crate) const FRAME_POOL_SIZE: usize = 3;
+    /// Largest chunk read from the SD card at once
+    pub(crate) const MAXIMUM_SD_READ_BLOCKS: u64 = 64;
+    /// Blocks of the SD card kept in memory, half of them are read ahead
+    pub(crate) const SD_CACHE_BLOCKS: usize = 16;
+    /// Compressed frames larger than this are not kept as boot snapshot. Only allocated on the
+    /// heap while saving or loading.
+    pub(crate) const MAXIMUM_SNAPSHOT_SIZE: usize = 24 * 1024;
+    /// Bitmaps of the glyphs loaded from the SD card, enough for a page of CJK text at 16x16
+    /// pixels. Only allocated on the heap while a book needs them.
+    pub(crate) const GLYPH_CACHE_SIZE: usize = 12 * 1024;
+    /// Text of a book that is laid out at once. Longer books are read in sections.
+    pub(crate) const MAXIMUM_TEXT_SIZE: usize = 24 * 1024;
+    /// Leaves the rest of the 400 KiB of SRAM for the instruction cache, the stacks and the
+    /// statics of the radio
+    pub(super) const STATIC_RAM_BUDGET: usize = 300 * 1024;
+}
+
+#[cfg(feature = "low-ram")]
+mod profile {
+    pub(crate) const HEAP_SIZE: usize = 24 * 1024;
+    /// Transfers larger than this are split, so this only makes writing frames a bit slower
+    pub(crate) const DMA_BUFFER_SIZE: usize = 4096;
+    /// No frame for the sleep screen, the page that was shown stays on the panel instead
+    pub(crate) const FRAME_POOL_SIZE: usize = 2;
+    /// Largest chunk read from the SD card at once
+    pub(crate) const MAXIMUM_SD_READ_BLOCKS: u64 = 16;
+    /// Reads ahead fewer blocks, so reading a book waits for the card more often
+    pub(crate) const SD_CACHE_BLOCKS: usize = 4;
+    /// Compressed frames larger than this are not kept as boot snapshot
+    pub(crate) const MAXIMUM_SNAPSHOT_SIZE: usize = 8 * 1024;
+    /// Glyphs that don't fit are left blank, so pages with many different characters have gaps
+    pub(crate) const GLYPH_CACHE_SIZE: usize = 4 * 1024;
+    /// Sections are shorter, so there are more breaks where the next section is loaded
+    pub(crate) const MAXIMUM_TEXT_SIZE: usize = 6 * 1024;
+    pub(super) const STATIC_RAM_BUDGET: usize = 144 * 1024;
+}
+
+pub(crate) use profile::{
+    DMA_BUFFER_SIZE, FRAME_POOL_SIZE, GLYPH_CACHE_SIZE, HEAP_SIZE, MAXIMUM_SD_READ_BLOCKS,
+    MAXIMUM_SNAPSHOT_SIZE, MAXIMUM_TEXT_SIZE, SD_CACHE_BLOCKS,
+};
+
+/// The pagination in the core crate breaks lines at these sizes
+pub(crate) use crustpoint_core::pagination::{MAXIMUM_LINE_SIZE, MAXIMUM_WORD_SIZE};
+
+/// Receive and transmit buffer
+const DMA_BUFFERS_SIZE: usize = 2 * DMA_BUFFER_SIZE;
+/// The frames are by far the largest statics
+const FRAMES_SIZE: usize = FRAME_POOL_SIZE * Frame::BUFFER_SIZE;
+const SD_CACHE_SIZE: usize = SD_CACHE_BLOCKS * embedded_sdmmc::Block::LEN;
+#[cfg(feature = "display-trace")]
+const TRACE_SIZE: usize = crate::eink_display::trace::SIZE;
+#[cfg(not(feature = "display-trace"))]
+const TRACE_SIZE: usize = 0;
+
+const _: () = assert!(
+    HEAP_SIZE + DMA_BUFFERS_SIZE + FRAMES_SIZE + SD_CACHE_SIZE + TRACE_SIZE
+        <= profile::STATIC_RAM_BUDGET,
+    "Static buffers exceed the RAM budget of the build profile"
+);
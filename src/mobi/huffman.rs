@@ -0,0 +1,102 @@
+//! HUFF/CDIC compression of Kindle books with the tables from the core crate. The dictionaries are
+//! often larger than the heap, so phrases are read from the CDIC records on the SD card as they
+//! come up.
+
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crustpoint_core::mobi::DecompressError;
+use crustpoint_core::mobi::huffman::{CDIC_HEADER_SIZE, Dictionary, Phrase};
+use embedded_sdmmc::RawFile;
+
+use super::{MobiError, read_exact};
+use crate::sd_card::SdCard;
+
+/// The HUFF record is a bit over 1 KiB
+const MAXIMUM_HUFF_SIZE: u32 = 4096;
+
+pub(super) struct Huffman {
+    tables: crustpoint_core::mobi::huffman::Huffman,
+    /// Offsets of the CDIC records in the file
+    dictionaries: Vec<u32>,
+}
+
+/// The CDIC records in the open book
+struct CardDictionary<'a> {
+    sd_card: &'a mut SdCard,
+    file: RawFile,
+    records: &'a [u32],
+}
+
+impl Huffman {
+    /// Reads the HUFF record at the offset and the header of the first CDIC record
+    pub(super) async fn open(
+        sd_card: &mut SdCard,
+        file: RawFile,
+        huff: (u32, u32),
+        dictionaries: Vec<u32>,
+    ) -> Result<Self, MobiError> {
+        let (offset, end) = huff;
+        // Can't truncate as the record size is capped
+        let mut record = vec![0; end.saturating_sub(offset).min(MAXIMUM_HUFF_SIZE) as usize];
+        read_exact(sd_card, file, offset, &mut record).await?;
+
+        let Some(&first) = dictionaries.first() else {
+            return Err(MobiError::InvalidHuffman);
+        };
+        let mut header = [0; CDIC_HEADER_SIZE as usize];
+        read_exact(sd_card, file, first, &mut header).await?;
+
+        let tables = crustpoint_core::mobi::huffman::Huffman::parse(&record, &header)
+            .map_err(|_| MobiError::InvalidHuffman)?;
+        Ok(Self {
+            tables,
+            dictionaries,
+        })
+    }
+
+    /// Decompresses a text record and appends it to the output
+    pub(super) async fn decompress(
+        &self,
+        sd_card: &mut SdCard,
+        file: RawFile,
+        data: &[u8],
+        output: &mut Vec<u8>,
+    ) -> Result<(), MobiError> {
+        let mut dictionary = CardDictionary {
+            sd_card,
+            file,
+            records: &self.dictionaries,
+        };
+        self.tables.decompress(&mut dictionary, data, output).await
+    }
+}
+
+impl Dictionary for CardDictionary<'_> {
+    type Error = MobiError;
+
+    async fn phrase(&mut self, record: usize, entry: u32) -> Result<Phrase, MobiError> {
+        let record = *self
+            .records
+            .get(record)
+            .ok_or(DecompressError::UnknownPhrase)?;
+
+        let mut offset = [0; 2];
+        read_exact(
+            self.sd_card,
+            self.file,
+            record + CDIC_HEADER_SIZE + 2 * entry,
+            &mut offset,
+        )
+        .await?;
+        let start = record + CDIC_HEADER_SIZE + u32::from(u16::from_be_bytes(offset));
+
+        let mut length = [0; 2];
+        read_exact(self.sd_card, self.file, start, &mut length).await?;
+        let (length, is_literal) = Phrase::length(u16::from_be_bytes(length));
+
+        let mut data = vec![0; length];
+        read_exact(self.sd_card, self.file, start + 2, &mut data).await?;
+        Ok(Phrase { data, is_literal })
+    }
+}
@@ -0,0 +1,473 @@
+//! Kindle books in the MOBI and AZW3 formats without DRM. They are Palm databases with a header
+//! record followed by the text records and then images and indexes. The text is HTML that is split
+//! into records of 4 KiB before compression, with PalmDoc or HUFF/CDIC compression.
+//!
+//! Record 0 starts with the PalmDoc header:
+//! - u16 compression, 1 for none, 2 for PalmDoc and 17480 for HUFF/CDIC
+//! - u16 unused
+//! - u32 length of the text
+//! - u16 number of text records
+//! - u16 maximum size of a text record
+//! - u16 encryption, 0 for none
+//!
+//! The MOBI header follows at [`MOBI_HEADER_OFFSET`] with the encoding, the title, where the
+//! HUFF/CDIC records are and which trailing entries the text records have. The EXTH header after it
+//! has metadata like the author and the cover as records of a u32 type, a u32 length including the
+//! type and length and the data. All integers are big endian.
+//!
+//! Only the text is read, without the formatting and images. AZW3 books are read the same way,
+//! which shows their text in order but without the structure from the index records.
+//!
+//! The decompression and the trailing entries are in the core crate to be tested on the host.
+
+mod huffman;
+mod text;
+
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::ops::Range;
+
+use crustpoint_core::mobi::{DecompressError, palm_doc, trailing_size};
+use embedded_sdmmc::{Mode, RawFile};
+
+use self::huffman::Huffman;
+use self::text::{Encoding, PlainText};
+use crate::sd_card::{OpenError, ReadPriority, SdCard, VolumeError};
+
+const DATABASE_HEADER_SIZE: u32 = 78;
+const RECORD_ENTRY_SIZE: u32 = 8;
+const MOBI_HEADER_OFFSET: usize = 16;
+const MOBI_MAGIC: [u8; 4] = *b"MOBI";
+/// Enough for the PalmDoc header and the MOBI header up to the trailing entry flags
+const HEADER_SIZE: usize = 256;
+/// Enough for books of a few MiB with the records before the HUFF/CDIC records
+const MAXIMUM_RECORDS: u16 = 2048;
+/// Records in the list that are read from the SD card at once
+const RECORD_LIST_CHUNK: u16 = 64;
+/// Longer titles are cut off
+const MAXIMUM_TITLE_SIZE: u32 = 128;
+const EXTH_MAGIC: [u8; 4] = *b"EXTH";
+/// Set in the MOBI header flags if there is an EXTH header
+const EXTH_FLAG: u32 = 0x40;
+/// Metadata after this is left out
+const MAXIMUM_EXTH_SIZE: u32 = 4096;
+const EXTH_AUTHOR: u32 = 100;
+/// Offset of the cover from the first image record
+const EXTH_COVER: u32 = 201;
+const EXTH_TITLE: u32 = 503;
+/// Text records are at most 4 KiB before compression, with a bit of leeway for broken books
+const MAXIMUM_RECORD_SIZE: u32 = 8 * 1024;
+
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum MobiError {
+    #[error("Failed to open book")]
+    Open(#[from] OpenError),
+    #[error("Failed to read book")]
+    Read(VolumeError),
+    #[error("Book ends early")]
+    Truncated,
+    #[error("File is not a MOBI book")]
+    NotMobi,
+    #[error("Book has more records than the maximum")]
+    TooManyRecords(u16),
+    #[error("Book is protected with DRM")]
+    Encrypted,
+    #[error("Book uses an unsupported compression")]
+    UnsupportedCompression(u16),
+    #[error("Book has a broken HUFF/CDIC dictionary")]
+    InvalidHuffman,
+    #[error("Failed to decompress text")]
+    InvalidCompression(DecompressError),
+    #[error("Text record is larger than the maximum size")]
+    RecordTooLarge(u32),
+}
+
+impl From<DecompressError> for MobiError {
+    fn from(error: DecompressError) -> Self {
+        Self::InvalidCompression(error)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+enum Compression {
+    None,
+    PalmDoc,
+    HuffCdic,
+}
+
+/// Text of the book from a text record on
+pub(crate) struct Section {
+    pub(crate) text: String,
+    /// Where the next section starts or None if this is the end of the book
+    pub(crate) next: Option<u16>,
+}
+
+pub(crate) struct Book {
+    file: RawFile,
+    title: String,
+    author: Option<String>,
+    /// Record of the cover image
+    cover: Option<u16>,
+    /// Offsets of the records that are needed followed by where the last one ends
+    records: Vec<u32>,
+    record_count: u16,
+    compression: Compression,
+    huffman: Option<Huffman>,
+    text_records: u16,
+    encoding: Encoding,
+    /// Which entries follow the text in each text record
+    trailing_entries: u16,
+}
+
+impl Book {
+    pub(crate) async fn open(sd_card: &mut SdCard, file: RawFile) -> Result<Self, MobiError> {
+        let mut database = [0; DATABASE_HEADER_SIZE as usize];
+        read_exact(sd_card, file, 0, &mut database).await?;
+        let is_book = &database[60..68] == b"BOOKMOBI";
+        if !is_book {
+            return Err(MobiError::NotMobi);
+        }
+
+        let record_count = u16_at(&database, 76);
+        let first_record = read_record_list(sd_card, file, 0..2.min(record_count)).await?;
+        let [start, end] = first_record[..] else {
+            return Err(MobiError::Truncated);
+        };
+
+        // Can't truncate as the header is small
+        let mut header = vec![0; end.saturating_sub(start).min(HEADER_SIZE as u32) as usize];
+        read_exact(sd_card, file, start, &mut header).await?;
+        if header.len() < MOBI_HEADER_OFFSET + 16
+            || header[MOBI_HEADER_OFFSET..MOBI_HEADER_OFFSET + 4] != MOBI_MAGIC
+        {
+            return Err(MobiError::NotMobi);
+        }
+
+        let compression = match u16_at(&header, 0) {
+            1 => Compression::None,
+            2 => Compression::PalmDoc,
+            17480 => Compression::HuffCdic,
+            other => return Err(MobiError::UnsupportedCompression(other)),
+        };
+
+        if u16_at(&header, 12) != 0 {
+            return Err(MobiError::Encrypted);
+        }
+
+        let text_records = u16_at(&header, 8);
+        let encoding = Encoding::from_code_page(u32_at(&header, 28));
+        let header_length = u32_at(&header, 20);
+        let trailing_entries = if header_length >= 0xE4 && header.len() >= 0xF4 {
+            u16_at(&header, 0xF2)
+        } else {
+            0
+        };
+
+        // The HUFF record is followed by the CDIC records
+        let (huff_first, huff_count) = if compression == Compression::HuffCdic {
+            if header.len() < 0x78 {
+                return Err(MobiError::InvalidHuffman);
+            }
+
+            let (first, count) = (u32_at(&header, 0x70), u32_at(&header, 0x74));
+            // At least the HUFF record and one CDIC record
+            if first == 0 || count < 2 {
+                return Err(MobiError::InvalidHuffman);
+            }
+
+            (first, count)
+        } else {
+            (0, 0)
+        };
+
+        // Records up to the last text record and the last CDIC record
+        let needed = (u32::from(text_records) + 1).max(huff_first.saturating_add(huff_count));
+        if needed > u32::from(record_count.min(MAXIMUM_RECORDS)) {
+            return Err(MobiError::TooManyRecords(record_count));
+        }
+
+        // Can't truncate as it is at most the number of records
+        let needed = needed as u16;
+        let mut records = read_record_list(sd_card, file, 0..needed).await?;
+        let end = if record_count > needed {
+            read_record_list(sd_card, file, needed..needed + 1).await?[0]
+        } else {
+            sd_card.length(file).await.map_err(MobiError::Read)?
+        };
+        records.push(end);
+
+        let huffman = if compression == Compression::HuffCdic {
+            // Can't truncate as it is checked against the number of records
+            let huff = huff_first as usize;
+            let dictionaries = records[huff + 1..huff + huff_count as usize].to_vec();
+            let huff = (records[huff], records[huff + 1]);
+            Some(Huffman::open(sd_card, file, huff, dictionaries).await?)
+        } else {
+            None
+        };
+
+        let metadata = read_metadata(sd_card, file, start..end, &header, encoding).await?;
+        let title = match metadata.title {
+            Some(title) => title,
+            None => read_title(sd_card, file, start, &header, encoding).await?,
+        };
+
+        Ok(Self {
+            file,
+            title,
+            author: metadata.author,
+            cover: metadata.cover,
+            records,
+            record_count,
+            compression,
+            huffman,
+            text_records,
+            encoding,
+            trailing_entries,
+        })
+    }
+
+    pub(crate) fn title(&self) -> &str {
+        &self.title
+    }
+
+    pub(crate) fn author(&self) -> Option<&str> {
+        self.author.as_deref()
+    }
+
+    /// Record of the cover image if the book has one
+    pub(crate) fn cover(&self) -> Option<u16> {
+        self.cover
+    }
+
+    /// Where the record starts in the file, for records after the text like images
+    pub(crate) async fn record_offset(
+        &self,
+        sd_card: &mut SdCard,
+        record: u16,
+    ) -> Result<u32, MobiError> {
+        if record >= self.record_count {
+            return Err(MobiError::Truncated);
+        }
+
+        if let Some(&offset) = self.records.get(usize::from(record)) {
+            return Ok(offset);
+        }
+
+        let offsets = read_record_list(sd_card, self.file, record..record + 1).await?;
+        offsets.first().copied().ok_or(MobiError::Truncated)
+    }
+
+    /// Reads the text from the text record on until it is longer than the maximum size or the book
+    /// ends. Text records start at 1.
+    pub(crate) async fn read_section(
+        &self,
+        sd_card: &mut SdCard,
+        first: u16,
+        maximum_size: usize,
+    ) -> Result<Section, MobiError> {
+        let mut text = PlainText::new(self.encoding);
+        let mut decompressed = Vec::new();
+        let mut record = first.max(1);
+        while record <= self.text_records && text.len() < maximum_size {
+            decompressed.clear();
+            self.read_record(sd_card, record, &mut decompressed).await?;
+            text.push(&decompressed);
+            record += 1;
+        }
+
+        Ok(Section {
+            text: text.into_text(),
+            next: (record <= self.text_records).then_some(record),
+        })
+    }
+
+    async fn read_record(
+        &self,
+        sd_card: &mut SdCard,
+        record: u16,
+        output: &mut Vec<u8>,
+    ) -> Result<(), MobiError> {
+        let start = self.records[usize::from(record)];
+        let end = self.records[usize::from(record) + 1];
+        let size = end.saturating_sub(start);
+        if size > MAXIMUM_RECORD_SIZE {
+            return Err(MobiError::RecordTooLarge(size));
+        }
+
+        // Can't truncate as it is at most the maximum record size
+        let mut data = vec![0; size as usize];
+        read_exact(sd_card, self.file, start, &mut data).await?;
+        let length = data.len() - trailing_size(&data, self.trailing_entries);
+        let data = &data[..length];
+
+        match (self.compression, &self.huffman) {
+            (Compression::None, _) => output.extend_from_slice(data),
+            (Compression::PalmDoc, _) => palm_doc::decompress(data, output)?,
+            (Compression::HuffCdic, Some(huffman)) => {
+                huffman.decompress(sd_card, self.file, data, output).await?;
+            }
+            (Compression::HuffCdic, None) => return Err(MobiError::InvalidHuffman),
+        }
+
+        Ok(())
+    }
+}
+
+/// Opens the book at the path and reads the section from the text record on
+pub(crate) async fn load_section(
+    sd_card: &mut SdCard,
+    path: &str,
+    first: u16,
+    maximum_size: usize,
+) -> Result<Section, MobiError> {
+    let file = sd_card.open(path, Mode::ReadOnly).await?;
+    let result = match Book::open(sd_card, file).await {
+        Ok(book) => book.read_section(sd_card, first, maximum_size).await,
+        Err(error) => Err(error),
+    };
+
+    if let Err(error) = sd_card.close(file).await {
+        defmt::warn!("Failed to close book: {:?}", error);
+    }
+
+    result
+}
+
+/// Reads the offsets of the records in the range from the record list
+async fn read_record_list(
+    sd_card: &mut SdCard,
+    file: RawFile,
+    records: Range<u16>,
+) -> Result<Vec<u32>, MobiError> {
+    let mut offsets = Vec::with_capacity(records.len());
+    let mut buffer = vec![0; usize::from(RECORD_LIST_CHUNK) * RECORD_ENTRY_SIZE as usize];
+    let mut record = records.start;
+    while record < records.end {
+        let count = (records.end - record).min(RECORD_LIST_CHUNK);
+        // Can't truncate as it is at most the chunk size
+        let chunk = &mut buffer[..usize::from(count) * RECORD_ENTRY_SIZE as usize];
+        let offset = DATABASE_HEADER_SIZE + u32::from(record) * RECORD_ENTRY_SIZE;
+        read_exact(sd_card, file, offset, chunk).await?;
+        offsets.extend(
+            chunk
+                .chunks_exact(RECORD_ENTRY_SIZE as usize)
+                .map(|entry| u32_at(entry, 0)),
+        );
+        record += count;
+    }
+
+    Ok(offsets)
+}
+
+/// Reads the full name of the book that is stored in record 0 after the headers
+async fn read_title(
+    sd_card: &mut SdCard,
+    file: RawFile,
+    record: u32,
+    header: &[u8],
+    encoding: Encoding,
+) -> Result<String, MobiError> {
+    if header.len() < 92 {
+        return Ok(String::new());
+    }
+
+    let offset = u32_at(header, 84);
+    let length = u32_at(header, 88).min(MAXIMUM_TITLE_SIZE);
+    // Can't truncate as it is at most the maximum title size
+    let mut title = vec![0; length as usize];
+    read_exact(sd_card, file, record + offset, &mut title).await?;
+    Ok(encoding.decode(&title))
+}
+
+/// Metadata from the EXTH header
+#[derive(Default)]
+struct Metadata {
+    title: Option<String>,
+    author: Option<String>,
+    cover: Option<u16>,
+}
+
+/// Reads the EXTH header after the MOBI header in record 0 if there is one
+async fn read_metadata(
+    sd_card: &mut SdCard,
+    file: RawFile,
+    record: Range<u32>,
+    header: &[u8],
+    encoding: Encoding,
+) -> Result<Metadata, MobiError> {
+    let mut metadata = Metadata::default();
+    if header.len() < 0x84 || u32_at(header, 0x80) & EXTH_FLAG == 0 {
+        return Ok(metadata);
+    }
+
+    // Can't truncate as the header offset is small
+    let start = record.start + MOBI_HEADER_OFFSET as u32 + u32_at(header, 20);
+    let length = record.end.saturating_sub(start).min(MAXIMUM_EXTH_SIZE);
+    // Can't truncate as it is at most the maximum EXTH size
+    let mut exth = vec![0; length as usize];
+    read_exact(sd_card, file, start, &mut exth).await?;
+    if exth.len() < 12 || exth[..4] != EXTH_MAGIC {
+        return Ok(metadata);
+    }
+
+    let first_image = u32_at(header, 0x6C);
+    let mut rest = &exth[12..];
+    for _ in 0..u32_at(&exth, 8) {
+        if rest.len() < 8 {
+            break;
+        }
+
+        let kind = u32_at(rest, 0);
+        // Can't truncate as usize is 32 bits
+        let length = u32_at(rest, 4) as usize;
+        let Some(data) = rest.get(8..length) else {
+            break;
+        };
+
+        match kind {
+            EXTH_AUTHOR => metadata.author = Some(encoding.decode(data)),
+            EXTH_TITLE => metadata.title = Some(encoding.decode(data)),
+            EXTH_COVER if data.len() >= 4 => {
+                metadata.cover = u16::try_from(first_image.saturating_add(u32_at(data, 0))).ok();
+            }
+            _ => {}
+        }
+
+        rest = &rest[length..];
+    }
+
+    Ok(metadata)
+}
+
+async fn read_exact(
+    sd_card: &mut SdCard,
+    file: RawFile,
+    offset: u32,
+    buffer: &mut [u8],
+) -> Result<(), MobiError> {
+    let read = sd_card
+        .read_at(file, offset, buffer, ReadPriority::Interactive)
+        .await
+        .map_err(MobiError::Read)?;
+
+    if read < buffer.len() {
+        return Err(MobiError::Truncated);
+    }
+
+    Ok(())
+}
+
+fn u16_at(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_be_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes([
+        bytes[offset],
+        bytes[offset + 1],
+        bytes[offset + 2],
+        bytes[offset + 3],
+    ])
+}
@@ -0,0 +1,260 @@
+//! Turns the HTML of a book into plain text for the layout. Paragraphs and line breaks become line
+//! breaks, white space is collapsed and the common entities are resolved. The HTML comes in pieces,
+//! so tags, entities and characters can be split between them.
+
+use alloc::string::String;
+
+/// Longer tag names are cut off, which doesn't matter for the tags that end paragraphs
+const MAXIMUM_NAME_SIZE: usize = 16;
+const MAXIMUM_ENTITY_SIZE: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub(super) enum Encoding {
+    Windows1252,
+    Utf8,
+}
+
+impl Encoding {
+    /// From the code page number in the MOBI header
+    pub(super) fn from_code_page(code_page: u32) -> Self {
+        match code_page {
+            65001 => Self::Utf8,
+            _ => Self::Windows1252,
+        }
+    }
+
+    /// Text without markup, like the metadata
+    pub(super) fn decode(self, bytes: &[u8]) -> String {
+        match self {
+            Self::Windows1252 => bytes.iter().map(|&byte| windows_1252(byte)).collect(),
+            Self::Utf8 => String::from_utf8_lossy(bytes).into_owned(),
+        }
+    }
+}
+
+enum State {
+    Text,
+    /// Inside a tag with the name so far and whether the name has ended
+    Tag {
+        name: heapless::String<MAXIMUM_NAME_SIZE>,
+        is_name_done: bool,
+    },
+    Entity(heapless::String<MAXIMUM_ENTITY_SIZE>),
+}
+
+pub(super) struct PlainText {
+    encoding: Encoding,
+    /// Start of a UTF-8 character that continues in the next piece
+    partial: heapless::Vec<u8, 4>,
+    state: State,
+    /// Text in the head, in scripts and in styles is not shown
+    is_hidden: bool,
+    text: String,
+}
+
+impl PlainText {
+    pub(super) fn new(encoding: Encoding) -> Self {
+        Self {
+            encoding,
+            partial: heapless::Vec::new(),
+            state: State::Text,
+            is_hidden: false,
+            text: String::new(),
+        }
+    }
+
+    /// Length of the text so far in bytes
+    pub(super) fn len(&self) -> usize {
+        self.text.len()
+    }
+
+    pub(super) fn push(&mut self, bytes: &[u8]) {
+        match self.encoding {
+            Encoding::Windows1252 => {
+                for &byte in bytes {
+                    self.push_character(windows_1252(byte));
+                }
+            }
+            Encoding::Utf8 => {
+                for &byte in bytes {
+                    // Continuation bytes without a start are dropped
+                    if self.partial.is_empty() && byte & 0xC0 == 0x80 {
+                        continue;
+                    }
+
+                    if byte & 0xC0 != 0x80 {
+                        self.partial.clear();
+                    }
+
+                    let _ = self.partial.push(byte);
+                    let partial = core::mem::take(&mut self.partial);
+                    match core::str::from_utf8(&partial) {
+                        Ok(character) => character
+                            .chars()
+                            .for_each(|character| self.push_character(character)),
+                        Err(error) if error.error_len().is_none() => self.partial = partial,
+                        // Invalid sequences are dropped
+                        Err(_) => {}
+                    }
+                }
+            }
+        }
+    }
+
+    pub(super) fn into_text(self) -> String {
+        self.text
+    }
+
+    fn push_character(&mut self, character: char) {
+        match &mut self.state {
+            State::Text => match character {
+                '<' => {
+                    self.state = State::Tag {
+                        name: heapless::String::new(),
+                        is_name_done: false,
+                    }
+                }
+                '&' => self.state = State::Entity(heapless::String::new()),
+                _ => self.push_text(character),
+            },
+            State::Tag { name, is_name_done } => match character {
+                '>' => {
+                    let name = core::mem::take(name);
+                    self.state = State::Text;
+                    self.end_tag(&name);
+                }
+                _ if *is_name_done => {}
+                _ if character.is_ascii_whitespace() => *is_name_done = !name.is_empty(),
+                // Self-closing tags like `<br/>`
+                '/' if !name.is_empty() => *is_name_done = true,
+                _ => {
+                    // Too long names are cut off
+                    let _ = name.push(character.to_ascii_lowercase());
+                }
+            },
+            State::Entity(entity) => match character {
+                ';' => {
+                    let entity = core::mem::take(entity);
+                    self.state = State::Text;
+                    match resolve_entity(&entity) {
+                        Some(character) => self.push_text(character),
+                        // Unknown entities are shown as they are
+                        None => {
+                            self.push_text('&');
+                            entity
+                                .chars()
+                                .for_each(|character| self.push_text(character));
+                            self.push_text(';');
+                        }
+                    }
+                }
+                _ if (character.is_ascii_alphanumeric() || character == '#')
+                    && entity.len() < MAXIMUM_ENTITY_SIZE =>
+                {
+                    let _ = entity.push(character);
+                }
+                // Not an entity but an ampersand in the text
+                _ => {
+                    let entity = core::mem::take(entity);
+                    self.state = State::Text;
+                    self.push_text('&');
+                    entity
+                        .chars()
+                        .for_each(|character| self.push_text(character));
+                    self.push_character(character);
+                }
+            },
+        }
+    }
+
+    fn push_text(&mut self, character: char) {
+        if self.is_hidden {
+            return;
+        }
+
+        if character.is_ascii_whitespace() {
+            // Collapsed and left out at the start of lines
+            if !self.text.is_empty() && !self.text.ends_with([' ', '\n']) {
+                self.text.push(' ');
+            }
+        } else {
+            self.text.push(character);
+        }
+    }
+
+    fn end_tag(&mut self, name: &str) {
+        match name {
+            "head" | "script" | "style" => self.is_hidden = true,
+            "/head" | "/script" | "/style" => self.is_hidden = false,
+            "br" | "/p" | "/div" | "/h1" | "/h2" | "/h3" | "/h4" | "/h5" | "/h6" | "/li"
+            | "/blockquote" | "/tr" | "mbp:pagebreak" => self.break_line(),
+            _ => {}
+        }
+    }
+
+    /// Ends the line without leaving more than one empty line
+    fn break_line(&mut self) {
+        if self.is_hidden || self.text.is_empty() || self.text.ends_with("\n\n") {
+            return;
+        }
+
+        if self.text.ends_with(' ') {
+            self.text.pop();
+        }
+
+        self.text.push('\n');
+    }
+}
+
+fn resolve_entity(entity: &str) -> Option<char> {
+    if let Some(number) = entity.strip_prefix('#') {
+        let code_point = match number.strip_prefix(['x', 'X']) {
+            Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+            None => number.parse().ok()?,
+        };
+        return char::from_u32(code_point);
+    }
+
+    match entity {
+        "amp" => Some('&'),
+        "lt" => Some('<'),
+        "gt" => Some('>'),
+        "quot" => Some('"'),
+        "apos" => Some('\''),
+        "nbsp" => Some('\u{00A0}'),
+        "mdash" => Some('—'),
+        "ndash" => Some('–'),
+        "hellip" => Some('…'),
+        "lsquo" => Some('‘'),
+        "rsquo" => Some('’'),
+        "ldquo" => Some('“'),
+        "rdquo" => Some('”'),
+        _ => None,
+    }
+}
+
+/// Windows-1252 is Latin-1 with punctuation in place of the control characters
+fn windows_1252(byte: u8) -> char {
+    match byte {
+        0x80 => '€',
+        0x82 => '‚',
+        0x84 => '„',
+        0x85 => '…',
+        0x8A => 'Š',
+        0x8C => 'Œ',
+        0x8E => 'Ž',
+        0x91 => '‘',
+        0x92 => '’',
+        0x93 => '“',
+        0x94 => '”',
+        0x95 => '•',
+        0x96 => '–',
+        0x97 => '—',
+        0x99 => '™',
+        0x9A => 'š',
+        0x9C => 'œ',
+        0x9E => 'ž',
+        0x9F => 'Ÿ',
+        _ => char::from(byte),
+    }
+}
@@ -0,0 +1,223 @@
+//! Connects to the WiFi network from [`crate::wifi`] as a station and runs the IP stack. Once it is
+//! started, the connection is kept up in the background and comes back after the network drops.
+//! Features like the progress and library sync wait for the stack to have an address. The mDNS
+//! responder runs next to it, so the reader can be reached as `crustpoint.local`.
+//!
+//! The radio can only be started once per boot, as its driver needs to stay alive for the tasks.
+//! [`Radio`] keeps it for both the network and the Bluetooth set up of the network.
+
+use defmt::{error, info, warn};
+use embassy_executor::{SpawnError, Spawner};
+use embassy_net::{Runner, Stack, StackResources};
+use embassy_time::Timer;
+use esp_hal::peripherals::{BT, WIFI};
+use esp_radio::Controller;
+use esp_radio::wifi::{
+    ClientConfig, ModeConfig, PowerSaveMode, WifiController, WifiDevice, WifiError, WifiEvent,
+    WifiStaState,
+};
+use static_cell::StaticCell;
+
+use crate::board;
+use crate::config::{Config, WifiCredentials};
+use crate::event_bus::{self, Event, NetworkEvent};
+use crate::flash::SharedFlash;
+use crate::wifi::{self, CredentialsStorage};
+use crate::{light_sleep, mdns, status_bar};
+
+/// Sockets that can be open at once: the mDNS responder, a DNS query, the time request and the
+/// progress sync next to a download of the library sync
+const SOCKETS: usize = 6;
+/// Waiting between attempts to connect to save battery while the network is out of reach
+const RECONNECT_DELAY_SECONDS: u64 = 10;
+
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum StartNetworkError {
+    #[error("Network was already started")]
+    AlreadyStarted,
+    #[error("Failed to start radio")]
+    Radio(esp_radio::InitializationError),
+    #[error("Failed to set up WiFi")]
+    Wifi(WifiError),
+    #[error("Failed to start network tasks")]
+    Spawn(#[from] SpawnError),
+}
+
+/// The radio of the board and the network once it is started
+pub(crate) struct Radio {
+    spawner: Spawner,
+    /// Started on first use
+    controller: Option<&'static Controller<'static>>,
+    /// Taken when the network is started
+    wifi: Option<WIFI<'static>>,
+    bluetooth: BT<'static>,
+    /// None if the partition is missing, as the network from the configuration file still works
+    storage: Option<CredentialsStorage>,
+    stack: Option<Stack<'static>>,
+}
+
+impl Radio {
+    pub(crate) fn new(spawner: Spawner, radio: board::Radio, flash: &'static SharedFlash) -> Self {
+        let storage = match CredentialsStorage::new(flash) {
+            Ok(storage) => Some(storage),
+            Err(error) => {
+                error!(
+                    "Failed to open WiFi storage: {:?}",
+                    defmt::Debug2Format(&error)
+                );
+                None
+            }
+        };
+
+        Self {
+            spawner,
+            controller: None,
+            wifi: Some(radio.wifi),
+            bluetooth: radio.bluetooth,
+            storage,
+            stack: None,
+        }
+    }
+
+    /// The IP stack if the network was started
+    pub(crate) fn stack(&self) -> Option<Stack<'static>> {
+        self.stack
+    }
+
+    /// Where the network set up over Bluetooth is kept
+    pub(crate) fn storage(&self) -> Option<&CredentialsStorage> {
+        self.storage.as_ref()
+    }
+
+    /// The network to connect to, see [`wifi::credentials`]
+    pub(crate) fn credentials(&self, config: &Config) -> Option<WifiCredentials> {
+        wifi::credentials(config, self.storage())
+    }
+
+    /// The radio driver and Bluetooth for the set up of the network. Bluetooth can be used again
+    /// once it is dropped.
+    pub(crate) fn bluetooth(
+        &mut self,
+    ) -> Result<(&'static Controller<'static>, BT<'_>), esp_radio::InitializationError> {
+        let controller = self.controller()?;
+        Ok((controller, self.bluetooth.reborrow()))
+    }
+
+    fn controller(
+        &mut self,
+    ) -> Result<&'static Controller<'static>, esp_radio::InitializationError> {
+        static CONTROLLER: StaticCell<Controller<'static>> = StaticCell::new();
+
+        if let Some(controller) = self.controller {
+            return Ok(controller);
+        }
+
+        let controller: &'static _ = CONTROLLER.init(esp_radio::init()?);
+        self.controller = Some(controller);
+        Ok(controller)
+    }
+
+    /// Starts WiFi and the IP stack with DHCP. The stack is returned right away and gets an address
+    /// once the connection is up.
+    pub(crate) fn start(
+        &mut self,
+        credentials: WifiCredentials,
+    ) -> Result<Stack<'static>, StartNetworkError> {
+        let wifi = self.wifi.take().ok_or(StartNetworkError::AlreadyStarted)?;
+        let radio = self.controller().map_err(StartNetworkError::Radio)?;
+        let stack = start(self.spawner, radio, wifi, credentials)?;
+        self.stack = Some(stack);
+        Ok(stack)
+    }
+}
+
+fn start(
+    spawner: Spawner,
+    radio: &'static Controller<'static>,
+    wifi: WIFI<'static>,
+    credentials: WifiCredentials,
+) -> Result<Stack<'static>, StartNetworkError> {
+    static RESOURCES: StaticCell<StackResources<SOCKETS>> = StaticCell::new();
+
+    let (controller, interfaces) =
+        esp_radio::wifi::new(radio, wifi, Default::default()).map_err(StartNetworkError::Wifi)?;
+
+    let resources = RESOURCES
+        .try_init(StackResources::new())
+        .ok_or(StartNetworkError::AlreadyStarted)?;
+    let seed = u64::from(esp_hal::rng::Rng::new().random()) << 32
+        | u64::from(esp_hal::rng::Rng::new().random());
+    let (stack, runner) = embassy_net::new(
+        interfaces.sta,
+        embassy_net::Config::dhcpv4(Default::default()),
+        resources,
+        seed,
+    );
+
+    light_sleep::keep_awake_for_radio();
+    spawner.spawn(keep_connected(controller, credentials))?;
+    spawner.spawn(run_stack(runner))?;
+    spawner.spawn(respond_to_mdns(stack))?;
+    Ok(stack)
+}
+
+#[embassy_executor::task]
+async fn keep_connected(mut controller: WifiController<'static>, credentials: WifiCredentials) {
+    let config = ModeConfig::Client(
+        ClientConfig::default()
+            .with_ssid(credentials.ssid.clone())
+            .with_password(credentials.password.clone()),
+    );
+
+    loop {
+        if esp_radio::wifi::sta_state() == WifiStaState::Connected {
+            controller.wait_for_event(WifiEvent::StaDisconnected).await;
+            info!("WiFi disconnected");
+            event_bus::publish(Event::Network(NetworkEvent::Disconnected));
+            status_bar::set_connected(false);
+            Timer::after_secs(RECONNECT_DELAY_SECONDS).await;
+        }
+
+        if !matches!(controller.is_started(), Ok(true)) {
+            if let Err(error) = controller.set_config(&config) {
+                warn!("Failed to configure WiFi: {:?}", error);
+                return;
+            }
+
+            if let Err(error) = controller.start_async().await {
+                warn!("Failed to start WiFi: {:?}", error);
+                return;
+            }
+
+            // The radio sleeps between the beacons of the access point while there is nothing to
+            // send, which is most of the time
+            if let Err(error) = controller.set_power_saving(PowerSaveMode::Maximum) {
+                warn!("Failed to turn on WiFi power saving: {:?}", error);
+            }
+        }
+
+        info!("Connecting to WiFi network {}", credentials.ssid.as_str());
+        match controller.connect_async().await {
+            Ok(()) => {
+                info!("WiFi connected");
+                event_bus::publish(Event::Network(NetworkEvent::Connected));
+                status_bar::set_connected(true);
+            }
+            Err(error) => {
+                warn!("Failed to connect to WiFi: {:?}", error);
+                Timer::after_secs(RECONNECT_DELAY_SECONDS).await;
+            }
+        }
+    }
+}
+
+#[embassy_executor::task]
+async fn run_stack(mut runner: Runner<'static, WifiDevice<'static>>) {
+    runner.run().await;
+}
+
+#[embassy_executor::task]
+async fn respond_to_mdns(stack: Stack<'static>) {
+    // Only the host name is answered, as there are no servers on the reader to announce yet
+    mdns::run(stack, &[]).await;
+}
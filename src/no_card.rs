@@ -0,0 +1,121 @@
+//! Without an SD card there are no books, profiles or themes. Instead of leaving the reader at a dead
+//! end, this menu asks for a card and offers the help text and a sample book that are built into the
+//! firmware. Settings changed here are kept in flash. Hyphenation patterns are on the card as well,
+//! so the texts are laid out without.
+
+use defmt::{error, info};
+use embedded_graphics::Drawable;
+use embedded_graphics::mono_font::MonoTextStyle;
+use embedded_graphics::mono_font::ascii::FONT_10X20;
+use embedded_graphics::pixelcolor::BinaryColor;
+use embedded_graphics::prelude::{OriginDimensions, Point, Size};
+use embedded_graphics::primitives::Rectangle;
+use embedded_graphics::text::Text;
+
+use crate::SharedDisplay;
+use crate::eink_display::Frame;
+use crate::input::Analog;
+use crate::pagination::NoHyphenation;
+use crate::reader;
+use crate::settings::{Settings, SettingsStorage};
+use crate::widget::{ListView, Response};
+
+const HELP: &str = include_str!("../assets/help.txt");
+/// Small enough to not take up much flash, but long enough to try turning pages
+const SAMPLE_BOOK: &str = include_str!("../assets/sample_book.txt");
+/// Where the menu starts below the prompt for a card
+const LIST_TOP: i32 = 105;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+enum Entry {
+    Help,
+    SampleBook,
+    DarkMode,
+}
+
+const ENTRIES: [Entry; 3] = [Entry::Help, Entry::SampleBook, Entry::DarkMode];
+
+/// Shows the menu until the reader backs out
+pub(crate) async fn show(
+    settings: &mut Settings,
+    storage: Option<&SettingsStorage>,
+    analog: &mut Analog<'_>,
+    display: &SharedDisplay,
+    frame: &mut Frame,
+) {
+    let mut list = ListView::new(ENTRIES.len(), 0);
+    // Where to continue the sample book as long as the menu is open
+    let mut position = 0;
+
+    loop {
+        draw_menu(settings, frame, &mut list);
+        if let Err(error) = display.animate(frame).await {
+            error!("Failed to display menu: {:?}", defmt::Debug2Format(&error));
+        }
+
+        let entry = loop {
+            match list.handle(analog.wait_for_press().await) {
+                Response::Ignored => {}
+                Response::Changed => break None,
+                Response::Confirmed => break Some(ENTRIES[list.selected()]),
+                Response::Cancelled => return,
+            }
+        };
+
+        match entry {
+            None => {}
+            Some(Entry::Help) => {
+                reader::read(HELP, 0, &NoHyphenation, None, analog, display, frame).await;
+            }
+            Some(Entry::SampleBook) => {
+                position = reader::read(
+                    SAMPLE_BOOK,
+                    position,
+                    &NoHyphenation,
+                    None,
+                    analog,
+                    display,
+                    frame,
+                )
+                .await;
+            }
+            Some(Entry::DarkMode) => {
+                settings.dark_mode = !settings.dark_mode;
+                info!("Dark mode {}", settings.dark_mode);
+                display.lock().await.set_inverted(settings.dark_mode);
+
+                if let Some(storage) = storage
+                    && let Err(error) = storage.save(settings)
+                {
+                    error!("Failed to save settings: {:?}", defmt::Debug2Format(&error));
+                }
+            }
+        }
+    }
+}
+
+fn draw_menu(settings: &Settings, frame: &mut Frame, list: &mut ListView) {
+    // White
+    frame.fill(0xFF);
+
+    let style = MonoTextStyle::new(&FONT_10X20, BinaryColor::On);
+    let prompt = "No SD card found\n\nInsert a card with your books\nand restart the reader.";
+    if let Err(error) = Text::new(prompt, Point::new(10, 30), style).draw(frame) {
+        error!("Failed to draw menu: {:?}", error);
+    }
+
+    let size = frame.size();
+    let area = Rectangle::new(
+        Point::new(0, LIST_TOP),
+        Size::new(size.width, size.height - LIST_TOP as u32),
+    );
+    let label = |index: usize| match ENTRIES[index] {
+        Entry::Help => "Getting started",
+        Entry::SampleBook => "Sample book",
+        Entry::DarkMode if settings.dark_mode => "Dark mode: on",
+        Entry::DarkMode => "Dark mode: off",
+    };
+    if let Err(error) = list.draw(frame, &area, label) {
+        error!("Failed to draw menu: {:?}", error);
+    }
+}
@@ -0,0 +1,265 @@
+//! Lays out and draws pages of text with the body font and margin of the theme. Where lines and
+//! pages break is worked out by [`Pages`] in the core crate, which has the rules and the tests.
+//!
+//! Paragraphs that start with a right-to-left letter are aligned to the right and lines with
+//! right-to-left text are reordered for display by [`bidi`] in the core crate.
+//!
+//! Characters the body font doesn't have are drawn from a [`GlyphCache`] if there is one, which is
+//! why lines are measured in pixels rather than characters.
+//!
+//! Laying out and drawing pages doesn't use the heap, so a fragmented heap can't stop the reader.
+//! Lines are built in fixed buffers sized in [`memory`].
+
+use core::ops::Range;
+
+use embedded_graphics::mono_font::{MonoFont, MonoTextStyle};
+use embedded_graphics::pixelcolor::BinaryColor;
+use embedded_graphics::prelude::{DrawTarget, OriginDimensions, Point, Size};
+use embedded_graphics::primitives::Rectangle;
+use embedded_graphics::text::{Baseline, Text};
+use embedded_graphics::{Drawable, Pixel};
+
+use crustpoint_core::bidi::{self, Direction};
+use crustpoint_core::pagination::{Metrics, Pages};
+
+use crate::eink_display::{DrawError, Frame};
+use crate::glyphs::GlyphCache;
+use crate::memory;
+use crate::status_bar;
+use crate::theme::Theme;
+
+/// Space between two lines of text in pixels
+const LINE_SPACING: u32 = 4;
+/// Kept free at the bottom for the footer
+const FOOTER_HEIGHT: u32 = 30;
+
+/// Text of a line as it is drawn, including the hyphen
+type LineText = heapless::String<{ memory::MAXIMUM_LINE_SIZE }>;
+
+pub(crate) use crustpoint_core::pagination::{Breaks, Hyphenator, NoHyphenation};
+
+/// Where and how large the text of a page is drawn
+pub(crate) struct Layout<'a> {
+    font: &'static MonoFont<'static>,
+    /// Pixels are drawn as blocks of this size
+    scale: u32,
+    area: Rectangle,
+    hyphenator: &'a dyn Hyphenator,
+    glyphs: Option<&'a GlyphCache>,
+}
+
+impl<'a> Layout<'a> {
+    pub(crate) fn new(theme: &Theme, hyphenator: &'a dyn Hyphenator, frame: &Frame) -> Self {
+        let margin = u32::from(theme.margin);
+        let size = frame.size();
+        // Kept free at the top for the status bar
+        let top = margin + status_bar::HEIGHT;
+        let area = Rectangle::new(
+            // Can't truncate as the margin is limited by the theme
+            Point::new(margin as i32, top as i32),
+            Size::new(
+                size.width.saturating_sub(2 * margin),
+                size.height.saturating_sub(top + margin + FOOTER_HEIGHT),
+            ),
+        );
+
+        Self {
+            font: theme.body_font.mono_font(),
+            scale: theme.body_font.scale(),
+            area,
+            hyphenator,
+            glyphs: None,
+        }
+    }
+
+    /// Draws the characters the body font doesn't have with the glyphs. The glyphs of a page need
+    /// to be loaded before the page is drawn.
+    pub(crate) fn with_glyphs(mut self, glyphs: &'a GlyphCache) -> Self {
+        self.glyphs = Some(glyphs);
+        self
+    }
+
+    /// Whether the character is drawn from the glyph cache instead of the body font
+    fn is_glyph(&self, character: char) -> bool {
+        !character.is_ascii() && self.glyphs.is_some_and(|glyphs| glyphs.contains(character))
+    }
+
+    fn width(&self, text: &str) -> u32 {
+        text.chars().map(|character| self.advance(character)).sum()
+    }
+
+    /// Width of a line in unscaled pixels
+    fn line_width(&self) -> u32 {
+        self.area.size.width / self.scale
+    }
+
+    fn line_height(&self) -> u32 {
+        let height = match self.glyphs {
+            Some(glyphs) => self.font.character_size.height.max(glyphs.size().height),
+            None => self.font.character_size.height,
+        };
+
+        height * self.scale + LINE_SPACING
+    }
+
+    fn rows(&self) -> usize {
+        // Can't truncate as usize is 32 bits
+        (self.area.size.height / self.line_height()) as usize
+    }
+
+    fn pages(&self) -> Pages<'_> {
+        Pages::new(self, self.hyphenator, self.line_width(), self.rows())
+    }
+
+    /// Byte offset into the text where the page starting at the offset ends
+    pub(crate) fn page_end(&self, text: &str, start: usize) -> usize {
+        self.pages().page_end(text, start)
+    }
+
+    /// Start of the page containing the position and its number counting from 1. Lays out the
+    /// pages from the start of the text, so turning back from a page uses the position before it.
+    pub(crate) fn page_at(&self, text: &str, position: usize) -> (usize, usize) {
+        self.pages().page_at(text, position)
+    }
+
+    /// Draws the lines of the page into the frame
+    pub(crate) fn draw_page(
+        &self,
+        frame: &mut Frame,
+        text: &str,
+        page: Range<usize>,
+    ) -> Result<(), DrawError> {
+        let mut position = self.area.top_left;
+        let mut start = page.start;
+        let paragraph_start = text[..start].rfind('\n').map_or(0, |index| index + 1);
+        let mut direction = bidi::paragraph_direction(&text[paragraph_start..]);
+        let pages = self.pages();
+        while start < page.end {
+            // Laid out like the page was, so the last line keeps its hyphen
+            let line = pages.line(&text[start..]);
+            let content = &text[start..start + line.length];
+            let mut hyphenated = LineText::new();
+            let content = if line.is_hyphenated {
+                // Always fits, as lines leave room for the hyphen
+                let _ = hyphenated.push_str(content);
+                let _ = hyphenated.push('-');
+                &hyphenated
+            } else {
+                content
+            };
+            let reordered;
+            let content = if direction == Direction::RightToLeft || bidi::has_right_to_left(content)
+            {
+                reordered = bidi::reorder(content, direction);
+                &reordered
+            } else {
+                content
+            };
+
+            position.x = match direction {
+                Direction::LeftToRight => self.area.top_left.x,
+                // Can't truncate as lines are small
+                Direction::RightToLeft => {
+                    self.area.top_left.x
+                        + self
+                            .area
+                            .size
+                            .width
+                            .saturating_sub(self.width(content) * self.scale)
+                            as i32
+                }
+            };
+
+            if self.scale == 1 {
+                self.draw_line(frame, content, position)?;
+            } else {
+                let mut target = Scaled {
+                    frame: &mut *frame,
+                    scale: self.scale,
+                };
+                // Can't truncate as the scale is small
+                self.draw_line(&mut target, content, position / self.scale as i32)?;
+            }
+
+            // Can't truncate as lines are small
+            position.y += self.line_height() as i32;
+            if text[start + line.length..].starts_with('\n') {
+                direction = bidi::paragraph_direction(&text[start + line.next..]);
+            }
+
+            start += line.next;
+        }
+
+        Ok(())
+    }
+
+    /// Draws the text with the body font and the characters it doesn't have with the glyphs
+    fn draw_line<D>(&self, target: &mut D, text: &str, mut position: Point) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = BinaryColor>,
+    {
+        let style = MonoTextStyle::new(self.font, BinaryColor::On);
+        let Some(glyphs) = self.glyphs else {
+            Text::with_baseline(text, position, style, Baseline::Top).draw(target)?;
+            return Ok(());
+        };
+
+        // Text between the glyphs is drawn in one go
+        let mut run_start = 0;
+        for (index, character) in text.char_indices() {
+            if !self.is_glyph(character) {
+                continue;
+            }
+
+            let run = &text[run_start..index];
+            position = Text::with_baseline(run, position, style, Baseline::Top).draw(target)?;
+            glyphs.draw(character, target, position)?;
+            // Can't truncate as glyphs are small
+            position.x += glyphs.size().width as i32;
+            run_start = index + character.len_utf8();
+        }
+
+        Text::with_baseline(&text[run_start..], position, style, Baseline::Top).draw(target)?;
+        Ok(())
+    }
+}
+
+impl Metrics for Layout<'_> {
+    /// Horizontal distance to the next character in unscaled pixels
+    fn advance(&self, character: char) -> u32 {
+        match self.glyphs {
+            Some(glyphs) if self.is_glyph(character) => glyphs.size().width,
+            _ => self.font.character_size.width + self.font.character_spacing,
+        }
+    }
+}
+
+/// Draws into the frame with each pixel as a square block of pixels
+struct Scaled<'a> {
+    frame: &'a mut Frame,
+    scale: u32,
+}
+
+impl OriginDimensions for Scaled<'_> {
+    fn size(&self) -> Size {
+        self.frame.size() / self.scale
+    }
+}
+
+impl DrawTarget for Scaled<'_> {
+    type Color = BinaryColor;
+    type Error = DrawError;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        for Pixel(point, color) in pixels {
+            // Can't truncate as the scale is small
+            let block = Rectangle::new(point * self.scale as i32, Size::new_equal(self.scale));
+            self.frame.fill_solid(&block, color)?;
+        }
+
+        Ok(())
+    }
+}
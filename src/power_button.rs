@@ -0,0 +1,61 @@
+//! Tells a short press of the power button from a long one. A short press turns the display off and
+//! on again, a long press turns the reader off. The thresholds can be set in the config file.
+//!
+//! The task handling the button starts before the config is read, so the thresholds are kept in
+//! statics it reads for every press.
+
+use embassy_futures::select::{Either, select};
+use embassy_time::{Duration, Instant, Timer};
+use esp_hal::gpio::Input;
+use portable_atomic::{AtomicU32, Ordering};
+
+const DEFAULT_LONG_PRESS_MILLISECONDS: u32 = 2000;
+/// Shorter presses are taken as a bounce of the contact
+const DEFAULT_MINIMUM_PRESS_MILLISECONDS: u32 = 50;
+
+static LONG_PRESS_MILLISECONDS: AtomicU32 = AtomicU32::new(DEFAULT_LONG_PRESS_MILLISECONDS);
+static MINIMUM_PRESS_MILLISECONDS: AtomicU32 = AtomicU32::new(DEFAULT_MINIMUM_PRESS_MILLISECONDS);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub(crate) enum Press {
+    /// Released before the long press threshold
+    Short,
+    /// Held for the long press threshold. The button can still be held.
+    Long,
+}
+
+/// Replaces the thresholds of the presses that follow. None keeps the current one.
+pub(crate) fn set_thresholds(long_press: Option<Duration>, minimum_press: Option<Duration>) {
+    // Can't truncate as the config only holds a few seconds
+    if let Some(long_press) = long_press {
+        LONG_PRESS_MILLISECONDS.store(long_press.as_millis() as u32, Ordering::Relaxed);
+    }
+    // Can't truncate as the config only holds a few seconds
+    if let Some(minimum_press) = minimum_press {
+        MINIMUM_PRESS_MILLISECONDS.store(minimum_press.as_millis() as u32, Ordering::Relaxed);
+    }
+}
+
+/// Waits for the next press. A long press is returned as soon as it reaches the threshold, so the
+/// reader knows when to let go.
+pub(crate) async fn wait_for_press(button: &mut Input<'_>) -> Press {
+    loop {
+        // Low = pressed, High = released. Only presses that start while waiting count, not the one
+        // that woke the reader or a long press that is still held.
+        button.wait_for_high().await;
+        button.wait_for_low().await;
+        let long_press = LONG_PRESS_MILLISECONDS.load(Ordering::Relaxed);
+        let minimum_press = MINIMUM_PRESS_MILLISECONDS.load(Ordering::Relaxed);
+        let start = Instant::now();
+
+        let release = button.wait_for_high();
+        let timeout = Timer::after_millis(u64::from(long_press));
+        if let Either::Second(()) = select(release, timeout).await {
+            return Press::Long;
+        }
+
+        if start.elapsed() >= Duration::from_millis(u64::from(minimum_press)) {
+            return Press::Short;
+        }
+    }
+}
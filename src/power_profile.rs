@@ -0,0 +1,73 @@
+//! The reader spends most of its time waiting for a button or for the panel to finish a refresh,
+//! so the CPU runs at half its clock unless something keeps the reader waiting on the CPU, like
+//! laying out a page or decompressing. That work holds a [`Boost`] for the full clock.
+//!
+//! Switching only changes the divider of the CPU clock from the PLL. The APB clock of the
+//! peripherals stays at 80 MHz at both speeds and the timers count the crystal, so the SPI, the
+//! ADC and embassy time don't notice. The busy waits of the ROM functions, e.g. in the flash
+//! driver, are calibrated for the full clock and only get longer at the lower one.
+
+use defmt::info;
+use esp_hal::peripherals::SYSTEM;
+use portable_atomic::{AtomicBool, AtomicU8, Ordering};
+
+/// Work that holds a boost right now. The clock drops when the last one is dropped.
+static BOOSTS: AtomicU8 = AtomicU8::new(0);
+/// The clock stays at full speed until start up is done, see [`start`]
+static IS_STARTED: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+enum Clock {
+    /// 80 MHz
+    Low,
+    /// 160 MHz, what esp-hal sets up at start
+    High,
+}
+
+/// Keeps the CPU at the full clock while it is held
+#[must_use = "the clock drops again when the boost is dropped"]
+pub(crate) struct Boost(());
+
+impl Drop for Boost {
+    fn drop(&mut self) {
+        critical_section::with(|_| {
+            if BOOSTS.fetch_sub(1, Ordering::Relaxed) == 1 && IS_STARTED.load(Ordering::Relaxed) {
+                set_clock(Clock::Low);
+            }
+        });
+    }
+}
+
+/// Runs the CPU at the full clock until the boost is dropped
+pub(crate) fn boost() -> Boost {
+    critical_section::with(|_| {
+        if BOOSTS.fetch_add(1, Ordering::Relaxed) == 0 {
+            set_clock(Clock::High);
+        }
+    });
+    Boost(())
+}
+
+/// Drops to the lower clock when nothing is boosted. Called once start up is done, as that is what
+/// the reader waits for the most.
+pub(crate) fn start() {
+    info!("Lowering CPU clock while idle");
+    critical_section::with(|_| {
+        IS_STARTED.store(true, Ordering::Relaxed);
+        if BOOSTS.load(Ordering::Relaxed) == 0 {
+            set_clock(Clock::Low);
+        }
+    });
+}
+
+fn set_clock(clock: Clock) {
+    // CPUPERIOD_SEL divides the 480 MHz PLL down to 80 MHz with 0 and 160 MHz with 1
+    let period = match clock {
+        Clock::Low => 0,
+        Clock::High => 1,
+    };
+    SYSTEM::regs()
+        .cpu_per_conf()
+        // SAFETY: Both values are valid for the 480 MHz PLL esp-hal sets up for the maximum clock
+        .modify(|_, writer| unsafe { writer.cpuperiod_sel().bits(period) });
+}
@@ -0,0 +1,218 @@
+//! Reader profiles for households sharing one device. Everything that is persisted per reader like
+//! settings, progress, statistics and collections lives in the directory of the profile on the SD
+//! card, e.g. `/PROFILES/ALEX/SETTINGS.TXT`. A profile is created by creating its directory.
+
+use alloc::format;
+use alloc::string::String;
+use core::sync::atomic::Ordering;
+
+use defmt::{error, info};
+use embedded_graphics::Drawable;
+use embedded_graphics::mono_font::MonoTextStyle;
+use embedded_graphics::mono_font::ascii::FONT_10X20;
+use embedded_graphics::pixelcolor::BinaryColor;
+use embedded_graphics::prelude::{OriginDimensions, Point, Size};
+use embedded_graphics::primitives::Rectangle;
+use embedded_graphics::text::Text;
+use portable_atomic::AtomicU8;
+
+use crate::SharedDisplay;
+use crate::eink_display::Frame;
+use crate::input::Analog;
+use crate::sd_card::{SdCard, VolumeError};
+use crate::widget::{ListView, Response};
+
+const DIRECTORY: &str = "/PROFILES";
+/// Used if there is no profile directory, so a single reader doesn't need to set anything up
+const DEFAULT_NAME: &str = "DEFAULT";
+/// embedded_sdmmc only supports FAT short names
+const MAXIMUM_NAME_LENGTH: usize = 8;
+const MAXIMUM_PROFILES: usize = 8;
+/// Where the profile list starts below the question
+const LIST_TOP: i32 = 45;
+
+/// Index of the profile that was active before deep sleep. RTC fast memory keeps its content in
+/// deep sleep, but has random content after power loss, so the index has to be checked.
+#[esp_hal::ram(unstable(rtc_fast, persistent))]
+static ACTIVE_PROFILE: AtomicU8 = AtomicU8::new(0);
+
+pub(crate) type Name = heapless::String<MAXIMUM_NAME_LENGTH>;
+
+/// Identifies a book by a hash of its path. Book paths can be longer than the FAT short names
+/// embedded_sdmmc supports, so the files that belong to a book are named after this.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub(crate) struct BookId(pub(crate) u32);
+
+impl BookId {
+    pub(crate) fn of(book_path: &str) -> Self {
+        Self(hash(book_path))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, defmt::Format)]
+pub(crate) struct Profile {
+    name: Name,
+}
+
+impl Profile {
+    fn default_profile() -> Self {
+        let mut name = Name::new();
+        // Can't fail as the default name is short enough
+        let _ = name.push_str(DEFAULT_NAME);
+        Self { name }
+    }
+
+    pub(crate) fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub(crate) fn directory(&self) -> String {
+        format!("{DIRECTORY}/{}", self.name)
+    }
+
+    /// Absolute path of a file that belongs to the profile
+    pub(crate) fn path(&self, file_name: &str) -> String {
+        format!("{DIRECTORY}/{}/{file_name}", self.name)
+    }
+
+    /// Absolute path of a file in a directory of the profile that belongs to a book
+    pub(crate) fn book_path(&self, directory: &str, book_path: &str) -> String {
+        self.book_file(directory, BookId::of(book_path))
+    }
+
+    /// Like [`Profile::book_path`] for when only the ID of the book is known
+    pub(crate) fn book_file(&self, directory: &str, book: BookId) -> String {
+        self.path(&format!("{directory}/{:08X}.TXT", book.0))
+    }
+}
+
+pub(crate) struct Profiles {
+    profiles: heapless::Vec<Profile, MAXIMUM_PROFILES>,
+    active: usize,
+}
+
+impl Profiles {
+    /// Only the default profile for when there is no SD card
+    pub(crate) fn single() -> Self {
+        let mut profiles = heapless::Vec::new();
+        // Can't fail as the capacity is larger than 0
+        let _ = profiles.push(Profile::default_profile());
+        Self {
+            profiles,
+            active: 0,
+        }
+    }
+
+    /// Lists the profile directories and restores which one was active before deep sleep
+    pub(crate) async fn load(sd_card: &mut SdCard) -> Result<Self, VolumeError> {
+        let entries = match sd_card.list_directory(DIRECTORY).await {
+            Ok(entries) => entries,
+            Err(embedded_sdmmc::Error::NotFound) => return Ok(Self::single()),
+            Err(error) => return Err(error),
+        };
+
+        let mut profiles = heapless::Vec::new();
+        for entry in entries
+            .iter()
+            .filter(|entry| entry.attributes.is_directory())
+        {
+            let Ok(base_name) = core::str::from_utf8(entry.name.base_name()) else {
+                continue;
+            };
+
+            // Skip "." and ".."
+            if base_name.starts_with('.') {
+                continue;
+            }
+
+            let mut name = Name::new();
+            if name.write_str(base_name).is_err() {
+                continue;
+            }
+
+            if profiles.push(Profile { name }).is_err() {
+                error!("More than {} profiles, ignoring the rest", MAXIMUM_PROFILES);
+                break;
+            }
+        }
+
+        if profiles.is_empty() {
+            return Ok(Self::single());
+        }
+
+        let active = usize::from(ACTIVE_PROFILE.load(Ordering::Relaxed));
+        let active = if active < profiles.len() { active } else { 0 };
+        Ok(Self { profiles, active })
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.profiles.len()
+    }
+
+    pub(crate) fn active(&self) -> &Profile {
+        &self.profiles[self.active]
+    }
+
+    fn set_active(&mut self, index: usize) {
+        self.active = index;
+        // Can't truncate as there are at most 8 profiles
+        ACTIVE_PROFILE.store(index as u8, Ordering::Relaxed);
+    }
+
+    /// Shows the profiles and lets the reader pick one with the buttons
+    pub(crate) async fn select(
+        &mut self,
+        analog: &mut Analog<'_>,
+        display: &SharedDisplay,
+        frame: &mut Frame,
+    ) {
+        let mut list = ListView::new(self.len(), self.active);
+        let selected = 'picker: loop {
+            self.draw_picker(frame, &mut list);
+            if let Err(error) = display.animate(frame).await {
+                error!(
+                    "Failed to display profile picker: {:?}",
+                    defmt::Debug2Format(&error)
+                );
+            }
+
+            loop {
+                match list.handle(analog.wait_for_press().await) {
+                    Response::Ignored => {}
+                    Response::Changed => break,
+                    Response::Confirmed => break 'picker list.selected(),
+                    Response::Cancelled => break 'picker self.active,
+                }
+            }
+        };
+
+        self.set_active(selected);
+        info!("Selected profile {}", self.active().name());
+    }
+
+    fn draw_picker(&self, frame: &mut Frame, list: &mut ListView) {
+        // White
+        frame.fill(0xFF);
+
+        let style = MonoTextStyle::new(&FONT_10X20, BinaryColor::On);
+        if let Err(error) = Text::new("Who is reading?", Point::new(10, 30), style).draw(frame) {
+            error!("Failed to draw profile picker: {:?}", error);
+        }
+
+        let size = frame.size();
+        let area = Rectangle::new(
+            Point::new(0, LIST_TOP),
+            Size::new(size.width, size.height - LIST_TOP as u32),
+        );
+        if let Err(error) = list.draw(frame, &area, |index| self.profiles[index].name()) {
+            error!("Failed to draw profile picker: {:?}", error);
+        }
+    }
+}
+
+/// 32-bit FNV-1a which is good enough to tell the books on a card apart
+fn hash(text: &str) -> u32 {
+    text.bytes().fold(0x811C_9DC5, |hash, byte| {
+        (hash ^ u32::from(byte)).wrapping_mul(0x0100_0193)
+    })
+}
@@ -0,0 +1,144 @@
+//! Reading progress per book and profile. For readers who switch between the audiobook and the
+//! ebook, the position in the audiobook is kept alongside the reading position so it can be
+//! adjusted on the device and synced with the rest of the progress.
+
+use alloc::format;
+use alloc::string::String;
+
+use crate::journal;
+use crate::profile::{BookId, Profile};
+use crate::sd_card::{ReadFileError, ReadPriority, SdCard, VolumeError, WriteFileError};
+
+/// Directory within the profile directory
+const DIRECTORY: &str = "PROGRESS";
+/// Way more than the progress needs, but keeps a broken file from taking up all memory
+const MAXIMUM_FILE_SIZE: u32 = 1024;
+
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum SaveProgressError {
+    #[error("Failed to create progress directory")]
+    CreateDirectory(VolumeError),
+    #[error("Failed to write progress file")]
+    Write(#[from] WriteFileError),
+}
+
+/// Time into the audiobook in seconds
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, defmt::Format)]
+pub(crate) struct AudiobookPosition(pub(crate) u32);
+
+impl AudiobookPosition {
+    /// Moves the position forward or, with a negative offset, back without going below the start
+    pub(crate) fn adjust(self, seconds: i32) -> Self {
+        Self(self.0.saturating_add_signed(seconds))
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, defmt::Format)]
+pub(crate) struct Progress {
+    /// Offset into the text content of the book
+    pub(crate) position: u32,
+    /// None if the reader doesn't listen to the audiobook of this book
+    pub(crate) audiobook: Option<AudiobookPosition>,
+    /// Length of the text content to tell how far into the book the position is. 0 if the book
+    /// was not opened since it was kept.
+    pub(crate) length: u32,
+}
+
+impl Progress {
+    /// Share of the book that was read from 0 to 1, if the length is known
+    pub(crate) fn percentage(&self) -> Option<f32> {
+        // Loses precision only for books with more than 16 million characters
+        (self.length > 0).then(|| (self.position as f32 / self.length as f32).min(1.0))
+    }
+
+    /// Progress of a book that was not opened yet is the start
+    pub(crate) async fn load(
+        sd_card: &mut SdCard,
+        profile: &Profile,
+        book_path: &str,
+    ) -> Result<Self, ReadFileError> {
+        let content = match sd_card
+            .read_file(
+                &profile.book_path(DIRECTORY, book_path),
+                MAXIMUM_FILE_SIZE,
+                ReadPriority::Interactive,
+            )
+            .await
+        {
+            Ok(content) => content,
+            Err(ReadFileError::Open(error)) if error.is_not_found() => return Ok(Self::default()),
+            Err(error) => return Err(error),
+        };
+
+        Ok(Self::parse(&String::from_utf8_lossy(&content)))
+    }
+
+    pub(crate) async fn save(
+        &self,
+        sd_card: &mut SdCard,
+        profile: &Profile,
+        book_path: &str,
+    ) -> Result<(), SaveProgressError> {
+        self.save_book(sd_card, profile, BookId::of(book_path))
+            .await
+    }
+
+    /// Like [`Progress::save`] for when only the ID of the book is known, e.g. from the journal.
+    /// The saved progress no longer needs to be recovered after a crash.
+    pub(crate) async fn save_book(
+        &self,
+        sd_card: &mut SdCard,
+        profile: &Profile,
+        book: BookId,
+    ) -> Result<(), SaveProgressError> {
+        sd_card
+            .create_directories(&profile.path(DIRECTORY))
+            .await
+            .map_err(SaveProgressError::CreateDirectory)?;
+
+        sd_card
+            .write_file(
+                &profile.book_file(DIRECTORY, book),
+                self.serialize().as_bytes(),
+            )
+            .await?;
+
+        journal::forget(book, self);
+        Ok(())
+    }
+
+    /// Unknown keys and invalid values are skipped to be able to read files from other versions
+    fn parse(content: &str) -> Self {
+        let mut progress = Self::default();
+        for line in content.lines() {
+            let Some((key, value)) = line.split_once('=') else {
+                continue;
+            };
+
+            let Ok(value) = value.trim().parse() else {
+                continue;
+            };
+
+            match key.trim() {
+                "position" => progress.position = value,
+                "audiobook" => progress.audiobook = Some(AudiobookPosition(value)),
+                "length" => progress.length = value,
+                _ => {}
+            }
+        }
+
+        progress
+    }
+
+    fn serialize(&self) -> String {
+        let mut content = format!("position={}\n", self.position);
+        if let Some(AudiobookPosition(seconds)) = self.audiobook {
+            content.push_str(&format!("audiobook={seconds}\n"));
+        }
+        if self.length > 0 {
+            content.push_str(&format!("length={}\n", self.length));
+        }
+
+        content
+    }
+}
@@ -0,0 +1,189 @@
+//! Sets up the WiFi network from a phone without recompiling or a configuration file. While the
+//! provisioning screen is open, the reader advertises over Bluetooth LE with a GATT service that
+//! takes the SSID and the password. Any generic BLE app like nRF Connect can write them:
+//!
+//! 1. Write the SSID as UTF-8 to the SSID characteristic
+//! 2. Write the password as UTF-8 to the password characteristic, nothing for open networks
+//! 3. Write 1 to the save characteristic
+//!
+//! The connection is not paired, so the password is sent without encryption. Bluetooth is only on
+//! while the screen is open to keep that window short.
+
+use alloc::string::String;
+
+use defmt::{info, warn};
+use embassy_futures::select::{Either, select};
+use esp_hal::peripherals::BT;
+use esp_radio::ble::controller::BleConnector;
+use trouble_host::prelude::*;
+
+use crate::config::WifiCredentials;
+use crate::wifi::{MAXIMUM_PASSWORD_LENGTH, MAXIMUM_SSID_LENGTH};
+
+/// Name the reader advertises with
+const NAME: &str = "Crustpoint";
+const CONNECTIONS_MAX: usize = 1;
+/// Signaling and attribute protocol channels
+const L2CAP_CHANNELS_MAX: usize = 2;
+/// Commands the controller can queue
+const COMMAND_SLOTS: usize = 20;
+/// Random static address, which needs the two highest bits set
+const ADDRESS: [u8; 6] = [0x43, 0x52, 0x55, 0x53, 0x54, 0xC0];
+
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum ProvisioningError {
+    #[error("Failed to start Bluetooth")]
+    Controller(esp_radio::ble::InvalidConfigError),
+    #[error("Failed to set up GATT server")]
+    Server,
+    #[error("Bluetooth host failed")]
+    Host,
+}
+
+#[gatt_server]
+struct Server {
+    provisioning: ProvisioningService,
+}
+
+#[gatt_service(uuid = "6e400001-b5a3-f393-e0a9-c7750000cb01")]
+struct ProvisioningService {
+    #[characteristic(uuid = "6e400002-b5a3-f393-e0a9-c7750000cb01", write)]
+    ssid: [u8; MAXIMUM_SSID_LENGTH],
+    #[characteristic(uuid = "6e400003-b5a3-f393-e0a9-c7750000cb01", write)]
+    password: [u8; MAXIMUM_PASSWORD_LENGTH],
+    #[characteristic(uuid = "6e400004-b5a3-f393-e0a9-c7750000cb01", write)]
+    save: u8,
+}
+
+/// Advertises until a phone connects and saves a network, which is returned
+pub(crate) async fn receive(
+    radio: &esp_radio::Controller<'_>,
+    bluetooth: BT<'_>,
+) -> Result<WifiCredentials, ProvisioningError> {
+    let connector = BleConnector::new(radio, bluetooth, Default::default())
+        .map_err(ProvisioningError::Controller)?;
+    let controller: ExternalController<_, COMMAND_SLOTS> = ExternalController::new(connector);
+
+    let mut resources: HostResources<DefaultPacketPool, CONNECTIONS_MAX, L2CAP_CHANNELS_MAX> =
+        HostResources::new();
+    let stack =
+        trouble_host::new(controller, &mut resources).set_random_address(Address::random(ADDRESS));
+    let Host {
+        mut peripheral,
+        mut runner,
+        ..
+    } = stack.build();
+
+    let server = Server::new_with_config(GapConfig::Peripheral(PeripheralConfig {
+        name: NAME,
+        appearance: &appearance::UNKNOWN,
+    }))
+    .map_err(|_| ProvisioningError::Server)?;
+
+    let host = async {
+        loop {
+            if let Err(error) = runner.run().await {
+                warn!("Bluetooth host failed: {:?}", defmt::Debug2Format(&error));
+                return;
+            }
+        }
+    };
+
+    let provisioning = async {
+        loop {
+            let connection = match advertise(&mut peripheral, &server).await {
+                Ok(connection) => connection,
+                Err(error) => {
+                    warn!("Failed to advertise: {:?}", defmt::Debug2Format(&error));
+                    return Err(ProvisioningError::Host);
+                }
+            };
+
+            info!("Provisioning connection established");
+            if let Some(credentials) = handle(&server, &connection).await {
+                return Ok(credentials);
+            }
+        }
+    };
+
+    match select(provisioning, host).await {
+        Either::First(result) => result,
+        Either::Second(()) => Err(ProvisioningError::Host),
+    }
+}
+
+async fn advertise<'values, 'server, C: Controller>(
+    peripheral: &mut Peripheral<'values, C, DefaultPacketPool>,
+    server: &'server Server<'values>,
+) -> Result<GattConnection<'values, 'server, DefaultPacketPool>, BleHostError<C::Error>> {
+    let mut data = [0; 31];
+    let length = AdStructure::encode_slice(
+        &[
+            AdStructure::Flags(LE_GENERAL_DISCOVERABLE | BR_EDR_NOT_SUPPORTED),
+            AdStructure::CompleteLocalName(NAME.as_bytes()),
+        ],
+        &mut data[..],
+    )?;
+
+    let advertiser = peripheral
+        .advertise(
+            &Default::default(),
+            Advertisement::ConnectableScannableUndirected {
+                adv_data: &data[..length],
+                scan_data: &[],
+            },
+        )
+        .await?;
+    let connection = advertiser.accept().await?.with_attribute_server(server)?;
+    Ok(connection)
+}
+
+/// Collects the writes until the network is saved or the phone disconnects
+async fn handle(
+    server: &Server<'_>,
+    connection: &GattConnection<'_, '_, DefaultPacketPool>,
+) -> Option<WifiCredentials> {
+    let service = &server.provisioning;
+    let mut ssid = String::new();
+    let mut password = String::new();
+    loop {
+        let event = match connection.next().await {
+            GattConnectionEvent::Disconnected { reason } => {
+                info!(
+                    "Provisioning connection closed: {:?}",
+                    defmt::Debug2Format(&reason)
+                );
+                return None;
+            }
+            GattConnectionEvent::Gatt { event } => event,
+            _ => continue,
+        };
+
+        let mut is_saved = false;
+        if let GattEvent::Write(write) = &event {
+            let value = String::from_utf8_lossy(write.data());
+            // Apps tend to send the whole value of the characteristic with zeros at the end
+            let value = value.trim_end_matches('\0');
+            if write.handle() == service.ssid.handle {
+                ssid = String::from(value);
+            } else if write.handle() == service.password.handle {
+                password = String::from(value);
+            } else if write.handle() == service.save.handle {
+                is_saved = write.data().first() == Some(&1) && !ssid.is_empty();
+            }
+        }
+
+        match event.accept() {
+            Ok(reply) => reply.send().await,
+            Err(error) => warn!(
+                "Failed to reply to write: {:?}",
+                defmt::Debug2Format(&error)
+            ),
+        }
+
+        if is_saved {
+            info!("Received WiFi network {}", ssid.as_str());
+            return Some(WifiCredentials { ssid, password });
+        }
+    }
+}
@@ -0,0 +1,107 @@
+//! QR codes to hand text like quotes or the address of a server on the reader over to a phone
+
+use alloc::vec;
+
+use embedded_graphics::Drawable;
+use embedded_graphics::mono_font::MonoTextStyle;
+use embedded_graphics::mono_font::ascii::FONT_10X20;
+use embedded_graphics::pixelcolor::BinaryColor;
+use embedded_graphics::prelude::{DrawTarget, OriginDimensions, Point, Size};
+use embedded_graphics::primitives::Rectangle;
+use embedded_graphics::text::{Alignment, Text};
+use qrcodegen_no_heap::{DataTooLong, QrCode, QrCodeEcc, Version};
+
+use crate::eink_display::{DrawError, Frame};
+
+/// The white border around the code that scanners need to find it, in modules
+const QUIET_ZONE: u32 = 4;
+/// Space below the code for the caption on a screen with a code
+const CAPTION_HEIGHT: u32 = 80;
+
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum DrawQrCodeError {
+    #[error("Text does not fit into a QR code")]
+    TooLong(DataTooLong),
+    #[error("Area is too small for the QR code")]
+    AreaTooSmall,
+    #[error("Failed to draw QR code")]
+    Draw(#[from] DrawError),
+}
+
+/// Draws the text as a QR code centered in the area. Modules are scaled up to the largest whole
+/// number of pixels that fits, as scanners struggle with uneven module sizes.
+pub(crate) fn draw(frame: &mut Frame, text: &str, area: &Rectangle) -> Result<(), DrawQrCodeError> {
+    // Both buffers together are almost 8 KiB for the largest version
+    let buffer_length = Version::MAX.buffer_len();
+    let mut temporary = vec![0; buffer_length];
+    let mut output = vec![0; buffer_length];
+
+    // The error correction is raised where that doesn't need a larger version
+    let code = QrCode::encode_text(
+        text,
+        &mut temporary,
+        &mut output,
+        QrCodeEcc::Low,
+        Version::MIN,
+        Version::MAX,
+        None,
+        true,
+    )
+    .map_err(DrawQrCodeError::TooLong)?;
+
+    // Size is between 21 and 177
+    let modules = code.size().unsigned_abs();
+    let scale = area.size.width.min(area.size.height) / (modules + 2 * QUIET_ZONE);
+    if scale == 0 {
+        return Err(DrawQrCodeError::AreaTooSmall);
+    }
+
+    frame.fill_solid(area, BinaryColor::Off)?;
+
+    let length = modules * scale;
+    let center = area.center();
+    // Can't truncate as the length is at most the area size
+    let origin = center - Point::new(length as i32 / 2, length as i32 / 2);
+    let module_size = Size::new_equal(scale);
+
+    for y in 0..code.size() {
+        for x in 0..code.size() {
+            if !code.get_module(x, y) {
+                continue;
+            }
+
+            let top_left = origin + Point::new(x, y) * scale as i32;
+            frame.fill_solid(&Rectangle::new(top_left, module_size), BinaryColor::On)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Draws the text as a code over the whole frame with a caption below. It is meant to be shown with
+/// a full refresh as leftover ghosting makes the code harder to scan. The font of the caption only
+/// covers ASCII.
+pub(crate) fn draw_screen(
+    frame: &mut Frame,
+    text: &str,
+    caption: &str,
+) -> Result<(), DrawQrCodeError> {
+    frame.fill(0xFF);
+
+    let size = frame.size();
+    let code_area = Rectangle::new(
+        Point::zero(),
+        Size::new(size.width, size.height - CAPTION_HEIGHT),
+    );
+    draw(frame, text, &code_area)?;
+
+    let style = MonoTextStyle::new(&FONT_10X20, BinaryColor::On);
+    // Can't truncate as the frame is less than 1000 pixels in each direction
+    let baseline = Point::new(
+        size.width as i32 / 2,
+        (size.height - CAPTION_HEIGHT / 2) as i32,
+    );
+    Text::with_alignment(caption, baseline, style, Alignment::Center).draw(frame)?;
+
+    Ok(())
+}
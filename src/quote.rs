@@ -0,0 +1,36 @@
+//! Sharing a passage of a book without any connection. The selected quote is shown as a QR code
+//! across the screen, so a phone camera can pick it up and share it from there.
+
+use alloc::format;
+use alloc::string::String;
+
+use crate::eink_display::Frame;
+use crate::qr_code::{self, DrawQrCodeError};
+
+/// Text from the selection cursor together with the book it is from
+pub(crate) struct Quote<'a> {
+    pub(crate) text: &'a str,
+    pub(crate) title: &'a str,
+    /// None if the book doesn't name an author
+    pub(crate) author: Option<&'a str>,
+}
+
+impl Quote<'_> {
+    /// What the phone gets when scanning the code
+    fn content(&self) -> String {
+        match self.author {
+            Some(author) => format!(
+                "\u{201C}{}\u{201D}\n\u{2014} {}, {author}",
+                self.text, self.title
+            ),
+            None => format!("\u{201C}{}\u{201D}\n\u{2014} {}", self.text, self.title),
+        }
+    }
+
+    /// Draws the share screen over the whole frame. It is meant to be shown with a full refresh as
+    /// leftover ghosting makes the code harder to scan.
+    pub(crate) fn draw_share(&self, frame: &mut Frame) -> Result<(), DrawQrCodeError> {
+        // The font only covers ASCII, so the caption doesn't repeat the quote or title
+        qr_code::draw_screen(frame, &self.content(), "Scan to share quote")
+    }
+}
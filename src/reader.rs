@@ -0,0 +1,196 @@
+//! Screen for reading text page by page with the buttons. Left and up turn back, right and down
+//! turn forward and back closes the text. In books, confirm opens the menu of the reader.
+//!
+//! Turning pages doesn't use the heap, so it keeps working when the heap is fragmented. Pages that
+//! do allocate while they are laid out are logged as a warning.
+
+use core::fmt::Write;
+use core::ops::Range;
+
+use defmt::{error, warn};
+use embedded_graphics::Drawable;
+use embedded_graphics::mono_font::MonoTextStyle;
+use embedded_graphics::mono_font::ascii::FONT_8X13;
+use embedded_graphics::pixelcolor::BinaryColor;
+use embedded_graphics::prelude::{DrawTarget, OriginDimensions, Point, Size};
+use embedded_graphics::primitives::Rectangle;
+use embedded_graphics::text::{Alignment, Text};
+
+use crate::SharedDisplay;
+use crate::diagnostics;
+use crate::eink_display::{DrawError, Frame};
+use crate::glyphs::GlyphCache;
+use crate::input::{Analog, Button};
+use crate::pagination::{Hyphenator, Layout};
+use crate::power_profile;
+use crate::status_bar;
+use crate::theme::{self, Footer};
+
+/// Distance of the footer baseline from the bottom of the screen
+const FOOTER_BOTTOM: i32 = 15;
+const PROGRESS_HEIGHT: u32 = 4;
+
+/// How the reader left the text
+#[derive(Debug, Clone, PartialEq, Eq, defmt::Format)]
+pub(crate) enum Exit {
+    /// Backed out on the page that starts at the position
+    Closed(usize),
+    /// Asked for the menu on the page that spans the positions
+    Menu(Range<usize>),
+}
+
+/// Shows the text from the page containing the position until the reader backs out. Returns the
+/// start of the page that was shown last to continue from there next time. The pages are laid out
+/// with the active theme each time, so after the font size changed, the text continues on the page
+/// that now contains the position. Characters the font doesn't have are drawn with the glyphs if
+/// there are any.
+pub(crate) async fn read(
+    text: &str,
+    position: usize,
+    hyphenator: &dyn Hyphenator,
+    glyphs: Option<&GlyphCache>,
+    analog: &mut Analog<'_>,
+    display: &SharedDisplay,
+    frame: &mut Frame,
+) -> usize {
+    match show(
+        text, position, false, hyphenator, glyphs, analog, display, frame,
+    )
+    .await
+    {
+        Exit::Closed(start) | Exit::Menu(Range { start, .. }) => start,
+    }
+}
+
+/// Like [`read`] for books, where confirm leaves the text for the menu as well
+pub(crate) async fn read_book(
+    text: &str,
+    position: usize,
+    hyphenator: &dyn Hyphenator,
+    glyphs: Option<&GlyphCache>,
+    analog: &mut Analog<'_>,
+    display: &SharedDisplay,
+    frame: &mut Frame,
+) -> Exit {
+    show(
+        text, position, true, hyphenator, glyphs, analog, display, frame,
+    )
+    .await
+}
+
+async fn show(
+    text: &str,
+    position: usize,
+    has_menu: bool,
+    hyphenator: &dyn Hyphenator,
+    glyphs: Option<&GlyphCache>,
+    analog: &mut Analog<'_>,
+    display: &SharedDisplay,
+    frame: &mut Frame,
+) -> Exit {
+    let theme = theme::active();
+    let mut layout = Layout::new(&theme, hyphenator, frame);
+    if let Some(glyphs) = glyphs {
+        layout = layout.with_glyphs(glyphs);
+    }
+    let (mut start, mut page_number) = layout.page_at(text, position);
+
+    loop {
+        let boost = power_profile::boost();
+        let end = layout.page_end(text, start);
+
+        if let Some(glyphs) = glyphs
+            && let Err(error) = glyphs.load(&text[start..end]).await
+        {
+            error!("Failed to load glyphs: {:?}", defmt::Debug2Format(&error));
+        }
+
+        // Nothing else runs until the next await
+        let allocated = diagnostics::total_allocated();
+        // White
+        frame.fill(0xFF);
+        if let Err(error) = layout.draw_page(frame, text, start..end) {
+            error!("Failed to draw page: {:?}", error);
+        }
+
+        if let Err(error) = draw_footer(frame, theme.footer, page_number, end, text.len()) {
+            error!("Failed to draw footer: {:?}", error);
+        }
+
+        // Can't fail as the end is within the text
+        let progress = u8::try_from(end * 100 / text.len().max(1)).unwrap_or(100);
+        status_bar::set_progress(Some(progress));
+        if let Err(error) = status_bar::current().draw(frame) {
+            error!("Failed to draw status bar: {:?}", error);
+        }
+
+        if diagnostics::total_allocated() != allocated {
+            warn!("Drawing page {} allocated on the heap", page_number);
+        }
+        // The refresh waits for the panel
+        drop(boost);
+
+        if let Err(error) = display.submit(frame).await {
+            error!("Failed to display page: {:?}", defmt::Debug2Format(&error));
+        }
+
+        loop {
+            match analog.wait_for_press().await {
+                Button::Right | Button::Down if end < text.len() => {
+                    start = end;
+                    page_number += 1;
+                    break;
+                }
+                Button::Left | Button::Up if start > 0 => {
+                    // Finding the start of the previous page lays out the text from the start
+                    let _boost = power_profile::boost();
+                    start = layout.page_at(text, start - 1).0;
+                    page_number -= 1;
+                    break;
+                }
+                Button::Back => {
+                    status_bar::set_progress(None);
+                    return Exit::Closed(start);
+                }
+                Button::Confirm if has_menu => {
+                    status_bar::set_progress(None);
+                    return Exit::Menu(start..end);
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Draws the page number or how far into the text the end of the page is
+fn draw_footer(
+    frame: &mut Frame,
+    footer: Footer,
+    page_number: usize,
+    end: usize,
+    length: usize,
+) -> Result<(), DrawError> {
+    let size = frame.size();
+    // Can't truncate as the frame is small
+    let bottom = size.height as i32 - FOOTER_BOTTOM;
+    match footer {
+        Footer::Hidden => {}
+        Footer::PageNumber => {
+            let style = MonoTextStyle::new(&FONT_8X13, BinaryColor::On);
+            // Can't truncate as the frame is small
+            let center = Point::new(size.width as i32 / 2, bottom);
+            let mut number = heapless::String::<20>::new();
+            // Always fits, as a usize has at most 10 digits
+            let _ = write!(number, "{page_number}");
+            Text::with_alignment(&number, center, style, Alignment::Center).draw(frame)?;
+        }
+        Footer::Progress => {
+            // Can't truncate as the frame and the lengths of the texts are small
+            let width = (u64::from(size.width) * end as u64 / length.max(1) as u64) as u32;
+            let bar = Rectangle::new(Point::new(0, bottom), Size::new(width, PROGRESS_HEIGHT));
+            frame.fill_solid(&bar, BinaryColor::On)?;
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,222 @@
+//! Menu that confirm opens over a page of a book. The entries act on the page that was shown, which
+//! is left to the caller as they need the SD card and the book. Entries that act on words of the
+//! page let the reader pick them from a list of the words.
+
+use alloc::format;
+use alloc::vec::Vec;
+use core::ops::Range;
+
+use defmt::error;
+use embedded_graphics::Drawable;
+use embedded_graphics::mono_font::MonoTextStyle;
+use embedded_graphics::mono_font::ascii::FONT_10X20;
+use embedded_graphics::pixelcolor::BinaryColor;
+use embedded_graphics::prelude::{OriginDimensions, Point, Size};
+use embedded_graphics::primitives::Rectangle;
+use embedded_graphics::text::Text;
+
+use crate::SharedDisplay;
+use crate::dictionary;
+use crate::eink_display::Frame;
+use crate::input::{Analog, Button};
+use crate::progress::AudiobookPosition;
+use crate::widget::{ListView, Response};
+
+/// Where the menu starts below the title
+const LIST_TOP: i32 = 45;
+/// Seconds that left and right move the audiobook position
+const SMALL_STEP: i32 = 30;
+/// Seconds that up and down move the audiobook position
+const LARGE_STEP: i32 = 5 * 60;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub(crate) enum Entry {
+    /// Adds a bookmark to the page or removes the bookmarks on it
+    Bookmark,
+    Bookmarks,
+    /// Shows the definition of a word on the page
+    LookUpWord,
+    /// Shows a passage of the page as a QR code
+    ShareQuote,
+    /// Sets where the reader is in the audiobook of the book
+    AudiobookPosition,
+}
+
+const ENTRIES: [Entry; 5] = [
+    Entry::Bookmark,
+    Entry::Bookmarks,
+    Entry::LookUpWord,
+    Entry::ShareQuote,
+    Entry::AudiobookPosition,
+];
+
+/// Lets the reader pick an entry with the buttons. Returns None if the reader backed out to the
+/// page.
+pub(crate) async fn select(
+    is_bookmarked: bool,
+    analog: &mut Analog<'_>,
+    display: &SharedDisplay,
+    frame: &mut Frame,
+) -> Option<Entry> {
+    let mut list = ListView::new(ENTRIES.len(), 0);
+    loop {
+        draw(is_bookmarked, frame, &mut list);
+        if let Err(error) = display.animate(frame).await {
+            error!("Failed to display menu: {:?}", defmt::Debug2Format(&error));
+        }
+
+        loop {
+            match list.handle(analog.wait_for_press().await) {
+                Response::Ignored => {}
+                Response::Changed => break,
+                Response::Confirmed => return Some(ENTRIES[list.selected()]),
+                Response::Cancelled => return None,
+            }
+        }
+    }
+}
+
+/// Lets the reader pick a word of the page from a list. Returns where the word is in the page or
+/// None if the reader backed out.
+pub(crate) async fn pick_word(
+    title: &str,
+    page: &str,
+    analog: &mut Analog<'_>,
+    display: &SharedDisplay,
+    frame: &mut Frame,
+) -> Option<Range<usize>> {
+    let words = words(page);
+    let mut list = ListView::new(words.len(), 0);
+    loop {
+        draw_list(title, frame, &mut list, |index| &page[words[index].clone()]);
+        if let Err(error) = display.animate(frame).await {
+            error!("Failed to display words: {:?}", defmt::Debug2Format(&error));
+        }
+
+        loop {
+            match list.handle(analog.wait_for_press().await) {
+                Response::Ignored => {}
+                Response::Changed => break,
+                Response::Confirmed => return words.get(list.selected()).cloned(),
+                Response::Cancelled => return None,
+            }
+        }
+    }
+}
+
+/// Lets the reader pick the first and the last word of a passage of the page. Returns where the
+/// passage is in the page or None if the reader backed out.
+pub(crate) async fn pick_quote(
+    page: &str,
+    analog: &mut Analog<'_>,
+    display: &SharedDisplay,
+    frame: &mut Frame,
+) -> Option<Range<usize>> {
+    let first = pick_word("First word of the quote", page, analog, display, frame).await?;
+    let last = pick_word("Last word of the quote", page, analog, display, frame).await?;
+    // Picking the last word before the first still makes a passage
+    Some(first.start.min(last.start)..first.end.max(last.end))
+}
+
+/// Lets the reader move the position in the audiobook with the buttons, back with left and up and
+/// forward with right and down. Returns the new position or None if the reader backed out.
+pub(crate) async fn adjust_audiobook(
+    mut position: AudiobookPosition,
+    analog: &mut Analog<'_>,
+    display: &SharedDisplay,
+    frame: &mut Frame,
+) -> Option<AudiobookPosition> {
+    loop {
+        draw_audiobook(position, frame);
+        if let Err(error) = display.animate(frame).await {
+            error!(
+                "Failed to display audiobook position: {:?}",
+                defmt::Debug2Format(&error)
+            );
+        }
+
+        position = match analog.wait_for_press().await {
+            Button::Left => position.adjust(-SMALL_STEP),
+            Button::Right => position.adjust(SMALL_STEP),
+            Button::Up => position.adjust(-LARGE_STEP),
+            Button::Down => position.adjust(LARGE_STEP),
+            Button::Confirm => return Some(position),
+            Button::Back => return None,
+        };
+    }
+}
+
+fn draw_audiobook(AudiobookPosition(seconds): AudiobookPosition, frame: &mut Frame) {
+    // White
+    frame.fill(0xFF);
+
+    let style = MonoTextStyle::new(&FONT_10X20, BinaryColor::On);
+    let text = format!(
+        "Audiobook position\n\n{}:{:02}:{:02}\n\n{}",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60,
+        "Left/right: 30 seconds\nUp/down: 5 minutes\nConfirm: Save"
+    );
+    if let Err(error) = Text::new(&text, Point::new(10, 30), style).draw(frame) {
+        error!("Failed to draw audiobook position: {:?}", error);
+    }
+}
+
+/// Where the words of the page are, without the punctuation around them
+fn words(page: &str) -> Vec<Range<usize>> {
+    let mut words = Vec::new();
+    let mut is_in_word = false;
+    for (index, _) in page.char_indices() {
+        let word = dictionary::word_at(page, index);
+        if let Some(word) = word
+            && !is_in_word
+            && !word.is_empty()
+            // Quotes before the word are not part of it
+            && let Some(offset) = page[index..].find(word)
+        {
+            let start = index + offset;
+            words.push(start..start + word.len());
+        }
+
+        is_in_word = word.is_some();
+    }
+
+    words
+}
+
+fn draw(is_bookmarked: bool, frame: &mut Frame, list: &mut ListView) {
+    let label = |index: usize| match ENTRIES[index] {
+        Entry::Bookmark if is_bookmarked => "Remove bookmark",
+        Entry::Bookmark => "Bookmark page",
+        Entry::Bookmarks => "Bookmarks",
+        Entry::LookUpWord => "Look up word",
+        Entry::ShareQuote => "Share quote",
+        Entry::AudiobookPosition => "Audiobook position",
+    };
+    draw_list("Menu", frame, list, label);
+}
+
+fn draw_list<'a>(
+    title: &str,
+    frame: &mut Frame,
+    list: &mut ListView,
+    label: impl Fn(usize) -> &'a str,
+) {
+    // White
+    frame.fill(0xFF);
+
+    let style = MonoTextStyle::new(&FONT_10X20, BinaryColor::On);
+    if let Err(error) = Text::new(title, Point::new(10, 30), style).draw(frame) {
+        error!("Failed to draw menu: {:?}", error);
+    }
+
+    let size = frame.size();
+    let area = Rectangle::new(
+        Point::new(0, LIST_TOP),
+        Size::new(size.width, size.height - LIST_TOP as u32),
+    );
+    if let Err(error) = list.draw(frame, &area, label) {
+        error!("Failed to draw menu: {:?}", error);
+    }
+}
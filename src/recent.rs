@@ -0,0 +1,109 @@
+//! Books the profile opened, with the most recent first, to sort the library by when the books were
+//! read. There is no clock to keep the time of reading, so only the order is kept. Each line of the
+//! file in the profile directory is a book like `book=/BOOKS/EMMA.EPUB`.
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use crate::profile::Profile;
+use crate::sd_card::{ReadFileError, ReadPriority, SdCard, VolumeError, WriteFileError};
+
+const FILE_NAME: &str = "RECENT.TXT";
+/// Books read longer ago are dropped
+const MAXIMUM_BOOKS: usize = 64;
+const MAXIMUM_FILE_SIZE: u32 = 8 * 1024;
+
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum SaveRecentError {
+    #[error("Failed to create profile directory")]
+    CreateDirectory(VolumeError),
+    #[error("Failed to write recent books file")]
+    Write(#[from] WriteFileError),
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, defmt::Format)]
+pub(crate) struct Recent {
+    /// Paths of the books with the most recent first
+    books: Vec<String>,
+}
+
+impl Recent {
+    /// A profile without the file has not read anything yet
+    pub(crate) async fn load(
+        sd_card: &mut SdCard,
+        profile: &Profile,
+    ) -> Result<Self, ReadFileError> {
+        let content = match sd_card
+            .read_file(
+                &profile.path(FILE_NAME),
+                MAXIMUM_FILE_SIZE,
+                ReadPriority::Interactive,
+            )
+            .await
+        {
+            Ok(content) => content,
+            Err(ReadFileError::Open(error)) if error.is_not_found() => return Ok(Self::default()),
+            Err(error) => return Err(error),
+        };
+
+        Ok(Self::parse(&String::from_utf8_lossy(&content)))
+    }
+
+    pub(crate) async fn save(
+        &self,
+        sd_card: &mut SdCard,
+        profile: &Profile,
+    ) -> Result<(), SaveRecentError> {
+        sd_card
+            .create_directories(&profile.directory())
+            .await
+            .map_err(SaveRecentError::CreateDirectory)?;
+
+        sd_card
+            .write_file(&profile.path(FILE_NAME), self.serialize().as_bytes())
+            .await?;
+
+        Ok(())
+    }
+
+    /// Moves the book to the front
+    pub(crate) fn open(&mut self, book_path: &str) {
+        self.books.retain(|path| path != book_path);
+        self.books.insert(0, String::from(book_path));
+        self.books.truncate(MAXIMUM_BOOKS);
+    }
+
+    /// Paths of the books with the most recent first
+    pub(crate) fn books(&self) -> impl Iterator<Item = &str> {
+        self.books.iter().map(String::as_str)
+    }
+
+    /// How many other books were read since this one. None if it was not read.
+    pub(crate) fn rank(&self, book_path: &str) -> Option<usize> {
+        self.books.iter().position(|path| path == book_path)
+    }
+
+    /// Unknown keys are skipped to be able to read files from other versions
+    fn parse(content: &str) -> Self {
+        let books = content
+            .lines()
+            .filter_map(|line| match line.split_once('=') {
+                Some(("book", path)) if !path.is_empty() => Some(String::from(path)),
+                _ => None,
+            })
+            .take(MAXIMUM_BOOKS)
+            .collect();
+
+        Self { books }
+    }
+
+    fn serialize(&self) -> String {
+        let mut content = String::new();
+        for path in &self.books {
+            content.push_str(&format!("book={path}\n"));
+        }
+
+        content
+    }
+}
@@ -0,0 +1,112 @@
+//! Saves what the panel shows as a BMP image on the SD card, e.g. to show a layout problem when
+//! reporting it. The images are numbered like `/SCREENS/SCR00001.BMP`.
+
+use alloc::format;
+use alloc::string::String;
+
+use embedded_sdmmc::{Mode, RawFile};
+
+use crate::eink_display::Frame;
+use crate::image::bmp;
+use crate::sd_card::{OpenError, SdCard, VolumeError};
+
+const DIRECTORY: &str = "/SCREENS";
+/// The numbers have five digits to fit the short names
+const MAXIMUM_NUMBER: u32 = 99_999;
+/// Bytes of a row in the image. Already a multiple of 4, so there is no padding.
+// Can't truncate as the frame is less than 1000 pixels wide
+const ROW_SIZE: usize = Frame::SIZE.width as usize / 8;
+/// Bytes of a row of the panel, which is a column of the image
+// Can't truncate as the frame is less than 1000 pixels wide
+const PANEL_ROW_SIZE: usize = Frame::BUFFER_SIZE / Frame::SIZE.width as usize;
+/// Rows of the image that are written at once
+const ROWS_PER_WRITE: usize = 8;
+
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum ScreenshotError {
+    #[error("Failed to create screenshot directory")]
+    CreateDirectory(VolumeError),
+    #[error("Failed to list screenshot directory")]
+    List(VolumeError),
+    #[error("All screenshot numbers are taken")]
+    NoNumber,
+    #[error("Failed to open screenshot file")]
+    Open(#[from] OpenError),
+    #[error("Failed to write screenshot file")]
+    Write(VolumeError),
+}
+
+/// Saves the frame with the next free number and returns the path
+pub(crate) async fn save(frame: &Frame, sd_card: &mut SdCard) -> Result<String, ScreenshotError> {
+    sd_card
+        .create_directories(DIRECTORY)
+        .await
+        .map_err(ScreenshotError::CreateDirectory)?;
+    let entries = sd_card
+        .list_directory(DIRECTORY)
+        .await
+        .map_err(ScreenshotError::List)?;
+    let number = entries
+        .iter()
+        .filter_map(|entry| parse_number(&format!("{}", entry.name)))
+        .max()
+        .map_or(1, |last| last + 1);
+    if number > MAXIMUM_NUMBER {
+        return Err(ScreenshotError::NoNumber);
+    }
+
+    let path = format!("{DIRECTORY}/SCR{number:05}.BMP");
+    let file = sd_card.open(&path, Mode::ReadWriteCreateOrTruncate).await?;
+    let result = write_image(frame, sd_card, file).await;
+
+    // Closing flushes the data to the card
+    let closed = sd_card.close(file).await;
+    result.and(closed).map_err(ScreenshotError::Write)?;
+    Ok(path)
+}
+
+fn parse_number(name: &str) -> Option<u32> {
+    name.strip_prefix("SCR")?.strip_suffix(".BMP")?.parse().ok()
+}
+
+/// Writes the frame in portrait like it is drawn
+async fn write_image(
+    frame: &Frame,
+    sd_card: &mut SdCard,
+    file: RawFile,
+) -> Result<(), VolumeError> {
+    sd_card
+        .write(file, &bmp::monochrome_header(Frame::SIZE))
+        .await?;
+
+    let mut rows = [0; ROW_SIZE * ROWS_PER_WRITE];
+    let mut filled = 0;
+    // Can't truncate as the frame is less than 1000 pixels on each side
+    let (width, height) = (Frame::SIZE.width as usize, Frame::SIZE.height as usize);
+    // BMP rows go from the bottom up
+    for y in (0..height).rev() {
+        let row = &mut rows[filled * ROW_SIZE..(filled + 1) * ROW_SIZE];
+        row.fill(0);
+        for x in 0..width {
+            if is_white(frame, x, y) {
+                row[x / 8] |= 1 << (7 - x % 8);
+            }
+        }
+
+        filled += 1;
+        if filled == ROWS_PER_WRITE || y == 0 {
+            sd_card.write(file, &rows[..filled * ROW_SIZE]).await?;
+            filled = 0;
+        }
+    }
+
+    Ok(())
+}
+
+/// Reverses the mapping of the draw target of the frame from drawing to panel coordinates
+fn is_white(frame: &Frame, x: usize, y: usize) -> bool {
+    // Can't truncate as the frame is less than 1000 pixels wide
+    let panel_row = Frame::SIZE.width as usize - 1 - x;
+    let byte = frame[panel_row * PANEL_ROW_SIZE + y / 8];
+    byte & (1 << (7 - y % 8)) != 0
+}
@@ -0,0 +1,282 @@
+//! The block device embedded_sdmmc works on. embedded_sdmmc only has a blocking API, so it works on
+//! a cache of blocks while the async [`Card`] moves blocks between the cache and the card.
+//!
+//! While fetching, a read that misses the cache fails with [`BlockError::Miss`]. The caller fetches
+//! the block asynchronously and runs the read again, which is only safe for operations that don't
+//! change anything like reading a file. Other operations read a missed block right away, which
+//! blocks the executor for the few blocks of file system data they need. Writes only change the
+//! cache and are written back asynchronously after each operation.
+
+use core::cell::RefCell;
+use core::cmp::Reverse;
+
+use embassy_futures::block_on;
+use embassy_sync::blocking_mutex::{
+    self,
+    raw::{CriticalSectionRawMutex, NoopRawMutex},
+};
+use embassy_sync::mutex::{Mutex, MutexGuard};
+use embedded_sdmmc::{Block, BlockCount, BlockDevice, BlockIdx};
+
+use crate::memory::SD_CACHE_BLOCKS;
+use crate::sd_card::card::{Card, CardError};
+
+pub(super) type SharedCard = Mutex<NoopRawMutex, Card>;
+
+/// Blocks fetched at once. At most half of the cache, to not replace the blocks of the file system
+/// that a read needs as well.
+// Can't truncate as the cache has a few blocks
+const MAXIMUM_FETCH: u32 = (SD_CACHE_BLOCKS / 2) as u32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error, defmt::Format)]
+pub(crate) enum BlockError {
+    #[error("SD card failed")]
+    Card(#[from] CardError),
+    /// Never returned from the operations of [`super::SdCard`], which fetch the block and try again
+    #[error("Block {0} is not in the cache")]
+    Miss(u32),
+    #[error("SPI bus or card is in use")]
+    Busy,
+}
+
+struct Slot {
+    /// None while the slot is free
+    index: Option<u32>,
+    block: Block,
+    /// Changed and not written back to the card yet
+    is_dirty: bool,
+    /// When the slot was last used, to replace the one used longest ago
+    last_used: u32,
+}
+
+struct Slots {
+    slots: [Slot; SD_CACHE_BLOCKS],
+    clock: u32,
+    /// Whether a miss fails instead of reading the block right away
+    is_fetching: bool,
+}
+
+impl Slots {
+    const fn new() -> Self {
+        Self {
+            slots: [const {
+                Slot {
+                    index: None,
+                    block: Block {
+                        contents: [0; Block::LEN],
+                    },
+                    is_dirty: false,
+                    last_used: 0,
+                }
+            }; SD_CACHE_BLOCKS],
+            clock: 0,
+            is_fetching: false,
+        }
+    }
+
+    fn find(&mut self, index: u32) -> Option<&mut Slot> {
+        self.clock = self.clock.wrapping_add(1);
+        let clock = self.clock;
+        let slot = self
+            .slots
+            .iter_mut()
+            .find(|slot| slot.index == Some(index))?;
+        slot.last_used = clock;
+        Some(slot)
+    }
+
+    /// The slot of the block or the one to replace with it, which is a free one or else the clean one
+    /// used longest ago. None if all slots are dirty.
+    fn slot_for(&mut self, index: u32) -> Option<&mut Slot> {
+        self.clock = self.clock.wrapping_add(1);
+        let clock = self.clock;
+        let position = self
+            .slots
+            .iter()
+            .position(|slot| slot.index == Some(index))
+            .or_else(|| {
+                self.slots
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, slot)| !slot.is_dirty)
+                    .min_by_key(|(_, slot)| {
+                        (
+                            slot.index.is_some(),
+                            Reverse(clock.wrapping_sub(slot.last_used)),
+                        )
+                    })
+                    .map(|(position, _)| position)
+            })?;
+
+        let slot = &mut self.slots[position];
+        slot.last_used = clock;
+        Some(slot)
+    }
+
+    /// A copy of the dirty block used longest ago to write it back
+    fn oldest_dirty(&self) -> Option<(u32, Block)> {
+        self.slots
+            .iter()
+            .filter(|slot| slot.is_dirty)
+            .max_by_key(|slot| self.clock.wrapping_sub(slot.last_used))
+            .and_then(|slot| Some((slot.index?, slot.block.clone())))
+    }
+
+    fn mark_clean(&mut self, index: u32) {
+        if let Some(slot) = self.slots.iter_mut().find(|slot| slot.index == Some(index)) {
+            slot.is_dirty = false;
+        }
+    }
+}
+
+/// Static as the blocks are too large for the stack and the futures
+static SLOTS: blocking_mutex::Mutex<CriticalSectionRawMutex, RefCell<Slots>> =
+    blocking_mutex::Mutex::new(RefCell::new(Slots::new()));
+
+/// Handle to the cache and the card. The volume manager of embedded_sdmmc owns one and the
+/// [`super::SdCard`] another.
+#[derive(Clone, Copy)]
+pub(crate) struct Blocks {
+    card: &'static SharedCard,
+}
+
+impl Blocks {
+    pub(super) fn new(card: &'static SharedCard) -> Self {
+        Self { card }
+    }
+
+    pub(super) fn card(&self) -> &'static SharedCard {
+        self.card
+    }
+
+    /// Whether a miss fails with [`BlockError::Miss`] instead of reading the block right away
+    pub(super) fn set_fetching(&self, is_fetching: bool) {
+        SLOTS.lock(|slots| slots.borrow_mut().is_fetching = is_fetching);
+    }
+
+    /// Forgets all blocks, e.g. when the card was swapped. Changes that were not written back are
+    /// lost.
+    pub(super) fn clear(&self) {
+        SLOTS.lock(|slots| {
+            for slot in &mut slots.borrow_mut().slots {
+                slot.index = None;
+                slot.is_dirty = false;
+            }
+        });
+    }
+
+    /// Reads the block and the ones after it that are not in the cache yet, as files are mostly
+    /// read in order
+    pub(super) async fn fetch(&self, start: u32, count: u32) -> Result<(), BlockError> {
+        // Cached blocks are only replaced once they are written back
+        self.flush().await?;
+
+        let mut card = self.card.lock().await;
+        let count = count.clamp(1, MAXIMUM_FETCH);
+        let mut block = Block::new();
+        for index in start..start.saturating_add(count) {
+            if SLOTS.lock(|slots| slots.borrow_mut().find(index).is_some()) {
+                continue;
+            }
+
+            card.read(index, core::slice::from_mut(&mut block)).await?;
+            store(&mut card, index, &block, false)?;
+        }
+
+        Ok(())
+    }
+
+    /// Writes the changed blocks back to the card
+    pub(super) async fn flush(&self) -> Result<(), BlockError> {
+        let mut card = self.card.lock().await;
+        while let Some((index, block)) = SLOTS.lock(|slots| slots.borrow().oldest_dirty()) {
+            card.write(index, core::slice::from_ref(&block)).await?;
+            SLOTS.lock(|slots| slots.borrow_mut().mark_clean(index));
+        }
+
+        Ok(())
+    }
+
+    /// The card is never locked while embedded_sdmmc runs, as that doesn't await
+    fn lock_card(&self) -> Result<MutexGuard<'static, NoopRawMutex, Card>, BlockError> {
+        self.card.try_lock().map_err(|_| BlockError::Busy)
+    }
+}
+
+/// Puts the block into the cache. Writes back the dirty block used longest ago if all are dirty,
+/// which blocks the executor.
+fn store(card: &mut Card, index: u32, block: &Block, is_dirty: bool) -> Result<(), BlockError> {
+    loop {
+        let oldest_dirty = SLOTS.lock(|slots| {
+            let mut slots = slots.borrow_mut();
+            if let Some(slot) = slots.slot_for(index) {
+                slot.is_dirty = is_dirty || (slot.index == Some(index) && slot.is_dirty);
+                slot.index = Some(index);
+                slot.block.contents = block.contents;
+                return None;
+            }
+
+            slots.oldest_dirty()
+        });
+
+        let Some((dirty_index, dirty_block)) = oldest_dirty else {
+            return Ok(());
+        };
+
+        if card.is_bus_busy() {
+            return Err(BlockError::Busy);
+        }
+        block_on(card.write(dirty_index, core::slice::from_ref(&dirty_block)))?;
+        SLOTS.lock(|slots| slots.borrow_mut().mark_clean(dirty_index));
+    }
+}
+
+impl BlockDevice for Blocks {
+    type Error = BlockError;
+
+    fn read(&self, blocks: &mut [Block], start_block_idx: BlockIdx) -> Result<(), Self::Error> {
+        for (block, index) in blocks.iter_mut().zip(start_block_idx.0..) {
+            let (is_cached, is_fetching) = SLOTS.lock(|slots| {
+                let mut slots = slots.borrow_mut();
+                let is_fetching = slots.is_fetching;
+                let Some(slot) = slots.find(index) else {
+                    return (false, is_fetching);
+                };
+
+                block.contents = slot.block.contents;
+                (true, is_fetching)
+            });
+
+            if is_cached {
+                continue;
+            }
+
+            if is_fetching {
+                return Err(BlockError::Miss(index));
+            }
+
+            let mut card = self.lock_card()?;
+            // The transaction would wait for the bus forever, as the executor is blocked
+            if card.is_bus_busy() {
+                return Err(BlockError::Busy);
+            }
+            block_on(card.read(index, core::slice::from_mut(block)))?;
+            store(&mut card, index, block, false)?;
+        }
+
+        Ok(())
+    }
+
+    fn write(&self, blocks: &[Block], start_block_idx: BlockIdx) -> Result<(), Self::Error> {
+        let mut card = self.lock_card()?;
+        for (block, index) in blocks.iter().zip(start_block_idx.0..) {
+            store(&mut card, index, block, true)?;
+        }
+
+        Ok(())
+    }
+
+    fn num_blocks(&self) -> Result<BlockCount, Self::Error> {
+        Ok(BlockCount(self.lock_card()?.block_count()))
+    }
+}
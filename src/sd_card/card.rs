@@ -0,0 +1,416 @@
+//! The SD card protocol in SPI mode on the async device of the shared bus, after the simplified
+//! physical layer specification of the SD Association. Every exchange with the card is its own
+//! transaction, so the display gets the bus in between and waiting for the card lets other tasks
+//! run.
+
+use embassy_futures::yield_now;
+use embassy_time::{Duration, Instant};
+use embedded_hal_async::spi::{Operation, SpiBus, SpiDevice};
+use embedded_sdmmc::Block;
+
+use crate::spi;
+use crate::timeout::{self, Operation as TimeoutOperation};
+
+pub(super) const BLOCK_SIZE: u32 = 512;
+/// The card answers within 8 bytes after a command
+const MAXIMUM_RESPONSE_BYTES: usize = 8;
+/// Idle state bit of the first response byte, R1, set until the card is initialized
+const IDLE: u8 = 0x01;
+const ILLEGAL_COMMAND: u8 = 0x04;
+/// Supply voltage of 2.7 to 3.6 V and a pattern the card echoes
+const VOLTAGE_CHECK: u32 = 0x1AA;
+/// Host capacity support in the initialization and card capacity status in the OCR register
+const HIGH_CAPACITY: u32 = 1 << 30;
+/// Precedes each data block
+const START_BLOCK: u8 = 0xFE;
+const DATA_RESPONSE_MASK: u8 = 0x1F;
+const DATA_ACCEPTED: u8 = 0x05;
+/// The specification allows a second for the initialization
+const INITIALIZE_TIMEOUT: Duration = Duration::from_secs(1);
+/// The specification allows 100 ms for reads
+const READ_TIMEOUT: Duration = Duration::from_millis(100);
+/// The specification allows 250 ms for standard and 500 ms for high capacity cards
+const WRITE_TIMEOUT: Duration = Duration::from_millis(500);
+const BUS_TIMEOUT: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+enum Command {
+    /// Resets the card into SPI mode
+    GoIdleState = 0,
+    /// Checks the supply voltage and tells cards of version 2 apart
+    SendIfCond = 8,
+    /// Reads the card specific data with the size
+    SendCsd = 9,
+    StopTransmission = 12,
+    SendStatus = 13,
+    SetBlockLength = 16,
+    ReadSingleBlock = 17,
+    ReadMultipleBlock = 18,
+    WriteBlock = 24,
+    /// Application specific, starts the initialization
+    SdSendOpCond = 41,
+    /// Prefix of the application specific commands
+    AppCmd = 55,
+    ReadOcr = 58,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error, defmt::Format)]
+pub(crate) enum CardError {
+    #[error("SPI transfer failed")]
+    Transfer,
+    #[error("Card did not answer command {0}")]
+    NoResponse(u8),
+    #[error("Card answered command {command} with error {response:#04x}")]
+    Command { command: u8, response: u8 },
+    #[error("Card does not support the supply voltage")]
+    UnsupportedVoltage,
+    #[error("Card did not finish initializing in time")]
+    InitializeTimeout,
+    #[error("Card is not initialized")]
+    NotInitialized,
+    #[error("Timeout waiting for the card")]
+    Timeout,
+    #[error("Card sent error token {0:#04x} instead of data")]
+    Read(u8),
+    #[error("Card rejected written data with {0:#04x}")]
+    WriteRejected(u8),
+    #[error("Card reported an error after writing")]
+    Write,
+}
+
+/// How blocks are addressed in commands
+#[derive(Debug, Clone, Copy)]
+enum Capacity {
+    /// Up to 2 GB, addressed in bytes
+    Standard,
+    /// High and extended capacity, addressed in blocks
+    High,
+}
+
+pub(crate) struct Card {
+    device: spi::Device<'static>,
+    bus: &'static spi::Bus<'static>,
+    /// None until initialized
+    capacity: Option<Capacity>,
+    block_count: u32,
+}
+
+impl Card {
+    pub(crate) fn new(device: spi::CardDevice) -> Self {
+        Self {
+            device: device.device,
+            bus: device.bus,
+            capacity: None,
+            block_count: 0,
+        }
+    }
+
+    pub(super) fn block_count(&self) -> u32 {
+        self.block_count
+    }
+
+    /// Whether another device is in the middle of a transaction on the bus
+    pub(super) fn is_bus_busy(&self) -> bool {
+        self.bus.try_lock().is_err()
+    }
+
+    /// Blocking operations fail if another device is in the middle of a transaction. Waiting for
+    /// the lock and releasing it right away ensures the bus is free. As long as there is no await
+    /// between this and the blocking operation, no other task can take the bus in between.
+    ///
+    /// If the bus stays taken, the blocking operation goes ahead anyway and fails with an error
+    /// instead of hanging.
+    pub(super) async fn wait_for_bus(&self) {
+        if let Ok(guard) =
+            timeout::run(TimeoutOperation::SdCardBus, BUS_TIMEOUT, self.bus.lock()).await
+        {
+            drop(guard);
+        }
+    }
+
+    /// Puts the card into SPI mode and reads its size. Fails if there is no card in the slot.
+    pub(super) async fn initialize(&mut self) -> Result<(), CardError> {
+        self.capacity = None;
+        {
+            // At least 74 clock cycles without chip select to let the card power up
+            let mut bus = self.bus.lock().await;
+            SpiBus::write(&mut *bus, &[0xFF; 10])
+                .await
+                .map_err(|_| CardError::Transfer)?;
+        }
+
+        let deadline = Instant::now() + INITIALIZE_TIMEOUT;
+        while self.command(Command::GoIdleState, 0).await != Ok(IDLE) {
+            if Instant::now() > deadline {
+                return Err(CardError::InitializeTimeout);
+            }
+            yield_now().await;
+        }
+
+        let is_version_2 = match self
+            .command_with_register(Command::SendIfCond, VOLTAGE_CHECK)
+            .await?
+        {
+            (IDLE, echo) if echo & 0xFFF == VOLTAGE_CHECK => true,
+            (IDLE, _) => return Err(CardError::UnsupportedVoltage),
+            (response, _) if response & ILLEGAL_COMMAND != 0 => false,
+            (response, _) => {
+                return Err(CardError::Command {
+                    // Can't truncate as the commands fit into 6 bits
+                    command: Command::SendIfCond as u8,
+                    response,
+                });
+            }
+        };
+
+        let argument = if is_version_2 { HIGH_CAPACITY } else { 0 };
+        loop {
+            self.command(Command::AppCmd, 0).await?;
+            match self.command(Command::SdSendOpCond, argument).await? {
+                0 => break,
+                IDLE if Instant::now() < deadline => yield_now().await,
+                IDLE => return Err(CardError::InitializeTimeout),
+                response => {
+                    return Err(CardError::Command {
+                        // Can't truncate as the commands fit into 6 bits
+                        command: Command::SdSendOpCond as u8,
+                        response,
+                    });
+                }
+            }
+        }
+
+        let capacity = if is_version_2 {
+            let (_, ocr) = self.command_with_register(Command::ReadOcr, 0).await?;
+            if ocr & HIGH_CAPACITY != 0 {
+                Capacity::High
+            } else {
+                Capacity::Standard
+            }
+        } else {
+            Capacity::Standard
+        };
+
+        if matches!(capacity, Capacity::Standard) {
+            self.expect(Command::SetBlockLength, BLOCK_SIZE).await?;
+        }
+
+        let mut csd = [0; 16];
+        self.expect(Command::SendCsd, 0).await?;
+        self.read_data(&mut csd).await?;
+        self.block_count = block_count(&csd);
+        self.capacity = Some(capacity);
+        Ok(())
+    }
+
+    /// Whether the card still answers, without going through the file system
+    pub(super) async fn is_present(&mut self) -> bool {
+        if self.capacity.is_none() {
+            return false;
+        }
+
+        // The status has a second byte that is zero as well when all is fine
+        self.command(Command::SendStatus, 0).await == Ok(0)
+            && self.read_byte().await.is_ok_and(|status| status == 0)
+    }
+
+    pub(super) async fn read(&mut self, start: u32, blocks: &mut [Block]) -> Result<(), CardError> {
+        let address = self.address(start)?;
+        if let [block] = blocks {
+            self.expect(Command::ReadSingleBlock, address).await?;
+            return self.read_data(&mut block.contents).await;
+        }
+
+        self.expect(Command::ReadMultipleBlock, address).await?;
+        let mut result = Ok(());
+        for block in blocks {
+            result = self.read_data(&mut block.contents).await;
+            if result.is_err() {
+                break;
+            }
+        }
+
+        // Stopped even after a failed read, as the card keeps sending otherwise
+        let stopped = self.command(Command::StopTransmission, 0).await;
+        result.and(stopped.map(|_| ()))
+    }
+
+    pub(super) async fn write(&mut self, start: u32, blocks: &[Block]) -> Result<(), CardError> {
+        for (index, block) in (start..).zip(blocks) {
+            let address = self.address(index)?;
+            self.expect(Command::WriteBlock, address).await?;
+
+            let mut response = [0xFF];
+            self.device
+                .transaction(&mut [
+                    Operation::Write(&[START_BLOCK]),
+                    Operation::Write(&block.contents),
+                    // The checksum is not checked in SPI mode
+                    Operation::Write(&[0xFF, 0xFF]),
+                    Operation::TransferInPlace(&mut response),
+                ])
+                .await
+                .map_err(|_| CardError::Transfer)?;
+
+            let [response] = response;
+            if response & DATA_RESPONSE_MASK != DATA_ACCEPTED {
+                return Err(CardError::WriteRejected(response));
+            }
+
+            self.wait_until_ready(WRITE_TIMEOUT).await?;
+            // Errors while programming are only in the status
+            if !self.is_present().await {
+                return Err(CardError::Write);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The address of the block in commands
+    fn address(&self, block: u32) -> Result<u32, CardError> {
+        match self.capacity {
+            Some(Capacity::High) => Ok(block),
+            // Can't overflow as standard capacity cards have at most 2 GB
+            Some(Capacity::Standard) => Ok(block * BLOCK_SIZE),
+            None => Err(CardError::NotInitialized),
+        }
+    }
+
+    /// Sends the command and returns the first byte of the response, R1
+    async fn command(&mut self, command: Command, argument: u32) -> Result<u8, CardError> {
+        // The card is still sending data when a transmission is stopped
+        if !matches!(command, Command::GoIdleState | Command::StopTransmission) {
+            self.wait_until_ready(WRITE_TIMEOUT).await?;
+        }
+
+        // The checksum is only checked for these two before the card is in SPI mode
+        let checksum = match command {
+            Command::GoIdleState => 0x95,
+            Command::SendIfCond => 0x87,
+            _ => 0x01,
+        };
+        let is_stop = command == Command::StopTransmission;
+        let [first, second, third, fourth] = argument.to_be_bytes();
+        // Can't truncate as the commands fit into 6 bits
+        let command = command as u8;
+        self.device
+            .write(&[0x40 | command, first, second, third, fourth, checksum])
+            .await
+            .map_err(|_| CardError::Transfer)?;
+
+        if is_stop {
+            // Stuff byte
+            self.read_byte().await?;
+        }
+
+        for _ in 0..MAXIMUM_RESPONSE_BYTES {
+            let response = self.read_byte().await?;
+            if response & 0x80 == 0 {
+                return Ok(response);
+            }
+        }
+
+        Err(CardError::NoResponse(command))
+    }
+
+    /// Sends the command and fails unless the card answers without an error
+    async fn expect(&mut self, command: Command, argument: u32) -> Result<(), CardError> {
+        match self.command(command, argument).await? {
+            0 => Ok(()),
+            response => Err(CardError::Command {
+                // Can't truncate as the commands fit into 6 bits
+                command: command as u8,
+                response,
+            }),
+        }
+    }
+
+    /// Sends a command that answers with a 32 bit register after the first byte
+    async fn command_with_register(
+        &mut self,
+        command: Command,
+        argument: u32,
+    ) -> Result<(u8, u32), CardError> {
+        let response = self.command(command, argument).await?;
+        let mut register = [0xFF; 4];
+        self.device
+            .transfer_in_place(&mut register)
+            .await
+            .map_err(|_| CardError::Transfer)?;
+        Ok((response, u32::from_be_bytes(register)))
+    }
+
+    /// Waits for the start of a data block and reads it into the buffer
+    async fn read_data(&mut self, buffer: &mut [u8]) -> Result<(), CardError> {
+        let deadline = Instant::now() + READ_TIMEOUT;
+        let token = loop {
+            match self.read_byte().await? {
+                0xFF if Instant::now() > deadline => return Err(CardError::Timeout),
+                0xFF => yield_now().await,
+                token => break token,
+            }
+        };
+
+        if token != START_BLOCK {
+            return Err(CardError::Read(token));
+        }
+
+        buffer.fill(0xFF);
+        // The checksum is not checked in SPI mode
+        let mut checksum = [0xFF; 2];
+        self.device
+            .transaction(&mut [
+                Operation::TransferInPlace(buffer),
+                Operation::TransferInPlace(&mut checksum),
+            ])
+            .await
+            .map_err(|_| CardError::Transfer)
+    }
+
+    /// The card holds its output low while busy
+    async fn wait_until_ready(&mut self, timeout: Duration) -> Result<(), CardError> {
+        let deadline = Instant::now() + timeout;
+        while self.read_byte().await? != 0xFF {
+            if Instant::now() > deadline {
+                return Err(CardError::Timeout);
+            }
+            yield_now().await;
+        }
+
+        Ok(())
+    }
+
+    async fn read_byte(&mut self) -> Result<u8, CardError> {
+        let mut byte = [0xFF];
+        self.device
+            .transfer_in_place(&mut byte)
+            .await
+            .map_err(|_| CardError::Transfer)?;
+        Ok(byte[0])
+    }
+}
+
+/// The number of blocks from the card specific data register
+fn block_count(csd: &[u8; 16]) -> u32 {
+    match csd[0] >> 6 {
+        // Version 1 of standard capacity cards
+        0 => {
+            let read_block_length = u32::from(csd[5] & 0x0F);
+            let size = (u32::from(csd[6] & 0x03) << 10)
+                | (u32::from(csd[7]) << 2)
+                | u32::from(csd[8] >> 6);
+            let multiplier = u32::from(((csd[9] & 0x03) << 1) | (csd[10] >> 7));
+            let bytes = u64::from(size + 1) << (multiplier + 2 + read_block_length);
+            // Can't truncate as standard capacity cards have at most 4 GiB
+            (bytes / u64::from(BLOCK_SIZE)) as u32
+        }
+        // Version 2 of high and extended capacity cards in units of 512 KiB
+        _ => {
+            let size =
+                (u32::from(csd[7] & 0x3F) << 16) | (u32::from(csd[8]) << 8) | u32::from(csd[9]);
+            (size + 1).saturating_mul(1024)
+        }
+    }
+}
@@ -0,0 +1,68 @@
+//! Adapts how much is read from the SD card at once.
+//!
+//! Reads await the card, but other reads and the screen that shows the data wait for the whole
+//! chunk, so large chunks delay them while small chunks pay the per read overhead more often. The
+//! chunk size is derived from the measured latency so that reading one chunk takes roughly a target
+//! duration. That target is short while the UI is in use and longer for background work like
+//! indexing the library.
+
+use embassy_time::Duration;
+
+use crate::{eink_display, memory};
+
+/// Who is waiting for the data that is read
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub(crate) enum ReadPriority {
+    /// The user is waiting, e.g. for the next page
+    Interactive,
+    /// Nobody is waiting, e.g. while indexing the library
+    Background,
+}
+
+const BLOCK_SIZE: u64 = 512;
+const MINIMUM_BLOCKS: u64 = 1;
+const MAXIMUM_BLOCKS: u64 = memory::MAXIMUM_SD_READ_BLOCKS;
+const INTERACTIVE_TARGET: Duration = Duration::from_millis(5);
+const BACKGROUND_TARGET: Duration = Duration::from_millis(50);
+/// Rough guess for a 512 byte block at 40 MHz including the command overhead until the first
+/// measurement comes in
+const INITIAL_BLOCK_LATENCY_MICROSECONDS: u64 = 500;
+
+pub(super) struct ChunkSize {
+    /// Moving average of the time it takes to read one block
+    block_latency_microseconds: u64,
+}
+
+impl ChunkSize {
+    pub(super) const fn new() -> Self {
+        Self {
+            block_latency_microseconds: INITIAL_BLOCK_LATENCY_MICROSECONDS,
+        }
+    }
+
+    /// The number of bytes to read in the next chunk. Always a multiple of the block size.
+    pub(super) fn get(&self, priority: ReadPriority) -> usize {
+        // Keep chunks small while the display is refreshing as the UI is likely to be in use
+        let target = match priority {
+            ReadPriority::Background if !eink_display::is_refreshing() => BACKGROUND_TARGET,
+            ReadPriority::Background | ReadPriority::Interactive => INTERACTIVE_TARGET,
+        };
+
+        let blocks = (target.as_micros() / self.block_latency_microseconds.max(1))
+            .clamp(MINIMUM_BLOCKS, MAXIMUM_BLOCKS);
+
+        // Can't truncate as it is at most the largest read of the profile, 32 KiB by default
+        (blocks * BLOCK_SIZE) as usize
+    }
+
+    pub(super) fn record(&mut self, bytes: usize, elapsed: Duration) {
+        if bytes == 0 {
+            return;
+        }
+
+        let blocks = (bytes as u64).div_ceil(BLOCK_SIZE);
+        let latency = elapsed.as_micros() / blocks;
+        // Weigh new measurements by a quarter to smooth out outliers
+        self.block_latency_microseconds = (self.block_latency_microseconds * 3 + latency) / 4;
+    }
+}
@@ -0,0 +1,57 @@
+use crate::sd_card::{BlockError, CardError};
+
+pub(crate) type VolumeError = embedded_sdmmc::Error<BlockError>;
+
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum MountError {
+    #[error("Failed to initialize SD card")]
+    Initialize(CardError),
+    #[error("Failed to open volume")]
+    OpenVolume(VolumeError),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum OpenError {
+    #[error("Path does not contain a file name")]
+    NoFileName,
+    #[error("Failed to access volume")]
+    Volume(VolumeError),
+}
+
+impl From<VolumeError> for OpenError {
+    fn from(error: VolumeError) -> Self {
+        Self::Volume(error)
+    }
+}
+
+impl OpenError {
+    pub(crate) fn is_not_found(&self) -> bool {
+        matches!(self, Self::Volume(embedded_sdmmc::Error::NotFound))
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum ReadFileError {
+    #[error("Failed to open file")]
+    Open(#[from] OpenError),
+    #[error("File is larger than the maximum size")]
+    TooLarge(u32),
+    #[error("Failed to read file")]
+    Read(VolumeError),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum WriteFileError {
+    #[error("Failed to open file")]
+    Open(#[from] OpenError),
+    #[error("Failed to write file")]
+    Write(VolumeError),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum RestoreError {
+    #[error("Failed to read copy")]
+    Read(#[from] ReadFileError),
+    #[error("Failed to write file from copy")]
+    Write(#[from] WriteFileError),
+}
@@ -0,0 +1,508 @@
+//! Files on the SD card through embedded_sdmmc. The card shares the SPI bus with the display
+//! through the async device, see [`card`], so waiting for the card lets other tasks run.
+//!
+//! embedded_sdmmc only has a blocking API, so it works on a cache of blocks, see [`blocks`]. Reading
+//! a file fetches the blocks asynchronously and reads ahead, so an awaited [`SdCard::read_at`] of
+//! any length leaves button handling and display refreshes going. Operations that change the card
+//! or walk directories read the few blocks of file system data they miss right away and write the
+//! changed blocks back asynchronously once they are done.
+//!
+//! The FAT support of embedded_sdmmc can't rename files, so [`SdCard::write_file`] can't write to a
+//! temporary file and move it in place. It writes a complete copy next to the file first instead,
+//! e.g. `SETTINGS.TX~` for `SETTINGS.TXT`, and removes it once the file is written. If power is
+//! lost in between, the next [`SdCard::read_file`] restores the file from the copy. Downloads don't
+//! need this, as the WebDAV manifest only records a book once its download completed.
+
+pub(crate) use crate::sd_card::blocks::BlockError;
+pub(crate) use crate::sd_card::card::CardError;
+pub(crate) use crate::sd_card::chunk_size::ReadPriority;
+pub(crate) use crate::sd_card::error::*;
+pub(crate) use crate::sd_card::presence::{CardChange, watch};
+
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use defmt::{info, warn};
+use embassy_futures::yield_now;
+use embassy_sync::mutex::Mutex;
+use embassy_time::Instant;
+use embedded_sdmmc::{
+    DirEntry, Mode, RawDirectory, RawFile, RawVolume, TimeSource, Timestamp, VolumeIdx,
+};
+use static_cell::StaticCell;
+
+use crate::sd_card::blocks::{Blocks, SharedCard};
+use crate::sd_card::card::{BLOCK_SIZE, Card};
+use crate::sd_card::chunk_size::ChunkSize;
+use crate::spi;
+use crate::supervisor::{self, Task};
+
+mod blocks;
+mod card;
+mod chunk_size;
+mod error;
+mod presence;
+
+/// Writes are split into chunks of this size to let other tasks run in between
+const WRITE_CHUNK_SIZE: usize = 512;
+/// Ends the copy written before replacing a file. A copy that doesn't end with it was cut short.
+const COPY_END: &[u8] = b"\n~END~\n";
+
+type VolumeManager = embedded_sdmmc::VolumeManager<Blocks, FixedTimeSource>;
+
+/// There is no real-time clock set up yet, so every file gets the same timestamp
+struct FixedTimeSource;
+
+impl TimeSource for FixedTimeSource {
+    fn get_timestamp(&self) -> Timestamp {
+        Timestamp {
+            // 2026
+            year_since_1970: 56,
+            zero_indexed_month: 0,
+            zero_indexed_day: 0,
+            hours: 0,
+            minutes: 0,
+            seconds: 0,
+        }
+    }
+}
+
+pub(crate) struct SdCard {
+    blocks: Blocks,
+    volume_manager: VolumeManager,
+    volume: RawVolume,
+    chunk_size: ChunkSize,
+}
+
+/// The card slot while no card is mounted, kept to mount a card once one is inserted
+pub(crate) struct Unmounted {
+    blocks: Blocks,
+}
+
+impl Unmounted {
+    /// There is one slot, so this can only be called once
+    pub(crate) fn new(device: spi::CardDevice) -> Self {
+        static CARD: StaticCell<SharedCard> = StaticCell::new();
+        let card = CARD.init(Mutex::new(Card::new(device)));
+        Self {
+            blocks: Blocks::new(card),
+        }
+    }
+
+    /// Whether a card answers in the slot. Initializes the card again, as a card that was just
+    /// inserted doesn't know the previous one was.
+    pub(crate) async fn is_inserted(&self) -> bool {
+        self.blocks.card().lock().await.initialize().await.is_ok()
+    }
+
+    /// Returns the slot with the error if the card can't be mounted, to try again later
+    pub(crate) async fn mount(self) -> Result<SdCard, (MountError, Self)> {
+        info!("Mounting SD card");
+        let mut card = self.blocks.card().lock().await;
+        if let Err(error) = card.initialize().await {
+            drop(card);
+            return Err((MountError::Initialize(error), self));
+        }
+        info!("SD card size: {} blocks", card.block_count());
+        card.wait_for_bus().await;
+        drop(card);
+
+        // The blocks might be from another card
+        self.blocks.clear();
+        let volume_manager = VolumeManager::new(self.blocks, FixedTimeSource);
+        let volume = match volume_manager.open_raw_volume(VolumeIdx(0)) {
+            Ok(volume) => volume,
+            Err(error) => return Err((MountError::OpenVolume(error), self)),
+        };
+
+        info!("SD card mounted");
+        Ok(SdCard {
+            blocks: self.blocks,
+            volume_manager,
+            volume,
+            chunk_size: ChunkSize::new(),
+        })
+    }
+}
+
+impl SdCard {
+    /// Whether the card still answers. Asks the card for its status, as the file system might
+    /// only read from the cache.
+    pub(crate) async fn is_present(&mut self) -> bool {
+        self.blocks.card().lock().await.is_present().await
+    }
+
+    /// Gives up the card after it was removed. Files that are still open fail from now on.
+    pub(crate) fn unmount(self) -> Unmounted {
+        // Fails with files still open, which is fine as the volume manager goes away anyway
+        if let Err(error) = self.volume_manager.close_volume(self.volume) {
+            defmt::warn!("Failed to close volume: {:?}", error);
+        }
+
+        // Changes that were not written back can't be written to the card anymore
+        self.blocks.clear();
+        Unmounted {
+            blocks: self.blocks,
+        }
+    }
+
+    /// Blocks that are not cached are read without awaiting, which would wait for the bus forever
+    /// if another device is in the middle of a transaction. Waiting for the bus first ensures it
+    /// is free, as long as there is no await until the operation on the volume manager.
+    async fn wait_for_bus(&self) {
+        self.blocks.card().lock().await.wait_for_bus().await;
+    }
+
+    /// Writes back the blocks changed by the last operation
+    async fn flush(&self) -> Result<(), VolumeError> {
+        self.blocks
+            .flush()
+            .await
+            .map_err(embedded_sdmmc::Error::DeviceError)
+    }
+
+    /// Opens the file at an absolute path like `/books/book.txt`
+    pub(crate) async fn open(&mut self, path: &str, mode: Mode) -> Result<RawFile, OpenError> {
+        self.wait_for_bus().await;
+
+        let (directories, file_name) = split_path(path);
+        if file_name.is_empty() {
+            return Err(OpenError::NoFileName);
+        }
+
+        let directory = self.open_directory(directories, false)?;
+        let file = self
+            .volume_manager
+            .open_file_in_dir(directory, file_name, mode);
+        self.close_directory(directory);
+        // Creating or truncating the file changes the card
+        self.flush().await?;
+
+        Ok(file?)
+    }
+
+    /// Deletes the file at the absolute path
+    pub(crate) async fn remove(&mut self, path: &str) -> Result<(), OpenError> {
+        self.wait_for_bus().await;
+
+        let (directories, file_name) = split_path(path);
+        if file_name.is_empty() {
+            return Err(OpenError::NoFileName);
+        }
+
+        let directory = self.open_directory(directories, false)?;
+        let result = self.volume_manager.delete_file_in_dir(directory, file_name);
+        self.close_directory(directory);
+        self.flush().await?;
+
+        Ok(result?)
+    }
+
+    /// Walks the directories from the root. Missing directories are created if requested.
+    fn open_directory(&mut self, path: &str, create: bool) -> Result<RawDirectory, VolumeError> {
+        let mut directory = self.volume_manager.open_root_dir(self.volume)?;
+        for name in path.split('/').filter(|name| !name.is_empty()) {
+            let mut child = self.volume_manager.open_dir(directory, name);
+            if create && matches!(child, Err(embedded_sdmmc::Error::NotFound)) {
+                child = self
+                    .volume_manager
+                    .make_dir_in_dir(directory, name)
+                    .and_then(|()| self.volume_manager.open_dir(directory, name));
+            }
+
+            self.close_directory(directory);
+            directory = child?;
+        }
+
+        Ok(directory)
+    }
+
+    /// Creates the directory at the absolute path including missing parents
+    pub(crate) async fn create_directories(&mut self, path: &str) -> Result<(), VolumeError> {
+        self.wait_for_bus().await;
+        let directory = self.open_directory(path, true)?;
+        self.close_directory(directory);
+        self.flush().await
+    }
+
+    /// Lists the entries of the directory at the absolute path
+    pub(crate) async fn list_directory(
+        &mut self,
+        path: &str,
+    ) -> Result<Vec<DirEntry>, VolumeError> {
+        self.wait_for_bus().await;
+        let directory = self.open_directory(path, false)?;
+
+        let mut entries = Vec::new();
+        let result = self
+            .volume_manager
+            .iterate_dir(directory, |entry| entries.push(entry.clone()));
+        self.close_directory(directory);
+
+        result.map(|()| entries)
+    }
+
+    fn close_directory(&mut self, directory: RawDirectory) {
+        if let Err(error) = self.volume_manager.close_dir(directory) {
+            defmt::warn!("Failed to close directory: {:?}", error);
+        }
+    }
+
+    pub(crate) async fn close(&mut self, file: RawFile) -> Result<(), VolumeError> {
+        self.wait_for_bus().await;
+        // Closing writes the size and time to the directory entry
+        let closed = self.volume_manager.close_file(file);
+        let flushed = self.flush().await;
+        closed.and(flushed)
+    }
+
+    pub(crate) async fn length(&mut self, file: RawFile) -> Result<u32, VolumeError> {
+        self.wait_for_bus().await;
+        self.volume_manager.file_length(file)
+    }
+
+    /// Reads into the buffer starting at the offset in the file and returns the number of bytes
+    /// read. The read is split into chunks with other tasks getting to run in between. The chunk
+    /// size adapts to the measured latency and the priority.
+    pub(crate) async fn read_at(
+        &mut self,
+        file: RawFile,
+        offset: u32,
+        buffer: &mut [u8],
+        priority: ReadPriority,
+    ) -> Result<usize, VolumeError> {
+        let watch = supervisor::watch(Task::Storage);
+        let mut total = 0;
+        while total < buffer.len() {
+            watch.check_in();
+            let end = buffer.len().min(total + self.chunk_size.get(priority));
+
+            let start = Instant::now();
+            // Can't truncate as it is within the file, which is at most 4 GiB
+            let position = offset + total as u32;
+            let read = self
+                .read_chunk(file, position, &mut buffer[total..end])
+                .await?;
+            self.chunk_size.record(read, start.elapsed());
+
+            if read == 0 {
+                // End of file
+                break;
+            }
+
+            total += read;
+            yield_now().await;
+        }
+
+        Ok(total)
+    }
+
+    /// Reads up to a block at a time, so each read needs at most one block of the file besides the
+    /// file system data. Blocks that are not cached are fetched asynchronously together with the
+    /// rest of the chunk.
+    async fn read_chunk(
+        &mut self,
+        file: RawFile,
+        offset: u32,
+        buffer: &mut [u8],
+    ) -> Result<usize, VolumeError> {
+        let mut total = 0;
+        while total < buffer.len() {
+            // Can't truncate as it is within the file, which is at most 4 GiB
+            let position = offset + total as u32;
+            // Can't truncate as it is at most a block
+            let rest_of_block = (BLOCK_SIZE - position % BLOCK_SIZE) as usize;
+            let end = buffer.len().min(total + rest_of_block);
+
+            self.blocks.set_fetching(true);
+            let result = self
+                .volume_manager
+                .file_seek_from_start(file, position)
+                .and_then(|()| self.volume_manager.read(file, &mut buffer[total..end]));
+            self.blocks.set_fetching(false);
+
+            match result {
+                Ok(0) => break,
+                Ok(read) => total += read,
+                Err(embedded_sdmmc::Error::DeviceError(BlockError::Miss(block))) => {
+                    // Can't truncate as the chunk is at most the largest read of the profile
+                    let blocks = (buffer.len() - total).div_ceil(BLOCK_SIZE as usize) as u32;
+                    self.blocks
+                        .fetch(block, blocks)
+                        .await
+                        .map_err(embedded_sdmmc::Error::DeviceError)?;
+                }
+                Err(error) => return Err(error),
+            }
+        }
+
+        Ok(total)
+    }
+
+    /// Reads the whole file at the path into memory if it is not larger than the maximum size. A
+    /// file that was cut short while written with [`Self::write_file`] is restored first.
+    pub(crate) async fn read_file(
+        &mut self,
+        path: &str,
+        maximum_size: u32,
+        priority: ReadPriority,
+    ) -> Result<Vec<u8>, ReadFileError> {
+        if let Err(error) = self.restore(path, maximum_size, priority).await {
+            warn!(
+                "Failed to restore {} from its copy: {:?}",
+                path,
+                defmt::Debug2Format(&error)
+            );
+        }
+
+        self.read_whole_file(path, maximum_size, priority).await
+    }
+
+    async fn read_whole_file(
+        &mut self,
+        path: &str,
+        maximum_size: u32,
+        priority: ReadPriority,
+    ) -> Result<Vec<u8>, ReadFileError> {
+        let file = self.open(path, Mode::ReadOnly).await?;
+        let result = self.read_open_file(file, maximum_size, priority).await;
+
+        if let Err(error) = self.close(file).await {
+            defmt::warn!("Failed to close file: {:?}", error);
+        }
+
+        result
+    }
+
+    /// Replaces the content of the file at the path or creates it. The data is written to a copy
+    /// first, so the file can be restored if power is lost while it is written.
+    pub(crate) async fn write_file(
+        &mut self,
+        path: &str,
+        data: &[u8],
+    ) -> Result<(), WriteFileError> {
+        let copy = copy_path(path);
+        self.write_whole_file(&copy, &[data, COPY_END]).await?;
+        self.write_whole_file(path, &[data]).await?;
+
+        // A complete copy that is left behind only restores the same content
+        if let Err(error) = self.remove(&copy).await {
+            warn!(
+                "Failed to remove {}: {:?}",
+                copy.as_str(),
+                defmt::Debug2Format(&error)
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Replaces the content of the file with the parts one after another
+    async fn write_whole_file(
+        &mut self,
+        path: &str,
+        parts: &[&[u8]],
+    ) -> Result<(), WriteFileError> {
+        let file = self.open(path, Mode::ReadWriteCreateOrTruncate).await?;
+        let mut result = Ok(());
+        for part in parts {
+            result = self.write(file, part).await;
+            if result.is_err() {
+                break;
+            }
+        }
+
+        // Closing flushes the data to the card
+        let closed = self.close(file).await;
+        result.and(closed).map_err(WriteFileError::Write)
+    }
+
+    /// Writes the file again from its copy if writing it was cut short. The copy is removed either
+    /// way, as a copy that was cut short means the file wasn't touched yet.
+    async fn restore(
+        &mut self,
+        path: &str,
+        maximum_size: u32,
+        priority: ReadPriority,
+    ) -> Result<(), RestoreError> {
+        let copy_path = copy_path(path);
+        // Can't truncate as the end marker is a few bytes
+        let maximum_size = maximum_size.saturating_add(COPY_END.len() as u32);
+        let copy = match self
+            .read_whole_file(&copy_path, maximum_size, priority)
+            .await
+        {
+            Ok(copy) => copy,
+            Err(ReadFileError::Open(error)) if error.is_not_found() => return Ok(()),
+            Err(error) => return Err(error.into()),
+        };
+
+        if let Some(data) = copy.strip_suffix(COPY_END) {
+            warn!("Restoring {} from its copy", path);
+            self.write_whole_file(path, &[data]).await?;
+        }
+
+        self.remove(&copy_path)
+            .await
+            .map_err(WriteFileError::from)?;
+        Ok(())
+    }
+
+    /// Writes the data at the end of the open file in chunks with other tasks getting to run in
+    /// between. The data is only flushed to the card when the file is closed.
+    pub(crate) async fn write(&mut self, file: RawFile, data: &[u8]) -> Result<(), VolumeError> {
+        let watch = supervisor::watch(Task::Storage);
+        for chunk in data.chunks(WRITE_CHUNK_SIZE) {
+            watch.check_in();
+            self.wait_for_bus().await;
+            self.volume_manager.write(file, chunk)?;
+            self.flush().await?;
+            yield_now().await;
+        }
+
+        Ok(())
+    }
+
+    async fn read_open_file(
+        &mut self,
+        file: RawFile,
+        maximum_size: u32,
+        priority: ReadPriority,
+    ) -> Result<Vec<u8>, ReadFileError> {
+        let length = self.length(file).await.map_err(ReadFileError::Read)?;
+        if length > maximum_size {
+            return Err(ReadFileError::TooLarge(length));
+        }
+
+        // Can't truncate as the length is at most the maximum size which is a reasonable amount
+        let mut buffer = vec![0; length as usize];
+        let read = self
+            .read_at(file, 0, &mut buffer, priority)
+            .await
+            .map_err(ReadFileError::Read)?;
+        buffer.truncate(read);
+
+        Ok(buffer)
+    }
+}
+
+/// Splits an absolute path into the directories and the file name
+fn split_path(path: &str) -> (&str, &str) {
+    let path = path.trim_start_matches('/');
+    path.rsplit_once('/').unwrap_or(("", path))
+}
+
+/// Path of the copy that is kept while the file at the path is replaced. Short names leave no room
+/// for a longer extension, so the last character of it becomes a tilde.
+fn copy_path(path: &str) -> String {
+    let (_, file_name) = split_path(path);
+    let mut copy = String::from(path);
+    match file_name.rsplit_once('.') {
+        Some((_, extension)) if !extension.is_empty() => {
+            copy.pop();
+        }
+        _ => copy.push('.'),
+    }
+    copy.push('~');
+    copy
+}
@@ -0,0 +1,69 @@
+//! The reader has no card detect pin, so the slot is probed now and then to notice a card being
+//! removed or inserted. A removed card is unmounted and a newly inserted one mounted, so tasks only
+//! ever see a card that is there or none.
+//!
+//! Tasks hold the lock of the card for a whole operation, so a card is never swapped out in the
+//! middle of one. Operations on a card that was pulled fail with an error like any other failed read.
+
+use defmt::{info, warn};
+use embassy_time::Timer;
+
+use crate::SharedSdCard;
+use crate::event_bus::{self, Event};
+use crate::sd_card::Unmounted;
+
+/// Probing takes a read from the card, so it is not done too often
+const PROBE_INTERVAL_SECONDS: u64 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub(crate) enum CardChange {
+    /// A card was inserted and mounted
+    Inserted,
+    /// The card was removed and unmounted
+    Removed,
+}
+
+/// Keeps the shared card in line with the slot and publishes the changes. Takes the slot if no card
+/// is mounted.
+pub(crate) async fn watch(sd_card: &SharedSdCard, mut unmounted: Option<Unmounted>) {
+    loop {
+        Timer::after_secs(PROBE_INTERVAL_SECONDS).await;
+
+        let mut mounted = sd_card.lock().await;
+        if let Some(card) = mounted.as_mut() {
+            if card.is_present().await {
+                continue;
+            }
+
+            warn!("SD card removed");
+            unmounted = mounted.take().map(|card| card.unmount());
+            event_bus::publish(Event::SdCard(CardChange::Removed));
+            continue;
+        }
+
+        // The slot is always kept while nothing is mounted
+        let Some(slot) = unmounted.take() else {
+            continue;
+        };
+
+        if !slot.is_inserted().await {
+            unmounted = Some(slot);
+            continue;
+        }
+
+        match slot.mount().await {
+            Ok(card) => {
+                info!("SD card inserted");
+                *mounted = Some(card);
+                event_bus::publish(Event::SdCard(CardChange::Inserted));
+            }
+            Err((error, slot)) => {
+                warn!(
+                    "Failed to mount inserted SD card: {:?}",
+                    defmt::Debug2Format(&error)
+                );
+                unmounted = Some(slot);
+            }
+        }
+    }
+}
@@ -0,0 +1,250 @@
+//! User preferences. Each profile keeps its own in a small text file with one `key=value` per line.
+//! The last saved settings are also kept in flash, so they still apply when there is no SD card.
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use embedded_storage::{ReadStorage, Storage};
+use esp_storage::FlashStorageError;
+
+use crate::flash::{self, FindPartitionError, Partition, SharedFlash};
+use crate::hyphenation;
+use crate::input::Calibration;
+use crate::library::{Filter, Sort};
+use crate::library_screen;
+use crate::profile::Profile;
+use crate::sd_card::{ReadFileError, ReadPriority, SdCard, VolumeError, WriteFileError};
+use crate::theme;
+
+const FILE_NAME: &str = "SETTINGS.TXT";
+/// Way more than the settings need, but keeps a broken file from taking up all memory
+const MAXIMUM_FILE_SIZE: u32 = 1024;
+/// Name of the data partition in partition-table.csv. It is laid out for the NVS library of
+/// ESP-IDF, which the firmware does not use, so it is free for the settings.
+const PARTITION_LABEL: &str = "nvs";
+const MAGIC: [u8; 4] = *b"SETS";
+/// Magic followed by the length of the settings text
+const HEADER_SIZE: usize = 6;
+
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum SaveSettingsError {
+    #[error("Failed to create profile directory")]
+    CreateDirectory(VolumeError),
+    #[error("Failed to write settings file")]
+    Write(#[from] WriteFileError),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum LoadStoredSettingsError {
+    #[error("Failed to read from flash")]
+    Read(FlashStorageError),
+    #[error("No settings were saved yet")]
+    NoSettings,
+    #[error("Stored settings are larger than the maximum size")]
+    TooLarge(u16),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum StoreSettingsError {
+    #[error("Settings are larger than the maximum size")]
+    TooLarge,
+    #[error("Failed to read from flash")]
+    Read(FlashStorageError),
+    #[error("Failed to write to flash")]
+    Write(FlashStorageError),
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, defmt::Format)]
+pub(crate) struct Settings {
+    /// White text on black which also makes full refreshes less glaring at night
+    pub(crate) dark_mode: bool,
+    /// None for the built-in theme
+    pub(crate) theme: Option<theme::Name>,
+    /// Replaces the body font size of the theme. None to keep the size of the theme.
+    pub(crate) font_size: Option<theme::Font>,
+    /// Names the hyphenation patterns on the SD card. None to only break lines between words.
+    pub(crate) language: Option<hyphenation::Language>,
+    /// Whether the library shows the titles in a list or the covers in a grid
+    pub(crate) library_view: library_screen::View,
+    pub(crate) library_sort: Sort,
+    /// Which formats the library shows
+    pub(crate) library_filter: Filter,
+    /// Thresholds of the buttons measured on this unit. None for the built-in ones.
+    pub(crate) button_calibration: Option<Calibration>,
+    /// Quicker page turns that leave more ghosting behind
+    pub(crate) turbo_page: bool,
+}
+
+impl Settings {
+    pub(crate) async fn load(
+        sd_card: &mut SdCard,
+        profile: &Profile,
+    ) -> Result<Self, ReadFileError> {
+        let content = sd_card
+            .read_file(
+                &profile.path(FILE_NAME),
+                MAXIMUM_FILE_SIZE,
+                ReadPriority::Interactive,
+            )
+            .await?;
+
+        Ok(Self::parse(&String::from_utf8_lossy(&content)))
+    }
+
+    pub(crate) async fn save(
+        &self,
+        sd_card: &mut SdCard,
+        profile: &Profile,
+    ) -> Result<(), SaveSettingsError> {
+        sd_card
+            .create_directories(&profile.directory())
+            .await
+            .map_err(SaveSettingsError::CreateDirectory)?;
+
+        sd_card
+            .write_file(&profile.path(FILE_NAME), self.serialize().as_bytes())
+            .await?;
+
+        Ok(())
+    }
+
+    /// Unknown keys and invalid values are skipped to be able to read files from other versions
+    fn parse(content: &str) -> Self {
+        let mut settings = Self::default();
+        for line in content.lines() {
+            let Some((key, value)) = line.split_once('=') else {
+                continue;
+            };
+
+            let value = value.trim();
+            match key.trim() {
+                "dark_mode" => {
+                    if let Ok(dark_mode) = value.parse() {
+                        settings.dark_mode = dark_mode;
+                    }
+                }
+                "theme" => settings.theme = theme::Name::try_from(value).ok(),
+                "font_size" => settings.font_size = theme::Font::parse(value),
+                "language" => settings.language = hyphenation::Language::try_from(value).ok(),
+                "library_view" => {
+                    if let Some(library_view) = library_screen::View::parse(value) {
+                        settings.library_view = library_view;
+                    }
+                }
+                "library_sort" => {
+                    if let Some(library_sort) = Sort::parse(value) {
+                        settings.library_sort = library_sort;
+                    }
+                }
+                "library_filter" => {
+                    if let Some(library_filter) = Filter::parse(value) {
+                        settings.library_filter = library_filter;
+                    }
+                }
+                "button_calibration" => settings.button_calibration = Calibration::parse(value),
+                "turbo_page" => {
+                    if let Ok(turbo_page) = value.parse() {
+                        settings.turbo_page = turbo_page;
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        settings
+    }
+
+    fn serialize(&self) -> String {
+        let mut content = format!(
+            "dark_mode={}\nlibrary_view={}\nlibrary_sort={}\nlibrary_filter={}\nturbo_page={}\n",
+            self.dark_mode,
+            self.library_view.as_str(),
+            self.library_sort.as_str(),
+            self.library_filter.as_str(),
+            self.turbo_page
+        );
+        if let Some(theme) = &self.theme {
+            content.push_str(&format!("theme={theme}\n"));
+        }
+
+        if let Some(font_size) = self.font_size {
+            content.push_str(&format!("font_size={}\n", font_size.as_str()));
+        }
+
+        if let Some(language) = &self.language {
+            content.push_str(&format!("language={language}\n"));
+        }
+
+        if let Some(calibration) = &self.button_calibration {
+            content.push_str(&format!("button_calibration={calibration}\n"));
+        }
+
+        content
+    }
+}
+
+/// Copy of the settings in flash for when there is no SD card
+pub(crate) struct SettingsStorage {
+    flash: &'static SharedFlash,
+    partition: Partition,
+}
+
+impl SettingsStorage {
+    pub(crate) fn new(flash: &'static SharedFlash) -> Result<Self, FindPartitionError> {
+        let partition = flash::find_partition(flash, PARTITION_LABEL)?;
+        Ok(Self { flash, partition })
+    }
+
+    fn read(&self, offset: u32, buffer: &mut [u8]) -> Result<(), FlashStorageError> {
+        self.flash
+            .lock(|flash| flash.borrow_mut().read(offset, buffer))
+    }
+
+    pub(crate) fn load(&self) -> Result<Settings, LoadStoredSettingsError> {
+        let mut header = [0; HEADER_SIZE];
+        self.read(self.partition.offset, &mut header)
+            .map_err(LoadStoredSettingsError::Read)?;
+
+        let (magic, length) = header.split_at(MAGIC.len());
+        if magic != MAGIC {
+            return Err(LoadStoredSettingsError::NoSettings);
+        }
+
+        let length = u16::from_le_bytes([length[0], length[1]]);
+        if u32::from(length) > MAXIMUM_FILE_SIZE {
+            return Err(LoadStoredSettingsError::TooLarge(length));
+        }
+
+        let mut content = vec![0; usize::from(length)];
+        self.read(self.partition.offset + HEADER_SIZE as u32, &mut content)
+            .map_err(LoadStoredSettingsError::Read)?;
+
+        Ok(Settings::parse(&String::from_utf8_lossy(&content)))
+    }
+
+    /// Flash is only written if the settings changed to not wear it out
+    pub(crate) fn save(&self, settings: &Settings) -> Result<(), StoreSettingsError> {
+        let content = settings.serialize();
+        let length = u16::try_from(content.len())
+            .ok()
+            .filter(|length| u32::from(*length) <= MAXIMUM_FILE_SIZE)
+            .ok_or(StoreSettingsError::TooLarge)?;
+
+        let mut stored = Vec::with_capacity(HEADER_SIZE + content.len());
+        stored.extend_from_slice(&MAGIC);
+        stored.extend_from_slice(&length.to_le_bytes());
+        stored.extend_from_slice(content.as_bytes());
+
+        let mut current = vec![0; stored.len()];
+        self.read(self.partition.offset, &mut current)
+            .map_err(StoreSettingsError::Read)?;
+        if current == stored {
+            return Ok(());
+        }
+
+        self.flash
+            .lock(|flash| flash.borrow_mut().write(self.partition.offset, &stored))
+            .map_err(StoreSettingsError::Write)
+    }
+}
@@ -0,0 +1,249 @@
+//! List of the settings of the active profile. Entries either flip a setting right away or open
+//! their own screen, which is left to the caller as those need the SD card and the profile.
+//! Settings that are on or off are rows with a checkbox.
+
+use defmt::error;
+use embedded_graphics::Drawable;
+use embedded_graphics::mono_font::MonoTextStyle;
+use embedded_graphics::mono_font::ascii::FONT_10X20;
+use embedded_graphics::pixelcolor::BinaryColor;
+use embedded_graphics::prelude::{OriginDimensions, Point, Size};
+use embedded_graphics::primitives::Rectangle;
+use embedded_graphics::text::Text;
+
+use crate::SharedDisplay;
+use crate::eink_display::Frame;
+use crate::input::{Analog, Button};
+use crate::library_screen::View;
+use crate::settings::Settings;
+use crate::theme::Font;
+use crate::widget::{ListView, Response, Slider, Toggle};
+
+/// Built into the firmware, so it can be read without an SD card
+pub(crate) const MANUAL: &str = include_str!("../assets/manual.txt");
+/// Where the settings list starts below the title
+const LIST_TOP: i32 = 45;
+/// Presses of left on the first entry that reveal the hidden entries
+const REVEAL_PRESSES: u8 = 5;
+/// By their value on the font size slider, where 0 follows the theme
+const FONT_SIZES: [Option<Font>; 5] = [
+    None,
+    Some(Font::Small),
+    Some(Font::Medium),
+    Some(Font::Large),
+    Some(Font::ExtraLarge),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub(crate) enum Entry {
+    DarkMode,
+    FontSize,
+    Theme,
+    LibraryView,
+    TurboPage,
+    /// Sets up the WiFi network from a phone over Bluetooth
+    #[cfg(feature = "radio")]
+    Wifi,
+    Manual,
+    BatteryHistory,
+    CalibrateButtons,
+    /// Hidden until revealed, as it is only of use for development
+    Benchmark,
+}
+
+/// The visible entries come first, followed by the hidden ones
+const ENTRIES: &[Entry] = &[
+    Entry::DarkMode,
+    Entry::FontSize,
+    Entry::Theme,
+    Entry::LibraryView,
+    Entry::TurboPage,
+    #[cfg(feature = "radio")]
+    Entry::Wifi,
+    Entry::Manual,
+    Entry::BatteryHistory,
+    Entry::CalibrateButtons,
+    Entry::Benchmark,
+];
+/// All but the benchmark
+const VISIBLE_ENTRIES: usize = ENTRIES.len() - 1;
+
+/// Keeps the selection while the entries open their screens
+pub(crate) struct SettingsScreen {
+    list: ListView,
+    /// Presses of left on the first entry in a row
+    reveal_presses: u8,
+}
+
+impl SettingsScreen {
+    pub(crate) fn new() -> Self {
+        Self {
+            list: ListView::new(VISIBLE_ENTRIES, 0),
+            reveal_presses: 0,
+        }
+    }
+
+    /// Lets the reader pick an entry with the buttons. Returns None if the reader backed out.
+    pub(crate) async fn select(
+        &mut self,
+        settings: &Settings,
+        analog: &mut Analog<'_>,
+        display: &SharedDisplay,
+        frame: &mut Frame,
+    ) -> Option<Entry> {
+        loop {
+            self.draw(settings, frame);
+            show(display, frame).await;
+
+            loop {
+                let previous = self.list.selected();
+                match self.handle(analog.wait_for_press().await) {
+                    Response::Ignored => {}
+                    Response::Changed => {
+                        // Only the highlight moves unless the list scrolls or more entries show
+                        match self.list.move_highlight(frame, &list_area(frame), previous) {
+                            Ok(true) => show(display, frame).await,
+                            Ok(false) => break,
+                            Err(error) => {
+                                error!("Failed to move highlight: {:?}", error);
+                                break;
+                            }
+                        }
+                    }
+                    Response::Confirmed => return Some(ENTRIES[self.list.selected()]),
+                    Response::Cancelled => return None,
+                }
+            }
+        }
+    }
+
+    /// Left on the first entry doesn't wrap around to the last entry but counts towards revealing
+    /// the hidden entries. Any other button starts the count over.
+    fn handle(&mut self, button: Button) -> Response {
+        if button != Button::Left || self.list.selected() != 0 {
+            self.reveal_presses = 0;
+            return self.list.handle(button);
+        }
+
+        self.reveal_presses = self.reveal_presses.saturating_add(1);
+        if self.reveal_presses == REVEAL_PRESSES {
+            self.list = ListView::new(ENTRIES.len(), 0);
+            Response::Changed
+        } else {
+            Response::Ignored
+        }
+    }
+
+    fn draw(&mut self, settings: &Settings, frame: &mut Frame) {
+        // White
+        frame.fill(0xFF);
+
+        let style = MonoTextStyle::new(&FONT_10X20, BinaryColor::On);
+        if let Err(error) = Text::new("Settings", Point::new(10, 30), style).draw(frame) {
+            error!("Failed to draw settings: {:?}", error);
+        }
+
+        let area = list_area(frame);
+        let label = |entry: Entry| match entry {
+            Entry::DarkMode => "Dark mode",
+            Entry::FontSize => match settings.font_size {
+                None => "Font size: theme",
+                Some(Font::Small) => "Font size: small",
+                Some(Font::Medium) => "Font size: medium",
+                Some(Font::Large) => "Font size: large",
+                Some(Font::ExtraLarge) => "Font size: x-large",
+            },
+            Entry::Theme => "Theme",
+            Entry::LibraryView => match settings.library_view {
+                View::List => "Library view: list",
+                View::Grid => "Library view: grid",
+            },
+            Entry::TurboPage => "Turbo page",
+            #[cfg(feature = "radio")]
+            Entry::Wifi => "WiFi",
+            Entry::Manual => "User manual",
+            Entry::BatteryHistory => "Battery history",
+            Entry::CalibrateButtons => "Calibrate buttons",
+            Entry::Benchmark => "Benchmark",
+        };
+        let result = self
+            .list
+            .draw_rows(frame, &area, |frame, index, row| match ENTRIES[index] {
+                Entry::DarkMode => {
+                    Toggle::new(label(Entry::DarkMode), settings.dark_mode).draw(frame, row)
+                }
+                Entry::TurboPage => {
+                    Toggle::new(label(Entry::TurboPage), settings.turbo_page).draw(frame, row)
+                }
+                entry => ListView::draw_label(frame, row, label(entry)),
+            });
+        if let Err(error) = result {
+            error!("Failed to draw settings: {:?}", error);
+        }
+    }
+}
+
+/// Lets the reader pick the font size with a slider, where 0 follows the theme. Returns the font
+/// size or None if the reader backed out.
+pub(crate) async fn pick_font_size(
+    font_size: Option<Font>,
+    analog: &mut Analog<'_>,
+    display: &SharedDisplay,
+    frame: &mut Frame,
+) -> Option<Option<Font>> {
+    let value = FONT_SIZES
+        .iter()
+        .position(|&size| size == font_size)
+        .unwrap_or(0);
+    // Can't truncate as there are only a few sizes
+    let last = (FONT_SIZES.len() - 1) as u16;
+    let mut slider = Slider::new("Font size", 0..=last, 1, value as u16);
+    loop {
+        draw_font_size(&slider, frame);
+        show(display, frame).await;
+
+        loop {
+            match slider.handle(analog.wait_for_press().await) {
+                Response::Ignored => {}
+                Response::Changed => break,
+                Response::Confirmed => return Some(FONT_SIZES[usize::from(slider.value())]),
+                Response::Cancelled => return None,
+            }
+        }
+    }
+}
+
+fn draw_font_size(slider: &Slider, frame: &mut Frame) {
+    // White
+    frame.fill(0xFF);
+
+    let size = frame.size();
+    // Two rows for the label and the track
+    let area = Rectangle::new(Point::new(0, LIST_TOP), Size::new(size.width, 60));
+    if let Err(error) = slider.draw(frame, &area) {
+        error!("Failed to draw font size: {:?}", error);
+    }
+
+    let style = MonoTextStyle::new(&FONT_10X20, BinaryColor::On);
+    let hint = "0 follows the theme\n\nLeft/right: Change\nConfirm: Save";
+    if let Err(error) = Text::new(hint, Point::new(10, LIST_TOP + 90), style).draw(frame) {
+        error!("Failed to draw font size: {:?}", error);
+    }
+}
+
+fn list_area(frame: &Frame) -> Rectangle {
+    let size = frame.size();
+    Rectangle::new(
+        Point::new(0, LIST_TOP),
+        Size::new(size.width, size.height - LIST_TOP as u32),
+    )
+}
+
+async fn show(display: &SharedDisplay, frame: &Frame) {
+    if let Err(error) = display.animate(frame).await {
+        error!(
+            "Failed to display settings: {:?}",
+            defmt::Debug2Format(&error)
+        );
+    }
+}
@@ -0,0 +1,102 @@
+//! Actions for the chords, which work on every screen as they don't go through the screen that
+//! waits for a press. Set in the `[chords]` section of the config file with lines like
+//! `confirm+up = "screenshot"`, where the first button is back, confirm, left or right and the
+//! second up or down. `"none"` takes the action off a chord.
+
+use defmt::{info, warn};
+
+use crate::display_task::DisplayHandle;
+use crate::eink_display::RefreshMode;
+use crate::input::{Chord, chord};
+use crate::{SharedSdCard, diagnostics};
+
+/// Chords that have an action without a config file
+const DEFAULT_SHORTCUTS: [(&str, Action); 3] = [
+    ("confirm+up", Action::Screenshot),
+    ("confirm+down", Action::FullRefresh),
+    ("back+down", Action::Overlay),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub(crate) enum Action {
+    /// Saves what the panel shows on the SD card
+    Screenshot,
+    /// Shows or hides the memory overlay
+    Overlay,
+    /// Clears the ghosting
+    FullRefresh,
+}
+
+impl Action {
+    /// None for anything else than the names of the actions like `"none"`
+    pub(crate) fn parse(value: &str) -> Option<Self> {
+        match value {
+            "screenshot" => Some(Self::Screenshot),
+            "overlay" => Some(Self::Overlay),
+            "full_refresh" => Some(Self::FullRefresh),
+            _ => None,
+        }
+    }
+}
+
+/// The action of each chord
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub(crate) struct Shortcuts([Option<Action>; Chord::COUNT]);
+
+impl Default for Shortcuts {
+    fn default() -> Self {
+        let mut shortcuts = Self([None; Chord::COUNT]);
+        for (chord, action) in DEFAULT_SHORTCUTS {
+            if let Some(chord) = Chord::parse(chord) {
+                shortcuts.set(chord, Some(action));
+            }
+        }
+        shortcuts
+    }
+}
+
+impl Shortcuts {
+    pub(crate) fn set(&mut self, chord: Chord, action: Option<Action>) {
+        self.0[chord.index()] = action;
+    }
+
+    fn get(&self, chord: Chord) -> Option<Action> {
+        self.0[chord.index()]
+    }
+}
+
+/// Carries out the action of each chord that is pressed
+#[embassy_executor::task]
+pub(crate) async fn run(
+    shortcuts: Shortcuts,
+    display: DisplayHandle,
+    sd_card: &'static SharedSdCard,
+) {
+    loop {
+        let chord = chord::wait().await;
+        let Some(action) = shortcuts.get(chord) else {
+            info!("No shortcut for {}", chord);
+            continue;
+        };
+
+        info!("Shortcut {} for {}", action, chord);
+        match action {
+            Action::Screenshot => match display.save_shown(sd_card).await {
+                Ok(path) => info!("Saved screenshot as {}", path.as_str()),
+                Err(error) => warn!(
+                    "Failed to save screenshot: {:?}",
+                    defmt::Debug2Format(&error)
+                ),
+            },
+            Action::Overlay => diagnostics::set_overlay_shown(!diagnostics::is_overlay_shown()),
+            Action::FullRefresh => {
+                if let Err(error) = display.refresh_shown(RefreshMode::Full).await {
+                    warn!(
+                        "Failed to refresh what is shown: {:?}",
+                        defmt::Debug2Format(&error)
+                    );
+                }
+            }
+        }
+    }
+}
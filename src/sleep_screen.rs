@@ -0,0 +1,91 @@
+//! E-ink keeps its image without power, so instead of a blank screen the panel shows an image of
+//! the reader's choice during deep sleep. That is the image of the active theme, a random image from
+//! the sleep image directory or else one of the fixed sleep image paths.
+
+use alloc::format;
+
+use defmt::{error, info};
+use esp_hal::rng::Rng;
+
+use crate::eink_display::Frame;
+use crate::image::{self, dither::Method};
+use crate::sd_card::SdCard;
+use crate::theme;
+
+const DIRECTORY: &str = "/SLEEP";
+const PATHS: [&str; 2] = ["/SLEEP.BMP", "/SLEEP.PNG"];
+
+/// Draws the sleep image into the frame and returns whether there was one. The frame is left white
+/// if there is none or it fails to load.
+pub(crate) async fn draw(sd_card: &mut SdCard, frame: &mut Frame) -> bool {
+    if let Some(path) = theme::active().sleep_screen
+        && load(sd_card, &path, frame).await
+    {
+        return true;
+    }
+
+    let is_drawn = match pick_from_directory(sd_card).await {
+        Some(path) => load(sd_card, &path, frame).await,
+        None => {
+            let mut is_drawn = false;
+            for path in PATHS {
+                if load(sd_card, path, frame).await {
+                    is_drawn = true;
+                    break;
+                }
+            }
+
+            is_drawn
+        }
+    };
+
+    if !is_drawn {
+        // Could have been drawn partially
+        frame.fill(0xFF);
+    }
+
+    is_drawn
+}
+
+async fn pick_from_directory(sd_card: &mut SdCard) -> Option<alloc::string::String> {
+    let entries = match sd_card.list_directory(DIRECTORY).await {
+        Ok(entries) => entries,
+        Err(embedded_sdmmc::Error::NotFound) => return None,
+        Err(error) => {
+            error!("Failed to list sleep images: {:?}", error);
+            return None;
+        }
+    };
+
+    let mut images = entries.iter().filter(|entry| {
+        !entry.attributes.is_directory() && image::is_supported(&format!("{}", entry.name))
+    });
+
+    let count = images.clone().count();
+    if count == 0 {
+        return None;
+    }
+
+    // Can't truncate as usize is 32 bits
+    let index = Rng::new().random() as usize % count;
+    let entry = images.nth(index)?;
+    Some(format!("{DIRECTORY}/{}", entry.name))
+}
+
+async fn load(sd_card: &mut SdCard, path: &str, frame: &mut Frame) -> bool {
+    match image::load(sd_card, path, frame, Method::FloydSteinberg).await {
+        Ok(()) => {
+            info!("Showing sleep image {}", path);
+            true
+        }
+        Err(error) if error.is_not_found() => false,
+        Err(error) => {
+            error!(
+                "Failed to load sleep image {}: {:?}",
+                path,
+                defmt::Debug2Format(&error)
+            );
+            false
+        }
+    }
+}
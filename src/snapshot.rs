@@ -0,0 +1,342 @@
+//! Keeps a compressed copy of the last rendered screen in flash to push it to the panel right after
+//! boot while the rest of the system is still starting. This masks the start up time like
+//! commercial readers do.
+//!
+//! What the panel showed before the reader turned off is also kept in RTC memory, which survives
+//! deep sleep. On wake up it brings back the page right away without laying it out again. Unlike
+//! flash, it is lost when the battery is disconnected and has room for a few KB only.
+
+use alloc::vec;
+use alloc::vec::Vec;
+use embedded_storage::{ReadStorage, Storage};
+use esp_storage::FlashStorageError;
+use portable_atomic::{AtomicU32, Ordering};
+
+use crate::eink_display::Frame;
+use crate::flash::{self, FindPartitionError, Partition, SharedFlash};
+use crate::memory;
+
+/// Name of the data partition in partition-table.csv
+const PARTITION_LABEL: &str = "snapshot";
+const MAGIC: [u8; 4] = *b"SNAP";
+/// Magic followed by the compressed length
+const HEADER_SIZE: usize = 8;
+/// Longest run or literal sequence one PackBits header byte can describe
+const MAXIMUM_RUN: usize = 128;
+/// Runs shorter than this are cheaper to keep in a literal sequence
+const MINIMUM_RUN: usize = 3;
+/// Compressed frames larger than this are not kept in RTC memory. RTC fast memory has 8 KiB for
+/// everything that is kept through deep sleep.
+const RTC_SIZE: usize = 4 * 1024;
+const RTC_MAGIC: u32 = u32::from_le_bytes(*b"RSNP");
+const RTC_MAGIC_INDEX: usize = 0;
+const RTC_LENGTH_INDEX: usize = 1;
+const RTC_CHECKSUM_INDEX: usize = 2;
+/// The compressed data follows the magic, the length and the checksum
+const RTC_HEADER_WORDS: usize = 3;
+
+/// Compressed frame kept through deep sleep, see [`keep`]. Only load and store atomics are
+/// available, so it is written word by word with the magic last. The checksum catches the random
+/// content after power loss.
+#[esp_hal::ram(unstable(rtc_fast, persistent))]
+static RTC_SNAPSHOT: [AtomicU32; RTC_HEADER_WORDS + RTC_SIZE / 4] =
+    [const { AtomicU32::new(0) }; RTC_HEADER_WORDS + RTC_SIZE / 4];
+
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum LoadSnapshotError {
+    #[error("Failed to read from flash")]
+    Read(FlashStorageError),
+    #[error("No snapshot was saved yet")]
+    NoSnapshot,
+    #[error("Snapshot is larger than the maximum size")]
+    TooLarge(u32),
+    #[error("Failed to decompress snapshot")]
+    Decompress(#[from] DecompressError),
+    #[error("Not enough memory to load the snapshot")]
+    OutOfMemory,
+    #[error("Snapshot does not match its checksum")]
+    Corrupted,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum SaveSnapshotError {
+    #[error("Not enough memory to compress the frame")]
+    OutOfMemory,
+    #[error("Frame does not compress to the maximum snapshot size")]
+    TooLarge,
+    #[error("Failed to read from flash")]
+    Read(FlashStorageError),
+    #[error("Failed to write to flash")]
+    Write(FlashStorageError),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum DecompressError {
+    #[error("Compressed data ends in the middle of a sequence or before the frame is filled")]
+    Truncated,
+    #[error("Compressed data is larger than a frame")]
+    Overflow,
+}
+
+pub(crate) struct SnapshotStorage {
+    flash: &'static SharedFlash,
+    partition: Partition,
+}
+
+impl SnapshotStorage {
+    pub(crate) fn new(flash: &'static SharedFlash) -> Result<Self, FindPartitionError> {
+        let partition = flash::find_partition(flash, PARTITION_LABEL)?;
+        Ok(Self { flash, partition })
+    }
+
+    fn maximum_size(&self) -> usize {
+        memory::MAXIMUM_SNAPSHOT_SIZE.min(self.partition.size - HEADER_SIZE)
+    }
+
+    fn read(&self, offset: u32, buffer: &mut [u8]) -> Result<(), FlashStorageError> {
+        self.flash
+            .lock(|flash| flash.borrow_mut().read(offset, buffer))
+    }
+
+    /// Restores the saved snapshot into the frame. The frame content is undefined on failure.
+    pub(crate) fn load(&mut self, frame: &mut Frame) -> Result<(), LoadSnapshotError> {
+        let mut header = [0; HEADER_SIZE];
+        self.read(self.partition.offset, &mut header)
+            .map_err(LoadSnapshotError::Read)?;
+
+        let (magic, length) = header.split_at(MAGIC.len());
+        if magic != MAGIC {
+            return Err(LoadSnapshotError::NoSnapshot);
+        }
+
+        let length = u32::from_le_bytes([length[0], length[1], length[2], length[3]]);
+        // Can't truncate as usize is 32 bits
+        if length as usize > self.maximum_size() {
+            return Err(LoadSnapshotError::TooLarge(length));
+        }
+
+        let mut compressed = vec![0; length as usize];
+        self.read(self.partition.offset + HEADER_SIZE as u32, &mut compressed)
+            .map_err(LoadSnapshotError::Read)?;
+
+        decompress(&compressed, frame)?;
+        Ok(())
+    }
+
+    /// Saves the frame as the new snapshot. Flash is only written if the frame changed since the
+    /// last save to not wear it out.
+    pub(crate) fn save(&mut self, frame: &Frame) -> Result<(), SaveSnapshotError> {
+        let maximum_size = self.maximum_size();
+        let mut snapshot = Vec::new();
+        snapshot
+            .try_reserve_exact(HEADER_SIZE + maximum_size)
+            .map_err(|_| SaveSnapshotError::OutOfMemory)?;
+
+        snapshot.extend_from_slice(&MAGIC);
+        snapshot.extend_from_slice(&[0; HEADER_SIZE - MAGIC.len()]);
+        compress(frame, &mut snapshot, HEADER_SIZE + maximum_size)?;
+
+        // Can't truncate as it is at most the maximum size
+        let length = (snapshot.len() - HEADER_SIZE) as u32;
+        snapshot[MAGIC.len()..HEADER_SIZE].copy_from_slice(&length.to_le_bytes());
+
+        if self.is_stored(&snapshot)? {
+            return Ok(());
+        }
+
+        // A torn write is caught by the decompression which requires the data to exactly fill a
+        // frame
+        self.flash
+            .lock(|flash| flash.borrow_mut().write(self.partition.offset, &snapshot))
+            .map_err(SaveSnapshotError::Write)
+    }
+
+    /// Compares the snapshot to what is in flash in small pieces to not need another large buffer
+    fn is_stored(&mut self, snapshot: &[u8]) -> Result<bool, SaveSnapshotError> {
+        let mut buffer = [0; 256];
+        let mut offset = self.partition.offset;
+        for chunk in snapshot.chunks(buffer.len()) {
+            let stored = &mut buffer[..chunk.len()];
+            self.read(offset, stored).map_err(SaveSnapshotError::Read)?;
+
+            if stored != chunk {
+                return Ok(false);
+            }
+
+            // Can't truncate as chunks are at most the buffer size
+            offset += chunk.len() as u32;
+        }
+
+        Ok(true)
+    }
+}
+
+/// Keeps the compressed frame in RTC memory to bring it back with [`restore`] after deep sleep.
+/// Replaces the frame that was kept before.
+pub(crate) fn keep(frame: &Frame) -> Result<(), SaveSnapshotError> {
+    let mut compressed = Vec::new();
+    compressed
+        .try_reserve_exact(RTC_SIZE)
+        .map_err(|_| SaveSnapshotError::OutOfMemory)?;
+    compress(frame, &mut compressed, RTC_SIZE)?;
+
+    RTC_SNAPSHOT[RTC_MAGIC_INDEX].store(0, Ordering::Relaxed);
+    let mut checksum = Checksum::new();
+    for (slot, chunk) in RTC_SNAPSHOT[RTC_HEADER_WORDS..]
+        .iter()
+        .zip(compressed.chunks(4))
+    {
+        let mut word = [0; 4];
+        word[..chunk.len()].copy_from_slice(chunk);
+        let word = u32::from_le_bytes(word);
+        checksum.add(word);
+        slot.store(word, Ordering::Relaxed);
+    }
+
+    // Can't truncate as it is at most the RTC size
+    RTC_SNAPSHOT[RTC_LENGTH_INDEX].store(compressed.len() as u32, Ordering::Relaxed);
+    RTC_SNAPSHOT[RTC_CHECKSUM_INDEX].store(checksum.0, Ordering::Relaxed);
+    RTC_SNAPSHOT[RTC_MAGIC_INDEX].store(RTC_MAGIC, Ordering::Relaxed);
+    Ok(())
+}
+
+/// Brings back the frame kept in RTC memory. The frame content is undefined on failure.
+pub(crate) fn restore(frame: &mut Frame) -> Result<(), LoadSnapshotError> {
+    if RTC_SNAPSHOT[RTC_MAGIC_INDEX].load(Ordering::Relaxed) != RTC_MAGIC {
+        return Err(LoadSnapshotError::NoSnapshot);
+    }
+
+    let length = RTC_SNAPSHOT[RTC_LENGTH_INDEX].load(Ordering::Relaxed);
+    // Can't truncate as usize is 32 bits
+    let length_bytes = length as usize;
+    if length_bytes > RTC_SIZE {
+        return Err(LoadSnapshotError::TooLarge(length));
+    }
+
+    let mut compressed = Vec::new();
+    compressed
+        .try_reserve_exact(length_bytes.next_multiple_of(4))
+        .map_err(|_| LoadSnapshotError::OutOfMemory)?;
+    let mut checksum = Checksum::new();
+    for slot in &RTC_SNAPSHOT[RTC_HEADER_WORDS..RTC_HEADER_WORDS + length_bytes.div_ceil(4)] {
+        let word = slot.load(Ordering::Relaxed);
+        checksum.add(word);
+        compressed.extend_from_slice(&word.to_le_bytes());
+    }
+
+    if checksum.0 != RTC_SNAPSHOT[RTC_CHECKSUM_INDEX].load(Ordering::Relaxed) {
+        return Err(LoadSnapshotError::Corrupted);
+    }
+
+    compressed.truncate(length_bytes);
+    decompress(&compressed, frame)?;
+    Ok(())
+}
+
+/// FNV-1a over words
+struct Checksum(u32);
+
+impl Checksum {
+    fn new() -> Self {
+        Self(0x811C_9DC5)
+    }
+
+    fn add(&mut self, word: u32) {
+        self.0 = (self.0 ^ word).wrapping_mul(0x0100_0193);
+    }
+}
+
+/// Length of the run of equal bytes at the start of the data
+fn run_length(data: &[u8]) -> usize {
+    let Some(first) = data.first() else {
+        return 0;
+    };
+
+    data.iter()
+        .take(MAXIMUM_RUN)
+        .take_while(|byte| *byte == first)
+        .count()
+}
+
+/// PackBits run-length encoding. Frames are mostly white with text in between, so this gets pages
+/// down to a few KB. A header byte n below 128 is followed by n + 1 literal bytes and a header byte
+/// above 128 by one byte that is repeated 257 - n times.
+fn compress(
+    data: &[u8],
+    output: &mut Vec<u8>,
+    maximum_size: usize,
+) -> Result<(), SaveSnapshotError> {
+    let mut index = 0;
+    while index < data.len() {
+        let run = run_length(&data[index..]);
+        if run >= MINIMUM_RUN {
+            if output.len() + 2 > maximum_size {
+                return Err(SaveSnapshotError::TooLarge);
+            }
+
+            // Can't truncate as the run is at most 128
+            output.push((257 - run) as u8);
+            output.push(data[index]);
+            index += run;
+            continue;
+        }
+
+        let start = index;
+        while index < data.len()
+            && index - start < MAXIMUM_RUN
+            && run_length(&data[index..]) < MINIMUM_RUN
+        {
+            index += 1;
+        }
+
+        let literal = &data[start..index];
+        if output.len() + 1 + literal.len() > maximum_size {
+            return Err(SaveSnapshotError::TooLarge);
+        }
+
+        // Can't truncate as the literal is at most 128 bytes long
+        output.push((literal.len() - 1) as u8);
+        output.extend_from_slice(literal);
+    }
+
+    Ok(())
+}
+
+/// Decodes PackBits compressed data, see [`compress`]. The data needs to fill the output exactly.
+fn decompress(mut data: &[u8], output: &mut [u8]) -> Result<(), DecompressError> {
+    let mut position = 0;
+    while let Some((&header, rest)) = data.split_first() {
+        match header {
+            0..=127 => {
+                let length = usize::from(header) + 1;
+                let literal = rest.get(..length).ok_or(DecompressError::Truncated)?;
+                output
+                    .get_mut(position..position + length)
+                    .ok_or(DecompressError::Overflow)?
+                    .copy_from_slice(literal);
+
+                position += length;
+                data = &rest[length..];
+            }
+            // No operation
+            128 => data = rest,
+            129..=255 => {
+                let length = 257 - usize::from(header);
+                let (&byte, rest) = rest.split_first().ok_or(DecompressError::Truncated)?;
+                output
+                    .get_mut(position..position + length)
+                    .ok_or(DecompressError::Overflow)?
+                    .fill(byte);
+
+                position += length;
+                data = rest;
+            }
+        }
+    }
+
+    if position != output.len() {
+        return Err(DecompressError::Truncated);
+    }
+
+    Ok(())
+}
@@ -13,6 +13,8 @@ use esp_hal::{
 };
 use static_cell::StaticCell;
 
+use crate::memory;
+
 #[derive(Debug, thiserror::Error)]
 pub(crate) enum SetUpError {
     #[error("Failed to create direct memory access (DMA) receive channel buffer")]
@@ -22,8 +24,16 @@ pub(crate) enum SetUpError {
     #[error("Failed to create SPI bus")]
     SpiBus(#[from] ConfigError),
 }
+pub(crate) type Bus<'a> = Mutex<NoopRawMutex, SpiDmaBus<'a, Async>>;
 pub(crate) type Device<'a> = SpiDevice<'a, NoopRawMutex, SpiDmaBus<'a, Async>, Output<'a>>;
 
+/// The device of the SD card and the bus it is on, which the card needs on its own for the clock
+/// cycles before the first command
+pub(crate) struct CardDevice {
+    pub(crate) device: Device<'static>,
+    pub(crate) bus: &'static Bus<'static>,
+}
+
 pub(crate) fn set_up_devices(
     spi: impl Instance + 'static,
     serial_clock: impl PeripheralOutput<'static>,
@@ -32,7 +42,7 @@ pub(crate) fn set_up_devices(
     direct_memory_access_channel: impl DmaChannelFor<AnySpi<'static>>,
     display_chip_select: impl OutputPin + 'static,
     sd_card_chip_select: impl OutputPin + 'static,
-) -> Result<(Device<'static>, Device<'static>), SetUpError> {
+) -> Result<(Device<'static>, CardDevice), SetUpError> {
     let configuration = Config::default()
         .with_frequency(Rate::from_mhz(40))
         .with_mode(esp_hal::spi::Mode::_0)
@@ -40,7 +50,7 @@ pub(crate) fn set_up_devices(
 
     // DMA = Direct Memory Access
     let (receive_buffer, receive_descriptor, transmit_buffer, transmit_descriptors) =
-        dma_buffers!(32_000);
+        dma_buffers!(memory::DMA_BUFFER_SIZE);
     let direct_memory_access_receive_buffer =
         DmaRxBuf::new(receive_descriptor, receive_buffer).map_err(SetUpError::DmaReceiveBuffer)?;
     let direct_memory_access_transmit_buffer = DmaTxBuf::new(transmit_descriptors, transmit_buffer)
@@ -61,7 +71,7 @@ pub(crate) fn set_up_devices(
 
     // Choosing to share the bus using embassy_embedded_hal over embedded_hal_bus to allow for async operations
     // Set up SPI bus sharing between devices with embassy
-    static SPI_BUS: StaticCell<Mutex<NoopRawMutex, SpiDmaBus<'static, Async>>> = StaticCell::new();
+    static SPI_BUS: StaticCell<Bus<'static>> = StaticCell::new();
     let spi_bus = Mutex::new(spi);
     let spi_bus = SPI_BUS.init(spi_bus);
 
@@ -72,7 +82,10 @@ pub(crate) fn set_up_devices(
 
     let sd_card_chip_select =
         Output::new(sd_card_chip_select, Level::High, OutputConfig::default());
-    let sd_card_spi = SpiDevice::new(spi_bus, sd_card_chip_select);
+    let sd_card_spi = CardDevice {
+        device: SpiDevice::new(spi_bus, sd_card_chip_select),
+        bus: spi_bus,
+    };
 
     Ok((display_spi, sd_card_spi))
 }
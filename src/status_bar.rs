@@ -0,0 +1,344 @@
+//! Strip along the top of the screen with the battery, the time and the reading progress. It lies
+//! in its own region of the panel, so it can be refreshed without touching the page below.
+//!
+//! Screens like the home screen and the reader draw the status bar with the rest of their frame,
+//! the reader with the progress through the book. A task brings it up to date every minute, as the
+//! minute of the [`clock`] changes once it is set, and right away when the battery starts or stops
+//! charging or the network connects or drops. It has the display task draw the change over what
+//! the panel shows and update only the strip, see
+//! [`crate::display_task::SharedDisplay::update_status_bar`]. Refreshes hold the display until
+//! they are done, so the update never interrupts a page turn, and screens without the status
+//! bar are left alone.
+
+use core::cell::RefCell;
+use core::fmt::Write;
+
+use defmt::warn;
+use embassy_futures::select::select;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::signal::Signal;
+use embassy_time::{Duration, Timer};
+use embedded_graphics::Drawable;
+use embedded_graphics::mono_font::MonoTextStyle;
+use embedded_graphics::mono_font::ascii::FONT_8X13;
+use embedded_graphics::pixelcolor::BinaryColor;
+use embedded_graphics::prelude::{DrawTarget, Point, Primitive, Size};
+use embedded_graphics::primitives::{Line, Polyline, PrimitiveStyle, Rectangle};
+use embedded_graphics::text::{Alignment, Baseline, Text, TextStyleBuilder};
+
+use portable_atomic::{AtomicBool, AtomicU8, Ordering};
+
+use crate::SharedRtc;
+use crate::battery;
+use crate::clock;
+use crate::display_task::{DisplayHandle, ShownFrameError};
+use crate::eink_display::{DrawError, Frame, Region};
+
+/// A multiple of 8 as the strip is a column band of the panel and [`REGION`] would cover more
+/// otherwise
+pub(crate) const HEIGHT: u32 = 24;
+const PADDING: i32 = 6;
+const BATTERY_SIZE: Size = Size::new(22, 11);
+/// The contact on the right of the battery icon
+const BATTERY_TIP_SIZE: Size = Size::new(2, 5);
+/// Zigzag of the charging bolt from the top right relative to its left edge in the middle
+const BOLT: [Point; 4] = [
+    Point::new(5, -6),
+    Point::new(0, 1),
+    Point::new(5, -1),
+    Point::new(0, 6),
+];
+
+const AREA: Rectangle = Rectangle::new(Point::zero(), Size::new(Frame::SIZE.width, HEIGHT));
+/// The strip in panel coordinates
+pub(crate) const REGION: Region = Region::covering(AREA).unwrap();
+/// Bytes of the frame buffer within [`REGION`]
+// Can't truncate as a u16 fits into a usize
+const REGION_BYTES: usize = REGION.width as usize / 8 * REGION.height as usize;
+
+/// How often the status bar is brought up to date, e.g. for the battery percentage
+const INTERVAL: Duration = Duration::from_secs(60);
+/// Stands for no battery percentage, as percentages only go up to 100
+const NO_PERCENTAGE: u8 = u8::MAX;
+
+/// What the status bar shows on the screens that have it
+static STATUS: Mutex<CriticalSectionRawMutex, RefCell<StatusBar>> =
+    Mutex::new(RefCell::new(StatusBar::EMPTY));
+/// Wakes the task before the interval is over
+static CHANGED: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+/// Smoothed by the battery gauge, as single readings sag under load
+static BATTERY_PERCENTAGE: AtomicU8 = AtomicU8::new(NO_PERCENTAGE);
+static IS_CONNECTED: AtomicBool = AtomicBool::new(false);
+
+/// Time of day as shown in the status bar
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub(crate) struct Time {
+    pub(crate) hour: u8,
+    pub(crate) minute: u8,
+}
+
+/// What the status bar shows. Parts that are None are left out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub(crate) struct StatusBar {
+    pub(crate) battery_percentage: Option<u8>,
+    /// Adds a bolt after the battery percentage
+    pub(crate) is_charging: bool,
+    pub(crate) time: Option<Time>,
+    /// How far into the current book in percent
+    pub(crate) progress: Option<u8>,
+    /// Connected to the WiFi network
+    pub(crate) is_connected: bool,
+}
+
+impl StatusBar {
+    const EMPTY: Self = Self {
+        battery_percentage: None,
+        is_charging: false,
+        time: None,
+        progress: None,
+        is_connected: false,
+    };
+
+    /// Where the status bar is drawn in frame coordinates
+    pub(crate) fn area() -> Rectangle {
+        AREA
+    }
+
+    /// Draws over the status bar area of the frame. Displaying [`REGION`] with `display_region`
+    /// updates only the status bar.
+    pub(crate) fn draw(&self, frame: &mut Frame) -> Result<(), DrawError> {
+        let area = Self::area();
+        frame.fill_solid(&area, BinaryColor::Off)?;
+
+        let text_style = MonoTextStyle::new(&FONT_8X13, BinaryColor::On);
+        // Can't truncate as the height is small
+        let middle = HEIGHT as i32 / 2;
+        let mut text = heapless::String::<8>::new();
+        // Where the parts on the left end
+        let mut left = Point::new(0, middle);
+
+        if let Some(percentage) = self.battery_percentage {
+            let icon_end = draw_battery(frame, Point::new(PADDING, middle), percentage)?;
+            // Can't fail as "100%" fits
+            let _ = write!(text, "{percentage}%");
+            let text_end = Text::with_text_style(
+                &text,
+                icon_end + Point::new(PADDING, 0),
+                text_style,
+                TextStyleBuilder::new()
+                    .alignment(Alignment::Left)
+                    .baseline(Baseline::Middle)
+                    .build(),
+            )
+            .draw(frame)?;
+            left = text_end;
+
+            if self.is_charging {
+                let bolt_left = Point::new(text_end.x + PADDING / 2, middle);
+                Polyline::new(&BOLT.map(|point| bolt_left + point))
+                    .into_styled(PrimitiveStyle::with_stroke(BinaryColor::On, 1))
+                    .draw(frame)?;
+                left = bolt_left + Point::new(BOLT[0].x, 0);
+            }
+        }
+
+        if self.is_connected {
+            Text::with_text_style(
+                "WiFi",
+                left + Point::new(PADDING, 0),
+                text_style,
+                TextStyleBuilder::new()
+                    .alignment(Alignment::Left)
+                    .baseline(Baseline::Middle)
+                    .build(),
+            )
+            .draw(frame)?;
+        }
+
+        if let Some(Time { hour, minute }) = self.time {
+            text.clear();
+            // Can't fail as "23:59" fits
+            let _ = write!(text, "{hour:02}:{minute:02}");
+            Text::with_text_style(
+                &text,
+                Point::new(area.center().x, middle),
+                text_style,
+                TextStyleBuilder::new()
+                    .alignment(Alignment::Center)
+                    .baseline(Baseline::Middle)
+                    .build(),
+            )
+            .draw(frame)?;
+        }
+
+        if let Some(progress) = self.progress {
+            text.clear();
+            // Can't fail as "100%" fits
+            let _ = write!(text, "{progress}%");
+            // Can't truncate as the frame is less than 1000 pixels wide
+            let right = area.size.width as i32 - PADDING;
+            Text::with_text_style(
+                &text,
+                Point::new(right, middle),
+                text_style,
+                TextStyleBuilder::new()
+                    .alignment(Alignment::Right)
+                    .baseline(Baseline::Middle)
+                    .build(),
+            )
+            .draw(frame)?;
+        }
+
+        // Separates the status bar from the page
+        let bottom = HEIGHT as i32 - 1;
+        Line::new(
+            Point::new(0, bottom),
+            Point::new(area.size.width as i32 - 1, bottom),
+        )
+        .into_styled(PrimitiveStyle::with_stroke(BinaryColor::On, 1))
+        .draw(frame)?;
+
+        Ok(())
+    }
+}
+
+/// What the status bar shows, for a screen that draws it with the rest of the frame. Empty before
+/// the task collected it.
+pub(crate) fn current() -> StatusBar {
+    STATUS.lock(|status| *status.borrow())
+}
+
+/// Called by the reader for each page it draws and with None once the book is closed. Doesn't wake
+/// the task, as the reader draws the status bar with the page.
+pub(crate) fn set_progress(progress: Option<u8>) {
+    STATUS.lock(|status| status.borrow_mut().progress = progress);
+}
+
+/// Called by the battery gauge after each quiet reading. None until the gauge has a reading.
+pub(crate) fn set_battery_percentage(percentage: Option<u8>) {
+    BATTERY_PERCENTAGE.store(percentage.unwrap_or(NO_PERCENTAGE), Ordering::Relaxed);
+}
+
+/// Called when the network connects or drops
+pub(crate) fn set_connected(is_connected: bool) {
+    IS_CONNECTED.store(is_connected, Ordering::Relaxed);
+    update();
+}
+
+/// Brings the status bar up to date right away instead of at the end of the interval, e.g. when
+/// charging started
+pub(crate) fn update() {
+    CHANGED.signal(());
+}
+
+/// Draws the status over the status bar that was drawn with the shown status, e.g. into the copy
+/// of what the panel shows. Returns false and leaves the frame as it was if the frame doesn't have
+/// that status bar, like the screens without one.
+pub(crate) fn draw_over(
+    frame: &mut Frame,
+    shown: &StatusBar,
+    status: &StatusBar,
+) -> Result<bool, DrawError> {
+    let mut before = [0; REGION_BYTES];
+    for (bytes, row) in before
+        .chunks_exact_mut(usize::from(REGION.width / 8))
+        .zip(frame.region_rows(REGION))
+    {
+        bytes.copy_from_slice(row);
+    }
+
+    // Drawing the shown status changes nothing if the frame has it
+    shown.draw(frame)?;
+    let has_status_bar = frame.region_rows(REGION).flatten().eq(before.iter());
+    if !has_status_bar {
+        frame.blit(&before, REGION)?;
+        return Ok(false);
+    }
+
+    status.draw(frame)?;
+    Ok(true)
+}
+
+/// Collects what the status bar shows every [`INTERVAL`], at the start of each minute or when
+/// something changed and updates the status bar on the panel when it changed
+#[embassy_executor::task]
+pub(crate) async fn run(display: DisplayHandle, rtc: &'static SharedRtc) {
+    loop {
+        let percentage = BATTERY_PERCENTAGE.load(Ordering::Relaxed);
+        let is_charging = battery::is_charging();
+        let is_connected = IS_CONNECTED.load(Ordering::Relaxed);
+        let time = clock::time_of_day(rtc);
+        let changed = STATUS.lock(|status| {
+            let status = status.borrow();
+            let collected = StatusBar {
+                battery_percentage: (percentage != NO_PERCENTAGE).then_some(percentage),
+                is_charging,
+                time,
+                // Kept up to date by the reader
+                progress: status.progress,
+                is_connected,
+            };
+            (*status != collected).then_some((*status, collected))
+        });
+
+        if let Some((shown, collected)) = changed {
+            // Kept until the panel shows it, so a failed update is tried again with the next one.
+            // Unless what the panel shows is not known, then the next screen draws it anyway.
+            match display.update_status_bar(shown, collected).await {
+                Ok(()) | Err(ShownFrameError::Unknown) => STATUS.lock(|status| {
+                    let mut status = status.borrow_mut();
+                    *status = StatusBar {
+                        // The reader can have turned the page in the meantime
+                        progress: status.progress,
+                        ..collected
+                    };
+                }),
+                Err(error) => warn!(
+                    "Failed to update status bar: {:?}",
+                    defmt::Debug2Format(&error)
+                ),
+            }
+        }
+
+        let due = clock::until_next_minute(rtc).map_or(INTERVAL, |due| due.min(INTERVAL));
+        select(Timer::after(due), CHANGED.wait()).await;
+    }
+}
+
+/// Draws a battery filled up to the percentage with its left edge centered on the point. Returns
+/// where the icon ends on the right.
+fn draw_battery(frame: &mut Frame, left: Point, percentage: u8) -> Result<Point, DrawError> {
+    // Can't truncate as the icon is small
+    let top_left = left - Point::new(0, BATTERY_SIZE.height as i32 / 2);
+    Rectangle::new(top_left, BATTERY_SIZE)
+        .into_styled(PrimitiveStyle::with_stroke(BinaryColor::On, 1))
+        .draw(frame)?;
+
+    let tip_top_left = left
+        + Point::new(
+            BATTERY_SIZE.width as i32,
+            -(BATTERY_TIP_SIZE.height as i32 / 2),
+        );
+    frame.fill_solid(
+        &Rectangle::new(tip_top_left, BATTERY_TIP_SIZE),
+        BinaryColor::On,
+    )?;
+
+    // Leaves a gap of one pixel between the outline and the charge
+    let inner = BATTERY_SIZE - Size::new(4, 4);
+    let charge = Size::new(
+        inner.width * u32::from(percentage.min(100)) / 100,
+        inner.height,
+    );
+    frame.fill_solid(
+        &Rectangle::new(top_left + Point::new(2, 2), charge),
+        BinaryColor::On,
+    )?;
+
+    Ok(tip_top_left
+        + Point::new(
+            BATTERY_TIP_SIZE.width as i32,
+            BATTERY_TIP_SIZE.height as i32 / 2,
+        ))
+}
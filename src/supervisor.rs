@@ -0,0 +1,139 @@
+//! Guards against the reader hanging until the battery is empty. The hardware watchdog resets the
+//! device when the executor stops running, e.g. in a blocking SPI transaction that never
+//! completes. The task feeding it also requires the display, input and storage to check in while
+//! they are busy and resets when one of them is stuck in an await that never completes.
+//!
+//! Reading progress survives the reset in RTC memory (see [`crate::journal`]) and the reason is
+//! kept as a crash report.
+
+use core::cell::RefCell;
+
+use defmt::error;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_time::{Duration, Instant, Timer};
+use esp_hal::peripherals::TIMG0;
+use esp_hal::timer::timg::{MwdtStage, MwdtStageAction, Wdt};
+
+use crate::crash_report;
+
+/// The executor needs to get to the supervisor within this time, so it has to be longer than the
+/// longest blocking operation like saving the snapshot to flash
+const HARDWARE_TIMEOUT_SECONDS: u64 = 10;
+const CHECK_INTERVAL_SECONDS: u64 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub(crate) enum Task {
+    /// Transfers to the display controller. Waiting for the busy line has its own timeout.
+    Display,
+    /// Reading the buttons while waiting for a press
+    Input,
+    /// Reading and writing files on the SD card
+    Storage,
+}
+
+impl Task {
+    const COUNT: usize = 3;
+    const ALL: [Self; Self::COUNT] = [Self::Display, Self::Input, Self::Storage];
+
+    fn index(self) -> usize {
+        match self {
+            Self::Display => 0,
+            Self::Input => 1,
+            Self::Storage => 2,
+        }
+    }
+
+    /// Longest time between check-ins while busy
+    fn limit(self) -> Duration {
+        match self {
+            // Sending a whole frame takes a fraction of a second
+            Self::Display => Duration::from_secs(5),
+            // Buttons are read every few milliseconds
+            Self::Input => Duration::from_secs(5),
+            // A slow card can take a while for a chunk while it is erasing
+            Self::Storage => Duration::from_secs(15),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct State {
+    /// Watches that are alive for the task
+    busy: u8,
+    last_check_in: Instant,
+}
+
+static STATES: Mutex<CriticalSectionRawMutex, RefCell<[State; Task::COUNT]>> =
+    Mutex::new(RefCell::new(
+        [State {
+            busy: 0,
+            last_check_in: Instant::from_ticks(0),
+        }; Task::COUNT],
+    ));
+
+/// Marks the task as busy until it is dropped
+pub(crate) struct Watch {
+    task: Task,
+}
+
+/// Starts supervising the task. It has to check in within its limit until the watch is dropped.
+pub(crate) fn watch(task: Task) -> Watch {
+    STATES.lock(|states| {
+        let state = &mut states.borrow_mut()[task.index()];
+        state.busy = state.busy.saturating_add(1);
+        state.last_check_in = Instant::now();
+    });
+
+    Watch { task }
+}
+
+impl Watch {
+    /// Tells the supervisor the task is still making progress
+    pub(crate) fn check_in(&self) {
+        STATES.lock(|states| states.borrow_mut()[self.task.index()].last_check_in = Instant::now());
+    }
+}
+
+impl Drop for Watch {
+    fn drop(&mut self) {
+        STATES.lock(|states| {
+            let state = &mut states.borrow_mut()[self.task.index()];
+            state.busy = state.busy.saturating_sub(1);
+        });
+    }
+}
+
+/// The first busy task that missed its check-in
+fn stuck() -> Option<Task> {
+    let now = Instant::now();
+    STATES.lock(|states| {
+        let states = states.borrow();
+        Task::ALL.into_iter().find(|task| {
+            let state = states[task.index()];
+            state.busy > 0 && now.saturating_duration_since(state.last_check_in) > task.limit()
+        })
+    })
+}
+
+/// Feeds the hardware watchdog as long as no task is stuck
+#[embassy_executor::task]
+pub(crate) async fn supervise(mut watchdog: Wdt<TIMG0<'static>>) {
+    watchdog.set_timeout(
+        MwdtStage::Stage0,
+        esp_hal::time::Duration::from_secs(HARDWARE_TIMEOUT_SECONDS),
+    );
+    watchdog.set_stage_action(MwdtStage::Stage0, MwdtStageAction::ResetSystem);
+    watchdog.enable();
+
+    loop {
+        if let Some(task) = stuck() {
+            error!("{} stopped checking in, resetting", task);
+            crash_report::save_stuck(task);
+            esp_hal::system::software_reset();
+        }
+
+        watchdog.feed();
+        Timer::after_secs(CHECK_INTERVAL_SECONDS).await;
+    }
+}
@@ -0,0 +1,289 @@
+//! Theme packs bundle everything about the look of the reader into one file that can be shared
+//! with others instead of describing each setting. Themes are `key=value` files in the theme
+//! directory on the SD card, e.g. `/THEMES/NIGHT.TXT`:
+//!
+//! ```text
+//! body_font=medium
+//! heading_font=large
+//! margin=24
+//! footer=progress
+//! sleep_screen=/THEMES/NIGHT.BMP
+//! ```
+//!
+//! Missing keys keep their defaults. The profile settings name the active theme and can override
+//! its body font size.
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::cell::RefCell;
+use core::fmt::Write;
+
+use defmt::{error, info};
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embedded_graphics::Drawable;
+use embedded_graphics::mono_font::ascii::{FONT_7X14, FONT_8X13, FONT_9X18, FONT_10X20};
+use embedded_graphics::mono_font::{MonoFont, MonoTextStyle};
+use embedded_graphics::pixelcolor::BinaryColor;
+use embedded_graphics::prelude::{OriginDimensions, Point, Size};
+use embedded_graphics::primitives::Rectangle;
+use embedded_graphics::text::Text;
+
+use crate::SharedDisplay;
+use crate::eink_display::Frame;
+use crate::input::Analog;
+use crate::sd_card::{ReadFileError, ReadPriority, SdCard, VolumeError};
+use crate::widget::{ListView, Response};
+
+const DIRECTORY: &str = "/THEMES";
+const EXTENSION: &str = "TXT";
+/// Way more than a theme needs, but keeps a broken file from taking up all memory
+const MAXIMUM_FILE_SIZE: u32 = 1024;
+/// Keeps some space for text on the screen
+const MAXIMUM_MARGIN: u16 = 100;
+/// Where the theme list starts below the title
+const LIST_TOP: i32 = 45;
+
+/// Themes are named after their file which is limited to FAT short names by embedded_sdmmc
+pub(crate) type Name = heapless::String<8>;
+
+/// The theme the screens are drawn with. Shared as the sleep screen is drawn by the power button
+/// task.
+static ACTIVE: Mutex<CriticalSectionRawMutex, RefCell<Theme>> =
+    Mutex::new(RefCell::new(Theme::DEFAULT));
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub(crate) enum Font {
+    Small,
+    Medium,
+    Large,
+    ExtraLarge,
+}
+
+impl Font {
+    /// Drawn at [`Font::scale`] times its size
+    pub(crate) fn mono_font(self) -> &'static MonoFont<'static> {
+        match self {
+            Self::Small => &FONT_8X13,
+            Self::Medium => &FONT_9X18,
+            Self::Large => &FONT_10X20,
+            Self::ExtraLarge => &FONT_7X14,
+        }
+    }
+
+    /// The largest built-in font is 10x20 pixels, so larger sizes draw a smaller font with each
+    /// pixel as a block of pixels
+    pub(crate) fn scale(self) -> u32 {
+        match self {
+            Self::Small | Self::Medium | Self::Large => 1,
+            Self::ExtraLarge => 2,
+        }
+    }
+
+    pub(crate) fn as_str(self) -> &'static str {
+        match self {
+            Self::Small => "small",
+            Self::Medium => "medium",
+            Self::Large => "large",
+            Self::ExtraLarge => "x-large",
+        }
+    }
+
+    pub(crate) fn parse(value: &str) -> Option<Self> {
+        match value {
+            "small" => Some(Self::Small),
+            "medium" => Some(Self::Medium),
+            "large" => Some(Self::Large),
+            "x-large" => Some(Self::ExtraLarge),
+            _ => None,
+        }
+    }
+}
+
+/// What is shown below the text of a page
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub(crate) enum Footer {
+    Hidden,
+    PageNumber,
+    /// Bar of how far into the book the page is
+    Progress,
+}
+
+impl Footer {
+    fn parse(value: &str) -> Option<Self> {
+        match value {
+            "hidden" => Some(Self::Hidden),
+            "page" => Some(Self::PageNumber),
+            "progress" => Some(Self::Progress),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, defmt::Format)]
+pub(crate) struct Theme {
+    pub(crate) body_font: Font,
+    pub(crate) heading_font: Font,
+    /// Space around the text in pixels
+    pub(crate) margin: u16,
+    pub(crate) footer: Footer,
+    /// Absolute path of the image shown during deep sleep instead of the sleep images
+    pub(crate) sleep_screen: Option<String>,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+impl Theme {
+    const DEFAULT: Self = Self {
+        body_font: Font::Medium,
+        heading_font: Font::Large,
+        margin: 20,
+        footer: Footer::PageNumber,
+        sleep_screen: None,
+    };
+
+    pub(crate) async fn load(sd_card: &mut SdCard, name: &str) -> Result<Self, ReadFileError> {
+        let content = sd_card
+            .read_file(
+                &format!("{DIRECTORY}/{name}.{EXTENSION}"),
+                MAXIMUM_FILE_SIZE,
+                ReadPriority::Interactive,
+            )
+            .await?;
+
+        Ok(Self::parse(&String::from_utf8_lossy(&content)))
+    }
+
+    /// Unknown keys and invalid values are skipped to be able to read themes made for other
+    /// versions
+    fn parse(content: &str) -> Self {
+        let mut theme = Self::default();
+        for line in content.lines() {
+            let Some((key, value)) = line.split_once('=') else {
+                continue;
+            };
+
+            let value = value.trim();
+            match key.trim() {
+                "body_font" => theme.body_font = Font::parse(value).unwrap_or(theme.body_font),
+                "heading_font" => {
+                    theme.heading_font = Font::parse(value).unwrap_or(theme.heading_font);
+                }
+                "margin" => {
+                    if let Ok(margin) = value.parse::<u16>() {
+                        theme.margin = margin.min(MAXIMUM_MARGIN);
+                    }
+                }
+                "footer" => theme.footer = Footer::parse(value).unwrap_or(theme.footer),
+                "sleep_screen" if value.starts_with('/') => {
+                    theme.sleep_screen = Some(String::from(value));
+                }
+                _ => {}
+            }
+        }
+
+        theme
+    }
+}
+
+/// The theme the screens are currently drawn with
+pub(crate) fn active() -> Theme {
+    ACTIVE.lock(|active| active.borrow().clone())
+}
+
+pub(crate) fn set_active(theme: Theme) {
+    ACTIVE.lock(|active| *active.borrow_mut() = theme);
+}
+
+/// Names of the themes on the SD card
+pub(crate) async fn list(sd_card: &mut SdCard) -> Result<Vec<Name>, VolumeError> {
+    let entries = match sd_card.list_directory(DIRECTORY).await {
+        Ok(entries) => entries,
+        Err(embedded_sdmmc::Error::NotFound) => return Ok(Vec::new()),
+        Err(error) => return Err(error),
+    };
+
+    let names = entries
+        .iter()
+        .filter(|entry| {
+            !entry.attributes.is_directory() && entry.name.extension() == EXTENSION.as_bytes()
+        })
+        .filter_map(|entry| {
+            let base_name = core::str::from_utf8(entry.name.base_name()).ok()?;
+            let mut name = Name::new();
+            name.write_str(base_name).ok()?;
+            Some(name)
+        })
+        .collect();
+
+    Ok(names)
+}
+
+/// Lets the reader pick one of the themes with the buttons. The first entry stands for the built-in
+/// default theme. Returns None if the reader backed out.
+pub(crate) async fn select(
+    names: &[Name],
+    current: Option<&str>,
+    analog: &mut Analog<'_>,
+    display: &SharedDisplay,
+    frame: &mut Frame,
+) -> Option<Option<Name>> {
+    let selected = current
+        .and_then(|current| names.iter().position(|name| name == current))
+        .map_or(0, |index| index + 1);
+    let mut list = ListView::new(names.len() + 1, selected);
+
+    loop {
+        draw_picker(names, frame, &mut list);
+        if let Err(error) = display.animate(frame).await {
+            error!(
+                "Failed to display theme picker: {:?}",
+                defmt::Debug2Format(&error)
+            );
+        }
+
+        loop {
+            match list.handle(analog.wait_for_press().await) {
+                Response::Ignored => {}
+                Response::Changed => break,
+                Response::Confirmed => {
+                    let name = list
+                        .selected()
+                        .checked_sub(1)
+                        .map(|index| names[index].clone());
+                    info!("Selected theme {}", name.as_deref().unwrap_or("default"));
+                    return Some(name);
+                }
+                Response::Cancelled => return None,
+            }
+        }
+    }
+}
+
+fn draw_picker(names: &[Name], frame: &mut Frame, list: &mut ListView) {
+    // White
+    frame.fill(0xFF);
+
+    let style = MonoTextStyle::new(&FONT_10X20, BinaryColor::On);
+    if let Err(error) = Text::new("Theme", Point::new(10, 30), style).draw(frame) {
+        error!("Failed to draw theme picker: {:?}", error);
+    }
+
+    let size = frame.size();
+    let area = Rectangle::new(
+        Point::new(0, LIST_TOP),
+        Size::new(size.width, size.height - LIST_TOP as u32),
+    );
+    let label = |index: usize| match index.checked_sub(1) {
+        Some(index) => names[index].as_str(),
+        None => "Default",
+    };
+    if let Err(error) = list.draw(frame, &area, label) {
+        error!("Failed to draw theme picker: {:?}", error);
+    }
+}
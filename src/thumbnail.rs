@@ -0,0 +1,343 @@
+//! Small covers for the grid view of the library. Covers are decoded, scaled down and dithered when
+//! the library is indexed and kept on the SD card, so the grid only reads a few KiB per book.
+//!
+//! Each book has a file in [`DIRECTORY`] named by a hash of its path, as the file system only has
+//! short names. The file starts with the u32 size and the u32 modification time of the book to
+//! notice when the book changed, both little endian. The image follows with a row after another
+//! from the top, the leftmost pixel in the highest bit and a set bit for ink.
+//!
+//! Only PNG and BMP covers that are stored without compression in the book can be read. Other
+//! books are shown with their title in place of the cover.
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::convert::Infallible;
+use core::ops::Range;
+
+use embedded_graphics::Drawable;
+use embedded_graphics::Pixel;
+use embedded_graphics::image::{Image, ImageRaw};
+use embedded_graphics::pixelcolor::BinaryColor;
+use embedded_graphics::prelude::{DrawTarget, OriginDimensions, Point, Size};
+use embedded_sdmmc::{Mode, RawFile};
+
+use crate::epub::zip::{Archive, ZipError};
+use crate::image::dither::{Ditherer, Method};
+use crate::image::{RowSink, bmp, png};
+use crate::library::{Book, Cover};
+use crate::mobi::{self, MobiError};
+use crate::sd_card::{OpenError, ReadFileError, ReadPriority, SdCard, VolumeError, WriteFileError};
+
+pub(crate) const DIRECTORY: &str = "/THUMBS";
+pub(crate) const SIZE: Size = Size::new(120, 180);
+const WIDTH_BYTES: usize = SIZE.width as usize / 8;
+const BITMAP_SIZE: usize = WIDTH_BYTES * SIZE.height as usize;
+/// Size and modification time of the book
+const HEADER_SIZE: usize = 8;
+
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum ThumbnailError {
+    #[error("Failed to open book")]
+    Open(#[from] OpenError),
+    #[error("Failed to read book archive")]
+    Zip(#[from] ZipError),
+    #[error("Failed to read Kindle book")]
+    Mobi(#[from] MobiError),
+    #[error("Cover is not in the book")]
+    MissingCover,
+    #[error("Failed to read cover")]
+    Read(VolumeError),
+    #[error("Cover is not a PNG or BMP image")]
+    UnsupportedFormat,
+    #[error("Failed to decode PNG cover")]
+    Png(#[from] png::DecodeError<Infallible>),
+    #[error("Failed to decode BMP cover")]
+    Bmp(#[from] bmp::DecodeError<Infallible>),
+    #[error("Failed to create thumbnail directory")]
+    CreateDirectory(VolumeError),
+    #[error("Failed to write thumbnail")]
+    Write(#[from] WriteFileError),
+}
+
+pub(crate) struct Thumbnail {
+    bitmap: Vec<u8>,
+}
+
+impl Thumbnail {
+    fn blank() -> Self {
+        Self {
+            bitmap: vec![0; BITMAP_SIZE],
+        }
+    }
+
+    pub(crate) fn draw<D>(&self, target: &mut D, position: Point) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = BinaryColor>,
+    {
+        let image = ImageRaw::<BinaryColor>::new(&self.bitmap, SIZE.width);
+        Image::new(&image, position).draw(target)
+    }
+}
+
+impl OriginDimensions for Thumbnail {
+    fn size(&self) -> Size {
+        SIZE
+    }
+}
+
+impl DrawTarget for Thumbnail {
+    type Color = BinaryColor;
+    type Error = Infallible;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        for Pixel(point, color) in pixels {
+            let (Ok(x), Ok(y)) = (usize::try_from(point.x), usize::try_from(point.y)) else {
+                continue;
+            };
+
+            // Can't truncate as the size is small
+            if x >= SIZE.width as usize || y >= SIZE.height as usize {
+                continue;
+            }
+
+            let byte = &mut self.bitmap[y * WIDTH_BYTES + x / 8];
+            let bit = 0x80 >> (x % 8);
+            match color {
+                BinaryColor::On => *byte |= bit,
+                BinaryColor::Off => *byte &= !bit,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Scales the image down to fit the thumbnail by averaging the pixels that fall onto the same
+/// thumbnail pixel, and dithers the result. Small images are scaled up.
+struct Scaler {
+    thumbnail: Thumbnail,
+    ditherer: Option<Ditherer>,
+    source: Size,
+    scaled: Size,
+    /// Rows received so far
+    source_row: u32,
+    /// Rows dithered so far
+    scaled_row: u32,
+    /// Sums of the gray values for each column of the scaled row
+    sums: Vec<u32>,
+    /// Rows added to the sums
+    summed_rows: u32,
+    /// Last scaled row which is repeated when scaling up
+    row: Vec<u8>,
+}
+
+impl Scaler {
+    fn new() -> Self {
+        Self {
+            thumbnail: Thumbnail::blank(),
+            ditherer: None,
+            source: Size::zero(),
+            scaled: Size::zero(),
+            source_row: 0,
+            scaled_row: 0,
+            sums: Vec::new(),
+            summed_rows: 0,
+            row: Vec::new(),
+        }
+    }
+}
+
+impl RowSink for Scaler {
+    type Error = Infallible;
+
+    fn start(&mut self, size: Size) -> Result<(), Self::Error> {
+        // Keeps the aspect ratio with the longer side filling the thumbnail
+        let scaled =
+            if size.width.saturating_mul(SIZE.height) <= size.height.saturating_mul(SIZE.width) {
+                let width = u64::from(size.width) * u64::from(SIZE.height) / u64::from(size.height);
+                // Can't truncate as it is at most the thumbnail width
+                Size::new((width as u32).clamp(1, SIZE.width), SIZE.height)
+            } else {
+                let height = u64::from(size.height) * u64::from(SIZE.width) / u64::from(size.width);
+                // Can't truncate as it is at most the thumbnail height
+                Size::new(SIZE.width, (height as u32).clamp(1, SIZE.height))
+            };
+
+        // Can't truncate as the thumbnail is small
+        let origin = Point::new(
+            ((SIZE.width - scaled.width) / 2) as i32,
+            ((SIZE.height - scaled.height) / 2) as i32,
+        );
+
+        self.source = size;
+        self.scaled = scaled;
+        self.sums = vec![0; scaled.width as usize];
+        self.row = vec![u8::MAX; scaled.width as usize];
+        self.ditherer = Some(Ditherer::new(
+            Method::FloydSteinberg,
+            origin,
+            scaled.width as usize,
+        ));
+        Ok(())
+    }
+
+    fn push_row(&mut self, gray: &[u8]) -> Result<(), Self::Error> {
+        let Some(ditherer) = self.ditherer.as_mut() else {
+            return Ok(());
+        };
+
+        for (column, sum) in (0..).zip(self.sums.iter_mut()) {
+            let pixels = span(column, self.scaled.width, self.source.width);
+            *sum += gray
+                .get(pixels)
+                .unwrap_or_default()
+                .iter()
+                .map(|&value| u32::from(value))
+                .sum::<u32>();
+        }
+        self.source_row += 1;
+        self.summed_rows += 1;
+
+        // Scaled rows are done once all their source rows are summed up
+        while self.scaled_row < self.scaled.height
+            && span(self.scaled_row, self.scaled.height, self.source.height).end
+                <= self.source_row as usize
+        {
+            if self.summed_rows > 0 {
+                for ((column, value), sum) in (0..).zip(self.row.iter_mut()).zip(&mut self.sums) {
+                    // Can't truncate as the spans are at most a few thousand pixels
+                    let count = span(column, self.scaled.width, self.source.width).len() as u32
+                        * self.summed_rows;
+                    // Can't truncate as it is the average of 8-bit values
+                    *value = (*sum / count.max(1)) as u8;
+                    *sum = 0;
+                }
+                self.summed_rows = 0;
+            }
+
+            ditherer.push_row(&self.row, &mut self.thumbnail)?;
+            self.scaled_row += 1;
+        }
+
+        Ok(())
+    }
+}
+
+/// Pixels of the source that fall onto the scaled pixel. At least one, so scaling up repeats them.
+fn span(scaled_index: u32, scaled_length: u32, source_length: u32) -> Range<usize> {
+    let start = u64::from(scaled_index) * u64::from(source_length) / u64::from(scaled_length);
+    let end = (u64::from(scaled_index) + 1) * u64::from(source_length) / u64::from(scaled_length);
+    // Can't truncate as it is at most the source length
+    start as usize..end.max(start + 1) as usize
+}
+
+/// Decodes the cover of the book and saves its thumbnail
+pub(crate) async fn generate(
+    sd_card: &mut SdCard,
+    book: &Book,
+    cover: &Cover,
+) -> Result<(), ThumbnailError> {
+    let file = sd_card.open(&book.path, Mode::ReadOnly).await?;
+    let result = decode_cover(sd_card, file, cover).await;
+
+    if let Err(error) = sd_card.close(file).await {
+        defmt::warn!("Failed to close book: {:?}", error);
+    }
+
+    let thumbnail = result?;
+    sd_card
+        .create_directories(DIRECTORY)
+        .await
+        .map_err(ThumbnailError::CreateDirectory)?;
+
+    let mut content = Vec::with_capacity(HEADER_SIZE + BITMAP_SIZE);
+    content.extend_from_slice(&header(book));
+    content.extend_from_slice(&thumbnail.bitmap);
+    sd_card.write_file(&path(book), &content).await?;
+    Ok(())
+}
+
+/// Reads the thumbnail of the book. None if there is none or it is from before the book changed.
+pub(crate) async fn load(
+    sd_card: &mut SdCard,
+    book: &Book,
+) -> Result<Option<Thumbnail>, ReadFileError> {
+    // Can't truncate as the thumbnail is small
+    let maximum_size = (HEADER_SIZE + BITMAP_SIZE) as u32;
+    let content = match sd_card
+        .read_file(&path(book), maximum_size, ReadPriority::Interactive)
+        .await
+    {
+        Ok(content) => content,
+        Err(ReadFileError::Open(error)) if error.is_not_found() => return Ok(None),
+        Err(error) => return Err(error),
+    };
+
+    let Some((stored_header, bitmap)) = content.split_at_checked(HEADER_SIZE) else {
+        return Ok(None);
+    };
+
+    if stored_header != header(book) || bitmap.len() != BITMAP_SIZE {
+        return Ok(None);
+    }
+
+    Ok(Some(Thumbnail {
+        bitmap: bitmap.to_vec(),
+    }))
+}
+
+async fn decode_cover(
+    sd_card: &mut SdCard,
+    file: RawFile,
+    cover: &Cover,
+) -> Result<Thumbnail, ThumbnailError> {
+    let offset = match cover {
+        Cover::Archive(path) => {
+            let archive = Archive::open(sd_card, file).await?;
+            let entry = archive.find(path).ok_or(ThumbnailError::MissingCover)?;
+            archive.stored_offset(sd_card, entry).await?
+        }
+        Cover::Record(record) => {
+            let book = mobi::Book::open(sd_card, file).await?;
+            book.record_offset(sd_card, *record).await?
+        }
+    };
+
+    let mut signature = [0; png::SIGNATURE.len()];
+    let read = sd_card
+        .read_at(file, offset, &mut signature, ReadPriority::Background)
+        .await
+        .map_err(ThumbnailError::Read)?;
+    let signature = &signature[..read];
+
+    let mut scaler = Scaler::new();
+    if signature == png::SIGNATURE {
+        png::decode_at(sd_card, file, offset, &mut scaler).await?;
+    } else if signature.starts_with(b"BM") {
+        bmp::decode_at(sd_card, file, offset, &mut scaler).await?;
+    } else {
+        return Err(ThumbnailError::UnsupportedFormat);
+    }
+
+    Ok(scaler.thumbnail)
+}
+
+fn header(book: &Book) -> [u8; HEADER_SIZE] {
+    let mut header = [0; HEADER_SIZE];
+    header[..4].copy_from_slice(&book.size.to_le_bytes());
+    header[4..].copy_from_slice(&book.modified.to_le_bytes());
+    header
+}
+
+/// Named by the FNV-1a hash of the path of the book
+fn path(book: &Book) -> String {
+    let hash = book.path.bytes().fold(0x811C_9DC5_u32, |hash, byte| {
+        (hash ^ u32::from(byte)).wrapping_mul(0x0100_0193)
+    });
+    format!("{DIRECTORY}/{hash:08X}.BIN")
+}
@@ -0,0 +1,85 @@
+//! Waiting on a peripheral that stopped responding would hang the waiting task forever. Operations
+//! that wait on peripherals go through [`run`] which gives up after a timeout, logs which operation
+//! it was and keeps metrics to see how close operations get to their timeout.
+
+use core::cell::RefCell;
+
+use defmt::warn;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_time::{Duration, Instant, TimeoutError, with_timeout};
+
+use crate::event_log::{self, Event};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub(crate) enum Operation {
+    /// The display controller working on a command or refresh
+    DisplayBusy,
+    /// Another device finishing its transaction on the shared SPI bus
+    SdCardBus,
+}
+
+impl Operation {
+    const COUNT: usize = 2;
+
+    fn index(self) -> usize {
+        match self {
+            Self::DisplayBusy => 0,
+            Self::SdCardBus => 1,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, defmt::Format)]
+pub(crate) struct Metrics {
+    /// Completed and timed out runs
+    pub(crate) count: u32,
+    pub(crate) timeouts: u32,
+    /// Longest completed run
+    pub(crate) maximum: Duration,
+}
+
+impl Metrics {
+    const fn new() -> Self {
+        Self {
+            count: 0,
+            timeouts: 0,
+            maximum: Duration::MIN,
+        }
+    }
+}
+
+static METRICS: Mutex<CriticalSectionRawMutex, RefCell<[Metrics; Operation::COUNT]>> =
+    Mutex::new(RefCell::new([Metrics::new(); Operation::COUNT]));
+
+/// Waits for the future to complete for at most the timeout
+pub(crate) async fn run<F: Future>(
+    operation: Operation,
+    timeout: Duration,
+    future: F,
+) -> Result<F::Output, TimeoutError> {
+    let start = Instant::now();
+    let result = with_timeout(timeout, future).await;
+    let elapsed = start.elapsed();
+
+    METRICS.lock(|metrics| {
+        let metrics = &mut metrics.borrow_mut()[operation.index()];
+        metrics.count = metrics.count.saturating_add(1);
+        if result.is_err() {
+            metrics.timeouts = metrics.timeouts.saturating_add(1);
+        } else {
+            metrics.maximum = metrics.maximum.max(elapsed);
+        }
+    });
+
+    if result.is_err() {
+        warn!("{} timed out after {} ms", operation, timeout.as_millis());
+        event_log::record(Event::Timeout(operation));
+    }
+
+    result
+}
+
+pub(crate) fn metrics(operation: Operation) -> Metrics {
+    METRICS.lock(|metrics| metrics.borrow()[operation.index()])
+}
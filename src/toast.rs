@@ -0,0 +1,39 @@
+//! Short messages across the middle of the screen that go away on their own, e.g. when the SD
+//! card was removed. Unlike a dialog they don't wait for a button.
+
+use embassy_time::Duration;
+use embedded_graphics::Drawable;
+use embedded_graphics::mono_font::MonoTextStyle;
+use embedded_graphics::mono_font::ascii::FONT_10X20;
+use embedded_graphics::pixelcolor::BinaryColor;
+use embedded_graphics::prelude::{DrawTarget, Point, Size};
+use embedded_graphics::primitives::Rectangle;
+use embedded_graphics::text::{Alignment, Baseline, Text, TextStyleBuilder};
+
+use crate::eink_display::{DrawError, Frame, Region};
+
+/// A multiple of 8 as the strip is a column band of the panel
+const HEIGHT: u32 = 40;
+// Can't truncate as the frame is less than 1000 pixels high
+const AREA: Rectangle = Rectangle::new(
+    Point::new(0, ((Frame::SIZE.height - HEIGHT) / 2) as i32),
+    Size::new(Frame::SIZE.width, HEIGHT),
+);
+/// The strip in panel coordinates
+pub(crate) const REGION: Region = Region::covering(AREA).unwrap();
+/// How long a toast stays before the screen below is brought back
+pub(crate) const DURATION: Duration = Duration::from_secs(3);
+
+/// Draws the message in white on a black strip. Displaying [`REGION`] with `display_region`
+/// updates only the strip.
+pub(crate) fn draw(message: &str, frame: &mut Frame) -> Result<(), DrawError> {
+    frame.fill_solid(&AREA, BinaryColor::On)?;
+
+    let character_style = MonoTextStyle::new(&FONT_10X20, BinaryColor::Off);
+    let text_style = TextStyleBuilder::new()
+        .alignment(Alignment::Center)
+        .baseline(Baseline::Middle)
+        .build();
+    Text::with_text_style(message, AREA.center(), character_style, text_style).draw(frame)?;
+    Ok(())
+}
@@ -0,0 +1,64 @@
+//! defmt filters the logs when building, see `DEFMT_LOG` in the cargo config, so changing that
+//! takes a new build. Logs that would flood the output, like every SPI transfer of a frame to the
+//! panel, are only printed once their module is turned up at run time, e.g. with the `log` console
+//! command while debugging the driver.
+
+use portable_atomic::{AtomicU8, Ordering};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub(crate) enum Module {
+    /// The driver of the display controller
+    Display,
+}
+
+impl Module {
+    const COUNT: usize = 1;
+
+    fn index(self) -> usize {
+        match self {
+            Self::Display => 0,
+        }
+    }
+
+    pub(crate) fn parse(name: &str) -> Option<Self> {
+        match name {
+            "display" => Some(Self::Display),
+            _ => None,
+        }
+    }
+}
+
+/// How much a module logs. Each level includes the ones before.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, defmt::Format)]
+#[repr(u8)]
+pub(crate) enum Level {
+    /// What is always logged
+    Normal,
+    /// Each step, e.g. each command sent to the display
+    Debug,
+    /// Each transfer, e.g. each block of data sent to the display
+    Trace,
+}
+
+impl Level {
+    pub(crate) fn parse(name: &str) -> Option<Self> {
+        match name {
+            "normal" => Some(Self::Normal),
+            "debug" => Some(Self::Debug),
+            "trace" => Some(Self::Trace),
+            _ => None,
+        }
+    }
+}
+
+static LEVELS: [AtomicU8; Module::COUNT] =
+    [const { AtomicU8::new(Level::Normal as u8) }; Module::COUNT];
+
+pub(crate) fn set(module: Module, level: Level) {
+    LEVELS[module.index()].store(level as u8, Ordering::Relaxed);
+}
+
+/// Whether the module logs at the level right now
+pub(crate) fn is_enabled(module: Module, level: Level) -> bool {
+    LEVELS[module.index()].load(Ordering::Relaxed) >= level as u8
+}
@@ -0,0 +1,770 @@
+//! Mirrors a folder on a WebDAV server like Nextcloud or `rclone serve webdav` into a directory on
+//! the SD card, so books can be added to the reader by dropping them into a shared folder. Books
+//! that are new or changed on the server are downloaded, books that were removed on the server are
+//! kept on the card. Subfolders are mirrored into the same directory.
+//!
+//! The file system only has short names, so the books get a name like `ALICEINW.EPUB` on the card.
+//! Which file came from where is kept in the file `WEBDAV.TXT` in the directory with a line per
+//! book like `file=ALICEINW.EPUB\t"5f3a"\t/dav/Books/Alice%20in%20Wonderland.epub` with the local
+//! name, the entity tag the server gave it and where it is on the server. The entity tag is empty
+//! until the download completed.
+//!
+//! If enabled, the bookmarks of the mirrored books are uploaded next to them as text files like
+//! `Alice in Wonderland.bookmarks.txt`.
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use crate::epub::xml::{self, Tags};
+use crate::http::{self, HttpError, Url};
+use crate::library::Format;
+use crate::sd_card::{OpenError, ReadFileError, ReadPriority, SdCard, VolumeError, WriteFileError};
+
+/// Where the books go if the configuration doesn't say
+pub(crate) const DEFAULT_DIRECTORY: &str = "/BOOKS/WEBDAV";
+const MANIFEST_NAME: &str = "WEBDAV.TXT";
+const MAXIMUM_MANIFEST_SIZE: u32 = 32 * 1024;
+/// Same as the library can index
+const MAXIMUM_FILES: usize = 256;
+/// Folders below the mirrored folder that are still mirrored
+const MAXIMUM_DEPTH: usize = 3;
+/// Longest response element of a listing that is parsed. Longer ones are skipped.
+const MAXIMUM_RESPONSE_SIZE: usize = 2 * 1024;
+/// Longest name before the extension on the file system
+const STEM_LENGTH: usize = 8;
+const BOOKMARKS_EXTENSION: &str = ".bookmarks.txt";
+/// Asks only for what is needed to tell books and folders apart and notice changes
+const PROPFIND_BODY: &str = "<?xml version=\"1.0\" encoding=\"utf-8\"?>\
+    <d:propfind xmlns:d=\"DAV:\"><d:prop>\
+    <d:resourcetype/><d:getcontentlength/><d:getetag/><d:getlastmodified/>\
+    </d:prop></d:propfind>";
+
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum WebdavError {
+    #[error("No SD card")]
+    NoCard,
+    #[error("Failed to create directory")]
+    CreateDirectory(VolumeError),
+    #[error("Failed to list directory")]
+    ListDirectory(VolumeError),
+    #[error("Failed to load the list of mirrored files")]
+    LoadManifest(ReadFileError),
+    #[error("Failed to save the list of mirrored files")]
+    SaveManifest(#[from] WriteFileError),
+    #[error("Failed to open file")]
+    Open(#[from] OpenError),
+    #[error("Failed to write file")]
+    Write(VolumeError),
+    #[error("Request to the server failed")]
+    Http(#[from] HttpError),
+    #[error("Server rejected the user name or password")]
+    Unauthorized,
+    #[error("Server responded with status {0}")]
+    Status(u16),
+}
+
+/// Folder on the server to mirror and where to
+#[derive(Clone, PartialEq, Eq)]
+pub(crate) struct Remote {
+    /// Server without a path, as the paths in listings are absolute
+    server: Url,
+    /// Path of the folder with a slash at the end
+    folder: String,
+    /// Header value for basic authentication, None for servers without accounts
+    authorization: Option<String>,
+    /// Absolute path of the directory on the card
+    pub(crate) directory: String,
+    pub(crate) upload_bookmarks: bool,
+}
+
+/// Keeps the password out of the logs
+impl core::fmt::Debug for Remote {
+    fn fmt(&self, formatter: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        formatter
+            .debug_struct("Remote")
+            .field("server", &self.server)
+            .field("folder", &self.folder)
+            .field("directory", &self.directory)
+            .field("upload_bookmarks", &self.upload_bookmarks)
+            .finish_non_exhaustive()
+    }
+}
+
+impl defmt::Format for Remote {
+    fn format(&self, formatter: defmt::Formatter<'_>) {
+        defmt::write!(
+            formatter,
+            "Remote {{ server: {}, folder: {}, directory: {}, upload_bookmarks: {}, .. }}",
+            self.server,
+            self.folder.as_str(),
+            self.directory.as_str(),
+            self.upload_bookmarks
+        );
+    }
+}
+
+impl Remote {
+    /// The URL is the folder like `http://cloud.example.com/remote.php/dav/files/reader/Books`.
+    /// None if it isn't a plain HTTP URL.
+    pub(crate) fn new(
+        url: &str,
+        username: Option<&str>,
+        password: &str,
+        directory: Option<String>,
+        upload_bookmarks: bool,
+    ) -> Option<Self> {
+        let url = Url::parse(url)?;
+        let directory = directory.map_or(String::from(DEFAULT_DIRECTORY), |directory| {
+            format!("/{}", directory.trim_matches('/'))
+        });
+
+        Some(Self {
+            folder: format!("{}/", url.path),
+            server: Url {
+                path: String::new(),
+                ..url
+            },
+            authorization: username.map(|username| http::basic_authorization(username, password)),
+            directory,
+            upload_bookmarks,
+        })
+    }
+
+    fn request(&self, method: &str, path: &str, headers: &[(&str, &str)], body: &str) -> String {
+        let mut headers = Vec::from(headers);
+        if let Some(authorization) = &self.authorization {
+            headers.push(("Authorization", authorization.as_str()));
+        }
+
+        let body_length = (!body.is_empty() || method == "PUT").then_some(body.len());
+        self.server.request(method, path, &headers, body_length)
+    }
+
+    fn manifest_path(&self) -> String {
+        format!("{}/{MANIFEST_NAME}", self.directory)
+    }
+}
+
+/// What a sync did
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, defmt::Format)]
+pub(crate) struct Summary {
+    pub(crate) downloaded: usize,
+    pub(crate) unchanged: usize,
+    pub(crate) failed: usize,
+    pub(crate) uploaded: usize,
+}
+
+/// Book or folder in a listing of the server
+#[derive(Debug, Clone, PartialEq, Eq, defmt::Format)]
+struct Entry {
+    /// Absolute path on the server as it was listed, so still percent-encoded
+    href: String,
+    is_folder: bool,
+    /// Changes when the file changes. The modification time for servers without entity tags.
+    tag: String,
+}
+
+/// Collects the entries of a multistatus response of a listing as it arrives
+#[derive(Default)]
+struct Multistatus {
+    /// Start of the current response element
+    pending: Vec<u8>,
+    entries: Vec<Entry>,
+}
+
+impl Multistatus {
+    fn push(&mut self, bytes: &[u8]) {
+        self.pending.extend_from_slice(bytes);
+        while let Some(end) = response_end(&self.pending) {
+            let element = String::from_utf8_lossy(&self.pending[..end]);
+            if let Some(entry) = parse_entry(&element)
+                && self.entries.len() < MAXIMUM_FILES
+            {
+                self.entries.push(entry);
+            }
+            self.pending.drain(..end);
+        }
+
+        if self.pending.len() > MAXIMUM_RESPONSE_SIZE {
+            defmt::warn!("Skipping too large entry in WebDAV listing");
+            self.pending.clear();
+        }
+    }
+}
+
+/// Where the first response element ends
+fn response_end(bytes: &[u8]) -> Option<usize> {
+    let mut offset = 0;
+    while let Some(start) = bytes[offset..]
+        .windows(2)
+        .position(|window| window == b"</")
+    {
+        let start = offset + start;
+        let end = start + bytes[start..].iter().position(|&byte| byte == b'>')?;
+        let tag = core::str::from_utf8(&bytes[start + 2..end]).unwrap_or_default();
+        if xml::name(tag) == "response" {
+            return Some(end + 1);
+        }
+        offset = end;
+    }
+
+    None
+}
+
+/// Entry of a response element, None for files that are not books
+fn parse_entry(element: &str) -> Option<Entry> {
+    let href = xml::text(element, "href")?;
+    let href = href_path(&href);
+
+    let mut is_folder = false;
+    let mut tags = Tags::new();
+    tags.push(element.as_bytes(), |tag| {
+        is_folder |= xml::name(tag) == "collection";
+    });
+
+    if !is_folder && Format::of(&decode(&href)).is_none() {
+        return None;
+    }
+
+    let tag = xml::text(element, "getetag")
+        .or_else(|| xml::text(element, "getlastmodified"))
+        .or_else(|| xml::text(element, "getcontentlength"))
+        .unwrap_or_default();
+    Some(Entry {
+        href: String::from(href),
+        is_folder,
+        tag,
+    })
+}
+
+/// Path of a reference that can also be a full URL
+fn href_path(href: &str) -> &str {
+    match href.split_once("://") {
+        Some((_, rest)) => rest.find('/').map_or("/", |start| &rest[start..]),
+        None => href,
+    }
+}
+
+/// Resolves the percent-encoded bytes of a path
+fn decode(path: &str) -> String {
+    let mut decoded = Vec::with_capacity(path.len());
+    let mut bytes = path.bytes();
+    while let Some(byte) = bytes.next() {
+        if byte != b'%' {
+            decoded.push(byte);
+            continue;
+        }
+
+        let mut digits = bytes.clone();
+        let value = match (digits.next(), digits.next()) {
+            (Some(high), Some(low)) => char::from(high)
+                .to_digit(16)
+                .zip(char::from(low).to_digit(16)),
+            _ => None,
+        };
+        match value {
+            Some((high, low)) => {
+                // Can't truncate as two hexadecimal digits are a byte
+                decoded.push((high * 16 + low) as u8);
+                bytes = digits;
+            }
+            None => decoded.push(byte),
+        }
+    }
+
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Last part of the path without the slash a folder ends with
+fn file_name(path: &str) -> &str {
+    path.trim_end_matches('/')
+        .rsplit('/')
+        .next()
+        .unwrap_or_default()
+}
+
+/// Short name for a file from the server that none of the taken names has. The name is made of
+/// the letters and digits of the remote name, with a number like `~1` at the end if the short name
+/// is taken.
+fn local_name(remote_name: &str, taken: &[String]) -> Option<String> {
+    let (stem, extension) = remote_name.rsplit_once('.')?;
+    let extension = extension.to_ascii_uppercase();
+    let mut short: String = stem
+        .chars()
+        .filter(char::is_ascii_alphanumeric)
+        .map(|character| character.to_ascii_uppercase())
+        .take(STEM_LENGTH)
+        .collect();
+    if short.is_empty() {
+        short.push_str("BOOK");
+    }
+
+    let is_taken = |name: &str| taken.iter().any(|taken| taken.eq_ignore_ascii_case(name));
+    let name = format!("{short}.{extension}");
+    if !is_taken(&name) {
+        return Some(name);
+    }
+
+    (1..100).find_map(|number| {
+        let suffix = format!("~{number}");
+        let length = short.len().min(STEM_LENGTH - suffix.len());
+        let name = format!("{}{suffix}.{extension}", &short[..length]);
+        (!is_taken(&name)).then_some(name)
+    })
+}
+
+/// Book that was downloaded from the server
+#[derive(Debug, Clone, PartialEq, Eq, defmt::Format)]
+struct Mirrored {
+    /// Name in the directory on the card
+    name: String,
+    /// Entity tag of the file that was downloaded, empty if the download didn't complete
+    tag: String,
+    href: String,
+}
+
+/// The books that were downloaded so far
+#[derive(Debug, Clone, Default, PartialEq, Eq, defmt::Format)]
+struct Manifest {
+    files: Vec<Mirrored>,
+}
+
+impl Manifest {
+    /// Nothing was mirrored yet if there is no file
+    async fn load(sd_card: &mut SdCard, remote: &Remote) -> Result<Self, ReadFileError> {
+        let content = match sd_card
+            .read_file(
+                &remote.manifest_path(),
+                MAXIMUM_MANIFEST_SIZE,
+                ReadPriority::Background,
+            )
+            .await
+        {
+            Ok(content) => content,
+            Err(ReadFileError::Open(error)) if error.is_not_found() => return Ok(Self::default()),
+            Err(error) => return Err(error),
+        };
+
+        Ok(Self::parse(&String::from_utf8_lossy(&content)))
+    }
+
+    async fn save(&self, sd_card: &mut SdCard, remote: &Remote) -> Result<(), WriteFileError> {
+        sd_card
+            .write_file(&remote.manifest_path(), self.serialize().as_bytes())
+            .await
+    }
+
+    fn find(&self, href: &str) -> Option<&Mirrored> {
+        self.files.iter().find(|file| file.href == href)
+    }
+
+    /// Adds the file or updates the file with the same reference
+    fn set(&mut self, mirrored: Mirrored) {
+        match self
+            .files
+            .iter_mut()
+            .find(|file| file.href == mirrored.href)
+        {
+            Some(file) => *file = mirrored,
+            None => self.files.push(mirrored),
+        }
+    }
+
+    /// Unknown keys and invalid lines are skipped to be able to read files from other versions
+    fn parse(content: &str) -> Self {
+        let mut manifest = Self::default();
+        for line in content.lines() {
+            let Some(("file", value)) = line.split_once('=') else {
+                continue;
+            };
+
+            let mut fields = value.split('\t');
+            let (Some(name), Some(tag), Some(href)) = (fields.next(), fields.next(), fields.next())
+            else {
+                continue;
+            };
+
+            if manifest.files.len() < MAXIMUM_FILES {
+                manifest.files.push(Mirrored {
+                    name: String::from(name),
+                    tag: String::from(tag),
+                    href: String::from(href),
+                });
+            }
+        }
+
+        manifest
+    }
+
+    fn serialize(&self) -> String {
+        let mut content = String::new();
+        for file in &self.files {
+            content.push_str(&format!(
+                "file={}\t{}\t{}\n",
+                file.name, file.tag, file.href
+            ));
+        }
+
+        content
+    }
+}
+
+/// Where the bookmarks of the book are uploaded to, next to the book
+fn bookmarks_href(href: &str) -> String {
+    let name_start = href.rfind('/').map_or(0, |slash| slash + 1);
+    let stem = match href[name_start..].rfind('.') {
+        Some(dot) => &href[..name_start + dot],
+        None => href,
+    };
+    format!("{stem}{BOOKMARKS_EXTENSION}")
+}
+
+#[cfg(feature = "radio")]
+pub(crate) use network::mirror;
+
+#[cfg(feature = "radio")]
+mod network {
+    use alloc::format;
+    use alloc::string::String;
+    use alloc::vec;
+    use alloc::vec::Vec;
+
+    use defmt::{info, warn};
+    use embassy_net::Stack;
+    use embedded_sdmmc::Mode;
+
+    use super::{
+        Entry, MAXIMUM_DEPTH, Manifest, Mirrored, Multistatus, PROPFIND_BODY, Remote, Summary,
+        WebdavError, bookmarks_href, decode, file_name, local_name,
+    };
+    use crate::SharedSdCard;
+    use crate::bookmarks::Bookmarks;
+    use crate::http::{self, Response};
+    use crate::profile::Profile;
+    use crate::sd_card::SdCard;
+
+    /// Downloads benefit from a larger window
+    const RECEIVE_BUFFER_SIZE: usize = 4 * 1024;
+    const SEND_BUFFER_SIZE: usize = 1024;
+    /// Bytes written to the card at once, a block
+    const CHUNK_SIZE: usize = 512;
+
+    /// Downloads the books that are new or changed on the server and uploads the bookmarks if
+    /// enabled. Books that fail to download are counted and tried again on the next sync.
+    pub(crate) async fn mirror(
+        stack: Stack<'_>,
+        remote: &Remote,
+        profile: &Profile,
+        sd_card: &SharedSdCard,
+    ) -> Result<Summary, WebdavError> {
+        stack.wait_config_up().await;
+
+        let (mut manifest, mut taken) = match sd_card.lock().await.as_mut() {
+            Some(sd_card) => prepare(sd_card, remote).await?,
+            None => return Err(WebdavError::NoCard),
+        };
+
+        let books = list(stack, remote).await?;
+        info!("Found {} books on the WebDAV server", books.len());
+
+        // Names are given out before downloading so a failed download keeps its name for the
+        // next try
+        let mut summary = Summary::default();
+        let mut downloads = Vec::new();
+        for book in &books {
+            let name = match manifest.find(&book.href) {
+                Some(mirrored) if mirrored.tag == book.tag && !book.tag.is_empty() => {
+                    summary.unchanged += 1;
+                    continue;
+                }
+                Some(mirrored) => mirrored.name.clone(),
+                None => {
+                    let Some(name) = local_name(file_name(&decode(&book.href)), &taken) else {
+                        warn!("No free name for {}", book.href.as_str());
+                        summary.failed += 1;
+                        continue;
+                    };
+                    taken.push(name.clone());
+                    manifest.set(Mirrored {
+                        name: name.clone(),
+                        tag: String::new(),
+                        href: book.href.clone(),
+                    });
+                    name
+                }
+            };
+            downloads.push((book, name));
+        }
+
+        if !downloads.is_empty() {
+            save_manifest(sd_card, remote, &manifest).await?;
+        }
+
+        for (book, name) in downloads {
+            let path = format!("{}/{name}", remote.directory);
+            info!("Downloading {} to {}", book.href.as_str(), path.as_str());
+            if let Err(error) = download(stack, remote, &book.href, &path, sd_card).await {
+                warn!(
+                    "Failed to download {}: {:?}",
+                    book.href.as_str(),
+                    defmt::Debug2Format(&error)
+                );
+                summary.failed += 1;
+                continue;
+            }
+
+            manifest.set(Mirrored {
+                name,
+                tag: book.tag.clone(),
+                href: book.href.clone(),
+            });
+            save_manifest(sd_card, remote, &manifest).await?;
+            summary.downloaded += 1;
+        }
+
+        if remote.upload_bookmarks {
+            summary.uploaded = upload_bookmarks(stack, remote, profile, &manifest, sd_card).await;
+        }
+
+        Ok(summary)
+    }
+
+    /// Loads the manifest and the names that are taken in the directory
+    async fn prepare(
+        sd_card: &mut SdCard,
+        remote: &Remote,
+    ) -> Result<(Manifest, Vec<String>), WebdavError> {
+        sd_card
+            .create_directories(&remote.directory)
+            .await
+            .map_err(WebdavError::CreateDirectory)?;
+        let manifest = Manifest::load(sd_card, remote)
+            .await
+            .map_err(WebdavError::LoadManifest)?;
+
+        let mut taken: Vec<String> = sd_card
+            .list_directory(&remote.directory)
+            .await
+            .map_err(WebdavError::ListDirectory)?
+            .iter()
+            .map(|entry| format!("{}", entry.name))
+            .collect();
+        taken.extend(manifest.files.iter().map(|file| file.name.clone()));
+        Ok((manifest, taken))
+    }
+
+    async fn save_manifest(
+        sd_card: &SharedSdCard,
+        remote: &Remote,
+        manifest: &Manifest,
+    ) -> Result<(), WebdavError> {
+        match sd_card.lock().await.as_mut() {
+            Some(sd_card) => Ok(manifest.save(sd_card, remote).await?),
+            None => Err(WebdavError::NoCard),
+        }
+    }
+
+    /// Books in the folder and its subfolders
+    async fn list(stack: Stack<'_>, remote: &Remote) -> Result<Vec<Entry>, WebdavError> {
+        let mut books = Vec::new();
+        let mut folders = vec![(remote.folder.clone(), 0)];
+        while let Some((folder, depth)) = folders.pop() {
+            for entry in list_folder(stack, remote, &folder).await? {
+                if !entry.is_folder {
+                    books.push(entry);
+                } else if depth < MAXIMUM_DEPTH
+                    && decode(&entry.href).trim_end_matches('/')
+                        != decode(&folder).trim_end_matches('/')
+                {
+                    folders.push((entry.href, depth + 1));
+                }
+            }
+        }
+
+        Ok(books)
+    }
+
+    /// Entries of the folder including the folder itself
+    async fn list_folder(
+        stack: Stack<'_>,
+        remote: &Remote,
+        folder: &str,
+    ) -> Result<Vec<Entry>, WebdavError> {
+        let head = remote.request(
+            "PROPFIND",
+            folder,
+            &[("Depth", "1"), ("Content-Type", "application/xml")],
+            PROPFIND_BODY,
+        );
+        let mut receive_buffer = [0; RECEIVE_BUFFER_SIZE];
+        let mut send_buffer = [0; SEND_BUFFER_SIZE];
+        let mut response = http::send(
+            stack,
+            &remote.server,
+            &head,
+            PROPFIND_BODY.as_bytes(),
+            &mut receive_buffer,
+            &mut send_buffer,
+        )
+        .await?;
+        check_status(&response)?;
+
+        let mut multistatus = Multistatus::default();
+        let mut buffer = [0; CHUNK_SIZE];
+        loop {
+            let read = response.read(&mut buffer).await?;
+            if read == 0 {
+                return Ok(multistatus.entries);
+            }
+            multistatus.push(&buffer[..read]);
+        }
+    }
+
+    /// Streams the file from the server into the file at the path
+    async fn download(
+        stack: Stack<'_>,
+        remote: &Remote,
+        href: &str,
+        path: &str,
+        sd_card: &SharedSdCard,
+    ) -> Result<(), WebdavError> {
+        let head = remote.request("GET", href, &[], "");
+        let mut receive_buffer = [0; RECEIVE_BUFFER_SIZE];
+        let mut send_buffer = [0; SEND_BUFFER_SIZE];
+        let mut response = http::send(
+            stack,
+            &remote.server,
+            &head,
+            &[],
+            &mut receive_buffer,
+            &mut send_buffer,
+        )
+        .await?;
+        check_status(&response)?;
+
+        let file = match sd_card.lock().await.as_mut() {
+            Some(sd_card) => sd_card.open(path, Mode::ReadWriteCreateOrTruncate).await?,
+            None => return Err(WebdavError::NoCard),
+        };
+
+        // The card is only locked while writing so the reader keeps working during the download
+        let mut result = Ok(());
+        let mut buffer = [0; CHUNK_SIZE];
+        loop {
+            let read = match response.read(&mut buffer).await {
+                Ok(0) => break,
+                Ok(read) => read,
+                Err(error) => {
+                    result = Err(error.into());
+                    break;
+                }
+            };
+
+            let written = match sd_card.lock().await.as_mut() {
+                Some(sd_card) => sd_card
+                    .write(file, &buffer[..read])
+                    .await
+                    .map_err(WebdavError::Write),
+                None => Err(WebdavError::NoCard),
+            };
+            if let Err(error) = written {
+                result = Err(error);
+                break;
+            }
+        }
+
+        if let Some(sd_card) = sd_card.lock().await.as_mut()
+            && let Err(error) = sd_card.close(file).await
+        {
+            warn!("Failed to close {}: {:?}", path, error);
+            result = result.and(Err(WebdavError::Write(error)));
+        }
+
+        result
+    }
+
+    /// Uploads the bookmarks of each book that has any and returns how many were uploaded
+    async fn upload_bookmarks(
+        stack: Stack<'_>,
+        remote: &Remote,
+        profile: &Profile,
+        manifest: &Manifest,
+        sd_card: &SharedSdCard,
+    ) -> usize {
+        let mut uploaded = 0;
+        for file in &manifest.files {
+            let path = format!("{}/{}", remote.directory, file.name);
+            let bookmarks = match sd_card.lock().await.as_mut() {
+                Some(sd_card) => Bookmarks::load(sd_card, profile, &path).await,
+                None => return uploaded,
+            };
+            let bookmarks = match bookmarks {
+                Ok(bookmarks) if bookmarks.is_empty() => continue,
+                Ok(bookmarks) => bookmarks,
+                Err(error) => {
+                    warn!(
+                        "Failed to load bookmarks of {}: {:?}",
+                        path.as_str(),
+                        defmt::Debug2Format(&error)
+                    );
+                    continue;
+                }
+            };
+
+            let result = upload(
+                stack,
+                remote,
+                &bookmarks_href(&file.href),
+                &bookmarks.export(),
+            )
+            .await;
+            match result {
+                Ok(()) => uploaded += 1,
+                Err(error) => warn!(
+                    "Failed to upload bookmarks of {}: {:?}",
+                    path.as_str(),
+                    defmt::Debug2Format(&error)
+                ),
+            }
+        }
+
+        uploaded
+    }
+
+    async fn upload(
+        stack: Stack<'_>,
+        remote: &Remote,
+        href: &str,
+        content: &str,
+    ) -> Result<(), WebdavError> {
+        let head = remote.request(
+            "PUT",
+            href,
+            &[("Content-Type", "text/plain; charset=utf-8")],
+            content,
+        );
+        let mut receive_buffer = [0; SEND_BUFFER_SIZE];
+        let mut send_buffer = [0; SEND_BUFFER_SIZE];
+        let response = http::send(
+            stack,
+            &remote.server,
+            &head,
+            content.as_bytes(),
+            &mut receive_buffer,
+            &mut send_buffer,
+        )
+        .await?;
+        // Created for new files and OK or No Content for replaced files
+        check_status(&response)
+    }
+
+    /// Listings are Multi-Status, which is a success as well
+    fn check_status(response: &Response<'_>) -> Result<(), WebdavError> {
+        match response.head.status {
+            _ if response.head.is_success() => Ok(()),
+            401 | 403 => Err(WebdavError::Unauthorized),
+            status => Err(WebdavError::Status(status)),
+        }
+    }
+}
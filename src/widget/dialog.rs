@@ -0,0 +1,133 @@
+use embedded_graphics::Drawable;
+use embedded_graphics::pixelcolor::BinaryColor;
+use embedded_graphics::prelude::{DrawTarget, OriginDimensions, Point, Primitive, Size};
+use embedded_graphics::primitives::{PrimitiveStyle, Rectangle};
+use embedded_graphics::text::{Alignment, Baseline, Text, TextStyleBuilder};
+
+use crate::eink_display::{DrawError, Frame};
+use crate::input::Button;
+use crate::widget::{INVERTED_TEXT_STYLE, MARGIN, ROW_HEIGHT, Response, TEXT_STYLE};
+
+const BORDER_WIDTH: u32 = 3;
+
+/// Box over the current screen with a message and a row of options like "Cancel" and "Delete".
+/// The screen below needs to be drawn again after the dialog is closed, or the area of the dialog
+/// put back from a copy with [`Frame::copy_region`].
+pub(crate) struct Dialog<'a> {
+    message: &'a str,
+    options: &'a [&'a str],
+    selected: usize,
+}
+
+impl<'a> Dialog<'a> {
+    /// The message can span multiple lines separated by line breaks. The first option is selected,
+    /// so that should be the safe one.
+    pub(crate) fn new(message: &'a str, options: &'a [&'a str]) -> Self {
+        Self {
+            message,
+            options,
+            selected: 0,
+        }
+    }
+
+    /// Index of the selected option
+    pub(crate) fn selected(&self) -> usize {
+        self.selected
+    }
+
+    pub(crate) fn handle(&mut self, button: Button) -> Response {
+        if self.options.is_empty() {
+            return match button {
+                Button::Confirm => Response::Confirmed,
+                Button::Back => Response::Cancelled,
+                _ => Response::Ignored,
+            };
+        }
+
+        match button {
+            Button::Left | Button::Up => {
+                self.selected = self
+                    .selected
+                    .checked_sub(1)
+                    .unwrap_or(self.options.len() - 1);
+                Response::Changed
+            }
+            Button::Right | Button::Down => {
+                self.selected = (self.selected + 1) % self.options.len();
+                Response::Changed
+            }
+            Button::Confirm => Response::Confirmed,
+            Button::Back => Response::Cancelled,
+        }
+    }
+
+    /// What the dialog covers in the middle of a frame of the size
+    pub(crate) fn area(&self, bounds: Size) -> Rectangle {
+        // Can't truncate as there are only a few lines
+        let lines = self.message.lines().count() as u32;
+        // A row for each line, a gap and the row of options
+        let height = (lines + 2) * ROW_HEIGHT + 2 * MARGIN as u32;
+        let width = bounds.width - 4 * MARGIN as u32;
+        Rectangle::with_center(
+            Point::new(bounds.width as i32 / 2, bounds.height as i32 / 2),
+            Size::new(width, height),
+        )
+    }
+
+    /// Draws the dialog in the middle of the frame over what is there
+    pub(crate) fn draw(&self, frame: &mut Frame) -> Result<(), DrawError> {
+        let area = self.area(frame.size());
+        let width = area.size.width;
+        // Can't truncate as there are only a few lines
+        let lines = self.message.lines().count() as u32;
+
+        frame.fill_solid(&area, BinaryColor::Off)?;
+        area.into_styled(PrimitiveStyle::with_stroke(BinaryColor::On, BORDER_WIDTH))
+            .draw(frame)?;
+
+        let center = TextStyleBuilder::new()
+            .alignment(Alignment::Center)
+            .baseline(Baseline::Middle)
+            .build();
+
+        let row_center = |row: u32| {
+            area.top_left
+                + Point::new(
+                    width as i32 / 2,
+                    MARGIN + (row * ROW_HEIGHT + ROW_HEIGHT / 2) as i32,
+                )
+        };
+
+        for (row, line) in (0..).zip(self.message.lines()) {
+            Text::with_text_style(line, row_center(row), TEXT_STYLE, center).draw(frame)?;
+        }
+
+        // Options share the last row evenly
+        let Ok(count) = u32::try_from(self.options.len()) else {
+            return Ok(());
+        };
+        if count == 0 {
+            return Ok(());
+        }
+
+        let option_width = (width - 2 * MARGIN as u32) / count;
+        let options_y = row_center(lines + 1).y;
+        for (index, option) in self.options.iter().enumerate() {
+            // Can't truncate as there are only a few options
+            let left = area.top_left.x + MARGIN + (index as u32 * option_width) as i32;
+            let option_center = Point::new(left + option_width as i32 / 2, options_y);
+            let style = if index == self.selected {
+                let highlight =
+                    Rectangle::with_center(option_center, Size::new(option_width, ROW_HEIGHT));
+                frame.fill_solid(&highlight, BinaryColor::On)?;
+                INVERTED_TEXT_STYLE
+            } else {
+                TEXT_STYLE
+            };
+
+            Text::with_text_style(option, option_center, style, center).draw(frame)?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,126 @@
+use core::ops::Range;
+
+use embedded_graphics::Drawable;
+use embedded_graphics::pixelcolor::BinaryColor;
+use embedded_graphics::prelude::{Point, Primitive, Size};
+use embedded_graphics::primitives::{PrimitiveStyleBuilder, Rectangle, StrokeAlignment};
+
+use crate::eink_display::{DrawError, Frame};
+use crate::input::Button;
+use crate::widget::Response;
+
+/// Width of the frame around the selected cell
+const CURSOR_WIDTH: u32 = 3;
+
+/// Cells in rows of a fixed number of columns with a cursor that scrolls by rows. Left and right
+/// move to the previous and next cell and wrap around, up and down move by a row. Drawing the cells
+/// is left to the screen, as cells like covers are read from the SD card one after another.
+pub(crate) struct GridView {
+    length: usize,
+    columns: usize,
+    cell_height: u32,
+    selected: usize,
+    /// Row of cells at the top of the area
+    first_visible_row: usize,
+}
+
+impl GridView {
+    /// The selected index is moved into the grid if it is outside
+    pub(crate) fn new(length: usize, selected: usize, columns: usize, cell_height: u32) -> Self {
+        Self {
+            length,
+            columns: columns.max(1),
+            cell_height: cell_height.max(1),
+            selected: selected.min(length.saturating_sub(1)),
+            first_visible_row: 0,
+        }
+    }
+
+    pub(crate) fn selected(&self) -> usize {
+        self.selected
+    }
+
+    pub(crate) fn handle(&mut self, button: Button) -> Response {
+        if self.length == 0 {
+            return match button {
+                Button::Back => Response::Cancelled,
+                _ => Response::Ignored,
+            };
+        }
+
+        match button {
+            Button::Left => {
+                self.selected = self.selected.checked_sub(1).unwrap_or(self.length - 1);
+                Response::Changed
+            }
+            Button::Right => {
+                self.selected = (self.selected + 1) % self.length;
+                Response::Changed
+            }
+            Button::Up => match self.selected.checked_sub(self.columns) {
+                Some(selected) => {
+                    self.selected = selected;
+                    Response::Changed
+                }
+                None => Response::Ignored,
+            },
+            Button::Down => {
+                let last_row = (self.length - 1) / self.columns;
+                if self.selected / self.columns == last_row {
+                    return Response::Ignored;
+                }
+
+                // The last row can be shorter
+                self.selected = (self.selected + self.columns).min(self.length - 1);
+                Response::Changed
+            }
+            Button::Confirm => Response::Confirmed,
+            Button::Back => Response::Cancelled,
+        }
+    }
+
+    /// Scrolls the selected cell into the area and returns the indexes of the cells that fit
+    pub(crate) fn visible(&mut self, area: &Rectangle) -> Range<usize> {
+        // Can't truncate as the frame is less than 1000 pixels tall
+        let visible_rows = (area.size.height / self.cell_height).max(1) as usize;
+        let selected_row = self.selected / self.columns;
+        if selected_row < self.first_visible_row {
+            self.first_visible_row = selected_row;
+        } else if selected_row >= self.first_visible_row + visible_rows {
+            self.first_visible_row = selected_row + 1 - visible_rows;
+        }
+
+        let start = self.first_visible_row * self.columns;
+        start..self.length.min(start + visible_rows * self.columns)
+    }
+
+    /// Where the cell is drawn. The index needs to be one of the visible cells.
+    pub(crate) fn cell(&self, area: &Rectangle, index: usize) -> Rectangle {
+        let row = index / self.columns - self.first_visible_row;
+        let column = index % self.columns;
+        // Can't truncate as there are only a few columns and visible rows
+        let width = area.size.width / self.columns as u32;
+        let top_left = area.top_left
+            + Point::new(
+                (column as u32 * width) as i32,
+                (row as u32 * self.cell_height) as i32,
+            );
+        Rectangle::new(top_left, Size::new(width, self.cell_height))
+    }
+
+    /// Frames the selected cell, after the cells are drawn
+    pub(crate) fn draw_cursor(&self, frame: &mut Frame, area: &Rectangle) -> Result<(), DrawError> {
+        if self.length == 0 {
+            return Ok(());
+        }
+
+        let style = PrimitiveStyleBuilder::new()
+            .stroke_color(BinaryColor::On)
+            .stroke_width(CURSOR_WIDTH)
+            .stroke_alignment(StrokeAlignment::Inside)
+            .build();
+        self.cell(area, self.selected)
+            .into_styled(style)
+            .draw(frame)
+    }
+}
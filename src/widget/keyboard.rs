@@ -0,0 +1,232 @@
+use alloc::string::String;
+
+use embedded_graphics::Drawable;
+use embedded_graphics::pixelcolor::BinaryColor;
+use embedded_graphics::prelude::{DrawTarget, Point, Primitive, Size};
+use embedded_graphics::primitives::{PrimitiveStyle, Rectangle};
+use embedded_graphics::text::{Alignment, Baseline, Text, TextStyleBuilder};
+
+use crate::eink_display::{DrawError, Frame};
+use crate::input::Button;
+use crate::widget::{INVERTED_TEXT_STYLE, MARGIN, ROW_HEIGHT, Response, TEXT_STYLE};
+
+const COLUMNS: usize = 10;
+/// Rows of characters above the row of special keys
+const CHARACTER_ROWS: usize = 4;
+const ROWS: usize = CHARACTER_ROWS + 1;
+const KEY_HEIGHT: u32 = 44;
+/// Characters of the font that fit into the text field next to the cursor
+const MAXIMUM_VISIBLE_CHARACTERS: usize = 40;
+
+const LETTERS: [&[u8; COLUMNS]; CHARACTER_ROWS] =
+    [b"1234567890", b"qwertyuiop", b"asdfghjkl-", b"zxcvbnm,._"];
+const SYMBOLS: [&[u8; COLUMNS]; CHARACTER_ROWS] =
+    [b"1234567890", b"!@#$%^&*()", b"-_=+[]{};:", b"'\",.<>/?\\|"];
+
+/// The keys in the last row which are twice as wide as the character keys
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SpecialKey {
+    Shift,
+    Symbols,
+    Space,
+    Delete,
+    Done,
+}
+
+const SPECIAL_KEYS: [SpecialKey; COLUMNS / 2] = [
+    SpecialKey::Shift,
+    SpecialKey::Symbols,
+    SpecialKey::Space,
+    SpecialKey::Delete,
+    SpecialKey::Done,
+];
+
+impl SpecialKey {
+    fn label(self, keyboard: &Keyboard) -> &'static str {
+        match self {
+            Self::Shift if keyboard.is_shifted => "SHIFT",
+            Self::Shift => "shift",
+            Self::Symbols if keyboard.is_symbols => "abc",
+            Self::Symbols => "#+=",
+            Self::Space => "space",
+            Self::Delete => "del",
+            Self::Done => "done",
+        }
+    }
+}
+
+/// Grid of keys to enter text with the buttons, e.g. for Wi-Fi passwords, searches and file names.
+/// The arrows move the cursor, confirm types the key under it and back cancels.
+/// Screens show the moving cursor with [`SharedDisplay::animate`](crate::SharedDisplay::animate)
+/// so that typing doesn't wait for a fast refresh on every key.
+pub(crate) struct Keyboard {
+    text: String,
+    maximum_length: usize,
+    row: usize,
+    column: usize,
+    /// Upper case letters until the next letter is typed
+    is_shifted: bool,
+    is_symbols: bool,
+}
+
+impl Keyboard {
+    /// Starts with the text, e.g. the current file name when renaming
+    pub(crate) fn new(text: &str, maximum_length: usize) -> Self {
+        let mut text = String::from(text);
+        text.truncate(
+            text.char_indices()
+                .nth(maximum_length)
+                .map_or(text.len(), |(index, _)| index),
+        );
+
+        Self {
+            text,
+            maximum_length,
+            row: 1,
+            column: 0,
+            is_shifted: false,
+            is_symbols: false,
+        }
+    }
+
+    pub(crate) fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub(crate) fn handle(&mut self, button: Button) -> Response {
+        match button {
+            Button::Up => self.row = self.row.checked_sub(1).unwrap_or(ROWS - 1),
+            Button::Down => self.row = (self.row + 1) % ROWS,
+            Button::Left if self.row == CHARACTER_ROWS => {
+                // Special keys span two columns
+                self.column = (self.column / 2 * 2).checked_sub(2).unwrap_or(COLUMNS - 2);
+            }
+            Button::Right if self.row == CHARACTER_ROWS => {
+                self.column = (self.column / 2 * 2 + 2) % COLUMNS;
+            }
+            Button::Left => self.column = self.column.checked_sub(1).unwrap_or(COLUMNS - 1),
+            Button::Right => self.column = (self.column + 1) % COLUMNS,
+            Button::Confirm => return self.press(),
+            Button::Back => return Response::Cancelled,
+        }
+
+        Response::Changed
+    }
+
+    fn press(&mut self) -> Response {
+        if self.row < CHARACTER_ROWS {
+            let character = self.character(self.row, self.column);
+            self.push(character);
+            self.is_shifted = false;
+            return Response::Changed;
+        }
+
+        match SPECIAL_KEYS[self.column / 2] {
+            SpecialKey::Shift => self.is_shifted = !self.is_shifted,
+            SpecialKey::Symbols => self.is_symbols = !self.is_symbols,
+            SpecialKey::Space => self.push(' '),
+            SpecialKey::Delete => {
+                self.text.pop();
+            }
+            SpecialKey::Done => return Response::Confirmed,
+        }
+
+        Response::Changed
+    }
+
+    fn push(&mut self, character: char) {
+        if self.text.chars().count() < self.maximum_length {
+            self.text.push(character);
+        }
+    }
+
+    fn character(&self, row: usize, column: usize) -> char {
+        let rows = if self.is_symbols { SYMBOLS } else { LETTERS };
+        let character = char::from(rows[row][column]);
+        if self.is_shifted {
+            character.to_ascii_uppercase()
+        } else {
+            character
+        }
+    }
+
+    /// Draws the entered text and the keys below it into the area
+    pub(crate) fn draw(&self, frame: &mut Frame, area: &Rectangle) -> Result<(), DrawError> {
+        frame.fill_solid(area, BinaryColor::Off)?;
+
+        let field = Rectangle::new(
+            area.top_left + Point::new(MARGIN, MARGIN),
+            Size::new(area.size.width - 2 * MARGIN as u32, ROW_HEIGHT),
+        );
+        field
+            .into_styled(PrimitiveStyle::with_stroke(BinaryColor::On, 1))
+            .draw(frame)?;
+        // Keeps the end of long texts visible, where the next character goes
+        let visible = self
+            .text
+            .char_indices()
+            .rev()
+            .nth(MAXIMUM_VISIBLE_CHARACTERS - 1)
+            .map_or(self.text.as_str(), |(index, _)| &self.text[index..]);
+        let text_position = Point::new(field.top_left.x + MARGIN, field.center().y);
+        let end = Text::with_baseline(visible, text_position, TEXT_STYLE, Baseline::Middle)
+            .draw(frame)?;
+        // Cursor after the text
+        Text::with_baseline("_", end, TEXT_STYLE, Baseline::Middle).draw(frame)?;
+
+        // Can't truncate as the frame is less than 1000 pixels wide
+        let key_width = area.size.width / COLUMNS as u32;
+        let keys_top = field.top_left.y + ROW_HEIGHT as i32 + MARGIN;
+        let centered = TextStyleBuilder::new()
+            .alignment(Alignment::Center)
+            .baseline(Baseline::Middle)
+            .build();
+
+        for row in 0..ROWS {
+            let is_special = row == CHARACTER_ROWS;
+            let (keys, width) = if is_special {
+                (COLUMNS / 2, key_width * 2)
+            } else {
+                (COLUMNS, key_width)
+            };
+
+            for key in 0..keys {
+                // Can't truncate as there are only a few keys
+                let top_left = Point::new(
+                    area.top_left.x + (key as u32 * width) as i32,
+                    keys_top + (row as u32 * KEY_HEIGHT) as i32,
+                );
+                let bounds = Rectangle::new(top_left, Size::new(width, KEY_HEIGHT));
+
+                let column = if is_special { key * 2 } else { key };
+                let is_selected = row == self.row
+                    && if is_special {
+                        self.column / 2 == key
+                    } else {
+                        self.column == column
+                    };
+
+                let style = if is_selected {
+                    frame.fill_solid(&bounds, BinaryColor::On)?;
+                    INVERTED_TEXT_STYLE
+                } else {
+                    bounds
+                        .into_styled(PrimitiveStyle::with_stroke(BinaryColor::On, 1))
+                        .draw(frame)?;
+                    TEXT_STYLE
+                };
+
+                let mut buffer = [0; 4];
+                let label = if is_special {
+                    SPECIAL_KEYS[key].label(self)
+                } else {
+                    self.character(row, column).encode_utf8(&mut buffer)
+                };
+
+                Text::with_text_style(label, bounds.center(), style, centered).draw(frame)?;
+            }
+        }
+
+        Ok(())
+    }
+}
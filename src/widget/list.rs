@@ -0,0 +1,161 @@
+use embedded_graphics::Drawable;
+use embedded_graphics::pixelcolor::BinaryColor;
+use embedded_graphics::prelude::{DrawTarget, Point, Size};
+use embedded_graphics::primitives::Rectangle;
+use embedded_graphics::text::{Baseline, Text};
+
+use crate::eink_display::{DrawError, DrawMode, Frame};
+use crate::input::Button;
+use crate::widget::{MARGIN, ROW_HEIGHT, Response, TEXT_STYLE};
+
+/// Vertical list with a cursor that scrolls when the cursor moves past the visible rows. Moving
+/// past the first or last item wraps around.
+pub(crate) struct ListView {
+    length: usize,
+    selected: usize,
+    /// Index of the item in the first visible row
+    first_visible: usize,
+}
+
+impl ListView {
+    /// The selected index is moved into the list if it is outside
+    pub(crate) fn new(length: usize, selected: usize) -> Self {
+        Self {
+            length,
+            selected: selected.min(length.saturating_sub(1)),
+            first_visible: 0,
+        }
+    }
+
+    pub(crate) fn selected(&self) -> usize {
+        self.selected
+    }
+
+    pub(crate) fn handle(&mut self, button: Button) -> Response {
+        if self.length == 0 {
+            return match button {
+                Button::Back => Response::Cancelled,
+                _ => Response::Ignored,
+            };
+        }
+
+        match button {
+            Button::Up | Button::Left => {
+                self.selected = self.selected.checked_sub(1).unwrap_or(self.length - 1);
+                Response::Changed
+            }
+            Button::Down | Button::Right => {
+                self.selected = (self.selected + 1) % self.length;
+                Response::Changed
+            }
+            Button::Confirm => Response::Confirmed,
+            Button::Back => Response::Cancelled,
+        }
+    }
+
+    /// Draws the visible items into the area with the selected one highlighted. The label of an
+    /// item is looked up by its index, so the items don't need to be collected first.
+    pub(crate) fn draw<'a>(
+        &mut self,
+        frame: &mut Frame,
+        area: &Rectangle,
+        label: impl Fn(usize) -> &'a str,
+    ) -> Result<(), DrawError> {
+        self.draw_rows(frame, area, |frame, index, row| {
+            Self::draw_label(frame, row, label(index))
+        })
+    }
+
+    /// Draws the label into the row like [`ListView::draw`] does, for rows of
+    /// [`ListView::draw_rows`] that are only a label
+    pub(crate) fn draw_label(
+        frame: &mut Frame,
+        row: &Rectangle,
+        label: &str,
+    ) -> Result<(), DrawError> {
+        let position = row.top_left + Point::new(MARGIN, (ROW_HEIGHT / 2) as i32);
+        Text::with_baseline(label, position, TEXT_STYLE, Baseline::Middle).draw(frame)?;
+        Ok(())
+    }
+
+    /// Like [`ListView::draw`] for items that are more than a label, like a [`Toggle`]. The row of
+    /// an item is drawn black on white and inverted after if it is selected.
+    ///
+    /// [`Toggle`]: crate::widget::Toggle
+    pub(crate) fn draw_rows(
+        &mut self,
+        frame: &mut Frame,
+        area: &Rectangle,
+        mut draw_row: impl FnMut(&mut Frame, usize, &Rectangle) -> Result<(), DrawError>,
+    ) -> Result<(), DrawError> {
+        frame.fill_solid(area, BinaryColor::Off)?;
+
+        let visible_rows = visible_rows(area);
+        if self.selected < self.first_visible {
+            self.first_visible = self.selected;
+        } else if self.selected >= self.first_visible + visible_rows {
+            self.first_visible = self.selected + 1 - visible_rows;
+        }
+
+        let end = self.length.min(self.first_visible + visible_rows);
+        for (row, index) in (self.first_visible..end).enumerate() {
+            // Can't truncate as there are only a few visible rows
+            let top_left = area.top_left + Point::new(0, (row as u32 * ROW_HEIGHT) as i32);
+            let row = Rectangle::new(top_left, Size::new(area.size.width, ROW_HEIGHT));
+            draw_row(frame, index, &row)?;
+        }
+
+        if self.length == 0 {
+            return Ok(());
+        }
+
+        self.invert_row(frame, area, self.selected)
+    }
+
+    /// Moves the highlight from the item that was selected when the list was drawn to the selected
+    /// one by inverting both rows, which is much quicker than drawing the list again. Returns false
+    /// if the list needs to be drawn again instead, because it would scroll or the selection
+    /// didn't move.
+    pub(crate) fn move_highlight(
+        &mut self,
+        frame: &mut Frame,
+        area: &Rectangle,
+        previous: usize,
+    ) -> Result<bool, DrawError> {
+        let visible = self.first_visible..self.first_visible + visible_rows(area);
+        if previous == self.selected
+            || !visible.contains(&previous)
+            || !visible.contains(&self.selected)
+        {
+            return Ok(false);
+        }
+
+        self.invert_row(frame, area, previous)?;
+        self.invert_row(frame, area, self.selected)?;
+        Ok(true)
+    }
+
+    /// Flips the pixels of the row of the visible item, which turns the highlight on or off
+    fn invert_row(
+        &self,
+        frame: &mut Frame,
+        area: &Rectangle,
+        index: usize,
+    ) -> Result<(), DrawError> {
+        // Can't truncate as there are only a few visible rows
+        let row = (index - self.first_visible) as u32;
+        let top_left = area.top_left + Point::new(0, (row * ROW_HEIGHT) as i32);
+        let highlight = Rectangle::new(top_left, Size::new(area.size.width, ROW_HEIGHT));
+
+        let mode = frame.draw_mode();
+        frame.set_draw_mode(DrawMode::Xor);
+        let result = frame.fill_solid(&highlight, BinaryColor::On);
+        frame.set_draw_mode(mode);
+        result
+    }
+}
+
+fn visible_rows(area: &Rectangle) -> usize {
+    // Can't truncate as the frame is less than 1000 pixels tall
+    (area.size.height / ROW_HEIGHT).max(1) as usize
+}
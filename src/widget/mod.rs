@@ -0,0 +1,46 @@
+//! Building blocks for screens that are navigated with the buttons, so the settings screen, the
+//! file browser and dialogs share how selection, scrolling and confirming work. Widgets draw into
+//! the [`Frame`](crate::eink_display::Frame) and take one button press at a time, leaving it to the
+//! screen to display the frame after a press changed something.
+
+mod dialog;
+mod grid;
+mod keyboard;
+mod list;
+mod slider;
+mod toggle;
+
+pub(crate) use crate::widget::dialog::Dialog;
+pub(crate) use crate::widget::grid::GridView;
+pub(crate) use crate::widget::keyboard::Keyboard;
+pub(crate) use crate::widget::list::ListView;
+pub(crate) use crate::widget::slider::Slider;
+pub(crate) use crate::widget::toggle::Toggle;
+
+use embedded_graphics::mono_font::MonoTextStyle;
+use embedded_graphics::mono_font::ascii::FONT_10X20;
+use embedded_graphics::pixelcolor::BinaryColor;
+
+/// Distance between the baselines of two rows
+const ROW_HEIGHT: u32 = 30;
+/// Space between the edge of a widget and its content
+const MARGIN: i32 = 10;
+
+const TEXT_STYLE: MonoTextStyle<'static, BinaryColor> =
+    MonoTextStyle::new(&FONT_10X20, BinaryColor::On);
+/// For text on a selected row which is drawn in black
+const INVERTED_TEXT_STYLE: MonoTextStyle<'static, BinaryColor> =
+    MonoTextStyle::new(&FONT_10X20, BinaryColor::Off);
+
+/// What a button press did to a widget
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub(crate) enum Response {
+    /// The button has no meaning for the widget
+    Ignored,
+    /// The widget needs to be drawn again
+    Changed,
+    /// The reader confirmed the current selection or value
+    Confirmed,
+    /// The reader backed out
+    Cancelled,
+}
@@ -0,0 +1,117 @@
+use core::fmt::Write;
+use core::ops::RangeInclusive;
+
+use embedded_graphics::Drawable;
+use embedded_graphics::pixelcolor::BinaryColor;
+use embedded_graphics::prelude::{DrawTarget, Point, Primitive, Size};
+use embedded_graphics::primitives::{PrimitiveStyle, Rectangle};
+use embedded_graphics::text::{Alignment, Baseline, Text, TextStyleBuilder};
+
+use crate::eink_display::{DrawError, Frame};
+use crate::input::Button;
+use crate::widget::{MARGIN, Response, TEXT_STYLE};
+
+const TRACK_HEIGHT: u32 = 12;
+/// Space on the right for the value
+const VALUE_WIDTH: u32 = 60;
+
+/// Value within a range that is changed in steps with left and right, e.g. for a font size
+pub(crate) struct Slider<'a> {
+    label: &'a str,
+    range: RangeInclusive<u16>,
+    step: u16,
+    value: u16,
+}
+
+impl<'a> Slider<'a> {
+    /// The value is moved into the range if it is outside
+    pub(crate) fn new(label: &'a str, range: RangeInclusive<u16>, step: u16, value: u16) -> Self {
+        let value = value.clamp(*range.start(), *range.end());
+        Self {
+            label,
+            range,
+            step,
+            value,
+        }
+    }
+
+    pub(crate) fn value(&self) -> u16 {
+        self.value
+    }
+
+    pub(crate) fn handle(&mut self, button: Button) -> Response {
+        let value = match button {
+            Button::Left => self.value.saturating_sub(self.step),
+            Button::Right => self.value.saturating_add(self.step),
+            Button::Confirm => return Response::Confirmed,
+            Button::Back => return Response::Cancelled,
+            // Left for moving between rows when the slider is part of a list
+            Button::Up | Button::Down => return Response::Ignored,
+        }
+        .clamp(*self.range.start(), *self.range.end());
+
+        if value == self.value {
+            return Response::Ignored;
+        }
+
+        self.value = value;
+        Response::Changed
+    }
+
+    /// Draws the label in the first half of the area and the track with the value in the second
+    pub(crate) fn draw(&self, frame: &mut Frame, area: &Rectangle) -> Result<(), DrawError> {
+        frame.fill_solid(area, BinaryColor::Off)?;
+
+        // Can't truncate as widgets are small
+        let row_height = area.size.height as i32 / 2;
+        let label_position = area.top_left + Point::new(MARGIN, row_height / 2);
+        Text::with_baseline(self.label, label_position, TEXT_STYLE, Baseline::Middle)
+            .draw(frame)?;
+
+        let track_width = area
+            .size
+            .width
+            .saturating_sub(VALUE_WIDTH + 2 * MARGIN as u32);
+        let track_top_left = area.top_left
+            + Point::new(
+                MARGIN,
+                row_height + row_height / 2 - TRACK_HEIGHT as i32 / 2,
+            );
+        let track = Rectangle::new(track_top_left, Size::new(track_width, TRACK_HEIGHT));
+        track
+            .into_styled(PrimitiveStyle::with_stroke(BinaryColor::On, 1))
+            .draw(frame)?;
+
+        let span = u32::from(self.range.end() - self.range.start());
+        let offset = u32::from(self.value - self.range.start());
+        let filled = if span == 0 {
+            track_width
+        } else {
+            track_width * offset / span
+        };
+        frame.fill_solid(
+            &Rectangle::new(track_top_left, Size::new(filled, TRACK_HEIGHT)),
+            BinaryColor::On,
+        )?;
+
+        let mut text = heapless::String::<8>::new();
+        // Can't fail as a u16 has at most 5 digits
+        let _ = write!(text, "{}", self.value);
+        let value_position = Point::new(
+            area.top_left.x + area.size.width as i32 - MARGIN,
+            row_height + row_height / 2 + area.top_left.y,
+        );
+        Text::with_text_style(
+            &text,
+            value_position,
+            TEXT_STYLE,
+            TextStyleBuilder::new()
+                .alignment(Alignment::Right)
+                .baseline(Baseline::Middle)
+                .build(),
+        )
+        .draw(frame)?;
+
+        Ok(())
+    }
+}
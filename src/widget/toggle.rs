@@ -0,0 +1,65 @@
+use embedded_graphics::Drawable;
+use embedded_graphics::pixelcolor::BinaryColor;
+use embedded_graphics::prelude::{DrawTarget, Point, Primitive, Size};
+use embedded_graphics::primitives::{PrimitiveStyle, Rectangle};
+use embedded_graphics::text::{Baseline, Text};
+
+use crate::eink_display::{DrawError, Frame};
+use crate::input::Button;
+use crate::widget::{MARGIN, Response, TEXT_STYLE};
+
+const BOX_SIZE: u32 = 16;
+
+/// Row with a label and a checkbox that is flipped with the confirm button
+pub(crate) struct Toggle<'a> {
+    label: &'a str,
+    is_on: bool,
+}
+
+impl<'a> Toggle<'a> {
+    pub(crate) fn new(label: &'a str, is_on: bool) -> Self {
+        Self { label, is_on }
+    }
+
+    pub(crate) fn is_on(&self) -> bool {
+        self.is_on
+    }
+
+    pub(crate) fn handle(&mut self, button: Button) -> Response {
+        match button {
+            Button::Confirm => {
+                self.is_on = !self.is_on;
+                Response::Changed
+            }
+            Button::Back => Response::Cancelled,
+            _ => Response::Ignored,
+        }
+    }
+
+    /// Draws the row into the area with the checkbox on the left
+    pub(crate) fn draw(&self, frame: &mut Frame, area: &Rectangle) -> Result<(), DrawError> {
+        frame.fill_solid(area, BinaryColor::Off)?;
+
+        // Can't truncate as rows are small
+        let middle = area.top_left.y + area.size.height as i32 / 2;
+        let box_top_left = Point::new(area.top_left.x + MARGIN, middle - BOX_SIZE as i32 / 2);
+        let checkbox = Rectangle::new(box_top_left, Size::new_equal(BOX_SIZE));
+        checkbox
+            .into_styled(PrimitiveStyle::with_stroke(BinaryColor::On, 2))
+            .draw(frame)?;
+
+        if self.is_on {
+            // Leaves a gap between the outline and the check
+            let check = Rectangle::new(
+                box_top_left + Point::new(4, 4),
+                Size::new_equal(BOX_SIZE - 8),
+            );
+            frame.fill_solid(&check, BinaryColor::On)?;
+        }
+
+        let position = Point::new(box_top_left.x + BOX_SIZE as i32 + MARGIN, middle);
+        Text::with_baseline(self.label, position, TEXT_STYLE, Baseline::Middle).draw(frame)?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,167 @@
+//! WiFi network to connect to. It is either set in the configuration file on the SD card or set up
+//! over Bluetooth with the provisioning screen, which stores it in the settings partition in flash
+//! so it works without the card as well. The configuration file wins if both are there.
+//!
+//! The stored password is scrambled with a key that is built into the firmware. That keeps it from
+//! showing up as plain text in a flash dump, but it is no protection against someone who has the
+//! firmware.
+
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use crustpoint_core::md5;
+use embedded_storage::{ReadStorage, Storage};
+use esp_storage::FlashStorageError;
+
+use crate::config::{Config, WifiCredentials};
+use crate::flash::{self, FindPartitionError, Partition, SharedFlash};
+
+/// Shares the partition with the settings, see [`crate::settings::SettingsStorage`]
+const PARTITION_LABEL: &str = "nvs";
+/// The settings are in the first sector, so writing one doesn't erase the other
+const OFFSET: u32 = 0x1000;
+const MAGIC: [u8; 4] = *b"WIFI";
+/// Magic followed by the lengths of the SSID and the password
+const HEADER_SIZE: usize = 6;
+/// Longest SSID the standard allows
+pub(crate) const MAXIMUM_SSID_LENGTH: usize = 32;
+/// Longest WPA2 passphrase
+pub(crate) const MAXIMUM_PASSWORD_LENGTH: usize = 63;
+const SCRAMBLE_KEY: &[u8] = b"crustpoint wifi";
+
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum LoadCredentialsError {
+    #[error("Failed to read from flash")]
+    Read(FlashStorageError),
+    #[error("No WiFi network was set up yet")]
+    NoCredentials,
+    #[error("Stored WiFi network is invalid")]
+    Invalid,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum StoreCredentialsError {
+    #[error("SSID or password is longer than the maximum")]
+    TooLong,
+    #[error("Failed to read from flash")]
+    Read(FlashStorageError),
+    #[error("Failed to write to flash")]
+    Write(FlashStorageError),
+}
+
+/// WiFi network in flash that was set up over Bluetooth
+pub(crate) struct CredentialsStorage {
+    flash: &'static SharedFlash,
+    partition: Partition,
+}
+
+impl CredentialsStorage {
+    pub(crate) fn new(flash: &'static SharedFlash) -> Result<Self, FindPartitionError> {
+        let partition = flash::find_partition(flash, PARTITION_LABEL)?;
+        Ok(Self { flash, partition })
+    }
+
+    fn read(&self, offset: u32, buffer: &mut [u8]) -> Result<(), FlashStorageError> {
+        self.flash
+            .lock(|flash| flash.borrow_mut().read(offset, buffer))
+    }
+
+    pub(crate) fn load(&self) -> Result<WifiCredentials, LoadCredentialsError> {
+        let offset = self.partition.offset + OFFSET;
+        let mut header = [0; HEADER_SIZE];
+        self.read(offset, &mut header)
+            .map_err(LoadCredentialsError::Read)?;
+
+        if header[..MAGIC.len()] != MAGIC {
+            return Err(LoadCredentialsError::NoCredentials);
+        }
+
+        let ssid_length = usize::from(header[4]);
+        let password_length = usize::from(header[5]);
+        if ssid_length == 0
+            || ssid_length > MAXIMUM_SSID_LENGTH
+            || password_length > MAXIMUM_PASSWORD_LENGTH
+        {
+            return Err(LoadCredentialsError::Invalid);
+        }
+
+        let mut content = vec![0; ssid_length + password_length];
+        // Can't truncate as the header is small
+        self.read(offset + HEADER_SIZE as u32, &mut content)
+            .map_err(LoadCredentialsError::Read)?;
+        scramble(&mut content);
+
+        let password = content.split_off(ssid_length);
+        let ssid = String::from_utf8(content).map_err(|_| LoadCredentialsError::Invalid)?;
+        let password = String::from_utf8(password).map_err(|_| LoadCredentialsError::Invalid)?;
+        Ok(WifiCredentials { ssid, password })
+    }
+
+    /// Flash is only written if the network changed to not wear it out
+    pub(crate) fn save(&self, credentials: &WifiCredentials) -> Result<(), StoreCredentialsError> {
+        let ssid = credentials.ssid.as_bytes();
+        let password = credentials.password.as_bytes();
+        if ssid.len() > MAXIMUM_SSID_LENGTH || password.len() > MAXIMUM_PASSWORD_LENGTH {
+            return Err(StoreCredentialsError::TooLong);
+        }
+
+        let mut content = Vec::with_capacity(ssid.len() + password.len());
+        content.extend_from_slice(ssid);
+        content.extend_from_slice(password);
+        scramble(&mut content);
+
+        let mut stored = Vec::with_capacity(HEADER_SIZE + content.len());
+        stored.extend_from_slice(&MAGIC);
+        // Can't truncate as the lengths are checked against the maximums
+        stored.push(ssid.len() as u8);
+        stored.push(password.len() as u8);
+        stored.extend_from_slice(&content);
+
+        let offset = self.partition.offset + OFFSET;
+        let mut current = vec![0; stored.len()];
+        self.read(offset, &mut current)
+            .map_err(StoreCredentialsError::Read)?;
+        if current == stored {
+            return Ok(());
+        }
+
+        self.flash
+            .lock(|flash| flash.borrow_mut().write(offset, &stored))
+            .map_err(StoreCredentialsError::Write)
+    }
+}
+
+/// The network from the configuration file or else the one that was set up over Bluetooth
+pub(crate) fn credentials(
+    config: &Config,
+    storage: Option<&CredentialsStorage>,
+) -> Option<WifiCredentials> {
+    if let Some(credentials) = &config.wifi {
+        return Some(credentials.clone());
+    }
+
+    match storage?.load() {
+        Ok(credentials) => Some(credentials),
+        Err(LoadCredentialsError::NoCredentials) => None,
+        Err(error) => {
+            defmt::error!(
+                "Failed to load WiFi network: {:?}",
+                defmt::Debug2Format(&error)
+            );
+            None
+        }
+    }
+}
+
+/// XORs with a stream of MD5 blocks of the key and a counter. Applying it twice restores the
+/// content.
+fn scramble(content: &mut [u8]) {
+    for (counter, chunk) in (0u32..).zip(content.chunks_mut(16)) {
+        let mut block = md5::Md5::new();
+        block.update(SCRAMBLE_KEY);
+        block.update(&counter.to_le_bytes());
+        for (byte, key) in chunk.iter_mut().zip(block.finish()) {
+            *byte ^= key;
+        }
+    }
+}
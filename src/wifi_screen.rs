@@ -0,0 +1,92 @@
+//! Lets the reader type the WiFi network in with the keyboard, for when there is no phone at hand
+//! to set it up over Bluetooth.
+
+use alloc::string::String;
+
+use defmt::error;
+use embedded_graphics::Drawable;
+use embedded_graphics::mono_font::MonoTextStyle;
+use embedded_graphics::mono_font::ascii::FONT_10X20;
+use embedded_graphics::pixelcolor::BinaryColor;
+use embedded_graphics::prelude::{OriginDimensions, Point, Size};
+use embedded_graphics::primitives::Rectangle;
+use embedded_graphics::text::Text;
+
+use crate::SharedDisplay;
+use crate::config::WifiCredentials;
+use crate::eink_display::Frame;
+use crate::input::Analog;
+use crate::widget::{Keyboard, Response};
+use crate::wifi::{MAXIMUM_PASSWORD_LENGTH, MAXIMUM_SSID_LENGTH};
+
+/// Where the keyboard starts below the title
+const KEYBOARD_TOP: i32 = 45;
+
+/// Asks for the name and then the password of the network. Returns None if the reader backed out.
+pub(crate) async fn enter(
+    analog: &mut Analog<'_>,
+    display: &SharedDisplay,
+    frame: &mut Frame,
+) -> Option<WifiCredentials> {
+    let ssid = enter_text("WiFi name", MAXIMUM_SSID_LENGTH, analog, display, frame)
+        .await
+        .filter(|ssid| !ssid.is_empty())?;
+    // Left empty for open networks
+    let password = enter_text(
+        "WiFi password",
+        MAXIMUM_PASSWORD_LENGTH,
+        analog,
+        display,
+        frame,
+    )
+    .await?;
+    Some(WifiCredentials { ssid, password })
+}
+
+/// Returns the typed text or None if the reader backed out
+async fn enter_text(
+    title: &str,
+    maximum_length: usize,
+    analog: &mut Analog<'_>,
+    display: &SharedDisplay,
+    frame: &mut Frame,
+) -> Option<String> {
+    let mut keyboard = Keyboard::new("", maximum_length);
+    loop {
+        draw(title, &keyboard, frame);
+        if let Err(error) = display.animate(frame).await {
+            error!(
+                "Failed to display keyboard: {:?}",
+                defmt::Debug2Format(&error)
+            );
+        }
+
+        loop {
+            match keyboard.handle(analog.wait_for_press().await) {
+                Response::Ignored => {}
+                Response::Changed => break,
+                Response::Confirmed => return Some(String::from(keyboard.text())),
+                Response::Cancelled => return None,
+            }
+        }
+    }
+}
+
+fn draw(title: &str, keyboard: &Keyboard, frame: &mut Frame) {
+    // White
+    frame.fill(0xFF);
+
+    let style = MonoTextStyle::new(&FONT_10X20, BinaryColor::On);
+    if let Err(error) = Text::new(title, Point::new(10, 30), style).draw(frame) {
+        error!("Failed to draw keyboard: {:?}", error);
+    }
+
+    let size = frame.size();
+    let area = Rectangle::new(
+        Point::new(0, KEYBOARD_TOP),
+        Size::new(size.width, size.height - KEYBOARD_TOP as u32),
+    );
+    if let Err(error) = keyboard.draw(frame, &area) {
+        error!("Failed to draw keyboard: {:?}", error);
+    }
+}
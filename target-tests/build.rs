@@ -0,0 +1,7 @@
+fn main() {
+    println!("cargo:rustc-link-arg=-Tdefmt.x");
+    // Collects the test functions for the runner of probe-rs
+    println!("cargo:rustc-link-arg-tests=-Tembedded-test.x");
+    // make sure linkall.x is the last linker script (otherwise might cause problems with flip-link)
+    println!("cargo:rustc-link-arg=-Tlinkall.x");
+}
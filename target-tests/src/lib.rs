@@ -0,0 +1,81 @@
+//! Brings up the board for the tests that run on it. The pins are the ones of
+//! `src/board/xteink_x4.rs`, which is part of the firmware binary and can't be linked against.
+
+#![no_std]
+
+use esp_backtrace as _;
+use esp_hal::Async;
+use esp_hal::analog::adc::{Adc, AdcCalLine, AdcConfig, AdcPin, Attenuation};
+use esp_hal::clock::CpuClock;
+use esp_hal::gpio::{Input, InputConfig, Level, Output, OutputConfig, Pull};
+use esp_hal::interrupt::software::SoftwareInterruptControl;
+use esp_hal::peripherals::{ADC1, GPIO1, GPIO2, GPIO7, GPIO8, GPIO10, SPI2};
+use esp_hal::timer::timg::TimerGroup;
+
+/// The display driver allocates little, and the frames live in statics
+const HEAP_SIZE: usize = 32 * 1024;
+
+pub type LadderPin<PIN> = AdcPin<PIN, ADC1<'static>, AdcCalLine<ADC1<'static>>>;
+
+/// The bus the display and the SD card share. Each test sets it up with the configuration of the
+/// device it tests.
+pub struct Spi {
+    pub spi: SPI2<'static>,
+    pub serial_clock: GPIO8<'static>,
+    pub master_out_slave_in: GPIO10<'static>,
+    pub master_in_slave_out: GPIO7<'static>,
+}
+
+pub struct Board {
+    pub spi: Spi,
+    /// Both chip selects start high, so a device only listens once its test selects it
+    pub display_chip_select: Output<'static>,
+    pub sd_card_chip_select: Output<'static>,
+    pub reset: Output<'static>,
+    pub data_command: Output<'static>,
+    pub busy: Input<'static>,
+    pub adc: Adc<'static, ADC1<'static>, Async>,
+    pub ladder_1: LadderPin<GPIO1<'static>>,
+    pub ladder_2: LadderPin<GPIO2<'static>>,
+}
+
+/// Sets up the logs, the heap and the scheduler for the timers of embassy, then takes the pins.
+/// Called once per test, as the runner resets the chip before each one.
+pub fn init() -> Board {
+    rtt_target::rtt_init_defmt!();
+
+    let config = esp_hal::Config::default().with_cpu_clock(CpuClock::max());
+    let peripherals = esp_hal::init(config);
+    esp_alloc::heap_allocator!(size: HEAP_SIZE);
+
+    let timer_group_0 = TimerGroup::new(peripherals.TIMG0);
+    let software_interrupt = SoftwareInterruptControl::new(peripherals.SW_INTERRUPT);
+    esp_rtos::start(timer_group_0.timer0, software_interrupt.software_interrupt0);
+
+    let mut configuration = AdcConfig::new();
+    let ladder_1 = configuration
+        .enable_pin_with_cal::<_, AdcCalLine<ADC1<'static>>>(peripherals.GPIO1, Attenuation::_11dB);
+    let ladder_2 = configuration
+        .enable_pin_with_cal::<_, AdcCalLine<ADC1<'static>>>(peripherals.GPIO2, Attenuation::_11dB);
+    let adc = Adc::new(peripherals.ADC1, configuration).into_async();
+
+    Board {
+        spi: Spi {
+            spi: peripherals.SPI2,
+            serial_clock: peripherals.GPIO8,
+            master_out_slave_in: peripherals.GPIO10,
+            master_in_slave_out: peripherals.GPIO7,
+        },
+        display_chip_select: Output::new(peripherals.GPIO21, Level::High, OutputConfig::default()),
+        sd_card_chip_select: Output::new(peripherals.GPIO12, Level::High, OutputConfig::default()),
+        reset: Output::new(peripherals.GPIO5, Level::Low, OutputConfig::default()),
+        data_command: Output::new(peripherals.GPIO4, Level::High, OutputConfig::default()),
+        busy: Input::new(
+            peripherals.GPIO6,
+            InputConfig::default().with_pull(Pull::Down),
+        ),
+        adc,
+        ladder_1,
+        ladder_2,
+    }
+}
@@ -0,0 +1,90 @@
+//! Reads the button ladders through the ADC and classifies the levels with the thresholds of the
+//! firmware. The presses need someone at the board, so that test only runs when ignored tests are
+//! included: `cargo test --test buttons -- --include-ignored`
+
+#![no_std]
+#![no_main]
+
+esp_bootloader_esp_idf::esp_app_desc!();
+
+#[embedded_test::tests(executor = esp_rtos::embassy::Executor::new())]
+mod tests {
+    use crustpoint_core::ladder::{Calibration, Ladder};
+    use crustpoint_target_tests::Board;
+    use embassy_time::{Duration, Timer};
+
+    /// The most the firmware takes the median of
+    const SAMPLES: usize = 9;
+    /// Readings of a ladder that is left alone stay this close together. Well below the
+    /// hysteresis, which has to absorb the noise.
+    const MAXIMUM_SPREAD: u16 = 50;
+    const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+    /// The buttons of the firmware in the order of the levels of their ladder
+    const BUTTONS: [(&str, Ladder, u8); 6] = [
+        ("back", Ladder::One, 0),
+        ("confirm", Ladder::One, 1),
+        ("left", Ladder::One, 2),
+        ("right", Ladder::One, 3),
+        ("up", Ladder::Two, 0),
+        ("down", Ladder::Two, 1),
+    ];
+
+    async fn read_level(board: &mut Board, ladder: Ladder) -> u16 {
+        match ladder {
+            Ladder::One => board.adc.read_oneshot(&mut board.ladder_1).await,
+            Ladder::Two => board.adc.read_oneshot(&mut board.ladder_2).await,
+        }
+    }
+
+    #[init]
+    fn init() -> Board {
+        crustpoint_target_tests::init()
+    }
+
+    #[test]
+    async fn released_ladders_are_above_the_thresholds(mut board: Board) {
+        let calibration = Calibration::default();
+        for ladder in [Ladder::One, Ladder::Two] {
+            let mut levels = [0; SAMPLES];
+            for sample in &mut levels {
+                *sample = read_level(&mut board, ladder).await;
+            }
+            defmt::info!("{} levels: {}", ladder, levels);
+
+            for level in levels {
+                assert!(level > calibration.highest(ladder));
+                assert_eq!(calibration.active_button(ladder, level, None), None);
+            }
+            let lowest = levels.iter().min().copied().unwrap_or_default();
+            let highest = levels.iter().max().copied().unwrap_or_default();
+            assert!(highest - lowest <= MAXIMUM_SPREAD);
+        }
+    }
+
+    #[test]
+    #[ignore]
+    #[timeout(120)]
+    async fn pressed_buttons_fall_into_their_ranges(mut board: Board) {
+        let calibration = Calibration::default();
+        for (name, ladder, button) in BUTTONS {
+            defmt::info!("Press and release {}", name);
+            let level = loop {
+                let level = read_level(&mut board, ladder).await;
+                if level <= calibration.highest(ladder) {
+                    // Lets the level settle after the contact bounced
+                    Timer::after(POLL_INTERVAL).await;
+                    break read_level(&mut board, ladder).await;
+                }
+                Timer::after(POLL_INTERVAL).await;
+            };
+
+            defmt::info!("Level of {}: {}", name, level);
+            assert_eq!(calibration.active_button(ladder, level, None), Some(button));
+
+            while read_level(&mut board, ladder).await <= calibration.highest(ladder) {
+                Timer::after(POLL_INTERVAL).await;
+            }
+        }
+    }
+}
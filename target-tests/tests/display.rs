@@ -0,0 +1,106 @@
+//! Brings up the display controller and refreshes the panel through the driver of the firmware.
+//! Leaves the screen white.
+
+#![no_std]
+#![no_main]
+
+esp_bootloader_esp_idf::esp_app_desc!();
+
+#[embedded_test::tests(executor = esp_rtos::embassy::Executor::new())]
+mod tests {
+    use crustpoint_core::Frame;
+    use crustpoint_display::{EinkDisplay, RefreshMode};
+    use crustpoint_target_tests::Board;
+    use embassy_time::{Delay, Duration};
+    use embedded_hal_bus::spi::ExclusiveDevice;
+    use esp_hal::Async;
+    use esp_hal::gpio::{Input, Output};
+    use esp_hal::spi::master::{Config, Spi};
+    use esp_hal::time::Rate;
+    use static_cell::StaticCell;
+
+    type Display = EinkDisplay<
+        ExclusiveDevice<Spi<'static, Async>, Output<'static>, Delay>,
+        Output<'static>,
+        Output<'static>,
+        Input<'static>,
+        (),
+    >;
+
+    /// A refresh that ends quicker than this didn't wait for the busy line to go high and low again
+    const SHORTEST_REFRESH: Duration = Duration::from_millis(100);
+    /// The timeout of a refresh before its mode was measured. Assumes the board is at room
+    /// temperature, as the controller picks longer waveforms in the cold.
+    const LONGEST_REFRESH: Duration = Duration::from_secs(10);
+
+    /// Frames are too large for the stack of the test
+    static FRAME: StaticCell<Frame> = StaticCell::new();
+
+    /// The same settings as the firmware, but without the DMA and the bus sharing, which the
+    /// driver doesn't see
+    async fn initialize(board: Board) -> Display {
+        let configuration = Config::default()
+            .with_frequency(Rate::from_mhz(40))
+            .with_mode(esp_hal::spi::Mode::_0);
+        let spi = Spi::new(board.spi.spi, configuration)
+            .expect("SPI configuration should be valid")
+            .with_sck(board.spi.serial_clock)
+            .with_mosi(board.spi.master_out_slave_in)
+            .with_miso(board.spi.master_in_slave_out)
+            .into_async();
+        let Ok(spi) = ExclusiveDevice::new(spi, board.display_chip_select, Delay);
+        // Keeps the SD card off the bus for the rest of the test
+        core::mem::forget(board.sd_card_chip_select);
+
+        EinkDisplay::initialize(spi, board.reset, board.data_command, board.busy, ())
+            .await
+            .expect("display should initialize")
+    }
+
+    #[init]
+    fn init() -> Board {
+        crustpoint_target_tests::init()
+    }
+
+    #[test]
+    async fn initialization_reads_the_temperature(board: Board) {
+        let display = initialize(board).await;
+        let temperature = display
+            .temperature()
+            .expect("temperature should be read during the initialization");
+        defmt::info!("Temperature: {} degrees Celsius", temperature.celsius());
+    }
+
+    #[test]
+    async fn refreshes_wait_for_the_busy_line(board: Board) {
+        let mut display = initialize(board).await;
+        let frame = Frame::initialize(FRAME.uninit());
+
+        for mode in [RefreshMode::Full, RefreshMode::Fast] {
+            let shown = display
+                .display(mode, frame)
+                .await
+                .expect("refresh should succeed");
+            // A refresh that failed is shown again with a full refresh after a recovery
+            assert_eq!(shown, mode);
+        }
+
+        let full = display.statistics().get(RefreshMode::Full);
+        let fast = display.statistics().get(RefreshMode::Fast);
+        defmt::info!(
+            "Full refresh: {}, fast refresh: {}",
+            full.maximum,
+            fast.maximum
+        );
+        assert_eq!((full.count, fast.count), (1, 1));
+        assert!(SHORTEST_REFRESH < fast.maximum);
+        assert!(fast.maximum < full.maximum);
+        assert!(full.maximum < LONGEST_REFRESH);
+        assert_eq!(display.statistics().retries(), 0);
+
+        display
+            .enter_deep_sleep()
+            .await
+            .expect("display should enter deep sleep");
+    }
+}
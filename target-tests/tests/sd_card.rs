@@ -0,0 +1,70 @@
+//! Mounts the SD card in the slot, which needs a card with a FAT file system in its first
+//! partition, the same as the firmware. Goes through the SPI driver of embedded-sdmmc, as the one
+//! of the firmware is tied to its shared bus and its timeouts, so this checks the bus, the pins and
+//! the card rather than that driver.
+
+#![no_std]
+#![no_main]
+
+esp_bootloader_esp_idf::esp_app_desc!();
+
+#[embedded_test::tests(executor = esp_rtos::embassy::Executor::new())]
+mod tests {
+    use crustpoint_target_tests::Board;
+    use embassy_time::Delay;
+    use embedded_hal_bus::spi::ExclusiveDevice;
+    use embedded_sdmmc::{SdCard, TimeSource, Timestamp, VolumeIdx, VolumeManager};
+    use esp_hal::spi::master::{Config, Spi};
+    use esp_hal::time::Rate;
+
+    /// Cards have to accept the initialization at this clock
+    const INITIALIZE_FREQUENCY: Rate = Rate::from_khz(400);
+
+    /// Nothing is written, so the timestamp doesn't matter
+    struct FixedTimeSource;
+
+    impl TimeSource for FixedTimeSource {
+        fn get_timestamp(&self) -> Timestamp {
+            Timestamp {
+                // 2026
+                year_since_1970: 56,
+                zero_indexed_month: 0,
+                zero_indexed_day: 0,
+                hours: 0,
+                minutes: 0,
+                seconds: 0,
+            }
+        }
+    }
+
+    #[init]
+    fn init() -> Board {
+        crustpoint_target_tests::init()
+    }
+
+    #[test]
+    fn card_mounts(board: Board) {
+        let configuration = Config::default()
+            .with_frequency(INITIALIZE_FREQUENCY)
+            .with_mode(esp_hal::spi::Mode::_0);
+        let spi = Spi::new(board.spi.spi, configuration)
+            .expect("SPI configuration should be valid")
+            .with_sck(board.spi.serial_clock)
+            .with_mosi(board.spi.master_out_slave_in)
+            .with_miso(board.spi.master_in_slave_out);
+        let Ok(spi) = ExclusiveDevice::new(spi, board.sd_card_chip_select, Delay);
+        // Keeps the display off the bus for the rest of the test
+        core::mem::forget(board.display_chip_select);
+
+        let card = SdCard::new(spi, Delay);
+        let size = card.num_bytes().expect("card should answer");
+        defmt::info!("SD card size: {} bytes", size);
+        assert!(size > 0);
+
+        let volume_manager = VolumeManager::new(card, FixedTimeSource);
+        let volume = volume_manager
+            .open_volume(VolumeIdx(0))
+            .expect("first partition should have a FAT file system");
+        volume.open_root_dir().expect("root directory should open");
+    }
+}